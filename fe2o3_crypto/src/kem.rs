@@ -6,10 +6,7 @@ use crate::{
     keys::Keys,
 };
 
-use oxedyne_fe2o3_core::{
-    prelude::*,
-    rand::RanDef,
-};
+use oxedyne_fe2o3_core::prelude::*;
 use oxedyne_fe2o3_iop_crypto::{
     kem::KeyExchanger,
 };
@@ -32,6 +29,7 @@ use std::{
 
 use secrecy::{
     ExposeSecret,
+    Secret,
 };
 
 
@@ -101,7 +99,7 @@ impl KeyExchanger for KeyExchangeScheme {
         const CIPHERTEXT_LEN: usize,
     >(
         &self,
-        _pk: [u8; PK_LEN], // TODO does the trait need this?
+        pk: [u8; PK_LEN],
     )
         -> Outcome<(
             [u8; SESSION_KEY_LEN],
@@ -109,19 +107,14 @@ impl KeyExchanger for KeyExchangeScheme {
         )>
     {
         match self {
-            Self::FireSaber(keys) => match keys {
-                Keys { pk: Some(pk_byts), .. } => { 
-                    let scheme = saber::FireSaber;
-                    let pk = res!(saber::PublicKey::from_bytes(&pk_byts[..]));
-                    let (sess_key1, ct1) = scheme.kem_encap(&pk);
-                    let ct2 = ct1.to_bytes::<{CIPHERTEXT_LEN}>();
-                    let ct3 = res!(<[u8; CIPHERTEXT_LEN]>::try_from(&ct2[..]));
-                    let sess_key2 = res!(<[u8; SESSION_KEY_LEN]>::try_from(&sess_key1[..]));
-                    Ok((sess_key2, ct3))
-                },
-                _ => Err(err!(
-                    "Require public key to encapsulate.";
-                Missing, Configuration)),
+            Self::FireSaber(..) => {
+                let scheme = saber::FireSaber;
+                let pk = res!(saber::PublicKey::from_bytes(&pk[..]));
+                let (sess_key1, ct1) = scheme.kem_encap(&pk);
+                let ct2 = ct1.to_vec();
+                let ct3 = res!(<[u8; CIPHERTEXT_LEN]>::try_from(&ct2[..]));
+                let sess_key2 = res!(<[u8; SESSION_KEY_LEN]>::try_from(&sess_key1[..]));
+                Ok((sess_key2, ct3))
             },
         }
     }
@@ -181,13 +174,45 @@ impl TryFrom<LocalId> for KeyExchangeScheme {
 
 impl KeyExchangeScheme {
 
-    pub const FIRESABER_PK_LEN:             usize = saber::FireSaber::PK_LEN;
-    pub const FIRESABER_SK_LEN:             usize = saber::FireSaber::SK_LEN;
-    pub const FIRESABER_SESSION_KEY_LEN:    usize = saber::FireSaber::SK_LEN;
+    pub const FIRESABER_PK_LEN:             usize = saber::FireSaber::PUBLIC_KEY_BYTES;
+    pub const FIRESABER_SK_LEN:             usize = saber::FireSaber::SECRET_KEY_BYTES;
+    pub const FIRESABER_SESSION_KEY_LEN:    usize = saber::KEY_BYTES;
     pub const FIRESABER_CIPHERTEXT_LEN:     usize = saber::FireSaber::CIPHERTEXT_BYTES;
 
+    /// Generates a fresh FireSaber key pair.
     pub fn new_firesaber() -> Self {
-        Self::FireSaber(Keys::randef())
+        let (pk, sk) = saber::FireSaber.kem_keygen();
+        let mut pk_byts = [0u8; Self::FIRESABER_PK_LEN];
+        pk_byts.copy_from_slice(&pk.to_bytes());
+        let mut sk_byts = [0u8; Self::FIRESABER_SK_LEN];
+        sk_byts.copy_from_slice(&sk.to_bytes());
+        Self::FireSaber(Keys::new(Some(pk_byts), Some(Secret::new(sk_byts))))
+    }
+
+    /// Creates a FireSaber scheme from existing keys, for example a peer public key for use in
+    /// encapsulation.
+    pub fn new_firesaber_with_keys(pk: Option<&[u8]>, sk: Option<&[u8]>) -> Outcome<Self> {
+        let pk = match pk {
+            Some(pk) => Some(res!(
+                <[u8; Self::FIRESABER_PK_LEN]>::try_from(pk),
+                Conversion, Bytes,
+            )),
+            None => None,
+        };
+        let sks = match sk {
+            Some(sk) => Some(Secret::new(res!(
+                <[u8; Self::FIRESABER_SK_LEN]>::try_from(sk),
+                Conversion, Bytes,
+            ))),
+            None => None,
+        };
+        Ok(Self::FireSaber(Keys::new(pk, sks)))
+    }
+
+    pub fn get_public_key(&self) -> Option<&[u8]> {
+        match self {
+            Self::FireSaber(keys) => keys.pk.as_ref().map(|pk| &pk[..]),
+        }
     }
 }
 
@@ -218,4 +243,32 @@ mod tests {
         msg!(" ciphertext: {}", ciphertext.byte_len());
         Ok(())
     }
+
+    #[test]
+    fn test_kem_scheme_firesaber_00() -> Outcome<()> {
+        // Bob generates a key pair and sends the public key to Alice.
+        let bob = KeyExchangeScheme::new_firesaber();
+        let pk = match bob.get_public_key() {
+            Some(pk) => res!(<[u8; KeyExchangeScheme::FIRESABER_PK_LEN]>::try_from(pk)),
+            None => return Err(err!("Missing public key."; Test, Missing)),
+        };
+        // Alice encapsulates a session key using only Bob's public key.
+        let alice = res!(KeyExchangeScheme::new_firesaber_with_keys(Some(&pk[..]), None));
+        let (alice_session_key, ciphertext) = res!(alice.encap::<
+            {KeyExchangeScheme::FIRESABER_PK_LEN},
+            {KeyExchangeScheme::FIRESABER_SESSION_KEY_LEN},
+            {KeyExchangeScheme::FIRESABER_CIPHERTEXT_LEN},
+        >(pk));
+        let bob_session_key = res!(bob.decap::<
+            {KeyExchangeScheme::FIRESABER_SESSION_KEY_LEN},
+            {KeyExchangeScheme::FIRESABER_CIPHERTEXT_LEN},
+        >(ciphertext));
+        assert_eq!(alice_session_key, bob_session_key);
+        // Alice cannot decapsulate without a secret key.
+        assert!(alice.decap::<
+            {KeyExchangeScheme::FIRESABER_SESSION_KEY_LEN},
+            {KeyExchangeScheme::FIRESABER_CIPHERTEXT_LEN},
+        >(ciphertext).is_err());
+        Ok(())
+    }
 }
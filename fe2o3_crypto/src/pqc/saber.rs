@@ -1847,22 +1847,22 @@ impl<
     }
 
     pub fn from_bytes(b: &[u8]) -> Outcome<Self> {
-        let mut end: usize = KEY_BYTES;
-        let rand = res!(
-            TryInto::<&[u8; KEY_BYTES]>::try_into(&b[..end]),
-            Conversion, Bytes,
-        );
+        let mut end: usize = SK_LEN;
+        let sk = res!(SecretKeyCPA::from_bytes(&b[..end]));
         let mut start = end;
+        end = start + PK_LEN + SEED_BYTES;
+        let pk = res!(PublicKey::from_bytes(&b[start..end]));
+        start = end;
         end = start + HASH_BYTES;
         let hash_pk = res!(
             TryInto::<&[u8; HASH_BYTES]>::try_into(&b[start..end]),
             Conversion, Bytes,
         );
         start = end;
-        end = start + PK_LEN + SEED_BYTES;
-        let pk = res!(PublicKey::from_bytes(&b[start..end]));
-        start = end;
-        let sk = res!(SecretKeyCPA::from_bytes(&b[start..]));
+        let rand = res!(
+            TryInto::<&[u8; KEY_BYTES]>::try_into(&b[start..]),
+            Conversion, Bytes,
+        );
         Ok( SecretKeyCCA {
             sk:     sk,
             pk:     pk,
@@ -0,0 +1,190 @@
+//! Canonical binary encoding of a `Dat`, for use when the bytes must be reproducible, such as
+//! when hashing or signing a daticle.
+//!
+//! The regular binary encoding faithfully records the kind of each daticle, so that
+//! `Dat::U8(42)` and `Dat::U16(42)` encode differently, as do a `Dat::Map` and `Dat::OrdMap` with
+//! the same entries.  The canonical encoding first reduces the daticle to its canonical form using
+//! the following rules, then encodes it in the usual way:
+//!
+//! - All integers (`U8`-`U128`, `I8`-`I128`, `C64` and `Aint`) are captured as the smallest
+//!   unsigned kind that can hold a non-negative value, or the smallest signed kind that can hold a
+//!   negative value.  An `Aint` is retained only when the value lies outside both ranges.
+//! - An `Adec` is normalised by removing trailing zeros, so that `1.0` and `1.00` coincide.
+//! - Variable length bytes (`BU8`-`BU64`, `BC64`) use the smallest length prefix.
+//! - A `Vek` is captured as a `List`, because narrowing the integer kinds may otherwise break
+//!   homogeneity.
+//! - A `Dat::OrdMap` becomes a `Dat::Map`, dropping the order keys.  The entries of a map are then
+//!   in the (deterministic) order of their canonical keys.  Two keys that only differ prior to
+//!   canonicalisation, such as `Dat::U8(1)` and `Dat::U16(1)`, produce an error.
+//! - An `ABox` is replaced by its canonical contents, since the annotation is presentational.
+//!
+//! All other kinds, including floats, fixed length byte arrays and fixed length numeric tuples,
+//! are retained, with any contained daticles canonicalised recursively.
+use crate::prelude::*;
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    byte::ToBytes,
+};
+
+use std::convert::TryFrom;
+
+use num_bigint::BigInt;
+
+
+impl Dat {
+
+    /// Appends the canonical encoding of the `Dat` to the given byte buffer.  Two daticles with
+    /// the same value, but captured using different integer widths, map kinds or byte length
+    /// prefixes, will produce identical bytes.
+    pub fn to_canonical_bytes(&self, buf: Vec<u8>) -> Outcome<Vec<u8>> {
+        let dat = res!(self.clone().into_canonical());
+        dat.to_bytes(buf)
+    }
+
+    /// Returns the canonical encoding of the `Dat` in a new byte vector, suitable for hashing or
+    /// signing.
+    pub fn as_canonical_bytes(&self) -> Outcome<Vec<u8>> {
+        self.to_canonical_bytes(Vec::new())
+    }
+
+    /// Consumes the `Dat` and returns its canonical form.  See the `binary::canon` module
+    /// documentation for the rules.
+    pub fn into_canonical(self) -> Outcome<Self> {
+        Ok(match self {
+            // Atomic Kinds ===========================
+            // Fixed
+            Self::U8(n)     => Self::canonical_uint(n as u128),
+            Self::U16(n)    => Self::canonical_uint(n as u128),
+            Self::U32(n)    => Self::canonical_uint(n as u128),
+            Self::U64(n)    => Self::canonical_uint(n as u128),
+            Self::U128(n)   => Self::canonical_uint(n),
+            Self::I8(n)     => Self::canonical_int(n as i128),
+            Self::I16(n)    => Self::canonical_int(n as i128),
+            Self::I32(n)    => Self::canonical_int(n as i128),
+            Self::I64(n)    => Self::canonical_int(n as i128),
+            Self::I128(n)   => Self::canonical_int(n),
+            // Variable
+            Self::Aint(bigint) => Self::canonical_aint(bigint),
+            Self::Adec(bigdec) => Self::Adec(bigdec.normalized()),
+            Self::C64(n)    => Self::canonical_uint(n as u128),
+            // Molecule Kinds =========================
+            // Unitary
+            Self::Usr(ukid, optboxd) => match optboxd {
+                Some(boxd) => Self::Usr(ukid, Some(Box::new(res!(boxd.into_canonical())))),
+                None => Self::Usr(ukid, None),
+            },
+            Self::Box(boxd) => Self::Box(Box::new(res!(boxd.into_canonical()))),
+            Self::Opt(boxoptd) => match *boxoptd {
+                Some(d) => Self::Opt(Box::new(Some(res!(d.into_canonical())))),
+                None => Self::Opt(Box::new(None)),
+            },
+            Self::ABox(_, boxd, _) => res!(boxd.into_canonical()),
+            // Heterogenous
+            Self::List(v) => Self::List(res!(Self::canonical_vec(v))),
+            Self::Tup2(a) => Self::Tup2(Box::new(res!(Self::canonical_array(*a)))),
+            Self::Tup3(a) => Self::Tup3(Box::new(res!(Self::canonical_array(*a)))),
+            Self::Tup4(a) => Self::Tup4(Box::new(res!(Self::canonical_array(*a)))),
+            Self::Tup5(a) => Self::Tup5(Box::new(res!(Self::canonical_array(*a)))),
+            Self::Tup6(a) => Self::Tup6(Box::new(res!(Self::canonical_array(*a)))),
+            Self::Tup7(a) => Self::Tup7(Box::new(res!(Self::canonical_array(*a)))),
+            Self::Tup8(a) => Self::Tup8(Box::new(res!(Self::canonical_array(*a)))),
+            Self::Tup9(a) => Self::Tup9(Box::new(res!(Self::canonical_array(*a)))),
+            Self::Tup10(a) => Self::Tup10(Box::new(res!(Self::canonical_array(*a)))),
+            Self::Map(map) => {
+                Self::Map(res!(Self::canonical_map(map.into_iter())))
+            },
+            Self::OrdMap(map) => {
+                Self::Map(res!(Self::canonical_map(
+                    map.into_iter().map(|(mk, v)| (mk.into_dat(), v))
+                )))
+            },
+            // Homogenous
+            Self::Vek(vek) => Self::List(res!(Self::canonical_vec(vek.0))),
+            // Variable length bytes
+            Self::BU8(v)    |
+            Self::BU16(v)   |
+            Self::BU32(v)   |
+            Self::BU64(v)   |
+            Self::BC64(v)   => Self::bytdat(v),
+            _ => self,
+        })
+    }
+
+    /// Captures the unsigned integer using the smallest unsigned kind.
+    pub fn canonical_uint(n: u128) -> Self {
+        if n <= u8::MAX as u128 {
+            Self::U8(n as u8)
+        } else if n <= u16::MAX as u128 {
+            Self::U16(n as u16)
+        } else if n <= u32::MAX as u128 {
+            Self::U32(n as u32)
+        } else if n <= u64::MAX as u128 {
+            Self::U64(n as u64)
+        } else {
+            Self::U128(n)
+        }
+    }
+
+    /// Captures a non-negative integer using the smallest unsigned kind, and a negative integer
+    /// using the smallest signed kind.
+    pub fn canonical_int(n: i128) -> Self {
+        if n >= 0 {
+            Self::canonical_uint(n as u128)
+        } else if n >= i8::MIN as i128 {
+            Self::I8(n as i8)
+        } else if n >= i16::MIN as i128 {
+            Self::I16(n as i16)
+        } else if n >= i32::MIN as i128 {
+            Self::I32(n as i32)
+        } else if n >= i64::MIN as i128 {
+            Self::I64(n as i64)
+        } else {
+            Self::I128(n)
+        }
+    }
+
+    /// Captures the integer using the smallest fixed width kind, if possible, otherwise as a
+    /// `Dat::Aint`.
+    pub fn canonical_aint(bigint: BigInt) -> Self {
+        if let Ok(n) = u128::try_from(&bigint) {
+            Self::canonical_uint(n)
+        } else if let Ok(n) = i128::try_from(&bigint) {
+            Self::canonical_int(n)
+        } else {
+            Self::Aint(bigint)
+        }
+    }
+
+    fn canonical_vec(v: Vec<Self>) -> Outcome<Vec<Self>> {
+        let mut result = Vec::with_capacity(v.len());
+        for d in v {
+            result.push(res!(d.into_canonical()));
+        }
+        Ok(result)
+    }
+
+    fn canonical_array<const N: usize>(a: [Self; N]) -> Outcome<[Self; N]> {
+        let mut result: [Self; N] = std::array::from_fn(|_| Self::Empty);
+        for (i, d) in a.into_iter().enumerate() {
+            result[i] = res!(d.into_canonical());
+        }
+        Ok(result)
+    }
+
+    fn canonical_map<I: Iterator<Item = (Self, Self)>>(entries: I) -> Outcome<DaticleMap> {
+        let mut map = DaticleMap::new();
+        for (k, v) in entries {
+            let k = res!(k.into_canonical());
+            let v = res!(v.into_canonical());
+            if map.contains_key(&k) {
+                return Err(err!(
+                    "The map contains multiple keys with the canonical form {:?}, so the map \
+                    has no canonical form.", k;
+                Input, Invalid, Exists));
+            }
+            map.insert(k, v);
+        }
+        Ok(map)
+    }
+}
@@ -391,6 +391,37 @@ impl Dat {
                 res!(Self::count_bytes_muncher(rs, count));
                 return Ok(());
             },
+            Self::ABOX_CODE => {
+                //
+                //   0                                       1   2  ...  n   1   2  ...  v
+                // +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+                // | c |   |   |   |   |   |   |   |   |   |   |   |   |   |   |  ...  |   |
+                // +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+                //       | \_______________________________/\______________/\______________/
+                //       |                 |                         |               |
+                //  NoteConfig         inner Dat                    c64        payload bytes
+                //
+                res!(rs.seek(SeekFrom::Current(1)));
+                *count += 1;
+                res!(Self::count_bytes_muncher(rs, count));
+                let mut c64code = [0;1];
+                res!(rs.read_exact(&mut c64code));
+                *count += 1;
+                if c64code[0] < Self::C64_CODE_START || c64code[0] > Self::C64_CODE_START + 8 {
+                    return Err(err!(
+                        "Expected a valid Dat::C64 code between {} and {} inclusive, \
+                        instead found {}.", Self::C64_CODE_START, Self::C64_CODE_START + 8,
+                        c64code[0];
+                    Invalid, Input, Decode, Bytes));
+                }
+                if c64code[0] == Self::C64_CODE_START {
+                    return Ok(());
+                }
+                let (vlen, c64len) = res!(Self::count_c64(&mut rs, c64code[0]));
+                *count += c64len + vlen;
+                res!(rs.seek(SeekFrom::Current(vlen as i64)));
+                return Ok(());
+            },
             // Heterogenous
             Self::TUP2_CODE     |
             Self::TUP3_CODE     |
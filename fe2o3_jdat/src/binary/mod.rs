@@ -1,5 +1,7 @@
+pub mod canon;
 pub mod core;
 pub mod count;
 pub mod dec;
 pub mod enc;
 pub mod load;
+pub mod stream;
@@ -0,0 +1,311 @@
+//! A push-based binary decoder for daticles arriving in arbitrary chunks, such as from a socket
+//! or a large file.
+//!
+//! Bytes are supplied to a `StreamDecoder` via `push`, and decoded `StreamEvent`s are drawn from
+//! it as an `Iterator`.  By default, each complete top-level daticle is yielded as a
+//! `StreamEvent::Dat`.  When `StreamConfig::events` is set, lists and maps are not buffered
+//! whole, but opened with a `StreamEvent::Start`, followed by their contents and closed with a
+//! `StreamEvent::End`, so that a single enormous list, such as a log, can be processed in bounded
+//! memory.
+//!
+//! ```
+//! use oxedyne_fe2o3_jdat::{
+//!     prelude::*,
+//!     binary::stream::{
+//!         StreamConfig,
+//!         StreamDecoder,
+//!         StreamEvent,
+//!     },
+//! };
+//! use oxedyne_fe2o3_core::prelude::*;
+//!
+//! fn main() -> Outcome<()> {
+//!     let mut byts = res!(dat!("hello").as_bytes());
+//!     byts = res!(dat!(42u8).to_bytes(byts));
+//!
+//!     let mut decoder = StreamDecoder::new(StreamConfig::default());
+//!     for chunk in byts.chunks(3) {
+//!         res!(decoder.push(chunk));
+//!     }
+//!     res!(decoder.finish());
+//!     let events: Vec<StreamEvent> = decoder.collect();
+//!     assert_eq!(events, vec![
+//!         StreamEvent::Dat(dat!("hello")),
+//!         StreamEvent::Dat(dat!(42u8)),
+//!     ]);
+//!     Ok(())
+//! }
+//! ```
+use crate::prelude::*;
+pub use crate::stream::{
+    StreamConfig,
+    StreamEvent,
+};
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    byte::FromBytes,
+};
+
+use std::{
+    collections::VecDeque,
+    io::{
+        self,
+        SeekFrom,
+    },
+};
+
+
+#[derive(Clone, Debug)]
+pub struct StreamDecoder {
+    cfg:        StreamConfig,
+    buf:        Vec<u8>,
+    // The list and map kinds currently open, along with the number of content bytes remaining.
+    stack:      Vec<(Kind, usize)>,
+    events:     VecDeque<StreamEvent>,
+    consumed:   usize,
+    // The measured length of an incomplete daticle at the front of the buffer, so that it need
+    // not be measured again on each push.
+    pending:    Option<usize>,
+}
+
+impl Iterator for StreamDecoder {
+    type Item = StreamEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.events.pop_front()
+    }
+}
+
+impl StreamDecoder {
+
+    pub fn new(cfg: StreamConfig) -> Self {
+        Self {
+            cfg,
+            buf:        Vec::new(),
+            stack:      Vec::new(),
+            events:     VecDeque::new(),
+            consumed:   0,
+            pending:    None,
+        }
+    }
+
+    /// The number of bytes held while waiting for a daticle to be completed.
+    pub fn buffered(&self) -> usize { self.buf.len() }
+    /// The total number of bytes decoded so far.
+    pub fn consumed(&self) -> usize { self.consumed }
+    /// The number of lists and maps currently open.
+    pub fn depth(&self) -> usize { self.stack.len() }
+
+    /// Accepts the next chunk of bytes, decoding as many events as possible.
+    pub fn push(&mut self, chunk: &[u8]) -> Outcome<()> {
+        self.buf.extend_from_slice(chunk);
+        let mut start: usize = 0;
+        let result = self.process(&mut start);
+        self.buf.drain(..start);
+        self.consumed += start;
+        result
+    }
+
+    /// Reads a single chunk of up to `chunk_size` bytes from the reader and pushes it to the
+    /// decoder, returning the number of bytes read.  A return value of zero indicates the end of
+    /// the reader.
+    pub fn push_from<R: io::Read>(&mut self, r: &mut R, chunk_size: usize) -> Outcome<usize> {
+        let mut chunk = vec![0u8; chunk_size];
+        let n = res!(r.read(&mut chunk));
+        if n > 0 {
+            res!(self.push(&chunk[..n]));
+        }
+        Ok(n)
+    }
+
+    /// Confirms that the stream ended cleanly, with no partial daticle buffered and no list or
+    /// map left open.
+    pub fn finish(&mut self) -> Outcome<()> {
+        if self.buf.len() > 0 {
+            return Err(err!(
+                "The stream ended with {} bytes of an incomplete daticle at byte {}.",
+                self.buf.len(), self.consumed;
+            Bytes, Input, Decode, Missing));
+        }
+        if let Some((kind, remaining)) = self.stack.last() {
+            return Err(err!(
+                "The stream ended with {} open, missing {} bytes at byte {}.",
+                kind, remaining, self.consumed;
+            Bytes, Input, Decode, Missing));
+        }
+        Ok(())
+    }
+
+    fn process(&mut self, start: &mut usize) -> Outcome<()> {
+        loop {
+            while let Some((kind, 0)) = self.stack.last() {
+                self.events.push_back(StreamEvent::End(kind.clone()));
+                self.stack.pop();
+            }
+            let avail = &self.buf[*start..];
+            if avail.len() == 0 {
+                return Ok(());
+            }
+            if self.cfg.events {
+                let kind_opt = match avail[0] {
+                    Dat::LIST_CODE  => Some(Kind::List),
+                    Dat::VEK_CODE   => Some(Kind::Vek),
+                    Dat::MAP_CODE   => Some(Kind::Map),
+                    Dat::OMAP_CODE  => Some(Kind::OrdMap),
+                    _ => None,
+                };
+                if let Some(kind) = kind_opt {
+                    if self.stack.len() >= self.cfg.max_depth {
+                        return Err(err!(
+                            "Opening a {} at byte {} would exceed the maximum depth of {}.",
+                            kind, self.consumed + *start, self.cfg.max_depth;
+                        Bytes, Input, Decode, Excessive));
+                    }
+                    // The contents are preceded by a Dat::C64 giving their byte length.
+                    if avail.len() < 2 {
+                        return Ok(());
+                    }
+                    let c64code = avail[1];
+                    if c64code < Dat::C64_CODE_START || c64code > Dat::C64_CODE_END {
+                        return Err(err!(
+                            "Expected a valid Dat::C64 code between {} and {} inclusive for \
+                            the length of a {} at byte {}, instead found {}.",
+                            Dat::C64_CODE_START, Dat::C64_CODE_END, kind,
+                            self.consumed + *start, c64code;
+                        Invalid, Input, Decode, Bytes));
+                    }
+                    let hlen = 2 + (c64code - Dat::C64_CODE_START) as usize;
+                    if avail.len() < hlen {
+                        return Ok(());
+                    }
+                    let (len, _) = res!(Dat::read_c64(&avail[1..hlen]));
+                    let len = try_into!(usize, len);
+                    res!(self.consume_in_parent(hlen + len, *start));
+                    *start += hlen;
+                    self.stack.push((kind.clone(), len));
+                    self.events.push_back(StreamEvent::Start(kind));
+                    continue;
+                }
+            }
+            let measured = match self.pending {
+                Some(n) => Some(n),
+                None => res!(Self::measure(avail)),
+            };
+            let n = match measured {
+                Some(n) => n,
+                None => {
+                    if avail.len() > self.cfg.max_buffer {
+                        return Err(err!(
+                            "The daticle at byte {} exceeds the maximum buffer size of {} bytes.",
+                            self.consumed + *start, self.cfg.max_buffer;
+                        Bytes, Input, Decode, TooBig));
+                    }
+                    return Ok(());
+                },
+            };
+            if n > self.cfg.max_buffer {
+                return Err(err!(
+                    "The daticle at byte {} has a length of {} bytes, which exceeds the \
+                    maximum buffer size of {} bytes.",
+                    self.consumed + *start, n, self.cfg.max_buffer;
+                Bytes, Input, Decode, TooBig));
+            }
+            if avail.len() < n {
+                self.pending = Some(n);
+                return Ok(());
+            }
+            self.pending = None;
+            let (dat, m) = res!(Dat::from_bytes(&avail[..n]));
+            if m != n {
+                return Err(err!(
+                    "The daticle at byte {} was expected to occupy {} bytes, but decoding \
+                    consumed {}.", self.consumed + *start, n, m;
+                Bytes, Input, Decode, Mismatch));
+            }
+            res!(self.consume_in_parent(n, *start));
+            *start += n;
+            self.events.push_back(StreamEvent::Dat(dat));
+        }
+    }
+
+    fn consume_in_parent(&mut self, n: usize, start: usize) -> Outcome<()> {
+        if let Some((kind, remaining)) = self.stack.last_mut() {
+            if n > *remaining {
+                return Err(err!(
+                    "The {} bytes at byte {} overrun the {} remaining bytes of the enclosing {}.",
+                    n, self.consumed + start, remaining, kind;
+                Bytes, Input, Decode, Overflow));
+            }
+            *remaining -= n;
+        }
+        Ok(())
+    }
+
+    /// Returns the encoded length of the daticle at the start of the buffer, if it can be
+    /// determined from the bytes available.  The length may exceed that of the buffer.
+    pub fn measure(buf: &[u8]) -> Outcome<Option<usize>> {
+        let mut r = Peek::new(buf);
+        match Dat::count_bytes(&mut r) {
+            Ok(n) => Ok(if r.past_end { None } else { Some(n) }),
+            Err(e) => if r.past_end {
+                Ok(None)
+            } else {
+                Err(e)
+            },
+        }
+    }
+}
+
+/// A reader over a partial buffer that records any attempt to read beyond its end, allowing
+/// `Dat::count_bytes` to distinguish between missing and invalid bytes.  Seeking beyond the end
+/// is permitted, since this skips over a payload whose length is already known.
+struct Peek<'a> {
+    buf:        &'a [u8],
+    pos:        usize,
+    past_end:   bool,
+}
+
+impl<'a> Peek<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self {
+            buf,
+            pos:        0,
+            past_end:   false,
+        }
+    }
+}
+
+impl<'a> io::Read for Peek<'a> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if out.len() == 0 {
+            return Ok(0);
+        }
+        if self.pos >= self.buf.len() {
+            self.past_end = true;
+            return Ok(0);
+        }
+        let n = std::cmp::min(out.len(), self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl<'a> io::Seek for Peek<'a> {
+    fn seek(&mut self, from: SeekFrom) -> io::Result<u64> {
+        let pos = match from {
+            SeekFrom::Start(n) => n as i128,
+            SeekFrom::Current(n) => self.pos as i128 + n as i128,
+            SeekFrom::End(n) => self.buf.len() as i128 + n as i128,
+        };
+        if pos < 0 || pos > usize::MAX as i128 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid seek to a negative or overflowing position.",
+            ));
+        }
+        self.pos = pos as usize;
+        Ok(pos as u64)
+    }
+}
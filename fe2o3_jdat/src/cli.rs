@@ -0,0 +1,515 @@
+//! The engine behind the `jdat` command line tool, which converts, pretty-prints, validates and
+//! extracts daticles from JDAT text, JSON, JDAT binary, CBOR and MessagePack.
+//!
+//! ```text
+//! jdat [convert] [OPTIONS] [FILE]     Convert the input to the output format (the default).
+//! jdat validate  [OPTIONS] [FILE]     Check the input, reporting the location of any error.
+//! jdat get PATH  [OPTIONS] [FILE]     Extract the subtree at the given path.
+//! ```
+//!
+//! The input is read from the file, or from stdin when the file is absent or `-`.  A path is either
+//! a JDAT list of keys and indices such as `["servers", 0, "name"]`, or a slash separated form such
+//! as `/servers/0/name`, in which segments consisting of digits are taken to be integers.
+use crate::{
+    prelude::*,
+    usr::{
+        UsrKind,
+        UsrKindCode,
+        UsrKindId,
+    },
+    string::enc::{
+        ByteEncoding,
+        EncoderConfig,
+        IntEncoding,
+        KindScope,
+    },
+};
+
+use oxedyne_fe2o3_core::prelude::*;
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{
+        Read,
+        Write,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+
+pub const USAGE: &str = "\
+Usage:
+  jdat [convert] [OPTIONS] [FILE]   Convert the input to the output format (the default).
+  jdat validate  [OPTIONS] [FILE]   Check the input, reporting the location of any error.
+  jdat get PATH  [OPTIONS] [FILE]   Extract the subtree at PATH, e.g. /servers/0/name or
+                                    '[\"servers\", 0, \"name\"]'.
+
+The input is read from FILE, or from stdin when FILE is absent or '-'.
+
+Options:
+  -i, --in FORMAT       Input format: jdat, json, bin, cbor or msgpack.  Defaults to the
+                        FILE extension, otherwise jdat.
+  -o, --out FORMAT      Output format: jdat, json, bin, cbor or msgpack.  Defaults to jdat.
+  -k, --kinds SCOPE     Kind labels shown in text: nothing, some, most or everything.
+  -b, --bytes ENCODING  Byte encoding in text: base2x, binary, decimal, hex or octal.
+  -n, --ints ENCODING   Integer encoding in text: binary, decimal, hex or octal.
+  -t, --tab N           Indent text output by N spaces (default 4).
+  -c, --compact         Write text output on a single line.
+  -w, --write FILE      Write the output to FILE rather than stdout.
+  -h, --help            Show this message.
+";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Jdat,
+    Json,
+    Binary,
+    Cbor,
+    MsgPack,
+}
+
+impl Format {
+
+    pub fn from_name(name: &str) -> Outcome<Self> {
+        Ok(match name.to_lowercase().as_str() {
+            "jdat" | "text"         => Self::Jdat,
+            "json"                  => Self::Json,
+            "bin" | "binary"        => Self::Binary,
+            "cbor"                  => Self::Cbor,
+            "msgpack" | "mpk"       => Self::MsgPack,
+            _ => return Err(err!(
+                "Unknown format '{}', expected jdat, json, bin, cbor or msgpack.", name;
+            Input, Invalid, Unknown)),
+        })
+    }
+
+    /// Infers the format from the file extension, if recognised.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("jdat")                        => Some(Self::Jdat),
+            Some("json")                        => Some(Self::Json),
+            Some("bin") | Some("jdatb")         => Some(Self::Binary),
+            Some("cbor")                        => Some(Self::Cbor),
+            Some("msgpack") | Some("mpk")       => Some(Self::MsgPack),
+            _ => None,
+        }
+    }
+
+    pub fn is_text(&self) -> bool {
+        matches!(self, Self::Jdat | Self::Json)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Convert,
+    Validate,
+    Get(Vec<Dat>),
+}
+
+#[derive(Clone, Debug)]
+pub struct CliConfig {
+    pub command:        Command,
+    pub input:          Option<PathBuf>,
+    pub output:         Option<PathBuf>,
+    pub in_format:      Option<Format>,
+    pub out_format:     Format,
+    pub kind_scope:     Option<KindScope>,
+    pub byte_encoding:  Option<ByteEncoding>,
+    pub int_encoding:   Option<IntEncoding>,
+    pub to_lines:       bool,
+    pub tab:            String,
+    pub help:           bool,
+}
+
+impl Default for CliConfig {
+    fn default() -> Self {
+        Self {
+            command:        Command::Convert,
+            input:          None,
+            output:         None,
+            in_format:      None,
+            out_format:     Format::Jdat,
+            kind_scope:     None,
+            byte_encoding:  None,
+            int_encoding:   None,
+            to_lines:       true,
+            tab:            fmt!("    "),
+            help:           false,
+        }
+    }
+}
+
+impl CliConfig {
+
+    /// Interprets the command line arguments, excluding the program name.
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Outcome<Self> {
+        let mut cfg = Self::default();
+        let mut args = args.into_iter().peekable();
+        match args.peek().map(|s| s.as_str()) {
+            Some("convert") => {
+                args.next();
+            },
+            Some("validate") => {
+                args.next();
+                cfg.command = Command::Validate;
+            },
+            Some("get") => {
+                args.next();
+                let path = match args.next() {
+                    Some(path) => path,
+                    None => return Err(err!(
+                        "The get command requires a path.";
+                    Input, Missing)),
+                };
+                cfg.command = Command::Get(res!(parse_path(&path)));
+            },
+            _ => (),
+        }
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| -> Outcome<String> {
+                match args.next() {
+                    Some(v) => Ok(v),
+                    None => Err(err!(
+                        "The option {} requires a value.", name;
+                    Input, Missing)),
+                }
+            };
+            match arg.as_str() {
+                "-i" | "--in"       => cfg.in_format = Some(res!(Format::from_name(&res!(value(&arg))))),
+                "-o" | "--out"      => cfg.out_format = res!(Format::from_name(&res!(value(&arg)))),
+                "-k" | "--kinds"    => cfg.kind_scope = Some(res!(parse_kind_scope(&res!(value(&arg))))),
+                "-b" | "--bytes"    => cfg.byte_encoding = Some(res!(parse_byte_encoding(&res!(value(&arg))))),
+                "-n" | "--ints"     => cfg.int_encoding = Some(res!(parse_int_encoding(&res!(value(&arg))))),
+                "-t" | "--tab"      => {
+                    let v = res!(value(&arg));
+                    let n = match v.parse::<usize>() {
+                        Ok(n) => n,
+                        Err(e) => return Err(err!(e,
+                            "The tab width '{}' is not a number.", v;
+                        Input, Invalid)),
+                    };
+                    cfg.tab = " ".repeat(n);
+                },
+                "-c" | "--compact"  => cfg.to_lines = false,
+                "-w" | "--write"    => cfg.output = Some(PathBuf::from(res!(value(&arg)))),
+                "-h" | "--help"     => cfg.help = true,
+                "-" => cfg.input = None,
+                s if s.starts_with('-') => return Err(err!(
+                    "Unknown option '{}'.", s;
+                Input, Invalid, Unknown)),
+                _ => {
+                    if cfg.input.is_some() {
+                        return Err(err!(
+                            "Only one input file may be given, found '{}' after '{}'.",
+                            arg, cfg.input.as_ref().map(|p| p.display().to_string()).unwrap_or_default();
+                        Input, Excessive));
+                    }
+                    cfg.input = Some(PathBuf::from(arg));
+                },
+            }
+        }
+        Ok(cfg)
+    }
+
+    pub fn encoder_config(&self) -> EncoderConfig<BTreeMap<UsrKindCode, UsrKind>, BTreeMap<String, UsrKindId>> {
+        let mut enc_cfg = match (self.out_format, self.to_lines) {
+            (Format::Json, true)    => EncoderConfig::<_, _>::json_to_lines(None, &self.tab),
+            (Format::Json, false)   => EncoderConfig::<_, _>::json(None),
+            (_, true)               => EncoderConfig::<_, _>::jdat_to_lines(None, &self.tab),
+            (_, false)              => EncoderConfig::<_, _>::jdat(None),
+        };
+        if let Some(kind_scope) = &self.kind_scope {
+            enc_cfg.kind_scope = kind_scope.clone();
+        }
+        if let Some(byte_encoding) = &self.byte_encoding {
+            enc_cfg.byte_encoding = byte_encoding.clone();
+        }
+        if let Some(int_encoding) = &self.int_encoding {
+            enc_cfg.int_encoding = int_encoding.clone();
+        }
+        enc_cfg
+    }
+}
+
+/// Executes the tool, reading from the input file or `stdin` and writing to the output file or
+/// `stdout`.
+pub fn run<R: Read, W: Write>(cfg: &CliConfig, stdin: &mut R, stdout: &mut W) -> Outcome<()> {
+    if cfg.help {
+        res!(stdout.write_all(USAGE.as_bytes()));
+        return Ok(());
+    }
+    let (byts, source) = match &cfg.input {
+        Some(path) => match fs::read(path) {
+            Ok(byts) => (byts, fmt!("'{}'", path.display())),
+            Err(e) => return Err(err!(e,
+                "While reading the input file '{}'.", path.display();
+            IO, File, Read)),
+        },
+        None => {
+            let mut byts = Vec::new();
+            res!(stdin.read_to_end(&mut byts));
+            (byts, fmt!("stdin"))
+        },
+    };
+    let in_format = cfg.in_format
+        .or_else(|| cfg.input.as_ref().and_then(|p| Format::from_path(p)))
+        .unwrap_or(Format::Jdat);
+    let dats = match decode(&byts, in_format) {
+        Ok(dats) => dats,
+        Err(e) => return Err(err!(e,
+            "Invalid {:?} input from {}.", in_format, source;
+        Input, Decode, Invalid)),
+    };
+
+    let out = match &cfg.command {
+        Command::Validate => fmt!(
+            "{} is valid {:?} containing {} daticle{}.\n",
+            source, in_format, dats.len(), if dats.len() == 1 { "" } else { "s" },
+        ).into_bytes(),
+        Command::Convert => res!(encode(&dats, cfg)),
+        Command::Get(path) => {
+            let mut found = Vec::new();
+            for dat in &dats {
+                match res!(get_path(dat, path)) {
+                    Some(d) => found.push(d.clone()),
+                    None => return Err(err!(
+                        "The path {:?} was not found in {}.", path, source;
+                    Input, Missing)),
+                }
+            }
+            res!(encode(&found, cfg))
+        },
+    };
+
+    match &cfg.output {
+        Some(path) => if let Err(e) = fs::write(path, &out) {
+            return Err(err!(e,
+                "While writing the output file '{}'.", path.display();
+            IO, File, Write));
+        },
+        None => {
+            res!(stdout.write_all(&out));
+            res!(stdout.flush());
+        },
+    }
+    Ok(())
+}
+
+/// Decodes all the daticles in the input.  Text contains a single top-level daticle, while the
+/// binary formats may hold a sequence.
+pub fn decode(byts: &[u8], format: Format) -> Outcome<Vec<Dat>> {
+    if format.is_text() {
+        let text = match std::str::from_utf8(byts) {
+            Ok(text) => text,
+            Err(e) => return Err(err!(e,
+                "The text is not valid UTF-8.";
+            Input, Decode, String, Invalid)),
+        };
+        return Ok(vec![res!(Dat::decode_string(text))]);
+    }
+    let mut dats = Vec::new();
+    let mut pos: usize = 0;
+    while pos < byts.len() {
+        let result = match format {
+            Format::Binary  => Dat::from_bytes(&byts[pos..]),
+            Format::Cbor    => Dat::from_cbor(&byts[pos..]),
+            _               => Dat::from_msgpack(&byts[pos..]),
+        };
+        let (dat, n) = match result {
+            Ok(r) => r,
+            Err(e) => return Err(err!(e,
+                "While decoding the daticle at byte {}.", pos;
+            Input, Decode, Bytes)),
+        };
+        if n == 0 {
+            return Err(err!(
+                "No progress decoding the daticle at byte {}.", pos;
+            Input, Decode, Bytes, Invalid));
+        }
+        dats.push(dat);
+        pos += n;
+    }
+    Ok(dats)
+}
+
+/// Encodes the daticles, with text outputs separated by newlines.
+pub fn encode(dats: &[Dat], cfg: &CliConfig) -> Outcome<Vec<u8>> {
+    let mut buf = Vec::new();
+    if cfg.out_format.is_text() {
+        let enc_cfg = cfg.encoder_config();
+        for dat in dats {
+            let s = res!(dat.encode_string_with_config(&enc_cfg));
+            buf.extend_from_slice(s.as_bytes());
+            buf.push(b'\n');
+        }
+    } else {
+        for dat in dats {
+            buf = match cfg.out_format {
+                Format::Binary  => res!(dat.to_bytes(buf)),
+                Format::Cbor    => res!(dat.to_cbor(buf)),
+                _               => res!(dat.to_msgpack(buf)),
+            };
+        }
+    }
+    Ok(buf)
+}
+
+/// Interprets a path given as a JDAT list, or in slash separated form.
+pub fn parse_path(s: &str) -> Outcome<Vec<Dat>> {
+    let s = s.trim();
+    if s.starts_with('[') {
+        return match res!(Dat::decode_string(s)) {
+            Dat::List(v) => Ok(v),
+            Dat::Vek(v) => Ok(v.0),
+            d => Err(err!(
+                "The path {:?} should be a list.", d;
+            Input, Invalid)),
+        };
+    }
+    let mut path = Vec::new();
+    for seg in s.split('/').filter(|seg| !seg.is_empty()) {
+        if seg.chars().all(|c| c.is_ascii_digit()) {
+            match seg.parse::<u64>() {
+                Ok(n) => path.push(Dat::canonical_uint(n as u128)),
+                Err(_) => path.push(dat!(seg)),
+            }
+        } else {
+            path.push(dat!(seg));
+        }
+    }
+    Ok(path)
+}
+
+/// Returns the daticle found by following the path of map keys and list indices.  Map keys are
+/// compared in canonical form, and an integer path segment that is not found as a map key is also
+/// tried as a string.  Boxes and annotations are transparent.
+pub fn get_path<'a>(dat: &'a Dat, path: &[Dat]) -> Outcome<Option<&'a Dat>> {
+    let mut dat = dat;
+    for key in path {
+        loop {
+            dat = match dat {
+                Dat::ABox(_, inner, _) => inner,
+                Dat::Box(inner) => inner,
+                Dat::Opt(inner) => match &**inner {
+                    Some(inner) => inner,
+                    None => return Ok(None),
+                },
+                _ => break,
+            };
+        }
+        let key = res!(key.clone().into_canonical());
+        let alt = match &key {
+            Dat::U8(n)  => Some(dat!(n.to_string())),
+            Dat::U16(n) => Some(dat!(n.to_string())),
+            Dat::U32(n) => Some(dat!(n.to_string())),
+            Dat::U64(n) => Some(dat!(n.to_string())),
+            _ => None,
+        };
+        let next = match dat {
+            Dat::Map(map) => {
+                let mut next = None;
+                for k in [Some(&key), alt.as_ref()].into_iter().flatten() {
+                    for (mk, v) in map {
+                        if res!(mk.clone().into_canonical()) == *k {
+                            next = Some(v);
+                            break;
+                        }
+                    }
+                    if next.is_some() {
+                        break;
+                    }
+                }
+                next
+            },
+            Dat::OrdMap(map) => {
+                let mut next = None;
+                for k in [Some(&key), alt.as_ref()].into_iter().flatten() {
+                    for (mk, v) in map {
+                        if res!(mk.dat().clone().into_canonical()) == *k {
+                            next = Some(v);
+                            break;
+                        }
+                    }
+                    if next.is_some() {
+                        break;
+                    }
+                }
+                next
+            },
+            _ => {
+                let i = match &key {
+                    Dat::U8(n)  => *n as usize,
+                    Dat::U16(n) => *n as usize,
+                    Dat::U32(n) => *n as usize,
+                    Dat::U64(n) => try_into!(usize, *n),
+                    _ => return Ok(None),
+                };
+                element(dat, i)
+            },
+        };
+        match next {
+            Some(d) => dat = d,
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(dat))
+}
+
+/// Returns the element at the given index of a list, vek or tuple.
+fn element(dat: &Dat, i: usize) -> Option<&Dat> {
+    match dat {
+        Dat::List(v) | Dat::Vek(Vek(v)) => v.get(i),
+        Dat::Tup2(a)    => a.get(i),
+        Dat::Tup3(a)    => a.get(i),
+        Dat::Tup4(a)    => a.get(i),
+        Dat::Tup5(a)    => a.get(i),
+        Dat::Tup6(a)    => a.get(i),
+        Dat::Tup7(a)    => a.get(i),
+        Dat::Tup8(a)    => a.get(i),
+        Dat::Tup9(a)    => a.get(i),
+        Dat::Tup10(a)   => a.get(i),
+        _ => None,
+    }
+}
+
+fn parse_kind_scope(s: &str) -> Outcome<KindScope> {
+    Ok(match s.to_lowercase().as_str() {
+        "nothing"       => KindScope::Nothing,
+        "some"          => KindScope::Some,
+        "most"          => KindScope::Most,
+        "everything"    => KindScope::Everything,
+        _ => return Err(err!(
+            "Unknown kind scope '{}', expected nothing, some, most or everything.", s;
+        Input, Invalid, Unknown)),
+    })
+}
+
+fn parse_byte_encoding(s: &str) -> Outcome<ByteEncoding> {
+    Ok(match s.to_lowercase().as_str() {
+        "base2x"    => ByteEncoding::Base2x,
+        "binary"    => ByteEncoding::Binary,
+        "decimal"   => ByteEncoding::Decimal,
+        "hex"       => ByteEncoding::Hex,
+        "octal"     => ByteEncoding::Octal,
+        _ => return Err(err!(
+            "Unknown byte encoding '{}', expected base2x, binary, decimal, hex or octal.", s;
+        Input, Invalid, Unknown)),
+    })
+}
+
+fn parse_int_encoding(s: &str) -> Outcome<IntEncoding> {
+    Ok(match s.to_lowercase().as_str() {
+        "binary"    => IntEncoding::Binary,
+        "decimal"   => IntEncoding::Decimal,
+        "hex"       => IntEncoding::Hex,
+        "octal"     => IntEncoding::Octal,
+        _ => return Err(err!(
+            "Unknown integer encoding '{}', expected binary, decimal, hex or octal.", s;
+        Input, Invalid, Unknown)),
+    })
+}
@@ -0,0 +1,475 @@
+//! Encoding and decoding of daticles as CBOR (RFC 8949).
+//!
+//! | Dat                             | CBOR                                                 |
+//! |---------------------------------|------------------------------------------------------|
+//! | `Empty`                         | null (undefined is also decoded as `Empty`)          |
+//! | `Bool`                          | true, false                                          |
+//! | `U8`-`U128`, `I8`-`I128`, `Aint`| major types 0 and 1, or bignum tags 2 and 3          |
+//! | `F32`, `F64`                    | single and double precision floats (half precision   |
+//! |                                 | is decoded as `F32`)                                 |
+//! | `Adec`                          | decimal fraction tag 4                               |
+//! | `Str`                           | text string                                          |
+//! | `BU8`-`BU64`, `BC64`            | byte string                                          |
+//! | `List`, `Vek`                   | array                                                |
+//! | `Map`, `OrdMap`                 | map                                                  |
+//! | other                           | `JDAT_TAG` wrapping a byte string of JDAT binary     |
+//!
+//! Indefinite length strings, arrays and maps are accepted when decoding.  Any other tag is
+//! ignored, yielding the tagged item.
+//!
+//! ```
+//! use oxedyne_fe2o3_jdat::prelude::*;
+//! use oxedyne_fe2o3_core::prelude::*;
+//!
+//! fn main() -> Outcome<()> {
+//!     let d = mapdat!{ "id" => 1000u32, "tags" => listdat!["a", "b"], "seq" => Dat::C64(7) };
+//!     let byts = res!(d.as_cbor());
+//!     let (d2, n) = res!(Dat::from_cbor(&byts));
+//!     assert_eq!(n, byts.len());
+//!     assert_eq!(d2, mapdat!{ "id" => 1000u16, "tags" => listdat!["a", "b"], "seq" => Dat::C64(7) });
+//!     Ok(())
+//! }
+//! ```
+use crate::{
+    prelude::*,
+    interop::{
+        MAX_DEPTH,
+        Reader,
+    },
+};
+
+use oxedyne_fe2o3_core::prelude::*;
+use oxedyne_fe2o3_num::float::{
+    Float32,
+    Float64,
+};
+
+use bigdecimal::BigDecimal;
+use num_bigint::{
+    BigInt,
+    Sign,
+};
+
+
+/// An unregistered tag, in the first come first served range, wrapping the JDAT binary encoding of
+/// a daticle.  The value spells "JDAT" in ASCII.
+pub const JDAT_TAG: u64 = 0x4a44_4154;
+
+const MAJOR_UINT:   u8 = 0;
+const MAJOR_NINT:   u8 = 1;
+const MAJOR_BYTES:  u8 = 2;
+const MAJOR_TEXT:   u8 = 3;
+const MAJOR_ARRAY:  u8 = 4;
+const MAJOR_MAP:    u8 = 5;
+const MAJOR_TAG:    u8 = 6;
+const MAJOR_SIMPLE: u8 = 7;
+
+const TAG_POS_BIGNUM:   u64 = 2;
+const TAG_NEG_BIGNUM:   u64 = 3;
+const TAG_DECIMAL:      u64 = 4;
+
+const FALSE:        u8 = 0xf4;
+const TRUE:         u8 = 0xf5;
+const NULL:         u8 = 0xf6;
+const UNDEFINED:    u8 = 0xf7;
+const HALF:         u8 = 0xf9;
+const SINGLE:       u8 = 0xfa;
+const DOUBLE:       u8 = 0xfb;
+const BREAK:        u8 = 0xff;
+const INDEFINITE:   u8 = 31;
+
+impl Dat {
+
+    /// Appends the CBOR encoding of the `Dat` to the given byte buffer.
+    pub fn to_cbor(&self, mut buf: Vec<u8>) -> Outcome<Vec<u8>> {
+        match self {
+            Self::Empty             => buf.push(NULL),
+            Self::Bool(b)           => buf.push(if *b { TRUE } else { FALSE }),
+            Self::U8(n)             => cbor_head(&mut buf, MAJOR_UINT, *n as u64),
+            Self::U16(n)            => cbor_head(&mut buf, MAJOR_UINT, *n as u64),
+            Self::U32(n)            => cbor_head(&mut buf, MAJOR_UINT, *n as u64),
+            Self::U64(n)            => cbor_head(&mut buf, MAJOR_UINT, *n),
+            Self::U128(n)           => cbor_bigint(&mut buf, &BigInt::from(*n)),
+            Self::I8(n)             => cbor_int(&mut buf, *n as i64),
+            Self::I16(n)            => cbor_int(&mut buf, *n as i64),
+            Self::I32(n)            => cbor_int(&mut buf, *n as i64),
+            Self::I64(n)            => cbor_int(&mut buf, *n),
+            Self::I128(n)           => cbor_bigint(&mut buf, &BigInt::from(*n)),
+            Self::Aint(bigint)      => cbor_bigint(&mut buf, bigint),
+            Self::F32(Float32(f))   => {
+                buf.push(SINGLE);
+                buf.extend_from_slice(&f.to_be_bytes());
+            },
+            Self::F64(Float64(f))   => {
+                buf.push(DOUBLE);
+                buf.extend_from_slice(&f.to_be_bytes());
+            },
+            Self::Adec(bigdec) => {
+                // The value is the mantissa times ten to the power of the negated scale.
+                let (mantissa, scale) = bigdec.as_bigint_and_exponent();
+                cbor_head(&mut buf, MAJOR_TAG, TAG_DECIMAL);
+                cbor_head(&mut buf, MAJOR_ARRAY, 2);
+                match scale.checked_neg() {
+                    Some(exp) => cbor_int(&mut buf, exp),
+                    None => return Err(err!(
+                        "The scale {} of the decimal {} cannot be negated.", scale, bigdec;
+                    Encode, Overflow)),
+                }
+                cbor_bigint(&mut buf, &mantissa);
+            },
+            Self::Str(s) => {
+                cbor_head(&mut buf, MAJOR_TEXT, s.len() as u64);
+                buf.extend_from_slice(s.as_bytes());
+            },
+            Self::ABox(_, boxd, _) => buf = res!(boxd.to_cbor(buf)),
+            Self::List(v) | Self::Vek(Vek(v)) => {
+                cbor_head(&mut buf, MAJOR_ARRAY, v.len() as u64);
+                for d in v {
+                    buf = res!(d.to_cbor(buf));
+                }
+            },
+            Self::Map(map) => {
+                cbor_head(&mut buf, MAJOR_MAP, map.len() as u64);
+                for (k, v) in map {
+                    buf = res!(k.to_cbor(buf));
+                    buf = res!(v.to_cbor(buf));
+                }
+            },
+            Self::OrdMap(map) => {
+                cbor_head(&mut buf, MAJOR_MAP, map.len() as u64);
+                for (mk, v) in map {
+                    buf = res!(mk.dat().to_cbor(buf));
+                    buf = res!(v.to_cbor(buf));
+                }
+            },
+            Self::BU8(v)    |
+            Self::BU16(v)   |
+            Self::BU32(v)   |
+            Self::BU64(v)   |
+            Self::BC64(v)   => {
+                cbor_head(&mut buf, MAJOR_BYTES, v.len() as u64);
+                buf.extend_from_slice(v);
+            },
+            _ => {
+                let byts = res!(self.as_bytes());
+                cbor_head(&mut buf, MAJOR_TAG, JDAT_TAG);
+                cbor_head(&mut buf, MAJOR_BYTES, byts.len() as u64);
+                buf.extend_from_slice(&byts);
+            },
+        }
+        Ok(buf)
+    }
+
+    pub fn as_cbor(&self) -> Outcome<Vec<u8>> {
+        self.to_cbor(Vec::new())
+    }
+
+    /// Decodes a single CBOR item from the start of the buffer, returning the `Dat` and the number
+    /// of bytes consumed.
+    pub fn from_cbor(buf: &[u8]) -> Outcome<(Self, usize)> {
+        let mut r = Reader::new(buf);
+        let dat = res!(cbor_item(&mut r, 0));
+        Ok((dat, r.pos()))
+    }
+}
+
+fn cbor_head(buf: &mut Vec<u8>, major: u8, n: u64) {
+    let m = major << 5;
+    if n < 24 {
+        buf.push(m | n as u8);
+    } else if n <= u8::MAX as u64 {
+        buf.push(m | 24);
+        buf.push(n as u8);
+    } else if n <= u16::MAX as u64 {
+        buf.push(m | 25);
+        buf.extend_from_slice(&(n as u16).to_be_bytes());
+    } else if n <= u32::MAX as u64 {
+        buf.push(m | 26);
+        buf.extend_from_slice(&(n as u32).to_be_bytes());
+    } else {
+        buf.push(m | 27);
+        buf.extend_from_slice(&n.to_be_bytes());
+    }
+}
+
+fn cbor_int(buf: &mut Vec<u8>, n: i64) {
+    if n >= 0 {
+        cbor_head(buf, MAJOR_UINT, n as u64);
+    } else {
+        cbor_head(buf, MAJOR_NINT, (-1 - n) as u64);
+    }
+}
+
+/// Uses the major integer types where the value fits in 64 bits, otherwise a bignum tag.
+fn cbor_bigint(buf: &mut Vec<u8>, n: &BigInt) {
+    if let Ok(u) = u64::try_from(n) {
+        cbor_head(buf, MAJOR_UINT, u);
+        return;
+    }
+    // Negative integers are encoded as -1 - n.
+    let m: BigInt = -1 - n;
+    if let Ok(u) = u64::try_from(&m) {
+        cbor_head(buf, MAJOR_NINT, u);
+        return;
+    }
+    let (tag, byts) = if n.sign() == Sign::Minus {
+        (TAG_NEG_BIGNUM, m.to_bytes_be().1)
+    } else {
+        (TAG_POS_BIGNUM, n.to_bytes_be().1)
+    };
+    cbor_head(buf, MAJOR_TAG, tag);
+    cbor_head(buf, MAJOR_BYTES, byts.len() as u64);
+    buf.extend_from_slice(&byts);
+}
+
+/// Reads the major type and argument of the next item, where an argument of `None` indicates an
+/// indefinite length.
+fn cbor_read_head(r: &mut Reader) -> Outcome<(u8, Option<u64>)> {
+    let pos = r.pos();
+    let b = res!(r.byte());
+    let major = b >> 5;
+    let info = b & 0x1f;
+    let n = match info {
+        0..=23  => info as u64,
+        24      => res!(r.byte()) as u64,
+        25      => u16::from_be_bytes(res!(r.take_array::<2>())) as u64,
+        26      => u32::from_be_bytes(res!(r.take_array::<4>())) as u64,
+        27      => u64::from_be_bytes(res!(r.take_array::<8>())),
+        INDEFINITE if major >= MAJOR_BYTES && major <= MAJOR_MAP => return Ok((major, None)),
+        _ => return Err(err!(
+            "Invalid additional information {} for major type {} at byte {}.",
+            info, major, pos;
+        Bytes, Input, Decode, Invalid)),
+    };
+    Ok((major, Some(n)))
+}
+
+/// Converts a length to a `usize`, confirming that it does not exceed the bytes remaining, since
+/// each element occupies at least one byte.
+fn cbor_len(r: &Reader, n: u64) -> Outcome<usize> {
+    let remaining = r.remaining();
+    if n > remaining as u64 {
+        return Err(err!(
+            "A length of {} at byte {} exceeds the {} bytes remaining.", n, r.pos(), remaining;
+        Bytes, Input, Decode, TooBig));
+    }
+    Ok(n as usize)
+}
+
+fn cbor_string(r: &mut Reader, major: u8, n: Option<u64>) -> Outcome<Vec<u8>> {
+    match n {
+        Some(n) => {
+            let len = res!(cbor_len(r, n));
+            Ok(res!(r.take(len)).to_vec())
+        },
+        None => {
+            // An indefinite length string is a sequence of definite length chunks of the same
+            // major type.
+            let mut v = Vec::new();
+            loop {
+                if r.peek() == Some(BREAK) {
+                    res!(r.byte());
+                    return Ok(v);
+                }
+                let pos = r.pos();
+                match res!(cbor_read_head(r)) {
+                    (m, Some(n)) if m == major => {
+                        let len = res!(cbor_len(r, n));
+                        v.extend_from_slice(res!(r.take(len)));
+                    },
+                    _ => return Err(err!(
+                        "Invalid chunk within an indefinite length string at byte {}.", pos;
+                    Bytes, Input, Decode, Invalid)),
+                }
+            }
+        },
+    }
+}
+
+fn cbor_item(r: &mut Reader, depth: usize) -> Outcome<Dat> {
+    if depth > MAX_DEPTH {
+        return Err(err!(
+            "The nesting of arrays, maps and tags exceeds the maximum depth of {}.", MAX_DEPTH;
+        Bytes, Input, Decode, Excessive));
+    }
+    let pos = r.pos();
+    if let Some(b) = r.peek() {
+        if b >> 5 == MAJOR_SIMPLE {
+            res!(r.byte());
+            return match b {
+                FALSE       => Ok(Dat::Bool(false)),
+                TRUE        => Ok(Dat::Bool(true)),
+                NULL        => Ok(Dat::Empty),
+                UNDEFINED   => Ok(Dat::Empty),
+                HALF        => {
+                    let h = u16::from_be_bytes(res!(r.take_array::<2>()));
+                    Ok(Dat::F32(Float32(half_to_f32(h))))
+                },
+                SINGLE      => Ok(Dat::F32(Float32(f32::from_be_bytes(res!(r.take_array::<4>()))))),
+                DOUBLE      => Ok(Dat::F64(Float64(f64::from_be_bytes(res!(r.take_array::<8>()))))),
+                _ => Err(err!(
+                    "Unsupported simple value or break code {:#04x} at byte {}.", b, pos;
+                Bytes, Input, Decode, Unknown)),
+            };
+        }
+    }
+    let (major, n) = res!(cbor_read_head(r));
+    match major {
+        MAJOR_UINT => Ok(Dat::canonical_uint(n.unwrap_or(0) as u128)),
+        MAJOR_NINT => Ok(Dat::canonical_int(-1 - n.unwrap_or(0) as i128)),
+        MAJOR_BYTES => Ok(Dat::bytdat(res!(cbor_string(r, major, n)))),
+        MAJOR_TEXT => {
+            let byts = res!(cbor_string(r, major, n));
+            match String::from_utf8(byts) {
+                Ok(s) => Ok(Dat::Str(s)),
+                Err(e) => Err(err!(e,
+                    "Invalid UTF-8 in the text string at byte {}.", pos;
+                Bytes, Input, Decode, String, Invalid)),
+            }
+        },
+        MAJOR_ARRAY => {
+            let mut v = Vec::new();
+            match n {
+                Some(n) => {
+                    let len = res!(cbor_len(r, n));
+                    for _ in 0..len {
+                        v.push(res!(cbor_item(r, depth + 1)));
+                    }
+                },
+                None => while !res!(cbor_break(r)) {
+                    v.push(res!(cbor_item(r, depth + 1)));
+                },
+            }
+            Ok(Dat::List(v))
+        },
+        MAJOR_MAP => {
+            let mut map = DaticleMap::new();
+            let mut count: u64 = 0;
+            loop {
+                match n {
+                    Some(n) => if count >= n {
+                        break;
+                    },
+                    None => if res!(cbor_break(r)) {
+                        break;
+                    },
+                }
+                let kpos = r.pos();
+                let k = res!(cbor_item(r, depth + 1));
+                let v = res!(cbor_item(r, depth + 1));
+                if map.insert(k, v).is_some() {
+                    return Err(err!(
+                        "Duplicate map key at byte {}.", kpos;
+                    Bytes, Input, Decode, Exists));
+                }
+                count += 1;
+            }
+            Ok(Dat::Map(map))
+        },
+        MAJOR_TAG => {
+            let tag = n.unwrap_or(0);
+            match tag {
+                TAG_POS_BIGNUM | TAG_NEG_BIGNUM => {
+                    let byts = match res!(cbor_item(r, depth + 1)) {
+                        Dat::BU8(v) | Dat::BU16(v) | Dat::BU32(v) | Dat::BU64(v) => v,
+                        d => return Err(err!(
+                            "Expected a byte string for the bignum tag at byte {}, found {:?}.",
+                            pos, d;
+                        Bytes, Input, Decode, Invalid)),
+                    };
+                    let n = BigInt::from_bytes_be(Sign::Plus, &byts);
+                    Ok(Dat::canonical_aint(if tag == TAG_POS_BIGNUM { n } else { -1 - n }))
+                },
+                TAG_DECIMAL => {
+                    let parts = match res!(cbor_item(r, depth + 1)) {
+                        Dat::List(v) if v.len() == 2 => v,
+                        d => return Err(err!(
+                            "Expected an array of two integers for the decimal fraction tag \
+                            at byte {}, found {:?}.", pos, d;
+                        Bytes, Input, Decode, Invalid)),
+                    };
+                    let exp = match cbor_bigint_of(&parts[0]).and_then(|n| i64::try_from(n).ok()) {
+                        Some(exp) => exp,
+                        None => return Err(err!(
+                            "Invalid exponent {:?} for the decimal fraction at byte {}.",
+                            parts[0], pos;
+                        Bytes, Input, Decode, Invalid)),
+                    };
+                    let mantissa = match cbor_bigint_of(&parts[1]) {
+                        Some(m) => m,
+                        None => return Err(err!(
+                            "Invalid mantissa {:?} for the decimal fraction at byte {}.",
+                            parts[1], pos;
+                        Bytes, Input, Decode, Invalid)),
+                    };
+                    match exp.checked_neg() {
+                        Some(scale) => Ok(Dat::Adec(BigDecimal::new(mantissa, scale))),
+                        None => Err(err!(
+                            "The exponent {} of the decimal fraction at byte {} cannot be negated.",
+                            exp, pos;
+                        Bytes, Input, Decode, Overflow)),
+                    }
+                },
+                JDAT_TAG => {
+                    let (major, n) = res!(cbor_read_head(r));
+                    if major != MAJOR_BYTES {
+                        return Err(err!(
+                            "Expected a byte string within the JDAT tag at byte {}, found major \
+                            type {}.", pos, major;
+                        Bytes, Input, Decode, Invalid));
+                    }
+                    let byts = res!(cbor_string(r, major, n));
+                    Reader::jdat(&byts)
+                },
+                _ => cbor_item(r, depth + 1),
+            }
+        },
+        _ => Err(err!(
+            "Unsupported major type {} at byte {}.", major, pos;
+        Bytes, Input, Decode, Unknown)),
+    }
+}
+
+/// Consumes a break code, if present.
+fn cbor_break(r: &mut Reader) -> Outcome<bool> {
+    match r.peek() {
+        Some(BREAK) => {
+            res!(r.byte());
+            Ok(true)
+        },
+        Some(_) => Ok(false),
+        None => Err(err!(
+            "The bytes ended within an indefinite length array or map.";
+        Bytes, Input, Decode, Missing)),
+    }
+}
+
+/// Extracts an integer from a decoded daticle.
+fn cbor_bigint_of(d: &Dat) -> Option<BigInt> {
+    match d {
+        Dat::U8(n)      => Some(BigInt::from(*n)),
+        Dat::U16(n)     => Some(BigInt::from(*n)),
+        Dat::U32(n)     => Some(BigInt::from(*n)),
+        Dat::U64(n)     => Some(BigInt::from(*n)),
+        Dat::U128(n)    => Some(BigInt::from(*n)),
+        Dat::I8(n)      => Some(BigInt::from(*n)),
+        Dat::I16(n)     => Some(BigInt::from(*n)),
+        Dat::I32(n)     => Some(BigInt::from(*n)),
+        Dat::I64(n)     => Some(BigInt::from(*n)),
+        Dat::I128(n)    => Some(BigInt::from(*n)),
+        Dat::Aint(n)    => Some(n.clone()),
+        _ => None,
+    }
+}
+
+/// Converts an IEEE 754 half precision float to single precision.
+fn half_to_f32(h: u16) -> f32 {
+    let sign = if h & 0x8000 != 0 { -1.0f32 } else { 1.0f32 };
+    let exp = ((h >> 10) & 0x1f) as i32;
+    let frac = (h & 0x03ff) as f32;
+    sign * match exp {
+        0   => frac * 2f32.powi(-24),
+        31  => if frac == 0.0 { f32::INFINITY } else { f32::NAN },
+        _   => (1.0 + frac / 1024.0) * 2f32.powi(exp - 15),
+    }
+}
@@ -0,0 +1,101 @@
+//! Interoperability with the CBOR (RFC 8949) and MessagePack binary formats, for clients that do
+//! not speak JDAT binary.
+//!
+//! Kinds with a natural counterpart in both formats, namely `Empty`, `Bool`, the integers,
+//! floats, strings, variable length bytes, lists and maps, are mapped directly.  Because neither
+//! format records integer widths, byte length prefixes or map orderings, these kinds decode to
+//! the canonical form described in the `binary::canon` module.  An `ABox` is encoded as its
+//! contents, since the annotation is presentational.
+//!
+//! The remaining JDAT-specific kinds, such as `C64`, `Usr`, `Box`, `Opt`, the tuples and the
+//! fixed length byte arrays, are captured losslessly by wrapping their JDAT binary encoding in a
+//! CBOR tag (`cbor::JDAT_TAG`) or a MessagePack extension type (`msgpack::JDAT_EXT`), and decode
+//! exactly as they were, including any daticles they contain.  An `Adec` is likewise decoded
+//! exactly, whether as a CBOR decimal fraction or a MessagePack extension.  A round trip is
+//! therefore not the same as `Dat::into_canonical`, which also captures a `C64` as an unsigned
+//! integer and normalises an `Adec`.  Only for a `Dat` composed solely of directly mapped kinds
+//! does
+//!
+//! ```text
+//! Dat::from_cbor(&dat.as_cbor()?)?.0 == dat.into_canonical()?
+//! ```
+//!
+//! hold, and likewise for MessagePack.
+pub mod cbor;
+pub mod msgpack;
+
+use crate::prelude::*;
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    byte::FromBytes,
+};
+
+
+/// The maximum nesting of lists and maps accepted when decoding.
+pub const MAX_DEPTH: usize = 64;
+
+/// A cursor over the bytes being decoded.
+pub(crate) struct Reader<'a> {
+    buf:    &'a [u8],
+    pos:    usize,
+}
+
+impl<'a> Reader<'a> {
+
+    pub(crate) fn new(buf: &'a [u8]) -> Self {
+        Self {
+            buf,
+            pos: 0,
+        }
+    }
+
+    pub(crate) fn pos(&self) -> usize { self.pos }
+    pub(crate) fn remaining(&self) -> usize { self.buf.len() - self.pos }
+
+    pub(crate) fn peek(&self) -> Option<u8> {
+        self.buf.get(self.pos).copied()
+    }
+
+    pub(crate) fn byte(&mut self) -> Outcome<u8> {
+        match self.peek() {
+            Some(b) => {
+                self.pos += 1;
+                Ok(b)
+            },
+            None => Err(err!(
+                "Expected a further byte at position {}.", self.pos;
+            Bytes, Input, Decode, Missing)),
+        }
+    }
+
+    pub(crate) fn take(&mut self, n: usize) -> Outcome<&'a [u8]> {
+        if n > self.remaining() {
+            return Err(err!(
+                "Expected {} bytes at position {}, only {} remain.",
+                n, self.pos, self.remaining();
+            Bytes, Input, Decode, Missing));
+        }
+        let byts = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(byts)
+    }
+
+    pub(crate) fn take_array<const N: usize>(&mut self) -> Outcome<[u8; N]> {
+        let mut a = [0u8; N];
+        a.copy_from_slice(res!(self.take(N)));
+        Ok(a)
+    }
+
+    /// Decodes JDAT binary that must exactly fill the given bytes.
+    pub(crate) fn jdat(byts: &[u8]) -> Outcome<Dat> {
+        let (dat, n) = res!(Dat::from_bytes(byts));
+        if n != byts.len() {
+            return Err(err!(
+                "The wrapped JDAT binary daticle occupies {} of the {} bytes provided.",
+                n, byts.len();
+            Bytes, Input, Decode, Mismatch));
+        }
+        Ok(dat)
+    }
+}
@@ -0,0 +1,365 @@
+//! Encoding and decoding of daticles as MessagePack.
+//!
+//! | Dat                                       | MessagePack                              |
+//! |-------------------------------------------|------------------------------------------|
+//! | `Empty`                                   | nil                                      |
+//! | `Bool`                                    | true, false                              |
+//! | `U8`-`U128`, `I8`-`I128`, `Aint` within   | int                                      |
+//! | the 64 bit range                          |                                          |
+//! | `F32`, `F64`                              | float 32, float 64                       |
+//! | `Str`                                     | str                                      |
+//! | `BU8`-`BU64`, `BC64`                      | bin                                      |
+//! | `List`, `Vek`                             | array                                    |
+//! | `Map`, `OrdMap`                           | map                                      |
+//! | other                                     | ext of type `JDAT_EXT` containing JDAT   |
+//! |                                           | binary                                   |
+//!
+//! ```
+//! use oxedyne_fe2o3_jdat::prelude::*;
+//! use oxedyne_fe2o3_core::prelude::*;
+//!
+//! fn main() -> Outcome<()> {
+//!     let d = listdat![-1i64, "two", Dat::C64(3), dat!(Some(dat!(4u8)))];
+//!     let byts = res!(d.as_msgpack());
+//!     let (d2, n) = res!(Dat::from_msgpack(&byts));
+//!     assert_eq!(n, byts.len());
+//!     assert_eq!(d2, listdat![-1i8, "two", Dat::C64(3), dat!(Some(dat!(4u8)))]);
+//!     Ok(())
+//! }
+//! ```
+use crate::{
+    prelude::*,
+    interop::{
+        MAX_DEPTH,
+        Reader,
+    },
+};
+
+use oxedyne_fe2o3_core::prelude::*;
+use oxedyne_fe2o3_num::float::{
+    Float32,
+    Float64,
+};
+
+
+/// The application extension type wrapping the JDAT binary encoding of a daticle.  The value is
+/// the ASCII code for 'J'.
+pub const JDAT_EXT: i8 = 0x4a;
+
+const NIL:      u8 = 0xc0;
+const FALSE:    u8 = 0xc2;
+const TRUE:     u8 = 0xc3;
+const BIN8:     u8 = 0xc4;
+const BIN16:    u8 = 0xc5;
+const BIN32:    u8 = 0xc6;
+const EXT8:     u8 = 0xc7;
+const EXT16:    u8 = 0xc8;
+const EXT32:    u8 = 0xc9;
+const FLOAT32:  u8 = 0xca;
+const FLOAT64:  u8 = 0xcb;
+const UINT8:    u8 = 0xcc;
+const UINT16:   u8 = 0xcd;
+const UINT32:   u8 = 0xce;
+const UINT64:   u8 = 0xcf;
+const INT8:     u8 = 0xd0;
+const INT16:    u8 = 0xd1;
+const INT32:    u8 = 0xd2;
+const INT64:    u8 = 0xd3;
+const FIXEXT1:  u8 = 0xd4;
+const FIXEXT16: u8 = 0xd8;
+const STR8:     u8 = 0xd9;
+const STR16:    u8 = 0xda;
+const STR32:    u8 = 0xdb;
+const ARRAY16:  u8 = 0xdc;
+const ARRAY32:  u8 = 0xdd;
+const MAP16:    u8 = 0xde;
+const MAP32:    u8 = 0xdf;
+
+impl Dat {
+
+    /// Appends the MessagePack encoding of the `Dat` to the given byte buffer.
+    pub fn to_msgpack(&self, mut buf: Vec<u8>) -> Outcome<Vec<u8>> {
+        match self {
+            Self::Empty             => buf.push(NIL),
+            Self::Bool(b)           => buf.push(if *b { TRUE } else { FALSE }),
+            Self::U8(n)             => msgpack_uint(&mut buf, *n as u64),
+            Self::U16(n)            => msgpack_uint(&mut buf, *n as u64),
+            Self::U32(n)            => msgpack_uint(&mut buf, *n as u64),
+            Self::U64(n)            => msgpack_uint(&mut buf, *n),
+            Self::I8(n)             => msgpack_int(&mut buf, *n as i64),
+            Self::I16(n)            => msgpack_int(&mut buf, *n as i64),
+            Self::I32(n)            => msgpack_int(&mut buf, *n as i64),
+            Self::I64(n)            => msgpack_int(&mut buf, *n),
+            Self::U128(n) if *n <= u64::MAX as u128 => msgpack_uint(&mut buf, *n as u64),
+            Self::I128(n) if *n >= i64::MIN as i128 && *n <= i64::MAX as i128 => {
+                msgpack_int(&mut buf, *n as i64)
+            },
+            Self::Aint(bigint) if i64::try_from(bigint).is_ok() || u64::try_from(bigint).is_ok() => {
+                match i64::try_from(bigint) {
+                    Ok(n) => msgpack_int(&mut buf, n),
+                    Err(_) => msgpack_uint(&mut buf, res!(u64::try_from(bigint))),
+                }
+            },
+            // Integers beyond the 64 bit range are captured in their canonical kind, so that they
+            // decode as the directly mapped integers do.
+            Self::U128(n) => res!(msgpack_jdat(&mut buf, &Self::canonical_uint(*n))),
+            Self::I128(n) => res!(msgpack_jdat(&mut buf, &Self::canonical_int(*n))),
+            Self::Aint(bigint) => res!(msgpack_jdat(&mut buf, &Self::canonical_aint(bigint.clone()))),
+            Self::F32(Float32(f))   => {
+                buf.push(FLOAT32);
+                buf.extend_from_slice(&f.to_be_bytes());
+            },
+            Self::F64(Float64(f))   => {
+                buf.push(FLOAT64);
+                buf.extend_from_slice(&f.to_be_bytes());
+            },
+            Self::Str(s) => {
+                res!(msgpack_len(&mut buf, s.len(), Some(0xa0), 32, Some(STR8), STR16, STR32));
+                buf.extend_from_slice(s.as_bytes());
+            },
+            Self::ABox(_, boxd, _) => buf = res!(boxd.to_msgpack(buf)),
+            Self::List(v) | Self::Vek(Vek(v)) => {
+                res!(msgpack_len(&mut buf, v.len(), Some(0x90), 16, None, ARRAY16, ARRAY32));
+                for d in v {
+                    buf = res!(d.to_msgpack(buf));
+                }
+            },
+            Self::Map(map) => {
+                res!(msgpack_len(&mut buf, map.len(), Some(0x80), 16, None, MAP16, MAP32));
+                for (k, v) in map {
+                    buf = res!(k.to_msgpack(buf));
+                    buf = res!(v.to_msgpack(buf));
+                }
+            },
+            Self::OrdMap(map) => {
+                res!(msgpack_len(&mut buf, map.len(), Some(0x80), 16, None, MAP16, MAP32));
+                for (mk, v) in map {
+                    buf = res!(mk.dat().to_msgpack(buf));
+                    buf = res!(v.to_msgpack(buf));
+                }
+            },
+            Self::BU8(v)    |
+            Self::BU16(v)   |
+            Self::BU32(v)   |
+            Self::BU64(v)   |
+            Self::BC64(v)   => {
+                res!(msgpack_len(&mut buf, v.len(), None, 0, Some(BIN8), BIN16, BIN32));
+                buf.extend_from_slice(v);
+            },
+            _ => res!(msgpack_jdat(&mut buf, self)),
+        }
+        Ok(buf)
+    }
+
+    pub fn as_msgpack(&self) -> Outcome<Vec<u8>> {
+        self.to_msgpack(Vec::new())
+    }
+
+    /// Decodes a single MessagePack object from the start of the buffer, returning the `Dat` and
+    /// the number of bytes consumed.
+    pub fn from_msgpack(buf: &[u8]) -> Outcome<(Self, usize)> {
+        let mut r = Reader::new(buf);
+        let dat = res!(msgpack_object(&mut r, 0));
+        Ok((dat, r.pos()))
+    }
+}
+
+/// Appends the JDAT binary encoding of the `Dat` as a `JDAT_EXT` extension.
+fn msgpack_jdat(buf: &mut Vec<u8>, dat: &Dat) -> Outcome<()> {
+    let byts = res!(dat.as_bytes());
+    match byts.len() {
+        1 | 2 | 4 | 8 | 16 => {
+            buf.push(FIXEXT1 + byts.len().trailing_zeros() as u8);
+        },
+        len => res!(msgpack_len(buf, len, None, 0, Some(EXT8), EXT16, EXT32)),
+    }
+    buf.push(JDAT_EXT as u8);
+    buf.extend_from_slice(&byts);
+    Ok(())
+}
+
+fn msgpack_uint(buf: &mut Vec<u8>, n: u64) {
+    if n < 0x80 {
+        buf.push(n as u8);
+    } else if n <= u8::MAX as u64 {
+        buf.push(UINT8);
+        buf.push(n as u8);
+    } else if n <= u16::MAX as u64 {
+        buf.push(UINT16);
+        buf.extend_from_slice(&(n as u16).to_be_bytes());
+    } else if n <= u32::MAX as u64 {
+        buf.push(UINT32);
+        buf.extend_from_slice(&(n as u32).to_be_bytes());
+    } else {
+        buf.push(UINT64);
+        buf.extend_from_slice(&n.to_be_bytes());
+    }
+}
+
+fn msgpack_int(buf: &mut Vec<u8>, n: i64) {
+    if n >= 0 {
+        msgpack_uint(buf, n as u64);
+    } else if n >= -32 {
+        buf.push(n as i8 as u8);
+    } else if n >= i8::MIN as i64 {
+        buf.push(INT8);
+        buf.push(n as i8 as u8);
+    } else if n >= i16::MIN as i64 {
+        buf.push(INT16);
+        buf.extend_from_slice(&(n as i16).to_be_bytes());
+    } else if n >= i32::MIN as i64 {
+        buf.push(INT32);
+        buf.extend_from_slice(&(n as i32).to_be_bytes());
+    } else {
+        buf.push(INT64);
+        buf.extend_from_slice(&n.to_be_bytes());
+    }
+}
+
+/// Writes a length using the fix format below `fix_limit` where available, otherwise the
+/// smallest of the 8, 16 or 32 bit formats available.
+fn msgpack_len(
+    buf:        &mut Vec<u8>,
+    len:        usize,
+    fix_opt:    Option<u8>,
+    fix_limit:  usize,
+    code8_opt:  Option<u8>,
+    code16:     u8,
+    code32:     u8,
+)
+    -> Outcome<()>
+{
+    if let Some(fix) = fix_opt {
+        if len < fix_limit {
+            buf.push(fix | len as u8);
+            return Ok(());
+        }
+    }
+    match code8_opt {
+        Some(code8) if len <= u8::MAX as usize => {
+            buf.push(code8);
+            buf.push(len as u8);
+            return Ok(());
+        },
+        _ => (),
+    }
+    if len <= u16::MAX as usize {
+        buf.push(code16);
+        buf.extend_from_slice(&(len as u16).to_be_bytes());
+    } else if len <= u32::MAX as usize {
+        buf.push(code32);
+        buf.extend_from_slice(&(len as u32).to_be_bytes());
+    } else {
+        return Err(err!(
+            "The length {} exceeds the MessagePack maximum of {}.", len, u32::MAX;
+        Bytes, Encode, TooBig));
+    }
+    Ok(())
+}
+
+/// Reads a length, confirming that it does not exceed the bytes remaining, since each element
+/// occupies at least one byte.
+fn msgpack_read_len(r: &mut Reader, bits: u8) -> Outcome<usize> {
+    let pos = r.pos();
+    let len = match bits {
+        8   => res!(r.byte()) as usize,
+        16  => u16::from_be_bytes(res!(r.take_array::<2>())) as usize,
+        _   => u32::from_be_bytes(res!(r.take_array::<4>())) as usize,
+    };
+    if len > r.remaining() {
+        return Err(err!(
+            "A length of {} at byte {} exceeds the {} bytes remaining.", len, pos, r.remaining();
+        Bytes, Input, Decode, TooBig));
+    }
+    Ok(len)
+}
+
+fn msgpack_object(r: &mut Reader, depth: usize) -> Outcome<Dat> {
+    if depth > MAX_DEPTH {
+        return Err(err!(
+            "The nesting of arrays and maps exceeds the maximum depth of {}.", MAX_DEPTH;
+        Bytes, Input, Decode, Excessive));
+    }
+    let pos = r.pos();
+    let b = res!(r.byte());
+    Ok(match b {
+        0x00..=0x7f => Dat::U8(b),
+        0x80..=0x8f => res!(msgpack_map(r, (b & 0x0f) as usize, depth)),
+        0x90..=0x9f => res!(msgpack_array(r, (b & 0x0f) as usize, depth)),
+        0xa0..=0xbf => res!(msgpack_str(r, (b & 0x1f) as usize, pos)),
+        NIL     => Dat::Empty,
+        FALSE   => Dat::Bool(false),
+        TRUE    => Dat::Bool(true),
+        BIN8    => { let n = res!(msgpack_read_len(r, 8)); Dat::bytdat(res!(r.take(n)).to_vec()) },
+        BIN16   => { let n = res!(msgpack_read_len(r, 16)); Dat::bytdat(res!(r.take(n)).to_vec()) },
+        BIN32   => { let n = res!(msgpack_read_len(r, 32)); Dat::bytdat(res!(r.take(n)).to_vec()) },
+        EXT8    => { let n = res!(msgpack_read_len(r, 8)); res!(msgpack_ext(r, n, pos)) },
+        EXT16   => { let n = res!(msgpack_read_len(r, 16)); res!(msgpack_ext(r, n, pos)) },
+        EXT32   => { let n = res!(msgpack_read_len(r, 32)); res!(msgpack_ext(r, n, pos)) },
+        FLOAT32 => Dat::F32(Float32(f32::from_be_bytes(res!(r.take_array::<4>())))),
+        FLOAT64 => Dat::F64(Float64(f64::from_be_bytes(res!(r.take_array::<8>())))),
+        UINT8   => Dat::U8(res!(r.byte())),
+        UINT16  => Dat::canonical_uint(u16::from_be_bytes(res!(r.take_array::<2>())) as u128),
+        UINT32  => Dat::canonical_uint(u32::from_be_bytes(res!(r.take_array::<4>())) as u128),
+        UINT64  => Dat::canonical_uint(u64::from_be_bytes(res!(r.take_array::<8>())) as u128),
+        INT8    => Dat::canonical_int(res!(r.byte()) as i8 as i128),
+        INT16   => Dat::canonical_int(i16::from_be_bytes(res!(r.take_array::<2>())) as i128),
+        INT32   => Dat::canonical_int(i32::from_be_bytes(res!(r.take_array::<4>())) as i128),
+        INT64   => Dat::canonical_int(i64::from_be_bytes(res!(r.take_array::<8>())) as i128),
+        FIXEXT1..=FIXEXT16 => res!(msgpack_ext(r, 1 << (b - FIXEXT1), pos)),
+        STR8    => { let n = res!(msgpack_read_len(r, 8)); res!(msgpack_str(r, n, pos)) },
+        STR16   => { let n = res!(msgpack_read_len(r, 16)); res!(msgpack_str(r, n, pos)) },
+        STR32   => { let n = res!(msgpack_read_len(r, 32)); res!(msgpack_str(r, n, pos)) },
+        ARRAY16 => { let n = res!(msgpack_read_len(r, 16)); res!(msgpack_array(r, n, depth)) },
+        ARRAY32 => { let n = res!(msgpack_read_len(r, 32)); res!(msgpack_array(r, n, depth)) },
+        MAP16   => { let n = res!(msgpack_read_len(r, 16)); res!(msgpack_map(r, n, depth)) },
+        MAP32   => { let n = res!(msgpack_read_len(r, 32)); res!(msgpack_map(r, n, depth)) },
+        0xe0..=0xff => Dat::canonical_int(b as i8 as i128),
+        _ => return Err(err!(
+            "The MessagePack format code {:#04x} at byte {} is not used.", b, pos;
+        Bytes, Input, Decode, Unknown)),
+    })
+}
+
+fn msgpack_str(r: &mut Reader, n: usize, pos: usize) -> Outcome<Dat> {
+    match std::str::from_utf8(res!(r.take(n))) {
+        Ok(s) => Ok(Dat::Str(s.to_string())),
+        Err(e) => Err(err!(e,
+            "Invalid UTF-8 in the str at byte {}.", pos;
+        Bytes, Input, Decode, String, Invalid)),
+    }
+}
+
+fn msgpack_array(r: &mut Reader, n: usize, depth: usize) -> Outcome<Dat> {
+    let mut v = Vec::with_capacity(std::cmp::min(n, r.remaining()));
+    for _ in 0..n {
+        v.push(res!(msgpack_object(r, depth + 1)));
+    }
+    Ok(Dat::List(v))
+}
+
+fn msgpack_map(r: &mut Reader, n: usize, depth: usize) -> Outcome<Dat> {
+    let mut map = DaticleMap::new();
+    for _ in 0..n {
+        let pos = r.pos();
+        let k = res!(msgpack_object(r, depth + 1));
+        let v = res!(msgpack_object(r, depth + 1));
+        if map.insert(k, v).is_some() {
+            return Err(err!(
+                "Duplicate map key at byte {}.", pos;
+            Bytes, Input, Decode, Exists));
+        }
+    }
+    Ok(Dat::Map(map))
+}
+
+fn msgpack_ext(r: &mut Reader, n: usize, pos: usize) -> Outcome<Dat> {
+    let typ = res!(r.byte()) as i8;
+    let byts = res!(r.take(n));
+    if typ != JDAT_EXT {
+        return Err(err!(
+            "Unsupported extension type {} at byte {}, expected {}.", typ, pos, JDAT_EXT;
+        Bytes, Input, Decode, Unknown));
+    }
+    Reader::jdat(byts)
+}
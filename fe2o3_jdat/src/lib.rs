@@ -110,16 +110,19 @@ pub mod macros;
 pub mod binary;
 pub mod cfg;
 pub mod chunk;
+pub mod cli;
 pub mod constant;
 pub mod conv;
 pub mod daticle;
 pub mod file;
 pub mod id;
 pub mod int;
+pub mod interop;
 pub mod kind;
 pub mod map;
 pub mod note;
 pub mod prelude;
+pub mod stream;
 pub mod string;
 pub mod usr;
 pub mod version;
@@ -0,0 +1,22 @@
+#![forbid(unsafe_code)]
+use oxedyne_fe2o3_jdat::cli::{
+    self,
+    CliConfig,
+};
+
+use std::io;
+
+
+fn main() {
+
+    let outcome = match CliConfig::from_args(std::env::args().skip(1)) {
+        Ok(cfg) => cli::run(&cfg, &mut io::stdin().lock(), &mut io::stdout().lock()),
+        Err(e) => Err(e),
+    };
+
+    if let Err(e) = outcome {
+        eprintln!("jdat: {}", e);
+        eprintln!("Try 'jdat --help' for usage.");
+        std::process::exit(1);
+    }
+}
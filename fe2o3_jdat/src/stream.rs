@@ -0,0 +1,46 @@
+//! The events and configuration shared by the push-based binary and text decoders,
+//! `binary::stream::StreamDecoder` and `string::stream::StreamDecoder`.
+//!
+use crate::prelude::*;
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum StreamEvent {
+    /// A complete daticle.  Within a map, daticles alternate between keys and values.
+    Dat(Dat),
+    /// The opening of a list or map.
+    Start(Kind),
+    /// The closing of the most recently opened list or map.
+    End(Kind),
+}
+
+#[derive(Clone, Debug)]
+pub struct StreamConfig {
+    /// Emit `StreamEvent::Start` and `StreamEvent::End` for lists and maps rather than yielding
+    /// them as a single daticle.
+    pub events:     bool,
+    /// The maximum number of bytes that will be buffered while waiting for a daticle to be
+    /// completed.
+    pub max_buffer: usize,
+    /// The maximum nesting of lists and maps when `events` is set.
+    pub max_depth:  usize,
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self {
+            events:     false,
+            max_buffer: 64 * 1024 * 1024,
+            max_depth:  64,
+        }
+    }
+}
+
+impl StreamConfig {
+    pub fn events() -> Self {
+        Self {
+            events: true,
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,814 @@
+//! A lossless concrete syntax tree for JDAT text, allowing tools to edit configuration files
+//! without disturbing comments, whitespace, key order or number formatting.
+//!
+//! The regular decoder produces a `Dat`, from which the original text cannot be recovered.  A
+//! `Cst` instead captures the text of every list and map entry, together with the surrounding
+//! "trivia" (whitespace and comments), so that `Cst::render` reproduces the input exactly.  Lists
+//! and maps, including those wrapped in a kind such as `(map|{...})`, are parsed into
+//! `CstMolecule`s.  All other daticles are held verbatim as `CstNode::Atom`s.
+//!
+//! Entries are addressed by a path of `Dat` keys, where a map key is matched against the decoded
+//! text of each key in canonical form, and a list entry is addressed by an integer index.  Keys
+//! and values are decoded with the configuration given when the text was parsed.  Edits
+//! re-encode only the affected value, leaving the remainder of the text untouched.
+//!
+//! ```
+//! use oxedyne_fe2o3_jdat::{
+//!     prelude::*,
+//!     string::cst::Cst,
+//! };
+//! use oxedyne_fe2o3_core::prelude::*;
+//!
+//! fn main() -> Outcome<()> {
+//!     let text = "{\n    ! The port. !\n    \"port\": 0x1f90,\n    \"host\": \"localhost\",\n}";
+//!     let mut cst = res!(Cst::parse(text));
+//!     assert_eq!(cst.render(), text);
+//!
+//!     res!(cst.set(&[dat!("port")], &dat!(8443u16)));
+//!     assert_eq!(
+//!         cst.render(),
+//!         "{\n    ! The port. !\n    \"port\": (u16|8443),\n    \"host\": \"localhost\",\n}",
+//!     );
+//!     Ok(())
+//! }
+//! ```
+use crate::{
+    prelude::*,
+    string::{
+        dec::DecoderConfig,
+        enc::EncoderConfig,
+    },
+    usr::{
+        UsrKind,
+        UsrKindCode,
+        UsrKindId,
+    },
+};
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    map::MapMut,
+};
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs,
+    path::Path,
+};
+
+
+/// A parsed JDAT text, consisting of a single top-level daticle and its surrounding trivia,
+/// together with the decoder configuration used to parse it.
+#[derive(Clone, Debug)]
+pub struct Cst<
+    M1: MapMut<UsrKindCode, UsrKind> + Clone + fmt::Debug + Default
+        = BTreeMap<UsrKindCode, UsrKind>,
+    M2: MapMut<String, UsrKindId> + Clone + fmt::Debug + Default
+        = BTreeMap<String, UsrKindId>,
+> {
+    pub leading:    String,
+    pub root:       CstNode,
+    pub trailing:   String,
+    pub cfg:        DecoderConfig<M1, M2>,
+}
+
+/// Two texts are equal when they render identically.
+impl<
+    M1: MapMut<UsrKindCode, UsrKind> + Clone + fmt::Debug + Default,
+    M2: MapMut<String, UsrKindId> + Clone + fmt::Debug + Default,
+>
+    PartialEq for Cst<M1, M2>
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.leading == other.leading && self.root == other.root && self.trailing == other.trailing
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CstNode {
+    /// The verbatim text of a daticle that is not a list or map.
+    Atom(String),
+    Molecule(CstMolecule),
+}
+
+/// A list or map.  The text renders as the `prefix`, the opening bracket, the entries, the `tail`,
+/// the closing bracket and then the `suffix`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CstMolecule {
+    /// Any kind label preceding the opening bracket, e.g. `(map|`.
+    pub prefix:     String,
+    /// Either `[` or `{`.
+    pub open:       char,
+    pub entries:    Vec<CstEntry>,
+    /// Trivia after the last entry.
+    pub tail:       String,
+    /// Any text closing the kind label, e.g. `)`.
+    pub suffix:     String,
+}
+
+/// A list or map entry.  The text renders as the `leading` trivia, the `key` (maps only), the
+/// `colon` including surrounding whitespace, the `value`, the `separator` (a comma, possibly
+/// preceded by spaces, or nothing) and the `trailing` trivia up to the end of the line.  Comments
+/// on the lines above an entry, and on the same line after it, therefore belong to the entry.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CstEntry {
+    pub leading:    String,
+    pub key:        Option<CstNode>,
+    pub colon:      String,
+    pub value:      CstNode,
+    pub separator:  String,
+    pub trailing:   String,
+}
+
+impl<
+    M1: MapMut<UsrKindCode, UsrKind> + Clone + fmt::Debug + Default,
+    M2: MapMut<String, UsrKindId> + Clone + fmt::Debug + Default,
+>
+    fmt::Display for Cst<M1, M2>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}{}", self.leading, self.root, self.trailing)
+    }
+}
+
+impl fmt::Display for CstNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Atom(s) => write!(f, "{}", s),
+            Self::Molecule(m) => write!(f, "{}", m),
+        }
+    }
+}
+
+impl fmt::Display for CstMolecule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.prefix, self.open)?;
+        for entry in &self.entries {
+            write!(f, "{}", entry)?;
+        }
+        write!(f, "{}{}{}", self.tail, self.close(), self.suffix)
+    }
+}
+
+impl fmt::Display for CstEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.leading)?;
+        if let Some(key) = &self.key {
+            write!(f, "{}{}", key, self.colon)?;
+        }
+        write!(f, "{}{}{}", self.value, self.separator, self.trailing)
+    }
+}
+
+impl CstMolecule {
+
+    pub fn close(&self) -> char {
+        if self.open == '{' { '}' } else { ']' }
+    }
+
+    pub fn is_map(&self) -> bool {
+        self.open == '{'
+    }
+
+    /// Returns the index of the entry with the given key in a map, or at the given index in a
+    /// list.  Map keys are decoded using the given configuration.
+    pub fn find<
+        M1: MapMut<UsrKindCode, UsrKind> + Clone + fmt::Debug + Default,
+        M2: MapMut<String, UsrKindId> + Clone + fmt::Debug + Default,
+    >(
+        &self,
+        key:    &Dat,
+        cfg:    &DecoderConfig<M1, M2>,
+    )
+        -> Outcome<Option<usize>>
+    {
+        if self.is_map() {
+            let key = res!(key.clone().into_canonical());
+            for (i, entry) in self.entries.iter().enumerate() {
+                if let Some(CstNode::Atom(text)) = &entry.key {
+                    let k = match Dat::decode_string_with_config(text.clone(), cfg) {
+                        Ok(k) => res!(k.into_canonical()),
+                        Err(e) => return Err(err!(e,
+                            "While decoding the map key '{}'.", text;
+                        String, Decode)),
+                    };
+                    if k == key {
+                        return Ok(Some(i));
+                    }
+                }
+            }
+            Ok(None)
+        } else {
+            let i = res!(Cst::index(key));
+            Ok(if i < self.entries.len() { Some(i) } else { None })
+        }
+    }
+
+    /// Appends a new entry, mimicking the indentation, line breaks and commas of the previous
+    /// entry.
+    pub fn push(&mut self, key: Option<CstNode>, value: CstNode) {
+        let mut entry = CstEntry {
+            leading:    String::new(),
+            key,
+            colon:      if self.is_map() { fmt!(": ") } else { String::new() },
+            value,
+            separator:  String::new(),
+            trailing:   String::new(),
+        };
+        if let Some(last) = self.entries.last_mut() {
+            if last.key.is_some() {
+                entry.colon = last.colon.clone();
+            }
+            // The whitespace at the end of the previous leading trivia is its indentation.
+            let indent_len = last.leading.len()
+                - last.leading.trim_end_matches(' ').len();
+            entry.leading = last.leading[last.leading.len() - indent_len..].to_string();
+            if last.separator.is_empty() {
+                last.separator = fmt!(",");
+            } else {
+                entry.separator = fmt!(",");
+            }
+            if last.trailing.ends_with('\n') {
+                entry.trailing = fmt!("\n");
+            }
+        }
+        self.entries.push(entry);
+    }
+
+    /// Removes the entry at the given index.  When the last entry is removed, the new last entry
+    /// inherits its separator, so that the presence of a trailing comma is preserved.  Any text
+    /// ending the line on which the entry begins, such as that following an opening bracket, is
+    /// retained.
+    pub fn remove(&mut self, i: usize) -> Option<CstEntry> {
+        if i >= self.entries.len() {
+            return None;
+        }
+        let follows_newline = i > 0 && self.entries[i - 1].trailing.ends_with('\n');
+        let entry = self.entries.remove(i);
+        let keep = match entry.leading.find('\n') {
+            Some(n) if !follows_newline => Some(entry.leading[..=n].to_string()),
+            Some(_) => None,
+            // The entry shares a line with the next, which takes its place.
+            None => Some(entry.leading.clone()),
+        };
+        match self.entries.get_mut(i) {
+            Some(next) => if let Some(keep) = keep {
+                if keep.contains('\n') {
+                    next.leading.insert_str(0, &keep);
+                } else if !next.leading.contains('\n') {
+                    next.leading = keep;
+                }
+            },
+            None => {
+                if let Some(keep) = keep {
+                    if keep.contains('\n') {
+                        self.tail.insert_str(0, &keep);
+                    }
+                }
+                if let Some(last) = self.entries.last_mut() {
+                    last.separator = entry.separator.clone();
+                }
+            },
+        }
+        Some(entry)
+    }
+}
+
+impl Cst {
+
+    /// Parses the text using the default decoder configuration.
+    pub fn parse(text: &str) -> Outcome<Self> {
+        Self::parse_with_config(text, &DecoderConfig::default())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Outcome<Self> {
+        let path = path.as_ref();
+        match fs::read_to_string(path) {
+            Ok(s) => Self::parse(&s),
+            Err(e) => Err(err!(e,
+                "While trying to read file '{}' as JDAT text.", path.display();
+            IO, File, Read)),
+        }
+    }
+
+    fn index(key: &Dat) -> Outcome<usize> {
+        match res!(key.clone().into_canonical()) {
+            Dat::U8(n)  => Ok(n as usize),
+            Dat::U16(n) => Ok(n as usize),
+            Dat::U32(n) => Ok(try_into!(usize, n)),
+            Dat::U64(n) => Ok(try_into!(usize, n)),
+            _ => Err(err!(
+                "A list can only be indexed by a non-negative integer, not {:?}.", key;
+            Input, Invalid)),
+        }
+    }
+}
+
+impl<
+    M1: MapMut<UsrKindCode, UsrKind> + Clone + fmt::Debug + Default,
+    M2: MapMut<String, UsrKindId> + Clone + fmt::Debug + Default,
+>
+    Cst<M1, M2>
+{
+    /// Parses the text, recognising the comment characters of the given decoder configuration,
+    /// which is retained for decoding keys and values.
+    pub fn parse_with_config(
+        text:   &str,
+        cfg:    &DecoderConfig<M1, M2>,
+    )
+        -> Outcome<Self>
+    {
+        let mut parser = Parser::new(text, cfg);
+        let leading = parser.trivia();
+        if parser.peek().is_none() {
+            return Err(err!(
+                "The text contains no daticle.";
+            String, Input, Decode, Missing));
+        }
+        let root = res!(parser.node(false));
+        let trailing = parser.trivia();
+        if let Some(c) = parser.peek() {
+            return Err(err!(
+                "Unexpected '{}' found at character {} after the top-level daticle.",
+                c, parser.pos;
+            String, Input, Decode, Unexpected));
+        }
+        Ok(Self {
+            leading,
+            root,
+            trailing,
+            cfg: cfg.clone(),
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Outcome<()> {
+        let path = path.as_ref();
+        match fs::write(path, self.render()) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(err!(e,
+                "While trying to write JDAT text to file '{}'.", path.display();
+            IO, File, Write)),
+        }
+    }
+
+    /// Reproduces the text, including any edits.
+    pub fn render(&self) -> String {
+        self.to_string()
+    }
+
+    pub fn to_dat(&self) -> Outcome<Dat> {
+        self.to_dat_with_config(&self.cfg)
+    }
+
+    pub fn to_dat_with_config<
+        N1: MapMut<UsrKindCode, UsrKind> + Clone + fmt::Debug + Default,
+        N2: MapMut<String, UsrKindId> + Clone + fmt::Debug + Default,
+    >(
+        &self,
+        cfg: &DecoderConfig<N1, N2>,
+    )
+        -> Outcome<Dat>
+    {
+        Dat::decode_string_with_config(self.render(), cfg)
+    }
+
+    /// Returns the node at the given path, if it exists.
+    pub fn get(&self, path: &[Dat]) -> Outcome<Option<&CstNode>> {
+        let mut node = &self.root;
+        for key in path {
+            match node {
+                CstNode::Molecule(m) => match res!(m.find(key, &self.cfg)) {
+                    Some(i) => node = &m.entries[i].value,
+                    None => return Ok(None),
+                },
+                CstNode::Atom(_) => return Ok(None),
+            }
+        }
+        Ok(Some(node))
+    }
+
+    /// Decodes the node at the given path, if it exists.
+    pub fn get_dat(&self, path: &[Dat]) -> Outcome<Option<Dat>> {
+        match res!(self.get(path)) {
+            Some(node) => Ok(Some(res!(
+                Dat::decode_string_with_config(node.to_string(), &self.cfg)))),
+            None => Ok(None),
+        }
+    }
+
+    /// Replaces the value at the given path with the given `Dat`, or inserts it if the final key
+    /// is absent from the enclosing map.  The value is encoded using the JDAT configuration, with
+    /// all kinds shown when the value it replaces starts with a kind label.
+    pub fn set(&mut self, path: &[Dat], dat: &Dat) -> Outcome<()> {
+        let full = match res!(self.get(path)) {
+            Some(CstNode::Atom(text)) => text.starts_with('('),
+            Some(CstNode::Molecule(m)) => !m.prefix.is_empty(),
+            None => false,
+        };
+        let cfg = if full {
+            EncoderConfig::<(), ()>::jdat_full(None)
+        } else {
+            EncoderConfig::<(), ()>::jdat(None)
+        };
+        self.set_with_config(path, dat, &cfg)
+    }
+
+    pub fn set_with_config<
+        N1: MapMut<UsrKindCode, UsrKind> + Clone + fmt::Debug + Default,
+        N2: MapMut<String, UsrKindId> + Clone + fmt::Debug + Default,
+    >(
+        &mut self,
+        path:   &[Dat],
+        dat:    &Dat,
+        cfg:    &EncoderConfig<N1, N2>,
+    )
+        -> Outcome<()>
+    {
+        let text = res!(dat.encode_string_with_config(cfg));
+        self.set_text(path, &text)
+    }
+
+    /// Replaces the value at the given path with the given JDAT text, or inserts it if the final
+    /// key is absent from the enclosing map.  An empty path replaces the root.
+    pub fn set_text(&mut self, path: &[Dat], text: &str) -> Outcome<()> {
+        let value = res!(self.parse_node(text));
+        let (last, parent_path) = match path.split_last() {
+            Some(split) => split,
+            None => {
+                self.root = value;
+                return Ok(());
+            },
+        };
+        let (parent, cfg) = res!(self.molecule_mut(parent_path));
+        match res!(parent.find(last, cfg)) {
+            Some(i) => parent.entries[i].value = value,
+            None => {
+                if !parent.is_map() {
+                    return Err(err!(
+                        "The index {:?} is beyond the end of the list.", last;
+                    Input, Invalid, Missing));
+                }
+                let key = res!(last.encode_string_with_config(
+                    &EncoderConfig::<(), ()>::jdat(None)));
+                parent.push(Some(CstNode::Atom(key)), value);
+            },
+        }
+        Ok(())
+    }
+
+    /// Removes the entry at the given path, including the comments attached to it, returning
+    /// whether it was found.
+    pub fn remove(&mut self, path: &[Dat]) -> Outcome<bool> {
+        let (last, parent_path) = match path.split_last() {
+            Some(split) => split,
+            None => return Err(err!(
+                "The root of the text cannot be removed.";
+            Input, Invalid)),
+        };
+        let (parent, cfg) = res!(self.molecule_mut(parent_path));
+        Ok(match res!(parent.find(last, cfg)) {
+            Some(i) => parent.remove(i).is_some(),
+            None => false,
+        })
+    }
+
+    /// Returns the list or map at the given path, along with the decoder configuration.
+    fn molecule_mut(
+        &mut self,
+        path: &[Dat],
+    )
+        -> Outcome<(&mut CstMolecule, &DecoderConfig<M1, M2>)>
+    {
+        let cfg = &self.cfg;
+        let mut node = &mut self.root;
+        for key in path {
+            node = match node {
+                CstNode::Molecule(m) => match res!(m.find(key, cfg)) {
+                    Some(i) => &mut m.entries[i].value,
+                    None => return Err(err!(
+                        "The key {:?} in path {:?} does not exist.", key, path;
+                    Input, Missing)),
+                },
+                CstNode::Atom(text) => return Err(err!(
+                    "The key {:?} in path {:?} cannot be found in '{}', which is not a list \
+                    or map.", key, path, text;
+                Input, Invalid)),
+            };
+        }
+        match node {
+            CstNode::Molecule(m) => Ok((m, cfg)),
+            CstNode::Atom(text) => Err(err!(
+                "The path {:?} leads to '{}', which is not a list or map.", path, text;
+            Input, Invalid)),
+        }
+    }
+
+    fn parse_node(&self, text: &str) -> Outcome<CstNode> {
+        let cst = res!(Self::parse_with_config(text, &self.cfg));
+        if !cst.leading.is_empty() || !cst.trailing.is_empty() {
+            return Err(err!(
+                "The replacement text '{}' should not be surrounded by whitespace or comments.",
+                text;
+            Input, Invalid));
+        }
+        Ok(cst.root)
+    }
+}
+
+struct Parser {
+    chars:      Vec<char>,
+    pos:        usize,
+    comment:    Option<[(char, char); 2]>,
+}
+
+impl Parser {
+
+    fn new<
+        M1: MapMut<UsrKindCode, UsrKind> + Clone + fmt::Debug + Default,
+        M2: MapMut<String, UsrKindId> + Clone + fmt::Debug + Default,
+    >(
+        text:   &str,
+        cfg:    &DecoderConfig<M1, M2>,
+    )
+        -> Self
+    {
+        Self {
+            chars:      text.chars().collect(),
+            pos:        0,
+            comment:    if cfg.comment_allowed {
+                Some([
+                    (cfg.comment1_start_char, cfg.comment1_end_char),
+                    (cfg.comment2_start_char, cfg.comment2_end_char),
+                ])
+            } else {
+                None
+            },
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn text(&self, start: usize) -> String {
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    fn comment_end(&self, c: char) -> Option<char> {
+        match &self.comment {
+            Some(pairs) => pairs.iter().find(|(s, _)| *s == c).map(|(_, e)| *e),
+            None => None,
+        }
+    }
+
+    /// Skips a comment starting at the current position, which ends with the closing character or
+    /// a newline, inclusive.  Returns whether the comment ended at a newline.
+    fn skip_comment(&mut self, end: char) -> bool {
+        self.pos += 1;
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            if c == '\n' {
+                return true;
+            }
+            if c == end {
+                return false;
+            }
+        }
+        false
+    }
+
+    /// Consumes whitespace and comments.
+    fn trivia(&mut self) -> String {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c == ' ' || c == '\t' || c == '\r' || c == '\n' {
+                self.pos += 1;
+            } else if let Some(end) = self.comment_end(c) {
+                self.skip_comment(end);
+            } else {
+                break;
+            }
+        }
+        self.text(start)
+    }
+
+    /// Consumes spaces and comments up to and including the end of the line.  Spaces alone,
+    /// followed by more content on the same line, are left to lead the next entry.
+    fn line_trivia(&mut self) -> String {
+        let start = self.pos;
+        let mut comment = false;
+        while let Some(c) = self.peek() {
+            if c == ' ' || c == '\t' || c == '\r' {
+                self.pos += 1;
+            } else if c == '\n' {
+                self.pos += 1;
+                return self.text(start);
+            } else if let Some(end) = self.comment_end(c) {
+                comment = true;
+                if self.skip_comment(end) {
+                    return self.text(start);
+                }
+            } else {
+                break;
+            }
+        }
+        if !comment {
+            self.pos = start;
+        }
+        self.text(start)
+    }
+
+    /// Parses a daticle.  Within a map, a ':' at the top level of an atom separates a key from
+    /// its value, while elsewhere it is left for the decoder to judge, as in `Dat::decode_string`.
+    fn node(&mut self, in_map: bool) -> Outcome<CstNode> {
+        let start = self.pos;
+        match self.peek() {
+            Some(c) if c == '[' || c == '{' => {
+                return Ok(CstNode::Molecule(res!(self.molecule(String::new()))));
+            },
+            Some('(') => {
+                if let Some(m) = res!(self.kinded_molecule()) {
+                    return Ok(CstNode::Molecule(m));
+                }
+                self.pos = start;
+            },
+            Some(c) if c == ']' || c == '}' || c == ')' || c == ',' || (in_map && c == ':') => {
+                return Err(err!(
+                    "Expected a daticle at character {}, found '{}'.", self.pos, c;
+                String, Input, Decode, Unexpected));
+            },
+            None => return Err(err!(
+                "Expected a daticle at character {}, found the end of the text.", self.pos;
+            String, Input, Decode, Missing)),
+            _ => (),
+        }
+        res!(self.atom(in_map));
+        Ok(CstNode::Atom(self.text(start)))
+    }
+
+    /// Attempts to parse a list or map within a kind label, such as `(map|{...})`, returning
+    /// `None` if the text turns out to be some other kind of daticle.
+    fn kinded_molecule(&mut self) -> Outcome<Option<CstMolecule>> {
+        let start = self.pos;
+        self.pos += 1;
+        while let Some(c) = self.peek() {
+            if c.is_alphanumeric() || c == '_' || c == ' ' {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        if self.peek() != Some('|') {
+            return Ok(None);
+        }
+        self.pos += 1;
+        while self.peek() == Some(' ') {
+            self.pos += 1;
+        }
+        if !matches!(self.peek(), Some('[') | Some('{')) {
+            return Ok(None);
+        }
+        let prefix = self.text(start);
+        let mut m = res!(self.molecule(prefix));
+        let suffix_start = self.pos;
+        while self.peek() == Some(' ') {
+            self.pos += 1;
+        }
+        if self.peek() != Some(')') {
+            // Perhaps an annotation or other content follows the list or map.
+            return Ok(None);
+        }
+        self.pos += 1;
+        m.suffix = self.text(suffix_start);
+        Ok(Some(m))
+    }
+
+    fn molecule(&mut self, prefix: String) -> Outcome<CstMolecule> {
+        let open = match self.peek() {
+            Some(c) => c,
+            None => return Err(err!(
+                "Expected '[' or '{{' at character {}.", self.pos;
+            String, Input, Decode, Missing)),
+        };
+        let close = if open == '{' { '}' } else { ']' };
+        self.pos += 1;
+        let mut m = CstMolecule {
+            prefix,
+            open,
+            entries:    Vec::new(),
+            tail:       String::new(),
+            suffix:     String::new(),
+        };
+        loop {
+            let leading = self.trivia();
+            match self.peek() {
+                Some(c) if c == close => {
+                    self.pos += 1;
+                    m.tail = leading;
+                    return Ok(m);
+                },
+                None => return Err(err!(
+                    "The text ended before the closing '{}'.", close;
+                String, Input, Decode, Missing)),
+                _ => (),
+            }
+            let first = res!(self.node(m.is_map()));
+            let (key, colon, value) = if m.is_map() {
+                let colon_start = self.pos;
+                self.trivia();
+                if self.peek() == Some(':') {
+                    self.pos += 1;
+                    self.trivia();
+                    let colon = self.text(colon_start);
+                    let value = res!(self.node(true));
+                    (Some(first), colon, value)
+                } else {
+                    // A map entry without a value.
+                    self.pos = colon_start;
+                    (None, String::new(), first)
+                }
+            } else {
+                (None, String::new(), first)
+            };
+            let sep_start = self.pos;
+            while self.peek() == Some(' ') {
+                self.pos += 1;
+            }
+            let separator = if self.peek() == Some(',') {
+                self.pos += 1;
+                self.text(sep_start)
+            } else {
+                self.pos = sep_start;
+                String::new()
+            };
+            let trailing = self.line_trivia();
+            let more = separator.len() > 0;
+            m.entries.push(CstEntry {
+                leading,
+                key,
+                colon,
+                value,
+                separator,
+                trailing,
+            });
+            if !more {
+                let tail = self.trivia();
+                if self.peek() == Some(close) {
+                    self.pos += 1;
+                    m.tail = tail;
+                    return Ok(m);
+                }
+                return Err(err!(
+                    "Expected ',' or '{}' at character {}.", close, self.pos;
+                String, Input, Decode, Missing));
+            }
+        }
+    }
+
+    /// Consumes an atom, including any balanced brackets and quotes it contains.
+    fn atom(&mut self, in_map: bool) -> Outcome<()> {
+        let start = self.pos;
+        let mut depth: usize = 0;
+        while let Some(c) = self.peek() {
+            if let Some(end) = self.comment_end(c) {
+                if depth == 0 {
+                    break;
+                }
+                self.skip_comment(end);
+                continue;
+            }
+            match c {
+                '"' | '\'' => {
+                    self.pos += 1;
+                    loop {
+                        match self.peek() {
+                            Some(q) if q == c => break,
+                            Some(_) => self.pos += 1,
+                            None => return Err(err!(
+                                "The quote starting at character {} is not closed.", start;
+                            String, Input, Decode, Missing)),
+                        }
+                    }
+                },
+                '(' | '[' | '{' => depth += 1,
+                ')' | ']' | '}' => {
+                    if depth == 0 {
+                        break;
+                    }
+                    depth -= 1;
+                },
+                ' ' | '\t' | '\r' | '\n' | ',' if depth == 0 => break,
+                ':' if depth == 0 && in_map => break,
+                _ => (),
+            }
+            self.pos += 1;
+        }
+        if depth > 0 {
+            return Err(err!(
+                "The daticle starting at character {} is not closed.", start;
+            String, Input, Decode, Missing));
+        }
+        Ok(())
+    }
+}
@@ -1,3 +1,5 @@
 pub mod core;
+pub mod cst;
 pub mod dec;
 pub mod enc;
+pub mod stream;
@@ -0,0 +1,496 @@
+//! A push-based text decoder for daticles arriving in arbitrary chunks, such as from a socket
+//! or a large file.
+//!
+//! The `StreamDecoder` scans incoming text for the boundaries of top-level daticles, tracking
+//! brackets, quotes and comments, and hands each complete daticle to the regular string decoder.
+//! Top-level daticles may be separated by spaces, newlines or commas, while tabs outside quotes
+//! are rejected, as by the regular decoder.  Bytes may be split anywhere, including within a
+//! multi-byte UTF-8 character.
+//!
+//! Decoded `StreamEvent`s are drawn from the decoder as an `Iterator`.  By default, each complete
+//! top-level daticle is yielded as a `StreamEvent::Dat`.  When `StreamConfig::events` is set,
+//! unkinded lists (`[...]`) and maps (`{...}`) are not buffered whole, but opened with a
+//! `StreamEvent::Start`, followed by their contents and closed with a `StreamEvent::End`, as for
+//! the binary `StreamDecoder`.  Explicitly kinded lists and maps, such as `(vek|[...])`,
+//! are still yielded whole.  Comments within an opened list or map are attached to the adjacent
+//! item in a `Dat::ABox`, and line comments are only allowed within lists and maps, as by
+//! `Dat::decode_string`.  A comment following a list or map that was itself opened and closed
+//! with events cannot be attached to it, and is dropped.
+//!
+//! ```
+//! use oxedyne_fe2o3_jdat::{
+//!     prelude::*,
+//!     string::stream::{
+//!         StreamDecoder,
+//!         StreamEvent,
+//!     },
+//! };
+//! use oxedyne_fe2o3_core::prelude::*;
+//!
+//! fn main() -> Outcome<()> {
+//!     let text = "(u8|1)\n[2, 3]\n\"four\"\n";
+//!     let mut decoder = StreamDecoder::default();
+//!     for chunk in text.as_bytes().chunks(4) {
+//!         res!(decoder.push(chunk));
+//!     }
+//!     res!(decoder.finish());
+//!     let events: Vec<StreamEvent> = decoder.collect();
+//!     assert_eq!(events, vec![
+//!         StreamEvent::Dat(dat!(1u8)),
+//!         StreamEvent::Dat(listdat![2u8, 3u8]),
+//!         StreamEvent::Dat(dat!("four")),
+//!     ]);
+//!     Ok(())
+//! }
+//! ```
+use crate::{
+    prelude::*,
+    note::NoteConfig,
+    string::dec::{
+        CommentCapture,
+        DecoderConfig,
+    },
+    usr::{
+        UsrKind,
+        UsrKindCode,
+        UsrKindId,
+    },
+};
+pub use crate::stream::{
+    StreamConfig,
+    StreamEvent,
+};
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    map::MapMut,
+};
+use oxedyne_fe2o3_text::string::Quote;
+
+use std::{
+    collections::{
+        BTreeMap,
+        VecDeque,
+    },
+    fmt,
+    io,
+};
+
+
+/// A list or map opened when events are enabled.
+#[derive(Clone, Debug)]
+struct Open {
+    kind:   Kind,
+    // Within a map, whether a key has been yielded without its value.
+    keyed:  bool,
+}
+
+/// The last item in the innermost open list or map, held until the next separator in case a
+/// comment is to be attached to it.
+#[derive(Clone, Debug)]
+enum Held {
+    Nothing,
+    Dat(Dat),
+    // A list or map already yielded as events.
+    Molecule,
+}
+
+#[derive(Clone, Debug)]
+pub struct StreamDecoder<
+    M1: MapMut<UsrKindCode, UsrKind> + Clone + fmt::Debug + Default,
+    M2: MapMut<String, UsrKindId> + Clone + fmt::Debug + Default,
+>{
+    cfg:        StreamConfig,
+    dec_cfg:    DecoderConfig<M1, M2>,
+    // Text yet to be decoded, and a byte index into it marking the extent of the scan.
+    text:       String,
+    scan:       usize,
+    // The start of the current daticle in the text, if one has begun.
+    begin:      Option<usize>,
+    depth:      usize,
+    quote:      Quote,
+    comment:    Option<CommentCapture>,
+    // The lists and maps currently open when events are enabled.
+    stack:      Vec<Open>,
+    held:       Held,
+    // Any comment to be attached to the held item.
+    note:       String,
+    note_cfg:   NoteConfig,
+    // Trailing bytes of an incomplete UTF-8 character.
+    partial:    Vec<u8>,
+    events:     VecDeque<StreamEvent>,
+    consumed:   usize,
+}
+
+impl Default for StreamDecoder<BTreeMap<UsrKindCode, UsrKind>, BTreeMap<String, UsrKindId>> {
+    fn default() -> Self {
+        Self::new(StreamConfig::default())
+    }
+}
+
+impl StreamDecoder<BTreeMap<UsrKindCode, UsrKind>, BTreeMap<String, UsrKindId>> {
+
+    /// A decoder using the default string decoder configuration.
+    pub fn new(cfg: StreamConfig) -> Self {
+        Self::with_decoder_config(cfg, DecoderConfig::default())
+    }
+}
+
+impl<
+    M1: MapMut<UsrKindCode, UsrKind> + Clone + fmt::Debug + Default,
+    M2: MapMut<String, UsrKindId> + Clone + fmt::Debug + Default,
+>
+    Iterator for StreamDecoder<M1, M2>
+{
+    type Item = StreamEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.events.pop_front()
+    }
+}
+
+impl<
+    M1: MapMut<UsrKindCode, UsrKind> + Clone + fmt::Debug + Default,
+    M2: MapMut<String, UsrKindId> + Clone + fmt::Debug + Default,
+>
+    StreamDecoder<M1, M2>
+{
+    /// A decoder using the given string decoder configuration, such as one with user kinds.
+    pub fn with_decoder_config(cfg: StreamConfig, dec_cfg: DecoderConfig<M1, M2>) -> Self {
+        Self {
+            cfg,
+            dec_cfg,
+            text:       String::new(),
+            scan:       0,
+            begin:      None,
+            depth:      0,
+            quote:      Quote::None,
+            comment:    None,
+            stack:      Vec::new(),
+            held:       Held::Nothing,
+            note:       String::new(),
+            note_cfg:   NoteConfig::default(),
+            partial:    Vec::new(),
+            events:     VecDeque::new(),
+            consumed:   0,
+        }
+    }
+
+    /// The number of bytes held while waiting for a daticle to be completed.
+    pub fn buffered(&self) -> usize { self.text.len() + self.partial.len() }
+    /// The total number of bytes decoded so far.
+    pub fn consumed(&self) -> usize { self.consumed }
+    /// The number of lists and maps currently open when events are enabled.
+    pub fn depth(&self) -> usize { self.stack.len() }
+
+    /// Accepts the next chunk of bytes, decoding as many daticles as possible.
+    pub fn push(&mut self, chunk: &[u8]) -> Outcome<()> {
+        self.partial.extend_from_slice(chunk);
+        let valid = match std::str::from_utf8(&self.partial) {
+            Ok(s) => {
+                self.text.push_str(s);
+                self.partial.len()
+            },
+            Err(e) => {
+                if e.error_len().is_some() {
+                    return Err(err!(
+                        "Invalid UTF-8 found at byte {}.",
+                        self.consumed + self.text.len() + e.valid_up_to();
+                    String, Input, Decode, Invalid));
+                }
+                // The remaining bytes are the start of a character yet to be completed.
+                let n = e.valid_up_to();
+                if let Ok(s) = std::str::from_utf8(&self.partial[..n]) {
+                    self.text.push_str(s);
+                }
+                n
+            },
+        };
+        self.partial.drain(..valid);
+        res!(self.process());
+        if self.buffered() > self.cfg.max_buffer {
+            return Err(err!(
+                "The daticle at byte {} exceeds the maximum buffer size of {} bytes.",
+                self.consumed, self.cfg.max_buffer;
+            String, Input, Decode, TooBig));
+        }
+        Ok(())
+    }
+
+    /// Reads a single chunk of up to `chunk_size` bytes from the reader and pushes it to the
+    /// decoder, returning the number of bytes read.  A return value of zero indicates the end of
+    /// the reader.
+    pub fn push_from<R: io::Read>(&mut self, r: &mut R, chunk_size: usize) -> Outcome<usize> {
+        let mut chunk = vec![0u8; chunk_size];
+        let n = res!(r.read(&mut chunk));
+        if n > 0 {
+            res!(self.push(&chunk[..n]));
+        }
+        Ok(n)
+    }
+
+    /// Decodes any final top-level atom, which cannot otherwise be known to be complete, and
+    /// confirms that the stream ended cleanly.
+    pub fn finish(&mut self) -> Outcome<()> {
+        if self.partial.len() > 0 {
+            return Err(err!(
+                "The stream ended within a UTF-8 character.";
+            String, Input, Decode, Missing));
+        }
+        if self.quote != Quote::None || self.depth > 0 {
+            return Err(err!(
+                "The stream ended with an incomplete daticle starting at byte {}.",
+                self.consumed + self.begin.unwrap_or(0);
+            String, Input, Decode, Missing));
+        }
+        if let Some(open) = self.stack.last() {
+            return Err(err!(
+                "The stream ended with {} open at byte {}.",
+                open.kind, self.consumed + self.text.len();
+            String, Input, Decode, Missing));
+        }
+        if let Some(begin) = self.begin.take() {
+            let end = self.text.len();
+            res!(self.decode(begin, end));
+            self.consume(end);
+        }
+        Ok(())
+    }
+
+    fn process(&mut self) -> Outcome<()> {
+        let mut consume_to: Option<usize> = None;
+        // Scan a copy of the new text, so that daticles can be decoded as they are found.
+        let offset = self.scan;
+        let fresh = self.text[offset..].to_string();
+        for (j, c) in fresh.char_indices() {
+            let i = offset + j;
+            if let Some(capture) = &self.comment {
+                let end_char = match capture {
+                    CommentCapture::Type1 => self.dec_cfg.comment1_end_char,
+                    CommentCapture::Type2 => self.dec_cfg.comment2_end_char,
+                };
+                let noting = self.depth == 0 && self.stack.len() > 0;
+                if c == end_char || c == '\n' {
+                    self.comment = None;
+                    if noting {
+                        self.note = self.note.trim_start().to_string();
+                        if c == '\n' {
+                            self.release(true);
+                        }
+                    } else if self.depth == 0 && c == '\n' {
+                        return Err(err!(
+                            "Line comment ending at byte {}, but line comments are only allowed \
+                            within lists and maps.", self.consumed + i;
+                        String, Input, Decode, Invalid));
+                    }
+                } else if noting && self.dec_cfg.comment_capture {
+                    self.note.push(c);
+                }
+                continue;
+            }
+            if self.quote != Quote::None {
+                if (c == '"' && self.quote == Quote::Double)
+                    || (c == '\'' && self.quote == Quote::Single)
+                {
+                    self.quote = Quote::None;
+                }
+                continue;
+            }
+            if self.dec_cfg.comment_allowed && (
+                c == self.dec_cfg.comment1_start_char || c == self.dec_cfg.comment2_start_char
+            ) {
+                if self.depth == 0 {
+                    if let Some(begin) = self.begin.take() {
+                        // A comment ends a top-level atom.
+                        res!(self.decode(begin, i));
+                        consume_to = Some(i);
+                    }
+                    if self.stack.len() > 0 {
+                        self.note_cfg = self.note_cfg.clone()
+                            .set_type1(c == self.dec_cfg.comment1_start_char);
+                    }
+                }
+                self.comment = Some(if c == self.dec_cfg.comment1_start_char {
+                    CommentCapture::Type1
+                } else {
+                    CommentCapture::Type2
+                });
+                continue;
+            }
+            match c {
+                '"' | '\'' if self.dec_cfg.quote_protection => {
+                    self.quote = if c == '"' { Quote::Double } else { Quote::Single };
+                    if self.begin.is_none() {
+                        self.begin = Some(i);
+                    }
+                },
+                '[' | '{' if self.cfg.events
+                    && self.depth == 0
+                    && self.begin.is_none()
+                    // A commented list or map is decoded whole, so that it can be annotated.
+                    && self.note.is_empty() =>
+                {
+                    let kind = match c {
+                        '[' => Kind::List,
+                        _ if self.dec_cfg.use_ordmaps => Kind::OrdMap,
+                        _ => Kind::Map,
+                    };
+                    if self.stack.len() >= self.cfg.max_depth {
+                        return Err(err!(
+                            "Opening a {} at byte {} would exceed the maximum depth of {}.",
+                            kind, self.consumed + i, self.cfg.max_depth;
+                        String, Input, Decode, Excessive));
+                    }
+                    self.release(false);
+                    self.stack.push(Open {
+                        kind:   kind.clone(),
+                        keyed:  false,
+                    });
+                    self.events.push_back(StreamEvent::Start(kind));
+                    consume_to = Some(i + 1);
+                },
+                '(' | '[' | '{' => {
+                    if self.begin.is_none() {
+                        self.begin = Some(i);
+                    }
+                    self.depth += 1;
+                },
+                ']' | '}' if self.cfg.events && self.depth == 0 && self.stack.len() > 0 => {
+                    if let Some(begin) = self.begin.take() {
+                        // The end of the last atom in the list or map.
+                        res!(self.decode(begin, i));
+                    }
+                    self.release(false);
+                    let kind = match self.stack.pop() {
+                        Some(Open { kind: Kind::List, .. }) if c == ']' => Kind::List,
+                        Some(Open { kind, .. }) if c == '}' && kind != Kind::List => kind,
+                        _ => return Err(err!(
+                            "Mismatched '{}' found at byte {}.", c, self.consumed + i;
+                        String, Input, Decode, Invalid)),
+                    };
+                    self.events.push_back(StreamEvent::End(kind));
+                    if let Some(parent) = self.stack.last_mut() {
+                        // The list or map is itself an item of the enclosing one.
+                        if parent.kind != Kind::List {
+                            parent.keyed = !parent.keyed;
+                        }
+                        self.held = Held::Molecule;
+                    }
+                    consume_to = Some(i + 1);
+                },
+                ')' | ']' | '}' => {
+                    if self.depth == 0 {
+                        return Err(err!(
+                            "Unbalanced '{}' found at byte {}.", c, self.consumed + i;
+                        String, Input, Decode, Invalid));
+                    }
+                    self.depth -= 1;
+                    if self.depth == 0 {
+                        if let Some(begin) = self.begin.take() {
+                            let end = i + c.len_utf8();
+                            res!(self.decode(begin, end));
+                            consume_to = Some(end);
+                        }
+                    }
+                },
+                ':' if self.depth == 0 && matches!(
+                    self.stack.last(),
+                    Some(Open { kind: Kind::Map, .. }) | Some(Open { kind: Kind::OrdMap, .. }),
+                ) => {
+                    if let Some(begin) = self.begin.take() {
+                        // The end of an atomic map key.
+                        res!(self.decode(begin, i));
+                    }
+                    self.release(false);
+                    consume_to = Some(i + 1);
+                },
+                '\t' => {
+                    // As for `Dat::decode_string`.
+                    return Err(err!(
+                        "Unquoted tab character found at byte {}, but tabs are prohibited.  \
+                        Replace all tabs with spaces.", self.consumed + i;
+                    String, Input, Decode, Invalid));
+                },
+                ' ' | '\n' | '\r' | ',' if self.depth == 0 => {
+                    if let Some(begin) = self.begin.take() {
+                        // The end of a top-level atom.
+                        res!(self.decode(begin, i));
+                    }
+                    if c == ',' {
+                        self.release(false);
+                    }
+                    consume_to = Some(i + 1);
+                },
+                _ => {
+                    if self.begin.is_none() {
+                        self.begin = Some(i);
+                    }
+                },
+            }
+        }
+        self.scan = self.text.len();
+        if let Some(end) = consume_to {
+            self.consume(end);
+        }
+        Ok(())
+    }
+
+    /// Decodes the daticle at the given byte range, yielding it at the top level or holding it
+    /// within an open list or map.
+    fn decode(&mut self, begin: usize, end: usize) -> Outcome<()> {
+        let dat = match Dat::decode_string_with_config(&self.text[begin..end], &self.dec_cfg) {
+            Ok(dat) => dat,
+            Err(e) => return Err(err!(e,
+                "While decoding the daticle at bytes {} to {} of the stream.",
+                self.consumed + begin, self.consumed + end;
+            String, Decode)),
+        };
+        if self.stack.is_empty() {
+            self.events.push_back(StreamEvent::Dat(dat));
+        } else if let Held::Dat(prev) = std::mem::replace(&mut self.held, Held::Dat(dat)) {
+            self.yield_dat(prev);
+        }
+        Ok(())
+    }
+
+    /// Yields the held item, wrapped in a `Dat::ABox` with any comment found since the previous
+    /// separator.  As for `Dat::decode_string`, a comment ending with a line is attached even when
+    /// empty or without an item, and within a map is given an empty value when it takes the place
+    /// of a key.
+    fn release(&mut self, line: bool) {
+        let held = std::mem::replace(&mut self.held, Held::Nothing);
+        let note = std::mem::take(&mut self.note);
+        let note_cfg = std::mem::take(&mut self.note_cfg);
+        let dat = match held {
+            Held::Molecule => return,
+            Held::Nothing if note.is_empty() && !line => return,
+            Held::Dat(dat) if note.is_empty() && !line => dat,
+            Held::Nothing => Dat::ABox(note_cfg, Box::new(Dat::Empty), note),
+            Held::Dat(dat) => Dat::ABox(note_cfg, Box::new(dat), note),
+        };
+        self.yield_dat(dat);
+        if line && matches!(self.stack.last(), Some(Open { keyed: true, .. })) {
+            self.yield_dat(Dat::Empty);
+        }
+    }
+
+    /// Yields a daticle, noting within a map whether a key awaits its value.
+    fn yield_dat(&mut self, dat: Dat) {
+        if let Some(open) = self.stack.last_mut() {
+            if open.kind != Kind::List {
+                open.keyed = !open.keyed;
+            }
+        }
+        self.events.push_back(StreamEvent::Dat(dat));
+    }
+
+    /// Discards the text up to the given byte index.
+    fn consume(&mut self, end: usize) {
+        self.text.drain(..end);
+        self.scan -= end;
+        if let Some(begin) = self.begin.as_mut() {
+            *begin -= end;
+        }
+        self.consumed += end;
+    }
+}
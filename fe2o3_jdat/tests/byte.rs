@@ -21,62 +21,66 @@ use oxedyne_fe2o3_num::{
 
 use std::collections::BTreeMap;
 
+/// The daticles of the binary omnibus test, one of each kind of atom, also used to test other
+/// encodings.
+pub fn omnibus_dats() -> Outcome<Vec<Dat>> {
+    let ukid = UsrKindId::new(5, Some("my_type"), Some(Kind::U8));
+    let mut ukids = UsrKinds::new(BTreeMap::new(), BTreeMap::new());
+    res!(ukids.add(ukid.clone()));
+
+    Ok(vec![
+        dat!(()),           // 1 
+        dat!(true),         // 2   
+        dat!(false),        // 3 
+        dat!(None::<u8>),   // 4 
+        dat!(0u8),          // 5 
+        dat!(0u16),         // 6 
+        dat!(0u32),         // 7 
+        dat!(0u64),         // 8 
+        dat!(0u128),        // 9 
+        dat!(0i8),          // 10
+        dat!(0i16),         // 11
+        dat!(0i32),         // 12
+        dat!(0i64),         // 13
+        dat!(0i128),        // 14
+        dat!(u8::MAX),      // 15
+        dat!(u16::MAX),     // 16
+        dat!(u32::MAX),     // 17
+        dat!(u64::MAX),     // 18
+        dat!(u128::MAX),    // 19
+        dat!(i8::MIN),      // 20
+        dat!(i16::MIN),     // 21
+        dat!(i32::MIN),     // 22
+        dat!(i64::MIN),     // 23
+        dat!(i128::MIN),    // 24
+        dat!(i8::MAX),      // 25
+        dat!(i16::MAX),     // 26
+        dat!(i32::MAX),     // 27
+        dat!(i64::MAX),     // 28
+        dat!(i128::MAX),    // 29
+        dat!(0.0f32),       // 30
+        dat!(f32::MIN),     // 31
+        dat!(f32::MAX),     // 32
+        dat!(0.0f64),       // 33
+        dat!(f64::MIN),     // 34
+        dat!(f64::MAX),     // 35
+        dat!(res!(aint!(fmt!("{}0", u128::MAX)))),                  // 36
+        dat!(res!(aint!(fmt!("{}0", u128::MIN)))),                  // 37
+        dat!(res!(adec!(fmt!("{:e}0", f64::MAX)))),                 // 38
+        dat!(res!(adec!(fmt!("{:e}0", f64::MIN)))),                 // 39
+        Dat::C64(u32::MAX as u64),                                  // 40
+        dat!("hello"),                                              // 41
+        res!(Dat::try_from((ukid.clone(), Some(best_dat!(42))))),   // 42
+        dat!(Box::new(best_dat!(-42))),                             // 43
+        dat!(Some(best_dat!(-256))),                                // 44
+    ])
+}
+
 pub fn test_binary_encdec_func(filter: &'static str) -> Outcome<()> {
 
     res!(test_it(filter, &["Binary omnibus", "all", "omnibus"], || {
 
-        let ukid = UsrKindId::new(5, Some("my_type"), Some(Kind::U8));
-        let mut ukids = UsrKinds::new(BTreeMap::new(), BTreeMap::new());
-        res!(ukids.add(ukid.clone()));
-
-        // Vec of tuples to be encoded then decoded with various codec settings, and the kind
-        // expected from decoding with encoding KindScope::Nothing (as in JSON). 
-        let dats: Vec<Dat> = vec![
-            dat!(()),           // 1 
-            dat!(true),         // 2   
-            dat!(false),        // 3 
-            dat!(None::<u8>),   // 4 
-            dat!(0u8),          // 5 
-            dat!(0u16),         // 6 
-            dat!(0u32),         // 7 
-            dat!(0u64),         // 8 
-            dat!(0u128),        // 9 
-            dat!(0i8),          // 10
-            dat!(0i16),         // 11
-            dat!(0i32),         // 12
-            dat!(0i64),         // 13
-            dat!(0i128),        // 14
-            dat!(u8::MAX),      // 15
-            dat!(u16::MAX),     // 16
-            dat!(u32::MAX),     // 17
-            dat!(u64::MAX),     // 18
-            dat!(u128::MAX),    // 19
-            dat!(i8::MIN),      // 20
-            dat!(i16::MIN),     // 21
-            dat!(i32::MIN),     // 22
-            dat!(i64::MIN),     // 23
-            dat!(i128::MIN),    // 24
-            dat!(i8::MAX),      // 25
-            dat!(i16::MAX),     // 26
-            dat!(i32::MAX),     // 27
-            dat!(i64::MAX),     // 28
-            dat!(i128::MAX),    // 29
-            dat!(0.0f32),       // 30
-            dat!(f32::MIN),     // 31
-            dat!(f32::MAX),     // 32
-            dat!(0.0f64),       // 33
-            dat!(f64::MIN),     // 34
-            dat!(f64::MAX),     // 35
-            dat!(res!(aint!(fmt!("{}0", u128::MAX)))),                  // 36
-            dat!(res!(aint!(fmt!("{}0", u128::MIN)))),                  // 37
-            dat!(res!(adec!(fmt!("{:e}0", f64::MAX)))),                 // 38
-            dat!(res!(adec!(fmt!("{:e}0", f64::MIN)))),                 // 39
-            Dat::C64(u32::MAX as u64),                                  // 40
-            dat!("hello"),                                              // 41
-            res!(Dat::try_from((ukid.clone(), Some(best_dat!(42))))),   // 42
-            dat!(Box::new(best_dat!(-42))),                             // 43
-            dat!(Some(best_dat!(-256))),                                // 44
-        ];
+        let dats = res!(omnibus_dats());
         let mut count: usize = 1;
         let total = dats.len();
 
@@ -91,7 +95,7 @@ pub fn test_binary_encdec_func(filter: &'static str) -> Outcome<()> {
                     "Omnibus test {} of {} using dat #{}: The daticle {:?} was \
                     encoded to {:?} then decoded to {:?}.",
                     count, total, i+1, d1, buf, d2;
-                ErrTag::Test, ErrTag::Mismatch));
+                Test, Mismatch));
             }
             
             test!("Omnibus test {} of {} successfully completed.", count, total);
@@ -556,7 +560,7 @@ pub fn test_binary_encdec_func(filter: &'static str) -> Outcome<()> {
             let (v2, _) = res!(Dat::from_bytes(&buf));
             req!(v1, v2);
         } else {
-            return Err(err!("Problem generating BigInt."), Integer));
+            return Err(err!("Problem generating BigInt."; Integer));
         }
         Ok(())
     }));
@@ -884,5 +888,98 @@ pub fn test_binary_encdec_func(filter: &'static str) -> Outcome<()> {
         Ok(())
     }));
 
+    res!(test_it(filter, &["Binary canonical 000", "all", "unit", "canon"], || {
+        let expected = res!(dat!(42u8).as_canonical_bytes());
+        let dats = vec![
+            dat!(42u16),
+            dat!(42u32),
+            dat!(42u64),
+            dat!(42u128),
+            dat!(42i8),
+            dat!(42i16),
+            dat!(42i32),
+            dat!(42i64),
+            dat!(42i128),
+            Dat::C64(42),
+            dat!(res!(aint!("42"))),
+            best_dat!(42u128),
+        ];
+        for d in dats {
+            let byts = res!(d.as_canonical_bytes());
+            req!(byts, expected, "Canonical encoding of {:?}", d);
+        }
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Binary canonical 010", "all", "unit", "canon"], || {
+        let expected = res!(dat!(-300i16).as_canonical_bytes());
+        for d in [dat!(-300i32), dat!(-300i64), dat!(-300i128), dat!(res!(aint!("-300")))] {
+            let byts = res!(d.as_canonical_bytes());
+            req!(byts, expected, "Canonical encoding of {:?}", d);
+        }
+        req!(res!(dat!(-300i32).into_canonical()), dat!(-300i16));
+        req!(res!(dat!(300i32).into_canonical()), dat!(300u16));
+        // A big integer beyond the native range is retained.
+        let big = dat!(res!(aint!(fmt!("{}0", u128::MAX))));
+        req!(res!(big.clone().into_canonical()), big);
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Binary canonical 020", "all", "unit", "canon", "map"], || {
+        let m1 = mapdat!{
+            "name" => "Alice",
+            "age" => 21u8,
+            dat!(7u64) => listdat![1u8, 2u16, 3u32],
+        };
+        let m2 = omapdat!{
+            dat!(7u16) => listdat![1u64, 2u8, 3u8],
+            "age" => 21i32,
+            "name" => "Alice",
+        };
+        req!(res!(m1.as_canonical_bytes()), res!(m2.as_canonical_bytes()));
+        // The ordinary encodings differ.
+        let ne = res!(m1.as_bytes()) != res!(m2.as_bytes());
+        req!(ne, true);
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Binary canonical 030", "all", "unit", "canon", "map"], || {
+        let d1 = listdat![
+            mapdat!{ "a" => omapdat!{ "x" => 1u8, "y" => 2u8 }, "b" => Dat::C64(3) },
+            Dat::BU8(vec![1, 2, 3]),
+            abox!(5u32, "a note"),
+            res!(adec!("1.50")),
+        ];
+        let d2 = listdat![
+            omapdat!{ "b" => 3u64, "a" => mapdat!{ "y" => 2u16, "x" => 1u32 } },
+            Dat::BU64(vec![1, 2, 3]),
+            5u8,
+            res!(adec!("1.5")),
+        ];
+        let byts1 = res!(d1.as_canonical_bytes());
+        let byts2 = res!(d2.as_canonical_bytes());
+        req!(byts1, byts2);
+        // Canonical encoding is idempotent.
+        let (d3, n) = res!(Dat::from_bytes(&byts1));
+        req!(n, byts1.len());
+        req!(res!(d3.as_canonical_bytes()), byts1);
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Binary canonical 040", "all", "unit", "canon", "map"], || {
+        let d = mapdat!{
+            1u8 => "one",
+            1u16 => "also one",
+        };
+        match d.as_canonical_bytes() {
+            Ok(byts) => return Err(err!(
+                "Canonical encoding should have detected the colliding keys, but \
+                produced {:?}.", byts;
+            Test, Unexpected)),
+            Err(e) => test!("Correctly detected error: {}", e),
+        }
+        Ok(())
+    }));
+
     Ok(())
 }
@@ -0,0 +1,108 @@
+use oxedyne_fe2o3_jdat::{
+    prelude::*,
+    cli::{
+        self,
+        CliConfig,
+        Command,
+        Format,
+    },
+};
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    test::test_it,
+};
+
+
+fn args(s: &str) -> Vec<String> {
+    s.split_whitespace().map(String::from).collect()
+}
+
+fn run(argstr: &str, input: &[u8]) -> Outcome<Vec<u8>> {
+    let cfg = res!(CliConfig::from_args(args(argstr)));
+    let mut stdin = input;
+    let mut stdout = Vec::new();
+    res!(cli::run(&cfg, &mut stdin, &mut stdout));
+    Ok(stdout)
+}
+
+const INPUT: &str = r#"{
+    "name": "server",
+    "ports": [8080, 8443],
+    "tls": {"enabled": true},
+}"#;
+
+pub fn test_cli_func(filter: &'static str) -> Outcome<()> {
+
+    res!(test_it(filter, &["Cli args 000", "all", "cli"], || {
+        let cfg = res!(CliConfig::from_args(args("get /ports/1 -o json -c -k nothing config.jdat")));
+        req!(cfg.command, Command::Get(vec![dat!("ports"), dat!(1u8)]));
+        req!(cfg.out_format, Format::Json);
+        req!(cfg.to_lines, false);
+        req!(cfg.input.as_ref().map(|p| p.display().to_string()), Some(fmt!("config.jdat")));
+        let cfg = res!(CliConfig::from_args(args(r#"get ["tls","enabled"]"#)));
+        req!(cfg.command, Command::Get(vec![dat!("tls"), dat!("enabled")]));
+        for bad in ["--bogus", "-o yaml", "-k", "a.jdat b.jdat", "get"] {
+            match CliConfig::from_args(args(bad)) {
+                Ok(cfg) => return Err(err!(
+                    "Expected '{}' to be rejected, got {:?}.", bad, cfg;
+                Test, Unexpected)),
+                Err(e) => test!("Correctly detected error: {}", e),
+            }
+        }
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Cli convert 000", "all", "cli"], || {
+        let expected = res!(Dat::decode_string(INPUT));
+        let out = res!(String::from_utf8(res!(run("", INPUT.as_bytes()))));
+        test!("{}", out);
+        let d = res!(Dat::decode_string(out));
+        req!(d, expected.clone());
+        let out = res!(String::from_utf8(res!(run("-o json -c", INPUT.as_bytes()))));
+        req!(out.trim(), r#"{ "name": "server", "ports": [ 8080, 8443], "tls": { "enabled": "true"}}"#);
+        // Round trip through each binary format.
+        let expected = res!(expected.into_canonical());
+        for fmt in ["bin", "cbor", "msgpack"] {
+            let byts = res!(run(&fmt!("-o {}", fmt), INPUT.as_bytes()));
+            let out = res!(String::from_utf8(res!(run(&fmt!("-i {} -c", fmt), &byts))));
+            let d = res!(res!(Dat::decode_string(out)).into_canonical());
+            req!(d, expected.clone(), "For format {}", fmt);
+        }
+        let out = res!(String::from_utf8(res!(run("-c -n hex", b"(u16|255)"))));
+        req!(out.trim(), "(u16|0x00ff)");
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Cli get 000", "all", "cli"], || {
+        let out = res!(String::from_utf8(res!(run("get /ports/1 -c -k nothing", INPUT.as_bytes()))));
+        req!(out.trim(), "8443");
+        let out = res!(String::from_utf8(res!(run(r#"get ["tls","enabled"] -c"#, INPUT.as_bytes()))));
+        req!(out.trim(), "(true)");
+        match run("get /ports/2", INPUT.as_bytes()) {
+            Ok(out) => return Err(err!(
+                "Expected a missing path, got {:?}.", String::from_utf8(out);
+            Test, Unexpected)),
+            Err(e) => test!("Correctly detected error: {}", e),
+        }
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Cli validate 000", "all", "cli"], || {
+        let out = res!(String::from_utf8(res!(run("validate", INPUT.as_bytes()))));
+        test!("{}", out);
+        match run("validate", b"{\n  \"a\": [1, 2,\n  \"b\": }") {
+            Ok(out) => return Err(err!(
+                "Expected invalid input, got {:?}.", String::from_utf8(out);
+            Test, Unexpected)),
+            Err(e) => {
+                let msg = fmt!("{}", e);
+                req!(msg.contains("line"), true, "No location in: {}", msg);
+                test!("Correctly detected error: {}", e);
+            },
+        }
+        Ok(())
+    }));
+
+    Ok(())
+}
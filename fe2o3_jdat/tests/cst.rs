@@ -0,0 +1,208 @@
+use oxedyne_fe2o3_jdat::{
+    prelude::*,
+    string::{
+        cst::{
+            Cst,
+            CstNode,
+        },
+        dec::DecoderConfig,
+        enc::EncoderConfig,
+    },
+    usr::{
+        UsrKindId,
+        UsrKinds,
+    },
+};
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    test::test_it,
+};
+
+use std::collections::BTreeMap;
+
+
+const CONFIG: &str = "! Server configuration. !
+(map|{
+    # Network #
+    (str|\"port\"): (u16|8443), ! The TLS port. !
+    (str|\"hosts\"): [
+        \"alpha\",  # primary #
+        'beta',
+    ],
+
+    (str|\"limits\"): {
+        \"rate\":   0x0400,
+        \"burst\":  1_000,
+    },
+    (str|\"ratio\"): 1.50e0,
+})
+";
+
+pub fn test_cst_func(filter: &'static str) -> Outcome<()> {
+
+    res!(test_it(filter, &["Cst round trip 000", "all", "cst"], || {
+        let texts = [
+            CONFIG,
+            "42",
+            "  (u8|42) ! note !\n",
+            "[]",
+            "{ }",
+            "[1,2 ,3]",
+            "{\"a\": (t2|[1, 2]), \"b\": (1, 2), \"c\": (abox|[1, 2] !c!)}",
+            "{\"a\"  :\n  [ {\"b\": \"c:d], {\"}, # x,} #\n ] !x\n}",
+            "(list|[ (str|\"x\") , (vek|[1, 2]) ])",
+        ];
+        for text in texts {
+            let cst = res!(Cst::parse(text));
+            req!(cst.render(), text.to_string());
+            req!(res!(cst.to_dat()), res!(Dat::decode_string(text)), "For '{}'", text);
+        }
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Cst round trip 010", "all", "cst", "error"], || {
+        for text in ["", "[1, 2", "{\"a\": 1 \"b\": 2}", "[1] 2", "'open"] {
+            match Cst::parse(text) {
+                Ok(cst) => return Err(err!(
+                    "Parsing '{}' should have failed, instead produced {:?}.", text, cst;
+                Test, Unexpected)),
+                Err(e) => test!("Correctly detected error: {}", e),
+            }
+        }
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Cst round trip 020", "all", "cst", "colon"], || {
+        // A ':' only separates a key from its value within a map, as for the decoder.
+        for text in [
+            "(u8|1):",
+            "{(u8|1):(str|\"a:b\"), (i16|-2) :(c64|3)}",
+            "[(u8|1), {(u8|2): (t2|[3, 4])}]",
+        ] {
+            let cst = res!(Cst::parse(text));
+            req!(cst.render(), text.to_string());
+            req!(res!(cst.to_dat()), res!(Dat::decode_string(text)), "For '{}'", text);
+        }
+        let cst = res!(Cst::parse("[(u8|1):2]"));
+        req!(cst.render(), "[(u8|1):2]".to_string());
+        match cst.to_dat() {
+            Ok(d) => return Err(err!(
+                "A ':' within a list should have been rejected, instead produced {:?}.", d;
+            Test, Unexpected)),
+            Err(e) => test!("Correctly detected error: {}", e),
+        }
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Cst get 000", "all", "cst", "get"], || {
+        let cst = res!(Cst::parse(CONFIG));
+        req!(res!(cst.get_dat(&[dat!("port")])), Some(dat!(8443u16)));
+        req!(res!(cst.get_dat(&[dat!("hosts"), dat!(1u8)])), Some(dat!("beta")));
+        req!(res!(cst.get_dat(&[dat!("limits"), dat!("rate")])), Some(dat!(1024u16)));
+        req!(res!(cst.get_dat(&[dat!("hosts"), dat!(2u8)])), None::<Dat>);
+        req!(res!(cst.get_dat(&[dat!("missing")])), None::<Dat>);
+        match res!(cst.get(&[dat!("limits"), dat!("burst")])) {
+            Some(CstNode::Atom(text)) => req!(text.as_str(), "1_000"),
+            other => return Err(err!(
+                "Expected the verbatim atom '1_000', found {:?}.", other;
+            Test, Unexpected)),
+        }
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Cst get 010", "all", "cst", "get", "usr"], || {
+        // Keys are decoded with the configuration used to parse the text.
+        let mut ukinds = UsrKinds::new(BTreeMap::new(), BTreeMap::new());
+        let ukid = UsrKindId::new(5, Some("colour"), Some(Kind::Str));
+        res!(ukinds.add(ukid.clone()));
+        let key = res!(Dat::try_from((ukid, Some(dat!("red")))));
+        let enc_cfg = EncoderConfig::jdat(Some(ukinds.clone()));
+        let key_str = res!(key.encode_string_with_config(&enc_cfg));
+        let text = fmt!("{{\n    {}: 1,\n    \"b\": 2,\n}}", key_str);
+        let mut cst = res!(Cst::parse_with_config(&text, &DecoderConfig::jdat(Some(ukinds))));
+        req!(res!(cst.get_dat(&[key.clone()])), Some(dat!(1u8)));
+        req!(res!(cst.get_dat(&[dat!("b")])), Some(dat!(2u8)));
+        res!(cst.set(&[key.clone()], &dat!(3u8)));
+        req!(res!(cst.get_dat(&[key.clone()])), Some(dat!(3u8)));
+        req!(true, res!(cst.remove(&[key.clone()])));
+        let removed = res!(cst.get_dat(&[key]));
+        req!(removed, None::<Dat>);
+        // Without the user kinds, the key cannot be decoded.
+        let cst = res!(Cst::parse(&text));
+        req!(true, cst.get_dat(&[dat!("b")]).is_err());
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Cst edit 000", "all", "cst", "edit"], || {
+        // Replacing a value re-encodes only that value, preserving its style of kind labels.
+        let mut cst = res!(Cst::parse(CONFIG));
+        res!(cst.set(&[dat!("port")], &dat!(9443u16)));
+        res!(cst.set(&[dat!("limits"), dat!("rate")], &dat!(2048u16)));
+        res!(cst.set_text(&[dat!("hosts"), dat!(0u8)], "\"gamma\""));
+        let expected = CONFIG
+            .replace("(u16|8443)", "(u16|9443)")
+            .replace("0x0400", "(u16|2048)")
+            .replace("\"alpha\"", "\"gamma\"");
+        req!(cst.render(), expected);
+        let cst = res!(Cst::parse(&cst.render()));
+        req!(res!(cst.get_dat(&[dat!("port")])), Some(dat!(9443u16)));
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Cst edit 010", "all", "cst", "edit", "insert"], || {
+        // New map entries follow the indentation and commas of the last entry.
+        let mut cst = res!(Cst::parse(CONFIG));
+        res!(cst.set(&[dat!("limits"), dat!("window")], &dat!(60u8)));
+        res!(cst.set(&[dat!("name")], &dat!("shield")));
+        let expected = CONFIG
+            .replace(
+                "\"burst\":  1_000,\n",
+                "\"burst\":  1_000,\n        \"window\":  (u8|60),\n",
+            )
+            .replace(
+                "(str|\"ratio\"): 1.50e0,\n",
+                "(str|\"ratio\"): 1.50e0,\n    \"name\": \"shield\",\n",
+            );
+        req!(cst.render(), expected);
+
+        // Single line molecules, with and without trailing commas.
+        let mut cst = res!(Cst::parse("{\"a\": 1, \"b\": 2}"));
+        res!(cst.set(&[dat!("c")], &dat!(3u8)));
+        req!(cst.render(), "{\"a\": 1, \"b\": 2, \"c\": (u8|3)}".to_string());
+        let mut cst = res!(Cst::parse("{\n  \"a\": 1\n}"));
+        res!(cst.set(&[dat!("b")], &dat!(true)));
+        req!(cst.render(), "{\n  \"a\": 1,\n  \"b\": (true)\n}".to_string());
+        let mut cst = res!(Cst::parse("{}"));
+        res!(cst.set(&[dat!("a")], &listdat![1u8, "x"]));
+        req!(cst.render(), "{\"a\": [ (u8|1), \"x\",]}".to_string());
+
+        // Lists cannot be extended beyond their end.
+        match cst.set(&[dat!("a"), dat!(5u8)], &dat!(1u8)) {
+            Ok(()) => return Err(err!(
+                "Setting beyond the end of a list should have failed.";
+            Test, Unexpected)),
+            Err(e) => test!("Correctly detected error: {}", e),
+        }
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Cst edit 020", "all", "cst", "edit", "remove"], || {
+        let mut cst = res!(Cst::parse(CONFIG));
+        req!(res!(cst.remove(&[dat!("port")])), true);
+        req!(res!(cst.remove(&[dat!("hosts"), dat!(1u8)])), true);
+        req!(res!(cst.remove(&[dat!("missing")])), false);
+        let expected = CONFIG
+            .replace("    # Network #\n    (str|\"port\"): (u16|8443), ! The TLS port. !\n", "")
+            .replace("        'beta',\n", "");
+        req!(cst.render(), expected);
+
+        // Removing the last entry passes its lack of a trailing comma to its predecessor.
+        let mut cst = res!(Cst::parse("[1, 2, 3]"));
+        req!(res!(cst.remove(&[dat!(2u8)])), true);
+        req!(cst.render(), "[1, 2]".to_string());
+        Ok(())
+    }));
+
+    Ok(())
+}
@@ -173,7 +173,7 @@ pub fn test_daticle_func(filter: &'static str) -> Outcome<()> {
         let n = res!(match d {
             Dat::I32(n) => Ok(n),
             _ => Err(err!("A Dat::I32 was expected, found {:?}", d;
-            ErrTag::Input, ErrTag::Conversion, ErrTag::Mismatch)),
+            Input, Conversion, Mismatch)),
         });
         req!(n, 42i32);
         Ok(())
@@ -774,7 +774,7 @@ pub fn test_daticle_func(filter: &'static str) -> Outcome<()> {
                 s   => return Err(err!(
                     "The usize for this machine is {}, which has not yet been \
                     mapped to a daticle kind.", s;
-                System, Unimplemented, Bug,)),
+                System, Unimplemented, Bug)),
             },
         );
         Ok(())
@@ -793,7 +793,7 @@ pub fn test_daticle_func(filter: &'static str) -> Outcome<()> {
                 s   => return Err(err!(
                     "The isize for this machine is {}, which has not yet been \
                     mapped to a daticle kind.", s;
-                System, Unimplemented, Bug,)),
+                System, Unimplemented, Bug)),
             },
         );
         Ok(())
@@ -1191,3 +1191,14 @@ pub fn test_daticle_func(filter: &'static str) -> Outcome<()> {
             dat!(42),
         ];
         match Dat::try_vek_from(v.clone()) {
+            Ok(d) => return Err(err!(
+                "Expected the heterogenous list {:?} to fail conversion to a Vek, \
+                but it produced {:?}.", v, d;
+            Test, Unexpected)),
+            Err(e) => test!("Correctly detected error: {}", e),
+        }
+        Ok(())
+    }));
+
+    Ok(())
+}
@@ -0,0 +1,320 @@
+use crate::byte::omnibus_dats;
+
+use oxedyne_fe2o3_jdat::{
+    prelude::*,
+    tup2dat,
+};
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    test::test_it,
+};
+
+use oxedyne_fe2o3_num::prelude::*;
+
+
+/// The daticles of the binary omnibus test, plus molecules and further atoms.
+fn corpus() -> Outcome<Vec<Dat>> {
+    let mut dats = res!(omnibus_dats());
+    dats.extend(vec![
+        dat!(res!(aint!(fmt!("-{}0", u128::MAX)))),
+        dat!(res!(aint!(fmt!("{}", u64::MAX as u128 + 1)))),
+        dat!(res!(adec!("-273.15"))),
+        dat!(res!(adec!("1.50"))),
+        dat!(""),
+        abox!(7u32, "an annotation"),
+        Dat::BU8(vec![1, 2, 3]),
+        Dat::BU32(vec![42; 300]),
+        Dat::BC64(Vec::new()),
+        Dat::B32(B32([7; 32])),
+        Dat::Tup3u16([1, 2, 3]),
+        tup2dat![1u8, "two"],
+        listdat![],
+        listdat![1u8, "two", 3.0f64, listdat![dat!(-4i64), dat!(())]],
+        Dat::Vek(Vek(vec![dat!(1u32), dat!(70000u32)])),
+        mapdat!{},
+        mapdat!{
+            "name" => "Alice",
+            "age" => 21u16,
+            dat!(42u8) => "Answer",
+            dat!(-1i32) => mapdat!{ "nested" => listdat![Dat::C64(9), Dat::BU8(vec![0xff])] },
+        },
+        omapdat!{ "z" => 1u8, "a" => 2u8 },
+        Dat::List((0..40u32).map(|i| dat!(i * 1000)).collect()),
+        dat!("x".repeat(70000)),
+    ]);
+    Ok(dats)
+}
+
+/// The form in which a daticle is expected to survive a CBOR or MessagePack round trip.  The
+/// directly mapped kinds are canonicalised, while the JDAT-specific kinds, together with any
+/// daticles they contain, are untouched.
+fn round_trip_form(d: &Dat) -> Outcome<Dat> {
+    Ok(match d {
+        Dat::Empty      |
+        Dat::Bool(_)    |
+        Dat::U8(_)      |
+        Dat::U16(_)     |
+        Dat::U32(_)     |
+        Dat::U64(_)     |
+        Dat::U128(_)    |
+        Dat::I8(_)      |
+        Dat::I16(_)     |
+        Dat::I32(_)     |
+        Dat::I64(_)     |
+        Dat::I128(_)    |
+        Dat::Aint(_)    |
+        Dat::F32(_)     |
+        Dat::F64(_)     |
+        Dat::Str(_)     |
+        Dat::BU8(_)     |
+        Dat::BU16(_)    |
+        Dat::BU32(_)    |
+        Dat::BU64(_)    |
+        Dat::BC64(_)    => res!(d.clone().into_canonical()),
+        Dat::ABox(_, boxd, _) => res!(round_trip_form(boxd)),
+        Dat::List(v) | Dat::Vek(Vek(v)) => {
+            let mut list = Vec::new();
+            for item in v {
+                list.push(res!(round_trip_form(item)));
+            }
+            Dat::List(list)
+        },
+        Dat::Map(map) => {
+            let mut map2 = DaticleMap::new();
+            for (k, v) in map {
+                map2.insert(res!(round_trip_form(k)), res!(round_trip_form(v)));
+            }
+            Dat::Map(map2)
+        },
+        Dat::OrdMap(map) => {
+            let mut map2 = DaticleMap::new();
+            for (mk, v) in map {
+                map2.insert(res!(round_trip_form(mk.dat())), res!(round_trip_form(v)));
+            }
+            Dat::Map(map2)
+        },
+        _ => d.clone(),
+    })
+}
+
+/// Daticles that are captured losslessly.
+fn jdat_specific() -> Outcome<Vec<Dat>> {
+    Ok(vec![
+        Dat::C64(u32::MAX as u64),
+        dat!(Box::new(best_dat!(-42))),
+        dat!(Some(best_dat!(-256))),
+        dat!(None::<u8>),
+        Dat::B32(B32([7; 32])),
+        Dat::Tup3u16([1, 2, 3]),
+        tup2dat![1u8, "two"],
+        listdat![Dat::C64(1), mapdat!{ "k" => tup2dat![2u16, Dat::C64(3)] }],
+    ])
+}
+
+fn hex(s: &str) -> Outcome<Vec<u8>> {
+    let mut v = Vec::new();
+    let s: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+    for i in (0..s.len()).step_by(2) {
+        v.push(res!(u8::from_str_radix(&s[i..i + 2], 16)));
+    }
+    Ok(v)
+}
+
+pub fn test_interop_func(filter: &'static str) -> Outcome<()> {
+
+    res!(test_it(filter, &["Interop cbor 000", "all", "interop", "cbor"], || {
+        for d in res!(corpus()) {
+            let byts = res!(d.as_cbor());
+            let (d2, n) = res!(Dat::from_cbor(&byts));
+            req!(n, byts.len(), "For {:?}", d);
+            req!(d2, res!(round_trip_form(&d)), "For {:?}", d);
+        }
+        for d in res!(jdat_specific()) {
+            let (d2, _) = res!(Dat::from_cbor(&res!(d.as_cbor())));
+            req!(d2, d);
+        }
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Interop cbor 010", "all", "interop", "cbor"], || {
+        // Examples from RFC 8949 Appendix A.
+        let cases: Vec<(&str, Dat)> = vec![
+            ("00", dat!(0u8)),
+            ("17", dat!(23u8)),
+            ("1818", dat!(24u8)),
+            ("1a000f4240", dat!(1_000_000u32)),
+            ("1bffffffffffffffff", dat!(u64::MAX)),
+            ("c249010000000000000000", dat!(u64::MAX as u128 + 1)),
+            ("3bffffffffffffffff", dat!(-(u64::MAX as i128) - 1)),
+            ("c349010000000000000000", dat!(-(u64::MAX as i128) - 2)),
+            ("20", dat!(-1i8)),
+            ("3903e7", dat!(-1000i16)),
+            ("fb3ff199999999999a", dat!(1.1f64)),
+            ("fa47c35000", dat!(100000.0f32)),
+            ("f4", dat!(false)),
+            ("f6", dat!(())),
+            ("4401020304", Dat::bytdat(vec![1, 2, 3, 4])),
+            ("6449455446", dat!("IETF")),
+            ("62c3bc", dat!("\u{00fc}")),
+            ("8301820203820405", listdat![1u8, listdat![2u8, 3u8], listdat![4u8, 5u8]]),
+            ("a26161016162820203", mapdat!{ "a" => 1u8, "b" => listdat![2u8, 3u8] }),
+            ("c48221196ab3", dat!(res!(adec!("273.15")))),
+        ];
+        for (h, d) in cases {
+            let byts = res!(hex(h));
+            req!(res!(d.as_cbor()), byts.clone(), "Encoding {:?}", d);
+            let (d2, n) = res!(Dat::from_cbor(&byts));
+            req!(n, byts.len());
+            req!(d2, d, "Decoding {}", h);
+        }
+        // Decoding only.
+        let cases: Vec<(&str, Dat)> = vec![
+            ("f93e00", dat!(1.5f32)),
+            ("f9c400", dat!(-4.0f32)),
+            ("f7", dat!(())),
+            ("c074323031332d30332d32315432303a30343a30305a", dat!("2013-03-21T20:04:00Z")),
+            ("5f42010243030405ff", Dat::bytdat(vec![1, 2, 3, 4, 5])),
+            ("7f657374726561646d696e67ff", dat!("streaming")),
+            ("9fff", listdat![]),
+            ("9f018202039f0405ffff", listdat![1u8, listdat![2u8, 3u8], listdat![4u8, 5u8]]),
+            ("bf61610161629f0203ffff", mapdat!{ "a" => 1u8, "b" => listdat![2u8, 3u8] }),
+        ];
+        for (h, d) in cases {
+            let byts = res!(hex(h));
+            let (d2, n) = res!(Dat::from_cbor(&byts));
+            req!(n, byts.len());
+            req!(d2, d, "Decoding {}", h);
+        }
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Interop cbor 020", "all", "interop", "cbor", "error"], || {
+        let mut deep = vec![0x81; 1000];
+        deep.push(0x00);
+        let cases = vec![
+            res!(hex("1a000f42")),          // Truncated integer.
+            res!(hex("830102")),            // Missing array element.
+            res!(hex("a2616101616102")),    // Duplicate key.
+            res!(hex("62c328")),            // Invalid UTF-8.
+            res!(hex("5f4101610262ff")),    // Text chunk within a byte string.
+            res!(hex("1c")),                // Reserved additional information.
+            res!(hex("9f01")),              // Unterminated indefinite array.
+            res!(hex("da4a44415441ff")),    // Malformed JDAT binary.
+            res!(hex("7bffffffffffffffff")), // Excessive length.
+            deep,
+        ];
+        for byts in cases {
+            match Dat::from_cbor(&byts) {
+                Ok(d) => return Err(err!(
+                    "Decoding {:02x?} should have failed, instead produced {:?}.", byts, d;
+                Test, Unexpected)),
+                Err(e) => test!("Correctly detected error: {}", e),
+            }
+        }
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Interop cbor 030", "all", "interop", "cbor", "canon"], || {
+        // A daticle of directly mapped kinds round trips to its canonical form.
+        let d = omapdat!{
+            "b" => Dat::Vek(Vek(vec![dat!(1u32), dat!(2u32)])),
+            "a" => abox!(mapdat!{ dat!(3i64) => Dat::BU32(vec![4]) }, "a note"),
+        };
+        let (d2, _) = res!(Dat::from_cbor(&res!(d.as_cbor())));
+        let expected = res!(d.clone().into_canonical());
+        req!(d2, expected);
+        // JDAT-specific kinds are not canonicalised.
+        for d in [Dat::C64(7), dat!(Box::new(dat!(5u32)))] {
+            let (d2, _) = res!(Dat::from_cbor(&res!(d.as_cbor())));
+            req!(d2.clone(), d.clone());
+            let differs = d2 != res!(d.clone().into_canonical());
+            req!(differs, true, "For {:?}", d);
+        }
+        // A decimal retains its scale.
+        let d = dat!(res!(adec!("1.50")));
+        let (d2, _) = res!(Dat::from_cbor(&res!(d.as_cbor())));
+        req!(res!(d2.as_bytes()), res!(d.as_bytes()));
+        let differs = res!(d2.as_bytes()) != res!(d.as_canonical_bytes());
+        req!(differs, true);
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Interop msgpack 000", "all", "interop", "msgpack"], || {
+        for d in res!(corpus()) {
+            let byts = res!(d.as_msgpack());
+            let (d2, n) = res!(Dat::from_msgpack(&byts));
+            req!(n, byts.len(), "For {:?}", d);
+            req!(d2, res!(round_trip_form(&d)), "For {:?}", d);
+        }
+        for d in res!(jdat_specific()) {
+            let (d2, _) = res!(Dat::from_msgpack(&res!(d.as_msgpack())));
+            req!(d2, d);
+        }
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Interop msgpack 010", "all", "interop", "msgpack"], || {
+        let cases: Vec<(&str, Dat)> = vec![
+            ("00", dat!(0u8)),
+            ("7f", dat!(127u8)),
+            ("cc80", dat!(128u8)),
+            ("cd0100", dat!(256u16)),
+            ("cf0000000100000000", dat!(u32::MAX as u64 + 1)),
+            ("ff", dat!(-1i8)),
+            ("e0", dat!(-32i8)),
+            ("d0df", dat!(-33i8)),
+            ("d1ff7f", dat!(-129i16)),
+            ("d38000000000000000", dat!(i64::MIN)),
+            ("c0", dat!(())),
+            ("c3", dat!(true)),
+            ("ca3fc00000", dat!(1.5f32)),
+            ("cb3ff8000000000000", dat!(1.5f64)),
+            ("a3616263", dat!("abc")),
+            ("c403010203", Dat::bytdat(vec![1, 2, 3])),
+            ("920102", listdat![1u8, 2u8]),
+            ("81a16101", mapdat!{ "a" => 1u8 }),
+        ];
+        for (h, d) in cases {
+            let byts = res!(hex(h));
+            req!(res!(d.as_msgpack()), byts.clone(), "Encoding {:?}", d);
+            let (d2, n) = res!(Dat::from_msgpack(&byts));
+            req!(n, byts.len());
+            req!(d2, d, "Decoding {}", h);
+        }
+        // JDAT-specific kinds use the extension type.
+        let d = Dat::C64(3);
+        let byts = res!(d.as_msgpack());
+        let jdat = res!(d.as_bytes());
+        req!(byts[0], 0xd5, "A C64 of 3 occupies 2 bytes and so uses fixext 2");
+        req!(byts[1], 0x4a);
+        req!(&byts[2..], &jdat[..]);
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Interop msgpack 020", "all", "interop", "msgpack", "error"], || {
+        let mut deep = vec![0x91; 1000];
+        deep.push(0x00);
+        let cases = vec![
+            res!(hex("cd01")),              // Truncated integer.
+            res!(hex("930102")),            // Missing array element.
+            res!(hex("82a16101a16102")),    // Duplicate key.
+            res!(hex("a2c328")),            // Invalid UTF-8.
+            res!(hex("c1")),                // Unused format code.
+            res!(hex("d4ff00")),            // Unsupported timestamp extension.
+            res!(hex("dbffffffff")),        // Excessive length.
+            deep,
+        ];
+        for byts in cases {
+            match Dat::from_msgpack(&byts) {
+                Ok(d) => return Err(err!(
+                    "Decoding {:02x?} should have failed, instead produced {:?}.", byts, d;
+                Test, Unexpected)),
+                Err(e) => test!("Correctly detected error: {}", e),
+            }
+        }
+        Ok(())
+    }));
+
+    Ok(())
+}
@@ -1,6 +1,10 @@
 mod byte;
+mod cli;
+mod cst;
 mod daticle;
+mod interop;
 mod map;
+mod stream;
 mod string;
 
 use oxedyne_fe2o3_core::prelude::*;
@@ -32,6 +36,10 @@ fn run_tests() -> Outcome<()> {
     res!(map::test_map_func(filter));
     res!(string::test_string_encdec_func(filter));
     res!(byte::test_binary_encdec_func(filter));
+    res!(stream::test_stream_func(filter));
+    res!(cst::test_cst_func(filter));
+    res!(interop::test_interop_func(filter));
+    res!(cli::test_cli_func(filter));
 
     Ok(())
 }
@@ -26,3 +26,12 @@ pub fn test_map_func(filter: &'static str) -> Outcome<()> {
                 test!("Found it! {:?}", found);
                 req!(&dat!(42u8), found);
             }
+            None => return Err(err!(
+                "Could not find the value at the given key path.";
+            Test, Missing)),
+        }
+        Ok(())
+    }));
+
+    Ok(())
+}
@@ -0,0 +1,334 @@
+use oxedyne_fe2o3_jdat::{
+    prelude::*,
+    binary::stream::StreamDecoder as BinaryStreamDecoder,
+    string::stream::StreamDecoder as StringStreamDecoder,
+    stream::{
+        StreamConfig,
+        StreamEvent,
+    },
+};
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    test::test_it,
+};
+use oxedyne_fe2o3_num::prelude::*;
+
+
+pub fn test_stream_func(filter: &'static str) -> Outcome<()> {
+
+    res!(test_it(filter, &["Binary stream 000", "all", "stream", "binary"], || {
+        let dats = vec![
+            dat!(42u8),
+            dat!("hello"),
+            listdat![1u8, "two", 3.0f64],
+            mapdat!{ "a" => 1u16, "b" => mapdat!{ "c" => listdat![2u8, "d"] } },
+            abox!(7u32, "an annotation"),
+            dat!(res!(aint!(fmt!("{}0", u128::MAX)))),
+            Dat::BU16(vec![42; 300]),
+        ];
+        let mut byts = Vec::new();
+        for d in &dats {
+            byts = res!(d.to_bytes(byts));
+        }
+        for chunk_size in [1, 2, 7, byts.len()] {
+            let mut decoder = BinaryStreamDecoder::new(StreamConfig::default());
+            let mut result = Vec::new();
+            for chunk in byts.chunks(chunk_size) {
+                res!(decoder.push(chunk));
+                while let Some(event) = decoder.next() {
+                    match event {
+                        StreamEvent::Dat(d) => result.push(d),
+                        _ => return Err(err!(
+                            "Unexpected event {:?} without events enabled.", event;
+                        Test, Unexpected)),
+                    }
+                }
+            }
+            res!(decoder.finish());
+            req!(result, dats, "Using chunks of {} bytes", chunk_size);
+            req!(decoder.buffered(), 0);
+            req!(decoder.consumed(), byts.len());
+        }
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Binary stream 010", "all", "stream", "binary", "events"], || {
+        let d = listdat![
+            mapdat!{ "x" => 1u8 },
+            listdat![],
+            "end",
+        ];
+        let mut byts = res!(d.as_bytes());
+        byts = res!(dat!(true).to_bytes(byts));
+        let mut decoder = BinaryStreamDecoder::new(StreamConfig::events());
+        for chunk in byts.chunks(3) {
+            res!(decoder.push(chunk));
+        }
+        res!(decoder.finish());
+        let events: Vec<StreamEvent> = decoder.collect();
+        let kinds: Vec<String> = events.iter().map(|e| match e {
+            StreamEvent::Dat(d) => fmt!("{:?}", d),
+            StreamEvent::Start(k) => fmt!("start {}", k),
+            StreamEvent::End(k) => fmt!("end {}", k),
+        }).collect();
+        for k in &kinds {
+            test!("{}", k);
+        }
+        req!(events.len(), 10);
+        req!(events[0].clone(), StreamEvent::Start(Kind::List));
+        req!(events[1].clone(), StreamEvent::Start(Kind::Map));
+        req!(events[2].clone(), StreamEvent::Dat(dat!("x")));
+        req!(events[3].clone(), StreamEvent::Dat(dat!(1u8)));
+        req!(events[4].clone(), StreamEvent::End(Kind::Map));
+        req!(events[5].clone(), StreamEvent::Start(Kind::List));
+        req!(events[6].clone(), StreamEvent::End(Kind::List));
+        req!(events[7].clone(), StreamEvent::Dat(dat!("end")));
+        req!(events[8].clone(), StreamEvent::End(Kind::List));
+        req!(events[9].clone(), StreamEvent::Dat(dat!(true)));
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Binary stream 020", "all", "stream", "binary"], || {
+        // A list too large to buffer whole can still be processed as events.
+        let mut items = Vec::new();
+        for i in 0..1_000u32 {
+            items.push(dat!(fmt!("item {}", i)));
+        }
+        let d = Dat::List(items);
+        let byts = res!(d.as_bytes());
+        let cfg = StreamConfig {
+            max_buffer: 64,
+            ..Default::default()
+        };
+        let mut decoder = BinaryStreamDecoder::new(cfg.clone());
+        match decoder.push(&byts) {
+            Ok(()) => return Err(err!(
+                "The decoder should have rejected a daticle exceeding the buffer size.";
+            Test, Unexpected)),
+            Err(e) => test!("Correctly detected error: {}", e),
+        }
+        let mut decoder = BinaryStreamDecoder::new(StreamConfig {
+            events: true,
+            ..cfg
+        });
+        let mut count: usize = 0;
+        for chunk in byts.chunks(16) {
+            res!(decoder.push(chunk));
+            let bounded = decoder.buffered() <= 64;
+            req!(bounded, true);
+            for event in &mut decoder {
+                if let StreamEvent::Dat(_) = event {
+                    count += 1;
+                }
+            }
+        }
+        res!(decoder.finish());
+        req!(count, 1_000);
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Binary stream 030", "all", "stream", "binary"], || {
+        let byts = res!(listdat![1u8, 2u8, 3u8].as_bytes());
+        let mut decoder = BinaryStreamDecoder::new(StreamConfig::events());
+        res!(decoder.push(&byts[..byts.len() - 1]));
+        req!(decoder.depth(), 1);
+        match decoder.finish() {
+            Ok(()) => return Err(err!(
+                "The decoder should have detected the incomplete list.";
+            Test, Unexpected)),
+            Err(e) => test!("Correctly detected error: {}", e),
+        }
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["String stream 000", "all", "stream", "string"], || {
+        let text = "(u8|1) \"héllo wörld\", [2, (u16|3)]\n\
+            {\"a\": 1, \"b\": [\"]\"]} !a comment with ] inside! (i8|-4)\n\
+            42 (str|\"(not a bracket\")\n\
+            -7";
+        let expected = vec![
+            dat!(1u8),
+            dat!("héllo wörld"),
+            listdat![2u8, 3u16],
+            mapdat!{ "a" => 1u8, "b" => listdat!["]"] },
+            dat!(-4i8),
+            dat!(42u8),
+            dat!("(not a bracket"),
+            dat!(-7i8),
+        ];
+        for chunk_size in [1, 2, 5, text.len()] {
+            let mut decoder = StringStreamDecoder::default();
+            for chunk in text.as_bytes().chunks(chunk_size) {
+                res!(decoder.push(chunk));
+            }
+            res!(decoder.finish());
+            let result: Vec<StreamEvent> = decoder.collect();
+            let expected: Vec<StreamEvent> = expected.iter()
+                .map(|d| StreamEvent::Dat(d.clone()))
+                .collect();
+            req!(result, expected, "Using chunks of {} bytes", chunk_size);
+        }
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["String stream 010", "all", "stream", "string"], || {
+        let mut decoder = StringStreamDecoder::default();
+        res!(decoder.push(b"[1, 2, (u8|3)"));
+        match decoder.finish() {
+            Ok(()) => return Err(err!(
+                "The decoder should have detected the incomplete list.";
+            Test, Unexpected)),
+            Err(e) => test!("Correctly detected error: {}", e),
+        }
+        let mut decoder = StringStreamDecoder::new(StreamConfig {
+            max_buffer: 8,
+            ..Default::default()
+        });
+        match decoder.push(b"[1, 2, 3, 4, 5, 6") {
+            Ok(()) => return Err(err!(
+                "The decoder should have rejected a daticle exceeding the buffer size.";
+            Test, Unexpected)),
+            Err(e) => test!("Correctly detected error: {}", e),
+        }
+        // Tabs are rejected, as by `Dat::decode_string`, whether or not a daticle is complete.
+        for txt in ["1\t2", "[1,\t2]", "{\"a\":\t1}"] {
+            req!(true, Dat::decode_string(txt).is_err(), "{:?}", txt);
+            let mut decoder = StringStreamDecoder::default();
+            let result = decoder.push(txt.as_bytes()).and_then(|()| decoder.finish());
+            req!(true, result.is_err(), "{:?}", txt);
+        }
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["String stream 020", "all", "stream", "string", "events"], || {
+        let text = "[{\"x\": 1, (u8|2): [\"]\"]}, [], (vek|[3, 4]), \"end\"] true";
+        let expected = vec![
+            StreamEvent::Start(Kind::List),
+            StreamEvent::Start(Kind::Map),
+            StreamEvent::Dat(dat!("x")),
+            StreamEvent::Dat(dat!(1u8)),
+            StreamEvent::Dat(dat!(2u8)),
+            StreamEvent::Start(Kind::List),
+            StreamEvent::Dat(dat!("]")),
+            StreamEvent::End(Kind::List),
+            StreamEvent::End(Kind::Map),
+            StreamEvent::Start(Kind::List),
+            StreamEvent::End(Kind::List),
+            StreamEvent::Dat(res!(Dat::decode_string("(vek|[3, 4])"))),
+            StreamEvent::Dat(dat!("end")),
+            StreamEvent::End(Kind::List),
+            StreamEvent::Dat(dat!(true)),
+        ];
+        for chunk_size in [1, 3, text.len()] {
+            let mut decoder = StringStreamDecoder::new(StreamConfig::events());
+            for chunk in text.as_bytes().chunks(chunk_size) {
+                res!(decoder.push(chunk));
+            }
+            res!(decoder.finish());
+            let result: Vec<StreamEvent> = decoder.collect();
+            req!(result, expected, "Using chunks of {} bytes", chunk_size);
+        }
+        let mut decoder = StringStreamDecoder::new(StreamConfig::events());
+        res!(decoder.push(b"[1, {\"a\": 2"));
+        req!(decoder.depth(), 2);
+        match decoder.finish() {
+            Ok(()) => return Err(err!(
+                "The decoder should have detected the unclosed list and map.";
+            Test, Unexpected)),
+            Err(e) => test!("Correctly detected error: {}", e),
+        }
+        let mut decoder = StringStreamDecoder::new(StreamConfig::events());
+        match decoder.push(b"[1, 2}") {
+            Ok(()) => return Err(err!(
+                "The decoder should have detected the mismatched bracket.";
+            Test, Unexpected)),
+            Err(e) => test!("Correctly detected error: {}", e),
+        }
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["String stream 030", "all", "stream", "string", "events", "comment"], || {
+        // Events reassembled into daticles agree with `Dat::decode_string` on commented text.
+        for txt in [
+            "[1, 2 !c! , 3]",
+            "[ !c! 1, 2]",
+            "[1 !c1! !c2!, 2]",
+            "[1, 2 !c!]",
+            "[1, 2, !c!]",
+            "[1, 2 # line\n 3]",
+            "[1, 2, # line\n 3]",
+            "[1, [2 !x!], (u8|3) !y!, 4]",
+            "[ !c! [1, 2], 3]",
+            "{\"a\": 1 !c!, \"b\": 2}",
+            "{\"a\" !k!: 1}",
+            "{\"a\": 1 # line\n \"b\": 2}",
+            "{\"a\": 1, # line\n \"b\": [2 # line\n]}",
+        ] {
+            let expected = res!(Dat::decode_string(txt));
+            for chunk_size in [1, 3, txt.len()] {
+                let mut decoder = StringStreamDecoder::new(StreamConfig::events());
+                for chunk in txt.as_bytes().chunks(chunk_size) {
+                    res!(decoder.push(chunk));
+                }
+                res!(decoder.finish());
+                let result = res!(assemble(decoder.collect()));
+                req!(result, vec![expected.clone()], "{:?} using chunks of {} bytes", txt, chunk_size);
+            }
+        }
+        // Line comments are only allowed within lists and maps.
+        for txt in ["1 # line\n", "# line\n1"] {
+            req!(true, Dat::decode_string(txt).is_err(), "{:?}", txt);
+            for events in [false, true] {
+                let mut decoder = StringStreamDecoder::new(StreamConfig {
+                    events,
+                    ..Default::default()
+                });
+                let result = decoder.push(txt.as_bytes()).and_then(|()| decoder.finish());
+                req!(true, result.is_err(), "{:?}", txt);
+            }
+        }
+        Ok(())
+    }));
+
+    Ok(())
+}
+
+/// Rebuilds the daticles described by string stream events.
+fn assemble(events: Vec<StreamEvent>) -> Outcome<Vec<Dat>> {
+    let mut stack: Vec<(Kind, Vec<Dat>)> = vec![(Kind::List, Vec::new())];
+    for event in events {
+        match event {
+            StreamEvent::Dat(d) => match stack.last_mut() {
+                Some((_, items)) => items.push(d),
+                None => return Err(err!("Empty stack."; Test, Bug)),
+            },
+            StreamEvent::Start(kind) => stack.push((kind, Vec::new())),
+            StreamEvent::End(_) => {
+                let (kind, items) = res!(stack.pop().ok_or(err!("Unbalanced end."; Test, Invalid)));
+                let d = match kind {
+                    Kind::List => Dat::List(items),
+                    _ => {
+                        if items.len() % 2 != 0 {
+                            return Err(err!("Unpaired map key in {:?}.", items; Test, Invalid));
+                        }
+                        let mut map = DaticleMap::new();
+                        let mut iter = items.into_iter();
+                        while let (Some(k), Some(v)) = (iter.next(), iter.next()) {
+                            map.insert(k, v);
+                        }
+                        Dat::Map(map)
+                    },
+                };
+                match stack.last_mut() {
+                    Some((_, items)) => items.push(d),
+                    None => return Err(err!("Unbalanced end."; Test, Invalid)),
+                }
+            },
+        }
+    }
+    match stack.pop() {
+        Some((_, items)) if stack.is_empty() => Ok(items),
+        _ => Err(err!("Unclosed list or map."; Test, Missing)),
+    }
+}
@@ -159,7 +159,7 @@ pub fn test_string_encdec_func(filter: &'static str) -> Outcome<()> {
                                                 but since the type scope is {:?}, {:?} was expected.",
                                                 count, total, i+1, d1, d1_str, d2,
                                                 enc_cfg, dec_cfg, kind_scope, k2;
-                                            ErrTag::Test, ErrTag::Mismatch));
+                                            Test, Mismatch));
                                         }
                                     } else {
                                         if *d1 != d2 {
@@ -169,7 +169,7 @@ pub fn test_string_encdec_func(filter: &'static str) -> Outcome<()> {
                                                 and {:?}.",
                                                 count, total, i+1, d1, d1_str,
                                                 d2, d2.kind(), enc_cfg, dec_cfg;
-                                            ErrTag::Test, ErrTag::Mismatch));
+                                            Test, Mismatch));
                                         }
                                     }
                                     //test!("Omnibus test {} of {} successfully completed.", count, total);
@@ -397,8 +397,8 @@ pub fn test_string_encdec_func(filter: &'static str) -> Outcome<()> {
     res!(test_it(filter, &["String decoding 080", "all", "empty"], || {
         match Dat::decode_string("(Empty|)") {
             Ok(_) => return Err(err!(
-                "Decoder should have detected superfluous '|' char.",
-            ))),
+                "Decoder should have detected superfluous '|' char.";
+            Test, Unexpected)),
             Err(e) => test!("Correctly detected error: {}", e),
         };
         Ok(())
@@ -486,8 +486,8 @@ pub fn test_string_encdec_func(filter: &'static str) -> Outcome<()> {
         match Dat::decode_string("(U8|\"42\")") {
             Ok(d) => return Err(err!(
                 "String decoding should have rejected the attempt to \
-                coerce a string to {:?}.", d,
-            ))),
+                coerce a string to {:?}.", d;
+            Test, Unexpected)),
             Err(e) => test!("Correctly detected error: {}", e),
         }
         Ok(())
@@ -518,8 +518,8 @@ pub fn test_string_encdec_func(filter: &'static str) -> Outcome<()> {
     res!(test_it(filter, &["String decoding 310", "all", "list"], || {
         match Dat::decode_string("[1,2,3,4") {
             Ok(_) => return Err(err!(
-                "String decoding should have detected incomplete map.",
-            ))),
+                "String decoding should have detected incomplete map.";
+            Test, Unexpected)),
             Err(e) => test!("Correctly detected error: {}", e),
         };
         Ok(())
@@ -854,7 +854,7 @@ pub fn test_string_encdec_func(filter: &'static str) -> Outcome<()> {
             },
             Err(e) => return Err(err!(
                 "Error while reading {:?}: {}", path, e;
-            ErrTag::File, ErrTag::Read)),
+            File, Read)),
         }
         Ok(())
     }));
@@ -1,11 +1,77 @@
+use oxedyne_fe2o3_core::prelude::*;
+
 use std::{
     future::Future,
     pin::Pin,
 };
 
+use tokio::{
+    io::{
+        AsyncRead,
+        AsyncReadExt,
+    },
+    sync::mpsc,
+};
+
 
 pub trait AsyncReadIterator {
     type Item: Send;
 
     fn next<'a>(&'a mut self) -> Pin<Box<dyn Future<Output = Option<Self::Item>> + Send + 'a>>;
 }
+
+/// Items sent on a channel are yielded until all senders have been dropped, allowing a task to
+/// generate output for a consumer such as a streaming HTTP response.
+impl<T: Send> AsyncReadIterator for mpsc::Receiver<T> {
+    type Item = T;
+
+    fn next<'a>(&'a mut self) -> Pin<Box<dyn Future<Output = Option<Self::Item>> + Send + 'a>> {
+        Box::pin(self.recv())
+    }
+}
+
+/// Yields the bytes of an async reader, such as a file, in pieces of at most `N` bytes until the
+/// end of the reader.
+#[derive(Debug)]
+pub struct AsyncReadChunks<
+    const N: usize,
+    R: AsyncRead + Unpin + Send,
+> {
+    reader: R,
+}
+
+impl<
+    const N: usize,
+    R: AsyncRead + Unpin + Send,
+>
+    AsyncReadChunks<N, R>
+{
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+        }
+    }
+}
+
+impl<
+    const N: usize,
+    R: AsyncRead + Unpin + Send,
+>
+    AsyncReadIterator for AsyncReadChunks<N, R>
+{
+    type Item = Outcome<Vec<u8>>;
+
+    fn next<'a>(&'a mut self) -> Pin<Box<dyn Future<Output = Option<Self::Item>> + Send + 'a>> {
+        Box::pin(async move {
+            let mut buf = vec![0u8; N];
+            match self.reader.read(&mut buf).await {
+                Ok(0) => None,
+                Ok(n) => {
+                    buf.truncate(n);
+                    Some(Ok(buf))
+                },
+                Err(e) => Some(Err(err!(e, "While reading the next chunk."; IO, Read))),
+            }
+        })
+    }
+}
@@ -1,3 +1,6 @@
+use std::time::Duration;
+
+
 // HTTP
 pub const HTTP_DEFAULT_HEADER_CHUNK_SIZE:       usize = 1_500;
 pub const HTTP_DEFAULT_BODY_CHUNK_SIZE:         usize = 5_000;
@@ -5,14 +8,67 @@ pub const HTTP_DEFAULT_BODY_CHUNK_SIZE:         usize = 5_000;
 pub const HTTP_HEADER_MAX_MULTILINES:           u8 = 10;
 pub const HTTP_HEADER_MAX_FIELDS:               u16 = 100;
 pub const HTTP_BODY_BYTES_MAX_VIEW:             usize = 300;
+pub const HTTP_CHUNK_LINE_MAX:                  usize = 4_096; // Chunk size and trailer lines.
+pub const HTTP_CHUNK_TRAILERS_MAX:              u16 = 100;
+pub const HTTP_BODY_SIZE_MAX:                   usize = 10_485_760;
+pub const HTTP_CLIENT_CONNECT_TIMEOUT:          Duration = Duration::from_secs(10);
+pub const HTTP_CLIENT_REQUEST_TIMEOUT:          Duration = Duration::from_secs(30);
+pub const HTTP_CLIENT_MAX_REDIRECTS:            usize = 10;
+pub const HTTP_CLIENT_MAX_IDLE_PER_HOST:        usize = 4;
+pub const HTTP_CLIENT_USER_AGENT:               &'static str = "fe2o3_net";
+// Form uploads larger than this are written to disk.
+pub const HTTP_FORM_MEMORY_LIMIT:               usize = 1_048_576;
+pub const HTTP_FORM_MAX_FIELD_SIZE:             usize = 65_536;
+pub const HTTP_FORM_MAX_PARTS:                  usize = 1_000;
+pub const HTTP_FORM_MAX_UPLOAD_SIZE:            usize = HTTP_BODY_SIZE_MAX;
+pub const HTTP_FORM_PART_HEADER_MAX:            usize = 8_192;
+// Smaller bodies are not worth compressing.
+pub const HTTP_COMPRESSION_MIN_SIZE:            usize = 1_024;
+pub const HTTP_CORS_MAX_AGE:                    u32 = 3_600;
 pub const SESSION_ID_KEY_LABEL:                 &'static str = "session_id";
 
 // SMTP
 //pub const SMTP_READ_BUFFER_SIZE:                usize = 10;//1_024;
+pub const SMTP_MAX_MESSAGE_SIZE:                usize = 10_485_760;
+pub const SMTP_MAX_RECIPIENTS:                  usize = 100;
+// RFC 5321 allows 512 bytes, but ESMTP parameters can make command lines longer.
+pub const SMTP_MAX_COMMAND_LINE:                usize = 2_048;
+// RFC 5321 section 4.5.3.1.5.
+pub const SMTP_MAX_REPLY_LINE:                  usize = 512;
+pub const SMTP_MAX_REPLY_LINES:                 usize = 100;
+pub const SMTP_MAX_ERRORS:                      usize = 10;
+pub const SMTP_SERVER_TIMEOUT:                  Duration = Duration::from_secs(300);
+pub const SMTP_CLIENT_TIMEOUT:                  Duration = Duration::from_secs(300);
+
+// Email
+pub const EMAIL_MAX_LINES:                      usize = 1_000_000;
+pub const EMAIL_MAX_MIME_DEPTH:                 usize = 16;
+pub const EMAIL_MAX_MIME_PARTS:                 usize = 1_000;
+// RFC 2045 Section 6.7 and 6.8.
+pub const EMAIL_ENCODED_LINE_MAX:               usize = 76;
+/// Headers signed by default, when present (RFC 6376 Section 5.4.1).
+pub const DKIM_SIGNED_HEADERS: &[&str] = &[
+    "from",
+    "reply-to",
+    "subject",
+    "date",
+    "to",
+    "cc",
+    "message-id",
+    "in-reply-to",
+    "references",
+    "mime-version",
+    "content-type",
+    "content-transfer-encoding",
+];
 
 // WebSocket
 pub const WEBSOCKET_GUID:                       &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
 pub const WEBSOCKET_LATENCY_HISTORY_SIZE:       usize = 540; // 3 hrs @ 30 s intervals
+pub const WEBSOCKET_MAX_MESSAGE_SIZE:           usize = 16_777_216;
+pub const WEBSOCKET_DEFLATE_EXTENSION:          &'static str = "permessage-deflate";
+// Smaller messages are not worth compressing.
+pub const WEBSOCKET_DEFLATE_THRESHOLD:          usize = 64;
 
 pub const READ_LOOP_SAFETY_LIMIT:               usize = 100;
 
@@ -25,3 +81,15 @@ pub const SPECIAL_DOMAINS: &[&str] = &[
     "example",     // RFC 6761
     "test",        // RFC 6761
 ];
+pub const DNS_PORT:                             u16 = 53;
+pub const DNS_CLASS_IN:                         u16 = 1;
+pub const DNS_RESOLV_CONF:                      &'static str = "/etc/resolv.conf";
+// Responses over UDP are limited to 512 bytes without EDNS, but allow for servers that ignore
+// this.
+pub const DNS_UDP_BUFFER_SIZE:                  usize = 4_096;
+pub const DNS_RESOLVER_TIMEOUT:                 Duration = Duration::from_secs(5);
+pub const DNS_RESOLVER_ATTEMPTS:                usize = 2;
+pub const DNS_CACHE_MAX_ENTRIES:                usize = 10_000;
+pub const DNS_CACHE_MAX_TTL:                    Duration = Duration::from_secs(86_400);
+// How long to remember that a name or record does not exist.
+pub const DNS_CACHE_NEGATIVE_TTL:               Duration = Duration::from_secs(300);
@@ -1,3 +1,6 @@
+pub mod msg;
+pub mod resolver;
+
 use crate::constant;
 
 use oxedyne_fe2o3_core::prelude::*;
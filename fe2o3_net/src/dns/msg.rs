@@ -0,0 +1,611 @@
+//! DNS messages in the RFC 1035 wire format.
+//!
+//! Record data is decoded for the A, AAAA, CNAME, MX, TXT (RFC 1035, RFC 3596) and SRV (RFC 2782)
+//! types, other types being kept as raw bytes.  Compressed names are followed when decoding, but
+//! names are always written in full when encoding.  Names are given without the trailing root
+//! dot, so that the root itself is the empty string.
+//!
+//! ```ignore
+//! let query = DnsMessage::query(Rand::rand_u16(), "example.com", DnsType::Mx);
+//! let bytes = res!(query.encode());
+//! ...
+//! let response = res!(DnsMessage::decode(&buf[..n]));
+//! ```
+use crate::constant;
+
+use oxedyne_fe2o3_core::prelude::*;
+
+use std::{
+    fmt,
+    net::{
+        Ipv4Addr,
+        Ipv6Addr,
+    },
+};
+
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum DnsType {
+    A,
+    Cname,
+    Mx,
+    Txt,
+    Aaaa,
+    Srv,
+    Other(u16),
+}
+
+impl DnsType {
+
+    pub fn code(&self) -> u16 {
+        match self {
+            Self::A         => 1,
+            Self::Cname     => 5,
+            Self::Mx        => 15,
+            Self::Txt       => 16,
+            Self::Aaaa      => 28,
+            Self::Srv       => 33,
+            Self::Other(n)  => *n,
+        }
+    }
+
+    pub fn from_code(code: u16) -> Self {
+        match code {
+            1   => Self::A,
+            5   => Self::Cname,
+            15  => Self::Mx,
+            16  => Self::Txt,
+            28  => Self::Aaaa,
+            33  => Self::Srv,
+            n   => Self::Other(n),
+        }
+    }
+}
+
+impl fmt::Display for DnsType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::A         => write!(f, "A"),
+            Self::Cname     => write!(f, "CNAME"),
+            Self::Mx        => write!(f, "MX"),
+            Self::Txt       => write!(f, "TXT"),
+            Self::Aaaa      => write!(f, "AAAA"),
+            Self::Srv       => write!(f, "SRV"),
+            Self::Other(n)  => write!(f, "TYPE{}", n),
+        }
+    }
+}
+
+/// The response code (RFC 1035 Section 4.1.1).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum DnsRcode {
+    #[default]
+    NoError,
+    FormErr,
+    ServFail,
+    NxDomain,
+    NotImp,
+    Refused,
+    Other(u8),
+}
+
+impl DnsRcode {
+
+    pub fn code(&self) -> u8 {
+        match self {
+            Self::NoError   => 0,
+            Self::FormErr   => 1,
+            Self::ServFail  => 2,
+            Self::NxDomain  => 3,
+            Self::NotImp    => 4,
+            Self::Refused   => 5,
+            Self::Other(n)  => *n & 0x0f,
+        }
+    }
+
+    pub fn from_code(code: u8) -> Self {
+        match code & 0x0f {
+            0 => Self::NoError,
+            1 => Self::FormErr,
+            2 => Self::ServFail,
+            3 => Self::NxDomain,
+            4 => Self::NotImp,
+            5 => Self::Refused,
+            n => Self::Other(n),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DnsHeader {
+    pub id:                     u16,
+    pub response:               bool,
+    pub opcode:                 u8,
+    pub authoritative:          bool,
+    pub truncated:              bool,
+    pub recursion_desired:      bool,
+    pub recursion_available:    bool,
+    pub rcode:                  DnsRcode,
+}
+
+impl DnsHeader {
+
+    pub fn flags(&self) -> u16 {
+        let mut flags = ((self.opcode as u16 & 0x0f) << 11) | self.rcode.code() as u16;
+        if self.response            { flags |= 0x8000; }
+        if self.authoritative       { flags |= 0x0400; }
+        if self.truncated           { flags |= 0x0200; }
+        if self.recursion_desired   { flags |= 0x0100; }
+        if self.recursion_available { flags |= 0x0080; }
+        flags
+    }
+
+    pub fn from_flags(id: u16, flags: u16) -> Self {
+        Self {
+            id,
+            response:               flags & 0x8000 != 0,
+            opcode:                 ((flags >> 11) & 0x0f) as u8,
+            authoritative:          flags & 0x0400 != 0,
+            truncated:              flags & 0x0200 != 0,
+            recursion_desired:      flags & 0x0100 != 0,
+            recursion_available:    flags & 0x0080 != 0,
+            rcode:                  DnsRcode::from_code((flags & 0x0f) as u8),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DnsQuestion {
+    pub name:   String,
+    pub qtype:  DnsType,
+    pub class:  u16,
+}
+
+impl DnsQuestion {
+
+    pub fn new<S: Into<String>>(name: S, qtype: DnsType) -> Self {
+        Self {
+            name:   name.into(),
+            qtype,
+            class:  constant::DNS_CLASS_IN,
+        }
+    }
+
+    /// Whether the given question asks the same thing, ignoring the case of the name.
+    pub fn matches(&self, other: &Self) -> bool {
+        self.qtype == other.qtype
+            && self.class == other.class
+            && trim_root(&self.name).eq_ignore_ascii_case(trim_root(&other.name))
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DnsMx {
+    pub preference: u16,
+    pub exchange:   String,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DnsSrv {
+    pub priority:   u16,
+    pub weight:     u16,
+    pub port:       u16,
+    pub target:     String,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DnsData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Cname(String),
+    Mx(DnsMx),
+    /// The character strings making up the record.
+    Txt(Vec<Vec<u8>>),
+    Srv(DnsSrv),
+    Other(u16, Vec<u8>),
+}
+
+impl DnsData {
+
+    pub fn rtype(&self) -> DnsType {
+        match self {
+            Self::A(_)          => DnsType::A,
+            Self::Aaaa(_)       => DnsType::Aaaa,
+            Self::Cname(_)      => DnsType::Cname,
+            Self::Mx(_)         => DnsType::Mx,
+            Self::Txt(_)        => DnsType::Txt,
+            Self::Srv(_)        => DnsType::Srv,
+            Self::Other(n, _)   => DnsType::from_code(*n),
+        }
+    }
+
+    /// Create a TXT record, splitting the text into character strings of up to 255 bytes.
+    pub fn txt(text: &str) -> Self {
+        let bytes = text.as_bytes();
+        if bytes.is_empty() {
+            return Self::Txt(vec![Vec::new()]);
+        }
+        Self::Txt(bytes.chunks(255).map(|chunk| chunk.to_vec()).collect())
+    }
+
+    /// The character strings of a TXT record joined together, as required for SPF and DKIM
+    /// records.
+    pub fn txt_string(&self) -> Option<String> {
+        match self {
+            Self::Txt(strings) => Some(String::from_utf8_lossy(&strings.concat()).to_string()),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DnsRecord {
+    pub name:   String,
+    pub class:  u16,
+    /// Time to live, in seconds.
+    pub ttl:    u32,
+    pub data:   DnsData,
+}
+
+impl DnsRecord {
+
+    pub fn new<S: Into<String>>(name: S, ttl: u32, data: DnsData) -> Self {
+        Self {
+            name:   name.into(),
+            class:  constant::DNS_CLASS_IN,
+            ttl,
+            data,
+        }
+    }
+
+    pub fn rtype(&self) -> DnsType { self.data.rtype() }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DnsMessage {
+    pub header:         DnsHeader,
+    pub questions:      Vec<DnsQuestion>,
+    pub answers:        Vec<DnsRecord>,
+    pub authorities:    Vec<DnsRecord>,
+    pub additionals:    Vec<DnsRecord>,
+}
+
+impl DnsMessage {
+
+    /// A standard query asking for recursion.
+    pub fn query(id: u16, name: &str, qtype: DnsType) -> Self {
+        Self {
+            header: DnsHeader {
+                id,
+                recursion_desired: true,
+                ..Default::default()
+            },
+            questions: vec![DnsQuestion::new(trim_root(name), qtype)],
+            ..Default::default()
+        }
+    }
+
+    /// An empty response to this query, with the same id and questions.
+    pub fn response(&self, rcode: DnsRcode) -> Self {
+        Self {
+            header: DnsHeader {
+                id:                     self.header.id,
+                response:               true,
+                opcode:                 self.header.opcode,
+                recursion_desired:      self.header.recursion_desired,
+                recursion_available:    true,
+                rcode,
+                ..Default::default()
+            },
+            questions: self.questions.clone(),
+            ..Default::default()
+        }
+    }
+
+    pub fn encode(&self) -> Outcome<Vec<u8>> {
+        let mut buf = Vec::with_capacity(512);
+        buf.extend_from_slice(&self.header.id.to_be_bytes());
+        buf.extend_from_slice(&self.header.flags().to_be_bytes());
+        for len in [
+            self.questions.len(),
+            self.answers.len(),
+            self.authorities.len(),
+            self.additionals.len(),
+        ] {
+            if len > u16::MAX as usize {
+                return Err(err!(
+                    "A DNS message section cannot hold {} entries.", len;
+                Encode, TooBig));
+            }
+            buf.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        for question in &self.questions {
+            res!(encode_name(&mut buf, &question.name));
+            buf.extend_from_slice(&question.qtype.code().to_be_bytes());
+            buf.extend_from_slice(&question.class.to_be_bytes());
+        }
+        for record in self.answers.iter()
+            .chain(self.authorities.iter())
+            .chain(self.additionals.iter())
+        {
+            res!(encode_record(&mut buf, record));
+        }
+        Ok(buf)
+    }
+
+    pub fn decode(buf: &[u8]) -> Outcome<Self> {
+        let mut rdr = DnsReader::new(buf);
+        let id = res!(rdr.u16());
+        let flags = res!(rdr.u16());
+        let qdcount = res!(rdr.u16());
+        let ancount = res!(rdr.u16());
+        let nscount = res!(rdr.u16());
+        let arcount = res!(rdr.u16());
+        let mut msg = Self {
+            header: DnsHeader::from_flags(id, flags),
+            ..Default::default()
+        };
+        for _ in 0..qdcount {
+            let name = res!(rdr.name());
+            let qtype = DnsType::from_code(res!(rdr.u16()));
+            let class = res!(rdr.u16());
+            msg.questions.push(DnsQuestion { name, qtype, class });
+        }
+        for _ in 0..ancount {
+            msg.answers.push(res!(rdr.record()));
+        }
+        for _ in 0..nscount {
+            msg.authorities.push(res!(rdr.record()));
+        }
+        for _ in 0..arcount {
+            msg.additionals.push(res!(rdr.record()));
+        }
+        Ok(msg)
+    }
+}
+
+/// Remove any trailing root dot.
+pub fn trim_root(name: &str) -> &str {
+    name.strip_suffix('.').unwrap_or(name)
+}
+
+/// Write a name in full as a sequence of labels.  Labels are not restricted to host name
+/// characters, so that names such as `_sip._tcp.example.com` can be used.
+pub fn encode_name(buf: &mut Vec<u8>, name: &str) -> Outcome<()> {
+    let name = trim_root(name);
+    let start = buf.len();
+    if !name.is_empty() {
+        for label in name.split('.') {
+            if label.is_empty() {
+                return Err(err!(
+                    "The DNS name '{}' contains an empty label.", name;
+                Encode, Invalid, Input));
+            }
+            if label.len() > 63 {
+                return Err(err!(
+                    "The DNS name label '{}' is longer than 63 bytes.", label;
+                Encode, Invalid, Size));
+            }
+            buf.push(label.len() as u8);
+            buf.extend_from_slice(label.as_bytes());
+        }
+    }
+    buf.push(0);
+    if buf.len() - start > 255 {
+        return Err(err!(
+            "The DNS name '{}' is longer than 255 bytes.", name;
+        Encode, Invalid, Size));
+    }
+    Ok(())
+}
+
+fn encode_record(buf: &mut Vec<u8>, record: &DnsRecord) -> Outcome<()> {
+    res!(encode_name(buf, &record.name));
+    buf.extend_from_slice(&record.rtype().code().to_be_bytes());
+    buf.extend_from_slice(&record.class.to_be_bytes());
+    buf.extend_from_slice(&record.ttl.to_be_bytes());
+    // Fill in the data length once the data has been written.
+    let len_pos = buf.len();
+    buf.extend_from_slice(&[0, 0]);
+    match &record.data {
+        DnsData::A(addr) => buf.extend_from_slice(&addr.octets()),
+        DnsData::Aaaa(addr) => buf.extend_from_slice(&addr.octets()),
+        DnsData::Cname(name) => res!(encode_name(buf, name)),
+        DnsData::Mx(mx) => {
+            buf.extend_from_slice(&mx.preference.to_be_bytes());
+            res!(encode_name(buf, &mx.exchange));
+        },
+        DnsData::Txt(strings) => for string in strings {
+            if string.len() > 255 {
+                return Err(err!(
+                    "A TXT record character string cannot be longer than 255 bytes, \
+                    found {}.", string.len();
+                Encode, Invalid, Size));
+            }
+            buf.push(string.len() as u8);
+            buf.extend_from_slice(string);
+        },
+        DnsData::Srv(srv) => {
+            buf.extend_from_slice(&srv.priority.to_be_bytes());
+            buf.extend_from_slice(&srv.weight.to_be_bytes());
+            buf.extend_from_slice(&srv.port.to_be_bytes());
+            res!(encode_name(buf, &srv.target));
+        },
+        DnsData::Other(_, data) => buf.extend_from_slice(data),
+    }
+    let len = buf.len() - len_pos - 2;
+    if len > u16::MAX as usize {
+        return Err(err!(
+            "The data for the {} record for '{}' is {} bytes long.",
+            record.rtype(), record.name, len;
+        Encode, TooBig));
+    }
+    buf[len_pos..len_pos + 2].copy_from_slice(&(len as u16).to_be_bytes());
+    Ok(())
+}
+
+/// Reads the fields of a message, checking every access against the end of the buffer.
+struct DnsReader<'a> {
+    buf:    &'a [u8],
+    pos:    usize,
+}
+
+impl<'a> DnsReader<'a> {
+
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn bytes(&mut self, n: usize) -> Outcome<&'a [u8]> {
+        match self.buf.get(self.pos..self.pos + n) {
+            Some(bytes) => {
+                self.pos += n;
+                Ok(bytes)
+            },
+            None => Err(err!(
+                "The DNS message ends before the {} bytes expected at position {}.", n, self.pos;
+            Decode, Invalid, Missing)),
+        }
+    }
+
+    fn u8(&mut self) -> Outcome<u8> {
+        Ok(res!(self.bytes(1))[0])
+    }
+
+    fn u16(&mut self) -> Outcome<u16> {
+        let b = res!(self.bytes(2));
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Outcome<u32> {
+        let b = res!(self.bytes(4));
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// Read a name, following compression pointers (RFC 1035 Section 4.1.4).  Pointers must
+    /// refer to earlier positions, which rules out loops.
+    fn name(&mut self) -> Outcome<String> {
+        let mut labels: Vec<String> = Vec::new();
+        let mut len = 0;
+        let mut pos = self.pos;
+        // The position after the name, once a pointer has been followed.
+        let mut end: Option<usize> = None;
+        loop {
+            let n = match self.buf.get(pos) {
+                Some(n) => *n as usize,
+                None => return Err(err!(
+                    "The DNS message ends within a name at position {}.", pos;
+                Decode, Invalid, Missing)),
+            };
+            match n & 0xc0 {
+                0x00 => {
+                    if n == 0 {
+                        pos += 1;
+                        break;
+                    }
+                    let label = match self.buf.get(pos + 1..pos + 1 + n) {
+                        Some(label) => label,
+                        None => return Err(err!(
+                            "The DNS message ends within a name label at position {}.", pos;
+                        Decode, Invalid, Missing)),
+                    };
+                    len += n + 1;
+                    if len > 255 {
+                        return Err(err!(
+                            "A DNS name at position {} is longer than 255 bytes.", self.pos;
+                        Decode, Invalid, Size));
+                    }
+                    labels.push(String::from_utf8_lossy(label).to_string());
+                    pos += n + 1;
+                },
+                0xc0 => {
+                    let low = match self.buf.get(pos + 1) {
+                        Some(low) => *low as usize,
+                        None => return Err(err!(
+                            "The DNS message ends within a name pointer at position {}.", pos;
+                        Decode, Invalid, Missing)),
+                    };
+                    let target = ((n & 0x3f) << 8) | low;
+                    if target >= pos {
+                        return Err(err!(
+                            "The DNS name pointer at position {} refers forward to {}.",
+                            pos, target;
+                        Decode, Invalid));
+                    }
+                    if end.is_none() {
+                        end = Some(pos + 2);
+                    }
+                    pos = target;
+                },
+                _ => return Err(err!(
+                    "Unsupported DNS name label type 0x{:02x} at position {}.", n, pos;
+                Decode, Invalid, Unimplemented)),
+            }
+        }
+        self.pos = end.unwrap_or(pos);
+        Ok(labels.join("."))
+    }
+
+    fn record(&mut self) -> Outcome<DnsRecord> {
+        let name = res!(self.name());
+        let rtype = res!(self.u16());
+        let class = res!(self.u16());
+        let ttl = res!(self.u32());
+        let len = res!(self.u16()) as usize;
+        let start = self.pos;
+        let end = start + len;
+        if end > self.buf.len() {
+            return Err(err!(
+                "The {} bytes of data for the DNS record '{}' overrun the message.", len, name;
+            Decode, Invalid, Size));
+        }
+        let data = match DnsType::from_code(rtype) {
+            DnsType::A => {
+                let b = res!(self.fixed_data(len, 4, &name));
+                DnsData::A(Ipv4Addr::new(b[0], b[1], b[2], b[3]))
+            },
+            DnsType::Aaaa => {
+                let b = res!(self.fixed_data(len, 16, &name));
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(b);
+                DnsData::Aaaa(Ipv6Addr::from(octets))
+            },
+            DnsType::Cname => DnsData::Cname(res!(self.name())),
+            DnsType::Mx => DnsData::Mx(DnsMx {
+                preference: res!(self.u16()),
+                exchange:   res!(self.name()),
+            }),
+            DnsType::Txt => {
+                let mut strings = Vec::new();
+                while self.pos < end {
+                    let n = res!(self.u8()) as usize;
+                    strings.push(res!(self.bytes(n)).to_vec());
+                }
+                DnsData::Txt(strings)
+            },
+            DnsType::Srv => DnsData::Srv(DnsSrv {
+                priority:   res!(self.u16()),
+                weight:     res!(self.u16()),
+                port:       res!(self.u16()),
+                target:     res!(self.name()),
+            }),
+            DnsType::Other(n) => DnsData::Other(n, res!(self.bytes(len)).to_vec()),
+        };
+        if self.pos != end {
+            return Err(err!(
+                "The {} record for '{}' has {} bytes of data, but {} were read.",
+                DnsType::from_code(rtype), name, len, self.pos - start;
+            Decode, Invalid, Mismatch));
+        }
+        Ok(DnsRecord { name, class, ttl, data })
+    }
+
+    fn fixed_data(&mut self, len: usize, expected: usize, name: &str) -> Outcome<&'a [u8]> {
+        if len != expected {
+            return Err(err!(
+                "Expected {} bytes of address data for '{}', found {}.", expected, name, len;
+            Decode, Invalid, Size));
+        }
+        self.bytes(len)
+    }
+}
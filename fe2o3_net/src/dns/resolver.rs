@@ -0,0 +1,452 @@
+//! An async stub resolver, sending recursive queries to the configured nameservers.
+//!
+//! Queries are sent over UDP, and repeated over TCP when the response is truncated.  Each
+//! nameserver is tried in turn, for the configured number of attempts, until one gives an answer
+//! or reports that the name does not exist.  Responses whose id or question do not match the
+//! query are ignored.  Answers are cached for the smallest TTL of the records, and the absence of
+//! a name or record for `DnsResolverConfig::negative_ttl`.  A lookup for a name that does not
+//! exist returns no records rather than an error.
+//!
+//! ```ignore
+//! let resolver = DnsResolver::new(DnsResolverConfig::system());
+//! for mx in res!(resolver.lookup_mx("example.com").await) {
+//!     ...
+//! }
+//! ```
+use crate::{
+    constant,
+    dns::msg::{
+        trim_root,
+        DnsData,
+        DnsMessage,
+        DnsMx,
+        DnsRcode,
+        DnsRecord,
+        DnsSrv,
+        DnsType,
+    },
+};
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    rand::Rand,
+};
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    net::{
+        IpAddr,
+        Ipv4Addr,
+        Ipv6Addr,
+        SocketAddr,
+    },
+    path::Path,
+    sync::{
+        Arc,
+        RwLock,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use tokio::{
+    io::{
+        AsyncReadExt,
+        AsyncWriteExt,
+    },
+    net::{
+        TcpStream,
+        UdpSocket,
+    },
+};
+
+
+#[derive(Clone, Debug)]
+pub struct DnsResolverConfig {
+    pub nameservers:        Vec<SocketAddr>,
+    /// Limits each exchange with a nameserver.
+    pub timeout:            Duration,
+    /// The number of times to try the list of nameservers.
+    pub attempts:           usize,
+    /// Zero disables the cache.
+    pub cache_max_entries:  usize,
+    pub max_ttl:            Duration,
+    pub negative_ttl:       Duration,
+}
+
+impl Default for DnsResolverConfig {
+    fn default() -> Self {
+        Self {
+            nameservers:        vec![
+                SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), constant::DNS_PORT),
+            ],
+            timeout:            constant::DNS_RESOLVER_TIMEOUT,
+            attempts:           constant::DNS_RESOLVER_ATTEMPTS,
+            cache_max_entries:  constant::DNS_CACHE_MAX_ENTRIES,
+            max_ttl:            constant::DNS_CACHE_MAX_TTL,
+            negative_ttl:       constant::DNS_CACHE_NEGATIVE_TTL,
+        }
+    }
+}
+
+impl DnsResolverConfig {
+
+    pub fn with_nameservers(nameservers: Vec<SocketAddr>) -> Self {
+        Self {
+            nameservers,
+            ..Default::default()
+        }
+    }
+
+    /// Use the nameservers listed in a `resolv.conf` file.
+    pub fn from_resolv_conf<P: AsRef<Path>>(path: P) -> Outcome<Self> {
+        let text = match fs::read_to_string(path.as_ref()) {
+            Ok(text) => text,
+            Err(e) => return Err(err!(e,
+                "While reading nameservers from {:?}.", path.as_ref();
+            IO, File, Read)),
+        };
+        let nameservers = Self::parse_resolv_conf(&text);
+        if nameservers.is_empty() {
+            return Err(err!(
+                "No nameservers were found in {:?}.", path.as_ref();
+            Configuration, Missing));
+        }
+        Ok(Self::with_nameservers(nameservers))
+    }
+
+    /// The `nameserver` addresses in the text of a `resolv.conf` file, ignoring any that cannot
+    /// be parsed, such as IPv6 addresses with a zone.
+    pub fn parse_resolv_conf(text: &str) -> Vec<SocketAddr> {
+        let mut nameservers = Vec::new();
+        for line in text.lines() {
+            let mut words = line.split_whitespace();
+            if words.next() != Some("nameserver") {
+                continue;
+            }
+            if let Some(Ok(ip)) = words.next().map(|word| word.parse::<IpAddr>()) {
+                nameservers.push(SocketAddr::new(ip, constant::DNS_PORT));
+            }
+        }
+        nameservers
+    }
+
+    /// Use the system nameservers, or the default if they cannot be read.
+    pub fn system() -> Self {
+        match Self::from_resolv_conf(constant::DNS_RESOLV_CONF) {
+            Ok(cfg) => cfg,
+            Err(e) => {
+                warn!("{}", e);
+                Self::default()
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct DnsCacheEntry {
+    records:    Vec<DnsRecord>,
+    expires:    Instant,
+}
+
+type DnsCache = BTreeMap<(String, DnsType), DnsCacheEntry>;
+
+/// An async stub resolver.  Clones share the cache.
+#[derive(Clone, Debug)]
+pub struct DnsResolver {
+    pub cfg:    DnsResolverConfig,
+    cache:      Arc<RwLock<DnsCache>>,
+}
+
+impl DnsResolver {
+
+    pub fn new(cfg: DnsResolverConfig) -> Self {
+        Self {
+            cfg,
+            cache: Arc::new(RwLock::new(BTreeMap::new())),
+        }
+    }
+
+    /// The records of the given type for the name, following any CNAME records in the answer.
+    /// The result is empty when the name or record does not exist.
+    pub async fn lookup(&self, name: &str, qtype: DnsType) -> Outcome<Vec<DnsRecord>> {
+        let key = (trim_root(name).to_lowercase(), qtype);
+        if let Some(records) = res!(self.cached(&key)) {
+            trace!("DNS cache hit for {} {}.", qtype, key.0);
+            return Ok(records);
+        }
+        let response = res!(self.query(name, qtype).await);
+        let records = match response.header.rcode {
+            DnsRcode::NxDomain => Vec::new(),
+            _ => Self::answer_records(&response, name, qtype),
+        };
+        let ttl = match records.iter().map(|record| record.ttl).min() {
+            Some(ttl) => Duration::from_secs(ttl as u64).min(self.cfg.max_ttl),
+            None => self.cfg.negative_ttl,
+        };
+        res!(self.cache_put(key, records.clone(), ttl));
+        Ok(records)
+    }
+
+    pub async fn lookup_ipv4(&self, name: &str) -> Outcome<Vec<Ipv4Addr>> {
+        Ok(res!(self.lookup(name, DnsType::A).await).into_iter()
+            .filter_map(|record| match record.data {
+                DnsData::A(addr) => Some(addr),
+                _ => None,
+            })
+            .collect())
+    }
+
+    pub async fn lookup_ipv6(&self, name: &str) -> Outcome<Vec<Ipv6Addr>> {
+        Ok(res!(self.lookup(name, DnsType::Aaaa).await).into_iter()
+            .filter_map(|record| match record.data {
+                DnsData::Aaaa(addr) => Some(addr),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// The IPv4 addresses for the name followed by the IPv6 addresses.
+    pub async fn lookup_ip(&self, name: &str) -> Outcome<Vec<IpAddr>> {
+        let mut addrs: Vec<IpAddr> = res!(self.lookup_ipv4(name).await).into_iter()
+            .map(IpAddr::V4)
+            .collect();
+        addrs.extend(res!(self.lookup_ipv6(name).await).into_iter().map(IpAddr::V6));
+        Ok(addrs)
+    }
+
+    /// The mail exchanges for the domain, most preferred first.
+    pub async fn lookup_mx(&self, domain: &str) -> Outcome<Vec<DnsMx>> {
+        let mut mxs: Vec<DnsMx> = res!(self.lookup(domain, DnsType::Mx).await).into_iter()
+            .filter_map(|record| match record.data {
+                DnsData::Mx(mx) => Some(mx),
+                _ => None,
+            })
+            .collect();
+        mxs.sort_by_key(|mx| mx.preference);
+        Ok(mxs)
+    }
+
+    /// The text of each TXT record, with its character strings joined.
+    pub async fn lookup_txt(&self, name: &str) -> Outcome<Vec<String>> {
+        Ok(res!(self.lookup(name, DnsType::Txt).await).iter()
+            .filter_map(|record| record.data.txt_string())
+            .collect())
+    }
+
+    /// The services for the name, such as `_submission._tcp.example.com`, in order of priority
+    /// and then decreasing weight.
+    pub async fn lookup_srv(&self, name: &str) -> Outcome<Vec<DnsSrv>> {
+        let mut srvs: Vec<DnsSrv> = res!(self.lookup(name, DnsType::Srv).await).into_iter()
+            .filter_map(|record| match record.data {
+                DnsData::Srv(srv) => Some(srv),
+                _ => None,
+            })
+            .collect();
+        srvs.sort_by(|a, b| a.priority.cmp(&b.priority).then(b.weight.cmp(&a.weight)));
+        Ok(srvs)
+    }
+
+    /// The canonical name for an alias, if it is one.
+    pub async fn lookup_cname(&self, name: &str) -> Outcome<Option<String>> {
+        Ok(res!(self.lookup(name, DnsType::Cname).await).into_iter()
+            .find_map(|record| match record.data {
+                DnsData::Cname(cname) => Some(cname),
+                _ => None,
+            }))
+    }
+
+    /// Send a query to each nameserver in turn, bypassing the cache, and return the first
+    /// response that either answers it or reports that the name does not exist.
+    pub async fn query(&self, name: &str, qtype: DnsType) -> Outcome<DnsMessage> {
+        if self.cfg.nameservers.is_empty() {
+            return Err(err!(
+                "No nameservers are configured for the query for {} {}.", qtype, name;
+            Configuration, Missing));
+        }
+        let mut last_err = None;
+        for _ in 0..self.cfg.attempts.max(1) {
+            for ns in &self.cfg.nameservers {
+                let request = DnsMessage::query(Rand::rand_u16(), name, qtype);
+                match self.exchange(*ns, &request).await {
+                    Ok(response) => match response.header.rcode {
+                        DnsRcode::NoError | DnsRcode::NxDomain => return Ok(response),
+                        rcode => {
+                            debug!("Nameserver {} responded to {} {} with {:?}.",
+                                ns, qtype, name, rcode);
+                            last_err = Some(err!(
+                                "Nameserver {} responded to {} {} with {:?}.",
+                                ns, qtype, name, rcode;
+                            Network, Unexpected));
+                        },
+                    },
+                    Err(e) => {
+                        debug!("{}", e);
+                        last_err = Some(e);
+                    },
+                }
+            }
+        }
+        Err(match last_err {
+            Some(e) => err!(e,
+                "No nameserver answered the query for {} {}.", qtype, name;
+            Network),
+            None => err!(
+                "No nameserver answered the query for {} {}.", qtype, name;
+            Network, Bug),
+        })
+    }
+
+    /// The number of names and types held in the cache, including any that have expired.
+    pub fn cache_len(&self) -> Outcome<usize> {
+        let unlocked_cache = lock_read!(self.cache);
+        Ok(unlocked_cache.len())
+    }
+
+    pub fn clear_cache(&self) -> Outcome<()> {
+        let mut unlocked_cache = lock_write!(self.cache);
+        unlocked_cache.clear();
+        Ok(())
+    }
+
+    fn cached(&self, key: &(String, DnsType)) -> Outcome<Option<Vec<DnsRecord>>> {
+        let unlocked_cache = lock_read!(self.cache);
+        Ok(match unlocked_cache.get(key) {
+            Some(entry) if entry.expires > Instant::now() => Some(entry.records.clone()),
+            _ => None,
+        })
+    }
+
+    fn cache_put(
+        &self,
+        key:        (String, DnsType),
+        records:    Vec<DnsRecord>,
+        ttl:        Duration,
+    )
+        -> Outcome<()>
+    {
+        if self.cfg.cache_max_entries == 0 || ttl.is_zero() {
+            return Ok(());
+        }
+        let mut unlocked_cache = lock_write!(self.cache);
+        if unlocked_cache.len() >= self.cfg.cache_max_entries && !unlocked_cache.contains_key(&key) {
+            let now = Instant::now();
+            unlocked_cache.retain(|_, entry| entry.expires > now);
+            if unlocked_cache.len() >= self.cfg.cache_max_entries {
+                // Make room by dropping the entry closest to expiry.
+                let soonest = unlocked_cache.iter()
+                    .min_by_key(|(_, entry)| entry.expires)
+                    .map(|(key, _)| key.clone());
+                if let Some(soonest) = soonest {
+                    unlocked_cache.remove(&soonest);
+                }
+            }
+        }
+        unlocked_cache.insert(key, DnsCacheEntry {
+            records,
+            expires: Instant::now() + ttl,
+        });
+        Ok(())
+    }
+
+    /// The answer records of the requested type, following a chain of CNAME records from the
+    /// name.  When CNAME records are requested, only those for the name itself are returned.
+    fn answer_records(response: &DnsMessage, name: &str, qtype: DnsType) -> Vec<DnsRecord> {
+        let mut current = trim_root(name).to_string();
+        // Each step must be a different record, which bounds the chain.
+        for _ in 0..=response.answers.len() {
+            let matches: Vec<DnsRecord> = response.answers.iter()
+                .filter(|record| record.rtype() == qtype
+                    && trim_root(&record.name).eq_ignore_ascii_case(&current))
+                .cloned()
+                .collect();
+            if !matches.is_empty() || qtype == DnsType::Cname {
+                return matches;
+            }
+            let next = response.answers.iter().find_map(|record| match &record.data {
+                DnsData::Cname(target) if trim_root(&record.name).eq_ignore_ascii_case(&current) =>
+                    Some(trim_root(target).to_string()),
+                _ => None,
+            });
+            match next {
+                Some(next) => current = next,
+                None => break,
+            }
+        }
+        Vec::new()
+    }
+
+    /// Exchange a query with a nameserver over UDP, switching to TCP if the response is
+    /// truncated.
+    async fn exchange(&self, ns: SocketAddr, request: &DnsMessage) -> Outcome<DnsMessage> {
+        let bytes = res!(request.encode());
+        let wait = self.cfg.timeout;
+        let response = match tokio::time::timeout(wait, Self::exchange_udp(ns, request, &bytes)).await {
+            Ok(result) => res!(result),
+            Err(e) => return Err(err!(e,
+                "Nameserver {} did not respond over UDP within {:?}.", ns, wait;
+            IO, Network, Timeout)),
+        };
+        if !response.header.truncated {
+            return Ok(response);
+        }
+        debug!("Response from nameserver {} was truncated, retrying over TCP.", ns);
+        match tokio::time::timeout(wait, Self::exchange_tcp(ns, request, &bytes)).await {
+            Ok(result) => result,
+            Err(e) => Err(err!(e,
+                "Nameserver {} did not respond over TCP within {:?}.", ns, wait;
+            IO, Network, Timeout)),
+        }
+    }
+
+    async fn exchange_udp(ns: SocketAddr, request: &DnsMessage, bytes: &[u8]) -> Outcome<DnsMessage> {
+        let local = match ns {
+            SocketAddr::V4(_) => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+            SocketAddr::V6(_) => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+        };
+        let socket = res!(UdpSocket::bind(local).await);
+        // Only datagrams from the nameserver are then received.
+        res!(socket.connect(ns).await);
+        res!(socket.send(bytes).await);
+        let mut buf = vec![0u8; constant::DNS_UDP_BUFFER_SIZE];
+        loop {
+            let n = res!(socket.recv(&mut buf).await);
+            match DnsMessage::decode(&buf[..n]) {
+                Ok(response) if Self::is_reply(request, &response) => return Ok(response),
+                Ok(_) => debug!("Ignoring a DNS message from {} that does not match the query.", ns),
+                Err(e) => debug!("Ignoring an invalid DNS message from {}: {}", ns, e),
+            }
+        }
+    }
+
+    async fn exchange_tcp(ns: SocketAddr, request: &DnsMessage, bytes: &[u8]) -> Outcome<DnsMessage> {
+        let mut stream = res!(TcpStream::connect(ns).await);
+        // Messages over TCP are preceded by their length (RFC 1035 Section 4.2.2).
+        let mut framed = Vec::with_capacity(bytes.len() + 2);
+        framed.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+        framed.extend_from_slice(bytes);
+        res!(stream.write_all(&framed).await);
+        let len = res!(stream.read_u16().await) as usize;
+        let mut buf = vec![0u8; len];
+        res!(stream.read_exact(&mut buf).await);
+        let response = res!(DnsMessage::decode(&buf));
+        if !Self::is_reply(request, &response) {
+            return Err(err!(
+                "The response from nameserver {} over TCP does not match the query.", ns;
+            Network, Mismatch));
+        }
+        Ok(response)
+    }
+
+    fn is_reply(request: &DnsMessage, response: &DnsMessage) -> bool {
+        response.header.response
+            && response.header.id == request.header.id
+            && response.questions.len() == request.questions.len()
+            && response.questions.iter()
+                .zip(request.questions.iter())
+                .all(|(a, b)| a.matches(b))
+    }
+}
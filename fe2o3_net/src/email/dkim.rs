@@ -0,0 +1,822 @@
+//! DomainKeys Identified Mail.
+//!
+//! - RFC 6376 DomainKeys Identified Mail (DKIM) Signatures.
+//! - RFC 8301 Cryptographic Algorithm and Key Usage Update to DKIM, which retires rsa-sha1 and
+//!   RSA keys shorter than 1024 bits.
+//! - RFC 8463 A New Cryptographic Signature Method for DKIM, i.e. ed25519-sha256.
+//!
+//! A `DkimSigner` adds a DKIM-Signature header to outgoing mail, while received mail is
+//! checked with `DkimMessage::verify`, given the public key record published by the signing
+//! domain at `<selector>._domainkey.<domain>`.
+//!
+//! Messages are signed and verified either as raw bytes, or as an `EmailMessage`.  Because an
+//! `EmailMessage` does not keep header folding or trailing whitespace, messages received as an
+//! `EmailMessage` can only be reliably verified when signed with relaxed canonicalisation.
+use crate::{
+    constant,
+    email::{
+        mime,
+        msg::{
+            EmailHeader,
+            EmailMessage,
+        },
+    },
+};
+
+use oxedyne_fe2o3_core::prelude::*;
+use oxedyne_fe2o3_crypto::sign::SignatureScheme;
+use oxedyne_fe2o3_iop_crypto::{
+    keys::KeyManager,
+    sign::Signer,
+};
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::Arc,
+    time::{
+        Duration,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+use ring::{
+    digest,
+    rand::SystemRandom,
+    signature::{
+        self,
+        RsaKeyPair,
+        UnparsedPublicKey,
+    },
+};
+
+
+/// Header and body canonicalisation (RFC 6376 Section 3.4).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Canonicalisation {
+    #[default]
+    Relaxed,
+    Simple,
+}
+
+impl fmt::Display for Canonicalisation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            Self::Relaxed   => "relaxed",
+            Self::Simple    => "simple",
+        })
+    }
+}
+
+impl FromStr for Canonicalisation {
+    type Err = Error<ErrTag>;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s {
+            "relaxed"   => Self::Relaxed,
+            "simple"    => Self::Simple,
+            _ => return Err(err!(
+                "Unrecognised DKIM canonicalisation '{}'.", s;
+            Unknown, Input)),
+        })
+    }
+}
+
+impl Canonicalisation {
+
+    /// Canonicalise a header field, given its name and the raw value following the colon.
+    pub fn header(&self, name: &str, value: &str) -> Vec<u8> {
+        match self {
+            Self::Simple => fmt!("{}:{}\r\n", name, value).into_bytes(),
+            Self::Relaxed => {
+                let value: Vec<u8> = value.bytes().filter(|b| *b != b'\r' && *b != b'\n').collect();
+                let value = compress_wsp(&value);
+                let value = String::from_utf8_lossy(&value);
+                fmt!("{}:{}\r\n", name.trim_end().to_lowercase(), value.trim_matches(' '))
+                    .into_bytes()
+            }
+        }
+    }
+
+    /// Canonicalise a body, which should use CRLF line endings.
+    pub fn body(&self, body: &[u8]) -> Vec<u8> {
+        let body = body.strip_suffix(b"\n").unwrap_or(body);
+        let mut lines: Vec<Vec<u8>> = match body.is_empty() {
+            true => Vec::new(),
+            false => body.split(|b| *b == b'\n')
+                .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+                .map(|line| match self {
+                    Self::Simple => line.to_vec(),
+                    Self::Relaxed => {
+                        let mut line = compress_wsp(line);
+                        if line.last() == Some(&b' ') {
+                            line.pop();
+                        }
+                        line
+                    }
+                })
+                .collect(),
+        };
+        while lines.last().map(|line| line.is_empty()).unwrap_or(false) {
+            lines.pop();
+        }
+        if lines.is_empty() {
+            return match self {
+                Self::Simple => b"\r\n".to_vec(),
+                Self::Relaxed => Vec::new(),
+            };
+        }
+        let mut out = Vec::with_capacity(body.len() + 2);
+        for line in lines {
+            out.extend_from_slice(&line);
+            out.extend_from_slice(b"\r\n");
+        }
+        out
+    }
+}
+
+/// Reduce each run of spaces and tabs to a single space.
+fn compress_wsp(byts: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(byts.len());
+    for b in byts {
+        match b {
+            b' ' | b'\t' => if out.last() != Some(&b' ') {
+                out.push(b' ');
+            },
+            _ => out.push(*b),
+        }
+    }
+    out
+}
+
+/// The signing algorithms permitted by RFC 8301 and RFC 8463.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DkimAlgorithm {
+    Ed25519Sha256,
+    RsaSha256,
+}
+
+impl fmt::Display for DkimAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            Self::Ed25519Sha256 => "ed25519-sha256",
+            Self::RsaSha256     => "rsa-sha256",
+        })
+    }
+}
+
+impl FromStr for DkimAlgorithm {
+    type Err = Error<ErrTag>;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "ed25519-sha256"    => Self::Ed25519Sha256,
+            "rsa-sha256"        => Self::RsaSha256,
+            _ => return Err(err!(
+                "Unsupported DKIM signing algorithm '{}'.", s;
+            Unimplemented, Input)),
+        })
+    }
+}
+
+impl DkimAlgorithm {
+    /// The key type, as given by the "k=" tag of a public key record.
+    pub fn key_type(&self) -> &'static str {
+        match self {
+            Self::Ed25519Sha256 => "ed25519",
+            Self::RsaSha256     => "rsa",
+        }
+    }
+}
+
+/// A private key used to sign messages.
+#[derive(Clone)]
+pub enum DkimSigningKey {
+    /// A `SignatureScheme::Ed25519` holding both the public and secret keys.
+    Ed25519(Box<SignatureScheme>),
+    Rsa(Arc<RsaKeyPair>),
+}
+
+impl fmt::Debug for DkimSigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DkimSigningKey({})", self.algorithm())
+    }
+}
+
+impl DkimSigningKey {
+
+    /// Use a `SignatureScheme::Ed25519` holding both the public and secret keys.
+    pub fn ed25519(scheme: SignatureScheme) -> Outcome<Self> {
+        match &scheme {
+            SignatureScheme::Ed25519(_) => Ok(Self::Ed25519(Box::new(scheme))),
+            _ => Err(err!(
+                "A DKIM Ed25519 key requires SignatureScheme::Ed25519, not {:?}.", scheme;
+            Invalid, Configuration)),
+        }
+    }
+
+    /// Use an unencrypted PKCS#8 RSA private key, of between 2048 and 4096 bits.
+    pub fn rsa_from_pkcs8(der: &[u8]) -> Outcome<Self> {
+        match RsaKeyPair::from_pkcs8(der) {
+            Ok(key) => Ok(Self::Rsa(Arc::new(key))),
+            Err(e) => Err(err!(
+                "Invalid PKCS#8 RSA private key: {}.", e;
+            Invalid, Input, Configuration)),
+        }
+    }
+
+    /// Use a PKCS#1 RSA private key, of between 2048 and 4096 bits.
+    pub fn rsa_from_der(der: &[u8]) -> Outcome<Self> {
+        match RsaKeyPair::from_der(der) {
+            Ok(key) => Ok(Self::Rsa(Arc::new(key))),
+            Err(e) => Err(err!(
+                "Invalid PKCS#1 RSA private key: {}.", e;
+            Invalid, Input, Configuration)),
+        }
+    }
+
+    pub fn algorithm(&self) -> DkimAlgorithm {
+        match self {
+            Self::Ed25519(_)    => DkimAlgorithm::Ed25519Sha256,
+            Self::Rsa(_)        => DkimAlgorithm::RsaSha256,
+        }
+    }
+
+    fn sign(&self, data: &[u8]) -> Outcome<Vec<u8>> {
+        match self {
+            // The Ed25519 signature is over the SHA-256 hash (RFC 8463 Section 3).
+            Self::Ed25519(scheme) => scheme.sign(digest::digest(&digest::SHA256, data).as_ref()),
+            Self::Rsa(key) => {
+                let mut sig = vec![0; key.public().modulus_len()];
+                match key.sign(&signature::RSA_PKCS1_SHA256, &SystemRandom::new(), data, &mut sig) {
+                    Ok(()) => Ok(sig),
+                    Err(e) => Err(err!(
+                        "RSA signing failed: {}.", e;
+                    Encode)),
+                }
+            }
+        }
+    }
+
+    /// The DNS TXT record to publish at `<selector>._domainkey.<domain>`.
+    pub fn public_record(&self) -> Outcome<String> {
+        let pk = match self {
+            Self::Ed25519(scheme) => match res!(scheme.get_public_key()) {
+                Some(pk) => pk.to_vec(),
+                None => return Err(err!(
+                    "The Ed25519 signing key has no public key."; Missing, Configuration)),
+            },
+            Self::Rsa(key) => rsa_spki(key.public().as_ref()),
+        };
+        Ok(fmt!("v=DKIM1; k={}; p={}", self.algorithm().key_type(), base64::encode(pk)))
+    }
+}
+
+/// Signs outgoing messages on behalf of a domain.
+#[derive(Clone, Debug)]
+pub struct DkimSigner {
+    /// The signing domain, "d=".
+    pub domain:         String,
+    /// The selector, "s=", under which the public key is published.
+    pub selector:       String,
+    pub key:            DkimSigningKey,
+    /// The names of the headers to sign, each instance of which present in the message is
+    /// signed.  "From" is always signed.
+    pub headers:        Vec<String>,
+    pub header_canon:   Canonicalisation,
+    pub body_canon:     Canonicalisation,
+    /// The agent or user identifier, "i=", which must be within the signing domain.
+    pub identity:       Option<String>,
+    /// When set, the signature expires after this time, "x=".
+    pub expiry:         Option<Duration>,
+}
+
+impl DkimSigner {
+
+    pub fn new<S: Into<String>>(domain: S, selector: S, key: DkimSigningKey) -> Self {
+        Self {
+            domain:         domain.into(),
+            selector:       selector.into(),
+            key,
+            headers:        constant::DKIM_SIGNED_HEADERS.iter().map(|h| h.to_string()).collect(),
+            header_canon:   Canonicalisation::Relaxed,
+            body_canon:     Canonicalisation::Relaxed,
+            identity:       None,
+            expiry:         None,
+        }
+    }
+
+    /// Return the value of a DKIM-Signature header for the message.
+    pub fn sign(&self, msg: &DkimMessage) -> Outcome<String> {
+        let mut names: Vec<String> = Vec::new();
+        if !self.headers.iter().any(|h| h.eq_ignore_ascii_case("from")) {
+            names.push(fmt!("from"));
+        }
+        names.extend(self.headers.iter().map(|h| h.to_lowercase()));
+        let mut signed = Vec::new();
+        for name in &names {
+            let count = msg.fields.iter().filter(|(n, _)| n.trim().eq_ignore_ascii_case(name)).count();
+            for _ in 0..count {
+                signed.push(name.clone());
+            }
+        }
+        if !signed.iter().any(|h| h == "from") {
+            return Err(err!("A message must have a From header to be signed."; Input, Missing));
+        }
+
+        let body_hash = digest::digest(&digest::SHA256, &self.body_canon.body(&msg.body));
+        let now = unix_time();
+        let mut value = fmt!("v=1; a={}; c={}/{}; d={}; s={}; t={};",
+            self.key.algorithm(), self.header_canon, self.body_canon,
+            self.domain, self.selector, now);
+        if let Some(expiry) = self.expiry {
+            value.push_str(&fmt!(" x={};", now + expiry.as_secs()));
+        }
+        if let Some(identity) = &self.identity {
+            value.push_str(&fmt!(" i={};", identity));
+        }
+        value.push_str(&fmt!(" h={}; bh={}; b=", signed.join(":"), base64::encode(body_hash)));
+
+        // The header is written with a space after the colon, which simple canonicalisation
+        // keeps.
+        let data = msg.header_hash_input(&signed, "DKIM-Signature", &fmt!(" {}", value), self.header_canon);
+        let sig = res!(self.key.sign(&data));
+        value.push_str(&base64::encode(sig));
+        Ok(value)
+    }
+
+    /// Return the message with a DKIM-Signature header prepended.
+    pub fn sign_bytes(&self, raw: &[u8]) -> Outcome<Vec<u8>> {
+        let raw = mime::crlf(raw);
+        let value = res!(self.sign(&DkimMessage::from_bytes(&raw)));
+        let mut signed = fmt!("DKIM-Signature: {}\r\n", value).into_bytes();
+        signed.extend_from_slice(&raw);
+        Ok(signed)
+    }
+
+    /// Add a DKIM-Signature header to the message.
+    pub fn sign_email(&self, email: &mut EmailMessage) -> Outcome<()> {
+        let value = res!(self.sign(&DkimMessage::from(&*email)));
+        email.headers.insert(0, EmailHeader::Other(fmt!("DKIM-Signature"), value));
+        Ok(())
+    }
+}
+
+/// A parsed DKIM-Signature header.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DkimSignature {
+    pub algorithm:      DkimAlgorithm,
+    pub header_canon:   Canonicalisation,
+    pub body_canon:     Canonicalisation,
+    pub domain:         String,
+    pub selector:       String,
+    /// The lower case names of the signed headers.
+    pub headers:        Vec<String>,
+    pub body_hash:      Vec<u8>,
+    pub signature:      Vec<u8>,
+    pub identity:       Option<String>,
+    pub body_length:    Option<usize>,
+    pub timestamp:      Option<u64>,
+    pub expiration:     Option<u64>,
+    /// The header name and value as they appear in the message.
+    pub field:          (String, String),
+}
+
+impl DkimSignature {
+
+    /// Parse the value of a DKIM-Signature header (RFC 6376 Section 3.5).
+    pub fn parse(name: &str, value: &str) -> Outcome<Self> {
+        let tags = res!(tag_list(value));
+        let get = |tag: &str| tags.iter().find(|(t, _)| t == tag).map(|(_, v)| v.as_str());
+        let require = |tag: &str| match get(tag) {
+            Some(v) => Ok(v),
+            None => Err(err!(
+                "The DKIM-Signature has no '{}=' tag.", tag;
+            Input, Missing)),
+        };
+        let b64 = |v: &str| -> Outcome<Vec<u8>> {
+            let v: String = v.chars().filter(|c| !c.is_whitespace()).collect();
+            Ok(res!(base64::decode(v), Decode, Input))
+        };
+
+        if res!(require("v")) != "1" {
+            return Err(err!(
+                "Unsupported DKIM-Signature version '{}'.", res!(require("v"));
+            Unimplemented, Input));
+        }
+        let algorithm = res!(DkimAlgorithm::from_str(res!(require("a"))));
+        let (header_canon, body_canon) = match get("c") {
+            None => (Canonicalisation::Simple, Canonicalisation::Simple),
+            Some(c) => match c.split_once('/') {
+                Some((h, b)) => (res!(Canonicalisation::from_str(h)), res!(Canonicalisation::from_str(b))),
+                None => (res!(Canonicalisation::from_str(c)), Canonicalisation::Simple),
+            },
+        };
+        let domain = res!(require("d")).to_lowercase();
+        let selector = res!(require("s")).to_string();
+        let headers: Vec<String> = res!(require("h"))
+            .split(':')
+            .map(|h| h.trim().to_lowercase())
+            .collect();
+        if !headers.iter().any(|h| h == "from") {
+            return Err(err!("The DKIM-Signature does not sign the From header."; Input, Missing));
+        }
+        let identity = get("i").map(str::to_string);
+        if let Some(identity) = &identity {
+            let id_domain = identity.rsplit_once('@').map(|(_, d)| d).unwrap_or("").to_lowercase();
+            if id_domain != domain && !id_domain.ends_with(&fmt!(".{}", domain)) {
+                return Err(err!(
+                    "The DKIM-Signature identity '{}' is not within the domain '{}'.", identity, domain;
+                Input, Invalid, Security));
+            }
+        }
+        let number = |tag: &str| -> Outcome<Option<u64>> {
+            match get(tag) {
+                Some(v) => Ok(Some(res!(v.parse::<u64>(), Decode, Input))),
+                None => Ok(None),
+            }
+        };
+        Ok(Self {
+            algorithm,
+            header_canon,
+            body_canon,
+            domain,
+            selector,
+            headers,
+            body_hash: res!(b64(res!(require("bh")))),
+            signature: res!(b64(res!(require("b")))),
+            identity,
+            body_length: res!(number("l")).map(|l| l as usize),
+            timestamp: res!(number("t")),
+            expiration: res!(number("x")),
+            field: (name.to_string(), value.to_string()),
+        })
+    }
+
+    /// Verify the signature with the public key published by the signing domain.  A signature
+    /// that does not match gives `false`, while an unusable signature or key is an error.
+    pub fn verify(&self, msg: &DkimMessage, key: &DkimPublicKey) -> Outcome<bool> {
+        if key.algorithm != self.algorithm {
+            return Err(err!(
+                "The {} key cannot verify an {} signature.", key.algorithm.key_type(), self.algorithm;
+            Mismatch, Input));
+        }
+        if !key.hashes.is_empty() && !key.hashes.iter().any(|h| h == "sha256") {
+            return Err(err!(
+                "The key does not permit sha256, only {:?}.", key.hashes;
+            Mismatch, Input));
+        }
+        if key.strict {
+            if let Some(identity) = &self.identity {
+                if !identity.to_lowercase().ends_with(&fmt!("@{}", self.domain)) {
+                    return Err(err!(
+                        "The key does not permit the subdomain identity '{}'.", identity;
+                    Security, Input));
+                }
+            }
+        }
+        if let Some(expiration) = self.expiration {
+            if expiration < unix_time() {
+                return Err(err!(
+                    "The DKIM-Signature expired at {}.", expiration;
+                Timeout, Input));
+            }
+        }
+
+        let mut body = self.body_canon.body(&msg.body);
+        if let Some(length) = self.body_length {
+            if length > body.len() {
+                return Ok(false);
+            }
+            body.truncate(length);
+        }
+        if digest::digest(&digest::SHA256, &body).as_ref() != &self.body_hash[..] {
+            debug!("DKIM body hash mismatch for d={} s={}.", self.domain, self.selector);
+            return Ok(false);
+        }
+
+        let data = msg.header_hash_input(
+            &self.headers,
+            &self.field.0,
+            &strip_signature(&self.field.1),
+            self.header_canon,
+        );
+        match self.algorithm {
+            DkimAlgorithm::Ed25519Sha256 => {
+                let scheme = res!(SignatureScheme::empty_ed25519().set_public_key(Some(&key.key)));
+                let hash = digest::digest(&digest::SHA256, &data);
+                scheme.verify(hash.as_ref(), &self.signature)
+            }
+            DkimAlgorithm::RsaSha256 => {
+                let pk = UnparsedPublicKey::new(
+                    &signature::RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY,
+                    &key.key,
+                );
+                Ok(pk.verify(&data, &self.signature).is_ok())
+            }
+        }
+    }
+}
+
+/// A public key record (RFC 6376 Section 3.6.1), e.g.
+/// `v=DKIM1; k=ed25519; p=11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo=`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DkimPublicKey {
+    pub algorithm:  DkimAlgorithm,
+    /// The raw Ed25519 key, or the PKCS#1 DER encoded RSA public key.
+    pub key:        Vec<u8>,
+    /// The permitted hash algorithms, where empty permits all.
+    pub hashes:     Vec<String>,
+    /// The "t=y" flag, indicating that the domain is testing DKIM.
+    pub testing:    bool,
+    /// The "t=s" flag, forbidding subdomains in the identity.
+    pub strict:     bool,
+}
+
+impl FromStr for DkimPublicKey {
+    type Err = Error<ErrTag>;
+
+    fn from_str(record: &str) -> std::result::Result<Self, Self::Err> {
+        let tags = res!(tag_list(record));
+        let get = |tag: &str| tags.iter().find(|(t, _)| t == tag).map(|(_, v)| v.as_str());
+        if let Some(v) = get("v") {
+            if v != "DKIM1" {
+                return Err(err!(
+                    "Unsupported DKIM key record version '{}'.", v;
+                Unimplemented, Input));
+            }
+        }
+        let algorithm = match get("k").unwrap_or("rsa") {
+            "rsa"       => DkimAlgorithm::RsaSha256,
+            "ed25519"   => DkimAlgorithm::Ed25519Sha256,
+            k => return Err(err!(
+                "Unsupported DKIM key type '{}'.", k;
+            Unimplemented, Input)),
+        };
+        let p: String = match get("p") {
+            Some(p) => p.chars().filter(|c| !c.is_whitespace()).collect(),
+            None => return Err(err!("The DKIM key record has no 'p=' tag."; Input, Missing)),
+        };
+        if p.is_empty() {
+            return Err(err!("The DKIM key has been revoked."; Input, Security, Missing));
+        }
+        let der = res!(base64::decode(&p), Decode, Input);
+        let key = match algorithm {
+            DkimAlgorithm::RsaSha256 => match rsa_from_spki(&der) {
+                Some(pkcs1) => pkcs1.to_vec(),
+                None => der,
+            },
+            DkimAlgorithm::Ed25519Sha256 => der,
+        };
+        let flags: Vec<&str> = get("t").map(|t| t.split(':').map(str::trim).collect()).unwrap_or_default();
+        Ok(Self {
+            algorithm,
+            key,
+            hashes: get("h")
+                .map(|h| h.split(':').map(|h| h.trim().to_lowercase()).collect())
+                .unwrap_or_default(),
+            testing: flags.contains(&"y"),
+            strict: flags.contains(&"s"),
+        })
+    }
+}
+
+/// The result of verifying one DKIM-Signature of a message.
+#[derive(Clone, Debug)]
+pub struct DkimVerification {
+    /// The signature, unless it could not be parsed.
+    pub signature:  Option<DkimSignature>,
+    pub result:     Outcome<bool>,
+}
+
+impl DkimVerification {
+    pub fn is_pass(&self) -> bool {
+        matches!(self.result, Ok(true))
+    }
+}
+
+/// The header fields and body of a message, as needed for DKIM.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DkimMessage {
+    /// Header names, and values as they follow the colon including any folding.
+    pub fields: Vec<(String, String)>,
+    pub body:   Vec<u8>,
+}
+
+impl From<&EmailMessage> for DkimMessage {
+    /// Use the headers and body as written by `EmailMessage`.
+    fn from(email: &EmailMessage) -> Self {
+        let mut fields = vec![(fmt!("From"), fmt!(" {}", email.from))];
+        for to in &email.to {
+            fields.push((fmt!("To"), fmt!(" {}", to)));
+        }
+        fields.push((fmt!("Subject"), fmt!(" {}", email.subject)));
+        for header in &email.headers {
+            fields.push((header.name().to_string(), fmt!(" {}", header.value())));
+        }
+        Self {
+            fields,
+            body: mime::crlf(email.body.as_bytes()),
+        }
+    }
+}
+
+impl DkimMessage {
+
+    /// Split a message with CRLF line endings into its header fields and body.
+    pub fn from_bytes(raw: &[u8]) -> Self {
+        let mut fields: Vec<(String, String)> = Vec::new();
+        let mut pos = 0;
+        while pos < raw.len() {
+            let end = match raw[pos..].windows(2).position(|w| w == b"\r\n") {
+                Some(i) => pos + i,
+                None => raw.len(),
+            };
+            let line = String::from_utf8_lossy(&raw[pos..end]).to_string();
+            pos = (end + 2).min(raw.len());
+            if line.is_empty() {
+                break;
+            }
+            match (line.starts_with([' ', '\t']), fields.last_mut(), line.split_once(':')) {
+                (true, Some((_, value)), _) => {
+                    value.push_str("\r\n");
+                    value.push_str(&line);
+                }
+                (false, _, Some((name, value))) => fields.push((name.to_string(), value.to_string())),
+                _ => debug!("Ignoring invalid header line '{}'.", line),
+            }
+        }
+        Self {
+            fields,
+            body: raw[pos..].to_vec(),
+        }
+    }
+
+    /// Parse each DKIM-Signature header, from the top.
+    pub fn signatures(&self) -> Vec<Outcome<DkimSignature>> {
+        self.fields.iter()
+            .filter(|(name, _)| name.trim().eq_ignore_ascii_case("dkim-signature"))
+            .map(|(name, value)| DkimSignature::parse(name, value))
+            .collect()
+    }
+
+    /// Verify each DKIM-Signature, using the public key record that the given function returns
+    /// for a domain and selector, i.e. the TXT record at `<selector>._domainkey.<domain>`.
+    pub fn verify<F: Fn(&str, &str) -> Option<String>>(
+        &self,
+        record_for: F,
+    )
+        -> Vec<DkimVerification>
+    {
+        self.signatures().into_iter().map(|sig| match sig {
+            Ok(sig) => {
+                let result = match record_for(&sig.domain, &sig.selector) {
+                    Some(record) => match DkimPublicKey::from_str(&record) {
+                        Ok(key) => sig.verify(self, &key),
+                        Err(e) => Err(e),
+                    },
+                    None => Err(err!(
+                        "No DKIM key record for selector '{}' of domain '{}'.",
+                        sig.selector, sig.domain;
+                    Missing, Configuration)),
+                };
+                DkimVerification { signature: Some(sig), result }
+            }
+            Err(e) => DkimVerification { signature: None, result: Err(e) },
+        }).collect()
+    }
+
+    /// The data to be signed, being the signed header fields followed by the DKIM-Signature
+    /// field with an empty "b=" value and without a trailing CRLF (RFC 6376 Section 3.7).
+    fn header_hash_input(
+        &self,
+        headers:    &[String],
+        sig_name:   &str,
+        sig_value:  &str,
+        canon:      Canonicalisation,
+    )
+        -> Vec<u8>
+    {
+        let mut data = Vec::new();
+        // Repeated names select instances from the bottom up (RFC 6376 Section 5.4.2).
+        let mut used: HashMap<&str, usize> = HashMap::new();
+        for name in headers {
+            let instances: Vec<&(String, String)> = self.fields.iter()
+                .filter(|(n, _)| n.trim().eq_ignore_ascii_case(name))
+                .collect();
+            let count = used.entry(name.as_str()).or_insert(0);
+            if *count < instances.len() {
+                let (n, v) = instances[instances.len() - 1 - *count];
+                data.extend_from_slice(&canon.header(n, v));
+                *count += 1;
+            }
+        }
+        let mut sig = canon.header(sig_name, sig_value);
+        sig.truncate(sig.len() - 2);
+        data.extend_from_slice(&sig);
+        data
+    }
+}
+
+/// Split a tag list such as `v=1; a=rsa-sha256` (RFC 6376 Section 3.2).
+fn tag_list(s: &str) -> Outcome<Vec<(String, String)>> {
+    let mut tags: Vec<(String, String)> = Vec::new();
+    for spec in s.split(';') {
+        if spec.trim().is_empty() {
+            continue;
+        }
+        match spec.split_once('=') {
+            Some((tag, value)) => {
+                let tag = tag.trim().to_string();
+                if tags.iter().any(|(t, _)| *t == tag) {
+                    return Err(err!(
+                        "Duplicate '{}=' tag in '{}'.", tag, s;
+                    Input, Invalid));
+                }
+                tags.push((tag, value.trim().to_string()));
+            }
+            None => return Err(err!(
+                "Invalid tag '{}' in '{}'.", spec.trim(), s;
+            Input, Invalid)),
+        }
+    }
+    Ok(tags)
+}
+
+/// Empty the "b=" tag value of a DKIM-Signature, keeping everything else as it is.
+fn strip_signature(value: &str) -> String {
+    value.split(';')
+        .map(|spec| match spec.split_once('=') {
+            Some((tag, _)) if tag.trim() == "b" => fmt!("{}=", tag),
+            _ => spec.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// DER encoding of the rsaEncryption algorithm identifier, 1.2.840.113549.1.1.1 with NULL
+// parameters.
+const RSA_ALGORITHM_ID: [u8; 15] = [
+    0x30, 0x0d, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01, 0x05, 0x00,
+];
+
+/// Read a DER item, returning its tag, content and the bytes that follow.
+fn der_item(byts: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let tag = *byts.first()?;
+    let first = *byts.get(1)? as usize;
+    let (len, start) = match first {
+        0..=0x7f => (first, 2),
+        0x81..=0x84 => {
+            let n = first - 0x80;
+            let mut len = 0usize;
+            for b in byts.get(2..2 + n)? {
+                len = (len << 8) | *b as usize;
+            }
+            (len, 2 + n)
+        }
+        _ => return None,
+    };
+    let content = byts.get(start..start.checked_add(len)?)?;
+    Some((tag, content, &byts[start + len..]))
+}
+
+fn der_wrap(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = content.len();
+    match len {
+        0..=0x7f => out.push(len as u8),
+        0x80..=0xff => out.extend_from_slice(&[0x81, len as u8]),
+        _ => out.extend_from_slice(&[0x82, (len >> 8) as u8, len as u8]),
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+/// Extract the PKCS#1 RSA public key from a SubjectPublicKeyInfo.
+fn rsa_from_spki(der: &[u8]) -> Option<&[u8]> {
+    let (0x30, spki, _) = der_item(der)? else { return None };
+    let (0x30, _, rest) = der_item(spki)? else { return None };
+    if !spki.starts_with(&RSA_ALGORITHM_ID) {
+        return None;
+    }
+    let (0x03, bits, _) = der_item(rest)? else { return None };
+    match bits.split_first()? {
+        (0, pkcs1) => Some(pkcs1),
+        _ => None,
+    }
+}
+
+/// Wrap a PKCS#1 RSA public key in a SubjectPublicKeyInfo, as DKIM key records conventionally
+/// use.
+fn rsa_spki(pkcs1: &[u8]) -> Vec<u8> {
+    let mut bits = vec![0];
+    bits.extend_from_slice(pkcs1);
+    let mut content = RSA_ALGORITHM_ID.to_vec();
+    content.extend_from_slice(&der_wrap(0x03, &bits));
+    der_wrap(0x30, &content)
+}
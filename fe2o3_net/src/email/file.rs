@@ -55,12 +55,19 @@ impl MboxEmailIterator {
                     debug!("{:05} line={}", line_num, String::from_utf8_lossy(&line));
                     line_num += 1;
                     if line.starts_with(b"From ") {
+                        // The separator is not part of the message.
                         if !email_content.is_empty() {
                             break;
                         }
+                        continue;
+                    }
+                    // Present the message as SMTP data, which is what `EmailMessage::read`
+                    // expects, dot stuffed with CRLF line endings.
+                    if line.starts_with(b".") {
+                        email_content.push(b'.');
                     }
                     email_content.extend_from_slice(&line);
-                    email_content.extend_from_slice(&[b'\n']);
+                    email_content.extend_from_slice(b"\r\n");
                 }
                 Ok(None) => {
                     if email_content.is_empty() {
@@ -72,6 +79,7 @@ impl MboxEmailIterator {
                 Err(e) => return Err(e),
             }
         }
+        email_content.extend_from_slice(b".\r\n");
         Ok(Some(email_content))
     }
 }
@@ -0,0 +1,1056 @@
+//! Multipurpose Internet Mail Extensions.
+//!
+//! - RFC 2045 Format of Internet Message Bodies, including the quoted-printable and base64
+//!   transfer encodings.
+//! - RFC 2046 Media Types, including the multipart syntax.
+//! - RFC 2047 Message Header Extensions for Non-ASCII Text, i.e. encoded words such as
+//!   `=?utf-8?Q?caf=C3=A9?=`.
+//! - RFC 2183 The Content-Disposition Header Field.
+//! - RFC 2231 Parameter Value and Encoded Word Extensions, i.e. parameters such as
+//!   `filename*=utf-8''caf%C3%A9.txt`.
+//!
+//! A received message is parsed into a tree of `MimePart`s, with transfer encodings removed,
+//! while an `EmailBuilder` composes a message for sending.
+use crate::{
+    constant,
+    email::msg::{
+        ContentDisposition,
+        EmailHeader,
+    },
+    media::{
+        ContentTypeValue,
+        Multipart,
+    },
+};
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    rand::Rand,
+};
+
+use std::{
+    fmt,
+};
+
+
+/// The Content-Transfer-Encoding of a part (RFC 2045 Section 6).
+#[derive(Clone, Copy, Debug, Default, Eq, Ord, PartialEq, PartialOrd)]
+pub enum TransferEncoding {
+    #[default]
+    SevenBit,
+    EightBit,
+    Binary,
+    QuotedPrintable,
+    Base64,
+}
+
+impl fmt::Display for TransferEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            Self::SevenBit          => "7bit",
+            Self::EightBit          => "8bit",
+            Self::Binary            => "binary",
+            Self::QuotedPrintable   => "quoted-printable",
+            Self::Base64            => "base64",
+        })
+    }
+}
+
+impl FromStr for TransferEncoding {
+    type Err = Error<ErrTag>;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s.trim().to_lowercase().as_str() {
+            "7bit"              => Self::SevenBit,
+            "8bit"              => Self::EightBit,
+            "binary"            => Self::Binary,
+            "quoted-printable"  => Self::QuotedPrintable,
+            "base64"            => Self::Base64,
+            _ => return Err(err!(
+                "Unrecognised Content-Transfer-Encoding '{}'.", s;
+            Unknown, Input)),
+        })
+    }
+}
+
+impl TransferEncoding {
+
+    pub fn decode(&self, byts: &[u8]) -> Outcome<Vec<u8>> {
+        match self {
+            Self::QuotedPrintable   => Ok(qp_decode(byts)),
+            Self::Base64            => base64_decode(byts),
+            _                       => Ok(byts.to_vec()),
+        }
+    }
+
+    /// Encode the given data.  Quoted-printable encoding treats the data as text, so that
+    /// line breaks become CRLF.
+    pub fn encode(&self, byts: &[u8]) -> Vec<u8> {
+        match self {
+            Self::QuotedPrintable   => qp_encode(byts),
+            Self::Base64            => base64_encode(byts),
+            _                       => byts.to_vec(),
+        }
+    }
+}
+
+/// The body of a `MimePart`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum MimeBody {
+    /// Content with the transfer encoding removed.
+    Data(Vec<u8>),
+    /// The parts of a multipart body, without the preamble and epilogue.
+    Parts(Vec<MimePart>),
+    /// An encapsulated message, e.g. a forwarded email.
+    Message(Box<MimePart>),
+}
+
+/// A MIME entity, being either a whole message or one of the parts of a multipart body.  The
+/// media type, parameters, encoding and disposition are taken from the headers when parsing,
+/// while the headers alone are written by `to_bytes`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MimePart {
+    pub headers:        Vec<EmailHeader>,
+    /// The lower case media type and subtype, e.g. "text/plain".
+    pub media_type:     String,
+    /// The Content-Type parameters, with lower case names.
+    pub params:         Vec<(String, String)>,
+    pub encoding:       TransferEncoding,
+    pub disposition:    Option<ContentDisposition>,
+    pub body:           MimeBody,
+}
+
+impl MimePart {
+
+    /// Parse a message, or part, consisting of headers, an empty line and a body.
+    pub fn parse(raw: &[u8]) -> Outcome<Self> {
+        let mut count = 0;
+        Self::parse_part(raw, "text/plain", 0, &mut count)
+    }
+
+    fn parse_part(
+        raw:            &[u8],
+        default_type:   &str,
+        depth:          usize,
+        count:          &mut usize,
+    )
+        -> Outcome<Self>
+    {
+        if depth > constant::EMAIL_MAX_MIME_DEPTH {
+            return Err(err!(
+                "MIME parts are nested more than {} deep.", constant::EMAIL_MAX_MIME_DEPTH;
+            Input, Excessive));
+        }
+        *count += 1;
+        if *count > constant::EMAIL_MAX_MIME_PARTS {
+            return Err(err!(
+                "The message contains more than {} MIME parts.", constant::EMAIL_MAX_MIME_PARTS;
+            Input, Excessive));
+        }
+
+        let (head, body) = split_head(raw);
+        let headers = parse_headers(head);
+
+        let mut media_type = default_type.to_string();
+        let mut params = Vec::new();
+        let mut encoding = TransferEncoding::SevenBit;
+        let mut disposition = None;
+        for header in &headers {
+            match header.name().to_lowercase().as_str() {
+                "content-type" => {
+                    let (mt, ps) = split_params(&header.value());
+                    if mt.contains('/') {
+                        media_type = mt.to_lowercase();
+                        params = ps;
+                    }
+                }
+                "content-transfer-encoding" => {
+                    // An unrecognised encoding leaves the content as it is (RFC 2045 Section
+                    // 6.4).
+                    encoding = TransferEncoding::from_str(&header.value())
+                        .unwrap_or(TransferEncoding::Binary);
+                }
+                "content-disposition" => {
+                    disposition = ContentDisposition::from_str(&header.value()).ok();
+                }
+                _ => (),
+            }
+        }
+
+        let boundary = match media_type.starts_with("multipart/") {
+            true => param(&params, "boundary").map(str::to_string),
+            false => None,
+        };
+        let body = match boundary {
+            Some(boundary) => {
+                // Parts of a digest are messages by default (RFC 2046 Section 5.1.5).
+                let child_type = match media_type.as_str() {
+                    "multipart/digest" => "message/rfc822",
+                    _ => "text/plain",
+                };
+                let mut parts = Vec::new();
+                for part in res!(split_multipart(body, &boundary)) {
+                    parts.push(res!(Self::parse_part(part, child_type, depth + 1, count)));
+                }
+                MimeBody::Parts(parts)
+            }
+            None if media_type == "message/rfc822" && encoding != TransferEncoding::Base64 => {
+                let inner = res!(encoding.decode(body));
+                MimeBody::Message(Box::new(res!(
+                    Self::parse_part(&inner, "text/plain", depth + 1, count)
+                )))
+            }
+            None => MimeBody::Data(res!(encoding.decode(body))),
+        };
+
+        Ok(Self {
+            headers,
+            media_type,
+            params,
+            encoding,
+            disposition,
+            body,
+        })
+    }
+
+    /// Create a part containing the given data, which will be written using the given
+    /// encoding.
+    pub fn data(
+        media_type:     &str,
+        params:         Vec<(String, String)>,
+        encoding:       TransferEncoding,
+        disposition:    Option<ContentDisposition>,
+        data:           Vec<u8>,
+    )
+        -> Outcome<Self>
+    {
+        let mut value = media_type.to_lowercase();
+        for (name, val) in &params {
+            value.push_str(&fmt!("; {}={}", name, quote_param(val)));
+        }
+        let mut headers = vec![
+            res!(EmailHeader::from_str(&fmt!("Content-Type: {}", value))),
+            EmailHeader::ContentTransferEncoding(encoding.to_string()),
+        ];
+        if let Some(d) = &disposition {
+            headers.push(EmailHeader::ContentDisposition(d.clone()));
+        }
+        Ok(Self {
+            headers,
+            media_type: media_type.to_lowercase(),
+            params,
+            encoding,
+            disposition,
+            body: MimeBody::Data(data),
+        })
+    }
+
+    /// Create a text part with a "utf-8" charset, choosing the encoding to suit the text.
+    pub fn text(subtype: &str, text: &str) -> Outcome<Self> {
+        let text = crlf(text.as_bytes());
+        let encoding = match text.is_ascii()
+            && text.split(|b| *b == b'\n').all(|line| line.len() <= 998)
+        {
+            true => TransferEncoding::SevenBit,
+            false => TransferEncoding::QuotedPrintable,
+        };
+        Self::data(
+            &fmt!("text/{}", subtype),
+            vec![(fmt!("charset"), fmt!("utf-8"))],
+            encoding,
+            None,
+            text,
+        )
+    }
+
+    /// Create a base64 encoded attachment.
+    pub fn attachment(filename: &str, media_type: &str, data: Vec<u8>) -> Outcome<Self> {
+        Self::data(
+            media_type,
+            Vec::new(),
+            TransferEncoding::Base64,
+            Some(ContentDisposition::Attachment(Some(filename.to_string()))),
+            data,
+        )
+    }
+
+    /// Create a multipart part with a random boundary.
+    pub fn multipart(subtype: Multipart, parts: Vec<MimePart>) -> Self {
+        // The "=_" cannot appear in quoted-printable or base64 content.
+        let boundary = fmt!("=_fe2o3_{}", Rand::generate_random_string(24, "0123456789abcdef"));
+        let media_type = fmt!("multipart/{}", subtype);
+        Self {
+            headers: vec![
+                EmailHeader::ContentType(ContentTypeValue::Multipart((subtype, boundary.clone()))),
+            ],
+            media_type,
+            params: vec![(fmt!("boundary"), boundary)],
+            encoding: TransferEncoding::SevenBit,
+            disposition: None,
+            body: MimeBody::Parts(parts),
+        }
+    }
+
+    /// Write the headers and body, applying the transfer encoding.
+    pub fn to_bytes(&self) -> Outcome<Vec<u8>> {
+        let mut byts = Vec::new();
+        for header in &self.headers {
+            byts.extend_from_slice(fmt!("{}\r\n", header).as_bytes());
+        }
+        byts.extend_from_slice(b"\r\n");
+        match &self.body {
+            MimeBody::Data(data) => byts.extend_from_slice(&self.encoding.encode(data)),
+            MimeBody::Parts(parts) => {
+                let boundary = match self.param("boundary") {
+                    Some(boundary) => boundary,
+                    None => return Err(err!(
+                        "A {} part has no boundary parameter.", self.media_type;
+                    Missing, Encode)),
+                };
+                for part in parts {
+                    byts.extend_from_slice(fmt!("--{}\r\n", boundary).as_bytes());
+                    byts.extend_from_slice(&res!(part.to_bytes()));
+                    byts.extend_from_slice(b"\r\n");
+                }
+                byts.extend_from_slice(fmt!("--{}--\r\n", boundary).as_bytes());
+            }
+            MimeBody::Message(msg) => byts.extend_from_slice(&res!(msg.to_bytes())),
+        }
+        Ok(byts)
+    }
+
+    /// The value of the first header with the given name, with any encoded words decoded.
+    pub fn header(&self, name: &str) -> Option<String> {
+        self.headers.iter()
+            .find(|h| h.name().eq_ignore_ascii_case(name))
+            .map(|h| decode_words(&h.value()))
+    }
+
+    /// The value of a Content-Type parameter.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        param(&self.params, name)
+    }
+
+    pub fn is_multipart(&self) -> bool {
+        matches!(self.body, MimeBody::Parts(_))
+    }
+
+    pub fn is_attachment(&self) -> bool {
+        matches!(self.disposition, Some(ContentDisposition::Attachment(_)))
+    }
+
+    /// The filename given by the Content-Disposition, or failing that the older Content-Type
+    /// "name" parameter.
+    pub fn filename(&self) -> Option<String> {
+        match &self.disposition {
+            Some(ContentDisposition::Attachment(Some(filename))) => Some(filename.clone()),
+            _ => self.param("name").map(decode_words),
+        }
+    }
+
+    /// The child parts of a multipart body, otherwise an empty slice.
+    pub fn parts(&self) -> &[MimePart] {
+        match &self.body {
+            MimeBody::Parts(parts) => parts,
+            _ => &[],
+        }
+    }
+
+    /// The decoded content of a non-multipart body.
+    pub fn content(&self) -> Option<&[u8]> {
+        match &self.body {
+            MimeBody::Data(data) => Some(data),
+            _ => None,
+        }
+    }
+
+    /// The content of a "text/*" part converted from its charset, with "\n" line endings.
+    pub fn text_content(&self) -> Option<String> {
+        match (self.media_type.starts_with("text/"), &self.body) {
+            (true, MimeBody::Data(data)) => {
+                let text = decode_charset(data, self.param("charset").unwrap_or("us-ascii"));
+                Some(text.replace("\r\n", "\n"))
+            }
+            _ => None,
+        }
+    }
+
+    /// This part and all its descendants, depth first.
+    pub fn walk(&self) -> Vec<&MimePart> {
+        let mut list = vec![self];
+        match &self.body {
+            MimeBody::Parts(parts) => for part in parts {
+                list.append(&mut part.walk());
+            },
+            MimeBody::Message(msg) => list.append(&mut msg.walk()),
+            MimeBody::Data(_) => (),
+        }
+        list
+    }
+
+    /// The first part with the given media type that is not an attachment, e.g. "text/html".
+    /// Encapsulated messages are not searched.
+    pub fn find(&self, media_type: &str) -> Option<&MimePart> {
+        if self.media_type.eq_ignore_ascii_case(media_type) && !self.is_attachment() {
+            return Some(self);
+        }
+        self.parts().iter().find_map(|part| part.find(media_type))
+    }
+
+    pub fn attachments(&self) -> Vec<&MimePart> {
+        self.walk().into_iter().filter(|part| part.is_attachment()).collect()
+    }
+}
+
+/// A file to be attached by an `EmailBuilder`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct EmailAttachment {
+    pub filename:   String,
+    pub media_type: String,
+    pub data:       Vec<u8>,
+}
+
+/// Composes a message ready for sending, e.g. with `SmtpClient::send`.
+/// ```ignore
+/// let msg = res!(EmailBuilder::new("Alice <alice@example.com>")
+///     .with_to("bob@example.org")
+///     .with_subject("Report")
+///     .with_text("See attached.")
+///     .with_html("<p>See attached.</p>")
+///     .with_attachment("report.pdf", "application/pdf", pdf)
+///     .to_bytes());
+/// ```
+/// Text and HTML bodies are combined as multipart/alternative, which becomes the first part
+/// of a multipart/mixed message when there are attachments.
+#[derive(Clone, Debug, Default)]
+pub struct EmailBuilder {
+    pub from:           String,
+    pub to:             Vec<String>,
+    pub cc:             Vec<String>,
+    pub subject:        String,
+    pub text:           Option<String>,
+    pub html:           Option<String>,
+    pub attachments:    Vec<EmailAttachment>,
+    pub headers:        Vec<EmailHeader>,
+}
+
+impl EmailBuilder {
+
+    pub fn new<S: Into<String>>(from: S) -> Self {
+        Self {
+            from: from.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_to<S: Into<String>>(mut self, to: S) -> Self {
+        self.to.push(to.into());
+        self
+    }
+
+    pub fn with_cc<S: Into<String>>(mut self, cc: S) -> Self {
+        self.cc.push(cc.into());
+        self
+    }
+
+    pub fn with_subject<S: Into<String>>(mut self, subject: S) -> Self {
+        self.subject = subject.into();
+        self
+    }
+
+    pub fn with_text<S: Into<String>>(mut self, text: S) -> Self {
+        self.text = Some(text.into());
+        self
+    }
+
+    pub fn with_html<S: Into<String>>(mut self, html: S) -> Self {
+        self.html = Some(html.into());
+        self
+    }
+
+    pub fn with_attachment<S: Into<String>>(
+        mut self,
+        filename:   S,
+        media_type: S,
+        data:       Vec<u8>,
+    )
+        -> Self
+    {
+        self.attachments.push(EmailAttachment {
+            filename:   filename.into(),
+            media_type: media_type.into(),
+            data,
+        });
+        self
+    }
+
+    /// Add a header, such as `EmailHeader::ReplyTo`, to the message.
+    pub fn with_header(mut self, header: EmailHeader) -> Self {
+        self.headers.push(header);
+        self
+    }
+
+    pub fn build(&self) -> Outcome<MimePart> {
+        if self.from.is_empty() {
+            return Err(err!("An email requires a From address."; Input, Missing));
+        }
+        if self.to.is_empty() && self.cc.is_empty() {
+            return Err(err!("An email requires at least one recipient."; Input, Missing));
+        }
+
+        let text = match &self.text {
+            Some(text) => Some(res!(MimePart::text("plain", text))),
+            None => None,
+        };
+        let html = match &self.html {
+            Some(html) => Some(res!(MimePart::text("html", html))),
+            None => None,
+        };
+        let body = match (text, html) {
+            (Some(text), Some(html)) => Some(MimePart::multipart(Multipart::Alternative, vec![text, html])),
+            (Some(part), None) | (None, Some(part)) => Some(part),
+            (None, None) => None,
+        };
+        let mut msg = match (body, self.attachments.is_empty()) {
+            (Some(body), true) => body,
+            (None, true) => res!(MimePart::text("plain", "")),
+            (body, false) => {
+                let mut parts: Vec<MimePart> = body.into_iter().collect();
+                for a in &self.attachments {
+                    parts.push(res!(MimePart::attachment(&a.filename, &a.media_type, a.data.clone())));
+                }
+                MimePart::multipart(Multipart::Mixed, parts)
+            }
+        };
+
+        let domain = self.from
+            .rsplit_once('@')
+            .map(|(_, domain)| domain.trim_end_matches('>').trim())
+            .filter(|domain| !domain.is_empty())
+            .unwrap_or("localhost");
+        let mut headers = vec![EmailHeader::From(res!(encode_address(&self.from)))];
+        if !self.to.is_empty() {
+            let to = res!(self.to.iter().map(|a| encode_address(a)).collect::<Outcome<Vec<_>>>());
+            headers.push(EmailHeader::To(to.join(", ")));
+        }
+        if !self.cc.is_empty() {
+            let cc = res!(self.cc.iter().map(|a| encode_address(a)).collect::<Outcome<Vec<_>>>());
+            headers.push(EmailHeader::Cc(cc.join(", ")));
+        }
+        headers.push(EmailHeader::Subject(encode_words(&self.subject)));
+        headers.push(EmailHeader::Date(chrono::Utc::now().to_rfc2822()));
+        headers.push(EmailHeader::MessageId(fmt!("<{}@{}>",
+            Rand::generate_random_string(24, "0123456789abcdefghijklmnopqrstuvwxyz"), domain)));
+        headers.push(EmailHeader::MimeVersion(fmt!("1.0")));
+        headers.extend(self.headers.iter().cloned());
+        headers.append(&mut msg.headers);
+        msg.headers = headers;
+        Ok(msg)
+    }
+
+    /// The message in wire format, with CRLF line endings but without SMTP dot stuffing.
+    pub fn to_bytes(&self) -> Outcome<Vec<u8>> {
+        let msg = res!(self.build());
+        msg.to_bytes()
+    }
+}
+
+/// Decode RFC 2047 encoded words, e.g. `=?iso-8859-1?q?caf=E9?=`, dropping the whitespace
+/// between adjacent encoded words.  Malformed encoded words are left as they are.
+pub fn decode_words(s: &str) -> String {
+    let mut out = String::new();
+    let mut rest = s;
+    let mut after_word = false;
+    while let Some(i) = rest.find("=?") {
+        let (before, from) = rest.split_at(i);
+        match decode_word(from) {
+            Some((decoded, len)) => {
+                if !(after_word && before.chars().all(char::is_whitespace)) {
+                    out.push_str(before);
+                }
+                out.push_str(&decoded);
+                rest = &from[len..];
+                after_word = true;
+            }
+            None => {
+                out.push_str(before);
+                out.push_str("=?");
+                rest = &from[2..];
+                after_word = false;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Decode the encoded word at the start of the string, returning the text and the length of
+/// the encoded word.
+fn decode_word(s: &str) -> Option<(String, usize)> {
+    let body = s.strip_prefix("=?")?;
+    let q1 = body.find('?')?;
+    let charset = &body[..q1];
+    let rest = &body[q1 + 1..];
+    let enc = rest.get(..1)?;
+    if rest.get(1..2)? != "?" {
+        return None;
+    }
+    let end = rest[2..].find("?=")?;
+    let text = &rest[2..2 + end];
+    if charset.is_empty() || text.contains(char::is_whitespace) {
+        return None;
+    }
+    let byts = match enc {
+        "B" | "b" => base64_decode(text.as_bytes()).ok()?,
+        "Q" | "q" => {
+            let mut byts = Vec::new();
+            let raw = text.as_bytes();
+            let mut i = 0;
+            while i < raw.len() {
+                match raw[i] {
+                    b'_' => byts.push(b' '),
+                    b'=' => match raw.get(i + 1..i + 3).and_then(hex_byte) {
+                        Some(b) => {
+                            byts.push(b);
+                            i += 2;
+                        }
+                        None => byts.push(b'='),
+                    },
+                    b => byts.push(b),
+                }
+                i += 1;
+            }
+            byts
+        }
+        _ => return None,
+    };
+    // RFC 2231 Section 5 allows a language suffix, e.g. "utf-8*en".
+    let charset = charset.split('*').next().unwrap_or(charset);
+    Some((decode_charset(&byts, charset), 2 + q1 + 1 + 2 + end + 2))
+}
+
+/// Encode text for an unstructured header such as Subject as RFC 2047 encoded words, when it
+/// is not plain ASCII.
+pub fn encode_words(s: &str) -> String {
+    if s.chars().all(|c| c.is_ascii() && !c.is_ascii_control()) && !s.contains("=?") {
+        return s.to_string();
+    }
+    // Keep each encoded word within 75 characters (RFC 2047 Section 2).
+    let mut words = Vec::new();
+    let mut chunk = String::new();
+    for c in s.chars() {
+        if chunk.len() + c.len_utf8() > 45 {
+            words.push(fmt!("=?utf-8?B?{}?=", base64::encode(&chunk)));
+            chunk.clear();
+        }
+        chunk.push(c);
+    }
+    if !chunk.is_empty() {
+        words.push(fmt!("=?utf-8?B?{}?=", base64::encode(&chunk)));
+    }
+    words.join("\r\n ")
+}
+
+/// Encode the display name of an address such as "Zoë <zoe@example.com>", leaving the
+/// address itself alone.  Control characters are refused, so that an address cannot end the
+/// header and inject another.
+fn encode_address(s: &str) -> Outcome<String> {
+    if let Some(c) = s.chars().find(|c| c.is_control()) {
+        return Err(err!(
+            "The address '{}' contains the control character {:?}.", s.escape_debug(), c;
+        Input, Invalid));
+    }
+    Ok(match s.rfind('<') {
+        Some(i) if i > 0 => {
+            let name = s[..i].trim().trim_matches('"');
+            let encoded = encode_words(name);
+            match encoded == name {
+                true => s.to_string(),
+                false => fmt!("{} {}", encoded, &s[i..]),
+            }
+        }
+        _ => s.to_string(),
+    })
+}
+
+/// Convert text in the given charset to a `String`.  Charsets other than ASCII, UTF-8,
+/// ISO-8859-1 and Windows-1252 are treated as UTF-8, with invalid sequences replaced.
+pub fn decode_charset(byts: &[u8], charset: &str) -> String {
+    match charset.trim().to_lowercase().as_str() {
+        "iso-8859-1" | "iso_8859-1" | "iso_8859-1:1987" | "latin1" | "l1" =>
+            byts.iter().map(|b| *b as char).collect(),
+        "windows-1252" | "cp1252" => byts.iter().map(|b| windows_1252(*b)).collect(),
+        _ => String::from_utf8_lossy(byts).into_owned(),
+    }
+}
+
+fn windows_1252(b: u8) -> char {
+    const HIGH: [u32; 32] = [
+        0x20ac, 0x81,   0x201a, 0x0192, 0x201e, 0x2026, 0x2020, 0x2021,
+        0x02c6, 0x2030, 0x0160, 0x2039, 0x0152, 0x8d,   0x017d, 0x8f,
+        0x90,   0x2018, 0x2019, 0x201c, 0x201d, 0x2022, 0x2013, 0x2014,
+        0x02dc, 0x2122, 0x0161, 0x203a, 0x0153, 0x9d,   0x017e, 0x0178,
+    ];
+    match b {
+        0x80..=0x9f => char::from_u32(HIGH[(b - 0x80) as usize]).unwrap_or(b as char),
+        _ => b as char,
+    }
+}
+
+/// Split a structured header value such as `text/plain; charset="utf-8"` into the leading
+/// value and its parameters, with lower case parameter names and quoting removed.  RFC 2231
+/// extended and continued parameters are decoded.
+pub fn split_params(value: &str) -> (String, Vec<(String, String)>) {
+    let mut segments = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    for c in value.chars() {
+        match c {
+            _ if escaped => {
+                current.push(c);
+                escaped = false;
+            }
+            '\\' if quoted => {
+                current.push(c);
+                escaped = true;
+            }
+            '"' => {
+                current.push(c);
+                quoted = !quoted;
+            }
+            ';' if !quoted => segments.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    segments.push(current);
+
+    let first = segments[0].trim().to_string();
+    let mut params = Vec::new();
+    // (name, section, extended, value)
+    let mut continued: Vec<(String, usize, bool, String)> = Vec::new();
+    for segment in &segments[1..] {
+        let (name, val) = match segment.split_once('=') {
+            Some((name, val)) => (name.trim().to_lowercase(), unquote(val.trim())),
+            None => continue,
+        };
+        let (name, extended) = match name.strip_suffix('*') {
+            Some(name) => (name.to_string(), true),
+            None => (name, false),
+        };
+        match name.split_once('*').map(|(base, n)| (base, n.parse::<usize>())) {
+            Some((base, Ok(n))) => continued.push((base.to_string(), n, extended, val)),
+            _ => match extended {
+                true => {
+                    let (charset, data) = split_extended(&val);
+                    params.push((name, decode_charset(&percent_decode(data), charset)));
+                }
+                false => params.push((name, val)),
+            },
+        }
+    }
+    continued.sort();
+    let mut i = 0;
+    while i < continued.len() {
+        let base = continued[i].0.clone();
+        let mut charset = "us-ascii";
+        let mut byts = Vec::new();
+        while i < continued.len() && continued[i].0 == base {
+            let (_, n, extended, val) = &continued[i];
+            match (*extended, *n) {
+                (true, 0) => {
+                    let (cs, data) = split_extended(val);
+                    charset = cs;
+                    byts.extend(percent_decode(data));
+                }
+                (true, _) => byts.extend(percent_decode(val)),
+                (false, _) => byts.extend_from_slice(val.as_bytes()),
+            }
+            i += 1;
+        }
+        params.push((base, decode_charset(&byts, charset)));
+    }
+    (first, params)
+}
+
+/// Look up a parameter by its lower case name.  The last match is used, so that an RFC 2231
+/// value takes precedence over a plain value given for older readers.
+pub fn param<'a>(params: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    params.iter().rev().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+}
+
+/// Quote a parameter value if it contains characters special to MIME (RFC 2045 Section 5.1).
+pub fn quote_param(value: &str) -> String {
+    match value.is_empty() || value.chars().any(|c| "()<>@,;:\\\"/[]?= ".contains(c)) {
+        true => fmt!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"")),
+        false => value.to_string(),
+    }
+}
+
+fn unquote(s: &str) -> String {
+    match s.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+        Some(inner) => {
+            let mut out = String::new();
+            let mut escaped = false;
+            for c in inner.chars() {
+                match c {
+                    '\\' if !escaped => escaped = true,
+                    _ => {
+                        out.push(c);
+                        escaped = false;
+                    }
+                }
+            }
+            out
+        }
+        None => s.to_string(),
+    }
+}
+
+/// Split an RFC 2231 extended value `charset'language'data` into the charset and data.
+fn split_extended(val: &str) -> (&str, &str) {
+    let mut parts = val.splitn(3, '\'');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(charset), Some(_), Some(data)) => (charset, data),
+        _ => ("us-ascii", val),
+    }
+}
+
+fn percent_decode(s: &str) -> Vec<u8> {
+    let raw = s.as_bytes();
+    let mut byts = Vec::with_capacity(raw.len());
+    let mut i = 0;
+    while i < raw.len() {
+        match (raw[i], raw.get(i + 1..i + 3).and_then(hex_byte)) {
+            (b'%', Some(b)) => {
+                byts.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                byts.push(b);
+                i += 1;
+            }
+        }
+    }
+    byts
+}
+
+/// Percent encode an RFC 2231 value, leaving the attribute characters alone.
+pub fn percent_encode(s: &str) -> String {
+    let mut out = String::new();
+    for b in s.bytes() {
+        match b.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&b) {
+            true => out.push(b as char),
+            false => out.push_str(&fmt!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+fn hex_byte(pair: &[u8]) -> Option<u8> {
+    if !pair.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    let s = std::str::from_utf8(pair).ok()?;
+    u8::from_str_radix(s, 16).ok()
+}
+
+/// Decode quoted-printable content, producing CRLF for hard line breaks.  Invalid escapes are
+/// kept literally, as RFC 2045 Section 6.7 suggests.
+pub fn qp_decode(byts: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(byts.len());
+    let mut lines = byts.split(|b| *b == b'\n').peekable();
+    while let Some(line) = lines.next() {
+        let hard_break = lines.peek().is_some();
+        // Trailing whitespace may have been added in transport.
+        let mut end = line.len();
+        while end > 0 && matches!(line[end - 1], b'\r' | b' ' | b'\t') {
+            end -= 1;
+        }
+        let line = &line[..end];
+        let (line, soft_break) = match line.last() {
+            Some(b'=') => (&line[..line.len() - 1], true),
+            _ => (line, false),
+        };
+        let mut i = 0;
+        while i < line.len() {
+            match (line[i], line.get(i + 1..i + 3).and_then(hex_byte)) {
+                (b'=', Some(b)) => {
+                    out.push(b);
+                    i += 3;
+                }
+                (b, _) => {
+                    out.push(b);
+                    i += 1;
+                }
+            }
+        }
+        if hard_break && !soft_break {
+            out.extend_from_slice(b"\r\n");
+        }
+    }
+    out
+}
+
+/// Quoted-printable encode text, keeping encoded lines within 76 characters.
+pub fn qp_encode(byts: &[u8]) -> Vec<u8> {
+    let max = constant::EMAIL_ENCODED_LINE_MAX - 1; // Leave room for a soft break "=".
+    let mut out = Vec::with_capacity(byts.len() + byts.len() / 8);
+    let text = crlf(byts);
+    let mut lines = text.split(|b| *b == b'\n').peekable();
+    while let Some(line) = lines.next() {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let mut len = 0;
+        for (i, b) in line.iter().enumerate() {
+            let at_end = i == line.len() - 1;
+            let literal = match b {
+                b' ' | b'\t' => !at_end,
+                b'=' => false,
+                33..=126 => true,
+                _ => false,
+            };
+            let token = match literal {
+                true => vec![*b],
+                false => fmt!("={:02X}", b).into_bytes(),
+            };
+            if len + token.len() > max {
+                out.extend_from_slice(b"=\r\n");
+                len = 0;
+            }
+            len += token.len();
+            out.extend_from_slice(&token);
+        }
+        if lines.peek().is_some() {
+            out.extend_from_slice(b"\r\n");
+        }
+    }
+    out
+}
+
+/// Decode base64 content, ignoring line breaks and tolerating missing padding.
+pub fn base64_decode(byts: &[u8]) -> Outcome<Vec<u8>> {
+    let mut clean: Vec<u8> = byts.iter()
+        .copied()
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect();
+    while !clean.len().is_multiple_of(4) {
+        clean.push(b'=');
+    }
+    Ok(res!(base64::decode(&clean), Decode, Input))
+}
+
+/// Base64 encode content in lines of 76 characters.
+pub fn base64_encode(byts: &[u8]) -> Vec<u8> {
+    let encoded = base64::encode(byts);
+    let mut out = Vec::with_capacity(encoded.len() + encoded.len() / 38);
+    for line in encoded.as_bytes().chunks(constant::EMAIL_ENCODED_LINE_MAX) {
+        out.extend_from_slice(line);
+        out.extend_from_slice(b"\r\n");
+    }
+    out
+}
+
+/// Convert bare LF line endings to CRLF.
+pub fn crlf(byts: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(byts.len());
+    for (i, b) in byts.iter().enumerate() {
+        if *b == b'\n' && (i == 0 || byts[i - 1] != b'\r') {
+            out.push(b'\r');
+        }
+        out.push(*b);
+    }
+    out
+}
+
+/// Split a part into its header block and body at the first empty line.
+fn split_head(raw: &[u8]) -> (&[u8], &[u8]) {
+    let mut pos = 0;
+    while pos < raw.len() {
+        let end = match raw[pos..].iter().position(|b| *b == b'\n') {
+            Some(i) => pos + i + 1,
+            None => raw.len(),
+        };
+        let line = &raw[pos..end];
+        if line == b"\r\n" || line == b"\n" {
+            return (&raw[..pos], &raw[end..]);
+        }
+        pos = end;
+    }
+    (raw, &[])
+}
+
+/// Unfold and parse a header block.  Lines that are not headers are ignored.
+fn parse_headers(head: &[u8]) -> Vec<EmailHeader> {
+    let head = String::from_utf8_lossy(head);
+    let mut lines: Vec<String> = Vec::new();
+    for line in head.split('\n') {
+        let line = line.trim_end_matches('\r');
+        match (line.starts_with([' ', '\t']), lines.last_mut()) {
+            (true, Some(last)) => {
+                last.push(' ');
+                last.push_str(line.trim_start());
+            }
+            _ => if !line.trim().is_empty() {
+                lines.push(line.to_string());
+            },
+        }
+    }
+    let mut headers = Vec::new();
+    for line in lines {
+        match EmailHeader::from_str(&line) {
+            Ok(header) => headers.push(header),
+            Err(_) => debug!("Ignoring invalid MIME header line '{}'.", line),
+        }
+    }
+    headers
+}
+
+/// Split a multipart body into its parts, without the CRLF that precedes each delimiter
+/// (RFC 2046 Section 5.1.1).  A missing closing delimiter is tolerated.
+fn split_multipart<'a>(body: &'a [u8], boundary: &str) -> Outcome<Vec<&'a [u8]>> {
+    let delimiter = fmt!("--{}", boundary);
+    let mut parts = Vec::new();
+    let mut start = None;
+    let mut closed = false;
+    let mut pos = 0;
+    while pos < body.len() {
+        let end = match body[pos..].iter().position(|b| *b == b'\n') {
+            Some(i) => pos + i + 1,
+            None => body.len(),
+        };
+        let mut line = &body[pos..end];
+        while let Some((last, rest)) = line.split_last() {
+            match last {
+                b'\r' | b'\n' | b' ' | b'\t' => line = rest,
+                _ => break,
+            }
+        }
+        if let Some(rest) = line.strip_prefix(delimiter.as_bytes()) {
+            let close = rest == b"--";
+            if rest.is_empty() || close {
+                if let Some(s) = start {
+                    let part = &body[s..pos];
+                    let part = part.strip_suffix(b"\n").unwrap_or(part);
+                    let part = part.strip_suffix(b"\r").unwrap_or(part);
+                    parts.push(part);
+                }
+                if close {
+                    closed = true;
+                    break;
+                }
+                start = Some(end);
+            }
+        }
+        pos = end;
+    }
+    match (start, closed) {
+        (None, _) => Err(err!(
+            "Multipart boundary '{}' not found.", boundary;
+        Input, Missing, Decode)),
+        (Some(s), false) => {
+            parts.push(&body[s..]);
+            Ok(parts)
+        }
+        (Some(_), true) => Ok(parts),
+    }
+}
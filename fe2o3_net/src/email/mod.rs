@@ -1,2 +1,4 @@
+pub mod dkim;
 pub mod file;
+pub mod mime;
 pub mod msg;
@@ -1,6 +1,10 @@
 use crate::{
     charset::Charset,
     constant,
+    email::mime::{
+        self,
+        MimePart,
+    },
     media::{
         ContentTypeValue,
         MediaType,
@@ -61,7 +65,7 @@ impl EmailMessage {
         let mut in_headers = true;
         let mut header_line = String::new();
     
-        let mut safety = ErrorWhen::new(constant::READ_LOOP_SAFETY_LIMIT);
+        let mut safety = ErrorWhen::new(constant::EMAIL_MAX_LINES);
         loop {
             res!(safety.inc());
             let mut line = Vec::new();
@@ -74,7 +78,8 @@ impl EmailMessage {
                 }
             }
             if byts_read == 2 {
-                if line[0] == b'\r' && line[1] == b'\n' {
+                if line[0] == b'\r' && line[1] == b'\n' && in_headers {
+                    res!(email.add_header(&header_line));
                     in_headers = false;
                     continue;
                 }
@@ -85,6 +90,7 @@ impl EmailMessage {
             let line = line.trim_end();
     
             if line.is_empty() && in_headers {
+                res!(email.add_header(&header_line));
                 in_headers = false;
                 continue;
             }
@@ -95,21 +101,12 @@ impl EmailMessage {
                     header_line.push(' ');
                     header_line.push_str(line.trim_start());
                 } else {
-                    if !header_line.is_empty() {
-                        match res!(EmailHeader::from_str(&header_line)) {
-                            EmailHeader::From(value) => email.from = value,
-                            EmailHeader::To(value) => email.to.push(value),
-                            EmailHeader::Subject(value) => email.subject = value,
-                            header => {
-                                email.headers.push(header);
-                            }
-                        }
-                    }
+                    res!(email.add_header(&header_line));
                     header_line = line.to_string();
                 }
             } else {
-                // Accumulate body lines.
-                email.body.push_str(line);
+                // Accumulate body lines, removing SMTP dot stuffing.
+                email.body.push_str(line.strip_prefix('.').filter(|l| l.starts_with('.')).unwrap_or(line));
                 email.body.push('\n');
             }
         }
@@ -117,6 +114,25 @@ impl EmailMessage {
         Ok(email)
     }
 
+    fn add_header(&mut self, header_line: &str) -> Outcome<()> {
+        if !header_line.is_empty() {
+            match res!(EmailHeader::from_str(header_line)) {
+                EmailHeader::From(value) => self.from = value,
+                EmailHeader::To(value) => self.to.push(value),
+                EmailHeader::Subject(value) => self.subject = value,
+                header => self.headers.push(header),
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse the MIME structure of the message, e.g. to find the HTML alternative or the
+    /// attachments.
+    pub fn mime(&self) -> Outcome<MimePart> {
+        let mut raw = fmt!("{}", self).replace('\n', "\r\n");
+        raw.push_str("\r\n");
+        MimePart::parse(raw.as_bytes())
+    }
 }
 
 /// RFC 5322 Internet Message Format https://datatracker.ietf.org/doc/html/rfc5322
@@ -153,6 +169,53 @@ pub enum EmailHeader {
     Other(String, String),
 }
 
+impl EmailHeader {
+
+    /// The header field name, e.g. "Message-ID".
+    pub fn name(&self) -> &str {
+        match self {
+            EmailHeader::From(_) => "From",
+            EmailHeader::To(_) => "To",
+            EmailHeader::Cc(_) => "Cc",
+            EmailHeader::Bcc(_) => "Bcc",
+            EmailHeader::Subject(_) => "Subject",
+            EmailHeader::Date(_) => "Date",
+            EmailHeader::MessageId(_) => "Message-ID",
+            EmailHeader::InReplyTo(_) => "In-Reply-To",
+            EmailHeader::References(_) => "References",
+            EmailHeader::ResentFrom(_) => "Resent-From",
+            EmailHeader::ResentTo(_) => "Resent-To",
+            EmailHeader::ResentCc(_) => "Resent-Cc",
+            EmailHeader::ResentBcc(_) => "Resent-Bcc",
+            EmailHeader::ResentDate(_) => "Resent-Date",
+            EmailHeader::ResentMessageId(_) => "Resent-Message-ID",
+            EmailHeader::ReplyTo(_) => "Reply-To",
+            EmailHeader::Sender(_) => "Sender",
+            EmailHeader::ReturnPath(_) => "Return-Path",
+            EmailHeader::ContentType(_) => "Content-Type",
+            EmailHeader::ContentTransferEncoding(_) => "Content-Transfer-Encoding",
+            EmailHeader::ContentDisposition(_) => "Content-Disposition",
+            EmailHeader::ContentId(_) => "Content-ID",
+            EmailHeader::MimeVersion(_) => "MIME-Version",
+            EmailHeader::XMailer(_) => "X-Mailer",
+            EmailHeader::XPriority(_) => "X-Priority",
+            EmailHeader::XMsMailPriority(_) => "X-MS-Mail-Priority",
+            EmailHeader::Importance(_) => "Importance",
+            EmailHeader::Received(_) => "Received",
+            EmailHeader::Other(name, _) => name,
+        }
+    }
+
+    /// The header field value as it appears on the wire.
+    pub fn value(&self) -> String {
+        let line = self.to_string();
+        match line.split_once(": ") {
+            Some((_, value)) => value.to_string(),
+            None => String::new(),
+        }
+    }
+}
+
 impl Default for EmailHeader {
     fn default() -> Self {
         Self::Other(String::new(), String::new())
@@ -227,22 +290,33 @@ impl FromStr for EmailHeader {
             "sender" => Ok(EmailHeader::Sender(value)),
             "return-path" => Ok(EmailHeader::ReturnPath(value)),
             "content-type" => {
-                let content_type = res!(MediaType::from_str(value.as_str()));
-                let content_type_value = match content_type {
-                    MediaType::Text(ref _text) => {
-                        ContentTypeValue::MediaType((content_type, Some(Charset::Utf_8)))
-                    }
-                    MediaType::Multipart(multipart) => {
-                        ContentTypeValue::Multipart((multipart, String::new()))
+                // Unrecognised media types, and values that would be written differently, e.g.
+                // with other parameters, are kept as received so that they can be verified by
+                // DKIM.
+                let (mt, params) = mime::split_params(&value);
+                let content_type = match MediaType::from_str(&mt.to_lowercase()) {
+                    Ok(MediaType::Multipart(multipart)) => mime::param(&params, "boundary")
+                        .map(|b| ContentTypeValue::Multipart((multipart, b.to_string()))),
+                    Ok(content_type) => {
+                        let charset = mime::param(&params, "charset")
+                            .and_then(|cs| Charset::from_str(&cs.to_lowercase()).ok());
+                        Some(ContentTypeValue::MediaType((content_type, charset)))
                     }
-                    _ => ContentTypeValue::MediaType((content_type, None)),
+                    Err(_) => None,
                 };
-                Ok(EmailHeader::ContentType(content_type_value))
+                match content_type {
+                    Some(ctv) if ctv.to_string() == value => Ok(EmailHeader::ContentType(ctv)),
+                    _ => Ok(EmailHeader::Other(fmt!("Content-Type"), value)),
+                }
             },
             "content-transfer-encoding" => Ok(EmailHeader::ContentTransferEncoding(value)),
             "content-disposition" => {
                 let disposition = res!(ContentDisposition::from_str(&value));
-                Ok(EmailHeader::ContentDisposition(disposition))
+                match disposition.to_string() == value {
+                    // As for the Content-Type.
+                    true => Ok(EmailHeader::ContentDisposition(disposition)),
+                    false => Ok(EmailHeader::Other(fmt!("Content-Disposition"), value)),
+                }
             },
             "content-id" => Ok(EmailHeader::ContentId(value)),
             "mime-version" => Ok(EmailHeader::MimeVersion(value)),
@@ -266,9 +340,11 @@ impl fmt::Display for ContentDisposition {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             ContentDisposition::Inline => write!(f, "inline"),
-            ContentDisposition::Attachment(Some(filename)) => {
-                write!(f, "attachment; filename={}", filename)
-            }
+            // Non-ASCII filenames use RFC 2231 encoding.
+            ContentDisposition::Attachment(Some(filename)) => match filename.is_ascii() {
+                true => write!(f, "attachment; filename={}", mime::quote_param(filename)),
+                false => write!(f, "attachment; filename*=utf-8''{}", mime::percent_encode(filename)),
+            },
             ContentDisposition::Attachment(None) => write!(f, "attachment"),
         }
     }
@@ -278,20 +354,16 @@ impl FromStr for ContentDisposition {
     type Err = Error<ErrTag>;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let parts: Vec<&str> = s.split(';').map(str::trim).collect();
-        match parts[0].to_lowercase().as_str() {
+        let (kind, params) = mime::split_params(s);
+        match kind.to_lowercase().as_str() {
             "inline" => Ok(ContentDisposition::Inline),
-            "attachment" => {
-                if parts.len() > 1 {
-                    let filename = parts[1].trim_start_matches("filename=").to_string();
-                    Ok(ContentDisposition::Attachment(Some(filename)))
-                } else {
-                    Ok(ContentDisposition::Attachment(None))
-                }
-            }
-            _ => Err(err!(
+            "" => Err(err!(
                 "Invalid Content-Disposition value: {}", s;
             Invalid, Input)),
+            // Unrecognised dispositions are treated as attachments (RFC 2183 Section 2.8).
+            _ => Ok(ContentDisposition::Attachment(
+                mime::param(&params, "filename").map(mime::decode_words)
+            )),
         }
     }
 }
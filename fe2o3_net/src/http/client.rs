@@ -0,0 +1,685 @@
+//! An async HTTP/1.1 client built on `HttpMessage`.
+//!
+//! Connections to each origin are kept alive and reused for subsequent requests, unless either
+//! side asks for the connection to be closed.  HTTPS uses `tokio-rustls`, trusting only the root
+//! certificates given in the `HttpClientConfig`.  Redirects are followed up to a configurable
+//! limit, but never from HTTPS to HTTP, and credentials are not passed on to a different origin.
+//! Responses may use `Content-Length`, chunked transfer encoding, or simply end when the server
+//! closes the connection.
+//!
+//! ```ignore
+//! let client = HttpClient::new(HttpClientConfig::default());
+//! let response = res!(client.get("http://localhost:8080/index.html").await);
+//! ```
+use crate::{
+    constant,
+    file::RequestPath,
+    http::{
+        fields::{
+            HeaderFields,
+            HeaderFieldValue,
+            HeaderName,
+        },
+        header::{
+            HttpHeader,
+            HttpHeadline,
+            HttpMethod,
+            HttpVersion,
+        },
+        loc::HttpLocator,
+        msg::HttpMessage,
+        status::HttpStatus,
+    },
+};
+
+use oxedyne_fe2o3_core::prelude::*;
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::BufReader,
+    path::Path,
+    pin::Pin,
+    sync::{
+        Arc,
+        RwLock,
+    },
+    task::{
+        Context,
+        Poll,
+    },
+    time::Duration,
+};
+
+use tokio::{
+    io::{
+        AsyncRead,
+        AsyncReadExt,
+        AsyncWrite,
+        ReadBuf,
+    },
+    net::TcpStream,
+};
+use tokio_rustls::{
+    client::TlsStream,
+    rustls::{
+        pki_types::ServerName,
+        ClientConfig,
+        RootCertStore,
+    },
+    TlsConnector,
+};
+
+
+#[derive(Clone, Debug)]
+pub struct HttpClientConfig {
+    pub connect_timeout:        Duration,
+    /// Limits each request, including any redirects.
+    pub request_timeout:        Duration,
+    pub max_redirects:          usize,
+    pub max_idle_per_host:      usize,
+    pub user_agent:             String,
+    /// Trusted root certificates for HTTPS.
+    pub roots:                  RootCertStore,
+}
+
+impl Default for HttpClientConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout:    constant::HTTP_CLIENT_CONNECT_TIMEOUT,
+            request_timeout:    constant::HTTP_CLIENT_REQUEST_TIMEOUT,
+            max_redirects:      constant::HTTP_CLIENT_MAX_REDIRECTS,
+            max_idle_per_host:  constant::HTTP_CLIENT_MAX_IDLE_PER_HOST,
+            user_agent:         constant::HTTP_CLIENT_USER_AGENT.to_string(),
+            roots:              RootCertStore::empty(),
+        }
+    }
+}
+
+impl HttpClientConfig {
+    /// Trust the certificates in the given PEM file, returning the number added.
+    pub fn add_root_certs_pem<P: AsRef<Path>>(&mut self, path: P) -> Outcome<usize> {
+        let file = match File::open(path.as_ref()) {
+            Ok(file) => file,
+            Err(e) => return Err(err!(e,
+                "While opening root certificate file {:?}.", path.as_ref();
+            IO, File, Read)),
+        };
+        let mut reader = BufReader::new(file);
+        let mut n = 0;
+        for result in rustls_pemfile::certs(&mut reader) {
+            let cert = res!(result);
+            res!(self.roots.add(cert));
+            n += 1;
+        }
+        Ok(n)
+    }
+}
+
+/// The parts of an absolute `http` or `https` URL needed to make a request.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HttpUrl {
+    pub secure: bool,
+    pub host:   String,
+    pub port:   u16,
+    /// The path and query, sent verbatim in the request line.
+    pub target: String,
+}
+
+impl HttpUrl {
+
+    pub fn new(url: &str) -> Outcome<Self> {
+        let lower = url.to_lowercase();
+        let (secure, rest) = if lower.starts_with("https://") {
+            (true, &url[8..])
+        } else if lower.starts_with("http://") {
+            (false, &url[7..])
+        } else {
+            return Err(err!(
+                "The URL '{}' must begin with 'http://' or 'https://'.", url;
+            Input, Invalid));
+        };
+        let rest = match rest.split_once('#') {
+            Some((rest, _frag)) => rest,
+            None => rest,
+        };
+        let (authority, target) = match rest.find(['/', '?']) {
+            Some(i) if rest[i..].starts_with('?') => (&rest[..i], fmt!("/{}", &rest[i..])),
+            Some(i) => (&rest[..i], rest[i..].to_string()),
+            None => (rest, fmt!("/")),
+        };
+        if authority.contains('@') {
+            return Err(err!(
+                "User information in the URL '{}' is not supported.", url;
+            Input, Invalid));
+        }
+        let (host, port_str) = if authority.starts_with('[') {
+            // IPv6 literal.
+            match authority.find(']') {
+                Some(i) => (&authority[..i + 1], authority[i + 1..].strip_prefix(':')),
+                None => return Err(err!(
+                    "Unterminated IPv6 address in the URL '{}'.", url;
+                Input, Invalid)),
+            }
+        } else {
+            match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            }
+        };
+        if host.is_empty() {
+            return Err(err!("The URL '{}' has no host.", url; Input, Missing));
+        }
+        let port = match port_str {
+            Some(s) => match s.parse::<u16>() {
+                Ok(port) => port,
+                Err(e) => return Err(err!(e,
+                    "Invalid port '{}' in the URL '{}'.", s, url;
+                Input, Invalid)),
+            },
+            None => if secure { 443 } else { 80 },
+        };
+        Ok(Self {
+            secure,
+            host: host.to_string(),
+            port,
+            target,
+        })
+    }
+
+    /// Resolve a `Location` relative to this URL.
+    pub fn join(&self, location: &str) -> Outcome<Self> {
+        let lower = location.to_lowercase();
+        if lower.starts_with("http://") || lower.starts_with("https://") {
+            return Self::new(location);
+        }
+        if location.starts_with("//") {
+            let scheme = if self.secure { "https:" } else { "http:" };
+            return Self::new(&fmt!("{}{}", scheme, location));
+        }
+        let target = if location.starts_with('/') {
+            location.to_string()
+        } else {
+            // Relative to the directory of the current path.
+            let path = match self.target.split_once('?') {
+                Some((path, _)) => path,
+                None => &self.target,
+            };
+            match path.rfind('/') {
+                Some(i) => fmt!("{}{}", &path[..i + 1], location),
+                None => fmt!("/{}", location),
+            }
+        };
+        Ok(Self {
+            target,
+            ..self.clone()
+        })
+    }
+
+    /// The value of the `Host` field, omitting the default port.
+    pub fn host_field(&self) -> String {
+        match (self.secure, self.port) {
+            (true, 443) | (false, 80) => self.host.clone(),
+            _ => fmt!("{}:{}", self.host, self.port),
+        }
+    }
+
+    fn origin(&self) -> (bool, String, u16) {
+        (self.secure, self.host.clone(), self.port)
+    }
+
+    /// Whether the scheme, host and port of the URLs match.
+    pub fn same_origin(&self, other: &Self) -> bool {
+        self.secure == other.secure
+            && self.port == other.port
+            && self.host.eq_ignore_ascii_case(&other.host)
+    }
+}
+
+/// A plain or TLS connection to a server.
+#[derive(Debug)]
+pub enum HttpStream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl AsyncRead for HttpStream {
+    fn poll_read(
+        self:   Pin<&mut Self>,
+        cx:     &mut Context<'_>,
+        buf:    &mut ReadBuf<'_>,
+    )
+        -> Poll<std::io::Result<()>>
+    {
+        match self.get_mut() {
+            Self::Plain(s) => Pin::new(s).poll_read(cx, buf),
+            Self::Tls(s) => Pin::new(s.as_mut()).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for HttpStream {
+    fn poll_write(
+        self:   Pin<&mut Self>,
+        cx:     &mut Context<'_>,
+        buf:    &[u8],
+    )
+        -> Poll<std::io::Result<usize>>
+    {
+        match self.get_mut() {
+            Self::Plain(s) => Pin::new(s).poll_write(cx, buf),
+            Self::Tls(s) => Pin::new(s.as_mut()).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(s) => Pin::new(s).poll_flush(cx),
+            Self::Tls(s) => Pin::new(s.as_mut()).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(s) => Pin::new(s).poll_shutdown(cx),
+            Self::Tls(s) => Pin::new(s.as_mut()).poll_shutdown(cx),
+        }
+    }
+}
+
+/// A connection, with any bytes read beyond the previous response.
+#[derive(Debug)]
+struct HttpConnection {
+    stream:     HttpStream,
+    remnant:    Vec<u8>,
+}
+
+type IdlePool = BTreeMap<(bool, String, u16), Vec<HttpConnection>>;
+
+/// The outcome of sending a request on a connection.
+enum Sent {
+    /// The response, and whether the connection can be reused.
+    Response(HttpMessage, bool),
+    /// The connection turned out to be closed before any of the response arrived, so the request
+    /// can be sent again on a new connection.
+    Closed(Error<ErrTag>),
+}
+
+/// An async HTTP/1.1 client.  Clones share the pool of idle connections.
+#[derive(Clone)]
+pub struct HttpClient {
+    pub cfg:    HttpClientConfig,
+    tls:        TlsConnector,
+    idle:       Arc<RwLock<IdlePool>>,
+}
+
+impl std::fmt::Debug for HttpClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HttpClient")
+            .field("cfg", &self.cfg)
+            .finish()
+    }
+}
+
+impl HttpClient {
+
+    pub fn new(cfg: HttpClientConfig) -> Self {
+        let tls_cfg = ClientConfig::builder()
+            .with_root_certificates(cfg.roots.clone())
+            .with_no_client_auth();
+        Self {
+            cfg,
+            tls:    TlsConnector::from(Arc::new(tls_cfg)),
+            idle:   Arc::new(RwLock::new(BTreeMap::new())),
+        }
+    }
+
+    pub async fn get(&self, url: &str) -> Outcome<HttpMessage> {
+        self.request(HttpMethod::GET, url, HeaderFields::default(), Vec::new()).await
+    }
+
+    pub async fn post(&self, url: &str, fields: HeaderFields, body: Vec<u8>) -> Outcome<HttpMessage> {
+        self.request(HttpMethod::POST, url, fields, body).await
+    }
+
+    pub async fn put(&self, url: &str, fields: HeaderFields, body: Vec<u8>) -> Outcome<HttpMessage> {
+        self.request(HttpMethod::PUT, url, fields, body).await
+    }
+
+    pub async fn delete(&self, url: &str) -> Outcome<HttpMessage> {
+        self.request(HttpMethod::DELETE, url, HeaderFields::default(), Vec::new()).await
+    }
+
+    /// Send a request with the given additional header fields and body, following redirects, and
+    /// return the final response.  `Host`, `User-Agent` and `Content-Length` are set by the
+    /// client.
+    pub async fn request(
+        &self,
+        method: HttpMethod,
+        url:    &str,
+        fields: HeaderFields,
+        body:   Vec<u8>,
+    )
+        -> Outcome<HttpMessage>
+    {
+        let url = res!(HttpUrl::new(url));
+        let wait = self.cfg.request_timeout;
+        match tokio::time::timeout(wait, self.follow(method, url, fields, body)).await {
+            Ok(result) => result,
+            Err(e) => Err(err!(e,
+                "HTTP request did not complete within {:?}.", wait;
+            IO, Network, Timeout)),
+        }
+    }
+
+    async fn follow(
+        &self,
+        mut method: HttpMethod,
+        mut url:    HttpUrl,
+        mut fields: HeaderFields,
+        mut body:   Vec<u8>,
+    )
+        -> Outcome<HttpMessage>
+    {
+        let mut redirects = 0;
+        loop {
+            let response = res!(self.exchange(method, &url, &fields, &body).await);
+            let status = match response.header.headline {
+                HttpHeadline::Response { status } => status,
+                _ => return Err(err!(
+                    "Expected a response from {:?}, received a request.", url;
+                IO, Network, Unexpected)),
+            };
+            match status {
+                HttpStatus::MovedPermanently    |
+                HttpStatus::Found               |
+                HttpStatus::SeeOther            |
+                HttpStatus::TemporaryRedirect   |
+                HttpStatus::PermanentRedirect   => (),
+                _ => return Ok(response),
+            }
+            let location = match response.header.get_a_field_value(&HeaderName::Location) {
+                Some(HeaderFieldValue::Generic(location)) => location.clone(),
+                _ => return Ok(response),
+            };
+            redirects += 1;
+            if redirects > self.cfg.max_redirects {
+                return Err(err!(
+                    "Exceeded the limit of {} redirects, the last to '{}'.",
+                    self.cfg.max_redirects, location;
+                IO, Network, TooBig));
+            }
+            let next = res!(url.join(&location));
+            if url.secure && !next.secure {
+                return Err(err!(
+                    "Refusing to follow a redirect from {:?} to the insecure {:?}.", url, next;
+                IO, Network, Security));
+            }
+            if !url.same_origin(&next) {
+                // Credentials intended for one origin are not disclosed to another.
+                for nam in [
+                    HeaderName::Authorization,
+                    HeaderName::ProxyAuthorization,
+                    HeaderName::Cookie,
+                ] {
+                    let _ = fields.remove(&nam);
+                }
+            }
+            url = next;
+            // Only 307 and 308 require the method and body to be retained.
+            match status {
+                HttpStatus::TemporaryRedirect | HttpStatus::PermanentRedirect => (),
+                HttpStatus::SeeOther => {
+                    method = HttpMethod::GET;
+                    body = Vec::new();
+                },
+                _ => if method == HttpMethod::POST {
+                    method = HttpMethod::GET;
+                    body = Vec::new();
+                },
+            }
+            debug!("Following {} redirect to {:?}.", status, url);
+        }
+    }
+
+    /// Send a single request, reusing an idle connection to the origin where possible.  A request
+    /// with an idempotent method (`GET`, `HEAD`, `PUT`, `DELETE` or `OPTIONS`) is retried once on
+    /// a new connection if a reused one turns out to have been closed by the server before any of
+    /// the response arrived.  Any other error, such as a malformed response, is not retried.
+    async fn exchange(
+        &self,
+        method: HttpMethod,
+        url:    &HttpUrl,
+        fields: &HeaderFields,
+        body:   &[u8],
+    )
+        -> Outcome<HttpMessage>
+    {
+        if let Some(mut cx) = res!(self.take_idle(url)) {
+            match res!(self.send_on(&mut cx, method, url, fields, body).await) {
+                Sent::Response(response, reusable) => {
+                    if reusable {
+                        res!(self.put_idle(url, cx));
+                    }
+                    return Ok(response);
+                },
+                Sent::Closed(_) if matches!(
+                    method,
+                    HttpMethod::GET
+                    | HttpMethod::HEAD
+                    | HttpMethod::PUT
+                    | HttpMethod::DELETE
+                    | HttpMethod::OPTIONS
+                ) => {
+                    debug!("Idle connection to {}:{} was closed, reconnecting.", url.host, url.port);
+                },
+                Sent::Closed(e) => return Err(e),
+            }
+        }
+        let mut cx = res!(self.connect(url).await);
+        match res!(self.send_on(&mut cx, method, url, fields, body).await) {
+            Sent::Response(response, reusable) => {
+                if reusable {
+                    res!(self.put_idle(url, cx));
+                }
+                Ok(response)
+            },
+            Sent::Closed(e) => Err(e),
+        }
+    }
+
+    async fn connect(&self, url: &HttpUrl) -> Outcome<HttpConnection> {
+        let wait = self.cfg.connect_timeout;
+        let addr = (url.host.trim_start_matches('[').trim_end_matches(']'), url.port);
+        let tcp = match tokio::time::timeout(wait, TcpStream::connect(addr)).await {
+            Ok(result) => match result {
+                Ok(tcp) => tcp,
+                Err(e) => return Err(err!(e,
+                    "While connecting to {}:{}.", url.host, url.port;
+                IO, Network)),
+            },
+            Err(e) => return Err(err!(e,
+                "Connection to {}:{} not established within {:?}.", url.host, url.port, wait;
+            IO, Network, Timeout)),
+        };
+        let stream = if url.secure {
+            let name = url.host.trim_start_matches('[').trim_end_matches(']').to_string();
+            let server_name = match ServerName::try_from(name) {
+                Ok(server_name) => server_name,
+                Err(e) => return Err(err!(e,
+                    "Invalid TLS server name '{}'.", url.host;
+                IO, Network, Invalid, Input)),
+            };
+            match self.tls.connect(server_name, tcp).await {
+                Ok(tls) => HttpStream::Tls(Box::new(tls)),
+                Err(e) => return Err(err!(e,
+                    "TLS handshake with {}:{} failed.", url.host, url.port;
+                IO, Network)),
+            }
+        } else {
+            HttpStream::Plain(tcp)
+        };
+        Ok(HttpConnection {
+            stream,
+            remnant: Vec::new(),
+        })
+    }
+
+    /// Write the request and read the response.  A connection that fails while writing, or that
+    /// closes before the first byte of the response, is reported as `Sent::Closed`.  Closing part
+    /// way through the response is an error.
+    async fn send_on(
+        &self,
+        cx:     &mut HttpConnection,
+        method: HttpMethod,
+        url:    &HttpUrl,
+        fields: &HeaderFields,
+        body:   &[u8],
+    )
+        -> Outcome<Sent>
+    {
+        let mut request = HttpMessage {
+            header: HttpHeader {
+                version:    HttpVersion::Http1_1,
+                headline:   HttpHeadline::Request {
+                    method,
+                    loc: HttpLocator {
+                        path: RequestPath::new(url.target.clone()),
+                        ..Default::default()
+                    },
+                },
+                fields:     fields.clone(),
+            },
+            body: body.to_vec(),
+            ..Default::default()
+        };
+        let _ = request.header.fields.remove(&HeaderName::Host);
+        let _ = request.insert(HeaderName::Host, HeaderFieldValue::Generic(url.host_field()), None);
+        if request.header.fields.get_one(&HeaderName::UserAgent).is_none() {
+            let _ = request.insert(
+                HeaderName::UserAgent,
+                HeaderFieldValue::Generic(self.cfg.user_agent.clone()),
+                None,
+            );
+        }
+        let _ = request.header.fields.remove(&HeaderName::ContentLength);
+        let close_requested = request.get_connection_close();
+        if let Err(e) = request.write_all(&mut cx.stream).await {
+            return Ok(Sent::Closed(err!(e,
+                "While writing a request to {}:{}.", url.host, url.port;
+            IO, Network, Write)));
+        }
+
+        if cx.remnant.is_empty() {
+            // Wait for the first byte, so that a closed connection can be told apart from a
+            // failure part way through a response.
+            let mut buf = [0u8; constant::HTTP_DEFAULT_HEADER_CHUNK_SIZE];
+            match cx.stream.read(&mut buf).await {
+                Ok(0) => return Ok(Sent::Closed(err!(
+                    "Connection to {}:{} closed before a response was received.",
+                    url.host, url.port;
+                IO, Network, Read))),
+                Ok(n) => cx.remnant.extend_from_slice(&buf[..n]),
+                Err(e) if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::UnexpectedEof
+                    | std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::BrokenPipe
+                ) => return Ok(Sent::Closed(err!(e,
+                    "Connection to {}:{} closed before a response was received.",
+                    url.host, url.port;
+                IO, Network, Read))),
+                Err(e) => return Err(err!(e,
+                    "While reading a response from {}:{}.", url.host, url.port;
+                IO, Network, Read)),
+            }
+        }
+
+        // Interim 1xx responses precede the final response, except for a switch of protocols,
+        // after which the connection no longer carries HTTP.
+        let mut response = loop {
+            let result = HttpMessage::read_response::<
+                { constant::HTTP_DEFAULT_HEADER_CHUNK_SIZE },
+                { constant::HTTP_DEFAULT_BODY_CHUNK_SIZE },
+                _,
+            >(Pin::new(&mut cx.stream), &cx.remnant, method, constant::HTTP_BODY_SIZE_MAX).await;
+            match res!(result) {
+                (Some(response), remnant) => {
+                    cx.remnant = remnant;
+                    match Self::status(&response) {
+                        Some(status) if (100..200).contains(&status)
+                            && status != HttpStatus::SwitchingProtocols as u16 => continue,
+                        _ => break response,
+                    }
+                },
+                (None, _) => return Err(err!(
+                    "Connection to {}:{} closed part way through a response.",
+                    url.host, url.port;
+                IO, Network, Read)),
+            }
+        };
+
+        let mut reusable = !close_requested
+            && !response.get_connection_close()
+            && Self::status(&response) != Some(HttpStatus::SwitchingProtocols as u16);
+        if Self::body_until_close(&response, method) {
+            // Without a length or chunking, the body is delimited by the connection closing.
+            let max = constant::HTTP_BODY_SIZE_MAX;
+            let mut rest = std::mem::take(&mut cx.remnant);
+            let limit = (max + 1).saturating_sub(rest.len()) as u64;
+            if let Err(e) = (&mut cx.stream).take(limit).read_to_end(&mut rest).await {
+                return Err(err!(e,
+                    "While reading a response body from {}:{}.", url.host, url.port;
+                IO, Network, Read));
+            }
+            if rest.len() > max {
+                return Err(err!(
+                    "The response body from {}:{} exceeds the limit of {} bytes.",
+                    url.host, url.port, max;
+                IO, Network, Input, TooBig));
+            }
+            response.body = rest;
+            reusable = false;
+        }
+        Ok(Sent::Response(response, reusable))
+    }
+
+    /// The status code of a response.
+    fn status(response: &HttpMessage) -> Option<u16> {
+        match response.header.headline {
+            HttpHeadline::Response { status } => Some(status as u16),
+            _ => None,
+        }
+    }
+
+    /// Whether the response body is delimited only by the server closing the connection.
+    fn body_until_close(response: &HttpMessage, method: HttpMethod) -> bool {
+        response.response_has_body(method)
+            && !response.header.is_chunked()
+            && response.header.fields.get_one(&HeaderName::ContentLength).is_none()
+    }
+
+    fn take_idle(&self, url: &HttpUrl) -> Outcome<Option<HttpConnection>> {
+        let mut unlocked_idle = lock_write!(self.idle);
+        Ok(match unlocked_idle.get_mut(&url.origin()) {
+            Some(list) => list.pop(),
+            None => None,
+        })
+    }
+
+    fn put_idle(&self, url: &HttpUrl, cx: HttpConnection) -> Outcome<()> {
+        let mut unlocked_idle = lock_write!(self.idle);
+        let list = unlocked_idle.entry(url.origin()).or_default();
+        if list.len() < self.cfg.max_idle_per_host {
+            list.push(cx);
+        }
+        Ok(())
+    }
+
+    /// The number of idle connections held for reuse.
+    pub fn idle_count(&self) -> Outcome<usize> {
+        let unlocked_idle = lock_read!(self.idle);
+        Ok(unlocked_idle.values().map(|list| list.len()).sum())
+    }
+}
@@ -888,6 +888,7 @@ pub enum HeaderFieldValue {
     ContentType(ContentTypeValue),
     SecWebSocketKey(String),
     SetCookie(Cookie),
+    TransferEncoding(Vec<String>),
     Upgrade(Vec<String>),
 }
 
@@ -925,6 +926,7 @@ impl fmt::Display for HeaderFieldValue {
                     write!(f, "{}={}", key, val)
                 }
             }
+            Self::TransferEncoding(list) => write!(f, "{}", list.join(", ")),
             Self::Upgrade(list) => write!(f, "{}", list.join(", ")),
         }
     }
@@ -984,27 +986,26 @@ impl HeaderFieldValue {
                 Invalid, Input, String, Decode)),
             },
             HeaderName::ContentType => { // A; B=C
-                let mut parts = value.split(';').map(str::trim).map(|w| w.to_lowercase());
+                let mut parts = value.split(';').map(str::trim);
                 match parts.next() { // A
                     Some(first) => {
-                        let media_type = res!(MediaType::from_str(&first));
+                        let media_type = res!(MediaType::from_str(&first.to_lowercase()));
                         let is_multipart = match media_type {
                             MediaType::Multipart(Multipart::FormData) => true,
                             _ => false,
                         };
                         match parts.next() { // B=C
                             Some(second) => {
-                                let mut parts2 = second.split('=')
-                                    .map(str::trim)
-                                    .map(|w| w.to_lowercase());
+                                // A boundary may itself contain '='.
+                                let mut parts2 = second.splitn(2, '=').map(str::trim);
                                 match parts2.next() { // B
                                     Some(left) => match is_multipart {
-                                        true => if left != "boundary" {
+                                        true => if !left.eq_ignore_ascii_case("boundary") {
                                             return Err(err!(
                                                 "Expected 'boundary' found '{}'.", left;
                                             Invalid, Input, String, Decode));
                                         },
-                                        false => if left != "charset" {
+                                        false => if !left.eq_ignore_ascii_case("charset") {
                                             return Err(err!(
                                                 "Expected 'charset' found '{}'.", left;
                                             Invalid, Input, String, Decode));
@@ -1014,13 +1015,14 @@ impl HeaderFieldValue {
                                 }
                                 match parts2.next() { // C
                                     Some(right) => match is_multipart {
+                                        // Boundaries are case sensitive, and may be quoted.
                                         true => Self::ContentType(ContentTypeValue::Multipart((
                                             Multipart::FormData,
-                                            right.to_string(),
+                                            right.trim_matches('"').to_string(),
                                         ))),
                                         false => Self::ContentType(ContentTypeValue::MediaType((
                                             media_type,
-                                            Some(res!(Charset::from_str(&right))),
+                                            Some(res!(Charset::from_str(&right.to_lowercase()))),
                                         ))),
                                     },
                                     None => return Err(err!("Missing {} value in '{}'.",
@@ -1046,6 +1048,11 @@ impl HeaderFieldValue {
             },
             HeaderName::SecWebSocketKey     |
             HeaderName::SecWebSocketAccept  => Self::SecWebSocketKey(value.to_string()),
+            HeaderName::TransferEncoding => {
+                let list: Vec<_> = value
+                    .split(',').map(str::trim).map(|w| w.to_lowercase()).collect();
+                Self::TransferEncoding(list)
+            },
             HeaderName::Upgrade => {
                 let list: Vec<_> = value
                     .split(',').map(str::trim).map(|w| w.to_lowercase()).collect();
@@ -1232,6 +1239,21 @@ impl HeaderFields {
         }
     }
 
+    /// Remove all values for the given name, returning whether the header field name was present.
+    pub fn remove(
+        &mut self,
+        nam: &HeaderName, 
+    ) 
+        -> bool
+    {
+        self.order.retain(|_, name| name != nam);
+        self.fields.remove(nam).is_some()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
     pub fn get_session_id(&self) -> Option<String> {
         if let Some(HeaderFieldValue::Cookie(cookies)) = self.get_one(&HeaderName::Cookie) {
             for cookie in cookies {
@@ -0,0 +1,692 @@
+//! Parsing of HTML form submissions in `application/x-www-form-urlencoded` and
+//! `multipart/form-data` request bodies.
+//!
+//! A form becomes a `DaticleMap` keyed by field name.  Text fields are `Dat::Str` values, a field
+//! given more than once becomes a `Dat::List` of its values in order, and an uploaded file becomes
+//! the `Dat::Map` of an `HttpUpload`.  Multipart bodies are read incrementally from any
+//! `AsyncRead`, and uploads larger than `HttpFormConfig::memory_limit` are written to a uniquely
+//! named file in the upload directory rather than held in memory.  The receiver of the form is
+//! responsible for moving or removing such files.
+//!
+//! ```ignore
+//! let form = res!(HttpForm::from_body(&request.header.fields, &request.body, &cfg).await);
+//! if let Some(dat) = form.get(&dat!("avatar")) {
+//!     let upload = res!(HttpUpload::from_dat(dat));
+//!     let byts = res!(upload.bytes().await);
+//! }
+//! ```
+use crate::{
+    constant,
+    http::fields::{
+        HeaderFields,
+        HeaderFieldValue,
+        HeaderName,
+    },
+    media::{
+        Application,
+        ContentTypeValue,
+        MediaType,
+        Multipart,
+    },
+};
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    rand::Rand,
+};
+use oxedyne_fe2o3_jdat::prelude::*;
+
+use std::path::PathBuf;
+
+use tokio::{
+    fs::File,
+    io::{
+        AsyncRead,
+        AsyncReadExt,
+        AsyncWriteExt,
+    },
+};
+
+
+#[derive(Clone, Debug)]
+pub struct HttpFormConfig {
+    /// Where uploads larger than the memory limit are written.
+    pub upload_dir:      PathBuf,
+    pub memory_limit:    usize,
+    /// Limits each text field, which is always held in memory.
+    pub max_field_size:  usize,
+    /// Limits each uploaded file, wherever it is held.
+    pub max_upload_size: usize,
+    pub max_parts:       usize,
+}
+
+impl Default for HttpFormConfig {
+    fn default() -> Self {
+        Self {
+            upload_dir:      std::env::temp_dir(),
+            memory_limit:    constant::HTTP_FORM_MEMORY_LIMIT,
+            max_field_size:  constant::HTTP_FORM_MAX_FIELD_SIZE,
+            max_upload_size: constant::HTTP_FORM_MAX_UPLOAD_SIZE,
+            max_parts:       constant::HTTP_FORM_MAX_PARTS,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum HttpUploadContent {
+    Memory(Vec<u8>),
+    File(PathBuf),
+}
+
+/// A file uploaded as part of a multipart form.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HttpUpload {
+    /// The name given by the client, without any directory components.
+    pub filename:       String,
+    pub content_type:   String,
+    pub size:           usize,
+    pub content:        HttpUploadContent,
+}
+
+impl HttpUpload {
+
+    pub fn to_dat(&self) -> Dat {
+        let mut map = DaticleMap::new();
+        map.insert(dat!("filename"), dat!(self.filename.clone()));
+        map.insert(dat!("content_type"), dat!(self.content_type.clone()));
+        map.insert(dat!("size"), dat!(self.size as u64));
+        match &self.content {
+            HttpUploadContent::Memory(byts) => {
+                map.insert(dat!("bytes"), Dat::BU64(byts.clone()));
+            }
+            HttpUploadContent::File(path) => {
+                map.insert(dat!("path"), dat!(path.to_string_lossy().to_string()));
+            }
+        }
+        Dat::Map(map)
+    }
+
+    pub fn from_dat(dat: &Dat) -> Outcome<Self> {
+        let map = match dat {
+            Dat::Map(map) => map,
+            _ => return Err(err!(
+                "Expected a map describing an upload, found a {:?}.", dat.kind();
+            Input, Invalid, Mismatch)),
+        };
+        let get_str = |key: &str| -> Outcome<String> {
+            match map.get(&dat!(key)) {
+                Some(Dat::Str(s)) => Ok(s.clone()),
+                _ => Err(err!(
+                    "Upload map is missing the string '{}'.", key;
+                Input, Missing)),
+            }
+        };
+        let size = match map.get(&dat!("size")) {
+            Some(Dat::U64(n)) => *n as usize,
+            _ => return Err(err!(
+                "Upload map is missing the size.";
+            Input, Missing)),
+        };
+        let content = match (map.get(&dat!("bytes")), map.get(&dat!("path"))) {
+            (Some(Dat::BU64(byts)), None) => HttpUploadContent::Memory(byts.clone()),
+            (None, Some(Dat::Str(path))) => HttpUploadContent::File(PathBuf::from(path)),
+            _ => return Err(err!(
+                "Upload map must contain either bytes or a path.";
+            Input, Invalid)),
+        };
+        Ok(Self {
+            filename:       res!(get_str("filename")),
+            content_type:   res!(get_str("content_type")),
+            size,
+            content,
+        })
+    }
+
+    /// The uploaded bytes, read from disk if necessary.
+    pub async fn bytes(&self) -> Outcome<Vec<u8>> {
+        match &self.content {
+            HttpUploadContent::Memory(byts) => Ok(byts.clone()),
+            HttpUploadContent::File(path) => match tokio::fs::read(path).await {
+                Ok(byts) => Ok(byts),
+                Err(e) => Err(err!(e,
+                    "While reading upload file {:?}.", path;
+                IO, File, Read)),
+            },
+        }
+    }
+
+    /// Remove the upload file, if any.  A file already moved away by its receiver is ignored.
+    pub async fn remove(&self) -> Outcome<()> {
+        if let HttpUploadContent::File(path) = &self.content {
+            match tokio::fs::remove_file(path).await {
+                Ok(()) => (),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+                Err(e) => return Err(err!(e,
+                    "While removing upload file {:?}.", path;
+                IO, File)),
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct HttpForm;
+
+impl HttpForm {
+
+    /// Parse the form in a request body, according to the `Content-Type` of the request.  Any
+    /// other kind of body yields an empty map, leaving the raw body to the receiver.
+    pub async fn from_body(
+        fields: &HeaderFields,
+        body:   &[u8],
+        cfg:    &HttpFormConfig,
+    )
+        -> Outcome<DaticleMap>
+    {
+        if let Some(boundary) = Self::multipart_boundary(fields) {
+            return Self::read_multipart::<{ constant::HTTP_DEFAULT_BODY_CHUNK_SIZE }, _>(
+                body,
+                boundary,
+                cfg,
+            ).await;
+        }
+        match fields.get_one(&HeaderName::ContentType) {
+            Some(HeaderFieldValue::ContentType(
+                ContentTypeValue::MediaType((MediaType::Application(Application::FormUrlEncoded), _))
+            )) => Self::parse_urlencoded(body),
+            _ => Ok(DaticleMap::new()),
+        }
+    }
+
+    /// The boundary of a `multipart/form-data` body, whose parts can be read incrementally using
+    /// `read_multipart` rather than buffering the whole body.
+    pub fn multipart_boundary(fields: &HeaderFields) -> Option<&str> {
+        match fields.get_one(&HeaderName::ContentType) {
+            Some(HeaderFieldValue::ContentType(
+                ContentTypeValue::Multipart((Multipart::FormData, boundary))
+            )) => Some(boundary),
+            _ => None,
+        }
+    }
+
+    /// The uploads in a form.
+    pub fn uploads(form: &DaticleMap) -> Vec<HttpUpload> {
+        let mut result = Vec::new();
+        for v in form.values() {
+            let vals = match v {
+                Dat::List(list) => list.iter().collect::<Vec<_>>(),
+                _ => vec![v],
+            };
+            for val in vals {
+                if let Ok(upload) = HttpUpload::from_dat(val) {
+                    result.push(upload);
+                }
+            }
+        }
+        result
+    }
+
+    pub fn parse_urlencoded(body: &[u8]) -> Outcome<DaticleMap> {
+        let body = match std::str::from_utf8(body) {
+            Ok(s) => s,
+            Err(e) => return Err(err!(e,
+                "Invalid UTF-8 in url encoded form.";
+            Input, Invalid, Decode, String)),
+        };
+        let mut form = DaticleMap::new();
+        for pair in body.split('&').filter(|p| !p.is_empty()) {
+            let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+            Self::insert(
+                &mut form,
+                res!(Self::percent_decode(k, true)),
+                dat!(res!(Self::percent_decode(v, true))),
+            );
+        }
+        Ok(form)
+    }
+
+    /// Decode `%XX` escapes, and optionally `+` as a space, as used in url encoded forms.
+    pub fn percent_decode(s: &str, plus_as_space: bool) -> Outcome<String> {
+        let byts = s.as_bytes();
+        let mut result = Vec::with_capacity(byts.len());
+        let mut i = 0;
+        while i < byts.len() {
+            match byts[i] {
+                b'%' => {
+                    let hex = match byts.get(i + 1..i + 3) {
+                        Some(hex) => hex,
+                        None => return Err(err!(
+                            "Incomplete percent escape in '{}'.", s;
+                        Input, Invalid, Decode)),
+                    };
+                    match std::str::from_utf8(hex).ok().and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                        Some(b) => result.push(b),
+                        None => return Err(err!(
+                            "Invalid percent escape in '{}'.", s;
+                        Input, Invalid, Decode)),
+                    }
+                    i += 3;
+                    continue;
+                }
+                b'+' if plus_as_space => result.push(b' '),
+                b => result.push(b),
+            }
+            i += 1;
+        }
+        match String::from_utf8(result) {
+            Ok(s) => Ok(s),
+            Err(e) => Err(err!(e,
+                "Percent decoded value is not valid UTF-8.";
+            Input, Invalid, Decode, String)),
+        }
+    }
+
+    /// Read a multipart form from the given source, which is consumed up to the closing
+    /// delimiter.  Any upload files already written are removed if the form turns out to be
+    /// invalid.
+    pub async fn read_multipart<
+        const CHUNK_SIZE: usize,
+        R: AsyncRead + Unpin,
+    >(
+        reader:     R,
+        boundary:   &str,
+        cfg:        &HttpFormConfig,
+    )
+        -> Outcome<DaticleMap>
+    {
+        let mut files = Vec::new();
+        let result = Self::read_parts::<CHUNK_SIZE, _>(reader, boundary, cfg, &mut files).await;
+        if result.is_err() {
+            for path in files {
+                let _ = tokio::fs::remove_file(path).await;
+            }
+        }
+        result
+    }
+
+    async fn read_parts<
+        const CHUNK_SIZE: usize,
+        R: AsyncRead + Unpin,
+    >(
+        mut reader: R,
+        boundary:   &str,
+        cfg:        &HttpFormConfig,
+        files:      &mut Vec<PathBuf>,
+    )
+        -> Outcome<DaticleMap>
+    {
+        if boundary.is_empty() || boundary.len() > 70 {
+            return Err(err!(
+                "Multipart boundary length {} must be between 1 and 70.", boundary.len();
+            Input, Invalid, Size));
+        }
+        // Every delimiter, including the first, is preceded by a line break.  Starting the buffer
+        // with one allows the first to be found like the others.
+        let delim = fmt!("\r\n--{}", boundary).into_bytes();
+        let mut buf = b"\r\n".to_vec();
+        let mut form = DaticleMap::new();
+
+        // Discard the preamble.
+        let mut pos = loop {
+            match find(&buf, &delim) {
+                Some(pos) => break pos,
+                None => {
+                    let keep = buf.len().min(delim.len() - 1);
+                    buf.drain(..buf.len() - keep);
+                    if !res!(Self::read_more::<CHUNK_SIZE, _>(&mut reader, &mut buf).await) {
+                        return Err(err!(
+                            "Multipart form ended before the first delimiter.";
+                        Input, Missing));
+                    }
+                }
+            }
+        };
+
+        let mut count = 0;
+        loop {
+            buf.drain(..pos + delim.len());
+            // The delimiter line ends the form with "--", otherwise a line break follows,
+            // possibly after some whitespace.
+            let header_start = loop {
+                if buf.starts_with(b"--") {
+                    return Ok(form);
+                }
+                match find(&buf, b"\r\n") {
+                    Some(i) if buf[..i].iter().all(|b| *b == b' ' || *b == b'\t') => break i,
+                    Some(_) => return Err(err!(
+                        "Unexpected characters following a multipart delimiter.";
+                    Input, Invalid)),
+                    None => if !res!(Self::read_more::<CHUNK_SIZE, _>(&mut reader, &mut buf).await) {
+                        return Err(err!(
+                            "Multipart form ended without a closing delimiter.";
+                        Input, Missing));
+                    },
+                }
+            };
+
+            count += 1;
+            if count > cfg.max_parts {
+                return Err(err!(
+                    "Multipart form exceeds the limit of {} parts.", cfg.max_parts;
+                Input, Size, TooBig));
+            }
+
+            // The part headers, beginning with the line break ending the delimiter line so that
+            // a part without headers is handled like the others.
+            let header_end = loop {
+                match find(&buf[header_start..], b"\r\n\r\n") {
+                    Some(i) => break header_start + i,
+                    None => {
+                        if buf.len() > constant::HTTP_FORM_PART_HEADER_MAX {
+                            return Err(err!(
+                                "Multipart part headers exceed the limit of {} bytes.",
+                                constant::HTTP_FORM_PART_HEADER_MAX;
+                            Input, Size, TooBig));
+                        }
+                        if !res!(Self::read_more::<CHUNK_SIZE, _>(&mut reader, &mut buf).await) {
+                            return Err(err!(
+                                "Multipart form ended within part headers.";
+                            Input, Missing));
+                        }
+                    }
+                }
+            };
+            // Without headers, the line break ending the delimiter line is also the first of the
+            // pair ending the headers.
+            let headers = if header_end > header_start {
+                &buf[header_start + 2..header_end]
+            } else {
+                &[]
+            };
+            let headers = match std::str::from_utf8(headers) {
+                Ok(s) => s.to_string(),
+                Err(e) => return Err(err!(e,
+                    "Invalid UTF-8 in multipart part headers.";
+                Input, Invalid, Decode, String)),
+            };
+            buf.drain(..header_end + 4);
+            let mut part = res!(FormPart::new(&headers));
+
+            // The part content, up to the next delimiter.
+            pos = loop {
+                match find(&buf, &delim) {
+                    Some(pos) => {
+                        res!(part.write(&buf[..pos], cfg, files).await);
+                        break pos;
+                    }
+                    None => {
+                        let keep = buf.len().min(delim.len() - 1);
+                        let n = buf.len() - keep;
+                        res!(part.write(&buf[..n], cfg, files).await);
+                        buf.drain(..n);
+                        if !res!(Self::read_more::<CHUNK_SIZE, _>(&mut reader, &mut buf).await) {
+                            return Err(err!(
+                                "Multipart form ended within the part '{}'.", part.name;
+                            Input, Missing));
+                        }
+                    }
+                }
+            };
+            let (name, val) = res!(part.finish().await);
+            Self::insert(&mut form, name, val);
+        }
+    }
+
+    /// Append bytes from the source, returning false at the end.
+    async fn read_more<
+        const CHUNK_SIZE: usize,
+        R: AsyncRead + Unpin,
+    >(
+        reader: &mut R,
+        buf:    &mut Vec<u8>,
+    )
+        -> Outcome<bool>
+    {
+        let mut chunk = [0u8; CHUNK_SIZE];
+        match reader.read(&mut chunk).await {
+            Ok(0) => Ok(false),
+            Ok(n) => {
+                buf.extend_from_slice(&chunk[..n]);
+                Ok(true)
+            }
+            Err(e) => Err(err!(e,
+                "While reading multipart form.";
+            IO, Network, Read)),
+        }
+    }
+
+    /// Insert a field value, collecting the values of a repeated field into a list.
+    fn insert(form: &mut DaticleMap, name: String, val: Dat) {
+        let key = dat!(name);
+        match form.remove(&key) {
+            None => {
+                form.insert(key, val);
+            }
+            Some(Dat::List(mut list)) => {
+                list.push(val);
+                form.insert(key, Dat::List(list));
+            }
+            Some(prev) => {
+                form.insert(key, Dat::List(vec![prev, val]));
+            }
+        }
+    }
+}
+
+/// A multipart part being received.
+struct FormPart {
+    name:           String,
+    filename:       Option<String>,
+    content_type:   String,
+    size:           usize,
+    mem:            Vec<u8>,
+    file:           Option<(PathBuf, File)>,
+}
+
+impl FormPart {
+
+    fn new(headers: &str) -> Outcome<Self> {
+        let mut disposition = None;
+        let mut content_type = None;
+        for line in headers.split("\r\n").filter(|l| !l.is_empty()) {
+            let (k, v) = match line.split_once(':') {
+                Some((k, v)) => (k.trim(), v.trim()),
+                None => return Err(err!(
+                    "Invalid multipart part header line '{}'.", line;
+                Input, Invalid)),
+            };
+            if k.eq_ignore_ascii_case("content-disposition") {
+                disposition = Some(v.to_string());
+            } else if k.eq_ignore_ascii_case("content-type") {
+                content_type = Some(v.to_string());
+            }
+        }
+        let disposition = match disposition {
+            Some(d) => d,
+            None => return Err(err!(
+                "Multipart part has no Content-Disposition.";
+            Input, Missing)),
+        };
+        let params = split_params(&disposition);
+        match params.first() {
+            Some((kind, None)) if kind.eq_ignore_ascii_case("form-data") => (),
+            _ => return Err(err!(
+                "Multipart part disposition '{}' is not form-data.", disposition;
+            Input, Invalid)),
+        }
+        let mut name = None;
+        let mut filename = None;
+        for (k, v) in params.into_iter().skip(1) {
+            match (k.to_lowercase().as_str(), v) {
+                ("name", Some(v)) => name = Some(v),
+                ("filename", Some(v)) if filename.is_none() => filename = Some(v),
+                // RFC 5987 extended notation, preferred to the plain filename.
+                ("filename*", Some(v)) => if let Some((_, enc)) = v.split_once("''") {
+                    filename = Some(res!(HttpForm::percent_decode(enc, false)));
+                },
+                _ => (),
+            }
+        }
+        let name = match name {
+            Some(name) => name,
+            None => return Err(err!(
+                "Multipart part disposition '{}' has no name.", disposition;
+            Input, Missing)),
+        };
+        // Only the final component of a path is of any use to the server.
+        let filename = filename.map(|f| match f.rfind(['/', '\\']) {
+            Some(i) => f[i + 1..].to_string(),
+            None => f,
+        });
+        let content_type = content_type.unwrap_or_else(|| match filename {
+            Some(_) => fmt!("application/octet-stream"),
+            None => fmt!("text/plain"),
+        });
+        Ok(Self {
+            name,
+            filename,
+            content_type,
+            size: 0,
+            mem: Vec::new(),
+            file: None,
+        })
+    }
+
+    async fn write(
+        &mut self,
+        byts:   &[u8],
+        cfg:    &HttpFormConfig,
+        files:  &mut Vec<PathBuf>,
+    )
+        -> Outcome<()>
+    {
+        if byts.is_empty() {
+            return Ok(());
+        }
+        self.size += byts.len();
+        if self.filename.is_none() {
+            if self.size > cfg.max_field_size {
+                return Err(err!(
+                    "Form field '{}' exceeds the limit of {} bytes.", self.name, cfg.max_field_size;
+                Input, Size, TooBig));
+            }
+            self.mem.extend_from_slice(byts);
+            return Ok(());
+        }
+        if self.size > cfg.max_upload_size {
+            return Err(err!(
+                "Upload '{}' exceeds the limit of {} bytes.", self.name, cfg.max_upload_size;
+            Input, Size, TooBig));
+        }
+        if self.file.is_none() && self.size > cfg.memory_limit {
+            let path = cfg.upload_dir.join(fmt!("upload_{}",
+                Rand::generate_random_string(16, "abcdefghijklmnopqrstuvwxyz0123456789")));
+            let file = match File::create(&path).await {
+                Ok(file) => file,
+                Err(e) => return Err(err!(e,
+                    "While creating upload file {:?}.", path;
+                IO, File, Write)),
+            };
+            files.push(path.clone());
+            self.file = Some((path, file));
+            let mem = std::mem::take(&mut self.mem);
+            res!(self.write_file(&mem).await);
+        }
+        if self.file.is_some() {
+            self.write_file(byts).await
+        } else {
+            self.mem.extend_from_slice(byts);
+            Ok(())
+        }
+    }
+
+    async fn write_file(&mut self, byts: &[u8]) -> Outcome<()> {
+        if let Some((path, file)) = &mut self.file {
+            if let Err(e) = file.write_all(byts).await {
+                return Err(err!(e,
+                    "While writing upload file {:?}.", path;
+                IO, File, Write));
+            }
+        }
+        Ok(())
+    }
+
+    async fn finish(self) -> Outcome<(String, Dat)> {
+        let val = match self.filename {
+            Some(filename) => {
+                let content = match self.file {
+                    Some((path, mut file)) => {
+                        if let Err(e) = file.flush().await {
+                            return Err(err!(e,
+                                "While flushing upload file {:?}.", path;
+                            IO, File, Write));
+                        }
+                        HttpUploadContent::File(path)
+                    }
+                    None => HttpUploadContent::Memory(self.mem),
+                };
+                HttpUpload {
+                    filename,
+                    content_type:   self.content_type,
+                    size:           self.size,
+                    content,
+                }.to_dat()
+            }
+            None => match String::from_utf8(self.mem) {
+                Ok(s) => Dat::Str(s),
+                Err(e) => Dat::BU64(e.into_bytes()),
+            },
+        };
+        Ok((self.name, val))
+    }
+}
+
+/// Split a header value of the form `a; b=c; d="e;f"` into its parameters, unquoting values.
+fn split_params(s: &str) -> Vec<(String, Option<String>)> {
+    let mut result = Vec::new();
+    let mut chars = s.chars().peekable();
+    loop {
+        let mut key = String::new();
+        while let Some(c) = chars.next_if(|c| *c != ';' && *c != '=') {
+            key.push(c);
+        }
+        let val = match chars.next() {
+            Some('=') => {
+                while chars.next_if(|c| *c == ' ' || *c == '\t').is_some() {}
+                let mut val = String::new();
+                if chars.next_if_eq(&'"').is_some() {
+                    while let Some(c) = chars.next() {
+                        match c {
+                            '"' => break,
+                            '\\' => if let Some(c) = chars.next() {
+                                val.push(c);
+                            },
+                            c => val.push(c),
+                        }
+                    }
+                    while chars.next_if(|c| *c != ';').is_some() {}
+                    chars.next();
+                } else {
+                    while let Some(c) = chars.next_if(|c| *c != ';') {
+                        val.push(c);
+                    }
+                    chars.next();
+                }
+                Some(val.trim_end().to_string())
+            }
+            _ => None,
+        };
+        let key = key.trim().to_string();
+        if !key.is_empty() {
+            result.push((key, val));
+        }
+        if chars.peek().is_none() {
+            break;
+        }
+    }
+    result
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
@@ -9,7 +9,10 @@ use oxedyne_fe2o3_core::prelude::*;
 use oxedyne_fe2o3_iop_crypto::enc::Encrypter;
 use oxedyne_fe2o3_iop_db::api::Database;
 use oxedyne_fe2o3_iop_hash::api::Hasher;
-use oxedyne_fe2o3_jdat::id::NumIdDat;
+use oxedyne_fe2o3_jdat::{
+    id::NumIdDat,
+    map::DaticleMap,
+};
 
 use std::{
     sync::{
@@ -43,7 +46,10 @@ pub trait WebHandler:
         id:         &String, 
     )
         -> impl std::future::Future<Output = Outcome<Option<HttpMessage>>> + Send;
-    
+
+    /// Handle a POST request.  A url encoded or multipart form in the body is parsed into the
+    /// `form` map by `crate::http::form::HttpForm`, otherwise the map is empty.  Uploads written
+    /// to disk are removed once the handler returns, unless it moves them elsewhere.
     fn handle_post<
         const SIDL: usize,
         const UIDL: usize,
@@ -57,6 +63,7 @@ pub trait WebHandler:
         loc:        HttpLocator,
         response:   Option<HttpMessage>,
         body:       Vec<u8>,
+        form:       DaticleMap,
         db:         Option<(Arc<RwLock<DB>>, UID)>,
         sid_opt:    &Option<SID>,
         id:         &String, 
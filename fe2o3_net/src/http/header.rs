@@ -386,4 +386,17 @@ impl HttpHeader {
     pub fn get_the_field_value(&self, nam: &HeaderName) -> Outcome<&HeaderFieldValue> { 
         self.fields.get_the_one(&nam)
     }
+
+    /// Whether the body is sent using chunked transfer encoding, which must be the final coding
+    /// applied.  When present, chunking takes precedence over any `Content-Length`.
+    pub fn is_chunked(&self) -> bool {
+        match self.fields.get_list(&HeaderName::TransferEncoding) {
+            Some(list) => match list.last() {
+                Some(HeaderFieldValue::TransferEncoding(codings)) =>
+                    codings.last().map(|c| c == "chunked").unwrap_or(false),
+                _ => false,
+            },
+            None => false,
+        }
+    }
 }
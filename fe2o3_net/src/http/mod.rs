@@ -1,6 +1,9 @@
+pub mod client;
 pub mod fields;
+pub mod form;
 pub mod handler;
 pub mod header;
 pub mod loc;
 pub mod msg;
+pub mod router;
 pub mod status;
@@ -5,6 +5,7 @@ use crate::{
         fields::{
             ConnectionType,
             Cookie,
+            HeaderField,
             HeaderFields,
             HeaderFieldValue,
             HeaderFieldCategory,
@@ -27,9 +28,15 @@ use crate::{
 use oxedyne_fe2o3_core::prelude::*;
 
 use std::{
+    fmt,
+    num::IntErrorKind,
     str::FromStr,
     future::Future,
     pin::Pin,
+    task::{
+        Context,
+        Poll,
+    },
 };
 
 use tokio::{
@@ -37,14 +44,27 @@ use tokio::{
         AsyncRead,
         AsyncReadExt,
         AsyncWriteExt,
+        ReadBuf,
     },
 };
 
 
+/// An async source of body bytes for a response of unknown length, sent using chunked transfer
+/// encoding.
+pub struct HttpBodyStream(pub Box<dyn AsyncReadIterator<Item = Outcome<Vec<u8>>> + Send>);
+
+impl fmt::Debug for HttpBodyStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HttpBodyStream")
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct HttpMessage {
     pub header:     HttpHeader,
     pub body:       Vec<u8>,
+    pub trailers:   HeaderFields, // Fields following a chunked body.
+    pub stream:     Option<HttpBodyStream>, // Body bytes following those in `body`.
 }
 
 impl HttpMessage {
@@ -56,7 +76,7 @@ impl HttpMessage {
                 headline:   HttpHeadline::Response { status },
                 fields:     HeaderFields::default(),
             },
-            body: Vec::new(),
+            ..Default::default()
         }
     }
 
@@ -74,6 +94,7 @@ impl HttpMessage {
                 fields,
             },
             body: txt.as_ref().as_bytes().to_vec(),
+            ..Default::default()
         }
     }
 
@@ -225,10 +246,31 @@ impl HttpMessage {
         const HEADER_CHUNK_SIZE: usize,
         const BODY_CHUNK_SIZE: usize,
         R: AsyncRead + Unpin,
+    >(
+        stream:     Pin<&mut R>,
+        remnant:    &Vec<u8>,
+        is_request: Option<bool>,
+    )
+        -> Outcome<(Option<Self>, Vec<u8>)>
+    {
+        Self::read_limited::<HEADER_CHUNK_SIZE, BODY_CHUNK_SIZE, _>(
+            stream,
+            remnant,
+            is_request,
+            constant::HTTP_BODY_SIZE_MAX,
+        ).await
+    }
+
+    /// As for `read`, rejecting a body larger than `body_max` bytes before it is read.
+    pub async fn read_limited<
+        const HEADER_CHUNK_SIZE: usize,
+        const BODY_CHUNK_SIZE: usize,
+        R: AsyncRead + Unpin,
     >(
         mut stream: Pin<&mut R>,
         remnant:    &Vec<u8>,
         is_request: Option<bool>,
+        body_max:   usize,
     )
         -> Outcome<(Option<Self>, Vec<u8>)>
     {
@@ -240,49 +282,293 @@ impl HttpMessage {
         ).await;
     
         match result {
-            Ok(Some((header, mut remnant, content_length))) => {
+            Ok(Some((header, remnant, content_length))) => {
                 trace!("remnant size = {}, content_length = {}", remnant.len(), content_length);
                 let mut msg = HttpMessage::default();
                 msg.header = header;
+                Self::read_body::<BODY_CHUNK_SIZE, _>(
+                    stream,
+                    msg,
+                    remnant,
+                    content_length,
+                    body_max,
+                ).await
+            }
+            Ok(None) => Ok((None, Vec::new())),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Read a response to a request with the given method, rejecting a body larger than
+    /// `body_max` bytes.  Responses to HEAD requests, and 1xx, 204 and 304 responses, have no
+    /// body whatever their fields say (RFC 9112 section 6.3), so none is read.
+    pub async fn read_response<
+        const HEADER_CHUNK_SIZE: usize,
+        const BODY_CHUNK_SIZE: usize,
+        R: AsyncRead + Unpin,
+    >(
+        mut stream: Pin<&mut R>,
+        remnant:    &Vec<u8>,
+        method:     HttpMethod,
+        body_max:   usize,
+    )
+        -> Outcome<(Option<Self>, Vec<u8>)>
+    {
+        let result = HttpHeader::read::<HEADER_CHUNK_SIZE, _>(
+            stream.as_mut(),
+            remnant,
+            Some(false),
+        ).await;
+        let (header, remnant, content_length) = match res!(result) {
+            Some(parts) => parts,
+            None => return Ok((None, Vec::new())),
+        };
+        let msg = HttpMessage {
+            header,
+            ..Default::default()
+        };
+        if !msg.response_has_body(method) {
+            return Ok((Some(msg), remnant));
+        }
+        Self::read_body::<BODY_CHUNK_SIZE, _>(
+            stream,
+            msg,
+            remnant,
+            content_length,
+            body_max,
+        ).await
+    }
+
+    /// Whether this response to a request with the given method can have a body.
+    pub fn response_has_body(&self, method: HttpMethod) -> bool {
+        match self.header.headline {
+            HttpHeadline::Response { status } => {
+                let code = status as u16;
+                method != HttpMethod::HEAD && code >= 200 && code != 204 && code != 304
+            }
+            _ => false,
+        }
+    }
+
+    /// Read the body of the message with the given header, starting with the bytes already read
+    /// beyond the header.
+    async fn read_body<
+        const BODY_CHUNK_SIZE: usize,
+        R: AsyncRead + Unpin,
+    >(
+        mut stream:     Pin<&mut R>,
+        mut msg:        Self,
+        mut remnant:    Vec<u8>,
+        content_length: usize,
+        body_max:       usize,
+    )
+        -> Outcome<(Option<Self>, Vec<u8>)>
+    {
+        if msg.header.is_chunked() {
+            let result = Self::read_chunked::<BODY_CHUNK_SIZE, _>(
+                stream.as_mut(),
+                remnant,
+                body_max,
+            ).await;
+            return match res!(result) {
+                Some((body, trailers, remnant)) => {
+                    msg.body = body;
+                    msg.trailers = trailers;
+                    Ok((Some(msg), remnant))
+                }
+                None => Ok((None, Vec::new())),
+            };
+        }
+
+        if content_length > body_max {
+            return Err(err!(
+                "HTTP body of {} bytes exceeds the limit of {} bytes.", content_length, body_max;
+            IO, Network, Input, TooBig));
+        }
     
-                if content_length > 0 {
-                    let mut body = Vec::with_capacity(content_length);
-                    body.extend_from_slice(&remnant);
-                    let mut bytes_read = body.len();
+        if content_length > 0 {
+            let mut body = Vec::with_capacity(content_length);
+            body.extend_from_slice(&remnant);
+            let mut bytes_read = body.len();
     
-                    while bytes_read < content_length {
-                        let mut chunk = [0; BODY_CHUNK_SIZE];
-                        let result = stream.as_mut().read(&mut chunk).await;
-                        match result {
-                            Ok(0) => {
-                                warn!("UnexpectedEof treated as connection closure.");
-                                return Ok((None, body.to_vec()));
-                            }
-                            Ok(n) => {
-                                body.extend_from_slice(&chunk[..n]);
-                                bytes_read += n;
-                            }
-                            Err(e) => return Err(e.into()),
-                        }
+            while bytes_read < content_length {
+                let mut chunk = [0; BODY_CHUNK_SIZE];
+                let result = stream.as_mut().read(&mut chunk).await;
+                match result {
+                    Ok(0) => {
+                        warn!("UnexpectedEof treated as connection closure.");
+                        return Ok((None, body.to_vec()));
+                    }
+                    Ok(n) => {
+                        body.extend_from_slice(&chunk[..n]);
+                        bytes_read += n;
                     }
+                    Err(e) => return Err(e.into()),
+                }
+            }
     
-                    remnant = if bytes_read > content_length {
-                        body[content_length..].to_vec()
-                    } else {
-                        Vec::new()
-                    };
+            remnant = if bytes_read > content_length {
+                body[content_length..].to_vec()
+            } else {
+                Vec::new()
+            };
     
-                    msg.body = body[..content_length].to_vec();
-                    Ok((Some(msg), remnant))
-                } else {
-                    Ok((Some(msg), remnant))
+            msg.body = body[..content_length].to_vec();
+            Ok((Some(msg), remnant))
+        } else {
+            Ok((Some(msg), remnant))
+        }
+    }
+
+    /// Decode a chunked body, starting with the bytes already read beyond the header.  Returns the
+    /// body, the trailer fields and any bytes read beyond the end of the message, or `None` if the
+    /// connection closes before the message is complete.  Chunk extensions are ignored.  A chunk
+    /// that would take the body beyond `body_max` bytes is rejected before it is read.
+    async fn read_chunked<
+        const BODY_CHUNK_SIZE: usize,
+        R: AsyncRead + Unpin,
+    >(
+        mut stream: Pin<&mut R>,
+        mut buf:    Vec<u8>,
+        body_max:   usize,
+    )
+        -> Outcome<Option<(Vec<u8>, HeaderFields, Vec<u8>)>>
+    {
+        let mut body = Vec::new();
+        loop {
+            let result = Self::read_line::<BODY_CHUNK_SIZE, _>(stream.as_mut(), &mut buf).await;
+            let line = match res!(result) {
+                Some(line) => line,
+                None => return Ok(None),
+            };
+            let size_str = match line.split_once(';') {
+                Some((size_str, _ext)) => size_str.trim(),
+                None => line.trim(),
+            };
+            let size = match usize::from_str_radix(size_str, 16) {
+                Ok(n) => n,
+                Err(e) if *e.kind() == IntErrorKind::PosOverflow => return Err(err!(e,
+                    "HTTP chunk size '{}' overflows.", size_str;
+                IO, Network, Invalid, Input, Overflow)),
+                Err(e) => return Err(err!(e,
+                    "Invalid HTTP chunk size '{}'.", size_str;
+                IO, Network, Invalid, Input, Decode)),
+            };
+            if size == 0 {
+                break;
+            }
+            if size > body_max.saturating_sub(body.len()) {
+                return Err(err!(
+                    "HTTP chunk of {} bytes would take the body of {} bytes beyond the limit \
+                    of {} bytes.", size, body.len(), body_max;
+                IO, Network, Input, TooBig));
+            }
+            let end = match size.checked_add(2) {
+                Some(end) => end,
+                None => return Err(err!(
+                    "HTTP chunk size {} is too large.", size;
+                IO, Network, Invalid, Input, Overflow)),
+            };
+            while buf.len() < end {
+                if !res!(Self::read_more::<BODY_CHUNK_SIZE, _>(stream.as_mut(), &mut buf).await) {
+                    warn!("UnexpectedEof treated as connection closure.");
+                    return Ok(None);
                 }
             }
-            Ok(None) => Ok((None, Vec::new())),
-            Err(e) => Err(e),
+            if &buf[size..end] != b"\r\n" {
+                return Err(err!(
+                    "HTTP chunk of {} bytes is not terminated by CRLF.", size;
+                IO, Network, Invalid, Input, Decode));
+            }
+            body.extend_from_slice(&buf[..size]);
+            buf.drain(..end);
+        }
+
+        // The trailer section ends with an empty line.
+        let mut trailers = HeaderFields::default();
+        let mut i: u16 = 1;
+        loop {
+            let result = Self::read_line::<BODY_CHUNK_SIZE, _>(stream.as_mut(), &mut buf).await;
+            let line = match res!(result) {
+                Some(line) => line,
+                None => return Ok(None),
+            };
+            if line.is_empty() {
+                break;
+            }
+            if i > constant::HTTP_CHUNK_TRAILERS_MAX {
+                return Err(err!(
+                    "Number of HTTP trailer fields exceeds limit of {}.",
+                    constant::HTTP_CHUNK_TRAILERS_MAX;
+                IO, Network, Invalid, Input));
+            }
+            let hf = res!(HeaderField::new(&line, Some(i)));
+            trailers.insert(hf.name, hf.value, Some(i));
+            i += 1;
+        }
+
+        Ok(Some((body, trailers, buf)))
+    }
+
+    /// Remove and return the next CRLF terminated line from the buffer, reading more from the
+    /// stream as necessary.  Returns `None` if the stream ends first.
+    async fn read_line<
+        const BODY_CHUNK_SIZE: usize,
+        R: AsyncRead + Unpin,
+    >(
+        mut stream: Pin<&mut R>,
+        buf:        &mut Vec<u8>,
+    )
+        -> Outcome<Option<String>>
+    {
+        loop {
+            if let Some(pos) = buf.windows(2).position(|w| w == b"\r\n") {
+                let line = match std::str::from_utf8(&buf[..pos]) {
+                    Ok(s) => s.to_string(),
+                    Err(e) => return Err(err!(e,
+                        "Invalid UTF-8 sequence in HTTP chunk line.";
+                    IO, Network, Invalid, Input)),
+                };
+                buf.drain(..pos + 2);
+                return Ok(Some(line));
+            }
+            if buf.len() > constant::HTTP_CHUNK_LINE_MAX {
+                return Err(err!(
+                    "HTTP chunk line exceeds the limit of {} bytes.",
+                    constant::HTTP_CHUNK_LINE_MAX;
+                IO, Network, Invalid, Input, TooBig));
+            }
+            if !res!(Self::read_more::<BODY_CHUNK_SIZE, _>(stream.as_mut(), buf).await) {
+                warn!("UnexpectedEof treated as connection closure.");
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Append the next read from the stream to the buffer, returning false at the end of the
+    /// stream.
+    async fn read_more<
+        const BODY_CHUNK_SIZE: usize,
+        R: AsyncRead + Unpin,
+    >(
+        mut stream: Pin<&mut R>,
+        buf:        &mut Vec<u8>,
+    )
+        -> Outcome<bool>
+    {
+        let mut chunk = [0; BODY_CHUNK_SIZE];
+        match stream.as_mut().read(&mut chunk).await {
+            Ok(0) => Ok(false),
+            Ok(n) => {
+                buf.extend_from_slice(&chunk[..n]);
+                Ok(true)
+            }
+            Err(e) => Err(e.into()),
         }
     }
 
+    /// Write the message.  A message with a body stream is sent using chunked transfer encoding,
+    /// otherwise the `Content-Length` is set from the body.
     pub async fn write_all<
         R: AsyncWriteExt + Unpin,
     >(
@@ -291,6 +577,9 @@ impl HttpMessage {
     )
         -> Outcome<()>
     {
+        if let Some(HttpBodyStream(mut source)) = self.stream.take() {
+            return self.write_chunked(stream, source.as_mut()).await;
+        }
         let _ = self.insert(
             HeaderName::ContentLength,
             HeaderFieldValue::ContentLength(self.body.len()),
@@ -306,6 +595,104 @@ impl HttpMessage {
         Ok(())
     }
 
+    /// Write the message using chunked transfer encoding, sending any bytes already in the body
+    /// as the first chunk, then each piece yielded by the source as it arrives, followed by the
+    /// trailer fields.  The `Content-Length` field is removed.  An error from the source aborts
+    /// the message, leaving the connection unusable.
+    pub async fn write_chunked<
+        R: AsyncWriteExt + Unpin,
+        S: AsyncReadIterator<Item = Outcome<Vec<u8>>> + ?Sized,
+    >(
+        mut self,
+        stream: &mut R,
+        source: &mut S,
+    )
+        -> Outcome<()>
+    {
+        let _ = self.header.fields.remove(&HeaderName::ContentLength);
+        let _ = self.header.fields.remove(&HeaderName::TransferEncoding);
+        let _ = self.insert(
+            HeaderName::TransferEncoding,
+            HeaderFieldValue::TransferEncoding(vec![fmt!("chunked")]),
+            Some(HeaderFieldCategory::General as u16),
+        );
+        if !self.trailers.is_empty() {
+            let names = self.trailers.iter()
+                .map(|(k, _)| k.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            let _ = self.header.fields.remove(&HeaderName::Trailer);
+            let _ = self.insert(
+                HeaderName::Trailer,
+                HeaderFieldValue::Generic(names),
+                Some(HeaderFieldCategory::General as u16),
+            );
+        }
+        self.log(log_get_level!());
+        res!(stream.write_all(&self.header.as_vec()).await);
+        if !self.body.is_empty() {
+            res!(Self::write_chunk(stream, &self.body).await);
+        }
+        while let Some(result) = source.next().await {
+            let byts = res!(result);
+            if !byts.is_empty() { // An empty chunk would end the body.
+                res!(Self::write_chunk(stream, &byts).await);
+            }
+        }
+        let mut last = b"0\r\n".to_vec();
+        for (k, header_field_values) in self.trailers.iter() {
+            for header_field_value in header_field_values {
+                last.extend_from_slice(fmt!("{}: {}\r\n", k, header_field_value).as_bytes());
+            }
+        }
+        last.extend_from_slice(b"\r\n");
+        res!(stream.write_all(&last).await);
+        res!(stream.flush().await);
+        Ok(())
+    }
+
+    /// Write and flush a single chunk of a chunked body.
+    async fn write_chunk<
+        R: AsyncWriteExt + Unpin,
+    >(
+        stream: &mut R,
+        byts:   &[u8],
+    )
+        -> Outcome<()>
+    {
+        res!(stream.write_all(fmt!("{:x}\r\n", byts.len()).as_bytes()).await);
+        res!(stream.write_all(byts).await);
+        res!(stream.write_all(b"\r\n").await);
+        res!(stream.flush().await);
+        Ok(())
+    }
+
+    /// Send the body from the given source after any bytes already in the body, using chunked
+    /// transfer encoding.
+    pub fn with_stream<
+        S: AsyncReadIterator<Item = Outcome<Vec<u8>>> + Send + 'static,
+    >(
+        mut self,
+        source: S,
+    )
+        -> Self
+    {
+        self.stream = Some(HttpBodyStream(Box::new(source)));
+        self
+    }
+
+    /// Add a trailer field, sent after a chunked body.
+    pub fn with_trailer(
+        mut self,
+        nam: HeaderName,
+        val: HeaderFieldValue,
+    )
+        -> Self
+    {
+        self.trailers.insert(nam, val, None);
+        self
+    }
+
     pub fn body_text(&mut self, txt: &str) {
         self.body = txt.as_bytes().to_vec()
     }
@@ -452,14 +839,22 @@ impl HttpMessage {
     }
 }
 
+/// Reads successive messages from a stream, rejecting any with a body larger than `body_max`
+/// bytes before the body is read.  Messages whose header satisfies the `stream_if` predicate,
+/// such as a multipart form upload, are returned without their `Content-Length` body, which can
+/// then be read incrementally via `body`.  Any part of it left unread is discarded before the
+/// next message.  A chunked body is always read in full.
 pub struct HttpMessageReader<
     'a,
     const HEADER_CHUNK_SIZE: usize,
     const BODY_CHUNK_SIZE: usize,
     R: AsyncRead + Unpin + Send,
 > {
-    stream: Pin<&'a mut R>,
-    buffer: Vec<u8>,
+    stream:     Pin<&'a mut R>,
+    buffer:     Vec<u8>,
+    body_max:   usize,
+    stream_if:  Option<fn(&HttpHeader) -> bool>,
+    unread:     usize, // Remaining bytes of a streamed body.
 }
 
 impl<
@@ -477,7 +872,91 @@ impl<
     {
         Self {
             stream,
-            buffer: Vec::new(),
+            buffer:     Vec::new(),
+            body_max:   constant::HTTP_BODY_SIZE_MAX,
+            stream_if:  None,
+            unread:     0,
+        }
+    }
+
+    pub fn with_body_max(mut self, body_max: usize) -> Self {
+        self.body_max = body_max;
+        self
+    }
+
+    /// Leave the `Content-Length` body of messages with a header satisfying the predicate to be
+    /// read via `body`.
+    pub fn with_streamed_body_if(mut self, stream_if: fn(&HttpHeader) -> bool) -> Self {
+        self.stream_if = Some(stream_if);
+        self
+    }
+
+    /// The unread body of the last message returned, which ends after its `Content-Length`.
+    pub fn body(&mut self) -> HttpBodyReader<'_, 'a, HEADER_CHUNK_SIZE, BODY_CHUNK_SIZE, R> {
+        HttpBodyReader { reader: self }
+    }
+
+    /// Discard any unread part of a streamed body, returning false if the stream ends first.
+    async fn skip_unread(&mut self) -> Outcome<bool> {
+        let n = std::cmp::min(self.unread, self.buffer.len());
+        self.buffer.drain(..n);
+        self.unread -= n;
+        let mut chunk = [0; BODY_CHUNK_SIZE];
+        while self.unread > 0 {
+            let len = std::cmp::min(self.unread, BODY_CHUNK_SIZE);
+            match self.stream.as_mut().read(&mut chunk[..len]).await {
+                Ok(0) => return Ok(false),
+                Ok(n) => self.unread -= n,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(true)
+    }
+
+    async fn read_next(&mut self) -> Outcome<Option<HttpMessage>> {
+        if !res!(self.skip_unread().await) {
+            return Ok(None);
+        }
+        let result = HttpHeader::read::<HEADER_CHUNK_SIZE, _>(
+            self.stream.as_mut(),
+            &self.buffer,
+            None,
+        ).await;
+        let (header, remnant, content_length) = match res!(result) {
+            Some(parts) => parts,
+            None => return Ok(None),
+        };
+        let mut msg = HttpMessage::default();
+        msg.header = header;
+        let streamed = match self.stream_if {
+            Some(stream_if) => !msg.header.is_chunked() && stream_if(&msg.header),
+            None => false,
+        };
+        if streamed {
+            if content_length > self.body_max {
+                return Err(err!(
+                    "HTTP body of {} bytes exceeds the limit of {} bytes.",
+                    content_length, self.body_max;
+                IO, Network, Input, TooBig));
+            }
+            self.buffer = remnant;
+            self.unread = content_length;
+            return Ok(Some(msg));
+        }
+        let result = HttpMessage::read_body::<BODY_CHUNK_SIZE, _>(
+            self.stream.as_mut(),
+            msg,
+            remnant,
+            content_length,
+            self.body_max,
+        ).await;
+        match res!(result) {
+            (Some(msg), remnant) => {
+                self.buffer = remnant;
+                trace!("Remnant = {} bytes", self.buffer.len());
+                Ok(Some(msg))
+            }
+            (None, _) => Ok(None),
         }
     }
 }
@@ -493,26 +972,66 @@ impl<
     type Item = Outcome<HttpMessage>;
 
     fn next<'b>(&'b mut self) -> Pin<Box<dyn Future<Output = Option<Self::Item>> + Send + 'b>> {
-        let mut stream = self.stream.as_mut();
-        let buffer = &mut self.buffer;
-
         Box::pin(async move {
-            let result = HttpMessage::read::<HEADER_CHUNK_SIZE, BODY_CHUNK_SIZE, _>(
-                stream.as_mut(),
-                buffer,
-                None,
-            )
-            .await;
-
-            match result {
-                Ok((Some(message), remnant)) => {
-                    *buffer = remnant;
-                    trace!("Remnant = {} bytes", buffer.len());
-                    Some(Ok(message))
-                }
-                Ok((None, _)) => None,
+            match self.read_next().await {
+                Ok(Some(message)) => Some(Ok(message)),
+                Ok(None) => None,
                 Err(e) => Some(Err(e)),
             }
         })
     }
 }
+
+/// The streamed body of a message, read from an `HttpMessageReader`.
+pub struct HttpBodyReader<
+    'r,
+    'a,
+    const HEADER_CHUNK_SIZE: usize,
+    const BODY_CHUNK_SIZE: usize,
+    R: AsyncRead + Unpin + Send,
+> {
+    reader: &'r mut HttpMessageReader<'a, HEADER_CHUNK_SIZE, BODY_CHUNK_SIZE, R>,
+}
+
+impl<
+    'r,
+    'a,
+    const HEADER_CHUNK_SIZE: usize,
+    const BODY_CHUNK_SIZE: usize,
+    R: AsyncRead + Unpin + Send,
+>
+    AsyncRead for HttpBodyReader<'r, 'a, HEADER_CHUNK_SIZE, BODY_CHUNK_SIZE, R>
+{
+    fn poll_read(
+        self:   Pin<&mut Self>,
+        cx:     &mut Context<'_>,
+        buf:    &mut ReadBuf<'_>,
+    )
+        -> Poll<std::io::Result<()>>
+    {
+        let reader = &mut *self.get_mut().reader;
+        let max = std::cmp::min(reader.unread, buf.remaining());
+        if max == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        if !reader.buffer.is_empty() {
+            let n = std::cmp::min(max, reader.buffer.len());
+            buf.put_slice(&reader.buffer[..n]);
+            reader.buffer.drain(..n);
+            reader.unread -= n;
+            return Poll::Ready(Ok(()));
+        }
+        let mut chunk = [0; BODY_CHUNK_SIZE];
+        let len = std::cmp::min(max, BODY_CHUNK_SIZE);
+        let mut chunk_buf = ReadBuf::new(&mut chunk[..len]);
+        match reader.stream.as_mut().poll_read(cx, &mut chunk_buf) {
+            Poll::Ready(Ok(())) => {
+                let n = chunk_buf.filled().len();
+                buf.put_slice(chunk_buf.filled());
+                reader.unread -= n;
+                Poll::Ready(Ok(()))
+            }
+            other => other,
+        }
+    }
+}
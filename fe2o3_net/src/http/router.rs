@@ -0,0 +1,877 @@
+//! Dispatch of HTTP requests to handlers by method and path pattern, through a chain of
+//! middleware.
+//!
+//! Patterns are matched segment by segment, ignoring empty segments so that a trailing slash
+//! makes no difference.  A segment `:name` matches any single segment, and `*name` (or just `*`)
+//! as the last segment matches the rest of the path, which may be empty.  Matched values are
+//! percent decoded and inserted into `HttpLocator::data` as strings, replacing any query parameter
+//! of the same name, an unnamed wildcard being stored under `*`.  Where more than one pattern
+//! matches a path, literal segments win over parameters and parameters over wildcards, so the
+//! order in which routes are added does not matter.
+//!
+//! Middleware runs in the order it was added, the first being outermost, after the route has been
+//! chosen and its parameters extracted.  A path matching no route reaches the end of the chain
+//! as a 404 Not Found, and one matching only routes for other methods as a 405 Method Not
+//! Allowed, so that middleware such as `HttpCors` can still answer it.
+//!
+//! ```ignore
+//! let mut router = HttpRouter::new();
+//! res!(router.get("/users/:id", |req: HttpRequest| async move {
+//!     Ok(HttpMessage::ok_respond_with_text(fmt!("User {:?}", req.param("id"))))
+//! }));
+//! router.add_middleware(HttpLogger::new(LogLevel::Info));
+//! router.add_middleware(HttpCompression::default());
+//! let response = res!(router.handle(res!(HttpRequest::from_message(request))).await);
+//! ```
+use crate::{
+    constant,
+    http::{
+        fields::{
+            HeaderFields,
+            HeaderFieldValue,
+            HeaderName,
+        },
+        form::HttpForm,
+        header::{
+            HttpHeadline,
+            HttpMethod,
+        },
+        loc::HttpLocator,
+        msg::HttpMessage,
+        status::HttpStatus,
+    },
+};
+
+use oxedyne_fe2o3_core::prelude::*;
+use oxedyne_fe2o3_jdat::prelude::*;
+
+use std::{
+    fmt,
+    future::Future,
+    io::Write,
+    pin::Pin,
+    sync::Arc,
+    time::Instant,
+};
+
+use flate2::{
+    write::{
+        DeflateEncoder,
+        GzEncoder,
+    },
+    Compression,
+};
+
+
+/// The future returned by route handlers and middleware.
+pub type HttpFuture<'a> = Pin<Box<dyn Future<Output = Outcome<HttpMessage>> + Send + 'a>>;
+
+/// A request as seen by middleware and route handlers.
+#[derive(Clone, Debug)]
+pub struct HttpRequest {
+    pub method: HttpMethod,
+    /// Holds the query and path parameters in `data`.
+    pub loc:    HttpLocator,
+    pub fields: HeaderFields,
+    pub body:   Vec<u8>,
+    /// A form parsed by `crate::http::form::HttpForm`, if the caller has done so.
+    pub form:   DaticleMap,
+    /// The pattern of the matched route.
+    pub route:  Option<String>,
+    /// Identifies the connection in log messages.
+    pub id:     String,
+}
+
+impl HttpRequest {
+
+    pub fn new(method: HttpMethod, loc: HttpLocator) -> Self {
+        Self {
+            method,
+            loc,
+            fields: HeaderFields::default(),
+            body:   Vec::new(),
+            form:   DaticleMap::new(),
+            route:  None,
+            id:     String::new(),
+        }
+    }
+
+    /// Take the method, locator, header fields and body from a request message.
+    pub fn from_message(msg: HttpMessage) -> Outcome<Self> {
+        match msg.header.headline {
+            HttpHeadline::Request { method, loc } => Ok(Self {
+                fields: msg.header.fields,
+                body:   msg.body,
+                ..Self::new(method, loc)
+            }),
+            HttpHeadline::Response { status } => Err(err!(
+                "Expected an HTTP request, found a {} response.", status;
+            Invalid, Input, Mismatch)),
+        }
+    }
+
+    pub fn with_fields(mut self, fields: HeaderFields) -> Self {
+        self.fields = fields;
+        self
+    }
+
+    pub fn with_body(mut self, body: Vec<u8>) -> Self {
+        self.body = body;
+        self
+    }
+
+    pub fn with_form(mut self, form: DaticleMap) -> Self {
+        self.form = form;
+        self
+    }
+
+    pub fn with_id<S: Into<String>>(mut self, id: S) -> Self {
+        self.id = id.into();
+        self
+    }
+
+    /// The value of a path parameter, or of a query parameter given as a string.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        match self.loc.data.get(&dat!(name)) {
+            Some(Dat::Str(s)) => Some(s.as_str()),
+            _ => None,
+        }
+    }
+
+    /// The first value of the given header field, as text.
+    pub fn field(&self, name: &HeaderName) -> Option<String> {
+        self.fields.get_one(name).map(|value| value.to_string())
+    }
+}
+
+/// Produces the response for a route.  Implemented for async closures taking an `HttpRequest`.
+pub trait HttpRouteHandler: Send + Sync {
+    fn handle(&self, req: HttpRequest) -> HttpFuture<'_>;
+}
+
+impl<
+    F:      Fn(HttpRequest) -> FUT + Send + Sync,
+    FUT:    Future<Output = Outcome<HttpMessage>> + Send + 'static,
+>
+    HttpRouteHandler for F
+{
+    fn handle(&self, req: HttpRequest) -> HttpFuture<'_> {
+        Box::pin(self(req))
+    }
+}
+
+/// Wraps the handling of a request.  An implementation can alter the request before passing it
+/// to `next`, respond without calling `next` at all, or alter the response that `next` returns.
+pub trait HttpMiddleware: Send + Sync {
+    fn handle<'a>(&'a self, req: HttpRequest, next: HttpNext<'a>) -> HttpFuture<'a>;
+}
+
+#[derive(Clone, Copy)]
+enum HttpEndpoint<'a> {
+    Route(&'a dyn HttpRouteHandler),
+    NotFound,
+    MethodNotAllowed(&'a [HttpMethod]),
+}
+
+/// The remainder of the middleware chain, ending with the route handler.
+pub struct HttpNext<'a> {
+    chain:      &'a [Arc<dyn HttpMiddleware>],
+    endpoint:   HttpEndpoint<'a>,
+}
+
+impl<'a> HttpNext<'a> {
+
+    pub fn run(self, req: HttpRequest) -> HttpFuture<'a> {
+        match self.chain.split_first() {
+            Some((first, rest)) => first.handle(req, HttpNext {
+                chain:      rest,
+                endpoint:   self.endpoint,
+            }),
+            None => match self.endpoint {
+                HttpEndpoint::Route(handler) => handler.handle(req),
+                HttpEndpoint::NotFound => Box::pin(async {
+                    Ok(HttpMessage::respond_with_text(HttpStatus::NotFound, "Not found."))
+                }),
+                HttpEndpoint::MethodNotAllowed(allowed) => {
+                    let allow = allowed.iter()
+                        .map(|method| method.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    Box::pin(async move {
+                        Ok(HttpMessage::respond_with_text(
+                            HttpStatus::MethodNotAllowed,
+                            "Method not allowed.",
+                        ).with_field(HeaderName::Allow, HeaderFieldValue::Generic(allow)))
+                    })
+                },
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum RouteSegment {
+    Literal(String),
+    Param(String),
+    Wildcard(String),
+}
+
+impl RouteSegment {
+
+    /// Lower ranks take precedence.
+    fn rank(&self) -> u8 {
+        match self {
+            Self::Literal(_)    => 0,
+            Self::Param(_)      => 1,
+            Self::Wildcard(_)   => 2,
+        }
+    }
+}
+
+/// The names and values of the parameters captured by a route.
+type RouteParams = Vec<(String, String)>;
+
+#[derive(Clone)]
+struct HttpRoute {
+    method:     HttpMethod,
+    pattern:    String,
+    segments:   Vec<RouteSegment>,
+    handler:    Arc<dyn HttpRouteHandler>,
+}
+
+impl HttpRoute {
+
+    /// The captured parameters and the precedence of the match, if the path matches.
+    fn matches(&self, path: &[String]) -> Option<(RouteParams, Vec<u8>)> {
+        let mut params = Vec::new();
+        let mut ranks = Vec::with_capacity(self.segments.len());
+        for (i, segment) in self.segments.iter().enumerate() {
+            ranks.push(segment.rank());
+            match segment {
+                RouteSegment::Wildcard(name) => {
+                    params.push((name.clone(), path[i.min(path.len())..].join("/")));
+                    return Some((params, ranks));
+                },
+                RouteSegment::Literal(literal) => match path.get(i) {
+                    Some(s) if s == literal => (),
+                    _ => return None,
+                },
+                RouteSegment::Param(name) => match path.get(i) {
+                    Some(s) => params.push((name.clone(), s.clone())),
+                    None => return None,
+                },
+            }
+        }
+        if path.len() == self.segments.len() {
+            Some((params, ranks))
+        } else {
+            None
+        }
+    }
+}
+
+/// Maps methods and path patterns to handlers.  Clones share the handlers and middleware.
+#[derive(Clone, Default)]
+pub struct HttpRouter {
+    routes:     Vec<HttpRoute>,
+    middleware: Vec<Arc<dyn HttpMiddleware>>,
+}
+
+impl fmt::Debug for HttpRouter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HttpRouter")
+            .field("routes", &self.routes.iter()
+                .map(|route| fmt!("{} {}", route.method, route.pattern))
+                .collect::<Vec<_>>())
+            .field("middleware", &self.middleware.len())
+            .finish()
+    }
+}
+
+impl HttpRouter {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a route, failing if the pattern is invalid or already has a handler for the method.
+    pub fn add_route<H: HttpRouteHandler + 'static>(
+        &mut self,
+        method:     HttpMethod,
+        pattern:    &str,
+        handler:    H,
+    )
+        -> Outcome<()>
+    {
+        let segments = res!(Self::parse_pattern(pattern));
+        for route in &self.routes {
+            if route.method == method && route.segments.len() == segments.len()
+                && route.segments.iter().zip(segments.iter()).all(|(a, b)| match (a, b) {
+                    (RouteSegment::Literal(a), RouteSegment::Literal(b)) => a == b,
+                    _ => a.rank() == b.rank(),
+                })
+            {
+                return Err(err!(
+                    "The {} route '{}' conflicts with the existing route '{}'.",
+                    method, pattern, route.pattern;
+                Configuration, Invalid, Input));
+            }
+        }
+        self.routes.push(HttpRoute {
+            method,
+            pattern: pattern.to_string(),
+            segments,
+            handler: Arc::new(handler),
+        });
+        Ok(())
+    }
+
+    pub fn get<H: HttpRouteHandler + 'static>(&mut self, pattern: &str, handler: H) -> Outcome<()> {
+        self.add_route(HttpMethod::GET, pattern, handler)
+    }
+
+    pub fn post<H: HttpRouteHandler + 'static>(&mut self, pattern: &str, handler: H) -> Outcome<()> {
+        self.add_route(HttpMethod::POST, pattern, handler)
+    }
+
+    pub fn put<H: HttpRouteHandler + 'static>(&mut self, pattern: &str, handler: H) -> Outcome<()> {
+        self.add_route(HttpMethod::PUT, pattern, handler)
+    }
+
+    pub fn patch<H: HttpRouteHandler + 'static>(&mut self, pattern: &str, handler: H) -> Outcome<()> {
+        self.add_route(HttpMethod::PATCH, pattern, handler)
+    }
+
+    pub fn delete<H: HttpRouteHandler + 'static>(&mut self, pattern: &str, handler: H) -> Outcome<()> {
+        self.add_route(HttpMethod::DELETE, pattern, handler)
+    }
+
+    /// Wrap every request in the given middleware, inside any added before it.
+    pub fn add_middleware<M: HttpMiddleware + 'static>(&mut self, middleware: M) {
+        self.middleware.push(Arc::new(middleware));
+    }
+
+    /// Route the request through the middleware to its handler.
+    pub async fn handle(&self, mut req: HttpRequest) -> Outcome<HttpMessage> {
+        let path = match Self::split_path(req.loc.path.as_str()) {
+            Ok(path) => path,
+            Err(e) => {
+                debug!("{}: {}", req.id, e);
+                return Ok(HttpMessage::respond_with_text(HttpStatus::BadRequest, "Invalid path."));
+            },
+        };
+        let mut best: Option<(&HttpRoute, RouteParams, Vec<u8>)> = None;
+        let mut allowed = Vec::new();
+        for route in &self.routes {
+            if let Some((params, ranks)) = route.matches(&path) {
+                if route.method != req.method {
+                    if !allowed.contains(&route.method) {
+                        allowed.push(route.method);
+                    }
+                    continue;
+                }
+                let better = match &best {
+                    Some((_, _, best_ranks)) => ranks < *best_ranks,
+                    None => true,
+                };
+                if better {
+                    best = Some((route, params, ranks));
+                }
+            }
+        }
+        let endpoint = match best {
+            Some((route, params, _)) => {
+                for (name, value) in params {
+                    req.loc.data.insert(dat!(name), dat!(value));
+                }
+                req.route = Some(route.pattern.clone());
+                HttpEndpoint::Route(route.handler.as_ref())
+            },
+            None if allowed.is_empty() => HttpEndpoint::NotFound,
+            None => HttpEndpoint::MethodNotAllowed(&allowed),
+        };
+        HttpNext {
+            chain: &self.middleware,
+            endpoint,
+        }.run(req).await
+    }
+
+    fn parse_pattern(pattern: &str) -> Outcome<Vec<RouteSegment>> {
+        if !pattern.starts_with('/') {
+            return Err(err!(
+                "The route pattern '{}' must begin with '/'.", pattern;
+            Configuration, Invalid, Input));
+        }
+        let parts: Vec<&str> = pattern.split('/').filter(|s| !s.is_empty()).collect();
+        let mut segments = Vec::with_capacity(parts.len());
+        let mut names: Vec<String> = Vec::new();
+        for (i, part) in parts.iter().enumerate() {
+            let segment = if let Some(name) = part.strip_prefix(':') {
+                if name.is_empty() {
+                    return Err(err!(
+                        "A parameter in the route pattern '{}' has no name.", pattern;
+                    Configuration, Invalid, Input, Missing));
+                }
+                RouteSegment::Param(name.to_string())
+            } else if let Some(name) = part.strip_prefix('*') {
+                if i + 1 != parts.len() {
+                    return Err(err!(
+                        "The wildcard in the route pattern '{}' must be the last segment.", pattern;
+                    Configuration, Invalid, Input));
+                }
+                RouteSegment::Wildcard(if name.is_empty() { fmt!("*") } else { name.to_string() })
+            } else {
+                RouteSegment::Literal(res!(HttpForm::percent_decode(part, false)))
+            };
+            if let RouteSegment::Param(name) | RouteSegment::Wildcard(name) = &segment {
+                if names.contains(name) {
+                    return Err(err!(
+                        "The parameter '{}' appears more than once in the route pattern '{}'.",
+                        name, pattern;
+                    Configuration, Invalid, Input));
+                }
+                names.push(name.clone());
+            }
+            segments.push(segment);
+        }
+        Ok(segments)
+    }
+
+    fn split_path(path: &str) -> Outcome<Vec<String>> {
+        let mut segments = Vec::new();
+        for part in path.split('/').filter(|s| !s.is_empty()) {
+            segments.push(res!(HttpForm::percent_decode(part, false)));
+        }
+        Ok(segments)
+    }
+}
+
+/// The status of a response, if it is one.
+fn response_status(response: &HttpMessage) -> Option<HttpStatus> {
+    match &response.header.headline {
+        HttpHeadline::Response { status } => Some(*status),
+        _ => None,
+    }
+}
+
+/// Logs each request with the status of its response and the time taken.
+#[derive(Clone, Debug)]
+pub struct HttpLogger {
+    pub level: LogLevel,
+}
+
+impl HttpLogger {
+    pub fn new(level: LogLevel) -> Self {
+        Self { level }
+    }
+}
+
+impl HttpMiddleware for HttpLogger {
+    fn handle<'a>(&'a self, req: HttpRequest, next: HttpNext<'a>) -> HttpFuture<'a> {
+        Box::pin(async move {
+            let start = Instant::now();
+            let method = req.method;
+            let loc = req.loc.path.as_str().to_string();
+            let id = req.id.clone();
+            let result = next.run(req).await;
+            match &result {
+                Ok(response) => match response_status(response) {
+                    Some(status) => log!(self.level, "{}: {} {} -> {} {} in {:?}",
+                        id, method, loc, status, status.desc(), start.elapsed()),
+                    None => log!(self.level, "{}: {} {} -> no status in {:?}",
+                        id, method, loc, start.elapsed()),
+                },
+                Err(e) => log!(self.level, "{}: {} {} -> error in {:?}: {}",
+                    id, method, loc, start.elapsed(), e),
+            }
+            result
+        })
+    }
+}
+
+/// Whether the credentials given with a request are acceptable.
+pub type HttpAuthCheck = Arc<dyn Fn(&HttpRequest, &str) -> bool + Send + Sync>;
+
+/// Requires an `Authorization` header using the given scheme, with credentials accepted by the
+/// check, responding with 401 Unauthorized otherwise.  Paths beginning with one of the public
+/// prefixes are exempt, comparing whole decoded segments as the router does, so that `/static`
+/// exempts `/static/app.js` but not `/static-admin`.  `OPTIONS` requests are not exempt, so
+/// `HttpCors` must be added first for preflight requests to succeed.
+#[derive(Clone)]
+pub struct HttpAuth {
+    pub scheme: String,
+    pub realm:  String,
+    pub public: Vec<String>,
+    check:      HttpAuthCheck,
+}
+
+impl fmt::Debug for HttpAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HttpAuth")
+            .field("scheme", &self.scheme)
+            .field("realm", &self.realm)
+            .field("public", &self.public)
+            .finish()
+    }
+}
+
+impl HttpAuth {
+
+    /// Check the credentials following the scheme name.
+    pub fn new<
+        S1: Into<String>,
+        S2: Into<String>,
+        F:  Fn(&HttpRequest, &str) -> bool + Send + Sync + 'static,
+    >(
+        scheme: S1,
+        realm:  S2,
+        check:  F,
+    )
+        -> Self
+    {
+        Self {
+            scheme: scheme.into(),
+            realm:  realm.into(),
+            public: Vec::new(),
+            check:  Arc::new(check),
+        }
+    }
+
+    /// Check a bearer token (RFC 6750).
+    pub fn bearer<
+        S: Into<String>,
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    >(
+        realm: S,
+        check: F,
+    )
+        -> Self
+    {
+        Self::new("Bearer", realm, move |_req: &HttpRequest, token: &str| check(token))
+    }
+
+    /// Check a user name and password (RFC 7617).
+    pub fn basic<
+        S: Into<String>,
+        F: Fn(&str, &str) -> bool + Send + Sync + 'static,
+    >(
+        realm: S,
+        check: F,
+    )
+        -> Self
+    {
+        Self::new("Basic", realm, move |_req: &HttpRequest, credentials: &str| {
+            let decoded = base64::decode(credentials).ok()
+                .and_then(|byts| String::from_utf8(byts).ok());
+            match decoded.as_deref().and_then(|s| s.split_once(':')) {
+                Some((user, password)) => check(user, password),
+                None => false,
+            }
+        })
+    }
+
+    /// Exempt paths whose segments begin with those of the given prefix.
+    pub fn with_public<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.public.push(prefix.into());
+        self
+    }
+
+    fn is_authorised(&self, req: &HttpRequest) -> bool {
+        if let Ok(path) = HttpRouter::split_path(req.loc.path.as_str()) {
+            let is_public = self.public.iter().any(|prefix| match HttpRouter::split_path(prefix) {
+                Ok(prefix) => path.starts_with(&prefix),
+                Err(_) => false,
+            });
+            if is_public {
+                return true;
+            }
+        }
+        let value = match req.field(&HeaderName::Authorization) {
+            Some(value) => value,
+            None => return false,
+        };
+        match value.trim().split_once(' ') {
+            Some((scheme, credentials)) if scheme.eq_ignore_ascii_case(&self.scheme) =>
+                (self.check)(req, credentials.trim()),
+            _ => false,
+        }
+    }
+}
+
+impl HttpMiddleware for HttpAuth {
+    fn handle<'a>(&'a self, req: HttpRequest, next: HttpNext<'a>) -> HttpFuture<'a> {
+        if self.is_authorised(&req) {
+            return next.run(req);
+        }
+        debug!("{}: Unauthorised {} request for {}.", req.id, req.method, req.loc.path.as_str());
+        let challenge = fmt!("{} realm=\"{}\"", self.scheme, self.realm.replace('"', "'"));
+        Box::pin(async move {
+            Ok(HttpMessage::respond_with_text(HttpStatus::Unauthorized, "Unauthorised.")
+                .with_field(HeaderName::WWWAuthenticate, HeaderFieldValue::Generic(challenge)))
+        })
+    }
+}
+
+/// Cross-origin resource sharing.  Preflight requests from an allowed origin are answered
+/// directly with 204 No Content, and other responses to an allowed origin are given the
+/// `Access-Control-Allow-Origin` field.  Requests from other origins pass through unchanged, for
+/// the browser to refuse.
+#[derive(Clone, Debug)]
+pub struct HttpCors {
+    /// Any origin is allowed when empty.
+    pub origins:        Vec<String>,
+    pub methods:        Vec<HttpMethod>,
+    /// The request headers allowed, or when empty, those asked for in the preflight request.
+    pub headers:        Vec<String>,
+    pub expose:         Vec<String>,
+    pub credentials:    bool,
+    /// How long, in seconds, the browser may cache a preflight response.
+    pub max_age:        Option<u32>,
+}
+
+impl Default for HttpCors {
+    fn default() -> Self {
+        Self {
+            origins:        Vec::new(),
+            methods:        vec![
+                HttpMethod::GET,
+                HttpMethod::HEAD,
+                HttpMethod::POST,
+                HttpMethod::PUT,
+                HttpMethod::PATCH,
+                HttpMethod::DELETE,
+            ],
+            headers:        Vec::new(),
+            expose:         Vec::new(),
+            credentials:    false,
+            max_age:        Some(constant::HTTP_CORS_MAX_AGE),
+        }
+    }
+}
+
+impl HttpCors {
+
+    pub fn with_origin<S: Into<String>>(mut self, origin: S) -> Self {
+        self.origins.push(origin.into());
+        self
+    }
+
+    pub fn with_methods(mut self, methods: Vec<HttpMethod>) -> Self {
+        self.methods = methods;
+        self
+    }
+
+    pub fn with_header<S: Into<String>>(mut self, header: S) -> Self {
+        self.headers.push(header.into());
+        self
+    }
+
+    pub fn with_expose<S: Into<String>>(mut self, header: S) -> Self {
+        self.expose.push(header.into());
+        self
+    }
+
+    pub fn with_credentials(mut self, credentials: bool) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    pub fn is_allowed(&self, origin: &str) -> bool {
+        self.origins.is_empty() || self.origins.iter().any(|o| o.eq_ignore_ascii_case(origin))
+    }
+
+    /// Add the fields common to preflight and actual responses.
+    fn add_fields(&self, response: HttpMessage, origin: &str) -> HttpMessage {
+        // Credentials cannot be used with a wildcard origin.
+        let allow_origin = if self.origins.is_empty() && !self.credentials {
+            fmt!("*")
+        } else {
+            origin.to_string()
+        };
+        let mut response = response.with_field(
+            HeaderName::AccessControlAllowOrigin,
+            HeaderFieldValue::Generic(allow_origin),
+        );
+        if !self.origins.is_empty() || self.credentials {
+            response = response.with_field(HeaderName::Vary, HeaderFieldValue::Generic(fmt!("Origin")));
+        }
+        if self.credentials {
+            response = response.with_field(
+                HeaderName::AccessControlAllowCredentials,
+                HeaderFieldValue::Generic(fmt!("true")),
+            );
+        }
+        response
+    }
+
+    fn preflight(&self, req: &HttpRequest, origin: &str) -> HttpMessage {
+        let mut response = self.add_fields(HttpMessage::new_response(HttpStatus::NoContent), origin);
+        let methods = self.methods.iter()
+            .map(|method| method.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        response = response.with_field(
+            HeaderName::AccessControlAllowMethods,
+            HeaderFieldValue::Generic(methods),
+        );
+        let headers = if self.headers.is_empty() {
+            req.field(&HeaderName::AccessControlRequestHeaders)
+        } else {
+            Some(self.headers.join(", "))
+        };
+        if let Some(headers) = headers {
+            response = response.with_field(
+                HeaderName::AccessControlAllowHeaders,
+                HeaderFieldValue::Generic(headers),
+            );
+        }
+        if let Some(max_age) = self.max_age {
+            response = response.with_field(
+                HeaderName::AccessControlMaxAge,
+                HeaderFieldValue::Generic(max_age.to_string()),
+            );
+        }
+        response
+    }
+}
+
+impl HttpMiddleware for HttpCors {
+    fn handle<'a>(&'a self, req: HttpRequest, next: HttpNext<'a>) -> HttpFuture<'a> {
+        let origin = match req.field(&HeaderName::Origin) {
+            Some(origin) if self.is_allowed(&origin) => origin,
+            _ => return next.run(req),
+        };
+        if req.method == HttpMethod::OPTIONS
+            && req.fields.get_one(&HeaderName::AccessControlRequestMethod).is_some()
+        {
+            let response = self.preflight(&req, &origin);
+            return Box::pin(async move { Ok(response) });
+        }
+        Box::pin(async move {
+            let mut response = self.add_fields(res!(next.run(req).await), &origin);
+            if !self.expose.is_empty() {
+                response = response.with_field(
+                    HeaderName::AccessControlExposeHeaders,
+                    HeaderFieldValue::Generic(self.expose.join(", ")),
+                );
+            }
+            Ok(response)
+        })
+    }
+}
+
+/// Compresses response bodies with gzip or deflate, when the client accepts it.  Only text,
+/// JSON, JavaScript, XML and SVG bodies of at least `min_size` bytes are compressed, and not
+/// streamed bodies or those already encoded.
+#[derive(Clone, Debug)]
+pub struct HttpCompression {
+    pub level:      u32,
+    pub min_size:   usize,
+}
+
+impl Default for HttpCompression {
+    fn default() -> Self {
+        Self {
+            level:      Compression::default().level(),
+            min_size:   constant::HTTP_COMPRESSION_MIN_SIZE,
+        }
+    }
+}
+
+impl HttpCompression {
+
+    /// The preferred encoding acceptable to the client, given its `Accept-Encoding` field.
+    pub fn choose_encoding(accept: &str) -> Option<&'static str> {
+        let mut gzip = None;
+        let mut deflate = None;
+        let mut any = None;
+        for item in accept.split(',') {
+            let mut parts = item.split(';');
+            let coding = parts.next().unwrap_or_default().trim().to_lowercase();
+            let mut q = 1.0;
+            for param in parts {
+                if let Some((k, v)) = param.split_once('=') {
+                    if k.trim().eq_ignore_ascii_case("q") {
+                        q = v.trim().parse::<f32>().unwrap_or(0.0);
+                    }
+                }
+            }
+            match coding.as_str() {
+                "gzip" | "x-gzip"   => gzip = Some(q),
+                "deflate"           => deflate = Some(q),
+                "*"                 => any = Some(q),
+                _ => (),
+            }
+        }
+        let gzip = gzip.or(any).unwrap_or(0.0);
+        let deflate = deflate.or(any).unwrap_or(0.0);
+        if gzip > 0.0 && gzip >= deflate {
+            Some("gzip")
+        } else if deflate > 0.0 {
+            Some("deflate")
+        } else {
+            None
+        }
+    }
+
+    pub fn is_compressible(content_type: &str) -> bool {
+        let content_type = content_type.to_lowercase();
+        content_type.starts_with("text/")
+            || ["json", "javascript", "xml", "svg"].iter().any(|s| content_type.contains(s))
+    }
+
+    pub fn compress(&self, encoding: &str, byts: &[u8]) -> Outcome<Vec<u8>> {
+        let level = Compression::new(self.level);
+        match encoding {
+            "gzip" => {
+                let mut encoder = GzEncoder::new(Vec::new(), level);
+                res!(encoder.write_all(byts));
+                Ok(res!(encoder.finish()))
+            },
+            "deflate" => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), level);
+                res!(encoder.write_all(byts));
+                Ok(res!(encoder.finish()))
+            },
+            _ => Err(err!(
+                "Unsupported content encoding '{}'.", encoding;
+            Encode, Unimplemented)),
+        }
+    }
+
+    fn should_compress(&self, response: &HttpMessage) -> bool {
+        if response.stream.is_some()
+            || response.body.len() < self.min_size
+            || response.header.fields.get_one(&HeaderName::ContentEncoding).is_some()
+        {
+            return false;
+        }
+        match response.header.fields.get_one(&HeaderName::ContentType) {
+            Some(value) => Self::is_compressible(&value.to_string()),
+            None => false,
+        }
+    }
+}
+
+impl HttpMiddleware for HttpCompression {
+    fn handle<'a>(&'a self, req: HttpRequest, next: HttpNext<'a>) -> HttpFuture<'a> {
+        let encoding = req.field(&HeaderName::AcceptEncoding)
+            .and_then(|accept| Self::choose_encoding(&accept));
+        Box::pin(async move {
+            let mut response = res!(next.run(req).await);
+            let encoding = match encoding {
+                Some(encoding) if self.should_compress(&response) => encoding,
+                _ => return Ok(response),
+            };
+            let compressed = res!(self.compress(encoding, &response.body));
+            if compressed.len() >= response.body.len() {
+                return Ok(response);
+            }
+            response.body = compressed;
+            response.header.fields.remove(&HeaderName::ContentLength);
+            Ok(response
+                .with_field(HeaderName::ContentEncoding, HeaderFieldValue::Generic(encoding.to_string()))
+                .with_field(HeaderName::Vary, HeaderFieldValue::Generic(fmt!("Accept-Encoding"))))
+        })
+    }
+}
@@ -11,6 +11,11 @@
 //! - Status code management with descriptive messages
 //! - Content type system supporting common web formats
 //! - Request and response message parsing
+//! - Chunked transfer encoding with trailers, and streaming of bodies of unknown length
+//! - Url encoded and multipart form parsing, with large uploads written to disk
+//! - Async client with keep-alive connection reuse, TLS, redirects and timeouts
+//! - Request routing by method and path pattern, with path parameters and wildcards
+//! - Composable middleware for logging, authentication, CORS and compression
 //! - Cookie and session handling
 //! - Support for HTTP/1.1, HTTP/2 and HTTP/3
 //!
@@ -18,6 +23,8 @@
 //! - Secure handshake implementation
 //! - Binary and text message support
 //! - Frame-level control with customisable chunk sizes
+//! - Fragmented message reassembly with a configurable maximum message size
+//! - Per-message compression using the permessage-deflate extension
 //! - Ping/pong heartbeat mechanism
 //! - Connection upgrade handling
 //! - Built-in latency tracking
@@ -26,11 +33,18 @@
 //! - Message composition and parsing
 //! - Command implementation (HELO, MAIL FROM, RCPT TO, etc.)
 //! - Header field processing
-//! - Multi-part content support
+//! - MIME parsing of nested multipart messages, with base64, quoted-printable and RFC 2047
+//!   encoded word decoding
+//! - Composition of multipart/alternative messages with attachments
+//! - DKIM signing and verification, using Ed25519 or RSA
 //! - Response code handling
+//! - Server session state machine with pipelining, size limits and STARTTLS
+//! - Submission client with STARTTLS, AUTH PLAIN and LOGIN, and pipelining
 //!
 //! ## DNS and Addressing
 //! - FQDN (Fully Qualified Domain Name) validation
+//! - DNS message encoding and decoding for A, AAAA, CNAME, MX, TXT and SRV records
+//! - Async stub resolver over UDP and TCP, with configurable nameservers and caching
 //! - Email address parsing and validation
 //! - Phone number handling with country codes
 //! - Generic contact address abstraction
@@ -49,7 +49,11 @@ impl Display for ContentTypeValue {
                 Some(cs) => write!(f, "{}; charset={}", mt, cs),
                 None => write!(f, "{}", mt),
             },
-            Self::Multipart((mt, b)) => write!(f, "{}; boundary={}", mt, b),
+            // A boundary containing special characters must be quoted (RFC 2046 Section 5.1.1).
+            Self::Multipart((mt, b)) => match b.chars().any(|c| "()<>@,;:\\\"/[]?= ".contains(c)) {
+                true => write!(f, "multipart/{}; boundary=\"{}\"", mt, b),
+                false => write!(f, "multipart/{}; boundary={}", mt, b),
+            },
         }
     }
 }
@@ -359,13 +363,23 @@ impl FromStr for Model {
 /// ╰────────────────────────────────────────────╯
 #[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
 pub enum Multipart {
+    Alternative,
+    Digest,
     FormData,
+    Mixed,
+    Parallel,
+    Related,
 }
 
 impl Display for Multipart {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", match self {
+            Self::Alternative   => "alternative",
+            Self::Digest        => "digest",
             Self::FormData      => "form-data",
+            Self::Mixed         => "mixed",
+            Self::Parallel      => "parallel",
+            Self::Related       => "related",
         })
     }
 }
@@ -375,7 +389,12 @@ impl FromStr for Multipart {
 
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
         Ok(match s {
+            "alternative"       => Self::Alternative,
+            "digest"            => Self::Digest,
             "form-data"         => Self::FormData,               
+            "mixed"             => Self::Mixed,
+            "parallel"          => Self::Parallel,
+            "related"           => Self::Related,
             _ => return Err(err!(
                 "Unrecognised Multipart Media subtype '{}'.", s;
             IO, Network, Unknown, Input)),
@@ -0,0 +1,459 @@
+//! An SMTP submission client (RFC 6409) for sending outbound email.
+//!
+//! After the greeting, the client introduces itself with EHLO and upgrades the connection with
+//! STARTTLS when the server offers it, or fails if `SmtpClientConfig::require_tls` is set and it
+//! does not.  Any credentials are then sent using AUTH PLAIN, or AUTH LOGIN if that is all the
+//! server supports, but never over a plain connection.  When the server supports `PIPELINING`, the
+//! `MAIL FROM:`, `RCPT TO:` and `DATA` commands for a message are sent together.
+//!
+//! ```ignore
+//! let mut client = res!(SmtpClient::connect("mail.example.com", 587, cfg).await);
+//! let report = res!(client.send("me@example.com", &[fmt!("you@example.com")], &message).await);
+//! if !report.is_delivered() { ... }
+//! res!(client.quit().await);
+//! ```
+use crate::{
+    constant,
+    smtp::{
+        codes::SmtpResponseCode,
+        reply::SmtpReply,
+    },
+};
+
+use oxedyne_fe2o3_core::prelude::*;
+
+use std::{
+    fmt,
+    sync::Arc,
+    time::Duration,
+};
+
+use tokio::{
+    io::{
+        AsyncRead,
+        AsyncWrite,
+        AsyncWriteExt,
+        BufReader,
+    },
+    net::TcpStream,
+};
+use tokio_rustls::{
+    rustls::{
+        pki_types::ServerName,
+        ClientConfig,
+        RootCertStore,
+    },
+    TlsConnector,
+};
+
+
+#[derive(Clone)]
+pub struct SmtpCredentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl fmt::Debug for SmtpCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SmtpCredentials")
+            .field("username", &self.username)
+            .field("password", &"<hidden>")
+            .finish()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SmtpClientConfig {
+    /// The name the client gives in its EHLO command.
+    pub hostname:       String,
+    /// Limits the connection, and the wait for each reply.
+    pub timeout:        Duration,
+    /// Fail rather than send mail over a plain connection.
+    pub require_tls:    bool,
+    /// Trusted root certificates for STARTTLS.
+    pub roots:          RootCertStore,
+    pub credentials:    Option<SmtpCredentials>,
+}
+
+impl Default for SmtpClientConfig {
+    fn default() -> Self {
+        Self {
+            hostname:       fmt!("localhost"),
+            timeout:        constant::SMTP_CLIENT_TIMEOUT,
+            require_tls:    true,
+            roots:          RootCertStore::empty(),
+            credentials:    None,
+        }
+    }
+}
+
+/// The server's replies to the submission of one message.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SmtpSendReport {
+    /// The reply to each `RCPT TO:` command, in order.
+    pub recipients: Vec<(String, SmtpReply)>,
+    /// The reply to the message content, if any recipient was accepted.
+    pub data:       Option<SmtpReply>,
+}
+
+impl SmtpSendReport {
+
+    /// The recipients the message was accepted for, assuming the server accepted the content.
+    pub fn accepted(&self) -> Vec<&String> {
+        self.recipients.iter()
+            .filter(|(_, reply)| reply.is_positive())
+            .map(|(address, _)| address)
+            .collect()
+    }
+
+    pub fn rejected(&self) -> Vec<&(String, SmtpReply)> {
+        self.recipients.iter()
+            .filter(|(_, reply)| !reply.is_positive())
+            .collect()
+    }
+
+    /// Whether the server took responsibility for the message, for at least one recipient.
+    pub fn is_delivered(&self) -> bool {
+        matches!(&self.data, Some(reply) if reply.is_positive())
+    }
+}
+
+/// The connection, which changes type when upgraded to TLS.
+trait SmtpIo: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> SmtpIo for S {}
+
+pub struct SmtpClient {
+    pub cfg:        SmtpClientConfig,
+    stream:         BufReader<Box<dyn SmtpIo>>,
+    server_name:    String,
+    /// The EHLO keywords and parameters offered by the server, e.g. `SIZE 10485760`.
+    extensions:     Vec<String>,
+    tls:            bool,
+}
+
+impl fmt::Debug for SmtpClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SmtpClient")
+            .field("cfg", &self.cfg)
+            .field("server_name", &self.server_name)
+            .field("extensions", &self.extensions)
+            .field("tls", &self.tls)
+            .finish()
+    }
+}
+
+impl SmtpClient {
+
+    /// Connect to the given server and prepare to send mail.
+    pub async fn connect(host: &str, port: u16, cfg: SmtpClientConfig) -> Outcome<Self> {
+        let stream = match tokio::time::timeout(cfg.timeout, TcpStream::connect((host, port))).await {
+            Ok(result) => res!(result, IO, Network, Init),
+            Err(e) => return Err(err!(e,
+                "Connection to {}:{} not made within {:?}.", host, port, cfg.timeout;
+            IO, Network, Timeout)),
+        };
+        Self::start(stream, host, cfg).await
+    }
+
+    /// Prepare to send mail over an established connection to the named server, reading the
+    /// greeting, securing the connection and authenticating as required.
+    pub async fn start<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
+        stream:         S,
+        server_name:    &str,
+        cfg:            SmtpClientConfig,
+    )
+        -> Outcome<Self>
+    {
+        let mut client = Self {
+            cfg,
+            stream:         BufReader::new(Box::new(stream)),
+            server_name:    server_name.to_string(),
+            extensions:     Vec::new(),
+            tls:            false,
+        };
+        let greeting = res!(client.reply().await);
+        if greeting.code != SmtpResponseCode::ServiceReady {
+            return Err(err!(
+                "The server at {} is not ready: {} {}", server_name, greeting.code, greeting.text();
+            IO, Network, Unexpected));
+        }
+        res!(client.ehlo().await);
+        if client.supports("STARTTLS") {
+            res!(client.starttls().await);
+            res!(client.ehlo().await);
+        } else if client.cfg.require_tls {
+            return Err(err!(
+                "The server at {} does not offer STARTTLS.", server_name;
+            IO, Network, Missing));
+        }
+        if let Some(credentials) = client.cfg.credentials.clone() {
+            res!(client.auth(&credentials).await);
+        }
+        Ok(client)
+    }
+
+    pub fn is_tls(&self) -> bool { self.tls }
+
+    pub fn extensions(&self) -> &[String] { &self.extensions }
+
+    /// Whether the server offered the given EHLO keyword, ignoring case.
+    pub fn supports(&self, keyword: &str) -> bool {
+        self.extension(keyword).is_some()
+    }
+
+    /// The parameters following the given EHLO keyword, if offered.
+    fn extension(&self, keyword: &str) -> Option<&str> {
+        self.extensions.iter().find_map(|ext| {
+            let (k, params) = ext.split_once(' ').unwrap_or((ext.as_str(), ""));
+            if k.eq_ignore_ascii_case(keyword) { Some(params) } else { None }
+        })
+    }
+
+    /// Submit a message, with the reverse path `from`, which may be empty for a bounce.  The
+    /// message is sent unless the server refuses the sender, or every recipient.
+    pub async fn send(
+        &mut self,
+        from:   &str,
+        to:     &[String],
+        data:   &[u8],
+    )
+        -> Outcome<SmtpSendReport>
+    {
+        if to.is_empty() {
+            return Err(err!("No recipients given."; Input, Missing));
+        }
+        res!(Self::check_address(from, "sender"));
+        for address in to {
+            if address.is_empty() {
+                return Err(err!("An empty recipient address was given."; Input, Invalid, Missing));
+            }
+            res!(Self::check_address(address, "recipient"));
+        }
+        let data = dot_stuff(data);
+        let mut mail = fmt!("MAIL FROM:<{}>", from);
+        if self.supports("SIZE") {
+            mail.push_str(&fmt!(" SIZE={}", data.len()));
+        }
+        if self.supports("8BITMIME") && !data.is_ascii() {
+            mail.push_str(" BODY=8BITMIME");
+        }
+        let mut report = SmtpSendReport {
+            recipients: Vec::new(),
+            data:       None,
+        };
+
+        if self.supports("PIPELINING") {
+            let mut cmds = fmt!("{}\r\n", mail);
+            for address in to {
+                cmds.push_str(&fmt!("RCPT TO:<{}>\r\n", address));
+            }
+            cmds.push_str("DATA\r\n");
+            res!(self.write(cmds.as_bytes()).await);
+            let mail_reply = res!(self.reply().await);
+            for address in to {
+                let reply = res!(self.reply().await);
+                report.recipients.push((address.clone(), reply));
+            }
+            let data_reply = res!(self.reply().await);
+            if !mail_reply.is_positive() {
+                return Err(Self::refused("MAIL FROM", &mail_reply));
+            }
+            if data_reply.code != SmtpResponseCode::StartMailInput {
+                // Abandon the transaction, which the server may still consider open.
+                res!(self.command("RSET").await);
+                if report.accepted().is_empty() {
+                    return Ok(report);
+                }
+                return Err(Self::refused("DATA", &data_reply));
+            }
+            if report.accepted().is_empty() {
+                // Send an empty message to complete the command, as no-one will receive it.
+                res!(self.write(b".\r\n").await);
+                res!(self.reply().await);
+                return Ok(report);
+            }
+        } else {
+            let mail_reply = res!(self.command(&mail).await);
+            if !mail_reply.is_positive() {
+                return Err(Self::refused("MAIL FROM", &mail_reply));
+            }
+            for address in to {
+                let reply = res!(self.command(&fmt!("RCPT TO:<{}>", address)).await);
+                report.recipients.push((address.clone(), reply));
+            }
+            if report.accepted().is_empty() {
+                res!(self.command("RSET").await);
+                return Ok(report);
+            }
+            let data_reply = res!(self.command("DATA").await);
+            if data_reply.code != SmtpResponseCode::StartMailInput {
+                return Err(Self::refused("DATA", &data_reply));
+            }
+        }
+
+        res!(self.write(&data).await);
+        res!(self.write(b".\r\n").await);
+        report.data = Some(res!(self.reply().await));
+        Ok(report)
+    }
+
+    /// Abandon any mail transaction.
+    pub async fn reset(&mut self) -> Outcome<()> {
+        let reply = res!(self.command("RSET").await);
+        if reply.is_positive() {
+            Ok(())
+        } else {
+            Err(Self::refused("RSET", &reply))
+        }
+    }
+
+    /// End the session and close the connection.
+    pub async fn quit(mut self) -> Outcome<()> {
+        let reply = res!(self.command("QUIT").await);
+        if reply.code != SmtpResponseCode::ServiceClosingTransmissionChannel {
+            warn!("Unexpected reply to QUIT: {} {}", reply.code, reply.text());
+        }
+        // The server may already have closed the connection.
+        if let Err(e) = self.stream.get_mut().shutdown().await {
+            debug!("While closing the connection to {}: {}", self.server_name, e);
+        }
+        Ok(())
+    }
+
+    async fn ehlo(&mut self) -> Outcome<()> {
+        let reply = res!(self.command(&fmt!("EHLO {}", self.cfg.hostname)).await);
+        if reply.code != SmtpResponseCode::RequestedMailActionOkayCompleted {
+            return Err(Self::refused("EHLO", &reply));
+        }
+        // The first line is the server's greeting.
+        self.extensions = reply.lines.into_iter().skip(1).collect();
+        Ok(())
+    }
+
+    async fn starttls(&mut self) -> Outcome<()> {
+        let reply = res!(self.command("STARTTLS").await);
+        if reply.code != SmtpResponseCode::ServiceReady {
+            return Err(Self::refused("STARTTLS", &reply));
+        }
+        let tls_cfg = ClientConfig::builder()
+            .with_root_certificates(self.cfg.roots.clone())
+            .with_no_client_auth();
+        let connector = TlsConnector::from(Arc::new(tls_cfg));
+        let server_name = match ServerName::try_from(self.server_name.clone()) {
+            Ok(name) => name,
+            Err(e) => return Err(err!(e,
+                "Invalid server name '{}' for TLS.", self.server_name;
+            IO, Network, Invalid, Input)),
+        };
+        // Replace the stream, discarding anything the server sent before the handshake.
+        let stream = std::mem::replace(
+            &mut self.stream,
+            BufReader::new(Box::new(tokio::io::empty()) as Box<dyn SmtpIo>),
+        ).into_inner();
+        let stream = match tokio::time::timeout(
+            self.cfg.timeout,
+            connector.connect(server_name, stream),
+        ).await {
+            Ok(result) => res!(result, IO, Network, Init),
+            Err(e) => return Err(err!(e,
+                "TLS handshake with {} not completed within {:?}.", self.server_name, self.cfg.timeout;
+            IO, Network, Timeout)),
+        };
+        self.stream = BufReader::new(Box::new(stream));
+        self.tls = true;
+        self.extensions.clear();
+        Ok(())
+    }
+
+    async fn auth(&mut self, credentials: &SmtpCredentials) -> Outcome<()> {
+        if !self.tls {
+            return Err(err!(
+                "Refusing to send credentials to {} over a plain connection.", self.server_name;
+            IO, Network, Security));
+        }
+        let mechanisms: Vec<String> = match self.extension("AUTH") {
+            Some(params) => params.split_whitespace().map(str::to_uppercase).collect(),
+            None => return Err(err!(
+                "The server at {} does not offer authentication.", self.server_name;
+            IO, Network, Missing)),
+        };
+        let reply = if mechanisms.iter().any(|m| m == "PLAIN") {
+            let plain = fmt!("\0{}\0{}", credentials.username, credentials.password);
+            res!(self.command(&fmt!("AUTH PLAIN {}", base64::encode(plain))).await)
+        } else if mechanisms.iter().any(|m| m == "LOGIN") {
+            let mut reply = res!(self.command("AUTH LOGIN").await);
+            for field in [&credentials.username, &credentials.password] {
+                if reply.code != SmtpResponseCode::AuthInputData {
+                    break;
+                }
+                reply = res!(self.command(&base64::encode(field)).await);
+            }
+            reply
+        } else {
+            return Err(err!(
+                "The server at {} offers no supported authentication mechanism in {:?}.",
+                self.server_name, mechanisms;
+            IO, Network, Unimplemented));
+        };
+        if reply.code == SmtpResponseCode::AuthenticationSuccessful {
+            Ok(())
+        } else {
+            Err(Self::refused("AUTH", &reply))
+        }
+    }
+
+    async fn command(&mut self, cmd: &str) -> Outcome<SmtpReply> {
+        res!(self.write(fmt!("{}\r\n", cmd).as_bytes()).await);
+        self.reply().await
+    }
+
+    async fn write(&mut self, byts: &[u8]) -> Outcome<()> {
+        let stream = self.stream.get_mut();
+        res!(stream.write_all(byts).await, IO, Network, Write);
+        res!(stream.flush().await, IO, Network, Write);
+        Ok(())
+    }
+
+    async fn reply(&mut self) -> Outcome<SmtpReply> {
+        match tokio::time::timeout(self.cfg.timeout, SmtpReply::read(&mut self.stream)).await {
+            Ok(result) => result,
+            Err(e) => Err(err!(e,
+                "No reply from {} within {:?}.", self.server_name, self.cfg.timeout;
+            IO, Network, Timeout)),
+        }
+    }
+
+    /// Refuse an address that could break out of the angle brackets of its command.
+    fn check_address(address: &str, role: &str) -> Outcome<()> {
+        match address.chars().find(|c| c.is_control() || c.is_whitespace() || *c == '<' || *c == '>') {
+            Some(c) => Err(err!(
+                "The {} address '{}' contains the invalid character {:?}.",
+                role, address.escape_debug(), c;
+            Input, Invalid)),
+            None => Ok(()),
+        }
+    }
+
+    fn refused(cmd: &str, reply: &SmtpReply) -> Error<ErrTag> {
+        err!(
+            "{} refused: {} {}", cmd, reply.code, reply.text();
+        IO, Network, Unexpected)
+    }
+}
+
+/// Prepare a message for the DATA command, ending every line with CRLF and doubling a leading
+/// dot, so that no line can be mistaken for the end of the message.
+pub fn dot_stuff(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 50 + 2);
+    let data = data.strip_suffix(b"\n").unwrap_or(data);
+    for line in data.split(|b| *b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.starts_with(b".") {
+            out.push(b'.');
+        }
+        out.extend_from_slice(line);
+        out.extend_from_slice(b"\r\n");
+    }
+    out
+}
@@ -11,6 +11,9 @@ use std::{
 };
 
 
+/// ESMTP parameter names and values, e.g. `SIZE=1000`.
+pub type EsmtpParams = Vec<(String, Option<String>)>;
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum SmtpCommand {
     Helo(Fqdn),
@@ -91,23 +94,25 @@ impl FromStr for SmtpCommand {
                 }
             }
             "MAIL" => {
-                if parts.len() != 2 || !parts[1].to_uppercase().starts_with("FROM:") {
-                    Err(err!(
+                match Self::path_arg(s, "FROM:") {
+                    Some(arg) => {
+                        let (address, _) = res!(Self::split_path(arg));
+                        Ok(SmtpCommand::MailFrom(address))
+                    }
+                    None => Err(err!(
                         "'{}' invalid: {} command requires 'FROM:' followed by an address.", s, cmd;
-                    Invalid, Input))
-                } else {
-                    let address = parts[1].trim_start_matches("FROM:<").trim_end_matches('>').to_string();
-                    Ok(SmtpCommand::MailFrom(address))
+                    Invalid, Input)),
                 }
             }
             "RCPT" => {
-                if parts.len() != 2 || !parts[1].to_uppercase().starts_with("TO:") {
-                    Err(err!(
-                        "'{}' invalid: {} command requires 'FROM:' followed by an address.", s, cmd;
-                    Invalid, Input))
-                } else {
-                    let address = parts[1].trim_start_matches("TO:<").trim_end_matches('>').to_string();
-                    Ok(SmtpCommand::RcptTo(address))
+                match Self::path_arg(s, "TO:") {
+                    Some(arg) => {
+                        let (address, _) = res!(Self::split_path(arg));
+                        Ok(SmtpCommand::RcptTo(address))
+                    }
+                    None => Err(err!(
+                        "'{}' invalid: {} command requires 'TO:' followed by an address.", s, cmd;
+                    Invalid, Input)),
                 }
             }
             "DATA" => {
@@ -179,13 +184,14 @@ impl FromStr for SmtpCommand {
                 }
             }
             "AUTH" => {
-                if parts.len() != 2 {
+                // The mechanism, optionally followed by an initial response.
+                if parts.len() != 2 && parts.len() != 3 {
                     Err(err!(
-                        "'{}' invalid: {} command requires a single argument.", s, cmd;
+                        "'{}' invalid: {} command requires a mechanism and an optional initial \
+                        response.", s, cmd;
                     Invalid, Input, Mismatch))
                 } else {
-                    let mechanism = parts[1].trim().to_string();
-                    Ok(SmtpCommand::Auth(mechanism))
+                    Ok(SmtpCommand::Auth(parts[1..].join(" ")))
                 }
             }
             "STARTTLS" => {
@@ -201,3 +207,65 @@ impl FromStr for SmtpCommand {
         }
     }
 }
+
+impl SmtpCommand {
+
+    /// The ESMTP parameters following the address in a `MAIL FROM:` or `RCPT TO:` command line,
+    /// e.g. `[("SIZE", Some("1000")), ("BODY", Some("8BITMIME"))]`, with upper case names.
+    pub fn esmtp_params(s: &str) -> Outcome<EsmtpParams> {
+        for prefix in ["FROM:", "TO:"] {
+            if let Some(arg) = Self::path_arg(s, prefix) {
+                let (_, params) = res!(Self::split_path(arg));
+                return Ok(params);
+            }
+        }
+        Err(err!(
+            "'{}' is not a MAIL or RCPT command.", s;
+        Invalid, Input))
+    }
+
+    /// The remainder of a command line following the command word and the given prefix, which
+    /// is matched ignoring case, e.g. `<a@b.c> SIZE=1000` in `MAIL FROM:<a@b.c> SIZE=1000`.
+    fn path_arg<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+        let s = s.trim();
+        let rest = s[s.find(char::is_whitespace)?..].trim_start();
+        match rest.get(..prefix.len()) {
+            Some(start) if start.eq_ignore_ascii_case(prefix) => Some(rest[prefix.len()..].trim_start()),
+            _ => None,
+        }
+    }
+
+    /// Split a reverse or forward path in angle brackets from the ESMTP parameters that follow.
+    /// Any obsolete source route is removed from the address, and the null path `<>` gives an
+    /// empty address.
+    fn split_path(arg: &str) -> Outcome<(String, EsmtpParams)> {
+        let end = match (arg.starts_with('<'), arg.find('>')) {
+            (true, Some(end)) => end,
+            _ => return Err(err!(
+                "The path in '{}' must be enclosed in angle brackets.", arg;
+            Invalid, Input)),
+        };
+        let mut address = &arg[1..end];
+        if address.starts_with('@') {
+            address = match address.find(':') {
+                Some(i) => &address[i + 1..],
+                None => return Err(err!(
+                    "Invalid source route in path '{}'.", arg;
+                Invalid, Input)),
+            };
+        }
+        if address.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return Err(err!(
+                "Invalid address in path '{}'.", arg;
+            Invalid, Input));
+        }
+        let params = arg[end + 1..]
+            .split_whitespace()
+            .map(|p| match p.split_once('=') {
+                Some((k, v)) => (k.to_uppercase(), Some(v.to_string())),
+                None => (p.to_uppercase(), None),
+            })
+            .collect();
+        Ok((address.to_string(), params))
+    }
+}
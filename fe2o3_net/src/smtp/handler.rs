@@ -1,65 +1,37 @@
-//use crate::{
-//    http::{
-//        msg::HttpMessage,
-//        loc::HttpLocator,
-//    },
-//};
-//
-//use oxedyne_fe2o3_core::prelude::*;
-//use oxedyne_fe2o3_iop_crypto::enc::Encrypter;
-//use oxedyne_fe2o3_iop_db::api::Database;
-//use oxedyne_fe2o3_iop_hash::api::Hasher;
-//use oxedyne_fe2o3_jdat::id::NumIdDat;
-//
-//use std::{
-//    sync::{
-//        Arc,
-//        RwLock,
-//    },
-//};
+use crate::smtp::{
+    reply::SmtpReply,
+    session::SmtpEnvelope,
+};
 
+use oxedyne_fe2o3_core::prelude::*;
 
+
+/// Supplied by the application to an `SmtpSession`, to decide which recipients to accept and what
+/// to do with each message received.
 pub trait EmailHandler:
     Clone
     + std::fmt::Debug
     + Send
     + Sync
 {
-    //fn handle_get<
-    //    const SIDL: usize,
-    //    const UIDL: usize,
-    //    SID:    NumIdDat<SIDL> + 'static,
-    //    UID:    NumIdDat<UIDL> + 'static,
-    //    ENC:    Encrypter,
-    //    KH:     Hasher,
-    //    DB:     Database<UIDL, UID, ENC, KH>,
-    //>(
-    //    &self,
-    //    loc:        HttpLocator,
-    //    response:   Option<HttpMessage>,
-    //    body:       Vec<u8>,
-    //    db:         Arc<RwLock<DB>>,
-    //    sid_opt:    &Option<SID>,
-    //    id:         &String, 
-    //)
-    //    -> impl std::future::Future<Output = Outcome<Option<HttpMessage>>> + Send;
-    //
-    //fn handle_post<
-    //    const SIDL: usize,
-    //    const UIDL: usize,
-    //    SID:    NumIdDat<SIDL> + 'static,
-    //    UID:    NumIdDat<UIDL> + 'static,
-    //    ENC:    Encrypter,
-    //    KH:     Hasher,
-    //    DB:     Database<UIDL, UID, ENC, KH>,
-    //>(
-    //    &self,
-    //    loc:        HttpLocator,
-    //    response:   Option<HttpMessage>,
-    //    body:       Vec<u8>,
-    //    db:         Arc<RwLock<DB>>,
-    //    sid_opt:    &Option<SID>,
-    //    id:         &String, 
-    //)
-    //    -> impl std::future::Future<Output = Outcome<Option<HttpMessage>>> + Send;
+    /// Whether to accept mail for the given `RCPT TO:` address.  Recipients that are refused are
+    /// told the mailbox is unavailable.
+    fn accept_recipient(&self, _address: &str) -> bool {
+        true
+    }
+
+    /// Whether the given credentials, received over TLS, are valid.
+    fn authenticate(&self, _username: &str, _password: &str) -> bool {
+        false
+    }
+
+    /// Take responsibility for a completed message, returning the reply to the client, which
+    /// should be positive only once the message is safely stored or delivered.  An error is
+    /// reported to the client as a transient local failure, so that it tries again later.
+    fn handle_email(
+        &self,
+        envelope:   SmtpEnvelope,
+        id:         &String,
+    )
+        -> impl std::future::Future<Output = Outcome<SmtpReply>> + Send;
 }
@@ -1,4 +1,7 @@
+pub mod client;
 pub mod cmd;
 pub mod codes;
 pub mod handler;
 pub mod msg;
+pub mod reply;
+pub mod session;
@@ -0,0 +1,137 @@
+use crate::{
+    constant,
+    smtp::codes::{
+        CompletionState,
+        SmtpResponseCode,
+    },
+};
+
+use oxedyne_fe2o3_core::prelude::*;
+
+use std::{
+    fmt,
+};
+
+use tokio::io::{
+    AsyncBufRead,
+    AsyncBufReadExt,
+    AsyncReadExt,
+};
+
+
+/// An SMTP reply, which spans multiple lines on the wire when there is more than one line of
+/// text, e.g.
+/// ```text
+/// 250-mail.example.com
+/// 250 PIPELINING
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct SmtpReply {
+    pub code:   SmtpResponseCode,
+    pub lines:  Vec<String>,
+}
+
+impl fmt::Display for SmtpReply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.lines.is_empty() {
+            return write!(f, "{}\r\n", self.code);
+        }
+        let last = self.lines.len() - 1;
+        for (i, line) in self.lines.iter().enumerate() {
+            let sep = if i == last { ' ' } else { '-' };
+            write!(f, "{}{}{}\r\n", self.code, sep, line)?;
+        }
+        Ok(())
+    }
+}
+
+impl SmtpReply {
+
+    pub fn new<S: Into<String>>(code: SmtpResponseCode, text: S) -> Self {
+        Self {
+            code,
+            lines: vec![text.into()],
+        }
+    }
+
+    pub fn multiline(code: SmtpResponseCode, lines: Vec<String>) -> Self {
+        Self {
+            code,
+            lines,
+        }
+    }
+
+    /// Read a reply sent by a server, refusing lines longer than 512 bytes and replies with
+    /// more than `constant::SMTP_MAX_REPLY_LINES` lines.
+    pub async fn read<R: AsyncBufRead + Unpin>(stream: &mut R) -> Outcome<Self> {
+        let mut code = None;
+        let mut lines = Vec::new();
+        loop {
+            if lines.len() >= constant::SMTP_MAX_REPLY_LINES {
+                return Err(err!(
+                    "SMTP reply exceeds {} lines.", constant::SMTP_MAX_REPLY_LINES;
+                IO, Network, Wire, TooBig));
+            }
+            let mut byts = Vec::new();
+            let mut limited = stream.take(constant::SMTP_MAX_REPLY_LINE as u64);
+            let n = res!(limited.read_until(b'\n', &mut byts).await, IO, Network, Read);
+            if n == 0 {
+                return Err(err!(
+                    "Connection closed while reading an SMTP reply.";
+                IO, Network, Read, Missing));
+            }
+            if !byts.ends_with(b"\n") {
+                if n == constant::SMTP_MAX_REPLY_LINE {
+                    return Err(err!(
+                        "SMTP reply line exceeds {} bytes.", constant::SMTP_MAX_REPLY_LINE;
+                    IO, Network, Wire, TooBig));
+                }
+                return Err(err!(
+                    "Connection closed while reading an SMTP reply.";
+                IO, Network, Read, Missing));
+            }
+            let line = res!(String::from_utf8(byts), Decode, Input);
+            let line = line.trim_end_matches(['\r', '\n']);
+            let (this_code, sep, text) = match (line.get(..3), line.get(3..4), line.get(4..)) {
+                (Some(c), sep, text) => (
+                    res!(SmtpResponseCode::from_str(c)),
+                    sep.unwrap_or(" "),
+                    text.unwrap_or(""),
+                ),
+                _ => return Err(err!(
+                    "Invalid SMTP reply line '{}'.", line;
+                IO, Network, Wire, Invalid, Input)),
+            };
+            match &code {
+                Some(code) if *code != this_code => return Err(err!(
+                    "SMTP reply line '{}' does not continue a {} reply.", line, code;
+                IO, Network, Wire, Invalid, Input)),
+                _ => code = Some(this_code),
+            }
+            lines.push(text.to_string());
+            match sep {
+                "-" => continue,
+                " " => break,
+                _ => return Err(err!(
+                    "Invalid SMTP reply line '{}'.", line;
+                IO, Network, Wire, Invalid, Input)),
+            }
+        }
+        match code {
+            Some(code) => Ok(Self { code, lines }),
+            None => Err(err!("Empty SMTP reply."; IO, Network, Wire, Missing)),
+        }
+    }
+
+    /// The text of all lines, joined with newlines.
+    pub fn text(&self) -> String {
+        self.lines.join("\n")
+    }
+
+    pub fn is_positive(&self) -> bool {
+        matches!(
+            self.code.state(),
+            CompletionState::PositiveCompletion | CompletionState::PositiveIntermediate
+        )
+    }
+}
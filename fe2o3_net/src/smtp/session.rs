@@ -0,0 +1,715 @@
+//! The server side of an SMTP session (RFC 5321), from the greeting to `QUIT`.
+//!
+//! Each command moves the session through the states of a mail transaction, `MAIL FROM:`, one or
+//! more `RCPT TO:`, then `DATA`, after which the message is handed to the `EmailHandler`.  Commands
+//! out of sequence are refused with a 503 reply.  The EHLO reply advertises:
+//! - `PIPELINING` (RFC 2920), so replies to a group of commands are sent together once no more
+//!   commands are waiting,
+//! - `SIZE` (RFC 1870), refusing messages that are declared or found to exceed the limit,
+//! - `8BITMIME` (RFC 6152),
+//! - `ENHANCEDSTATUSCODES` (RFC 2034), included in every reply,
+//! - `STARTTLS` (RFC 3207), when the `SmtpServerConfig` has a TLS configuration,
+//! - `AUTH PLAIN LOGIN` (RFC 4954), when enabled, but only once the connection uses TLS.
+//!
+//! ```ignore
+//! let session = SmtpSession::new(SmtpServerConfig::default(), handler, id);
+//! res!(session.run(tcp_stream).await);
+//! ```
+use crate::{
+    constant,
+    smtp::{
+        cmd::SmtpCommand,
+        codes::SmtpResponseCode,
+        handler::EmailHandler,
+        reply::SmtpReply,
+    },
+};
+
+use oxedyne_fe2o3_core::prelude::*;
+
+use std::{
+    sync::Arc,
+    time::Duration,
+};
+
+use tokio::io::{
+    AsyncBufRead,
+    AsyncBufReadExt,
+    AsyncRead,
+    AsyncWrite,
+    AsyncWriteExt,
+    BufReader,
+};
+use tokio_rustls::{
+    rustls::ServerConfig,
+    TlsAcceptor,
+};
+
+
+#[derive(Clone, Debug)]
+pub struct SmtpServerConfig {
+    /// The name the server gives in its greeting and EHLO reply.
+    pub hostname:           String,
+    pub max_message_size:   usize,
+    pub max_recipients:     usize,
+    /// The session is closed once the client has made this many errors.
+    pub max_errors:         usize,
+    /// Limits the wait for each line from the client.
+    pub timeout:            Duration,
+    /// Offer STARTTLS using this configuration.
+    pub tls:                Option<Arc<ServerConfig>>,
+    /// Offer authentication over TLS, with credentials checked by the `EmailHandler`.
+    pub auth:               bool,
+}
+
+impl Default for SmtpServerConfig {
+    fn default() -> Self {
+        Self {
+            hostname:           fmt!("localhost"),
+            max_message_size:   constant::SMTP_MAX_MESSAGE_SIZE,
+            max_recipients:     constant::SMTP_MAX_RECIPIENTS,
+            max_errors:         constant::SMTP_MAX_ERRORS,
+            timeout:            constant::SMTP_SERVER_TIMEOUT,
+            tls:                None,
+            auth:               false,
+        }
+    }
+}
+
+/// A completed mail transaction.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SmtpEnvelope {
+    /// The name the client gave in its HELO or EHLO command.
+    pub client: String,
+    /// The reverse path, empty for a bounce.
+    pub from:   String,
+    pub to:     Vec<String>,
+    /// The message, with the dot stuffing removed.
+    pub data:   Vec<u8>,
+    /// Whether the message was received over TLS.
+    pub tls:    bool,
+    /// The user authenticated by the client, if any.
+    pub user:   Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SmtpState {
+    /// Waiting for HELO or EHLO.
+    Connected,
+    /// Waiting for MAIL.
+    Greeted,
+    /// Waiting for the first RCPT.
+    Mail,
+    /// Waiting for more RCPT, or DATA.
+    Rcpt,
+}
+
+enum SessionEnd<S> {
+    Closed,
+    StartTls(S),
+}
+
+enum Line {
+    Complete(Vec<u8>),
+    /// The line was discarded, noting whether it ended with CRLF.
+    TooLong { crlf: bool },
+    Eof,
+    Timeout,
+}
+
+enum DataEnd {
+    Complete,
+    TooBig,
+    BareNewline,
+}
+
+#[derive(Debug)]
+pub struct SmtpSession<EH: EmailHandler> {
+    pub cfg:        SmtpServerConfig,
+    pub handler:    EH,
+    pub id:         String,
+    state:          SmtpState,
+    extended:       bool, // EHLO rather than HELO.
+    envelope:       SmtpEnvelope,
+    errors:         usize,
+}
+
+impl<EH: EmailHandler> SmtpSession<EH> {
+
+    pub fn new(cfg: SmtpServerConfig, handler: EH, id: String) -> Self {
+        Self {
+            cfg,
+            handler,
+            id,
+            state:      SmtpState::Connected,
+            extended:   false,
+            envelope:   SmtpEnvelope::default(),
+            errors:     0,
+        }
+    }
+
+    pub fn state(&self) -> SmtpState { self.state }
+
+    /// Greet the client and serve commands until the client quits or disconnects, upgrading the
+    /// connection to TLS when asked.
+    pub async fn run<S: AsyncRead + AsyncWrite + Unpin + Send>(
+        mut self,
+        stream: S,
+    )
+        -> Outcome<()>
+    {
+        let greeting = SmtpReply::new(
+            SmtpResponseCode::ServiceReady,
+            fmt!("{} ESMTP Service ready", self.cfg.hostname),
+        );
+        let stream = match res!(self.serve(BufReader::new(stream), greeting.to_string()).await) {
+            SessionEnd::Closed => return Ok(()),
+            SessionEnd::StartTls(stream) => stream,
+        };
+        let acceptor = match &self.cfg.tls {
+            Some(tls) => TlsAcceptor::from(tls.clone()),
+            None => return Err(err!(
+                "{}: STARTTLS accepted without a TLS configuration.", self.id;
+            Bug, Configuration, Missing)),
+        };
+        let stream = match acceptor.accept(stream).await {
+            Ok(stream) => stream,
+            Err(e) => return Err(err!(e,
+                "{}: During the STARTTLS handshake.", self.id;
+            IO, Network, Init)),
+        };
+        debug!("{}: SMTP session upgraded to TLS.", self.id);
+        // The client must start again after the upgrade.
+        self.state = SmtpState::Connected;
+        self.extended = false;
+        self.envelope = SmtpEnvelope {
+            tls: true,
+            ..Default::default()
+        };
+        match res!(self.serve(BufReader::new(stream), String::new()).await) {
+            SessionEnd::Closed => Ok(()),
+            SessionEnd::StartTls(_) => Err(err!(
+                "{}: STARTTLS accepted on a TLS connection.", self.id;
+            Bug, Unexpected)),
+        }
+    }
+
+    /// Serve commands, starting with any given output, until the session ends or the client asks
+    /// to start TLS.
+    async fn serve<S: AsyncRead + AsyncWrite + Unpin + Send>(
+        &mut self,
+        mut stream: BufReader<S>,
+        out:        String,
+    )
+        -> Outcome<SessionEnd<S>>
+    {
+        let mut out = out.into_bytes();
+        loop {
+            if self.errors >= self.cfg.max_errors {
+                res!(Self::flush(&mut stream, &mut out).await);
+                return Ok(SessionEnd::Closed);
+            }
+            // Replies to pipelined commands are sent together, once no more commands are waiting.
+            if !out.is_empty() && stream.buffer().is_empty() {
+                res!(Self::flush(&mut stream, &mut out).await);
+            }
+            let line = match res!(self.read_line(&mut stream, constant::SMTP_MAX_COMMAND_LINE).await) {
+                Line::Complete(line) => line,
+                Line::TooLong { .. } => {
+                    self.error(&mut out, SmtpResponseCode::CommandUnrecognized, "5.5.6 Line too long");
+                    continue;
+                }
+                Line::Eof => return Ok(SessionEnd::Closed),
+                Line::Timeout => {
+                    Self::push(&mut out, SmtpReply::new(
+                        SmtpResponseCode::ServiceNotAvailableClosingTransmissionChannel,
+                        "4.4.2 Timeout, closing connection",
+                    ));
+                    res!(Self::flush(&mut stream, &mut out).await);
+                    return Ok(SessionEnd::Closed);
+                }
+            };
+            let line = match String::from_utf8(line) {
+                Ok(line) => line.trim_end_matches(['\r', '\n']).to_string(),
+                Err(_) => {
+                    self.error(&mut out, SmtpResponseCode::SyntaxErrorInParameters,
+                        "5.5.2 Invalid characters in command");
+                    continue;
+                }
+            };
+            trace!("{}: C: {}", self.id, line);
+
+            let cmd = match SmtpCommand::from_str(&line) {
+                Ok(cmd) => cmd,
+                Err(e) if e.tags().contains(&ErrTag::Unknown) => {
+                    self.error(&mut out, SmtpResponseCode::CommandUnrecognized,
+                        "5.5.2 Command unrecognised");
+                    continue;
+                }
+                Err(_) => {
+                    self.error(&mut out, SmtpResponseCode::SyntaxErrorInParameters,
+                        "5.5.4 Syntax error in parameters");
+                    continue;
+                }
+            };
+
+            let extended = matches!(cmd, SmtpCommand::Ehlo(_));
+            match cmd {
+                SmtpCommand::Helo(fqdn) | SmtpCommand::Ehlo(fqdn) => {
+                    self.extended = extended;
+                    self.reset();
+                    self.envelope.client = fqdn.as_str().to_string();
+                    self.state = SmtpState::Greeted;
+                    let greeting = fmt!("{} greets {}", self.cfg.hostname, fqdn.as_str());
+                    if self.extended {
+                        let mut lines = vec![
+                            greeting,
+                            fmt!("PIPELINING"),
+                            fmt!("SIZE {}", self.cfg.max_message_size),
+                            fmt!("8BITMIME"),
+                            fmt!("ENHANCEDSTATUSCODES"),
+                        ];
+                        if self.cfg.tls.is_some() && !self.envelope.tls {
+                            lines.push(fmt!("STARTTLS"));
+                        }
+                        if self.auth_offered() {
+                            lines.push(fmt!("AUTH PLAIN LOGIN"));
+                        }
+                        Self::push(&mut out, SmtpReply::multiline(
+                            SmtpResponseCode::RequestedMailActionOkayCompleted, lines));
+                    } else {
+                        Self::push(&mut out, SmtpReply::new(
+                            SmtpResponseCode::RequestedMailActionOkayCompleted, greeting));
+                    }
+                }
+                SmtpCommand::MailFrom(address) => {
+                    let reply = self.mail(&line, address);
+                    Self::push(&mut out, reply);
+                }
+                SmtpCommand::RcptTo(address) => {
+                    let reply = self.rcpt(&line, address);
+                    Self::push(&mut out, reply);
+                }
+                SmtpCommand::Data => {
+                    match self.state {
+                        SmtpState::Rcpt => (),
+                        SmtpState::Mail => {
+                            Self::push(&mut out, SmtpReply::new(
+                                SmtpResponseCode::TransactionFailed, "5.5.1 No valid recipients"));
+                            continue;
+                        }
+                        _ => {
+                            Self::push(&mut out, SmtpReply::new(
+                                SmtpResponseCode::BadSequenceOfCommands, "5.5.1 Send MAIL first"));
+                            continue;
+                        }
+                    }
+                    Self::push(&mut out, SmtpReply::new(
+                        SmtpResponseCode::StartMailInput,
+                        "End data with <CR><LF>.<CR><LF>",
+                    ));
+                    res!(Self::flush(&mut stream, &mut out).await);
+                    match res!(self.read_data(&mut stream).await) {
+                        Some(DataEnd::Complete) => {
+                            let reply = self.deliver().await;
+                            Self::push(&mut out, reply);
+                        }
+                        Some(DataEnd::TooBig) => {
+                            self.reset();
+                            Self::push(&mut out, SmtpReply::new(
+                                SmtpResponseCode::ExceededStorageAllocation,
+                                "5.3.4 Message size exceeds fixed maximum message size",
+                            ));
+                        }
+                        Some(DataEnd::BareNewline) => {
+                            self.reset();
+                            Self::push(&mut out, SmtpReply::new(
+                                SmtpResponseCode::TransactionFailed,
+                                "5.5.2 Bare <LF> line endings are not allowed in message data",
+                            ));
+                        }
+                        None => return Ok(SessionEnd::Closed),
+                    }
+                }
+                SmtpCommand::Rset => {
+                    self.reset();
+                    Self::push(&mut out, SmtpReply::new(
+                        SmtpResponseCode::RequestedMailActionOkayCompleted, "2.0.0 Ok"));
+                }
+                SmtpCommand::Noop => {
+                    Self::push(&mut out, SmtpReply::new(
+                        SmtpResponseCode::RequestedMailActionOkayCompleted, "2.0.0 Ok"));
+                }
+                SmtpCommand::Help(_) => {
+                    Self::push(&mut out, SmtpReply::new(
+                        SmtpResponseCode::HelpMessage, "2.0.0 See RFC 5321"));
+                }
+                SmtpCommand::Vrfy(_) => {
+                    Self::push(&mut out, SmtpReply::new(
+                        SmtpResponseCode::CannotVerifyUserButWillAttemptDelivery,
+                        "2.5.0 Cannot verify user, but will accept message and attempt delivery",
+                    ));
+                }
+                SmtpCommand::Auth(arg) if self.auth_offered() => {
+                    match res!(self.auth(&mut stream, &mut out, &arg).await) {
+                        Some(reply) => Self::push(&mut out, reply),
+                        None => return Ok(SessionEnd::Closed),
+                    }
+                }
+                SmtpCommand::Expn(_) | SmtpCommand::Auth(_) => {
+                    Self::push(&mut out, SmtpReply::new(
+                        SmtpResponseCode::CommandNotImplemented, "5.5.1 Command not implemented"));
+                }
+                SmtpCommand::StartTls => {
+                    if self.cfg.tls.is_none() || self.envelope.tls {
+                        Self::push(&mut out, SmtpReply::new(
+                            SmtpResponseCode::CommandNotImplemented, "5.5.1 STARTTLS not available"));
+                        continue;
+                    }
+                    Self::push(&mut out, SmtpReply::new(
+                        SmtpResponseCode::ServiceReady, "2.0.0 Ready to start TLS"));
+                    res!(Self::flush(&mut stream, &mut out).await);
+                    // Dropping the buffer discards anything the client sent before the handshake,
+                    // which could otherwise be mistaken for commands sent over TLS.
+                    return Ok(SessionEnd::StartTls(stream.into_inner()));
+                }
+                SmtpCommand::Quit => {
+                    Self::push(&mut out, SmtpReply::new(
+                        SmtpResponseCode::ServiceClosingTransmissionChannel, "2.0.0 Bye"));
+                    res!(Self::flush(&mut stream, &mut out).await);
+                    return Ok(SessionEnd::Closed);
+                }
+                // Not commands a client can send.
+                SmtpCommand::Response(..) | SmtpCommand::Email(_) => {
+                    self.error(&mut out, SmtpResponseCode::CommandUnrecognized,
+                        "5.5.2 Command unrecognised");
+                }
+            }
+        }
+    }
+
+    fn mail(&mut self, line: &str, address: String) -> SmtpReply {
+        match self.state {
+            SmtpState::Greeted => (),
+            SmtpState::Connected => return SmtpReply::new(
+                SmtpResponseCode::BadSequenceOfCommands, "5.5.1 Send HELO or EHLO first"),
+            SmtpState::Mail | SmtpState::Rcpt => return SmtpReply::new(
+                SmtpResponseCode::BadSequenceOfCommands, "5.5.1 Nested MAIL command"),
+        }
+        let params = match SmtpCommand::esmtp_params(line) {
+            Ok(params) => params,
+            Err(_) => return SmtpReply::new(
+                SmtpResponseCode::SyntaxErrorInParameters, "5.5.4 Syntax error in parameters"),
+        };
+        for (k, v) in params {
+            match (self.extended, k.as_str(), v.as_deref()) {
+                (true, "SIZE", Some(size)) => match size.parse::<usize>() {
+                    Ok(size) if size > self.cfg.max_message_size => return SmtpReply::new(
+                        SmtpResponseCode::ExceededStorageAllocation,
+                        "5.3.4 Message size exceeds fixed maximum message size",
+                    ),
+                    Ok(_) => (),
+                    Err(_) => return SmtpReply::new(
+                        SmtpResponseCode::SyntaxErrorInParameters, "5.5.4 Invalid SIZE parameter"),
+                },
+                (true, "BODY", Some(body))
+                    if body.eq_ignore_ascii_case("7BIT") || body.eq_ignore_ascii_case("8BITMIME") => (),
+                _ => return SmtpReply::new(
+                    SmtpResponseCode::MailFromRcptToParametersNotRecognizedOrImplemented,
+                    fmt!("5.5.4 Unsupported parameter {}", k),
+                ),
+            }
+        }
+        self.envelope.from = address;
+        self.state = SmtpState::Mail;
+        SmtpReply::new(SmtpResponseCode::RequestedMailActionOkayCompleted, "2.1.0 Ok")
+    }
+
+    fn rcpt(&mut self, line: &str, address: String) -> SmtpReply {
+        match self.state {
+            SmtpState::Mail | SmtpState::Rcpt => (),
+            _ => return SmtpReply::new(
+                SmtpResponseCode::BadSequenceOfCommands, "5.5.1 Send MAIL first"),
+        }
+        match SmtpCommand::esmtp_params(line) {
+            Ok(params) if params.is_empty() => (),
+            _ => return SmtpReply::new(
+                SmtpResponseCode::MailFromRcptToParametersNotRecognizedOrImplemented,
+                "5.5.4 Unsupported parameters",
+            ),
+        }
+        if self.envelope.to.len() >= self.cfg.max_recipients {
+            return SmtpReply::new(
+                SmtpResponseCode::InsufficientSystemStorage, "4.5.3 Too many recipients");
+        }
+        if address.is_empty() || !self.handler.accept_recipient(&address) {
+            return SmtpReply::new(
+                SmtpResponseCode::MailboxUnavailableOrAccessDenied, "5.1.1 Mailbox unavailable");
+        }
+        self.envelope.to.push(address);
+        self.state = SmtpState::Rcpt;
+        SmtpReply::new(SmtpResponseCode::RequestedMailActionOkayCompleted, "2.1.5 Ok")
+    }
+
+    fn auth_offered(&self) -> bool {
+        self.cfg.auth && self.envelope.tls && self.extended
+    }
+
+    /// Authenticate the client using the PLAIN (RFC 4616) or LOGIN mechanism, returning the final
+    /// reply, or `None` if the connection was lost.
+    async fn auth<S: AsyncRead + AsyncWrite + Unpin + Send>(
+        &mut self,
+        stream: &mut BufReader<S>,
+        out:    &mut Vec<u8>,
+        arg:    &str,
+    )
+        -> Outcome<Option<SmtpReply>>
+    {
+        if self.envelope.user.is_some() {
+            return Ok(Some(SmtpReply::new(
+                SmtpResponseCode::BadSequenceOfCommands, "5.5.1 Already authenticated")));
+        }
+        if self.state != SmtpState::Greeted {
+            return Ok(Some(SmtpReply::new(
+                SmtpResponseCode::BadSequenceOfCommands, "5.5.1 AUTH not permitted during a mail transaction")));
+        }
+        let (mechanism, initial) = match arg.split_once(' ') {
+            Some((mechanism, initial)) => (mechanism.to_uppercase(), Some(initial.to_string())),
+            None => (arg.to_uppercase(), None),
+        };
+        let (username, password) = match mechanism.as_str() {
+            "PLAIN" => {
+                let response = match initial {
+                    Some(initial) => Some(initial),
+                    None => res!(self.challenge(stream, out, "").await),
+                };
+                let plain = match response.map(|r| Self::decode(&r)) {
+                    Some(Some(plain)) => plain,
+                    Some(None) => return Ok(Some(SmtpReply::new(
+                        SmtpResponseCode::SyntaxErrorInParameters, "5.5.2 Invalid or cancelled response"))),
+                    None => return Ok(None),
+                };
+                // The authorisation identity, if any, must be the authenticated user.
+                let mut parts = plain.splitn(3, '\0');
+                match (parts.next(), parts.next(), parts.next()) {
+                    (Some(authz), Some(user), Some(pass)) if authz.is_empty() || authz == user =>
+                        (user.to_string(), pass.to_string()),
+                    _ => return Ok(Some(SmtpReply::new(
+                        SmtpResponseCode::SyntaxErrorInParameters, "5.5.2 Invalid PLAIN response"))),
+                }
+            }
+            "LOGIN" => {
+                let mut fields = Vec::new();
+                for (i, prompt) in ["Username:", "Password:"].iter().enumerate() {
+                    let response = match (i, &initial) {
+                        (0, Some(initial)) => Some(initial.clone()),
+                        _ => res!(self.challenge(stream, out, &base64::encode(prompt)).await),
+                    };
+                    match response.map(|r| Self::decode(&r)) {
+                        Some(Some(field)) => fields.push(field),
+                        Some(None) => return Ok(Some(SmtpReply::new(
+                            SmtpResponseCode::SyntaxErrorInParameters, "5.5.2 Invalid or cancelled response"))),
+                        None => return Ok(None),
+                    }
+                }
+                let password = fields.pop().unwrap_or_default();
+                (fields.pop().unwrap_or_default(), password)
+            }
+            _ => return Ok(Some(SmtpReply::new(
+                SmtpResponseCode::CommandParameterNotImplemented,
+                "5.5.4 Unrecognised authentication mechanism",
+            ))),
+        };
+        if self.handler.authenticate(&username, &password) {
+            debug!("{}: Authenticated as '{}'.", self.id, username);
+            self.envelope.user = Some(username);
+            Ok(Some(SmtpReply::new(
+                SmtpResponseCode::AuthenticationSuccessful, "2.7.0 Authentication successful")))
+        } else {
+            self.errors += 1;
+            Ok(Some(SmtpReply::new(
+                SmtpResponseCode::AuthenticationCredentialsInvalid,
+                "5.7.8 Authentication credentials invalid",
+            )))
+        }
+    }
+
+    /// Send an authentication challenge and return the response, or `None` if the connection was
+    /// lost.
+    async fn challenge<S: AsyncRead + AsyncWrite + Unpin + Send>(
+        &self,
+        stream:     &mut BufReader<S>,
+        out:        &mut Vec<u8>,
+        challenge:  &str,
+    )
+        -> Outcome<Option<String>>
+    {
+        Self::push(out, SmtpReply::new(SmtpResponseCode::AuthInputData, challenge));
+        res!(Self::flush(stream, out).await);
+        match res!(self.read_line(stream, constant::SMTP_MAX_COMMAND_LINE).await) {
+            Line::Complete(line) => Ok(Some(
+                String::from_utf8_lossy(&line).trim_end_matches(['\r', '\n']).to_string()
+            )),
+            Line::TooLong { .. } => Ok(Some(fmt!("*"))),
+            Line::Eof | Line::Timeout => Ok(None),
+        }
+    }
+
+    /// Decode a base64 authentication response, with `None` for an invalid or cancelled response.
+    fn decode(response: &str) -> Option<String> {
+        match response {
+            "*" => return None,
+            "=" => return Some(String::new()),
+            _ => (),
+        }
+        base64::decode(response).ok().and_then(|byts| String::from_utf8(byts).ok())
+    }
+
+    /// Hand the message to the handler, and start a new transaction.
+    async fn deliver(&mut self) -> SmtpReply {
+        let mut envelope = SmtpEnvelope {
+            client: self.envelope.client.clone(),
+            tls:    self.envelope.tls,
+            user:   self.envelope.user.clone(),
+            ..Default::default()
+        };
+        std::mem::swap(&mut envelope, &mut self.envelope);
+        self.state = SmtpState::Greeted;
+        match self.handler.handle_email(envelope, &self.id).await {
+            Ok(reply) => reply,
+            Err(e) => {
+                error!(e, "{}: While handling an email.", self.id);
+                SmtpReply::new(
+                    SmtpResponseCode::LocalErrorInProcessing, "4.3.0 Local error in processing")
+            }
+        }
+    }
+
+    /// Read the message following a DATA command into the envelope, returning whether it fits
+    /// within the size limit and uses only CRLF line endings, or `None` if the connection was
+    /// lost.  Only `.<CRLF>` following a CRLF ends the data, so that a bare LF cannot be used to
+    /// smuggle commands past another server that interprets it differently.
+    async fn read_data<R: AsyncBufRead + Unpin>(
+        &mut self,
+        stream: &mut R,
+    )
+        -> Outcome<Option<DataEnd>>
+    {
+        let max = self.cfg.max_message_size;
+        let mut fits = true;
+        let mut bare_newline = false;
+        let mut after_crlf = true;
+        loop {
+            let mut line = match res!(self.read_line(stream, max + 3).await) {
+                Line::Complete(line) => line,
+                Line::TooLong { crlf } => {
+                    fits = false;
+                    after_crlf = crlf;
+                    bare_newline |= !crlf;
+                    continue;
+                }
+                Line::Eof | Line::Timeout => return Ok(None),
+            };
+            if after_crlf && line == b".\r\n" {
+                break;
+            }
+            after_crlf = line.ends_with(b"\r\n");
+            if !after_crlf {
+                bare_newline = true;
+            }
+            if line.starts_with(b".") {
+                line.remove(0);
+            }
+            if fits && !bare_newline && self.envelope.data.len() + line.len() <= max {
+                self.envelope.data.extend_from_slice(&line);
+            } else {
+                fits = false;
+                self.envelope.data.clear();
+            }
+        }
+        Ok(Some(if bare_newline {
+            DataEnd::BareNewline
+        } else if fits {
+            DataEnd::Complete
+        } else {
+            DataEnd::TooBig
+        }))
+    }
+
+    /// Read a line, including its ending, discarding lines longer than the given limit.
+    async fn read_line<R: AsyncBufRead + Unpin>(
+        &self,
+        stream: &mut R,
+        max:    usize,
+    )
+        -> Outcome<Line>
+    {
+        let mut line = Vec::new();
+        let mut too_long = false;
+        let mut prev = None;
+        loop {
+            let buf = match tokio::time::timeout(self.cfg.timeout, stream.fill_buf()).await {
+                Ok(result) => res!(result, IO, Network, Read),
+                Err(_) => return Ok(Line::Timeout),
+            };
+            if buf.is_empty() {
+                return Ok(Line::Eof);
+            }
+            let (n, complete) = match buf.iter().position(|b| *b == b'\n') {
+                Some(i) => (i + 1, true),
+                None => (buf.len(), false),
+            };
+            if !too_long {
+                if line.len() + n > max {
+                    too_long = true;
+                    line.clear();
+                } else {
+                    line.extend_from_slice(&buf[..n]);
+                }
+            }
+            let crlf = match n {
+                1 => prev == Some(b'\r'),
+                _ => buf[n - 2] == b'\r',
+            };
+            prev = buf.get(n - 1).copied();
+            stream.consume(n);
+            if complete {
+                return Ok(if too_long { Line::TooLong { crlf } } else { Line::Complete(line) });
+            }
+        }
+    }
+
+    /// Abandon any mail transaction.
+    fn reset(&mut self) {
+        self.envelope.from.clear();
+        self.envelope.to.clear();
+        self.envelope.data.clear();
+        if self.state != SmtpState::Connected {
+            self.state = SmtpState::Greeted;
+        }
+    }
+
+    /// Reply to a client error, closing the session if there have been too many.
+    fn error(&mut self, out: &mut Vec<u8>, code: SmtpResponseCode, text: &str) {
+        self.errors += 1;
+        if self.errors >= self.cfg.max_errors {
+            Self::push(out, SmtpReply::new(
+                SmtpResponseCode::ServiceNotAvailableClosingTransmissionChannel,
+                "4.7.0 Too many errors, closing connection",
+            ));
+        } else {
+            Self::push(out, SmtpReply::new(code, text));
+        }
+    }
+
+    fn push(out: &mut Vec<u8>, reply: SmtpReply) {
+        out.extend_from_slice(reply.to_string().as_bytes());
+    }
+
+    async fn flush<W: AsyncWrite + Unpin>(stream: &mut W, out: &mut Vec<u8>) -> Outcome<()> {
+        res!(stream.write_all(out).await, IO, Network, Write);
+        res!(stream.flush().await, IO, Network, Write);
+        out.clear();
+        Ok(())
+    }
+}
@@ -9,6 +9,7 @@ use crate::{
         msg::HttpMessage,
     },
     ws::{
+        deflate::WebSocketDeflate,
         handler::WebSocketHandler,
         status::WebSocketStatusCode,
     },
@@ -79,13 +80,15 @@ pub fn connect_request(
         Upgrade: websocket\r\n\
         Connection: Upgrade\r\n\
         Sec-WebSocket-Key: {}\r\n\
-        Sec-WebSocket-Version: 13\r\n\r\n",
-        host, key_str.clone(),
+        Sec-WebSocket-Version: 13\r\n\
+        Sec-WebSocket-Extensions: {}\r\n\r\n",
+        host, key_str.clone(), WebSocketDeflate::offer(),
     );
     Ok((
         HttpMessage {
             header: res!(HttpHeader::parse(msg, Some(true))),
             body:   Vec::new(),
+            ..Default::default()
         },
         key_str,
     ))
@@ -103,11 +106,17 @@ pub struct WebSocket<
 > {
     stream:         Pin<&'a mut S>,
     is_server:      bool,
-    buffer:         Vec<u8>,
+    inbuf:          Vec<u8>, // Bytes received but not yet forming a complete frame.
+    buffer:         Vec<u8>, // Payload of a fragmented message received so far.
+    frag:           Option<(u8, bool)>, // Opcode and compression of a fragmented message.
+    failed:         bool,
     pub latency:    RingBuffer<{ constant::WEBSOCKET_LATENCY_HISTORY_SIZE }, Option<u16>>,
     pub handler:    WSH,
     chunk_size:     usize,
     chunk_thresh:   usize,
+    max_msg_size:   usize,
+    allow_deflate:  bool,
+    deflate:        Option<WebSocketDeflate>,
     phantom1:       PhantomData<UID>,
     phantom2:       PhantomData<ENC>,
     phantom3:       PhantomData<KH>,
@@ -137,11 +146,17 @@ impl<
         Self {
             stream:         Pin::new(stream),
             is_server:      false,
+            inbuf:          Vec::new(),
             buffer:         Vec::new(),
+            frag:           None,
+            failed:         false,
             latency:        RingBuffer::default(),
             handler,
             chunk_size,
             chunk_thresh,
+            max_msg_size:   constant::WEBSOCKET_MAX_MESSAGE_SIZE,
+            allow_deflate:  true,
+            deflate:        None,
             phantom1:       PhantomData,
             phantom2:       PhantomData,
             phantom3:       PhantomData,
@@ -160,11 +175,17 @@ impl<
         Self {
             stream:         Pin::new(stream),
             is_server:      true,
+            inbuf:          Vec::new(),
             buffer:         Vec::new(),
+            frag:           None,
+            failed:         false,
             latency:        RingBuffer::default(),
             handler,
             chunk_size,
             chunk_thresh,
+            max_msg_size:   constant::WEBSOCKET_MAX_MESSAGE_SIZE,
+            allow_deflate:  true,
+            deflate:        None,
             phantom1:       PhantomData,
             phantom2:       PhantomData,
             phantom3:       PhantomData,
@@ -172,8 +193,21 @@ impl<
         }
     }
 
+    /// Limit the size of a received message, after any decompression.
+    pub fn with_max_message_size(mut self, max_msg_size: usize) -> Self {
+        self.max_msg_size = max_msg_size;
+        self
+    }
+
+    /// Whether a server accepts an offer by the client to use the permessage-deflate extension.
+    pub fn with_deflate(mut self, allow_deflate: bool) -> Self {
+        self.allow_deflate = allow_deflate;
+        self
+    }
+
     pub fn is_server(&self) -> bool { self.is_server }
     pub fn is_client(&self) -> bool { !self.is_server }
+    pub fn is_deflate(&self) -> bool { self.deflate.is_some() }
 
     pub async fn connect(
         &mut self,
@@ -203,6 +237,7 @@ impl<
     )
         -> Outcome<()>
     {
+        let offered = !Self::extension_values(&request).is_empty();
         let result = request.write_all(&mut self.stream).await;
         res!(result);
         let result = HttpMessage::read::<
@@ -216,6 +251,16 @@ impl<
                 let accept_key = Self::accept_key(&key);
 
                 if response.is_websocket_handshake(&accept_key) {
+                    let accepted = Self::extension_values(&response);
+                    if !accepted.is_empty() {
+                        if !offered {
+                            return Err(err!(
+                                "Server accepted websocket extensions {:?} that were not offered.",
+                                accepted;
+                            IO, Network, Invalid, Input));
+                        }
+                        self.deflate = Some(res!(WebSocketDeflate::accepted(&accepted)));
+                    }
                     info!("Client connection successfully upgraded to a websocket.");
                 } else {
                     return Err(err!(
@@ -234,6 +279,14 @@ impl<
         Ok(())
     }
 
+    /// The values of any `Sec-WebSocket-Extensions` fields in the message.
+    fn extension_values(msg: &HttpMessage) -> Vec<String> {
+        match msg.header.fields.get_list(&HeaderName::SecWebSocketExtensions) {
+            Some(list) => list.iter().map(|v| v.to_string()).collect(),
+            None => Vec::new(),
+        }
+    }
+
     /// The accept key is just a hash of the incoming request key.
     pub fn accept_key(key: &String) -> String {
         let concatenated = fmt!("{}{}", key, constant::WEBSOCKET_GUID);
@@ -274,12 +327,24 @@ impl<
 
         let accept_key = Self::accept_key(&key);
 
+        let mut extensions = String::new();
+        if self.allow_deflate {
+            if let Some((deflate, accepted)) = WebSocketDeflate::accept(
+                &Self::extension_values(&request)
+            ) {
+                self.deflate = Some(deflate);
+                extensions = fmt!("Sec-WebSocket-Extensions: {}\r\n", accepted);
+            }
+        }
+
         let response = fmt!(
             "HTTP/1.1 101 Switching Protocols\r\n\
             Upgrade: websocket\r\n\
             Connection: Upgrade\r\n\
-            Sec-WebSocket-Accept: {}\r\n\r\n",
+            Sec-WebSocket-Accept: {}\r\n\
+            {}\r\n",
             accept_key,
+            extensions,
         );
 
         match self.stream.write_all(response.as_bytes()).await {
@@ -294,186 +359,251 @@ impl<
         Ok(())
     }
 
+    /// Read the next message.  The frames of a fragmented message are reassembled, and control
+    /// frames arriving between them are returned as they arrive.  A protocol violation by the
+    /// peer fails the connection, sending a close frame with an appropriate status before
+    /// returning the error, after which reading returns `None`.  The read state is kept between
+    /// calls, so the future can safely be dropped, e.g. in a `tokio::select!`.
     pub async fn read(&mut self) -> Outcome<Option<WebSocketMessage>> {
-        let mut is_final_frame = false;
-        let mut opcode = 0;
-    
-        while !is_final_frame {
-            // Read the first byte of the frame header.
-            let mut header_byte = [0u8; 1];
-            let result = self.stream.read_exact(&mut header_byte).await;
-            match result {
-                Ok(_n) => (),
-                Err(e) if e.kind() == tokio::io::ErrorKind::UnexpectedEof => {
-                    return Ok(None);
-                }
-                Err(e) => return Err(err!(e,
-                    "While trying to read first byte of the frame header.";
-                IO, Network, Read, Wire)),
+        loop {
+            if self.failed {
+                return Ok(None);
             }
-    
-            // Extract the FIN bit and opcode from the header byte.
-            is_final_frame = (header_byte[0] & 0x80) != 0;
-            
-            if opcode == 0 {
-                opcode = header_byte[0] & 0x0F;
+            let frame = match res!(self.read_frame().await) {
+                Some(frame) => frame,
+                None => return Ok(None),
+            };
+            if let Some(message) = res!(self.receive_frame(frame).await) {
+                return Ok(Some(message));
             }
-        
-            // Read the second byte of the frame header.
-            let mut length_byte = [0u8; 1];
-            let result = self.stream.read_exact(&mut length_byte).await;
-            match result {
-                Ok(_n) => (),
+        }
+    }
+
+    /// Read the next complete frame, returning its FIN bit, RSV1 bit, opcode and unmasked
+    /// payload.
+    async fn read_frame(&mut self) -> Outcome<Option<(bool, bool, u8, Vec<u8>)>> {
+        loop {
+            if let Some(frame) = res!(self.parse_frame().await) {
+                return Ok(Some(frame));
+            }
+            let mut chunk = vec![0u8; self.chunk_size.max(1)];
+            match self.stream.read(&mut chunk).await {
+                Ok(0) => return Ok(None),
+                Ok(n) => self.inbuf.extend_from_slice(&chunk[..n]),
                 Err(e) if e.kind() == tokio::io::ErrorKind::UnexpectedEof => {
                     return Ok(None);
                 }
                 Err(e) => return Err(err!(e,
-                    "While trying to read second byte of the frame header.";
+                    "While trying to read websocket frame.";
                 IO, Network, Read, Wire)),
             }
-        
-            // Extract the payload length and mask flag from the length byte.
-            let masked = (length_byte[0] & 0x80) != 0;
-            let payload_length = match length_byte[0] & 0x7F {
-                127 => {
-                    // 64-bit extended payload length.
-                    let mut extended_length_bytes = [0u8; 8];
-                    let result = self.stream.read_exact(&mut extended_length_bytes).await;
-                    match result {
-                        Ok(_n) => (),
-                        Err(e) if e.kind() == tokio::io::ErrorKind::UnexpectedEof => {
-                            return Ok(None);
-                        }
-                        Err(e) => return Err(err!(e,
-                            "While trying to read second byte of the frame header.";
-                        IO, Network, Read, Wire)),
-                    }
-                    u64::from_be_bytes(extended_length_bytes) as usize
-                }
-                126 => {
-                    // 16-bit extended payload length.
-                    let mut extended_length_bytes = [0u8; 2];
-                    let result = self.stream.read_exact(&mut extended_length_bytes).await;
-                    match result {
-                        Ok(_n) => (),
-                        Err(e) if e.kind() == tokio::io::ErrorKind::UnexpectedEof => {
-                            return Ok(None);
-                        }
-                        Err(e) => return Err(err!(e,
-                            "While trying to read second byte of the frame header.";
-                        IO, Network, Read, Wire)),
-                    }
-                    u16::from_be_bytes(extended_length_bytes) as usize
-                }
-                len => len as usize,
-            };
+        }
+    }
 
-            // Read the masking key if the frame is masked.
-            let mut masking_key = [0u8; 4];
-            if masked {
-                let result = self.stream.read_exact(&mut masking_key).await;
-                match result {
-                    Ok(_n) => (),
-                    Err(e) if e.kind() == tokio::io::ErrorKind::UnexpectedEof => {
-                        return Ok(None);
-                    }
-                    Err(e) => return Err(err!(e,
-                        "While trying to read second byte of the frame header.";
-                    IO, Network, Read, Wire)),
-                }
-            }
-        
-            // Read the (possibly fragmented) payload data.
-            let mut payload = vec![0u8; payload_length];
-            let mut bytes_read = 0;
-            while bytes_read < payload_length {
-                let chunk_size = std::cmp::min(
-                    self.chunk_size,
-                    payload_length - bytes_read,
-                );
-                let result = self.stream.read_exact(&mut payload[
-                    bytes_read..bytes_read + chunk_size
-                ]).await;
-                match result {
-                    Ok(_n) => {
-                        // Unmask the payload chunk if the frame is masked.
-                        if masked {
-                            for i in 0..chunk_size {
-                                payload[bytes_read + i] ^= masking_key[(bytes_read + i) % 4];
-                            }
-                        }
-                        bytes_read += chunk_size;
-                        self.buffer.extend_from_slice(&payload[
-                            bytes_read - chunk_size..bytes_read
-                        ]);
-                    }
-                    Err(e) if e.kind() == tokio::io::ErrorKind::UnexpectedEof => {
-                        return Ok(None);
-                    }
-                    Err(e) => return Err(err!(e,
-                        "While trying to read payload chunk.";
-                    IO, Network, Read, Wire)),
-                }
-            }
+    /// Remove a frame from the bytes received so far, if complete.  The header is validated as
+    /// soon as it arrives.
+    async fn parse_frame(&mut self) -> Outcome<Option<(bool, bool, u8, Vec<u8>)>> {
+        if self.inbuf.len() < 2 {
+            return Ok(None);
+        }
+        let fin = (self.inbuf[0] & 0x80) != 0;
+        let rsv1 = (self.inbuf[0] & 0x40) != 0;
+        let rsv23 = self.inbuf[0] & 0x30;
+        let opcode = self.inbuf[0] & 0x0F;
+        let masked = (self.inbuf[1] & 0x80) != 0;
+        let (payload_length, mut start) = match self.inbuf[1] & 0x7F {
+            127 => match self.inbuf.get(2..10) {
+                // 64-bit extended payload length.
+                Some(byts) => (u64::from_be_bytes(res!(<[u8; 8]>::try_from(byts))), 10),
+                None => return Ok(None),
+            },
+            126 => match self.inbuf.get(2..4) {
+                // 16-bit extended payload length.
+                Some(byts) => (u16::from_be_bytes(res!(<[u8; 2]>::try_from(byts))) as u64, 4),
+                None => return Ok(None),
+            },
+            len => (len as u64, 2),
+        };
+        let is_control = opcode >= 0x8;
 
-            // Unmask the payload if the frame is masked.
-            if masked {
-                for i in 0..payload_length {
-                    payload[i] ^= masking_key[i % 4];
-                }
-            }
+        let violation = if payload_length & 0x8000_0000_0000_0000 != 0 {
+            // RFC 6455 5.2, the most significant bit of a 64-bit length must be zero.
+            Some((WebSocketStatusCode::ProtocolError, fmt!(
+                "The most significant bit of the 64-bit payload length {:#x} is set.",
+                payload_length)))
+        } else if rsv23 != 0 {
+            Some((WebSocketStatusCode::ProtocolError, fmt!("Reserved frame bits {:#x} are set.", rsv23)))
+        } else if rsv1 && (self.deflate.is_none() || is_control || opcode == 0x0) {
+            Some((WebSocketStatusCode::ProtocolError, fmt!(
+                "The RSV1 bit is set on a frame with opcode {}, but only the first frame of a \
+                data message may be compressed.", opcode)))
+        } else if !matches!(opcode, 0x0 | 0x1 | 0x2 | 0x8 | 0x9 | 0xA) {
+            Some((WebSocketStatusCode::ProtocolError, fmt!("Unknown opcode: {}", opcode)))
+        } else if is_control && (!fin || payload_length > 125) {
+            Some((WebSocketStatusCode::ProtocolError, fmt!(
+                "Control frame with opcode {} is fragmented or has a payload of {} bytes, \
+                exceeding 125.", opcode, payload_length)))
+        } else if masked != self.is_server {
+            Some((WebSocketStatusCode::ProtocolError, fmt!(
+                "Frames from a {} must {}be masked.",
+                if self.is_server { "client" } else { "server" },
+                if self.is_server { "" } else { "not " })))
+        } else if opcode == 0x0 && self.frag.is_none() {
+            Some((WebSocketStatusCode::ProtocolError, fmt!(
+                "Continuation frame received without a message to continue.")))
+        } else if (opcode == 0x1 || opcode == 0x2) && self.frag.is_some() {
+            Some((WebSocketStatusCode::ProtocolError, fmt!(
+                "New data message started before the previous fragmented message was finished.")))
+        } else if !is_control && !matches!(
+            payload_length.checked_add(self.buffer.len() as u64),
+            Some(len) if len <= self.max_msg_size as u64,
+        ) {
+            Some((WebSocketStatusCode::MessageTooBig, fmt!(
+                "Websocket message exceeds the limit of {} bytes.", self.max_msg_size)))
+        } else {
+            None
+        };
+        if let Some((code, msg)) = violation {
+            return Err(self.fail(code, msg).await);
         }
-        
-        // Construct the appropriate WebSocketMessage variant based on the opcode.
-        let message = match opcode {
-            0x0 => {
-                // Continuation frame (not supported in this example).
-                unimplemented!("Continuation frames are not supported");
-            }
-            0x1 => {
-                // Text frame.
-                let text = res!(std::str::from_utf8(&self.buffer)).to_string();
-                WebSocketMessage::Text(text)
+
+        let mut masking_key = [0u8; 4];
+        if masked {
+            match self.inbuf.get(start..start + 4) {
+                Some(byts) => masking_key.copy_from_slice(byts),
+                None => return Ok(None),
             }
-            0x2 => {
-                // Binary frame.
-                WebSocketMessage::Binary(self.buffer.clone())
+            start += 4;
+        }
+        let end = match usize::try_from(payload_length).ok().and_then(|len| start.checked_add(len)) {
+            Some(end) => end,
+            None => return Err(self.fail(
+                WebSocketStatusCode::MessageTooBig,
+                fmt!("The frame payload length of {} bytes overflows.", payload_length),
+            ).await),
+        };
+        if self.inbuf.len() < end {
+            return Ok(None);
+        }
+        let mut payload = self.inbuf[start..end].to_vec();
+        self.inbuf.drain(..end);
+        if masked {
+            for (i, b) in payload.iter_mut().enumerate() {
+                *b ^= masking_key[i % 4];
             }
+        }
+        Ok(Some((fin, rsv1, opcode, payload)))
+    }
+
+    /// Add a frame to the message being received, returning any message completed.
+    async fn receive_frame(
+        &mut self,
+        (fin, rsv1, opcode, payload): (bool, bool, u8, Vec<u8>),
+    )
+        -> Outcome<Option<WebSocketMessage>>
+    {
+        // Control frames are never fragmented, but may arrive between the frames of a message.
+        match opcode {
             0x8 => {
                 // Close frame.
-                let status_code = if self.buffer.len() >= 2 {
-                    let nu16 = u16::from_be_bytes([self.buffer[0], self.buffer[1]]);
-                    let code = res!(WebSocketStatusCode::try_from(nu16));
-                    Some(code)
+                if payload.len() == 1 {
+                    return Err(self.fail(
+                        WebSocketStatusCode::ProtocolError,
+                        fmt!("Close frame payload of 1 byte is too short for a status code."),
+                    ).await);
+                }
+                let status_code = if payload.len() >= 2 {
+                    let nu16 = u16::from_be_bytes([payload[0], payload[1]]);
+                    // Codes 1005, 1006 and 1015 are reserved for local use and never sent.
+                    match WebSocketStatusCode::try_from(nu16) {
+                        Ok(WebSocketStatusCode::NoStatusReceived)
+                        | Ok(WebSocketStatusCode::AbnormalClosure)
+                        | Ok(WebSocketStatusCode::TlsHandshake)
+                        | Err(_) => return Err(self.fail(
+                            WebSocketStatusCode::ProtocolError,
+                            fmt!("Close frame status code {} is not valid.", nu16),
+                        ).await),
+                        Ok(code) => Some(code),
+                    }
                 } else {
                     None
                 };
-                let reason = if self.buffer.len() > 2 {
-                    Some(res!(std::str::from_utf8(&self.buffer[2..])).to_string())
+                let reason = if payload.len() > 2 {
+                    match std::str::from_utf8(&payload[2..]) {
+                        Ok(s) => Some(s.to_string()),
+                        Err(e) => return Err(self.fail(
+                            WebSocketStatusCode::InvalidFramePayloadData,
+                            fmt!("Close frame reason is not valid UTF-8: {}", e),
+                        ).await),
+                    }
                 } else {
                     None
                 };
-                WebSocketMessage::Close(status_code, reason)
-            }
-            0x9 => {
-                // Ping frame.
-                WebSocketMessage::Ping(self.buffer.clone())
-            }
-            0xA => {
-                // Pong frame.
-                WebSocketMessage::Pong(self.buffer.clone())
+                return Ok(Some(WebSocketMessage::Close(status_code, reason)));
             }
+            0x9 => return Ok(Some(WebSocketMessage::Ping(payload))), // Ping frame.
+            0xA => return Ok(Some(WebSocketMessage::Pong(payload))), // Pong frame.
+            0x0 => self.buffer.extend_from_slice(&payload), // Continuation frame.
             _ => {
-                // Unknown opcode.
-                return Err(err!("Unknown opcode: {}", opcode; IO, Network, Invalid, Input));
+                self.frag = Some((opcode, rsv1));
+                self.buffer = payload;
             }
+        }
+        if !fin {
+            return Ok(None);
+        }
+
+        let (opcode, compressed) = match self.frag.take() {
+            Some(frag) => frag,
+            None => return Ok(None),
         };
-    
-        // Clear the buffer for the next message.
-        self.buffer.clear();
-    
-        Ok(Some(message))
-    }    
+        let mut data = std::mem::take(&mut self.buffer);
+        if compressed {
+            let max_size = self.max_msg_size;
+            let result = match &mut self.deflate {
+                Some(deflate) => deflate.decompress(&data, max_size),
+                None => Err(err!("Compressed message received without deflate."; Bug)),
+            };
+            data = match result {
+                Ok(data) => data,
+                Err(e) => {
+                    let code = if e.tags().contains(&ErrTag::TooBig) {
+                        WebSocketStatusCode::MessageTooBig
+                    } else {
+                        WebSocketStatusCode::InvalidFramePayloadData
+                    };
+                    return Err(self.fail(code, fmt!("{}", e)).await);
+                }
+            };
+        }
+        // Construct the appropriate WebSocketMessage variant based on the opcode.
+        Ok(Some(match opcode {
+            0x1 => match String::from_utf8(data) {
+                Ok(text) => WebSocketMessage::Text(text),
+                Err(e) => return Err(self.fail(
+                    WebSocketStatusCode::InvalidFramePayloadData,
+                    fmt!("Text message is not valid UTF-8: {}", e),
+                ).await),
+            },
+            _ => WebSocketMessage::Binary(data),
+        }))
+    }
+
+    /// Fail the connection, making a best effort to tell the peer why.
+    async fn fail(
+        &mut self,
+        code:   WebSocketStatusCode,
+        msg:    String,
+    )
+        -> Error<ErrTag>
+    {
+        self.failed = true;
+        if let Err(e) = self.send(&WebSocketMessage::Close(Some(code), None)).await {
+            warn!("While sending close frame after websocket failure: {}", e);
+        }
+        err!("Websocket connection failed ({:?}): {}", code, msg;
+            IO, Network, Invalid, Input)
+    }
 
     pub async fn send(
         &mut self,
@@ -491,7 +621,7 @@ impl<
         };
     
         // Get the payload data and its length.
-        let payload = match message {
+        let mut payload = match message {
             WebSocketMessage::Text(text) => text.as_bytes().to_vec(),
             WebSocketMessage::Binary(data) => data.clone(),
             WebSocketMessage::Ping(data) => data.clone(),
@@ -508,118 +638,108 @@ impl<
             }
         };
 
-        let payload_length = payload.len();
-    
-        // Generate masking key for client-side masking.
-        let mut masking_key = [0u8; 4];
-        if self.is_client() {
-            Rand::fill_u8(&mut masking_key);
-        }
-
-        // Determine if chunking is required based on the chunking threshold.
-        if payload_length > self.chunk_thresh {
-            // Send the message in chunks.
-            let mut bytes_sent = 0;
-            while bytes_sent < payload_length {
-                let remaining_bytes = payload_length - bytes_sent;
-                let chunk_size = std::cmp::min(remaining_bytes, self.chunk_size);
-                let is_final = remaining_bytes <= self.chunk_size;
-
-                // Use initial opcode for first frame, continuation (0x0) for others
-                let opcode = if bytes_sent == 0 { initial_opcode } else { 0x0 };
-
-                // Construct the frame header.
-                let mut header = Vec::new();
-
-                // First byte: FIN bit and opcode
-                header.push(if is_final { 0x80 | opcode } else { opcode });
-
-                // Second byte: Mask bit set for client-side masking and payload length.
-                let mask_bit = if self.is_client() { 0x80 } else { 0x00 };
-                if chunk_size <= 125 {
-                    header.push(mask_bit | chunk_size as u8);
-                } else if chunk_size <= 65535 {
-                    header.push(mask_bit | 126);
-                    header.extend_from_slice(&(chunk_size as u16).to_be_bytes());
-                } else {
-                    header.push(mask_bit | 127);
-                    header.extend_from_slice(&(chunk_size as u64).to_be_bytes());
+        if initial_opcode >= 0x8 {
+            // Control frames cannot be fragmented or compressed.
+            if payload.len() > 125 {
+                return Err(err!(
+                    "Control frame payload of {} bytes exceeds the limit of 125.", payload.len();
+                IO, Network, Input, Size, TooBig));
+            }
+            res!(self.write_frame(true, false, initial_opcode, &payload).await);
+        } else {
+            let mut compressed = false;
+            if payload.len() >= constant::WEBSOCKET_DEFLATE_THRESHOLD {
+                if let Some(deflate) = &mut self.deflate {
+                    payload = res!(deflate.compress(&payload));
+                    compressed = true;
                 }
-
-                // Write the masking key for client-side masking.
-                if self.is_client() {
-                    header.extend_from_slice(&masking_key);
+            }
+            // Determine if chunking is required based on the chunking threshold.
+            if payload.len() > self.chunk_thresh {
+                // Send the message in chunks, using the initial opcode for the first frame, and
+                // continuation (0x0) for the others.
+                let chunk_size = self.chunk_size.max(1);
+                let num_chunks = payload.len().div_ceil(chunk_size);
+                for (i, chunk) in payload.chunks(chunk_size).enumerate() {
+                    res!(self.write_frame(
+                        i == num_chunks - 1,
+                        compressed && i == 0,
+                        if i == 0 { initial_opcode } else { 0x0 },
+                        chunk,
+                    ).await);
                 }
+            } else {
+                res!(self.write_frame(true, compressed, initial_opcode, &payload).await);
+            }
+        }
+    
+        // Flush the stream.
+        let result = self.stream.flush().await;
+        res!(result);
+    
+        Ok(())
+    }
 
-                // Write the frame header to the stream.
-                let result = self.stream.write_all(&header).await;
-                res!(result);
-
-                // Mask and send payload chunk.
-                let mut chunk = payload[bytes_sent..bytes_sent + chunk_size].to_vec();
+    async fn write_frame(
+        &mut self,
+        fin:        bool,
+        rsv1:       bool,
+        opcode:     u8,
+        payload:    &[u8],
+    )
+        -> Outcome<()>
+    {
+        let payload_length = payload.len();
 
-                if self.is_client() {
-                    for i in 0..chunk_size {
-                        chunk[i] ^= masking_key[i % 4];
-                    }
-                }
+        // Construct the frame header.
+        let mut header = Vec::with_capacity(14);
 
-                // Write the payload chunk to the stream.
-                let result = self.stream.write_all(&chunk).await;
-                res!(result);
+        // First byte: FIN bit, RSV1 bit and opcode.
+        let mut byt = opcode;
+        if fin {
+            byt |= 0x80;
+        }
+        if rsv1 {
+            byt |= 0x40;
+        }
+        header.push(byt);
 
-                bytes_sent += chunk_size;
-            }
+        // Second byte: Mask bit set for client-side masking and payload length.
+        let mask_bit = if self.is_client() { 0x80 } else { 0x00 };
+        if payload_length <= 125 {
+            header.push(mask_bit | payload_length as u8);
+        } else if payload_length <= 65535 {
+            header.push(mask_bit | 126);
+            header.extend_from_slice(&(payload_length as u16).to_be_bytes());
         } else {
-            // Send the message as a single frame.
-            // Construct the frame header.
-            let mut header = Vec::new();
-
-            // First byte: FIN bit set and opcode.
-            header.push(0x80 | initial_opcode);
-
-            // Second byte: Mask bit set for client-side masking and payload length.
-            let mask_bit = if self.is_client() { 0x80 } else { 0x00 };
-            if payload_length <= 125 {
-                header.push(mask_bit | payload_length as u8);
-            } else if payload_length <= 65535 {
-                header.push(mask_bit | 126);
-                header.extend_from_slice(&(payload_length as u16).to_be_bytes());
-            } else {
-                header.push(mask_bit | 127);
-                header.extend_from_slice(&(payload_length as u64).to_be_bytes());
-            }
-
-            // Write the masking key for client-side masking.
-            if self.is_client() {
-                header.extend_from_slice(&masking_key);
-            }
+            header.push(mask_bit | 127);
+            header.extend_from_slice(&(payload_length as u64).to_be_bytes());
+        }
 
-            // Write the frame header to the stream.
+        // Write the frame header to the stream.
+        if self.is_client() {
+            // Each frame from a client is masked using a new key.
+            let mut masking_key = [0u8; 4];
+            Rand::fill_u8(&mut masking_key);
+            header.extend_from_slice(&masking_key);
             let result = self.stream.write_all(&header).await;
             res!(result);
-
-            // Apply masking to the payload for client-side masking.
-            let mut masked_payload = payload.clone();
-            if self.is_client() {
-                for i in 0..payload_length {
-                    masked_payload[i] ^= masking_key[i % 4];
-                }
-            }
-
-            // Write the masked payload to the stream.
+            let masked_payload: Vec<u8> = payload.iter()
+                .enumerate()
+                .map(|(i, b)| b ^ masking_key[i % 4])
+                .collect();
             let result = self.stream.write_all(&masked_payload).await;
             res!(result);
+        } else {
+            let result = self.stream.write_all(&header).await;
+            res!(result);
+            let result = self.stream.write_all(payload).await;
+            res!(result);
         }
-    
-        // Flush the stream.
-        let result = self.stream.flush().await;
-        res!(result);
-    
+
         Ok(())
     }
 
-
     pub async fn close(
         &mut self,
         status_code:    Option<WebSocketStatusCode>,
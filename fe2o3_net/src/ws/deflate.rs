@@ -0,0 +1,243 @@
+//! The permessage-deflate extension (RFC 7692), negotiated during the upgrade handshake using the
+//! `Sec-WebSocket-Extensions` field.  The payload of each compressed data message is a raw
+//! DEFLATE stream ending in a sync flush, with the final four bytes `00 00 ff ff` removed, and the
+//! first frame of the message has the RSV1 bit set.  Unless either side asks for no context
+//! takeover, the sliding window carries over from one message to the next.
+//!
+//! The compressor always uses the maximum 32 KiB window, so offers limiting the server window are
+//! declined.  Any window used by the peer can be decompressed.
+use crate::constant;
+
+use oxedyne_fe2o3_core::prelude::*;
+
+use flate2::{
+    Compress,
+    Compression,
+    Decompress,
+    FlushCompress,
+    FlushDecompress,
+    Status,
+};
+
+
+const SYNC_FLUSH_TAIL: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+const MAX_WINDOW_BITS: u8 = 15;
+
+/// The negotiated state of the permessage-deflate extension on one side of a connection.
+#[derive(Debug)]
+pub struct WebSocketDeflate {
+    compressor:         Compress,
+    decompressor:       Decompress,
+    /// Whether to start each message sent with an empty window.
+    reset_compressor:   bool,
+    /// Whether the peer starts each message it sends with an empty window.
+    reset_decompressor: bool,
+}
+
+impl WebSocketDeflate {
+
+    fn new(reset_compressor: bool, reset_decompressor: bool) -> Self {
+        Self {
+            compressor:     Compress::new(Compression::default(), false),
+            decompressor:   Decompress::new(false),
+            reset_compressor,
+            reset_decompressor,
+        }
+    }
+
+    /// The offer made by a client.
+    pub fn offer() -> String {
+        constant::WEBSOCKET_DEFLATE_EXTENSION.to_string()
+    }
+
+    /// Accept the first acceptable permessage-deflate offer in the given
+    /// `Sec-WebSocket-Extensions` field values received by a server, returning the server state
+    /// and the field value for the response.
+    pub fn accept(values: &[String]) -> Option<(Self, String)> {
+        'offers: for (name, params) in parse_extensions(values) {
+            if name != constant::WEBSOCKET_DEFLATE_EXTENSION {
+                continue;
+            }
+            let mut server_no_context_takeover = false;
+            let mut client_no_context_takeover = false;
+            let mut seen = Vec::new();
+            for (k, v) in params {
+                if seen.contains(&k) {
+                    continue 'offers;
+                }
+                match (k.as_str(), &v) {
+                    ("server_no_context_takeover", None) => server_no_context_takeover = true,
+                    ("client_no_context_takeover", None) => client_no_context_takeover = true,
+                    ("server_max_window_bits", Some(bits)) => match window_bits(bits) {
+                        Some(MAX_WINDOW_BITS) => (),
+                        _ => continue 'offers,
+                    },
+                    // The client can limit its window, but there is no need to ask it to.
+                    ("client_max_window_bits", None) => (),
+                    ("client_max_window_bits", Some(bits)) => if window_bits(bits).is_none() {
+                        continue 'offers;
+                    },
+                    _ => continue 'offers,
+                }
+                seen.push(k);
+            }
+            let mut response = Self::offer();
+            if server_no_context_takeover {
+                response.push_str("; server_no_context_takeover");
+            }
+            if client_no_context_takeover {
+                response.push_str("; client_no_context_takeover");
+            }
+            return Some((
+                Self::new(server_no_context_takeover, client_no_context_takeover),
+                response,
+            ));
+        }
+        None
+    }
+
+    /// The client state following the server's acceptance of an offer made using `Self::offer`.
+    pub fn accepted(values: &[String]) -> Outcome<Self> {
+        let mut extensions = parse_extensions(values);
+        if extensions.len() != 1 {
+            return Err(err!(
+                "Server accepted {} websocket extensions, only {} was offered.",
+                extensions.len(), constant::WEBSOCKET_DEFLATE_EXTENSION;
+            IO, Network, Invalid, Input));
+        }
+        let (name, params) = extensions.remove(0);
+        if name != constant::WEBSOCKET_DEFLATE_EXTENSION {
+            return Err(err!(
+                "Server accepted the websocket extension '{}', which was not offered.", name;
+            IO, Network, Invalid, Input));
+        }
+        let mut server_no_context_takeover = false;
+        let mut client_no_context_takeover = false;
+        for (k, v) in params {
+            match (k.as_str(), &v) {
+                ("server_no_context_takeover", None) => server_no_context_takeover = true,
+                ("client_no_context_takeover", None) => client_no_context_takeover = true,
+                ("server_max_window_bits", Some(bits)) if window_bits(bits).is_some() => (),
+                _ => return Err(err!(
+                    "Invalid or unexpected {} parameter '{}' in server response.",
+                    constant::WEBSOCKET_DEFLATE_EXTENSION, k;
+                IO, Network, Invalid, Input)),
+            }
+        }
+        Ok(Self::new(client_no_context_takeover, server_no_context_takeover))
+    }
+
+    /// Compress the payload of a message to be sent.
+    pub fn compress(&mut self, data: &[u8]) -> Outcome<Vec<u8>> {
+        let start_in = self.compressor.total_in();
+        let mut out = Vec::with_capacity(data.len() / 2 + 64);
+        loop {
+            if out.capacity() - out.len() < 64 {
+                out.reserve(out.capacity());
+            }
+            let consumed = (self.compressor.total_in() - start_in) as usize;
+            if let Err(e) = self.compressor.compress_vec(&data[consumed..], &mut out, FlushCompress::Sync) {
+                return Err(err!(e,
+                    "While compressing websocket message.";
+                IO, Network, Encode));
+            }
+            let consumed = (self.compressor.total_in() - start_in) as usize;
+            // A sync flush is complete once it leaves spare output capacity.
+            if consumed == data.len() && out.len() < out.capacity() {
+                break;
+            }
+        }
+        if out.ends_with(&SYNC_FLUSH_TAIL) {
+            out.truncate(out.len() - SYNC_FLUSH_TAIL.len());
+        }
+        if self.reset_compressor {
+            self.compressor.reset();
+        }
+        Ok(out)
+    }
+
+    /// Decompress the payload of a received message, which may not exceed the given size when
+    /// decompressed.
+    pub fn decompress(&mut self, data: &[u8], max_size: usize) -> Outcome<Vec<u8>> {
+        let mut input = Vec::with_capacity(data.len() + SYNC_FLUSH_TAIL.len());
+        input.extend_from_slice(data);
+        input.extend_from_slice(&SYNC_FLUSH_TAIL);
+        let start_in = self.decompressor.total_in();
+        let mut out = Vec::with_capacity((data.len() * 2 + 64).min(max_size.saturating_add(1)));
+        loop {
+            if out.capacity() == out.len() {
+                out.reserve(out.capacity().min(max_size.saturating_add(1) - out.len()).max(64));
+            }
+            let consumed = (self.decompressor.total_in() - start_in) as usize;
+            let len = out.len();
+            let result = self.decompressor.decompress_vec(
+                &input[consumed..],
+                &mut out,
+                FlushDecompress::Sync,
+            );
+            let status = match result {
+                Ok(status) => status,
+                Err(e) => return Err(err!(e,
+                    "While decompressing websocket message.";
+                IO, Network, Decode, Invalid, Input)),
+            };
+            if out.len() > max_size {
+                return Err(err!(
+                    "Decompressed websocket message exceeds the limit of {} bytes.", max_size;
+                IO, Network, Input, Size, TooBig));
+            }
+            let now_consumed = (self.decompressor.total_in() - start_in) as usize;
+            if let Status::StreamEnd = status {
+                // The peer ended the stream, so the next message starts afresh.
+                self.decompressor.reset(false);
+                return Ok(out);
+            }
+            if now_consumed == input.len() && out.len() < out.capacity() {
+                break;
+            }
+            if now_consumed == consumed && out.len() == len {
+                return Err(err!(
+                    "Compressed websocket message is incomplete.";
+                IO, Network, Decode, Invalid, Input));
+            }
+        }
+        if self.reset_decompressor {
+            self.decompressor.reset(false);
+        }
+        Ok(out)
+    }
+}
+
+/// Parse `Sec-WebSocket-Extensions` field values into extension names and their parameters, in
+/// order, e.g. `a; b=1, c` becomes `[("a", [("b", Some("1"))]), ("c", [])]`.
+fn parse_extensions(values: &[String]) -> Vec<(String, Vec<(String, Option<String>)>)> {
+    let mut result = Vec::new();
+    for value in values {
+        for ext in value.split(',') {
+            let mut parts = ext.split(';').map(str::trim);
+            let name = match parts.next() {
+                Some(name) if !name.is_empty() => name.to_lowercase(),
+                _ => continue,
+            };
+            let params = parts
+                .filter(|p| !p.is_empty())
+                .map(|p| match p.split_once('=') {
+                    Some((k, v)) => (
+                        k.trim().to_lowercase(),
+                        Some(v.trim().trim_matches('"').to_string()),
+                    ),
+                    None => (p.to_lowercase(), None),
+                })
+                .collect();
+            result.push((name, params));
+        }
+    }
+    result
+}
+
+fn window_bits(s: &str) -> Option<u8> {
+    match s.parse::<u8>() {
+        Ok(n) if (8..=MAX_WINDOW_BITS).contains(&n) => Some(n),
+        _ => None,
+    }
+}
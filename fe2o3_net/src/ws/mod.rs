@@ -1,4 +1,5 @@
 pub mod core;
+pub mod deflate;
 pub mod handler;
 pub mod status;
 
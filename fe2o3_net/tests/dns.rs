@@ -1,5 +1,20 @@
 use oxedyne_fe2o3_net::{
-    dns::Fqdn,
+    dns::{
+        msg::{
+            DnsData,
+            DnsMessage,
+            DnsMx,
+            DnsRcode,
+            DnsRecord,
+            DnsSrv,
+            DnsType,
+        },
+        resolver::{
+            DnsResolver,
+            DnsResolverConfig,
+        },
+        Fqdn,
+    },
 };
 
 use oxedyne_fe2o3_core::{
@@ -7,6 +22,34 @@ use oxedyne_fe2o3_core::{
     test::test_it,
 };
 
+use std::{
+    net::{
+        IpAddr,
+        Ipv4Addr,
+        Ipv6Addr,
+        SocketAddr,
+    },
+    sync::{
+        atomic::{
+            AtomicUsize,
+            Ordering,
+        },
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::{
+    io::{
+        AsyncReadExt,
+        AsyncWriteExt,
+    },
+    net::{
+        TcpListener,
+        UdpSocket,
+    },
+};
+
 
 pub fn test_dns(filter: &'static str) -> Outcome<()> {
 
@@ -98,5 +141,335 @@ pub fn test_dns(filter: &'static str) -> Outcome<()> {
         Ok(())
     }));
 
+    res!(test_it(filter, &["Dns message 000", "all", "dns", "message"], || {
+        let mut query = DnsMessage::query(0xbeef, "example.com.", DnsType::Mx);
+        req!(fmt!("example.com"), query.questions[0].name.clone());
+        let bytes = res!(query.encode());
+        let decoded = res!(DnsMessage::decode(&bytes));
+        req!(query.clone(), decoded);
+        req!(true, decoded.header.recursion_desired);
+        req!(false, decoded.header.response);
+
+        query.header.opcode = 2;
+        let mut response = query.response(DnsRcode::NxDomain);
+        response.header.authoritative = true;
+        response.answers = vec![
+            DnsRecord::new("example.com", 3600, DnsData::Mx(DnsMx {
+                preference: 10,
+                exchange:   fmt!("mail.example.com"),
+            })),
+            DnsRecord::new("mail.example.com", 60, DnsData::A(Ipv4Addr::new(192, 0, 2, 1))),
+            DnsRecord::new("mail.example.com", 60, DnsData::Aaaa(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1))),
+            DnsRecord::new("www.example.com", 60, DnsData::Cname(fmt!("example.com"))),
+            DnsRecord::new("_sip._tcp.example.com", 60, DnsData::Srv(DnsSrv {
+                priority:   1,
+                weight:     2,
+                port:       5060,
+                target:     fmt!("sip.example.com"),
+            })),
+            DnsRecord::new("example.com", 60, DnsData::txt("v=spf1 -all")),
+        ];
+        response.authorities = vec![DnsRecord::new("", 0, DnsData::Other(99, vec![1, 2, 3]))];
+        response.additionals = vec![DnsRecord::new("example.com", 0, DnsData::Txt(vec![]))];
+        let bytes = res!(response.encode());
+        let decoded = res!(DnsMessage::decode(&bytes));
+        req!(response.clone(), decoded);
+        req!(DnsRcode::NxDomain, decoded.header.rcode);
+        req!(2, decoded.header.opcode);
+        req!(DnsType::Other(99), decoded.authorities[0].rtype());
+        req!(fmt!("TYPE99"), fmt!("{}", decoded.authorities[0].rtype()));
+
+        // Truncated messages.
+        for n in [0, 11, 12 + 13, bytes.len() - 1] {
+            let result = DnsMessage::decode(&bytes[..n]);
+            req!(true, result.is_err(), "Decoding {} bytes", n);
+        }
+
+        // A response using name compression.
+        let mut packet = vec![
+            0x12, 0x34, 0x81, 0x80, 0, 1, 0, 2, 0, 0, 0, 0,
+            7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0, 0, 15, 0, 1,
+        ];
+        for (pref, label) in [(20u8, b"backup".to_vec()), (10u8, b"mail".to_vec())] {
+            packet.extend_from_slice(&[0xc0, 12, 0, 15, 0, 1, 0, 0, 0x0e, 0x10]);
+            packet.extend_from_slice(&[0, (2 + 1 + label.len() + 2) as u8, 0, pref, label.len() as u8]);
+            packet.extend_from_slice(&label);
+            packet.extend_from_slice(&[0xc0, 12]);
+        }
+        let decoded = res!(DnsMessage::decode(&packet));
+        req!(0x1234, decoded.header.id);
+        req!(true, decoded.header.response);
+        req!(true, decoded.header.recursion_available);
+        req!(fmt!("example.com"), decoded.questions[0].name.clone());
+        req!(2, decoded.answers.len());
+        req!(fmt!("example.com"), decoded.answers[1].name.clone());
+        req!(3600, decoded.answers[1].ttl);
+        req!(DnsData::Mx(DnsMx {
+            preference: 10,
+            exchange:   fmt!("mail.example.com"),
+        }), decoded.answers[1].data.clone());
+
+        // A pointer to itself.
+        let mut looped = packet.clone();
+        looped[29] = 0xc0;
+        looped[30] = 29;
+        req!(true, DnsMessage::decode(&looped).is_err());
+        // Data length disagreeing with the data.
+        let mut bad_len = packet.clone();
+        bad_len[40] += 1;
+        req!(true, DnsMessage::decode(&bad_len).is_err());
+
+        // Names that cannot be encoded.
+        for name in ["a..example.com", &("a".repeat(64) + ".com"), &"abcdefg.".repeat(40)] {
+            let result = DnsMessage::query(1, name, DnsType::A).encode();
+            req!(true, result.is_err(), "Encoding '{}'", name);
+        }
+
+        let text = "k".repeat(600);
+        let data = DnsData::txt(&text);
+        match &data {
+            DnsData::Txt(strings) => req!(vec![255, 255, 90], strings.iter().map(|s| s.len()).collect::<Vec<_>>()),
+            _ => return Err(err!("Expected a TXT record."; Test, Unexpected)),
+        }
+        let joined = data.txt_string();
+        req!(Some(text.clone()), joined);
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Dns resolver 000", "all", "dns", "resolver"], || {
+        let rt = res!(tokio::runtime::Runtime::new());
+        rt.block_on(test_resolver())
+    }));
+
+    Ok(())
+}
+
+/// A nameserver answering from a fixed set of records over UDP and TCP on the same loopback port.
+/// Queries for `fail.example.com` receive SERVFAIL, those for `big.example.com` over UDP are
+/// truncated, and those for `spoof.example.com` over UDP are preceded by a reply with the wrong
+/// id and some junk.
+#[derive(Clone)]
+struct FakeNameserver {
+    records: Arc<Vec<DnsRecord>>,
+    queries: Arc<AtomicUsize>,
+}
+
+impl FakeNameserver {
+
+    async fn start(records: Vec<DnsRecord>) -> Outcome<(SocketAddr, Arc<AtomicUsize>)> {
+        let udp = res!(UdpSocket::bind("127.0.0.1:0").await);
+        let addr = res!(udp.local_addr());
+        let tcp = res!(TcpListener::bind(addr).await);
+        let server = Self {
+            records: Arc::new(records),
+            queries: Arc::new(AtomicUsize::new(0)),
+        };
+        let queries = server.queries.clone();
+        let udp_server = server.clone();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 512];
+            while let Ok((n, peer)) = udp.recv_from(&mut buf).await {
+                let query = match DnsMessage::decode(&buf[..n]) {
+                    Ok(query) => query,
+                    Err(_) => continue,
+                };
+                let response = udp_server.respond(&query, true);
+                if response.questions[0].name == "spoof.example.com" {
+                    let mut spoof = response.clone();
+                    spoof.header.id = spoof.header.id.wrapping_add(1);
+                    if let Ok(bytes) = spoof.encode() {
+                        let _ = udp.send_to(&bytes, peer).await;
+                    }
+                    let _ = udp.send_to(b"junk", peer).await;
+                }
+                if let Ok(bytes) = response.encode() {
+                    let _ = udp.send_to(&bytes, peer).await;
+                }
+            }
+        });
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = tcp.accept().await {
+                let server = server.clone();
+                tokio::spawn(async move {
+                    let len = match stream.read_u16().await {
+                        Ok(len) => len as usize,
+                        Err(_) => return,
+                    };
+                    let mut buf = vec![0u8; len];
+                    if stream.read_exact(&mut buf).await.is_err() {
+                        return;
+                    }
+                    if let Ok(query) = DnsMessage::decode(&buf) {
+                        if let Ok(bytes) = server.respond(&query, false).encode() {
+                            let _ = stream.write_all(&(bytes.len() as u16).to_be_bytes()).await;
+                            let _ = stream.write_all(&bytes).await;
+                        }
+                    }
+                });
+            }
+        });
+        Ok((addr, queries))
+    }
+
+    fn respond(&self, query: &DnsMessage, udp: bool) -> DnsMessage {
+        self.queries.fetch_add(1, Ordering::SeqCst);
+        let question = match query.questions.first() {
+            Some(question) => question,
+            None => return query.response(DnsRcode::FormErr),
+        };
+        let name = question.name.to_lowercase();
+        if name == "fail.example.com" {
+            return query.response(DnsRcode::ServFail);
+        }
+        let mut response = query.response(DnsRcode::NoError);
+        if udp && name == "big.example.com" {
+            response.header.truncated = true;
+            return response;
+        }
+        if !self.records.iter().any(|record| record.name == name) {
+            return query.response(DnsRcode::NxDomain);
+        }
+        let mut current = name;
+        loop {
+            let mut next = None;
+            for record in self.records.iter().filter(|record| record.name == current) {
+                match &record.data {
+                    DnsData::Cname(target) => {
+                        response.answers.push(record.clone());
+                        if question.qtype != DnsType::Cname {
+                            next = Some(target.clone());
+                        }
+                    },
+                    _ if record.rtype() == question.qtype => response.answers.push(record.clone()),
+                    _ => (),
+                }
+            }
+            match next {
+                Some(next) => current = next,
+                None => break,
+            }
+        }
+        response
+    }
+}
+
+async fn test_resolver() -> Outcome<()> {
+    let dkim_key = fmt!("v=DKIM1; k=rsa; p={}", "A".repeat(400));
+    let big = "b".repeat(3_000);
+    let (addr, queries) = res!(FakeNameserver::start(vec![
+        DnsRecord::new("mail.example.com", 300, DnsData::A(Ipv4Addr::new(192, 0, 2, 10))),
+        DnsRecord::new("mail.example.com", 300, DnsData::Aaaa(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x10))),
+        DnsRecord::new("example.com", 300, DnsData::Mx(DnsMx { preference: 20, exchange: fmt!("backup.example.com") })),
+        DnsRecord::new("example.com", 300, DnsData::Mx(DnsMx { preference: 10, exchange: fmt!("mail.example.com") })),
+        DnsRecord::new("www.example.com", 300, DnsData::Cname(fmt!("web.example.com"))),
+        DnsRecord::new("web.example.com", 300, DnsData::A(Ipv4Addr::new(192, 0, 2, 20))),
+        DnsRecord::new("sel._domainkey.example.com", 300, DnsData::txt(&dkim_key)),
+        DnsRecord::new("_submission._tcp.example.com", 300, DnsData::Srv(DnsSrv { priority: 10, weight: 5, port: 587, target: fmt!("a.example.com") })),
+        DnsRecord::new("_submission._tcp.example.com", 300, DnsData::Srv(DnsSrv { priority: 0, weight: 1, port: 465, target: fmt!("b.example.com") })),
+        DnsRecord::new("_submission._tcp.example.com", 300, DnsData::Srv(DnsSrv { priority: 10, weight: 50, port: 587, target: fmt!("c.example.com") })),
+        DnsRecord::new("big.example.com", 300, DnsData::txt(&big)),
+        DnsRecord::new("zero.example.com", 0, DnsData::A(Ipv4Addr::new(192, 0, 2, 40))),
+        DnsRecord::new("spoof.example.com", 300, DnsData::A(Ipv4Addr::new(192, 0, 2, 30))),
+    ]).await);
+    let count = || queries.load(Ordering::SeqCst);
+    let cfg = DnsResolverConfig {
+        timeout:    Duration::from_secs(2),
+        attempts:   1,
+        ..DnsResolverConfig::with_nameservers(vec![addr])
+    };
+    let resolver = DnsResolver::new(cfg.clone());
+
+    // Answers are cached regardless of the case of the name.
+    let mail_v4 = Ipv4Addr::new(192, 0, 2, 10);
+    req!(vec![mail_v4], res!(resolver.lookup_ipv4("mail.example.com").await));
+    req!(1, count());
+    req!(vec![mail_v4], res!(resolver.lookup_ipv4("MAIL.Example.com.").await));
+    req!(1, count());
+    req!(1, res!(resolver.cache_len()));
+    let mail_v6 = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x10);
+    req!(vec![IpAddr::V4(mail_v4), IpAddr::V6(mail_v6)], res!(resolver.lookup_ip("mail.example.com").await));
+    req!(2, count());
+
+    req!(vec![
+        DnsMx { preference: 10, exchange: fmt!("mail.example.com") },
+        DnsMx { preference: 20, exchange: fmt!("backup.example.com") },
+    ], res!(resolver.lookup_mx("example.com").await));
+
+    // Aliases are followed.
+    req!(vec![Ipv4Addr::new(192, 0, 2, 20)], res!(resolver.lookup_ipv4("www.example.com").await));
+    req!(Some(fmt!("web.example.com")), res!(resolver.lookup_cname("www.example.com").await));
+    let no_cname = res!(resolver.lookup_cname("web.example.com").await);
+    req!(true, no_cname.is_none());
+
+    // Long TXT records are joined.
+    req!(vec![dkim_key.clone()], res!(resolver.lookup_txt("sel._domainkey.example.com").await));
+
+    let targets: Vec<String> = res!(resolver.lookup_srv("_submission._tcp.example.com").await)
+        .into_iter().map(|srv| srv.target).collect();
+    req!(vec![fmt!("b.example.com"), fmt!("c.example.com"), fmt!("a.example.com")], targets);
+
+    // Names and records that do not exist are empty, and remembered.
+    let before = count();
+    req!(true, res!(resolver.lookup_ipv4("missing.example.com").await).is_empty());
+    req!(true, res!(resolver.lookup_ipv4("missing.example.com").await).is_empty());
+    req!(true, res!(resolver.lookup_mx("mail.example.com").await).is_empty());
+    req!(true, res!(resolver.lookup_mx("mail.example.com").await).is_empty());
+    req!(before + 2, count());
+
+    // Truncated responses are repeated over TCP.
+    let before = count();
+    req!(vec![big.clone()], res!(resolver.lookup_txt("big.example.com").await));
+    req!(before + 2, count());
+
+    // Mismatched and invalid responses are ignored.
+    req!(vec![Ipv4Addr::new(192, 0, 2, 30)], res!(resolver.lookup_ipv4("spoof.example.com").await));
+
+    // A TTL of zero is not cached.
+    let before = count();
+    res!(resolver.lookup_ipv4("zero.example.com").await);
+    res!(resolver.lookup_ipv4("zero.example.com").await);
+    req!(before + 2, count());
+
+    req!(true, resolver.lookup_ipv4("fail.example.com").await.is_err());
+
+    res!(resolver.clear_cache());
+    req!(0, res!(resolver.cache_len()));
+    let before = count();
+    res!(resolver.lookup_ipv4("mail.example.com").await);
+    req!(before + 1, count());
+
+    // A cache holding a single entry.
+    let small = DnsResolver::new(DnsResolverConfig {
+        cache_max_entries: 1,
+        ..cfg.clone()
+    });
+    res!(small.lookup_ipv4("mail.example.com").await);
+    res!(small.lookup_ipv4("web.example.com").await);
+    req!(1, res!(small.cache_len()));
+
+    // A nameserver that never answers is skipped.
+    let silent = res!(UdpSocket::bind("127.0.0.1:0").await);
+    let silent_addr = res!(silent.local_addr());
+    let fallback = DnsResolver::new(DnsResolverConfig {
+        timeout:    Duration::from_millis(200),
+        attempts:   1,
+        ..DnsResolverConfig::with_nameservers(vec![silent_addr, addr])
+    });
+    req!(vec![mail_v4], res!(fallback.lookup_ipv4("mail.example.com").await));
+    let unanswered = DnsResolver::new(DnsResolverConfig {
+        timeout:    Duration::from_millis(200),
+        attempts:   2,
+        ..DnsResolverConfig::with_nameservers(vec![silent_addr])
+    });
+    req!(true, unanswered.lookup_ipv4("mail.example.com").await.is_err());
+    let unconfigured = DnsResolver::new(DnsResolverConfig::with_nameservers(Vec::new()));
+    req!(true, unconfigured.lookup_ipv4("mail.example.com").await.is_err());
+
+    let conf = "# Generated\nsearch example.com\nnameserver 192.0.2.53\nnameserver fe80::1%eth0\n\
+        nameserver 2001:db8::53\noptions edns0\n";
+    req!(vec![
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 53)), 53),
+        SocketAddr::new(IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x53)), 53),
+    ], DnsResolverConfig::parse_resolv_conf(conf));
     Ok(())
 }
@@ -1,10 +1,25 @@
 use oxedyne_fe2o3_net::{
     //dns::Fqdn,
     email::{
+        dkim::{
+            Canonicalisation,
+            DkimMessage,
+            DkimPublicKey,
+            DkimSigner,
+            DkimSigningKey,
+        },
         file::{
             MboxEmailIterator,
         },
+        mime::{
+            self,
+            EmailBuilder,
+            MimeBody,
+            MimePart,
+            TransferEncoding,
+        },
         msg::{
+            ContentDisposition,
             EmailHeader,
             EmailMessage,
         },
@@ -18,14 +33,81 @@ use oxedyne_fe2o3_core::{
     prelude::*,
     test::test_it,
 };
+use oxedyne_fe2o3_crypto::sign::SignatureScheme;
+use oxedyne_fe2o3_iop_crypto::keys::KeyManager;
 use oxedyne_fe2o3_text::string::Stringer;
 
-use std::{
-    path::Path,
-    pin::Pin,
-};
+use std::pin::Pin;
 
 
+/// RFC 8463 Appendix A.
+const RFC8463_MESSAGE: &str = "DKIM-Signature: v=1; a=ed25519-sha256; c=relaxed/relaxed;\r
+ d=football.example.com; i=@football.example.com;\r
+ q=dns/txt; s=brisbane; t=1528637909; h=from : to :\r
+ subject : date : message-id : from : subject : date;\r
+ bh=2jUSOH9NhtVGCQWNr9BrIAPreKQjO6Sn7XIkfJVOzv8=;\r
+ b=/gCrinpcQOoIfuHNQIbq4pgh9kyIK3AQUdt9OdqQehSwhEIug4D11Bus\r
+ Fa3bT3FY5OsU7ZbnKELq+eXdp1Q1Dw==\r
+DKIM-Signature: v=1; a=rsa-sha256; c=relaxed/relaxed;\r
+ d=football.example.com; i=@football.example.com;\r
+ q=dns/txt; s=test; t=1528637909; h=from : to : subject :\r
+ date : message-id : from : subject : date;\r
+ bh=2jUSOH9NhtVGCQWNr9BrIAPreKQjO6Sn7XIkfJVOzv8=;\r
+ b=F45dVWDfMbQDGHJFlXUNB2HKfbCeLRyhDXgFpEL8GwpsRe0IeIixNTe3\r
+ DhCVlUrSjV4BwcVcOF6+FF3Zo9Rpo1tFOeS9mPYQTnGdaSGsgeefOsk2Jz\r
+ dA+L10TeYt9BgDfQNZtKdN1WO//KgIqXP7OdEFE4LjFYNcUxZQ4FADY+8=\r
+From: Joe SixPack <joe@football.example.com>\r
+To: Suzie Q <suzie@shopping.example.net>\r
+Subject: Is dinner ready?\r
+Date: Fri, 11 Jul 2003 21:00:37 -0700 (PDT)\r
+Message-ID: <20030712040037.46341.5F8J@football.example.com>\r
+\r
+Hi.\r
+\r
+We lost the game.  Are you hungry yet?\r
+\r
+Joe.\r
+";
+
+const RFC8463_ED25519_SECRET: &str = "nWGxne/9WmC6hEr0kuwsxERJxWl7MmkZcDusAxyuf2A=";
+const RFC8463_ED25519_RECORD: &str =
+    "v=DKIM1; k=ed25519; p=11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo=";
+const RFC8463_RSA_RECORD: &str = "v=DKIM1; k=rsa; \
+    p=MIGfMA0GCSqGSIb3DQEBAQUAA4GNADCBiQKBgQDkHlOQoBTzWRiGs5V6NpP3idY6Wk08a5qhdR6wy5bdOKb2jLQiY/\
+    J16JYi0Qvx/byYzCNb3W91y3FutACDfzwQ/BC/e/8uBsCR+yz1Lxj+PL6lHvqMKrM3rG4hstT5QjvHO9PzoxZyVYLzBfO2\
+    EeC3Ip3G+2kryOTIKT+l/K4w3QIDAQAB";
+
+/// A 2048 bit RSA test key, in PKCS#8.
+const TEST_RSA_PKCS8: &[&str] = &[
+    "MIIEvgIBADANBgkqhkiG9w0BAQEFAASCBKgwggSkAgEAAoIBAQCtVjXldOw429mgrPmuTSWSNX1N9gOXt6zbrF3g",
+    "JKcoSQMEXBx1rZVcffNMx1yTv01Pj2aMezdqvFisOpTHRiCcBpvpbBiMajR3hq/IY40W/uIvYc0bXNNovBG/pH4w",
+    "Fmvo2EYXfFrVvt1vxH8hT9FliuDeMskQNoWAfmf7FOB2JvLkVLtxsTdAMcTjxXaUYxPphV/B2F3ZlUSFSB0h+sRU",
+    "Xg+aIU37VQ2qg/CemznoIjNliqm+fjNLz09z+cih8G8V5ykRzFTWIPbBwhP1uIL4Syi9ZwNMYSehbEmVwNFQPn/9",
+    "rkzaU8ro9bbMyONCghmyUwIrGR/csOloT2xHQo+dAgMBAAECggEABcFlepK6ZDyX5OVRc2JAON2vT9x3FpLPTx+m",
+    "91IS6HwczkiBBmmcL8JxMW+ziSayj7gke3jSPdL2rnNR14MNLU5tNLU1jyCt+3kHUwP2iO4E1HYZdsrggdYFey5K",
+    "+q2dPtGYPt/kGTxO7c7huhlZy9bDwYUArESPLsAjvjsdeowhtLTrRYuK2Y0TeeWKohjC4p5Q/UuxpoLYFaPCLohY",
+    "yVRaMBsy6UV5uN2Pmdb2366oTaKpH2wNnG0Ar8C65jJ3eXVHrQ+EV44hfZ8ifrk5khZTSFPdERVcVtuXadwWmcY7",
+    "zCN03PkMD0FnI99MUD2vYdKJIL6F+OeJBv0+wjVYwQKBgQDV91Uut2GMfe8AOqVZziuGd6cUxnr0AZ93wBaXlMjo",
+    "X0bQZVEd6qz8BIRD9Yzlh2dD87VrpvScvr+Zh7Tk4CES8j1dm33NHExGIOQcxsNgU3ZzQ98CiEof0iMzXcgZ4vy5",
+    "KL/JQBnJPJKUBNPsyp9KC15AFuf+8QMTzMMIzZAe3QKBgQDPY5E2nh5UDhmXEUu8E1CQQMIdV890Yz/kxXJ7O75Y",
+    "gCDDm6pup4bddpg9vrRO1Nb5pSYj9KzJ6g3iPn3Pj36O+sMpDkS37maPsq9YhRhVrH/sbNuSMC6+nD8fttbKhUkK",
+    "36WBwgUaUDV7tMJOPB+tOyV3zb7VPukGxP7BynxHwQKBgF/49MhjkOnHgvCDXa+Ofo9uFA+Jh5TzNWgtTPTo08UH",
+    "F2haSaw1mynfjMQ+29gIiBU+t112Ibo9BQwEhusybSNmbIgUOrVJwByknC0cKn6GcVb6Vol4QGdNhmR/6EVUPy9+",
+    "S6gljGtrNAQYtkY8DH7V4oy0uZLs4Ah9i+3i5oYxAoGBAL7ySMCGAxyXQt6B0T5gdjvrNA76S4s42ppTZV2rm002",
+    "43PWvqxVWOpNiEzJapBujWSbtytB21mpQ5E8wm+6CeexYMFLXuAPA6Vo+uNA4C/5vGq9q8jEzjU3/TLBELC02ZTG",
+    "aEe5kSMyGJxGyORpnSzNmWjeKceDveA6vulE+vrBAoGBAMxevRMXJbxxxPw7w+3EuojoLah0i8nTWZrFVa7z/BiG",
+    "EIaSkGks2G2sOrvJrcYRQAoifr/f3i0oChyRtpSzah1nWj4IrZAZZUQ8VkZ2UB9jQxB4GjqKR+IM1fXkh8w/meh4",
+    "0qXx4DK4y2yWOCawcUPs/i/sO6bw2tYXyCk0YRFl",
+];
+
+fn rfc8463_record(domain: &str, selector: &str) -> Option<String> {
+    match (domain, selector) {
+        ("football.example.com", "brisbane") => Some(RFC8463_ED25519_RECORD.to_string()),
+        ("football.example.com", "test") => Some(RFC8463_RSA_RECORD.to_string()),
+        _ => None,
+    }
+}
+
 pub fn test_email(filter: &'static str) -> Outcome<()> {
 
     res!(test_it(filter, &["Email message 000", "all", "email"], || {
@@ -64,6 +146,7 @@ Sender\r
             headers:    vec![
                 EmailHeader::Received(fmt!("from servera.example.org (servera.example.org [192.168.0.1]) by serverb.example.com (Postfix) with ESMTP id 12345678 for <recipient@example.com>; Fri, 11 Jun 2023 09:30:00 -0500 (EST)")),
                 EmailHeader::Date(fmt!("Fri, 11 Jun 2023 09:30:00 -0500")),
+                EmailHeader::MessageId(fmt!("<12345.67890@servera.example.org>")),
             ],
         };
         for line in Stringer::new(fmt!("{:?}", email)).to_lines("  ") {
@@ -75,29 +158,450 @@ Sender\r
 
     res!(test_it(filter, &["Mbox reader 000", "all", "email", "mbox"], || {
 
-        let rt = res!(tokio::runtime::Runtime::new());
+        // Two messages, separated by a "From " line.
+        let dir = std::env::temp_dir().join("fe2o3_net_test");
+        res!(std::fs::create_dir_all(&dir));
+        let path = dir.join("Inbox");
+        res!(std::fs::write(&path, "From alice@example.com Fri Jun 11 09:30:00 2023\n\
+From: alice@example.com\n\
+To: bob@example.com\n\
+Subject: First\n\
+\n\
+Hello Bob.\n\
+\n\
+From carol@example.com Fri Jun 11 10:30:00 2023\n\
+From: carol@example.com\n\
+To: bob@example.com\n\
+Subject: Second\n\
+\n\
+Hello again.\n"));
 
-        let home = res!(std::env::var("HOME"));
-        let path = Path::new(&home).join("tmp/Inbox");
+        let rt = res!(tokio::runtime::Runtime::new());
         let result = rt.block_on(async {
-            MboxEmailIterator::new(path).await
+            MboxEmailIterator::new(&path).await
         });
 
-        let mut mbox_iter = res!(result);
-        match mbox_iter.next() {
-            Some(Ok(email)) => {
-                test!("{:?}", email);
+        let mbox_iter = res!(result);
+        let mut subjects = Vec::new();
+        for result in mbox_iter {
+            match result {
+                Ok(email) => {
+                    test!("{:?}", email);
+                    subjects.push(email.subject);
+                }
+                Err(e) => return Err(err!(e,
+                    "While reading email {} from mbox file.", subjects.len();
+                Test, IO, File, Read)),
             }
-            Some(Err(e)) => {
-                return Err(err!(e,
-                    "While reading first email from mbox file.";
-                Test, IO, File, Read)); 
+        }
+        res!(std::fs::remove_file(&path));
+        req!(vec![fmt!("First"), fmt!("Second")], subjects);
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Mime 000", "all", "email", "mime"], || {
+
+        // A mixed message containing text and HTML alternatives, an attachment with an RFC
+        // 2231 filename and a forwarded message.
+        let wire = "From: =?ISO-8859-1?Q?Andr=E9?= Pirard <pirard@example.be>\r
+To: bob@example.com\r
+Subject: =?utf-8?B?Q2Fmw6k=?=\r
+ =?utf-8?Q?_au_lait?=\r
+MIME-Version: 1.0\r
+Content-Type: multipart/mixed; boundary=\"outer=_1\"\r
+\r
+This is the preamble.\r
+--outer=_1\r
+Content-Type: multipart/alternative; boundary=inner\r
+\r
+--inner\r
+Content-Type: text/plain; charset=iso-8859-1\r
+Content-Transfer-Encoding: quoted-printable\r
+\r
+Caf=E9 au lait, which is a long line that has been wrapped with a soft line=\r
+ break.\r
+Second line.\r
+--inner\r
+Content-Type: text/html; charset=\"utf-8\"\r
+Content-Transfer-Encoding: base64\r
+\r
+PHA+Q2Fmw6k8L3A+\r
+--inner--\r
+--outer=_1\r
+Content-Type: application/pdf; name=\"old.pdf\"\r
+Content-Disposition: attachment;\r
+ filename*=utf-8''r%C3%A9sum%C3%A9.pdf\r
+Content-Transfer-Encoding: base64\r
+\r
+JVBERi0xLjQK\r
+--outer=_1\r
+Content-Type: message/rfc822\r
+\r
+From: carol@example.org\r
+Subject: Original\r
+\r
+Forwarded.\r
+--outer=_1--\r
+This is the epilogue.\r
+";
+        let msg = res!(MimePart::parse(wire.as_bytes()));
+        req!(fmt!("multipart/mixed"), msg.media_type.clone());
+        req!(Some(fmt!("Café au lait")), msg.header("subject"));
+        req!(Some(fmt!("André Pirard <pirard@example.be>")), msg.header("From"));
+        req!(3, msg.parts().len());
+
+        let alternative = &msg.parts()[0];
+        req!(fmt!("multipart/alternative"), alternative.media_type.clone());
+        req!(2, alternative.parts().len());
+
+        let plain = match msg.find("text/plain") {
+            Some(part) => part,
+            None => return Err(err!("Expected a text/plain part."; Test, Missing)),
+        };
+        req!(TransferEncoding::QuotedPrintable, plain.encoding);
+        req!(
+            Some(fmt!("Café au lait, which is a long line that has been wrapped with a soft line break.\nSecond line.")),
+            plain.text_content()
+        );
+        let html = match msg.find("text/html") {
+            Some(part) => part,
+            None => return Err(err!("Expected a text/html part."; Test, Missing)),
+        };
+        req!(Some(fmt!("<p>Café</p>")), html.text_content());
+
+        let attachments = msg.attachments();
+        req!(1, attachments.len());
+        req!(fmt!("application/pdf"), attachments[0].media_type.clone());
+        req!(Some(fmt!("résumé.pdf")), attachments[0].filename());
+        req!(
+            Some(ContentDisposition::Attachment(Some(fmt!("résumé.pdf")))),
+            attachments[0].disposition.clone()
+        );
+        req!(Some(&b"%PDF-1.4\n"[..]), attachments[0].content());
+
+        match &msg.parts()[2].body {
+            MimeBody::Message(inner) => {
+                req!(Some(fmt!("Original")), inner.header("subject"));
+                req!(Some(fmt!("Forwarded.")), inner.text_content());
             }
-            None => {
-                return Err(err!(
-                    "Expected to read an email from the mbox file.";
-                Test, Missing, Data)); 
+            body => return Err(err!(
+                "Expected an encapsulated message, found {:?}.", body;
+            Test, Unexpected)),
+        }
+        req!(7, msg.walk().len());
+
+        // A missing boundary is an error.
+        let bad = "Content-Type: multipart/mixed; boundary=x\r\n\r\nNo parts here.\r\n";
+        let result = MimePart::parse(bad.as_bytes());
+        req!(true, result.is_err());
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Mime 001", "all", "email", "mime"], || {
+
+        // RFC 2047 Section 8.
+        for (encoded, decoded) in [
+            ("(=?ISO-8859-1?Q?a?=)", "(a)"),
+            ("(=?ISO-8859-1?Q?a?= b)", "(a b)"),
+            ("(=?ISO-8859-1?Q?a?= =?ISO-8859-1?Q?b?=)", "(ab)"),
+            ("(=?ISO-8859-1?Q?a?=\r\n    =?ISO-8859-1?Q?b?=)", "(ab)"),
+            ("(=?ISO-8859-1?Q?a_b?=)", "(a b)"),
+            ("(=?ISO-8859-1?Q?a?= =?ISO-8859-2?Q?_b?=)", "(a b)"),
+            ("=?US-ASCII?Q?Keith_Moore?= <moore@cs.utk.edu>", "Keith Moore <moore@cs.utk.edu>"),
+            ("=?ISO-8859-1?Q?Olle_J=E4rnefors?=", "Olle Järnefors"),
+            ("=?windows-1252?Q?=93quoted=94?=", "\u{201c}quoted\u{201d}"),
+            ("Not =?encoded", "Not =?encoded"),
+        ] {
+            let result = mime::decode_words(encoded);
+            req!(decoded.to_string(), result);
+        }
+        let subject = "Grüße aus Köln, ein ziemlich langer Betreff mit Umlauten äöü";
+        let encoded = mime::encode_words(subject);
+        test!("{}", encoded);
+        req!(true, encoded.lines().all(|line| line.trim().len() <= 75));
+        let decoded = mime::decode_words(&encoded);
+        req!(subject.to_string(), decoded);
+        req!(fmt!("Plain"), mime::encode_words("Plain"));
+
+        // Quoted-printable.
+        let text = "A line with a trailing space \r\nE = mc², and a long line that goes on and on and on for well over seventy six characters.\r\n";
+        let encoded = TransferEncoding::QuotedPrintable.encode(text.as_bytes());
+        let encoded_str = String::from_utf8_lossy(&encoded).to_string();
+        test!("{}", encoded_str);
+        req!(true, encoded_str.split("\r\n").all(|line| line.len() <= 76));
+        req!(true, encoded_str.contains("space=20\r\n"));
+        req!(true, encoded_str.contains("E =3D mc=C2=B2"));
+        let decoded = res!(TransferEncoding::QuotedPrintable.decode(&encoded));
+        req!(text.as_bytes().to_vec(), decoded);
+        let decoded = mime::qp_decode(b"soft=\r\nbreak, =zz, trailing   \r\nend");
+        req!(b"softbreak, =zz, trailing\r\nend".to_vec(), decoded);
+
+        // Base64.
+        let data: Vec<u8> = (0..=255).collect();
+        let encoded = TransferEncoding::Base64.encode(&data);
+        req!(true, encoded.split(|b| *b == b'\n').all(|line| line.len() <= 77));
+        let decoded = res!(TransferEncoding::Base64.decode(&encoded));
+        req!(data, decoded);
+        let decoded = res!(mime::base64_decode(b"aGVs\r\nbG8"));
+        req!(b"hello".to_vec(), decoded);
+        let result = mime::base64_decode(b"not*base64");
+        req!(true, result.is_err());
+
+        // Parameters.
+        let (value, params) = mime::split_params(
+            "attachment; filename*0*=utf-8''caf%C3%A9; filename*1=\" menu.txt\"; size=\"1;2\"");
+        req!(fmt!("attachment"), value);
+        req!(Some("1;2"), mime::param(&params, "size"));
+        req!(Some("café menu.txt"), mime::param(&params, "filename"));
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Mime 002", "all", "email", "mime"], || {
+
+        let pdf = b"%PDF-1.4\n\x00\x01\x02\xff".to_vec();
+        let builder = EmailBuilder::new("Zoë <zoe@example.com>")
+            .with_to("bob@example.org")
+            .with_to("Carol <carol@example.net>")
+            .with_subject("Café menu")
+            .with_text("Hello Bob,\n\n.Menu attached, prices in €.\n")
+            .with_html("<p>Hello Bob,</p>")
+            .with_attachment("menu.pdf", "application/pdf", pdf.clone())
+            .with_attachment("naïve.txt", "text/plain", b"plain text".to_vec())
+            .with_header(EmailHeader::ReplyTo(fmt!("noreply@example.com")));
+        let wire = res!(builder.to_bytes());
+        let wire_str = String::from_utf8_lossy(&wire).to_string();
+        for line in wire_str.lines() {
+            test!("{}", line);
+        }
+        req!(true, wire.is_ascii());
+        req!(true, wire_str.split("\r\n").all(|line| line.len() <= 998));
+
+        let msg = res!(MimePart::parse(&wire));
+        req!(fmt!("multipart/mixed"), msg.media_type.clone());
+        req!(Some(fmt!("Café menu")), msg.header("subject"));
+        req!(Some(fmt!("Zoë <zoe@example.com>")), msg.header("from"));
+        req!(Some(fmt!("bob@example.org, Carol <carol@example.net>")), msg.header("to"));
+        req!(Some(fmt!("noreply@example.com")), msg.header("reply-to"));
+        req!(Some(fmt!("1.0")), msg.header("mime-version"));
+        req!(true, msg.header("date").is_some());
+        req!(true, msg.header("message-id").map(|id| id.ends_with("@example.com>")).unwrap_or(false));
+
+        req!(fmt!("multipart/alternative"), msg.parts()[0].media_type.clone());
+        let plain = msg.find("text/plain").and_then(|part| part.text_content());
+        req!(Some(fmt!("Hello Bob,\n\n.Menu attached, prices in €.\n")), plain);
+        let html = msg.find("text/html").and_then(|part| part.text_content());
+        req!(Some(fmt!("<p>Hello Bob,</p>")), html);
+
+        let attachments = msg.attachments();
+        req!(2, attachments.len());
+        req!(Some(fmt!("menu.pdf")), attachments[0].filename());
+        req!(Some(&pdf[..]), attachments[0].content());
+        req!(Some(fmt!("naïve.txt")), attachments[1].filename());
+        req!(Some(fmt!("plain text")), attachments[1].text_content());
+
+        // A message without a text or HTML body still has one part per attachment.
+        let msg = res!(EmailBuilder::new("zoe@example.com")
+            .with_to("bob@example.org")
+            .with_attachment("a.bin", "application/octet-stream", vec![1, 2, 3])
+            .build());
+        req!(1, msg.parts().len());
+        let result = EmailBuilder::new("zoe@example.com").build();
+        req!(true, result.is_err());
+        // Addresses cannot inject headers.
+        for builder in [
+            EmailBuilder::new("zoe@example.com\r\nBcc: eve@example.net").with_to("bob@example.org"),
+            EmailBuilder::new("zoe@example.com").with_to("bob@example.org\nBcc: eve@example.net"),
+            EmailBuilder::new("zoe@example.com").with_to("Bob <bob@example.org>")
+                .with_cc("Eve <eve@example.net>\r\nX-Injected: yes"),
+        ] {
+            req!(true, builder.build().is_err());
+        }
+
+        // Received over SMTP into an `EmailMessage`, with dot stuffing.
+        let mut data = Vec::new();
+        for line in wire_str.split_inclusive("\r\n") {
+            if line.starts_with('.') {
+                data.push(b'.');
+            }
+            data.extend_from_slice(line.as_bytes());
+        }
+        data.extend_from_slice(b".\r\n");
+        let mut stream = std::io::Cursor::new(data);
+        let rt = res!(tokio::runtime::Runtime::new());
+        let email = res!(rt.block_on(async {
+            EmailMessage::read(&mut Pin::new(&mut stream)).await
+        }));
+        let msg = res!(email.mime());
+        req!(2, msg.attachments().len());
+        let plain = msg.find("text/plain").and_then(|part| part.text_content());
+        req!(Some(fmt!("Hello Bob,\n\n.Menu attached, prices in €.\n")), plain);
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Dkim 000", "all", "email", "dkim"], || {
+
+        // Both RFC 8463 example signatures verify.
+        let msg = DkimMessage::from_bytes(RFC8463_MESSAGE.as_bytes());
+        let results = msg.verify(rfc8463_record);
+        req!(2, results.len());
+        for result in &results {
+            test!("{:?}", result.result);
+            req!(true, result.is_pass());
+        }
+
+        // Altering the body or a signed header breaks both signatures.
+        for (from, to) in [
+            ("We lost the game.", "We won the game."),
+            ("Is dinner ready?", "Is lunch ready?"),
+        ] {
+            let altered = RFC8463_MESSAGE.replace(from, to);
+            let results = DkimMessage::from_bytes(altered.as_bytes()).verify(rfc8463_record);
+            req!(2, results.len());
+            for result in &results {
+                req!(true, matches!(result.result, Ok(false)));
+            }
+        }
+
+        // Relaxed canonicalisation tolerates changes to whitespace and header case.
+        let rewrapped = RFC8463_MESSAGE
+            .replace("Subject: Is dinner ready?", "subject:   Is dinner\r\n\tready?  ")
+            .replace("We lost the game.  Are", "We lost the game. Are")
+            .replace("Joe.\r\n", "Joe.   \r\n\r\n\r\n");
+        let results = DkimMessage::from_bytes(rewrapped.as_bytes()).verify(rfc8463_record);
+        req!(true, results.iter().all(|r| r.is_pass()));
+
+        // Missing, mismatched and revoked keys are errors.
+        let results = msg.verify(|_, _| None);
+        req!(true, results.iter().all(|r| r.result.is_err()));
+        let results = msg.verify(|_, _| Some(RFC8463_ED25519_RECORD.to_string()));
+        req!(true, results[0].is_pass());
+        req!(true, results[1].result.is_err());
+        let results = msg.verify(|_, _| Some(fmt!("v=DKIM1; k=ed25519; p=")));
+        req!(true, results[0].result.is_err());
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Dkim 001", "all", "email", "dkim"], || {
+
+        // RFC 6376 Section 3.4.5.
+        let fields = [("A", " X"), ("B ", " Y\t\r\n\tZ  ")];
+        let mut relaxed = Vec::new();
+        let mut simple = Vec::new();
+        for (name, value) in fields {
+            relaxed.extend(Canonicalisation::Relaxed.header(name, value));
+            simple.extend(Canonicalisation::Simple.header(name, value));
+        }
+        req!(b"a:X\r\nb:Y Z\r\n".to_vec(), relaxed);
+        req!(b"A: X\r\nB : Y\t\r\n\tZ  \r\n".to_vec(), simple);
+
+        let body = b" C \r\nD \t E\r\n\r\n\r\n";
+        let relaxed = Canonicalisation::Relaxed.body(body);
+        req!(b" C\r\nD E\r\n".to_vec(), relaxed);
+        let simple = Canonicalisation::Simple.body(body);
+        req!(b" C \r\nD \t E\r\n".to_vec(), simple);
+
+        // Empty bodies (RFC 6376 Section 3.4.3 and 3.4.4).
+        let relaxed = Canonicalisation::Relaxed.body(b"\r\n\r\n");
+        req!(Vec::<u8>::new(), relaxed);
+        let simple = Canonicalisation::Simple.body(b"");
+        req!(b"\r\n".to_vec(), simple);
+
+        // Key records.
+        let key = res!(DkimPublicKey::from_str("v=DKIM1; k=rsa; h=sha1:sha256; t=y:s; p=MIGfMA0GCSqGSIb3DQEBAQUAA4GNADCBiQKBgQDkHlOQoBTzWRiGs5V6NpP3idY6Wk08a5qhdR6wy5bdOKb2jLQiY/J16JYi0Qvx/byYzCNb3W91y3FutACDfzwQ/BC/e/8uBsCR+yz1Lxj+PL6lHvqMKrM3rG4hstT5QjvHO9PzoxZyVYLzBfO2EeC3Ip3G+2kryOTIKT+l/K4w3QIDAQAB"));
+        req!(true, key.testing);
+        req!(true, key.strict);
+        req!(vec![fmt!("sha1"), fmt!("sha256")], key.hashes.clone());
+        // The PKCS#1 key is extracted from the SubjectPublicKeyInfo.
+        req!(140, key.key.len());
+        let result = DkimPublicKey::from_str("v=DKIM1; k=dsa; p=AAAA");
+        req!(true, result.is_err());
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Dkim 002", "all", "email", "dkim"], || {
+
+        let sk = res!(base64::decode(RFC8463_ED25519_SECRET));
+        let pk = res!(base64::decode("11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo="));
+        let ed25519 = res!(DkimSigningKey::ed25519(res!(
+            SignatureScheme::empty_ed25519().clone_with_keys(Some(&pk), Some(&sk))
+        )));
+        let rsa = res!(DkimSigningKey::rsa_from_pkcs8(&res!(base64::decode(TEST_RSA_PKCS8.concat()))));
+
+        let wire = res!(EmailBuilder::new("Joe <joe@football.example.com>")
+            .with_to("suzie@shopping.example.net")
+            .with_subject("Dinner  menu")
+            .with_text("Hi.\n\nThe menu is attached.  \n")
+            .with_attachment("menu.txt", "text/plain", b"Soup\r\nBread\r\n".to_vec())
+            .to_bytes());
+
+        for key in [ed25519, rsa] {
+            let record = res!(key.public_record());
+            test!("{}", record);
+            let lookup = |d: &str, s: &str| match (d, s) {
+                ("football.example.com", "sel") => Some(record.clone()),
+                _ => None,
+            };
+            for (header_canon, body_canon) in [
+                (Canonicalisation::Relaxed, Canonicalisation::Relaxed),
+                (Canonicalisation::Relaxed, Canonicalisation::Simple),
+                (Canonicalisation::Simple, Canonicalisation::Simple),
+            ] {
+                let mut signer = DkimSigner::new("football.example.com", "sel", key.clone());
+                signer.header_canon = header_canon;
+                signer.body_canon = body_canon;
+                signer.identity = Some(fmt!("joe@mail.football.example.com"));
+                signer.expiry = Some(std::time::Duration::from_secs(3_600));
+                let signed = res!(signer.sign_bytes(&wire));
+
+                let msg = DkimMessage::from_bytes(&signed);
+                let sigs = msg.signatures();
+                req!(1, sigs.len());
+                let sig = match &sigs[0] {
+                    Ok(sig) => sig.clone(),
+                    Err(e) => return Err(err!(e.clone(), "Parsing the signature."; Test)),
+                };
+                req!(key.algorithm(), sig.algorithm);
+                req!(true, sig.headers.iter().any(|h| h == "content-type"));
+                let results = msg.verify(lookup);
+                req!(true, results[0].is_pass());
+
+                // An added header that was not signed does not matter, but a second From does.
+                let mut added = b"X-Spam-Score: 0\r\n".to_vec();
+                added.extend_from_slice(&signed);
+                let results = DkimMessage::from_bytes(&added).verify(lookup);
+                req!(true, results[0].is_pass());
+                let mut added = signed.clone();
+                let pos = match added.windows(4).position(|w| w == b"\r\n\r\n") {
+                    Some(pos) => pos + 2,
+                    None => return Err(err!("No end of headers."; Test, Missing)),
+                };
+                added.splice(pos..pos, b"From: mallory@example.org\r\n".iter().copied());
+                let results = DkimMessage::from_bytes(&added).verify(lookup);
+                req!(true, matches!(results[0].result, Ok(false)));
             }
+
+            // An `EmailMessage` read from the wire can be verified, and signed.
+            let signer = DkimSigner::new("football.example.com", "sel", key.clone());
+            let signed = res!(signer.sign_bytes(&wire));
+            let mut data = signed.clone();
+            data.extend_from_slice(b".\r\n");
+            let mut stream = std::io::Cursor::new(data);
+            let rt = res!(tokio::runtime::Runtime::new());
+            let mut email = res!(rt.block_on(async {
+                EmailMessage::read(&mut Pin::new(&mut stream)).await
+            }));
+            let results = DkimMessage::from(&email).verify(lookup);
+            req!(1, results.len());
+            req!(true, results[0].is_pass());
+
+            email.headers.retain(|h| !h.name().eq_ignore_ascii_case("dkim-signature"));
+            res!(signer.sign_email(&mut email));
+            let results = DkimMessage::from(&email).verify(lookup);
+            req!(1, results.len());
+            req!(true, results[0].is_pass());
+            email.subject.push_str(" (altered)");
+            let results = DkimMessage::from(&email).verify(lookup);
+            req!(true, matches!(results[0].result, Ok(false)));
         }
         Ok(())
     }));
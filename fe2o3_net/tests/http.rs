@@ -1,18 +1,157 @@
 use oxedyne_fe2o3_net::{
-    conc::AsyncReadIterator,
+    conc::{
+        AsyncReadChunks,
+        AsyncReadIterator,
+    },
     constant,
-    http::msg::HttpMessageReader,
+    http::{
+        client::{
+            HttpClient,
+            HttpClientConfig,
+            HttpUrl,
+        },
+        fields::{
+            HeaderField,
+            HeaderFields,
+            HeaderFieldValue,
+            HeaderName,
+        },
+        form::{
+            HttpForm,
+            HttpFormConfig,
+            HttpUpload,
+            HttpUploadContent,
+        },
+        header::{
+            HttpHeader,
+            HttpHeadline,
+            HttpMethod,
+        },
+        loc::HttpLocator,
+        msg::{
+            HttpMessage,
+            HttpMessageReader,
+        },
+        router::{
+            HttpAuth,
+            HttpCompression,
+            HttpCors,
+            HttpFuture,
+            HttpLogger,
+            HttpMiddleware,
+            HttpNext,
+            HttpRequest,
+            HttpRouter,
+        },
+        status::HttpStatus,
+    },
 };
 
 use oxedyne_fe2o3_core::{
     prelude::*,
+    test::test_it,
 };
+use oxedyne_fe2o3_jdat::prelude::*;
 
 use std::{
+    io::Read,
+    path::PathBuf,
     pin::Pin,
+    sync::{
+        Arc,
+        Mutex,
+        atomic::{
+            AtomicUsize,
+            Ordering,
+        },
+    },
+    time::Duration,
+};
+
+use tokio::{
+    io::{
+        AsyncRead,
+        AsyncWrite,
+        AsyncWriteExt,
+    },
+    net::TcpListener,
+};
+use tokio_rustls::{
+    rustls::{
+        self,
+        pki_types::{
+            CertificateDer,
+            PrivateKeyDer,
+            PrivatePkcs8KeyDer,
+        },
+    },
+    TlsAcceptor,
 };
 
 
+type TestReader<'a, R> = HttpMessageReader<
+    'a,
+    { constant::HTTP_DEFAULT_HEADER_CHUNK_SIZE },
+    { constant::HTTP_DEFAULT_BODY_CHUNK_SIZE },
+    R,
+>;
+
+/// A multipart form with text fields, a repeated field, and two uploads, the second given by the
+/// caller.
+fn multipart_wire(big: &[u8]) -> Vec<u8> {
+    let mut wire = b"This preamble is ignored.\r\n\
+--AaB03x\r\n\
+Content-Disposition: form-data; name=\"submit-name\"\r\n\r\n\
+Larry\r\n\
+--AaB03x  \r\n\
+content-disposition: form-data; name=tag\r\n\r\n\
+one\r\n\
+--AaB03x\r\n\
+Content-Disposition: form-data; name=\"tag\"\r\n\r\n\
+two\r\n\
+--AaB03x\r\n\
+Content-Disposition: form-data; name=\"small\"; filename=\"C:\\\\docs\\\\a.txt\"\r\n\
+Content-Type: text/plain\r\n\r\n\
+contains \r\n--AaB03 but not the delimiter\r\n\
+--AaB03x\r\n\
+Content-Disposition: form-data; name=\"big\"; filename=\"euro.bin\"; \
+filename*=UTF-8''%E2%82%ACuro.bin\r\n\r\n".to_vec();
+    wire.extend_from_slice(big);
+    wire.extend_from_slice(b"\r\n--AaB03x--\r\nThis epilogue is ignored.");
+    wire
+}
+
+fn is_upload(header: &HttpHeader) -> bool {
+    HttpForm::multipart_boundary(&header.fields).is_some()
+}
+
+/// An empty directory for uploads.
+fn form_test_dir(name: &str) -> Outcome<PathBuf> {
+    let dir = std::env::temp_dir().join("fe2o3_net_test").join(name);
+    if dir.exists() {
+        res!(std::fs::remove_dir_all(&dir));
+    }
+    res!(std::fs::create_dir_all(&dir));
+    Ok(dir)
+}
+
+/// Read all the messages on the wire.
+fn read_all(wire: &[u8]) -> Outcome<Vec<HttpMessage>> {
+    let rt = res!(tokio::runtime::Runtime::new());
+    rt.block_on(read_all_async(wire))
+}
+
+async fn read_all_async(wire: &[u8]) -> Outcome<Vec<HttpMessage>> {
+    let mut stream = std::io::Cursor::new(wire);
+    let mut reader: TestReader<'_, _> = HttpMessageReader::new(Pin::new(&mut stream));
+    let mut msgs = Vec::new();
+    while let Some(result) = reader.next().await {
+        msgs.push(res!(result));
+    }
+    Ok(msgs)
+}
+
+
 pub fn test_http(filter: &'static str) -> Outcome<()> {
 
     match filter {
@@ -24,7 +163,7 @@ Accept: text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/w
 Accept-Language: en-US,en;q=0.5\r\n\
 Accept-Encoding: gzip, deflate, br\r\n\
 Content-Type: application/x-www-form-urlencoded\r\n\
-Content-Length: 25\r\n\
+Content-Length: 26\r\n\
 Origin: null\r\n\
 Connection: keep-alive\r\n\
 Cookie: _ga_PYS2NF62KB=GS1.1.1703943898.1.1.1703943949.0.0.0; _ga=GA1.1.1548945696.1703943899\r\n\
@@ -64,5 +203,935 @@ Sec-Fetch-User: ?1\r\n\r\n";
         _ => (),
     }
 
+    res!(test_it(filter, &["Chunked read 000", "all", "http", "chunked"], || {
+        // Chunk sizes are hex, with an ignored extension, followed by trailers and then a
+        // pipelined second request.
+        let wire = "POST /upload HTTP/1.1\r\n\
+Host: my.domain.com\r\n\
+Transfer-Encoding: gzip, chunked\r\n\
+Trailer: Expires\r\n\r\n\
+7\r\nMozilla\r\n\
+11;name=value\r\nDeveloper Network\r\n\
+0\r\n\
+Expires: Wed, 21 Oct 2015 07:28:00 GMT\r\n\r\n\
+GET /next HTTP/1.1\r\n\
+Host: my.domain.com\r\n\r\n";
+        let msgs = res!(read_all(wire.as_bytes()));
+        req!(2, msgs.len());
+        req!(true, msgs[0].header.is_chunked());
+        req!("MozillaDeveloper Network", &msgs[0].body_as_string());
+        match msgs[0].trailers.get_one(&HeaderName::Expires) {
+            Some(HeaderFieldValue::Generic(s)) => req!("Wed, 21 Oct 2015 07:28:00 GMT", s.as_str()),
+            other => return Err(err!("Unexpected trailer {:?}.", other; Test, Mismatch)),
+        }
+        req!(true, msgs[1].body.is_empty());
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Chunked read 001", "all", "http", "chunked"], || {
+        // A chunk must be followed by CRLF.
+        let wire = "POST /upload HTTP/1.1\r\n\
+Transfer-Encoding: chunked\r\n\r\n\
+3\r\nabcd\r\n0\r\n\r\n";
+        req!(true, read_all(wire.as_bytes()).is_err());
+        // As must its size be hex.
+        let wire = "POST /upload HTTP/1.1\r\n\
+Transfer-Encoding: chunked\r\n\r\n\
+x3\r\nabc\r\n0\r\n\r\n";
+        req!(true, read_all(wire.as_bytes()).is_err());
+        // A chunk size that overflows, or exceeds the body size limit, is rejected without
+        // waiting for the chunk.
+        let wire = "POST /upload HTTP/1.1\r\n\
+Transfer-Encoding: chunked\r\n\r\n\
+1ffffffffffffffff\r\nabc";
+        req!(true, read_all(wire.as_bytes()).is_err());
+        let wire = fmt!("POST /upload HTTP/1.1\r\n\
+Transfer-Encoding: chunked\r\n\r\n\
+{:x}\r\nabc", constant::HTTP_BODY_SIZE_MAX + 1);
+        req!(true, read_all(wire.as_bytes()).is_err());
+        // A truncated body ends the stream without a message.
+        let wire = "POST /upload HTTP/1.1\r\n\
+Transfer-Encoding: chunked\r\n\r\n\
+a\r\nabc";
+        req!(0, res!(read_all(wire.as_bytes())).len());
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Body limit 000", "all", "http", "chunked", "limit"], || {
+        let rt = res!(tokio::runtime::Runtime::new());
+        let read = |wire: &str| rt.block_on(async {
+            let mut stream = std::io::Cursor::new(wire.as_bytes());
+            let mut reader: TestReader<'_, _> =
+                HttpMessageReader::new(Pin::new(&mut stream)).with_body_max(10);
+            reader.next().await
+        });
+        let result = read("POST /a HTTP/1.1\r\nContent-Length: 10\r\n\r\n0123456789");
+        req!(true, matches!(result, Some(Ok(_))));
+        // Rejected before the body arrives.
+        let result = read("POST /a HTTP/1.1\r\nContent-Length: 11\r\n\r\n");
+        req!(true, matches!(result, Some(Err(_))));
+        let result = read("POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+            6\r\n012345\r\n5\r\n");
+        req!(true, matches!(result, Some(Err(_))));
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Chunked write 000", "all", "http", "chunked"], || {
+        let rt = res!(tokio::runtime::Runtime::new());
+        let wire = res!(rt.block_on(async {
+            // Generated output, arriving after the start of the body.
+            let (tx, rx) = tokio::sync::mpsc::channel::<Outcome<Vec<u8>>>(4);
+            tokio::spawn(async move {
+                for word in ["streamed ", "", "output"] {
+                    if tx.send(Ok(word.as_bytes().to_vec())).await.is_err() {
+                        break;
+                    }
+                }
+            });
+            let msg = HttpMessage::ok_respond_with_text("Some ")
+                .with_stream(rx)
+                .with_trailer(HeaderName::Expires, HeaderFieldValue::Generic(fmt!("never")));
+            let mut wire = Vec::new();
+            res!(msg.write_all(&mut wire).await);
+            // A file, or any other reader, in pieces.
+            let file = std::io::Cursor::new("0123456789".repeat(100).into_bytes());
+            let msg = HttpMessage::new_response(HttpStatus::OK)
+                .with_stream(AsyncReadChunks::<64, _>::new(file));
+            res!(msg.write_all(&mut wire).await);
+            Ok::<_, Error<ErrTag>>(wire)
+        }));
+        let wire_str = String::from_utf8_lossy(&wire).to_string();
+        req!(false, wire_str.to_lowercase().contains("content-length"));
+        let msgs = res!(read_all(&wire));
+        req!(2, msgs.len());
+        req!("Some streamed output", &msgs[0].body_as_string());
+        match msgs[0].trailers.get_one(&HeaderName::Expires) {
+            Some(HeaderFieldValue::Generic(s)) => req!("never", s.as_str()),
+            other => return Err(err!("Unexpected trailer {:?}.", other; Test, Mismatch)),
+        }
+        req!("0123456789".repeat(100), msgs[1].body_as_string().to_string());
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Form 000", "all", "http", "form"], || {
+        let form = res!(HttpForm::parse_urlencoded(
+            b"name=J%C3%BCrgen+Smith&empty=&flag&tag=a&tag=b%26c&&tag=d"));
+        req!(Some(&dat!("Jürgen Smith")), form.get(&dat!("name")));
+        req!(Some(&dat!("")), form.get(&dat!("empty")));
+        req!(Some(&dat!("")), form.get(&dat!("flag")));
+        req!(Some(&listdat!["a", "b&c", "d"]), form.get(&dat!("tag")));
+        req!(4, form.len());
+        // Invalid escapes.
+        req!(true, HttpForm::parse_urlencoded(b"a=%2").is_err());
+        req!(true, HttpForm::parse_urlencoded(b"a=%zz").is_err());
+        req!(true, HttpForm::parse_urlencoded(b"a=%ff").is_err());
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Form 001", "all", "http", "form"], || {
+        let dir = res!(form_test_dir("form_001"));
+        let cfg = HttpFormConfig {
+            upload_dir:     dir.clone(),
+            memory_limit:   100,
+            ..Default::default()
+        };
+        // The boundary is case sensitive, and may be quoted.
+        let hf = res!(HeaderField::new(
+            "Content-Type: multipart/form-data; boundary=\"AaB03x\"", None));
+        let mut fields = HeaderFields::default();
+        fields.insert(hf.name, hf.value, None);
+        let big: Vec<u8> = (0..=255u8).cycle().take(1_024).collect();
+        let wire = multipart_wire(&big);
+        let rt = res!(tokio::runtime::Runtime::new());
+        let forms = res!(rt.block_on(async {
+            let form = res!(HttpForm::from_body(&fields, &wire, &cfg).await);
+            // Reading in small pieces splits delimiters across reads.
+            let form_small_reads = res!(HttpForm::read_multipart::<7, _>(
+                &wire[..], "AaB03x", &cfg).await);
+            Ok::<_, Error<ErrTag>>([form, form_small_reads])
+        }));
+        for form in &forms {
+            req!(4, form.len());
+            req!(Some(&dat!("Larry")), form.get(&dat!("submit-name")));
+            req!(Some(&listdat!["one", "two"]), form.get(&dat!("tag")));
+            let small = match form.get(&dat!("small")) {
+                Some(dat) => res!(HttpUpload::from_dat(dat)),
+                None => return Err(err!("Upload 'small' missing."; Test, Missing)),
+            };
+            req!(fmt!("a.txt"), small.filename.clone());
+            req!(fmt!("text/plain"), small.content_type.clone());
+            req!(HttpUploadContent::Memory(
+                b"contains \r\n--AaB03 but not the delimiter".to_vec()), small.content.clone());
+            let big_upload = match form.get(&dat!("big")) {
+                Some(dat) => res!(HttpUpload::from_dat(dat)),
+                None => return Err(err!("Upload 'big' missing."; Test, Missing)),
+            };
+            req!(fmt!("€uro.bin"), big_upload.filename.clone());
+            req!(fmt!("application/octet-stream"), big_upload.content_type.clone());
+            req!(1_024, big_upload.size);
+            let path = match &big_upload.content {
+                HttpUploadContent::File(path) => path.clone(),
+                other => return Err(err!(
+                    "Expected the upload on disk, found {:?}.", other;
+                Test, Mismatch)),
+            };
+            req!(true, path.starts_with(&dir));
+            req!(big.clone(), res!(rt.block_on(big_upload.bytes())));
+            req!(2, HttpForm::uploads(form).len());
+            for upload in HttpForm::uploads(form) {
+                res!(rt.block_on(upload.remove()));
+            }
+            req!(false, path.exists());
+        }
+        // Other bodies are left to the receiver.
+        let form = res!(rt.block_on(HttpForm::from_body(&HeaderFields::default(), &wire, &cfg)));
+        req!(true, form.is_empty());
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Form 002", "all", "http", "form"], || {
+        let dir = res!(form_test_dir("form_002"));
+        let cfg = HttpFormConfig {
+            upload_dir:     dir.clone(),
+            memory_limit:   100,
+            ..Default::default()
+        };
+        let wire = multipart_wire(&vec![b'x'; 1_024]);
+        let rt = res!(tokio::runtime::Runtime::new());
+        let read = |wire: &[u8], cfg: &HttpFormConfig| rt.block_on(
+            HttpForm::read_multipart::<64, _>(wire, "AaB03x", cfg)
+        );
+        // An upload already written to disk is removed when the form is truncated.
+        req!(true, read(&wire[..wire.len() - 40], &cfg).is_err());
+        req!(0, res!(std::fs::read_dir(&dir)).count());
+        // No delimiter.
+        req!(true, read(b"just some text", &cfg).is_err());
+        // Limits.
+        req!(true, read(&wire, &HttpFormConfig { max_parts: 4, ..cfg.clone() }).is_err());
+        req!(true, read(&wire, &HttpFormConfig { max_field_size: 4, ..cfg.clone() }).is_err());
+        req!(true, read(&wire, &HttpFormConfig { max_upload_size: 1_000, ..cfg.clone() }).is_err());
+        req!(0, res!(std::fs::read_dir(&dir)).count());
+        // A part must be form data with a name.
+        let wire = b"--AaB03x\r\nContent-Disposition: attachment; name=a\r\n\r\nx\r\n--AaB03x--";
+        req!(true, read(wire, &cfg).is_err());
+        let wire = b"--AaB03x\r\nContent-Disposition: form-data\r\n\r\nx\r\n--AaB03x--";
+        req!(true, read(wire, &cfg).is_err());
+        let wire = b"--AaB03x\r\nContent-Disposition: form-data; name=a\r\n\r\nx\r\n--AaB03x--";
+        req!(Some(&dat!("x")), res!(read(wire, &cfg)).get(&dat!("a")));
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Form 003", "all", "http", "form"], || {
+        // Uploads are streamed from the reader, and a body left unread is skipped.
+        let dir = res!(form_test_dir("form_003"));
+        let cfg = HttpFormConfig {
+            upload_dir:     dir.clone(),
+            memory_limit:   100,
+            ..Default::default()
+        };
+        let body = multipart_wire(&vec![b'x'; 1_024]);
+        let mut upload = fmt!("POST /upload HTTP/1.1\r\n\
+Content-Type: multipart/form-data; boundary=AaB03x\r\n\
+Content-Length: {}\r\n\r\n", body.len()).into_bytes();
+        upload.extend_from_slice(&body);
+        let mut wire = upload.clone();
+        wire.extend_from_slice(&upload);
+        wire.extend_from_slice(b"GET /next HTTP/1.1\r\n\r\n");
+        let rt = res!(tokio::runtime::Runtime::new());
+        let (form, paths) = res!(rt.block_on(async {
+            let mut stream = std::io::Cursor::new(&wire[..]);
+            let mut reader: TestReader<'_, _> =
+                HttpMessageReader::new(Pin::new(&mut stream)).with_streamed_body_if(is_upload);
+            let mut paths = Vec::new();
+            let mut form = None;
+            while let Some(result) = reader.next().await {
+                let msg = res!(result);
+                if let HttpHeadline::Request { loc, .. } = &msg.header.headline {
+                    paths.push(loc.path.as_string().clone());
+                }
+                req!(true, msg.body.is_empty());
+                if form.is_none() && is_upload(&msg.header) {
+                    form = Some(res!(HttpForm::read_multipart::<64, _>(
+                        reader.body(), "AaB03x", &cfg).await));
+                }
+            }
+            Ok::<_, Error<ErrTag>>((form, paths))
+        }));
+        req!(vec![fmt!("/upload"), fmt!("/upload"), fmt!("/next")], paths);
+        let form = match form {
+            Some(form) => form,
+            None => return Err(err!("Streamed form missing."; Test, Missing)),
+        };
+        req!(Some(&dat!("Larry")), form.get(&dat!("submit-name")));
+        for upload in HttpForm::uploads(&form) {
+            res!(rt.block_on(upload.remove()));
+        }
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Url 000", "all", "http", "client", "url"], || {
+        let url = res!(HttpUrl::new("https://Example.com:8443/a/b?x=1#frag"));
+        req!(true, url.secure);
+        req!(fmt!("Example.com"), url.host.clone());
+        req!(8443, url.port);
+        req!(fmt!("/a/b?x=1"), url.target.clone());
+        req!(fmt!("Example.com:8443"), url.host_field());
+        let url = res!(HttpUrl::new("http://[::1]?q"));
+        req!(fmt!("[::1]"), url.host.clone());
+        req!(80, url.port);
+        req!(fmt!("/?q"), url.target.clone());
+        req!(fmt!("[::1]"), url.host_field());
+        // Redirect locations.
+        let base = res!(HttpUrl::new("http://host/dir/page?x=1"));
+        req!(fmt!("/dir/other"), res!(base.join("other")).target);
+        req!(fmt!("/top"), res!(base.join("/top")).target);
+        let url = res!(base.join("//elsewhere:81/p"));
+        req!((false, fmt!("elsewhere"), 81), (url.secure, url.host.clone(), url.port));
+        req!(true, res!(base.join("https://secure/")).secure);
+        // Origins.
+        req!(true, base.same_origin(&res!(HttpUrl::new("HTTP://HOST:80/elsewhere"))));
+        req!(false, base.same_origin(&res!(HttpUrl::new("https://host/dir/page"))));
+        req!(false, base.same_origin(&res!(HttpUrl::new("http://host:8080/dir/page"))));
+        // Invalid.
+        req!(true, HttpUrl::new("ftp://host/").is_err());
+        req!(true, HttpUrl::new("http://host:port/").is_err());
+        req!(true, HttpUrl::new("http://user@host/").is_err());
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Router 000", "all", "http", "router"], || {
+        let rt = res!(tokio::runtime::Runtime::new());
+        rt.block_on(test_router_routes())
+    }));
+
+    res!(test_it(filter, &["Router 001", "all", "http", "router", "middleware"], || {
+        let rt = res!(tokio::runtime::Runtime::new());
+        rt.block_on(test_router_middleware())
+    }));
+
+    res!(test_it(filter, &["Client 000", "all", "http", "client"], || {
+        let rt = res!(tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(4)
+            .enable_all()
+            .build());
+        rt.block_on(test_client_plain())
+    }));
+
+    res!(test_it(filter, &["Client 001", "all", "http", "client", "tls"], || {
+        let rt = res!(tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(4)
+            .enable_all()
+            .build());
+        rt.block_on(test_client_tls())
+    }));
+
+    Ok(())
+}
+
+fn route_request(method: HttpMethod, loc: &str, fields: &[(HeaderName, &str)]) -> Outcome<HttpRequest> {
+    let mut request = HttpRequest::new(method, res!(HttpLocator::new(loc)));
+    for (name, value) in fields {
+        request.fields.insert(name.clone(), HeaderFieldValue::Generic(value.to_string()), None);
+    }
+    Ok(request)
+}
+
+fn response_status(response: &HttpMessage) -> Option<HttpStatus> {
+    match response.header.headline {
+        HttpHeadline::Response { status } => Some(status),
+        _ => None,
+    }
+}
+
+fn response_field(response: &HttpMessage, name: &HeaderName) -> Option<String> {
+    response.header.fields.get_one(name).map(|value| value.to_string())
+}
+
+/// Respond with the route pattern and the named parameters.
+fn echo_params(names: &'static [&'static str]) -> impl Fn(HttpRequest) -> std::future::Ready<Outcome<HttpMessage>> {
+    move |req: HttpRequest| {
+        let mut text = req.route.clone().unwrap_or_default();
+        for name in names {
+            text.push_str(&fmt!(" {}={}", name, req.param(name).unwrap_or("-")));
+        }
+        std::future::ready(Ok(HttpMessage::ok_respond_with_text(text)))
+    }
+}
+
+async fn test_router_routes() -> Outcome<()> {
+    let mut router = HttpRouter::new();
+    res!(router.get("/", echo_params(&[])));
+    res!(router.get("/users/:id", echo_params(&["id"])));
+    res!(router.get("/users/me", echo_params(&[])));
+    res!(router.put("/users/:id", echo_params(&["id"])));
+    res!(router.post("/users/:id/posts/:post", echo_params(&["id", "post"])));
+    res!(router.get("/files/*path", echo_params(&["path"])));
+    res!(router.get("/static/*", echo_params(&["*"])));
+    res!(router.get("/a/:x/c", echo_params(&["x"])));
+    res!(router.get("/a/b/*rest", echo_params(&["rest"])));
+
+    for (method, loc, expected) in [
+        (HttpMethod::GET,   "/",                        "/"),
+        (HttpMethod::GET,   "/users/42",                "/users/:id id=42"),
+        (HttpMethod::GET,   "/users/42/",               "/users/:id id=42"),
+        (HttpMethod::GET,   "//users//42",              "/users/:id id=42"),
+        (HttpMethod::GET,   "/users/me",                "/users/me"),
+        (HttpMethod::GET,   "/users/caf%C3%A9%2Fx",     "/users/:id id=caf\u{e9}/x"),
+        (HttpMethod::PUT,   "/users/42",                "/users/:id id=42"),
+        (HttpMethod::POST,  "/users/7/posts/9",         "/users/:id/posts/:post id=7 post=9"),
+        (HttpMethod::GET,   "/files/a/b/c.txt",         "/files/*path path=a/b/c.txt"),
+        (HttpMethod::GET,   "/files",                   "/files/*path path="),
+        (HttpMethod::GET,   "/static/x/y",              "/static/* *=x/y"),
+        // Literal segments take precedence, segment by segment.
+        (HttpMethod::GET,   "/a/b/c",                   "/a/b/*rest rest=c"),
+        (HttpMethod::GET,   "/a/z/c",                   "/a/:x/c x=z"),
+        // Path parameters replace query parameters.
+        (HttpMethod::GET,   "/users/42?id=9",           "/users/:id id=42"),
+    ] {
+        let response = res!(router.handle(res!(route_request(method, loc, &[]))).await);
+        let status = response_status(&response);
+        req!(Some(HttpStatus::OK), status, "{} {}", method, loc);
+        let body = response.body_as_string().to_string();
+        req!(expected, body.as_str(), "{} {}", method, loc);
+    }
+
+    for (method, loc, expected) in [
+        (HttpMethod::GET,       "/users",           HttpStatus::NotFound),
+        (HttpMethod::GET,       "/users/1/posts/2", HttpStatus::MethodNotAllowed),
+        (HttpMethod::GET,       "/users/%zz",       HttpStatus::BadRequest),
+    ] {
+        let response = res!(router.handle(res!(route_request(method, loc, &[]))).await);
+        let status = response_status(&response);
+        req!(Some(expected), status, "{} {}", method, loc);
+    }
+    let response = res!(router.handle(res!(route_request(HttpMethod::DELETE, "/users/42", &[]))).await);
+    let status = response_status(&response);
+    req!(Some(HttpStatus::MethodNotAllowed), status);
+    req!(Some(fmt!("GET, PUT")), response_field(&response, &HeaderName::Allow));
+
+    // Query parameters remain available.
+    let mut router = HttpRouter::new();
+    res!(router.get("/search/:kind", |req: HttpRequest| async move {
+        let q = req.loc.data.get(&dat!("q")).cloned();
+        Ok(HttpMessage::ok_respond_with_text(fmt!("{} {:?}", req.param("kind").unwrap_or("-"), q)))
+    }));
+    let response = res!(router.handle(res!(route_request(HttpMethod::GET, "/search/books?q=rust", &[]))).await);
+    let body = response.body_as_string().to_string();
+    req!(fmt!("books {:?}", Some(res!(Dat::from_str("rust")))), body);
+
+    // Invalid patterns.
+    for pattern in ["users", "/a/*/b", "/:", "/:a/:a", "/:a/*a"] {
+        let result = router.get(pattern, echo_params(&[]));
+        req!(true, result.is_err(), "Pattern '{}'", pattern);
+    }
+    // Conflicting patterns.
+    let mut other = HttpRouter::new();
+    res!(other.get("/users/:id", echo_params(&[])));
+    res!(other.get("/users/me", echo_params(&[])));
+    res!(other.put("/users/:name", echo_params(&[])));
+    res!(other.get("/users/:id/*", echo_params(&[])));
+    for pattern in ["/users/:name", "/users/me/", "/users/:x/*rest"] {
+        let result = other.get(pattern, echo_params(&[]));
+        req!(true, result.is_err(), "Pattern '{}'", pattern);
+    }
+
+    // A request read from the wire.
+    let wire = b"GET /search/films?q=noir HTTP/1.1\r\nHost: example.com\r\nX-Test: yes\r\n\r\n";
+    let mut msgs = res!(read_all_async(wire).await);
+    let req = res!(HttpRequest::from_message(msgs.remove(0)));
+    req!(HttpMethod::GET, req.method);
+    req!(Some(fmt!("yes")), req.field(&HeaderName::NonStandard(fmt!("x-test"))));
+    let response = res!(router.handle(req).await);
+    let body = response.body_as_string().to_string();
+    req!(fmt!("films {:?}", Some(res!(Dat::from_str("noir")))), body);
+    let result = HttpRequest::from_message(HttpMessage::new_response(HttpStatus::OK));
+    req!(true, result.is_err());
+    Ok(())
+}
+
+/// Records the order in which it is entered and left, optionally answering directly.
+#[derive(Clone)]
+struct TraceMiddleware {
+    name:   &'static str,
+    trace:  Arc<Mutex<Vec<String>>>,
+    answer: bool,
+}
+
+impl TraceMiddleware {
+    fn push(&self, event: String) {
+        if let Ok(mut trace) = self.trace.lock() {
+            trace.push(event);
+        }
+    }
+}
+
+impl HttpMiddleware for TraceMiddleware {
+    fn handle<'a>(&'a self, req: HttpRequest, next: HttpNext<'a>) -> HttpFuture<'a> {
+        Box::pin(async move {
+            self.push(fmt!("{} in {}", self.name, req.param("id").unwrap_or("-")));
+            if self.answer && req.param("id") == Some("stop") {
+                return Ok(HttpMessage::respond_with_text(HttpStatus::Forbidden, "Stopped."));
+            }
+            let response = res!(next.run(req).await);
+            self.push(fmt!("{} out", self.name));
+            Ok(response)
+        })
+    }
+}
+
+async fn test_router_middleware() -> Outcome<()> {
+    // Order.
+    let trace = Arc::new(Mutex::new(Vec::new()));
+    let mut router = HttpRouter::new();
+    let handler_trace = trace.clone();
+    res!(router.get("/items/:id", move |_req: HttpRequest| {
+        if let Ok(mut trace) = handler_trace.lock() {
+            trace.push(fmt!("handler"));
+        }
+        async { Ok(HttpMessage::ok_respond_with_text("item")) }
+    }));
+    router.add_middleware(HttpLogger::new(LogLevel::Debug));
+    router.add_middleware(TraceMiddleware { name: "outer", trace: trace.clone(), answer: false });
+    router.add_middleware(TraceMiddleware { name: "inner", trace: trace.clone(), answer: true });
+    let take_trace = || -> Outcome<Vec<String>> {
+        match trace.lock() {
+            Ok(mut trace) => Ok(std::mem::take(&mut *trace)),
+            Err(_) => Err(err!("Lock poisoned."; Test, Poisoned)),
+        }
+    };
+    res!(router.handle(res!(route_request(HttpMethod::GET, "/items/1", &[]))).await);
+    req!(vec![
+        fmt!("outer in 1"),
+        fmt!("inner in 1"),
+        fmt!("handler"),
+        fmt!("inner out"),
+        fmt!("outer out"),
+    ], res!(take_trace()));
+    let response = res!(router.handle(res!(route_request(HttpMethod::GET, "/items/stop", &[]))).await);
+    let status = response_status(&response);
+    req!(Some(HttpStatus::Forbidden), status);
+    req!(vec![fmt!("outer in stop"), fmt!("inner in stop"), fmt!("outer out")], res!(take_trace()));
+    // Unmatched requests still pass through the middleware.
+    let response = res!(router.handle(res!(route_request(HttpMethod::GET, "/nothing", &[]))).await);
+    let status = response_status(&response);
+    req!(Some(HttpStatus::NotFound), status);
+    req!(vec![fmt!("outer in -"), fmt!("inner in -"), fmt!("inner out"), fmt!("outer out")], res!(take_trace()));
+
+    // Authentication, with CORS outside it.
+    let mut router = HttpRouter::new();
+    res!(router.get("/api/:id", echo_params(&["id"])));
+    res!(router.get("/public/:id", echo_params(&["id"])));
+    res!(router.get("/public-admin/:id", echo_params(&["id"])));
+    router.add_middleware(HttpCors::default()
+        .with_origin("https://app.example.com")
+        .with_credentials(true)
+        .with_expose("X-Total"));
+    router.add_middleware(HttpAuth::bearer("api", |token: &str| token == "secret")
+        .with_public("/public"));
+    let origin = (HeaderName::Origin, "https://app.example.com");
+    for (loc, auth, expected) in [
+        ("/api/1",      None,                       HttpStatus::Unauthorized),
+        ("/api/1",      Some("Bearer wrong"),       HttpStatus::Unauthorized),
+        ("/api/1",      Some("Basic secret"),       HttpStatus::Unauthorized),
+        ("/api/1",      Some("Bearer secret"),      HttpStatus::OK),
+        ("/api/1",      Some("bearer  secret "),    HttpStatus::OK),
+        ("/public/1",   None,                       HttpStatus::OK),
+        ("//public//1", None,                       HttpStatus::OK),
+        ("/p%75blic/1", None,                       HttpStatus::OK),
+        ("/public-admin/1", None,                   HttpStatus::Unauthorized),
+        ("/publicity",  None,                       HttpStatus::Unauthorized),
+    ] {
+        let mut fields = vec![origin.clone()];
+        if let Some(auth) = auth {
+            fields.push((HeaderName::Authorization, auth));
+        }
+        let response = res!(router.handle(res!(route_request(HttpMethod::GET, loc, &fields))).await);
+        let status = response_status(&response);
+        req!(Some(expected), status, "{} with {:?}", loc, auth);
+        req!(Some(fmt!("https://app.example.com")),
+            response_field(&response, &HeaderName::AccessControlAllowOrigin));
+        req!(Some(fmt!("true")), response_field(&response, &HeaderName::AccessControlAllowCredentials));
+        req!(Some(fmt!("X-Total")), response_field(&response, &HeaderName::AccessControlExposeHeaders));
+        if expected == HttpStatus::Unauthorized {
+            req!(Some(fmt!("Bearer realm=\"api\"")), response_field(&response, &HeaderName::WWWAuthenticate));
+        }
+    }
+
+    // A preflight request is answered before authentication.
+    let response = res!(router.handle(res!(route_request(HttpMethod::OPTIONS, "/api/1", &[
+        origin.clone(),
+        (HeaderName::AccessControlRequestMethod, "GET"),
+        (HeaderName::AccessControlRequestHeaders, "authorization"),
+    ]))).await);
+    let status = response_status(&response);
+    req!(Some(HttpStatus::NoContent), status);
+    req!(Some(fmt!("https://app.example.com")), response_field(&response, &HeaderName::AccessControlAllowOrigin));
+    req!(Some(fmt!("GET, HEAD, POST, PUT, PATCH, DELETE")),
+        response_field(&response, &HeaderName::AccessControlAllowMethods));
+    req!(Some(fmt!("authorization")), response_field(&response, &HeaderName::AccessControlAllowHeaders));
+    req!(Some(fmt!("3600")), response_field(&response, &HeaderName::AccessControlMaxAge));
+    req!(Some(fmt!("Origin")), response_field(&response, &HeaderName::Vary));
+
+    // Other origins are given no CORS fields.
+    let response = res!(router.handle(res!(route_request(HttpMethod::OPTIONS, "/api/1", &[
+        (HeaderName::Origin, "https://evil.example.com"),
+        (HeaderName::AccessControlRequestMethod, "GET"),
+    ]))).await);
+    let status = response_status(&response);
+    req!(Some(HttpStatus::Unauthorized), status);
+    req!(None::<String>, response_field(&response, &HeaderName::AccessControlAllowOrigin));
+
+    // Any origin.
+    let mut router = HttpRouter::new();
+    res!(router.get("/open", echo_params(&[])));
+    router.add_middleware(HttpCors::default());
+    let response = res!(router.handle(res!(route_request(HttpMethod::GET, "/open", &[
+        (HeaderName::Origin, "https://anywhere.example.com"),
+    ]))).await);
+    req!(Some(fmt!("*")), response_field(&response, &HeaderName::AccessControlAllowOrigin));
+    req!(None::<String>, response_field(&response, &HeaderName::Vary));
+
+    // Basic authentication.
+    let mut router = HttpRouter::new();
+    res!(router.get("/", echo_params(&[])));
+    router.add_middleware(HttpAuth::basic("site", |user: &str, password: &str| {
+        user == "jane" && password == "p:ss"
+    }));
+    for (credentials, expected) in [
+        (fmt!("Basic {}", base64::encode("jane:p:ss")), HttpStatus::OK),
+        (fmt!("Basic {}", base64::encode("jane:wrong")), HttpStatus::Unauthorized),
+        (fmt!("Basic {}", base64::encode("jane")), HttpStatus::Unauthorized),
+        (fmt!("Basic !!!"), HttpStatus::Unauthorized),
+    ] {
+        let response = res!(router.handle(res!(route_request(HttpMethod::GET, "/", &[
+            (HeaderName::Authorization, &credentials),
+        ]))).await);
+        let status = response_status(&response);
+        req!(Some(expected), status, "{}", credentials);
+    }
+
+    // Compression.
+    req!(Some("gzip"), HttpCompression::choose_encoding("gzip, deflate, br"));
+    req!(Some("deflate"), HttpCompression::choose_encoding("deflate;q=1, gzip;q=0.5"));
+    req!(Some("gzip"), HttpCompression::choose_encoding("br, *;q=0.1"));
+    req!(Some("deflate"), HttpCompression::choose_encoding("gzip;q=0, *"));
+    req!(None::<&str>, HttpCompression::choose_encoding("gzip;q=0, identity"));
+    req!(None::<&str>, HttpCompression::choose_encoding(""));
+
+    let text = "All work and no play makes Jack a dull boy.\n".repeat(100);
+    let mut router = HttpRouter::new();
+    let page = text.clone();
+    res!(router.get("/page", move |_req: HttpRequest| {
+        let page = page.clone();
+        async move { Ok(HttpMessage::ok_respond_with_text(page)) }
+    }));
+    res!(router.get("/small", |_req: HttpRequest| async { Ok(HttpMessage::ok_respond_with_text("tiny")) }));
+    let binary = text.clone();
+    res!(router.get("/binary", move |_req: HttpRequest| {
+        let binary = binary.clone();
+        async move {
+            Ok(HttpMessage::new_response(HttpStatus::OK)
+                .with_field(HeaderName::ContentType, HeaderFieldValue::Generic(fmt!("image/png")))
+                .with_body(binary.into_bytes()))
+        }
+    }));
+    router.add_middleware(HttpCompression::default());
+
+    for (accept, expected) in [("gzip", Some("gzip")), ("deflate", Some("deflate")), ("br", None)] {
+        let response = res!(router.handle(res!(route_request(HttpMethod::GET, "/page", &[
+            (HeaderName::AcceptEncoding, accept),
+        ]))).await);
+        let encoding = response_field(&response, &HeaderName::ContentEncoding);
+        req!(expected.map(str::to_string), encoding.clone(), "{}", accept);
+        let mut decoded = String::new();
+        match encoding.as_deref() {
+            Some("gzip") => {
+                res!(flate2::read::GzDecoder::new(&response.body[..]).read_to_string(&mut decoded));
+                req!(Some(fmt!("Accept-Encoding")), response_field(&response, &HeaderName::Vary));
+            },
+            Some("deflate") => {
+                res!(flate2::read::DeflateDecoder::new(&response.body[..]).read_to_string(&mut decoded));
+            },
+            _ => decoded = response.body_as_string().to_string(),
+        }
+        req!(text.clone(), decoded.clone());
+        if expected.is_some() {
+            req!(true, response.body.len() < text.len());
+            // The length sent is that of the compressed body.
+            let len = response.body.len();
+            let mut wire = Vec::new();
+            res!(response.write_all(&mut wire).await);
+            let msgs = res!(read_all_async(&wire).await);
+            req!(len, msgs[0].body.len());
+        }
+    }
+    for loc in ["/small", "/binary"] {
+        let response = res!(router.handle(res!(route_request(HttpMethod::GET, loc, &[
+            (HeaderName::AcceptEncoding, "gzip"),
+        ]))).await);
+        req!(None::<String>, response_field(&response, &HeaderName::ContentEncoding), "{}", loc);
+    }
+    Ok(())
+}
+
+/// Respond to requests on the connection until it closes.
+async fn serve<S: AsyncRead + AsyncWrite + Unpin + Send>(stream: S) -> Outcome<()> {
+    let (mut read_stream, mut write_stream) = tokio::io::split(stream);
+    let mut reader: TestReader<'_, _> = HttpMessageReader::new(Pin::new(&mut read_stream));
+    while let Some(result) = reader.next().await {
+        let request = res!(result);
+        let (method, path) = match &request.header.headline {
+            HttpHeadline::Request { method, loc } => (*method, loc.path.as_string().clone()),
+            _ => return Err(err!("Expected a request."; Test, Unexpected)),
+        };
+        let response = match path.as_str() {
+            "/hello" => HttpMessage::ok_respond_with_text("hello"),
+            "/redirect" => HttpMessage::new_response(HttpStatus::Found)
+                .with_field(HeaderName::Location, HeaderFieldValue::Generic(fmt!("/hello"))),
+            "/see-other" => HttpMessage::new_response(HttpStatus::SeeOther)
+                .with_field(HeaderName::Location, HeaderFieldValue::Generic(fmt!("echo"))),
+            "/loop" => HttpMessage::new_response(HttpStatus::Found)
+                .with_field(HeaderName::Location, HeaderFieldValue::Generic(fmt!("/loop"))),
+            "/same-origin" => HttpMessage::new_response(HttpStatus::Found)
+                .with_field(HeaderName::Location, HeaderFieldValue::Generic(fmt!("/auth"))),
+            "/cross-origin" => {
+                // The same server, reached by another name.
+                let host = match request.header.fields.get_one(&HeaderName::Host) {
+                    Some(host) => fmt!("{}", host).replace("127.0.0.1", "localhost"),
+                    None => return Err(err!("Expected a Host field."; Test, Missing)),
+                };
+                HttpMessage::new_response(HttpStatus::Found).with_field(
+                    HeaderName::Location,
+                    HeaderFieldValue::Generic(fmt!("http://{}/auth", host)),
+                )
+            },
+            "/insecure" => HttpMessage::new_response(HttpStatus::Found)
+                .with_field(HeaderName::Location, HeaderFieldValue::Generic(fmt!("http://localhost/hello"))),
+            "/auth" => HttpMessage::ok_respond_with_text(fmt!(
+                "authorization {}, cookie {}",
+                request.header.fields.get_one(&HeaderName::Authorization).is_some(),
+                request.header.fields.get_one(&HeaderName::Cookie).is_some(),
+            )),
+            "/echo" => HttpMessage::ok_respond_with_text(
+                fmt!("{} {}", method, request.body_as_string())),
+            "/chunked" => {
+                let file = std::io::Cursor::new("chunk".repeat(1_000).into_bytes());
+                HttpMessage::new_response(HttpStatus::OK)
+                    .with_stream(AsyncReadChunks::<100, _>::new(file))
+            },
+            "/bye" => {
+                // A keep-alive response, after which the server closes the connection anyway.
+                res!(HttpMessage::ok_respond_with_text("bye").write_all(&mut write_stream).await);
+                break;
+            },
+            "/malformed" => {
+                res!(write_stream.write_all(b"HTTP/1.1 abc\r\n\r\n").await);
+                continue;
+            },
+            "/interim" => {
+                // Interim responses before the final one.
+                res!(write_stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n\
+                    HTTP/1.1 103 Early Hints\r\nLink: </style.css>; rel=preload\r\n\r\n").await);
+                res!(HttpMessage::ok_respond_with_text("final").write_all(&mut write_stream).await);
+                continue;
+            },
+            "/switch" => {
+                res!(write_stream.write_all(b"HTTP/1.1 101 Switching Protocols\r\n\
+                    Upgrade: test\r\nConnection: Upgrade\r\n\r\n").await);
+                break;
+            },
+            "/close" => {
+                // No length, so the body ends when the connection closes.
+                res!(write_stream.write_all(b"HTTP/1.1 200 OK\r\n\r\nuntil close").await);
+                break;
+            },
+            "/head" | "/not-modified" => {
+                // The length of the body that a GET would have, with no body sent.
+                let status = if method == HttpMethod::HEAD { "200 OK" } else { "304 Not Modified" };
+                let head = fmt!("HTTP/1.1 {}\r\nContent-Length: 5\r\n\r\n", status);
+                res!(write_stream.write_all(head.as_bytes()).await);
+                continue;
+            },
+            "/huge" => {
+                // No length, with a body larger than the client accepts.
+                res!(write_stream.write_all(b"HTTP/1.1 200 OK\r\n\r\n").await);
+                let chunk = vec![b'x'; 1_048_576];
+                for _ in 0..=(constant::HTTP_BODY_SIZE_MAX / chunk.len()) {
+                    if write_stream.write_all(&chunk).await.is_err() {
+                        break;
+                    }
+                }
+                break;
+            },
+            "/slow" => {
+                tokio::time::sleep(Duration::from_secs(2)).await;
+                HttpMessage::ok_respond_with_text("slow")
+            },
+            _ => HttpMessage::respond_with_text(HttpStatus::NotFound, "not found"),
+        };
+        res!(response.write_all(&mut write_stream).await);
+    }
+    let _ = write_stream.shutdown().await;
+    Ok(())
+}
+
+/// Accept connections on an ephemeral local port, returning the port and the number of
+/// connections accepted so far.
+async fn start_server(acceptor: Option<TlsAcceptor>) -> Outcome<(u16, Arc<AtomicUsize>)> {
+    let listener = res!(TcpListener::bind("127.0.0.1:0").await);
+    let port = res!(listener.local_addr()).port();
+    let count = Arc::new(AtomicUsize::new(0));
+    let count_clone = count.clone();
+    tokio::spawn(async move {
+        while let Ok((tcp, _)) = listener.accept().await {
+            count_clone.fetch_add(1, Ordering::SeqCst);
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                let result = match acceptor {
+                    Some(acceptor) => match acceptor.accept(tcp).await {
+                        Ok(tls) => serve(tls).await,
+                        Err(e) => Err(err!(e, "TLS accept failed."; Test, Network)),
+                    },
+                    None => serve(tcp).await,
+                };
+                if let Err(e) = result {
+                    debug!("Test server connection: {}", e);
+                }
+            });
+        }
+    });
+    Ok((port, count))
+}
+
+fn status_of(response: &HttpMessage) -> Outcome<HttpStatus> {
+    match response.header.headline {
+        HttpHeadline::Response { status } => Ok(status),
+        _ => Err(err!("Expected a response."; Test, Unexpected)),
+    }
+}
+
+async fn test_client_plain() -> Outcome<()> {
+    let (port, count) = res!(start_server(None).await);
+    let base = fmt!("http://127.0.0.1:{}", port);
+    let client = HttpClient::new(HttpClientConfig {
+        request_timeout: Duration::from_secs(1),
+        max_redirects: 3,
+        ..Default::default()
+    });
+
+    // Keep-alive connection reuse.
+    for _ in 0..3 {
+        let response = res!(client.get(&fmt!("{}/hello", base)).await);
+        req!(HttpStatus::OK, res!(status_of(&response)));
+        req!("hello", &response.body_as_string());
+    }
+    req!(1, count.load(Ordering::SeqCst));
+    req!(1, res!(client.idle_count()));
+
+    // Methods and bodies.
+    let response = res!(client.post(&fmt!("{}/echo", base), HeaderFields::default(), b"abc".to_vec()).await);
+    req!("POST abc", &response.body_as_string());
+    let response = res!(client.put(&fmt!("{}/echo", base), HeaderFields::default(), b"def".to_vec()).await);
+    req!("PUT def", &response.body_as_string());
+    let response = res!(client.delete(&fmt!("{}/echo", base)).await);
+    req!("DELETE ", &response.body_as_string());
+
+    // Redirects, with a 303 turning a POST into a GET.
+    let response = res!(client.get(&fmt!("{}/redirect", base)).await);
+    req!("hello", &response.body_as_string());
+    let response = res!(client.post(&fmt!("{}/see-other", base), HeaderFields::default(), b"abc".to_vec()).await);
+    req!("GET ", &response.body_as_string());
+    req!(true, client.get(&fmt!("{}/loop", base)).await.is_err());
+    req!(1, count.load(Ordering::SeqCst));
+
+    // Credentials follow a redirect only within the origin.
+    let mut fields = HeaderFields::default();
+    fields.insert(HeaderName::Authorization, HeaderFieldValue::Generic(fmt!("Bearer abc")), None);
+    fields.insert(HeaderName::Cookie, HeaderFieldValue::Generic(fmt!("session_id=abc")), None);
+    let response = res!(client.request(
+        HttpMethod::GET, &fmt!("{}/same-origin", base), fields.clone(), Vec::new()).await);
+    req!("authorization true, cookie true", &response.body_as_string());
+    let response = res!(client.request(
+        HttpMethod::GET, &fmt!("{}/cross-origin", base), fields, Vec::new()).await);
+    req!("authorization false, cookie false", &response.body_as_string());
+    req!(2, count.load(Ordering::SeqCst));
+
+    // Chunked responses.
+    let response = res!(client.get(&fmt!("{}/chunked", base)).await);
+    req!("chunk".repeat(1_000), response.body_as_string().to_string());
+
+    // A body ending with the connection, which is then not reused.
+    let response = res!(client.get(&fmt!("{}/close", base)).await);
+    req!("until close", &response.body_as_string());
+    let response = res!(client.get(&fmt!("{}/hello", base)).await);
+    req!("hello", &response.body_as_string());
+    req!(3, count.load(Ordering::SeqCst));
+
+    // Responses without a body, whatever their length, on a connection that is then reused.
+    let response = res!(client.request(
+        HttpMethod::HEAD, &fmt!("{}/head", base), HeaderFields::default(), Vec::new()).await);
+    req!(HttpStatus::OK, res!(status_of(&response)));
+    req!(true, response.body.is_empty());
+    let response = res!(client.get(&fmt!("{}/not-modified", base)).await);
+    req!(HttpStatus::NotModified, res!(status_of(&response)));
+    req!(true, response.body.is_empty());
+    let response = res!(client.get(&fmt!("{}/interim", base)).await);
+    req!(HttpStatus::OK, res!(status_of(&response)));
+    req!("final", &response.body_as_string());
+    let response = res!(client.get(&fmt!("{}/hello", base)).await);
+    req!("hello", &response.body_as_string());
+    req!(3, count.load(Ordering::SeqCst));
+
+    // A switch of protocols is the final response, and the connection is not reused.
+    let response = res!(client.get(&fmt!("{}/switch", base)).await);
+    req!(HttpStatus::SwitchingProtocols, res!(status_of(&response)));
+    let response = res!(client.get(&fmt!("{}/hello", base)).await);
+    req!("hello", &response.body_as_string());
+    req!(4, count.load(Ordering::SeqCst));
+
+    // An idle connection closed by the server is replaced, but a malformed response on a reused
+    // connection is not retried.
+    let response = res!(client.get(&fmt!("{}/bye", base)).await);
+    req!("bye", &response.body_as_string());
+    let response = res!(client.get(&fmt!("{}/hello", base)).await);
+    req!("hello", &response.body_as_string());
+    req!(5, count.load(Ordering::SeqCst));
+    req!(true, client.get(&fmt!("{}/malformed", base)).await.is_err());
+    req!(5, count.load(Ordering::SeqCst));
+
+    // A body ending with the connection is still limited in size.
+    req!(true, client.get(&fmt!("{}/huge", base)).await.is_err());
+
+    // Timeouts.
+    req!(true, client.get(&fmt!("{}/slow", base)).await.is_err());
+    Ok(())
+}
+
+async fn test_client_tls() -> Outcome<()> {
+    let cert = res!(rcgen::generate_simple_self_signed(vec![fmt!("localhost")]));
+    let cert_der = CertificateDer::from(res!(cert.serialize_der()));
+    let key_der = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(cert.serialize_private_key_der()));
+    let server_cfg = res!(rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert_der.clone()], key_der));
+    let (port, count) = res!(start_server(Some(TlsAcceptor::from(Arc::new(server_cfg)))).await);
+
+    // Untrusted.
+    let client = HttpClient::new(HttpClientConfig::default());
+    req!(true, client.get(&fmt!("https://localhost:{}/hello", port)).await.is_err());
+
+    let mut cfg = HttpClientConfig::default();
+    res!(cfg.roots.add(cert_der));
+    let client = HttpClient::new(cfg);
+    for _ in 0..2 {
+        let response = res!(client.get(&fmt!("https://localhost:{}/redirect", port)).await);
+        req!("hello", &response.body_as_string());
+    }
+    let response = res!(client.get(&fmt!("https://localhost:{}/chunked", port)).await);
+    req!("chunk".repeat(1_000), response.body_as_string().to_string());
+    req!(2, count.load(Ordering::SeqCst)); // Including the untrusted attempt.
+
+    // No downgrade to HTTP.
+    req!(true, client.get(&fmt!("https://localhost:{}/insecure", port)).await.is_err());
     Ok(())
 }
@@ -2,6 +2,7 @@ mod dns;
 mod email;
 mod http;
 mod smtp;
+mod ws;
 
 use oxedyne_fe2o3_core::prelude::*;
 
@@ -25,12 +26,13 @@ fn main() -> Outcome<()> {
 
 fn run_tests() -> Outcome<()> {
 
-    let filter = "dns";
+    let filter = "all";
 
     res!(dns::test_dns(filter));
     res!(email::test_email(filter));
     res!(http::test_http(filter));
     res!(smtp::test_smtp(filter));
+    res!(ws::test_ws(filter));
 
     Ok(())
 }
@@ -6,8 +6,22 @@ use oxedyne_fe2o3_net::{
     //    EmailMessage,
     //},
     smtp::{
+        client::{
+            dot_stuff,
+            SmtpClient,
+            SmtpClientConfig,
+            SmtpCredentials,
+        },
         cmd::SmtpCommand,
+        codes::SmtpResponseCode,
+        handler::EmailHandler,
         //msg::SmtpMessageReader,
+        reply::SmtpReply,
+        session::{
+            SmtpEnvelope,
+            SmtpServerConfig,
+            SmtpSession,
+        },
     },
 };
 
@@ -21,6 +35,132 @@ use oxedyne_fe2o3_core::{
 //    pin::Pin,
 //};
 
+use std::{
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::Duration,
+};
+
+use tokio::{
+    io::{
+        AsyncBufRead,
+        AsyncBufReadExt,
+        AsyncRead,
+        AsyncWrite,
+        AsyncWriteExt,
+        BufReader,
+        DuplexStream,
+    },
+    net::TcpListener,
+    task::JoinHandle,
+};
+use tokio_rustls::{
+    rustls::{
+        self,
+        pki_types::{
+            CertificateDer,
+            PrivateKeyDer,
+            PrivatePkcs8KeyDer,
+            ServerName,
+        },
+    },
+    TlsConnector,
+};
+
+
+/// Keeps the messages received, refusing mail for `nobody` and failing to handle mail from `fail`.
+/// The only user is `user`, with the password `secret`.
+#[derive(Clone, Debug, Default)]
+struct TestEmailHandler {
+    received: Arc<Mutex<Vec<SmtpEnvelope>>>,
+}
+
+impl EmailHandler for TestEmailHandler {
+
+    fn accept_recipient(&self, address: &str) -> bool {
+        !address.starts_with("nobody@")
+    }
+
+    fn authenticate(&self, username: &str, password: &str) -> bool {
+        username == "user" && password == "secret"
+    }
+
+    fn handle_email(
+        &self,
+        envelope:   SmtpEnvelope,
+        _id:        &String,
+    )
+        -> impl std::future::Future<Output = Outcome<SmtpReply>> + Send
+    {
+        let received = self.received.clone();
+        async move {
+            if envelope.from.starts_with("fail@") {
+                return Err(err!("Could not store message."; Test, IO));
+            }
+            match received.lock() {
+                Ok(mut received) => received.push(envelope),
+                Err(_) => return Err(err!("Lock poisoned."; Test, Poisoned)),
+            }
+            Ok(SmtpReply::new(
+                SmtpResponseCode::RequestedMailActionOkayCompleted,
+                "2.0.0 Ok: queued",
+            ))
+        }
+    }
+}
+
+/// Start a session on one end of an in-memory stream, returning the other end for the client.
+fn start_session(
+    cfg:        SmtpServerConfig,
+    handler:    TestEmailHandler,
+)
+    -> (BufReader<DuplexStream>, JoinHandle<Outcome<()>>)
+{
+    let (client, server) = tokio::io::duplex(65_536);
+    let session = SmtpSession::new(cfg, handler, fmt!("Smtp|Test"));
+    (BufReader::new(client), tokio::spawn(session.run(server)))
+}
+
+/// Read a reply, returning the code and the text of each line.
+async fn read_reply<R: AsyncBufRead + Unpin>(stream: &mut R) -> Outcome<(u16, Vec<String>)> {
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        if res!(stream.read_line(&mut line).await) == 0 {
+            return Err(err!("Connection closed while reading a reply."; Test, Missing));
+        }
+        let line = line.trim_end();
+        let code = match line.get(..3).map(str::parse::<u16>) {
+            Some(Ok(code)) => code,
+            _ => return Err(err!("Invalid reply line '{}'.", line; Test, Invalid)),
+        };
+        lines.push(line.get(4..).unwrap_or("").to_string());
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok((code, lines));
+        }
+    }
+}
+
+/// Send a command and require a reply with the given code.
+async fn command<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut BufReader<S>,
+    cmd:    &str,
+    code:   u16,
+)
+    -> Outcome<Vec<String>>
+{
+    res!(stream.get_mut().write_all(fmt!("{}\r\n", cmd).as_bytes()).await);
+    let (actual, lines) = res!(read_reply(stream).await);
+    if actual != code {
+        return Err(err!(
+            "Expected {} in reply to '{}', received {} {:?}.", code, cmd, actual, lines;
+        Test, Mismatch));
+    }
+    Ok(lines)
+}
+
 
 pub fn test_smtp(filter: &'static str) -> Outcome<()> {
 
@@ -73,5 +213,387 @@ pub fn test_smtp(filter: &'static str) -> Outcome<()> {
         Ok(())
     }));
 
+    res!(test_it(filter, &["Smtp commands 001", "all", "smtp"], || {
+        let test = [
+            ("mail from:<sender@example.com>", SmtpCommand::MailFrom(fmt!("sender@example.com"))),
+            ("MAIL FROM: <sender@example.com> SIZE=1000 BODY=8BITMIME",
+                SmtpCommand::MailFrom(fmt!("sender@example.com"))),
+            ("MAIL FROM:<>", SmtpCommand::MailFrom(String::new())),
+            ("RCPT TO:<@relay.example.com:user@example.com>", SmtpCommand::RcptTo(fmt!("user@example.com"))),
+        ];
+        for (wire, expected) in test {
+            let cmd = res!(SmtpCommand::from_str(wire));
+            req!(expected, cmd, "(L: expected, R: actual)");
+        }
+        for wire in ["MAIL FROM:sender@example.com", "MAIL TO:<sender@example.com>", "RCPT TO:<a b@c>"] {
+            req!(true, SmtpCommand::from_str(wire).is_err(), "{}", wire);
+        }
+        req!(
+            vec![(fmt!("SIZE"), Some(fmt!("1000"))), (fmt!("SMTPUTF8"), None)],
+            res!(SmtpCommand::esmtp_params("MAIL FROM:<a@example.com> size=1000 SMTPUTF8")),
+        );
+        let reply = SmtpReply::multiline(
+            SmtpResponseCode::RequestedMailActionOkayCompleted,
+            vec![fmt!("mail.example.com"), fmt!("PIPELINING")],
+        );
+        req!(fmt!("250-mail.example.com\r\n250 PIPELINING\r\n"), reply.to_string());
+        let rt = res!(tokio::runtime::Runtime::new());
+        let read = |wire: String| rt.block_on(async move {
+            SmtpReply::read(&mut wire.as_bytes()).await
+        });
+        req!(reply, res!(read(reply.to_string())));
+        // Hostile servers cannot make the client buffer without limit.
+        req!(true, read(fmt!("250 {}\r\n", "x".repeat(600))).is_err());
+        req!(true, read(fmt!("{}250 End\r\n", "250-More\r\n".repeat(200))).is_err());
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Smtp session 000", "all", "smtp", "session"], || {
+        let rt = res!(tokio::runtime::Runtime::new());
+        rt.block_on(async {
+            let handler = TestEmailHandler::default();
+            let (mut client, session) = start_session(SmtpServerConfig::default(), handler.clone());
+            let (code, _) = res!(read_reply(&mut client).await);
+            req!(220, code);
+            let lines = res!(command(&mut client, "EHLO client.example.com", 250).await);
+            for capability in ["PIPELINING", "SIZE 10485760", "8BITMIME", "ENHANCEDSTATUSCODES"] {
+                req!(true, lines.contains(&capability.to_string()), "{}", capability);
+            }
+            req!(false, lines.contains(&fmt!("STARTTLS")));
+            res!(command(&mut client, "MAIL FROM:<sender@example.org>", 250).await);
+            res!(command(&mut client, "RCPT TO:<recipient@example.com>", 250).await);
+            res!(command(&mut client, "RCPT TO:<other@example.com>", 250).await);
+            res!(command(&mut client, "DATA", 354).await);
+            let data = "Subject: Test\r\n\r\n..A line starting with a dot.\r\nEnd\r\n.\r\n";
+            res!(client.get_mut().write_all(data.as_bytes()).await);
+            req!(true, res!(read_reply(&mut client).await).1[0].starts_with("2.0.0"));
+            // A second transaction on the same session.
+            res!(command(&mut client, "MAIL FROM:<>", 250).await);
+            res!(command(&mut client, "RCPT TO:<recipient@example.com>", 250).await);
+            res!(command(&mut client, "DATA", 354).await);
+            res!(client.get_mut().write_all(b"Bounce\r\n.\r\n").await);
+            req!(250, res!(read_reply(&mut client).await).0);
+            res!(command(&mut client, "QUIT", 221).await);
+            res!(res!(session.await));
+
+            let received = match handler.received.lock() {
+                Ok(received) => received.clone(),
+                Err(_) => return Err(err!("Lock poisoned."; Test, Poisoned)),
+            };
+            req!(2, received.len());
+            req!(fmt!("client.example.com"), received[0].client.clone());
+            req!(fmt!("sender@example.org"), received[0].from.clone());
+            req!(vec![fmt!("recipient@example.com"), fmt!("other@example.com")], received[0].to.clone());
+            req!(b"Subject: Test\r\n\r\n.A line starting with a dot.\r\nEnd\r\n".to_vec(),
+                received[0].data.clone());
+            req!(false, received[0].tls);
+            req!(String::new(), received[1].from.clone());
+            req!(b"Bounce\r\n".to_vec(), received[1].data.clone());
+            Ok(())
+        })
+    }));
+
+    res!(test_it(filter, &["Smtp session 001", "all", "smtp", "session"], || {
+        let rt = res!(tokio::runtime::Runtime::new());
+        rt.block_on(async {
+            let handler = TestEmailHandler::default();
+            let cfg = SmtpServerConfig {
+                max_message_size:   100,
+                max_recipients:     2,
+                ..Default::default()
+            };
+            let (mut client, session) = start_session(cfg, handler.clone());
+            req!(220, res!(read_reply(&mut client).await).0);
+
+            // Commands out of sequence.
+            res!(command(&mut client, "MAIL FROM:<sender@example.org>", 503).await);
+            res!(command(&mut client, "EHLO client.example.com", 250).await);
+            res!(command(&mut client, "RCPT TO:<recipient@example.com>", 503).await);
+            res!(command(&mut client, "DATA", 503).await);
+            res!(command(&mut client, "MAIL FROM:<sender@example.org>", 250).await);
+            res!(command(&mut client, "MAIL FROM:<sender@example.org>", 503).await);
+            res!(command(&mut client, "DATA", 554).await);
+            res!(command(&mut client, "RSET", 250).await);
+            res!(command(&mut client, "RCPT TO:<recipient@example.com>", 503).await);
+
+            // A pipelined transaction, with replies in order.
+            res!(client.get_mut().write_all(
+                b"MAIL FROM:<sender@example.org>\r\n\
+                RCPT TO:<nobody@example.com>\r\n\
+                RCPT TO:<a@example.com>\r\n\
+                RCPT TO:<b@example.com>\r\n\
+                RCPT TO:<c@example.com>\r\n\
+                DATA\r\n"
+            ).await);
+            for code in [250, 550, 250, 250, 452, 354] {
+                req!(code, res!(read_reply(&mut client).await).0);
+            }
+            res!(client.get_mut().write_all(b"Short.\r\n.\r\n").await);
+            req!(250, res!(read_reply(&mut client).await).0);
+
+            // Size limits, declared or not.
+            res!(command(&mut client, "MAIL FROM:<sender@example.org> SIZE=101", 552).await);
+            res!(command(&mut client, "MAIL FROM:<sender@example.org> SIZE=100", 250).await);
+            res!(command(&mut client, "RCPT TO:<a@example.com>", 250).await);
+            res!(command(&mut client, "DATA", 354).await);
+            let data = fmt!("{}\r\n.\r\n", "x".repeat(200));
+            res!(client.get_mut().write_all(data.as_bytes()).await);
+            req!(552, res!(read_reply(&mut client).await).0);
+            // The transaction was abandoned.
+            res!(command(&mut client, "DATA", 503).await);
+
+            // A bare LF neither ends the data nor is accepted, so commands cannot be smuggled.
+            res!(command(&mut client, "MAIL FROM:<sender@example.org>", 250).await);
+            res!(command(&mut client, "RCPT TO:<a@example.com>", 250).await);
+            res!(command(&mut client, "DATA", 354).await);
+            res!(client.get_mut().write_all(
+                b"Smuggle\n.\r\nMAIL FROM:<evil@example.org>\r\n.\n\r\n.\r\n").await);
+            req!(554, res!(read_reply(&mut client).await).0);
+            res!(command(&mut client, "DATA", 503).await);
+
+            // A handler failure is transient.
+            res!(command(&mut client, "MAIL FROM:<fail@example.org>", 250).await);
+            res!(command(&mut client, "RCPT TO:<a@example.com>", 250).await);
+            res!(command(&mut client, "DATA", 354).await);
+            res!(client.get_mut().write_all(b"Lost.\r\n.\r\n").await);
+            req!(451, res!(read_reply(&mut client).await).0);
+
+            // Parameters are only for EHLO clients.
+            res!(command(&mut client, "HELO client.example.com", 250).await);
+            res!(command(&mut client, "MAIL FROM:<sender@example.org> SIZE=10", 555).await);
+            res!(command(&mut client, "MAIL FROM:<sender@example.org>", 250).await);
+            res!(command(&mut client, "RCPT TO:<a@example.com> NOTIFY=NEVER", 555).await);
+
+            res!(command(&mut client, "NOOP", 250).await);
+            res!(command(&mut client, "VRFY someone", 252).await);
+            res!(command(&mut client, "STARTTLS", 502).await);
+            res!(command(&mut client, "FOO", 500).await);
+            res!(command(&mut client, "MAIL FROM:sender", 501).await);
+            let long = fmt!("NOOP {}", "x".repeat(3_000));
+            res!(command(&mut client, &long, 500).await);
+            res!(command(&mut client, "250 Ok", 500).await);
+
+            req!(1, match handler.received.lock() {
+                Ok(received) => received.len(),
+                Err(_) => return Err(err!("Lock poisoned."; Test, Poisoned)),
+            });
+
+            // Too many errors closes the session.
+            for _ in 0..5 {
+                res!(command(&mut client, "FOO", 500).await);
+            }
+            res!(command(&mut client, "FOO", 421).await);
+            res!(res!(session.await));
+            let mut rest = String::new();
+            req!(0, res!(client.read_line(&mut rest).await));
+            Ok(())
+        })
+    }));
+
+    res!(test_it(filter, &["Smtp session 002", "all", "smtp", "session"], || {
+        let rt = res!(tokio::runtime::Runtime::new());
+        rt.block_on(async {
+            let cert = res!(rcgen::generate_simple_self_signed(vec![fmt!("localhost")]));
+            let cert_der = CertificateDer::from(res!(cert.serialize_der()));
+            let key_der = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(cert.serialize_private_key_der()));
+            let server_tls = res!(rustls::ServerConfig::builder()
+                .with_no_client_auth()
+                .with_single_cert(vec![cert_der.clone()], key_der));
+            let mut roots = rustls::RootCertStore::empty();
+            res!(roots.add(cert_der));
+            let client_tls = rustls::ClientConfig::builder()
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+
+            let handler = TestEmailHandler::default();
+            let cfg = SmtpServerConfig {
+                tls:    Some(Arc::new(server_tls)),
+                auth:   true,
+                ..Default::default()
+            };
+            let (mut client, session) = start_session(cfg, handler.clone());
+            req!(220, res!(read_reply(&mut client).await).0);
+            let lines = res!(command(&mut client, "EHLO client.example.com", 250).await);
+            req!(true, lines.contains(&fmt!("STARTTLS")));
+            // Authentication is only offered over TLS.
+            req!(false, lines.contains(&fmt!("AUTH PLAIN LOGIN")));
+            res!(command(&mut client, "AUTH PLAIN AHVzZXIAc2VjcmV0", 502).await);
+            res!(command(&mut client, "MAIL FROM:<sender@example.org>", 250).await);
+            // A command injected before the handshake must be ignored.
+            res!(client.get_mut().write_all(b"STARTTLS\r\nRCPT TO:<injected@example.com>\r\n").await);
+            req!(220, res!(read_reply(&mut client).await).0);
+
+            let connector = TlsConnector::from(Arc::new(client_tls));
+            let server_name = res!(ServerName::try_from("localhost"));
+            let stream = res!(connector.connect(server_name, client.into_inner()).await);
+            let mut client = BufReader::new(stream);
+            // The session starts again.
+            res!(command(&mut client, "MAIL FROM:<sender@example.org>", 503).await);
+            let lines = res!(command(&mut client, "EHLO client.example.com", 250).await);
+            req!(false, lines.contains(&fmt!("STARTTLS")));
+            req!(true, lines.contains(&fmt!("AUTH PLAIN LOGIN")));
+            res!(command(&mut client, "STARTTLS", 502).await);
+            req!(vec![fmt!("VXNlcm5hbWU6")], res!(command(&mut client, "AUTH LOGIN", 334).await));
+            req!(vec![fmt!("UGFzc3dvcmQ6")], res!(command(&mut client, "dXNlcg==", 334).await));
+            res!(command(&mut client, "d3Jvbmc=", 535).await); // "wrong"
+            res!(command(&mut client, "AUTH CRAM-MD5", 504).await);
+            res!(command(&mut client, "AUTH PLAIN", 334).await);
+            res!(command(&mut client, "*", 501).await);
+            // "\0user\0secret"
+            res!(command(&mut client, "AUTH PLAIN AHVzZXIAc2VjcmV0", 235).await);
+            res!(command(&mut client, "AUTH PLAIN AHVzZXIAc2VjcmV0", 503).await);
+            res!(command(&mut client, "MAIL FROM:<sender@example.org>", 250).await);
+            res!(command(&mut client, "RCPT TO:<recipient@example.com>", 250).await);
+            res!(command(&mut client, "DATA", 354).await);
+            res!(client.get_mut().write_all(b"Secret.\r\n.\r\n").await);
+            req!(250, res!(read_reply(&mut client).await).0);
+            res!(command(&mut client, "QUIT", 221).await);
+            res!(res!(session.await));
+
+            let received = match handler.received.lock() {
+                Ok(received) => received.clone(),
+                Err(_) => return Err(err!("Lock poisoned."; Test, Poisoned)),
+            };
+            req!(1, received.len());
+            req!(vec![fmt!("recipient@example.com")], received[0].to.clone());
+            req!(true, received[0].tls);
+            req!(Some(fmt!("user")), received[0].user.clone());
+            Ok(())
+        })
+    }));
+
+    res!(test_it(filter, &["Smtp client 000", "all", "smtp", "client"], || {
+        req!(b"a\r\n..b\r\n\r\n...\r\n".to_vec(), dot_stuff(b"a\n.b\r\n\n..\n"));
+        req!(b"..\r\n".to_vec(), dot_stuff(b"."));
+        let rt = res!(tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(4)
+            .enable_all()
+            .build());
+        rt.block_on(test_client())
+    }));
+
+    res!(test_it(filter, &["Smtp session 003", "all", "smtp", "session"], || {
+        let rt = res!(tokio::runtime::Runtime::new());
+        rt.block_on(async {
+            let cfg = SmtpServerConfig {
+                timeout: Duration::from_millis(100),
+                ..Default::default()
+            };
+            let (mut client, session) = start_session(cfg, TestEmailHandler::default());
+            req!(220, res!(read_reply(&mut client).await).0);
+            req!(421, res!(read_reply(&mut client).await).0);
+            res!(res!(session.await));
+            Ok(())
+        })
+    }));
+
+    Ok(())
+}
+
+/// Listen on loopback, serving SMTP sessions with the given configuration.
+async fn start_server(cfg: SmtpServerConfig, handler: TestEmailHandler) -> Outcome<u16> {
+    let listener = res!(TcpListener::bind("127.0.0.1:0").await);
+    let port = res!(listener.local_addr()).port();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let session = SmtpSession::new(cfg.clone(), handler.clone(), fmt!("Smtp|Test"));
+            tokio::spawn(session.run(stream));
+        }
+    });
+    Ok(port)
+}
+
+async fn test_client() -> Outcome<()> {
+    let cert = res!(rcgen::generate_simple_self_signed(vec![fmt!("localhost")]));
+    let cert_der = CertificateDer::from(res!(cert.serialize_der()));
+    let key_der = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(cert.serialize_private_key_der()));
+    let server_tls = res!(rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert_der.clone()], key_der));
+    let handler = TestEmailHandler::default();
+    let tls_port = res!(start_server(SmtpServerConfig {
+        tls:    Some(Arc::new(server_tls)),
+        auth:   true,
+        ..Default::default()
+    }, handler.clone()).await);
+    let plain_port = res!(start_server(SmtpServerConfig::default(), handler.clone()).await);
+
+    let mut cfg = SmtpClientConfig {
+        hostname:       fmt!("client.example.com"),
+        credentials:    Some(SmtpCredentials {
+            username: fmt!("user"),
+            password: fmt!("secret"),
+        }),
+        ..Default::default()
+    };
+    // Untrusted.
+    req!(true, SmtpClient::connect("localhost", tls_port, cfg.clone()).await.is_err());
+    res!(cfg.roots.add(cert_der));
+
+    let mut client = res!(SmtpClient::connect("localhost", tls_port, cfg.clone()).await);
+    req!(true, client.is_tls());
+    req!(true, client.supports("pipelining"));
+    let to = vec![fmt!("a@example.com"), fmt!("nobody@example.com"), fmt!("b@example.com")];
+    let data = "Subject: Caf\u{e9}\n\n.Hidden dot\n.\nEnd".as_bytes();
+    let report = res!(client.send("sender@example.org", &to, data).await);
+    req!(true, report.is_delivered());
+    req!(vec![&to[0], &to[2]], report.accepted());
+    req!(1, report.rejected().len());
+    req!(SmtpResponseCode::MailboxUnavailableOrAccessDenied, report.rejected()[0].1.code.clone());
+    // Every recipient refused.
+    let report = res!(client.send("sender@example.org", &[fmt!("nobody@example.com")], b"Lost").await);
+    req!(false, report.is_delivered());
+    req!(true, report.data.is_none());
+    // The server refuses the content.
+    let report = res!(client.send("fail@example.org", &[fmt!("a@example.com")], b"Lost").await);
+    req!(false, report.is_delivered());
+    req!(true, report.data.is_some());
+    res!(client.quit().await);
+
+    {
+        let received = match handler.received.lock() {
+            Ok(received) => received.clone(),
+            Err(_) => return Err(err!("Lock poisoned."; Test, Poisoned)),
+        };
+        req!(1, received.len());
+        req!(fmt!("client.example.com"), received[0].client.clone());
+        req!(vec![to[0].clone(), to[2].clone()], received[0].to.clone());
+        req!("Subject: Caf\u{e9}\r\n\r\n.Hidden dot\r\n.\r\nEnd\r\n".as_bytes().to_vec(),
+            received[0].data.clone());
+        req!(Some(fmt!("user")), received[0].user.clone());
+        req!(true, received[0].tls);
+    }
+
+    // Wrong password.
+    let mut bad = cfg.clone();
+    bad.credentials = Some(SmtpCredentials {
+        username: fmt!("user"),
+        password: fmt!("wrong"),
+    });
+    let result = SmtpClient::connect("localhost", tls_port, bad).await;
+    req!(true, result.is_err());
+
+    // No TLS.
+    req!(true, SmtpClient::connect("localhost", plain_port, cfg.clone()).await.is_err());
+    cfg.require_tls = false;
+    // Credentials are never sent in the clear.
+    req!(true, SmtpClient::connect("localhost", plain_port, cfg.clone()).await.is_err());
+    cfg.credentials = None;
+    let mut client = res!(SmtpClient::connect("localhost", plain_port, cfg).await);
+    req!(false, client.is_tls());
+    let report = res!(client.send("", &[fmt!("a@example.com")], b"Bounce\r\n").await);
+    req!(true, report.is_delivered());
+    // Addresses cannot inject commands.
+    for (from, to) in [
+        ("a@example.org>\r\nRCPT TO:<b@example.com", "a@example.com"),
+        ("a@example.org", "a@example.com> NOTIFY=NEVER"),
+        ("a@example.org", "a@example.com\nDATA"),
+        ("a@example.org", ""),
+    ] {
+        req!(true, client.send(from, &[to.to_string()], b"Injected\r\n").await.is_err());
+    }
+    let report = res!(client.send("", &[fmt!("a@example.com")], b"Still connected\r\n").await);
+    req!(true, report.is_delivered());
+    res!(client.quit().await);
     Ok(())
 }
@@ -0,0 +1,414 @@
+use oxedyne_fe2o3_net::{
+    http::{
+        header::HttpHeader,
+        msg::HttpMessage,
+    },
+    ws::{
+        WebSocket,
+        WebSocketMessage,
+        connect_request,
+        deflate::WebSocketDeflate,
+        handler::WebSocketSinkHandler,
+        status::WebSocketStatusCode,
+    },
+};
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    rand::RanDef,
+    test::test_it,
+};
+use oxedyne_fe2o3_iop_db::api::{
+    Database,
+    Meta,
+    RestSchemesOverride,
+};
+use oxedyne_fe2o3_jdat::prelude::*;
+use oxedyne_fe2o3_namex::id::{
+    InNamex,
+    NamexId,
+};
+
+use tokio::io::{
+    AsyncReadExt,
+    AsyncWriteExt,
+    DuplexStream,
+};
+
+
+/// The websocket is generic over a database, which these tests do not need.
+#[derive(Debug)]
+struct NoDb;
+
+impl InNamex for NoDb {
+    fn name_id(&self) -> Outcome<NamexId> {
+        Ok(NamexId::randef())
+    }
+}
+
+impl Database<8, u64, (), ()> for NoDb {
+    fn insert(
+        &self,
+        _key:   Dat,
+        _val:   Dat,
+        _user:  u64,
+        _or:    Option<&RestSchemesOverride<(), ()>>,
+    )
+        -> Outcome<(bool, usize)>
+    {
+        Err(err!("No database."; Test, Missing))
+    }
+
+    fn get(
+        &self,
+        _key:   &Dat,
+        _or:    Option<&RestSchemesOverride<(), ()>>,
+    )
+        -> Outcome<Option<(Dat, Meta<8, u64>)>>
+    {
+        Err(err!("No database."; Test, Missing))
+    }
+
+    fn delete(
+        &self,
+        _key:   &Dat,
+        _user:  u64,
+        _or:    Option<&RestSchemesOverride<(), ()>>,
+    )
+        -> Outcome<bool>
+    {
+        Err(err!("No database."; Test, Missing))
+    }
+}
+
+type TestWebSocket<'a> = WebSocket<'a, 8, u64, (), (), NoDb, DuplexStream, WebSocketSinkHandler>;
+
+const MASK: [u8; 4] = [0x37, 0xfa, 0x21, 0x3d];
+
+/// A frame from a client, masked, with a payload shorter than 126 bytes.
+fn client_frame(byt0: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![byt0, 0x80 | payload.len() as u8];
+    frame.extend_from_slice(&MASK);
+    frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ MASK[i % 4]));
+    frame
+}
+
+/// Read a frame sent by a server, returning the first byte and the payload.
+async fn server_frame(peer: &mut DuplexStream) -> Outcome<(u8, Vec<u8>)> {
+    let mut head = [0u8; 2];
+    res!(peer.read_exact(&mut head).await);
+    let len = match head[1] {
+        126 => {
+            let mut ext = [0u8; 2];
+            res!(peer.read_exact(&mut ext).await);
+            u16::from_be_bytes(ext) as usize
+        }
+        127 => {
+            let mut ext = [0u8; 8];
+            res!(peer.read_exact(&mut ext).await);
+            u64::from_be_bytes(ext) as usize
+        }
+        len => len as usize,
+    };
+    let mut payload = vec![0u8; len];
+    res!(peer.read_exact(&mut payload).await);
+    Ok((head[0], payload))
+}
+
+/// Read an HTTP message header.
+async fn read_head(peer: &mut DuplexStream) -> Outcome<String> {
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut byt = [0u8; 1];
+        res!(peer.read_exact(&mut byt).await);
+        head.push(byt[0]);
+    }
+    Ok(String::from_utf8_lossy(&head).to_string())
+}
+
+/// Upgrade a server websocket, offering permessage-deflate, and return the response.
+async fn upgrade_server(ws: &mut TestWebSocket<'_>, peer: &mut DuplexStream) -> Outcome<String> {
+    let (request, _key) = res!(connect_request("localhost"));
+    res!(ws.connect_as_server(request).await);
+    read_head(peer).await
+}
+
+/// Expect the connection to fail with a close frame carrying the given status.
+async fn expect_failure(
+    ws:     &mut TestWebSocket<'_>,
+    peer:   &mut DuplexStream,
+    code:   WebSocketStatusCode,
+)
+    -> Outcome<()>
+{
+    req!(true, ws.read().await.is_err());
+    let (byt0, payload) = res!(server_frame(peer).await);
+    req!(0x88, byt0);
+    req!(code.to_bytes().to_vec(), payload);
+    // Nothing more is read from a failed connection.
+    res!(peer.write_all(&client_frame(0x81, b"ignored")).await);
+    req!(true, res!(ws.read().await).is_none());
+    Ok(())
+}
+
+pub fn test_ws(filter: &'static str) -> Outcome<()> {
+
+    res!(test_it(filter, &["Deflate 000", "all", "ws", "deflate"], || {
+        // RFC 7692 section 7.2.3.1.
+        let mut deflate = res!(WebSocketDeflate::accepted(&[fmt!("permessage-deflate")]));
+        let byts = res!(deflate.decompress(&[0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00], 100));
+        req!(b"Hello".to_vec(), byts);
+        // Without a practical limit.
+        let mut deflate = res!(WebSocketDeflate::accepted(&[fmt!("permessage-deflate")]));
+        let byts = res!(deflate.decompress(&[0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00], usize::MAX));
+        req!(b"Hello".to_vec(), byts);
+        // The same message is shorter the second time, using the window of the first.
+        let msg = "A message worth compressing, a message worth compressing.".as_bytes();
+        let mut client = res!(WebSocketDeflate::accepted(&[fmt!("permessage-deflate")]));
+        let (mut server, _) = match WebSocketDeflate::accept(&[fmt!("permessage-deflate")]) {
+            Some(accepted) => accepted,
+            None => return Err(err!("Offer declined."; Test, Mismatch)),
+        };
+        let first = res!(server.compress(msg));
+        let second = res!(server.compress(msg));
+        req!(true, first.len() < msg.len());
+        req!(true, second.len() < first.len());
+        req!(msg.to_vec(), res!(client.decompress(&first, 1_000)));
+        req!(msg.to_vec(), res!(client.decompress(&second, 1_000)));
+        // Unless there is no context takeover.
+        let (mut server, response) = match WebSocketDeflate::accept(&[
+            fmt!("permessage-deflate; server_no_context_takeover"),
+        ]) {
+            Some(accepted) => accepted,
+            None => return Err(err!("Offer declined."; Test, Mismatch)),
+        };
+        req!(fmt!("permessage-deflate; server_no_context_takeover"), response.clone());
+        let mut client = res!(WebSocketDeflate::accepted(&[response]));
+        let first = res!(server.compress(msg));
+        let second = res!(server.compress(msg));
+        req!(first.clone(), second.clone());
+        req!(msg.to_vec(), res!(client.decompress(&first, 1_000)));
+        req!(msg.to_vec(), res!(client.decompress(&second, 1_000)));
+        // A small message may expand hugely.
+        let bomb = res!(server.compress(&vec![0u8; 1_000_000]));
+        req!(true, bomb.len() < 2_000);
+        req!(true, client.decompress(&bomb, 10_000).is_err());
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Deflate 001", "all", "ws", "deflate"], || {
+        let accept = |offers: &[&str]| WebSocketDeflate::accept(
+            &offers.iter().map(|s| s.to_string()).collect::<Vec<_>>()
+        ).map(|(_, response)| response);
+        req!(Some(fmt!("permessage-deflate")),
+            accept(&["x-webkit-deflate-frame, permessage-deflate; client_max_window_bits"]));
+        // The server window cannot be limited, so the first offer is declined.
+        req!(Some(fmt!("permessage-deflate; client_no_context_takeover")), accept(&[
+            "permessage-deflate; server_max_window_bits=10",
+            "permessage-deflate; client_no_context_takeover; server_max_window_bits=\"15\"",
+        ]));
+        req!(None::<String>, accept(&["permessage-deflate; unknown"]));
+        req!(None::<String>, accept(&["permessage-deflate; client_max_window_bits=16"]));
+        req!(None::<String>, accept(&["permessage-deflate; server_no_context_takeover; server_no_context_takeover"]));
+        req!(None::<String>, accept(&["x-webkit-deflate-frame"]));
+        // The client only offers the defaults.
+        let accepted = |response: &str| WebSocketDeflate::accepted(&[response.to_string()]);
+        req!(true, accepted("permessage-deflate; server_max_window_bits=9").is_ok());
+        req!(true, accepted("permessage-deflate; client_max_window_bits=9").is_err());
+        req!(true, accepted("permessage-deflate, permessage-deflate").is_err());
+        req!(true, accepted("x-webkit-deflate-frame").is_err());
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["WebSocket 000", "all", "ws"], || {
+        let rt = res!(tokio::runtime::Runtime::new());
+        rt.block_on(async {
+            let (mut stream, mut peer) = tokio::io::duplex(4_096);
+            let mut ws: TestWebSocket<'_> = WebSocket::new_server(
+                &mut stream, WebSocketSinkHandler, 10, 20);
+            let response = res!(upgrade_server(&mut ws, &mut peer).await);
+            req!(true, response.contains("Sec-WebSocket-Extensions: permessage-deflate\r\n"));
+            req!(true, ws.is_deflate());
+
+            // A control frame between the frames of a fragmented message.
+            res!(peer.write_all(&client_frame(0x01, b"Hel")).await);
+            res!(peer.write_all(&client_frame(0x89, b"ping")).await);
+            res!(peer.write_all(&client_frame(0x80, b"lo")).await);
+            match res!(ws.read().await) {
+                Some(WebSocketMessage::Ping(byts)) => req!(b"ping".to_vec(), byts),
+                other => return Err(err!("Expected a ping, found {:?}.", other; Test, Mismatch)),
+            }
+            match res!(ws.read().await) {
+                Some(WebSocketMessage::Text(txt)) => req!(fmt!("Hello"), txt),
+                other => return Err(err!("Expected text, found {:?}.", other; Test, Mismatch)),
+            }
+
+            // A compressed message in two fragments, RFC 7692 section 7.2.3.1, with only the
+            // first frame marked as compressed.
+            res!(peer.write_all(&client_frame(0x41, &[0xf2, 0x48, 0xcd])).await);
+            res!(peer.write_all(&client_frame(0x80, &[0xc9, 0xc9, 0x07, 0x00])).await);
+            match res!(ws.read().await) {
+                Some(WebSocketMessage::Text(txt)) => req!(fmt!("Hello"), txt),
+                other => return Err(err!("Expected text, found {:?}.", other; Test, Mismatch)),
+            }
+
+            // Large messages are sent compressed, in fragments with RSV1 set on the first.
+            let msg = "Hello websocket, ".repeat(10);
+            res!(ws.send(&WebSocketMessage::Text(msg.clone())).await);
+            let mut deflate = res!(WebSocketDeflate::accepted(&[fmt!("permessage-deflate")]));
+            let mut data = Vec::new();
+            let mut frames = Vec::new();
+            loop {
+                let (byt0, payload) = res!(server_frame(&mut peer).await);
+                frames.push(byt0);
+                data.extend_from_slice(&payload);
+                if byt0 & 0x80 != 0 {
+                    break;
+                }
+            }
+            req!(true, frames.len() > 1);
+            req!(0x41, frames[0]);
+            for byt0 in &frames[1..] {
+                req!(0x00, byt0 & 0x7f);
+            }
+            let expected = msg.into_bytes();
+            req!(expected, res!(deflate.decompress(&data, 1_000)));
+            // Control frames are never fragmented.
+            res!(ws.send(&WebSocketMessage::Ping(vec![7u8; 100])).await);
+            req!((0x89, vec![7u8; 100]), res!(server_frame(&mut peer).await));
+            req!(true, ws.send(&WebSocketMessage::Ping(vec![7u8; 126])).await.is_err());
+
+            // A continuation frame without a message to continue.
+            res!(peer.write_all(&client_frame(0x80, b"lo")).await);
+            expect_failure(&mut ws, &mut peer, WebSocketStatusCode::ProtocolError).await
+        })
+    }));
+
+    res!(test_it(filter, &["WebSocket 001", "all", "ws"], || {
+        let rt = res!(tokio::runtime::Runtime::new());
+        // Each case is a sequence of client frames that fails the connection with the status.
+        let cases: Vec<(Vec<Vec<u8>>, WebSocketStatusCode)> = vec![
+            // A new message before the previous one is finished.
+            (vec![client_frame(0x01, b"ab"), client_frame(0x81, b"cd")],
+                WebSocketStatusCode::ProtocolError),
+            // A fragmented control frame.
+            (vec![client_frame(0x09, b"ab")], WebSocketStatusCode::ProtocolError),
+            // Reserved bits.
+            (vec![client_frame(0xa1, b"ab")], WebSocketStatusCode::ProtocolError),
+            // RSV1 on a control frame.
+            (vec![client_frame(0xc9, b"ab")], WebSocketStatusCode::ProtocolError),
+            // A close frame with an unknown status code.
+            (vec![client_frame(0x88, &999u16.to_be_bytes())], WebSocketStatusCode::ProtocolError),
+            // A close frame with a status code reserved for local use.
+            (vec![client_frame(0x88, &1005u16.to_be_bytes())],
+                WebSocketStatusCode::ProtocolError),
+            // An unknown opcode.
+            (vec![client_frame(0x83, b"ab")], WebSocketStatusCode::ProtocolError),
+            // Unmasked.
+            (vec![vec![0x81, 0x02, b'a', b'b']], WebSocketStatusCode::ProtocolError),
+            // Invalid UTF-8 text.
+            (vec![client_frame(0x01, &[0xe2, 0x82]), client_frame(0x80, &[0x28])],
+                WebSocketStatusCode::InvalidFramePayloadData),
+            // Too big once reassembled, before the payload arrives.
+            (vec![client_frame(0x02, &[0u8; 60]), vec![0x80, 0x80 | 126, 0x00, 0xff]],
+                WebSocketStatusCode::MessageTooBig),
+            // A 64-bit length with the most significant bit set.
+            (vec![[&[0x82, 0x80 | 127][..], &[0xff; 8]].concat()],
+                WebSocketStatusCode::ProtocolError),
+            // The largest continuation length, after a first fragment.
+            (vec![client_frame(0x02, &[0u8; 60]),
+                [&[0x80, 0x80 | 127, 0x7f][..], &[0xff; 7]].concat()],
+                WebSocketStatusCode::MessageTooBig),
+            // Too big once decompressed.
+            (vec![client_frame(0xc2, &{
+                let mut deflate = res!(WebSocketDeflate::accepted(&[fmt!("permessage-deflate")]));
+                res!(deflate.compress(&[0u8; 1_000]))
+            })], WebSocketStatusCode::MessageTooBig),
+        ];
+        for (frames, code) in cases {
+            res!(rt.block_on(async {
+                let (mut stream, mut peer) = tokio::io::duplex(4_096);
+                let mut ws: TestWebSocket<'_> = WebSocket::new_server(
+                    &mut stream, WebSocketSinkHandler, 10, 20)
+                    .with_max_message_size(100);
+                res!(upgrade_server(&mut ws, &mut peer).await);
+                for frame in frames {
+                    res!(peer.write_all(&frame).await);
+                }
+                expect_failure(&mut ws, &mut peer, code).await
+            }));
+        }
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["WebSocket 002", "all", "ws"], || {
+        let rt = res!(tokio::runtime::Runtime::new());
+        for allow_deflate in [true, false] {
+            res!(rt.block_on(async {
+                let (mut client_stream, mut client_peer) = tokio::io::duplex(4_096);
+                let (mut server_stream, mut server_peer) = tokio::io::duplex(4_096);
+                let mut client: TestWebSocket<'_> = WebSocket::new_client(
+                    &mut client_stream, WebSocketSinkHandler, 10, 20);
+                let mut server: TestWebSocket<'_> = WebSocket::new_server(
+                    &mut server_stream, WebSocketSinkHandler, 1_000, 2_000)
+                    .with_deflate(allow_deflate);
+                let (request, key) = res!(connect_request("localhost"));
+                let (client_result, server_result) = tokio::join!(
+                    client.connect(request, Some(key)),
+                    async {
+                        // Pass the request to the server as an HTTP server would, and relay the
+                        // response back to the client.
+                        let request = res!(read_head(&mut client_peer).await);
+                        let request = HttpMessage {
+                            header: res!(HttpHeader::parse(request, Some(true))),
+                            ..Default::default()
+                        };
+                        res!(server.connect(request, None).await);
+                        let response = res!(read_head(&mut server_peer).await);
+                        res!(client_peer.write_all(response.as_bytes()).await);
+                        Ok(())
+                    },
+                );
+                res!(client_result);
+                res!(server_result);
+                tokio::spawn(async move {
+                    let _ = tokio::io::copy_bidirectional(&mut client_peer, &mut server_peer).await;
+                });
+                req!(allow_deflate, client.is_deflate());
+                req!(allow_deflate, server.is_deflate());
+
+                let text = "Round trip ".repeat(1_000);
+                let byts: Vec<u8> = (0..50_000u32).map(|i| (i % 251) as u8).collect();
+                // Send and read concurrently, as the messages exceed the stream buffers.
+                let text_msg = WebSocketMessage::Text(text.clone());
+                let byts_msg = WebSocketMessage::Binary(byts.clone());
+                for _ in 0..2 {
+                    let (sent, received) = tokio::join!(
+                        client.send(&text_msg),
+                        server.read(),
+                    );
+                    res!(sent);
+                    match res!(received) {
+                        Some(WebSocketMessage::Text(txt)) => req!(text.clone(), txt),
+                        other => return Err(err!("Expected text, found {:?}.", other;
+                            Test, Mismatch)),
+                    }
+                    let (sent, received) = tokio::join!(
+                        server.send(&byts_msg),
+                        client.read(),
+                    );
+                    res!(sent);
+                    match res!(received) {
+                        Some(WebSocketMessage::Binary(b)) => req!(byts.clone(), b),
+                        other => return Err(err!("Expected binary, found {:?}.", other;
+                            Test, Mismatch)),
+                    }
+                }
+                Ok(())
+            }));
+        }
+        Ok(())
+    }));
+
+    Ok(())
+}
@@ -0,0 +1,249 @@
+//! Example of an application extending Shield messaging with its own commands, here a two person
+//! chat.  Start one peer listening, then another that connects to it, and type lines
+//! to send them to every peer with whom a session is established:
+//!
+//! ```ignore
+//! cargo run --example chat -- 60200 alice
+//! cargo run --example chat -- 60201 bob 60200
+//! ```
+//!
+//! Lines starting with `/echo ` are instead sent as an `echo` command, which the peer returns as
+//! a line of chat.
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    path::NormalPath,
+};
+use oxedyne_fe2o3_crypto::enc::EncryptionScheme;
+use oxedyne_fe2o3_hash::{
+    csum::ChecksumScheme,
+    hash::HashScheme,
+};
+use oxedyne_fe2o3_jdat::prelude::*;
+use oxedyne_fe2o3_net::id;
+use oxedyne_fe2o3_o3db_sync::O3db;
+use oxedyne_fe2o3_shield::{
+    app::server::new_protocol,
+    srv::{
+        cfg::ServerConfig,
+        client::Client,
+        context::ServerContext,
+        handler::ShieldHandler,
+        msg::{
+            protocol::{
+                DefaultProtocolTypes,
+                ProtocolMode,
+            },
+            syntax as srv_syntax,
+        },
+    },
+};
+use oxedyne_fe2o3_syntax::{
+    SyntaxRef,
+    arg::{
+        Arg,
+        ArgConfig,
+    },
+    cmd::{
+        Cmd,
+        CmdConfig,
+    },
+    msg::{
+        Msg,
+        MsgCmd,
+    },
+};
+
+use std::{
+    env,
+    net::SocketAddr,
+    path::Path,
+    time::Duration,
+};
+
+use local_ip_address::local_ip;
+use tokio::io::{
+    AsyncBufReadExt,
+    BufReader,
+};
+
+
+type ChatClient = Client<
+    8,
+    {id::MID_LEN},
+    {id::SID_LEN},
+    {id::UID_LEN},
+    DefaultProtocolTypes<
+        {id::MID_LEN},
+        {id::SID_LEN},
+        {id::UID_LEN},
+        ChatHandler,
+    >,
+>;
+
+type NoDb = O3db<
+    { id::UID_LEN },
+    id::Uid,
+    EncryptionScheme,
+    HashScheme,
+    HashScheme,
+    ChecksumScheme,
+>;
+
+const CONNECT_WAIT: Duration = Duration::from_secs(30);
+
+/// Prints what peers say, and returns what they ask to be echoed.
+#[derive(Clone, Debug, Default)]
+struct ChatHandler;
+
+impl ShieldHandler for ChatHandler {
+
+    fn cmds(&self) -> Outcome<Vec<Cmd>> {
+        let arg_nick = Arg::from(ArgConfig {
+            name:   fmt!("nick"),
+            hyph1:  fmt!("n"),
+            vals:   vec![(Kind::Str, fmt!("Nickname"))],
+            help:   Some(fmt!("Name of the speaker")),
+            ..Default::default()
+        });
+        let arg_text = Arg::from(ArgConfig {
+            name:   fmt!("text"),
+            hyph1:  fmt!("t"),
+            vals:   vec![(Kind::Str, fmt!("Text"))],
+            help:   Some(fmt!("What was said")),
+            ..Default::default()
+        });
+        let mut say = Cmd::from(CmdConfig {
+            name:   fmt!("say"),
+            help:   Some(fmt!("A line of chat")),
+            ..Default::default()
+        });
+        say = res!(say.add_arg(arg_nick.required(true)));
+        say = res!(say.add_arg(arg_text.clone().required(true)));
+        let mut echo = Cmd::from(CmdConfig {
+            name:   fmt!("echo"),
+            help:   Some(fmt!("Text to be returned by the peer")),
+            ..Default::default()
+        });
+        echo = res!(echo.add_arg(arg_text.required(true)));
+        Ok(vec![say, echo])
+    }
+
+    fn handle(
+        &mut self,
+        mcmd:   MsgCmd,
+        peer:   &SocketAddr,
+        syntax: SyntaxRef,
+    )
+        -> Outcome<Option<Msg>>
+    {
+        match mcmd.name.as_str() {
+            "say" => {
+                let nick = res!(str_arg(&mcmd, "-n"));
+                let text = res!(str_arg(&mcmd, "-t"));
+                println!("<{}> {}", nick, text);
+                Ok(None)
+            },
+            "echo" => {
+                // Returned as a line of chat, rather than another echo to be returned.
+                let text = res!(str_arg(&mcmd, "-t"));
+                let msg = Msg::new(syntax);
+                let reply = res!(res!(res!(msg.new_cmd("say"))
+                    .add_arg_val("-n", Some(Dat::Str(fmt!("echo")))))
+                    .add_arg_val("-t", Some(Dat::Str(text))));
+                Ok(Some(res!(msg.add_cmd(reply))))
+            },
+            _ => Err(err!(
+                "Unrecognised chat command '{}' from {:?}.", mcmd.name, peer;
+                Input, Unknown)),
+        }
+    }
+}
+
+fn str_arg(mcmd: &MsgCmd, arg: &str) -> Outcome<String> {
+    match mcmd.get_arg_vals(arg) {
+        Some(vals) if !vals.is_empty() => Ok(try_extract_dat!(vals[0].clone(), Str)),
+        _ => Err(err!("Expected argument '{}' in command '{}'.", arg, mcmd.name; Input, Missing)),
+    }
+}
+
+fn main() -> Outcome<()> {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 {
+        println!("Usage: chat <local port> <nickname> [<peer port> [<peer ip address>]]");
+        return Ok(());
+    }
+    let port = res!(args[1].parse::<u16>(), Input, Invalid);
+    let nick = args[2].clone();
+    let peer_opt = match args.get(3) {
+        Some(peer_port) => {
+            let peer_port = res!(peer_port.parse::<u16>(), Input, Invalid);
+            let peer_ip = match args.get(4) {
+                Some(ip) => res!(ip.parse(), Input, Invalid),
+                None => res!(local_ip()),
+            };
+            Some(SocketAddr::new(peer_ip, peer_port))
+        },
+        None => None,
+    };
+    // The server blocks on its socket, so leave workers free for the client.
+    let rt = res!(tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(4)
+        .enable_all()
+        .build());
+    rt.block_on(chat(port, nick, peer_opt))
+}
+
+async fn chat(
+    port:       u16,
+    nick:       String,
+    peer_opt:   Option<SocketAddr>,
+)
+    -> Outcome<()>
+{
+    let cfg = ServerConfig {
+        server_port_udp: port,
+        ..Default::default()
+    };
+    let protocol = res!(res!(new_protocol(&cfg, ProtocolMode::Test)).with_handler(ChatHandler));
+    let syntax = protocol.app_syntax.clone();
+    let context = ServerContext::<_, _, _, _, _, EncryptionScheme, HashScheme, NoDb>::new(
+        cfg,
+        Path::new(".").normalise().absolute(),
+        None,
+        protocol,
+    );
+    let mut client: ChatClient = res!(Client::start(context, res!(srv_syntax::base_msg())));
+    println!("Listening on {:?} as {}.", client.addr, nick);
+    if let Some(peer) = peer_opt {
+        res!(client.connect(peer, CONNECT_WAIT).await);
+        println!("Connected to {:?}.", peer);
+    }
+
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    while let Some(line) = res!(lines.next_line().await) {
+        let msg = Msg::new(syntax.clone());
+        let mcmd = match line.strip_prefix("/echo ") {
+            Some(text) => res!(res!(msg.new_cmd("echo"))
+                .add_arg_val("-t", Some(Dat::Str(text.to_string())))),
+            None => res!(res!(res!(msg.new_cmd("say"))
+                .add_arg_val("-n", Some(Dat::Str(nick.clone()))))
+                .add_arg_val("-t", Some(Dat::Str(line)))),
+        };
+        let msg = res!(msg.add_cmd(mcmd));
+        let peers = {
+            let unlocked_sessions = lock_read!(client.sessions);
+            unlocked_sessions.iter()
+                .filter(|(_, session)| session.is_established())
+                .map(|(addr, _)| *addr)
+                .collect::<Vec<_>>()
+        };
+        if peers.is_empty() {
+            println!("No peers are connected yet.");
+        }
+        for peer in peers {
+            res!(client.send_msg(peer, msg.clone()));
+        }
+    }
+    client.finish().await
+}
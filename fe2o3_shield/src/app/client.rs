@@ -0,0 +1,187 @@
+use crate::{
+    app::{
+        cfg::AppConfig,
+        server::{
+            AppProtocol,
+            new_protocol,
+        },
+    },
+    srv::{
+        cfg::ServerConfig,
+        client::Client,
+        context::ServerContext,
+        msg::{
+            protocol::{
+                DefaultProtocolTypes,
+                ProtocolMode,
+            },
+            syntax as srv_syntax,
+        },
+    },
+};
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    path::NormalPath,
+};
+use oxedyne_fe2o3_crypto::enc::EncryptionScheme;
+use oxedyne_fe2o3_hash::{
+    csum::ChecksumScheme,
+    hash::HashScheme,
+};
+use oxedyne_fe2o3_jdat::{
+    prelude::*,
+    cfg::Config,
+};
+use oxedyne_fe2o3_net::id;
+use oxedyne_fe2o3_o3db_sync::O3db;
+use oxedyne_fe2o3_syntax::{
+    msg::MsgCmd,
+    opt::OptionRefVec,
+};
+use oxedyne_fe2o3_tui::lib_tui::repl::Evaluation;
+
+use std::{
+    net::{
+        IpAddr,
+        SocketAddr,
+    },
+    path::Path,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use local_ip_address::local_ip;
+
+
+pub type AppClient = Client<
+    8,
+    {id::MID_LEN},
+    {id::SID_LEN},
+    {id::UID_LEN},
+    DefaultProtocolTypes<
+        {id::MID_LEN},
+        {id::SID_LEN},
+        {id::UID_LEN},
+    >,
+>;
+
+/// The client holds no state worth keeping, so runs without a database.
+type NoDb = O3db<
+    { id::UID_LEN },
+    id::Uid,
+    EncryptionScheme,
+    HashScheme,
+    HashScheme,
+    ChecksumScheme,
+>;
+
+const CONNECT_WAIT: Duration = Duration::from_secs(30);
+const DEFAULT_REPLY_WAIT_SECS: u64 = 2;
+
+/// Connect to a Shield server, by default the one configured for this app on this machine, send
+/// any given messages and report any replies received while waiting.
+pub async fn run_client(
+    app_cfg:    &AppConfig,
+    cmd:        Option<&MsgCmd>,
+)
+    -> Outcome<Evaluation>
+{
+    let root_path = Path::new(&app_cfg.app_root)
+        .normalise() // Now a NormPathBuf.
+        .absolute();
+    let mut server_cfg = res!(ServerConfig::from_datmap(app_cfg.server_cfg.clone()));
+    res!(server_cfg.check_and_fix());
+
+    let mut ip_addr = res!(local_ip());
+    let mut port = server_cfg.server_port_udp;
+    let mut local_port = None;
+    let mut msgs = Vec::new();
+    let mut wait = DEFAULT_REPLY_WAIT_SECS;
+    if let Some(msg_cmd) = cmd {
+        if msg_cmd.has_arg("addr") {
+            let vals = res!(msg_cmd.get_arg_vals("addr").with_len(1));
+            let addr_str = try_extract_dat!(vals[0].clone(), Str);
+            ip_addr = res!(addr_str.parse::<IpAddr>(), Input, Invalid);
+        }
+        if msg_cmd.has_arg("port") {
+            let vals = res!(msg_cmd.get_arg_vals("port").with_len(1));
+            port = res!(u16_val(&vals[0]));
+        }
+        if msg_cmd.has_arg("local") {
+            let vals = res!(msg_cmd.get_arg_vals("local").with_len(1));
+            local_port = Some(res!(u16_val(&vals[0])));
+        }
+        if msg_cmd.has_arg("wait") {
+            let vals = res!(msg_cmd.get_arg_vals("wait").with_len(1));
+            wait = res!(u16_val(&vals[0])) as u64;
+        }
+        if let Some(vals) = msg_cmd.get_arg_vals("send") {
+            for val in vals {
+                msgs.push(try_extract_dat!(val.clone(), Str));
+            }
+        }
+    }
+    let server_addr = SocketAddr::new(ip_addr, port);
+    server_cfg.server_port_udp = match local_port {
+        Some(local_port) => local_port,
+        None => res!(port.checked_add(1).ok_or(err!(
+            "Specify a local port for the client, as server port {} is the last.", port;
+            Input, Invalid))),
+    };
+    server_cfg.trusted_seeds.clear(); // The client does not take part in discovery.
+
+    let protocol: AppProtocol = res!(new_protocol(&server_cfg, ProtocolMode::Production));
+    let context = ServerContext::<_, _, _, _, _, EncryptionScheme, HashScheme, NoDb>::new(
+        server_cfg,
+        root_path,
+        None,
+        protocol,
+    );
+    let mut client: AppClient = res!(Client::start(context, res!(srv_syntax::base_msg())));
+
+    let result = exchange(&client, server_addr, msgs, Duration::from_secs(wait)).await;
+    res!(client.finish().await);
+    let lines = res!(result);
+    Ok(Evaluation::Output(lines.join("\n")))
+}
+
+async fn exchange(
+    client:         &AppClient,
+    server_addr:    SocketAddr,
+    msgs:           Vec<String>,
+    wait:           Duration,
+)
+    -> Outcome<Vec<String>>
+{
+    let mut lines = Vec::new();
+    let start = Instant::now();
+    res!(client.connect(server_addr, CONNECT_WAIT).await);
+    lines.push(fmt!("Connected from {:?} to {:?} in {:?}.", client.addr, server_addr, start.elapsed()));
+    for msg in msgs {
+        res!(client.send(server_addr, msg.clone().into_bytes()));
+        lines.push(fmt!("Sent '{}'.", msg));
+    }
+    let start = Instant::now();
+    while start.elapsed() < wait {
+        match res!(client.recv(wait.saturating_sub(start.elapsed())).await) {
+            Some(delivery) => lines.push(fmt!(
+                "Received from {:?}: '{}'.", delivery.peer, String::from_utf8_lossy(&delivery.data),
+            )),
+            None => break,
+        }
+    }
+    Ok(lines)
+}
+
+fn u16_val(dat: &Dat) -> Outcome<u16> {
+    match dat {
+        Dat::U8(n)  => Ok(*n as u16),
+        Dat::U16(n) => Ok(*n),
+        _ => Err(err!(
+            "Expected a number from 0 to {}, found {:?}.", u16::MAX, dat;
+            Input, Invalid)),
+    }
+}
@@ -3,6 +3,8 @@ use oxedyne_fe2o3_o3db_sync::{
     comm::response::Wait,
 };
 
+use std::time::Duration;
+
 pub const VERSION:                          SemVer = SemVer::new(0, 1, 0);
 pub const DB_DIR:                           &'static str = "o3db";
 pub const DEFAULT_LOG_LEVEL:                &'static str = "info";
@@ -14,6 +16,7 @@ pub const WALLET_NAME:                      &'static str = "wallet.jdat";
 pub const NUM_PREV_PASSHASHES_TO_RETAIN:    usize = 10;
 // Timeouts.
 pub const GET_DATA_WAIT:                    Wait = Wait::new_default();
+pub const SERVER_REPLY_WAIT:                Duration = Duration::from_secs(5);
 // Key derivation functions.
 pub const KDF_HASH_LEN:                     u32 = 32;
 pub const KDF_SALT_LEN:                     usize = 16;
@@ -1,4 +1,5 @@
 pub mod cfg;
+pub mod client;
 pub mod constant;
 pub mod repl;
 pub mod server;
@@ -1,14 +1,24 @@
 use crate::{
     app::{
         cfg::AppConfig,
+        client,
         constant as app_const,
         server,
         tui::AppStatus,
     },
+    srv::{
+        cmd::Command,
+        guard::snapshot::GuardStatus,
+    },
 };
 
 use oxedyne_fe2o3_core::{
     prelude::*,
+    channels::{
+        Recv,
+        simplex,
+        Simplex,
+    },
     log::{
         bot::FileConfig,
         console::{
@@ -68,6 +78,11 @@ use std::{
         Path,
         PathBuf,
     },
+    sync::Arc,
+    time::{
+        Duration,
+        SystemTime,
+    },
 };
 
 use secrecy::{
@@ -92,7 +107,7 @@ pub struct AppShellContext {
                         ChecksumScheme,
                     >,
     pub wallet:     Wallet<{ app_const::NUM_PREV_PASSHASHES_TO_RETAIN }, Dat>,
-    //server: Server,
+    pub server:     Option<(Simplex<Command>, Arc<tokio::runtime::Runtime>)>,
 }
 
 impl ShellContext for AppShellContext {
@@ -209,8 +224,12 @@ impl AppShellContext {
                     info!("│ New server session.   │");
                     info!("└───────────────────────┘");
 
+                    if self.server.is_some() {
+                        evals.push(Evaluation::Error(fmt!("The server is already running.")));
+                        continue;
+                    }
                     let rt = res!(tokio::runtime::Runtime::new());
-                    let (eval, _cmd_chan) = res!(rt.block_on(
+                    let (eval, server_opt) = res!(rt.block_on(
                         server::start_server(
                             &self.app_cfg,
                             &self.stat,
@@ -219,8 +238,20 @@ impl AppShellContext {
                             None,
                         )
                     ));
-                    evals.push(eval);
+                    match server_opt {
+                        Some((cmd_chan, _handle)) => {
+                            // Keep the runtime, and with it the server, for the rest of the
+                            // session so that commands such as "guard" can reach it.
+                            self.server = Some((cmd_chan, Arc::new(rt)));
+                            evals.push(Evaluation::Output(fmt!("The server is running.")));
+                        },
+                        None => evals.push(eval),
+                    }
                 }
+                "client"    => {
+                    let rt = res!(tokio::runtime::Runtime::new());
+                    evals.push(res!(rt.block_on(client::run_client(&self.app_cfg, Some(cmd)))));
+                },
                 "shell"     => evals.push(res!(self.start_shell(&shell_cfg, Some(cmd)))),
                 // Filesystem
                 "cd"        => evals.push(res!(cmds::change_directory(cmd))),
@@ -228,6 +259,8 @@ impl AppShellContext {
                 "pwd"       => evals.push(res!(cmds::print_working_directory())),
                 // Wallet
                 "secrets"   => evals.push(res!(self.secrets(&shell_cfg, Some(cmd)))),
+                // Guard
+                "guard"     => evals.push(res!(self.guard(Some(cmd)))),
                 _ => (), // Not implemented yet.
             }
         }
@@ -348,4 +381,70 @@ impl AppShellContext {
         }
         Ok(Evaluation::None)
     }
+
+    /// Inspect and edit the guard state of the running server, which saves any change to its
+    /// database.
+    pub fn guard(
+        &mut self,
+        cmd:    Option<&MsgCmd>,
+    )
+        -> Outcome<Evaluation>
+    {
+        let msg_cmd = match cmd {
+            Some(msg_cmd) if msg_cmd.has_args() => msg_cmd,
+            _ => return Err(err!("Missing message command."; Invalid, Input, Missing)),
+        };
+        let (target, status) = if res!(msg_cmd.has_only_arg("list")) {
+            (None, None)
+        } else if res!(msg_cmd.has_only_arg("whitelist")) {
+            let vals = res!(msg_cmd.get_arg_vals("whitelist").with_len(1));
+            (Some(try_extract_dat!(vals[0].clone(), Str)), Some(GuardStatus::Whitelist))
+        } else if msg_cmd.has_arg("blacklist") {
+            let vals = res!(msg_cmd.get_arg_vals("blacklist").with_len(1));
+            let until = if msg_cmd.has_arg("secs") {
+                let vals = res!(msg_cmd.get_arg_vals("secs").with_len(1));
+                let secs = match vals[0] {
+                    Dat::U8(n)  => n as u64,
+                    Dat::U16(n) => n as u64,
+                    Dat::U32(n) => n as u64,
+                    Dat::U64(n) => n,
+                    _ => return Err(err!(
+                        "The blacklist duration must be a positive number of seconds, found {:?}.",
+                        vals[0];
+                        Input, Invalid)),
+                };
+                Some(SystemTime::now() + Duration::from_secs(secs))
+            } else {
+                None
+            };
+            (Some(try_extract_dat!(vals[0].clone(), Str)), Some(GuardStatus::Blacklist(until)))
+        } else if res!(msg_cmd.has_only_arg("unban")) {
+            let vals = res!(msg_cmd.get_arg_vals("unban").with_len(1));
+            (Some(try_extract_dat!(vals[0].clone(), Str)), Some(GuardStatus::Normal))
+        } else {
+            return Err(err!(
+                "Use one of list, whitelist, blacklist (with optional secs) or unban.";
+                Invalid, Input));
+        };
+
+        let cmd_chan = match &self.server {
+            Some((cmd_chan, _)) => cmd_chan,
+            None => return Ok(Evaluation::Error(fmt!(
+                "The server is not running, start it using the \"server\" command."))),
+        };
+        let change_opt = match (target, status) {
+            (Some(target), Some(status)) => Some((target, status)),
+            _ => None,
+        };
+        let reply = simplex();
+        res!(cmd_chan.send(Command::Guard(change_opt, reply.clone())));
+        let lines = match reply.recv_timeout(app_const::SERVER_REPLY_WAIT) {
+            Recv::Result(result) => res!(res!(result)),
+            Recv::Empty => return Err(err!(
+                "The server did not reply to the guard command within {:?}.",
+                app_const::SERVER_REPLY_WAIT;
+                Channel, Timeout)),
+        };
+        Ok(Evaluation::Output(lines.join("\n")))
+    }
 }
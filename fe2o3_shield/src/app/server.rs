@@ -9,6 +9,10 @@ use crate::{
         cmd::Command,
         constant as srv_const,
         context::ServerContext,
+        handler::{
+            ShieldHandler,
+            ShieldSinkHandler,
+        },
         msg::{
             protocol::{
                 DefaultProtocolTypes,
@@ -30,7 +34,10 @@ use oxedyne_fe2o3_core::{
     channels::Simplex,
     path::NormalPath,
 };
-use oxedyne_fe2o3_crypto::enc::EncryptionScheme;
+use oxedyne_fe2o3_crypto::{
+    enc::EncryptionScheme,
+    sign::SignatureScheme,
+};
 use oxedyne_fe2o3_hash::{
     csum::ChecksumScheme,
     hash::HashScheme,
@@ -58,6 +65,48 @@ use std::{
 use tokio;
 
 
+pub type AppProtocol<H = ShieldSinkHandler> = Protocol<
+    8,
+    {id::MID_LEN},
+    {id::SID_LEN},
+    {id::UID_LEN},
+    DefaultProtocolTypes<
+        {id::MID_LEN},
+        {id::SID_LEN},
+        {id::UID_LEN},
+        H,
+    >,
+>;
+
+/// The protocol used by the app server, and by the app client to talk to it.  Other applications
+/// can use it with their own application message handler.
+pub fn new_protocol<H: ShieldHandler + 'static>(
+    server_cfg: &ServerConfig,
+    mode:       ProtocolMode,
+)
+    -> Outcome<AppProtocol<H>>
+{
+    let chunk_cfg = ServerConfig::new_chunk_cfg(1_000, 200, false, true);
+    Protocol::new(
+        server_cfg,
+        WireSchemesInput {
+            // Keyed for each session once the handshake completes.
+            enc:    Alt::Specific(Some(EncryptionScheme::new_aes_256_gcm())),
+            csum:   Alt::Specific(None::<ChecksumScheme>),
+            powh:   Alt::Specific(ServerConfig::default_packet_pow_hash_scheme()),
+            // A fresh packet signing key pair, replaced by any saved by an earlier run.
+            sign:   Alt::Specific(Some(SignatureScheme::new_ed25519())),
+            hsenc:  Alt::Specific(None::<EncryptionScheme>),
+            chnk:   Some(chunk_cfg),
+        },
+        [0u8; 8],
+        id::Mid::default(),
+        id::Sid::default(),
+        id::Uid::default(),
+        mode,
+    )
+}
+
 pub async fn start_server(
     app_cfg:        &AppConfig,
     stat:           &AppStatus,
@@ -130,35 +179,10 @@ pub async fn start_server(
     // │ Start server.         │
     // └───────────────────────┘
     
-    let chunk_cfg = ServerConfig::new_chunk_cfg(1_000, 200, true, true);
-
-    let protocol: Protocol<
-        8,
-        {id::MID_LEN},
-        {id::SID_LEN},
-        {id::UID_LEN},
-        DefaultProtocolTypes<
-            {id::MID_LEN},
-            {id::SID_LEN},
-            {id::UID_LEN},
-        >,
-    > =
-        res!(Protocol::new(
-            &server_cfg,
-            WireSchemesInput {
-                enc:    Alt::Specific(None::<EncryptionScheme>),
-                csum:   Alt::Specific(None::<ChecksumScheme>),
-                powh:   Alt::Specific(ServerConfig::default_packet_pow_hash_scheme()),
-                sign:   Alt::Specific(ServerConfig::default_packet_signature_scheme()),
-                hsenc:  Alt::Specific(None::<EncryptionScheme>),
-                chnk:   Some(chunk_cfg),
-            },
-            [0u8; 8],
-            id::Mid::default(),
-            id::Sid::default(),
-            id::Uid::default(),
-            if test_stream.is_some() { ProtocolMode::Test } else { mode },
-        ));
+    let protocol: AppProtocol = res!(new_protocol(
+        &server_cfg,
+        if test_stream.is_some() { ProtocolMode::Test } else { mode },
+    ));
 
     let server_context = ServerContext::new(
         server_cfg,
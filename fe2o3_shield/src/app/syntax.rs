@@ -77,6 +77,65 @@ pub fn new_shell(
     });
     cmd = res!(cmd.add_arg(a1));
     s = res!(s.add_cmd(cmd));
+    // =============================================================================================
+
+    // ---------------------------------------------------------------------------------------------
+    // Command: client
+    // ---------------------------------------------------------------------------------------------
+    let mut cmd = Cmd::from(CmdConfig {
+        name:   fmt!("client"),
+        help:   Some(fmt!("Connect to a Shield server, by default the one configured on this \
+                    machine, send messages and show any replies")),
+        cat:    fmt!("Control"),
+        ..Default::default()
+    });
+    let a1 = Arg::from(ArgConfig {
+        name:   fmt!("addr"),
+        hyph1:  fmt!("a"),
+        vals:   vec![(Kind::Str, fmt!("Server IP address, quoted if IPv6"))],
+        reqd:   false,
+        help:   Some(fmt!("Server IP address, by default that of this machine.")),
+        ..Default::default()
+    });
+    let a2 = Arg::from(ArgConfig {
+        name:   fmt!("port"),
+        hyph1:  fmt!("p"),
+        vals:   vec![(Kind::Unknown, fmt!("Server UDP port"))],
+        reqd:   false,
+        help:   Some(fmt!("Server UDP port, by default the configured server port.")),
+        ..Default::default()
+    });
+    let a3 = Arg::from(ArgConfig {
+        name:   fmt!("local"),
+        hyph1:  fmt!("l"),
+        vals:   vec![(Kind::Unknown, fmt!("Client UDP port"))],
+        reqd:   false,
+        help:   Some(fmt!("Client UDP port, by default one more than the server port.")),
+        ..Default::default()
+    });
+    let a4 = Arg::from(ArgConfig {
+        name:   fmt!("send"),
+        hyph1:  fmt!("s"),
+        vals:   vec![(Kind::Str, fmt!("Message text"))],
+        reqd:   false,
+        help:   Some(fmt!("Send a message once connected, may be repeated.")),
+        ..Default::default()
+    });
+    let a5 = Arg::from(ArgConfig {
+        name:   fmt!("wait"),
+        hyph1:  fmt!("w"),
+        vals:   vec![(Kind::Unknown, fmt!("Seconds"))],
+        reqd:   false,
+        help:   Some(fmt!("How long to wait for replies, by default 2 seconds.")),
+        ..Default::default()
+    });
+    cmd = res!(cmd.add_arg(a1));
+    cmd = res!(cmd.add_arg(a2));
+    cmd = res!(cmd.add_arg(a3));
+    cmd = res!(cmd.add_arg(a4));
+    cmd = res!(cmd.add_arg(a5));
+    s = res!(s.add_cmd(cmd));
+    // =============================================================================================
     
     // ┌───────────────────────┐
     // │ WALLET                │
@@ -110,7 +169,69 @@ pub fn new_shell(
     cmd = res!(cmd.add_arg(a2));
     s = res!(s.add_cmd(cmd));
     // =============================================================================================
-    
+
+    // ┌───────────────────────┐
+    // │ GUARD                 │
+    // └───────────────────────┘
+    // ---------------------------------------------------------------------------------------------
+    // Command: guard
+    // ---------------------------------------------------------------------------------------------
+    let mut cmd = Cmd::from(CmdConfig {
+        name:   fmt!("guard"),
+        help:   Some(fmt!("Inspect and edit the address and user guard state of the running \
+                    server, which saves any change. Quote IPv6 addresses and user ids consisting \
+                    only of digits.")),
+        cat:    fmt!("Guard"),
+        ..Default::default()
+    });
+    let a1 = Arg::from(ArgConfig {
+        name:   fmt!("list"),
+        hyph1:  fmt!("l"),
+        vals:   vec![],
+        reqd:   false,
+        help:   Some(fmt!("List addresses and users with a non-normal status or history.")),
+        ..Default::default()
+    });
+    let a2 = Arg::from(ArgConfig {
+        name:   fmt!("whitelist"),
+        hyph1:  fmt!("w"),
+        vals:   vec![(Kind::Str, fmt!("IP address or hexadecimal user id"))],
+        reqd:   false,
+        help:   Some(fmt!("Exempt an address or user from throttling and blacklisting.")),
+        ..Default::default()
+    });
+    let a3 = Arg::from(ArgConfig {
+        name:   fmt!("blacklist"),
+        hyph1:  fmt!("b"),
+        vals:   vec![(Kind::Str, fmt!("IP address or hexadecimal user id"))],
+        reqd:   false,
+        help:   Some(fmt!("Drop all packets from an address or user.")),
+        ..Default::default()
+    });
+    let a4 = Arg::from(ArgConfig {
+        name:   fmt!("secs"),
+        hyph1:  fmt!("s"),
+        vals:   vec![(Kind::Unknown, fmt!("Blacklist duration in seconds"))],
+        reqd:   false,
+        help:   Some(fmt!("Limit the duration of a blacklisting, which is otherwise indefinite.")),
+        ..Default::default()
+    });
+    let a5 = Arg::from(ArgConfig {
+        name:   fmt!("unban"),
+        hyph1:  fmt!("u"),
+        vals:   vec![(Kind::Str, fmt!("IP address or hexadecimal user id"))],
+        reqd:   false,
+        help:   Some(fmt!("Return an address or user to normal, clearing its history.")),
+        ..Default::default()
+    });
+    cmd = res!(cmd.add_arg(a1));
+    cmd = res!(cmd.add_arg(a2));
+    cmd = res!(cmd.add_arg(a3));
+    cmd = res!(cmd.add_arg(a4));
+    cmd = res!(cmd.add_arg(a5));
+    s = res!(s.add_cmd(cmd));
+    // =============================================================================================
+
     // ┌───────────────────────┐
     // │ WORKSPACE             │
     // └───────────────────────┘
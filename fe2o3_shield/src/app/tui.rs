@@ -326,6 +326,7 @@ pub fn run() -> Outcome<()> {
         ws:         BTreeMap::new(),
         db:         res!(new_db(&db_root, &db_default_enc_key)),
         wallet,
+        server:     None,
     };
 
     let mut shell_cfg = ShellConfig::default();
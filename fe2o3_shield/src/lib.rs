@@ -22,12 +22,16 @@
 //! - **Message system**: Packet handling, assembly, and handshake protocols
 //! - **Guard system**: DoS protection with Monitor → Throttle → Blacklist state progression
 //! - **Cryptographic schemes**: Pluggable encryption, signing, and hashing implementations
-//! - **Proof-of-work engine**: Time-bounded PoW with linear difficulty scaling
+//! - **Proof-of-work engine**: Time-bounded PoW with linear, exponential or stepped difficulty scaling
 //! - **Configuration management**: Runtime context and parameter tuning
+//! - **Client**: Handshake with a server, then exchange session messages with it
+//! - **Application messages**: Commands of an application syntax, sent encrypted within a session
+//!   and passed to the `ShieldHandler` of the receiving peer (see `examples/chat.rs`)
 //!
 //! ### Application Layer (`app`)
 //! High-level interfaces and tools:
 //! - **Server wrapper**: Simplified server setup and management
+//! - **Client command**: Connect to a local or remote server and exchange messages
 //! - **REPL interface**: Interactive command processing
 //! - **TUI support**: Text user interface components
 //! - **Syntax parsing**: Command and configuration parsing
@@ -43,6 +47,12 @@
 //! 5. **HReq3**: Client session confirmation
 //! 6. **HResp3**: Server handshake completion
 //!
+//! ### Key Renewal
+//! - **Session rekeying**: the session initiator renews the session key hourly by default, via a
+//!   sealed `RekeyReq`/`RekeyResp` KEM exchange within the session
+//! - **Signing key rotation**: a new packet signing key is announced to peers in a `KeyRotate`
+//!   signed with both the old and new keys
+//!
 //! ### Packet Structure
 //! - **UDP buffer**: 1,400 bytes (avoiding IP fragmentation)
 //! - **Default packet**: 700 bytes (substantial headroom)
@@ -52,8 +62,8 @@
 //! ### DoS Protection
 //! Multi-layered defence with configurable thresholds:
 //! - **Rate limiting**: 30 requests/second baseline with throttling
-//! - **Proof-of-work**: 0-30 zero-bit difficulty scaling with request volume
-//! - **Address blacklisting**: 30 minutes to 3 days with randomised duration
+//! - **Proof-of-work**: zero-bit difficulty scaling with request volume, raised for misbehaving addresses
+//! - **Address blacklisting**: 30 minutes to 3 days with randomised duration, persisted across restarts
 //! - **Message assembly limits**: 128 total repetitions, 32 per packet
 //!
 //! ## Cryptographic Features
@@ -106,7 +116,7 @@
 //! Key parameters for tuning protocol behaviour:
 //! - **Network**: UDP buffer size, packet sizes, chunking thresholds
 //! - **Security**: PoW difficulty range, rate limiting thresholds
-//! - **Session**: Handshake timeouts, session expiry intervals
+//! - **Session**: Idle session expiry, including stalled handshakes, and session key renewal interval
 //! - **Guard system**: Throttling limits, blacklist durations
 //!
 //! ## Performance Characteristics
@@ -1,5 +1,16 @@
 use crate::{
-    srv::constant,
+    srv::{
+        constant,
+        discovery::DiscoveryParams,
+        pow::{
+            DifficultyParams,
+            DifficultyProfile,
+        },
+        msg::reliable::{
+            self,
+            ReliableParams,
+        },
+    },
     //packet::PacketValidator,
     //schemes::{
     //    WireSchemes,
@@ -41,11 +52,16 @@ use oxedyne_fe2o3_hash::{
 //};
 
 use std::{
-    collections::BTreeMap,
+    collections::{
+        BTreeMap,
+        BTreeSet,
+    },
     net::{
+        IpAddr,
         SocketAddr,
         ToSocketAddrs,
     },
+    time::Duration,
 };
 
 
@@ -60,11 +76,17 @@ pub struct ServerConfig {
     pub log_level:                      String,
     pub server_address:                 String,
     pub server_port_udp:                u16,
-    pub server_rps_zbits_profile:       u8, // 0 = linear, ..
+    pub server_rps_zbits_profile:       u8, // 0 = linear, 1 = exponential, 2 = stepped
     pub server_pow_zbits_min:           u16, // min zero bits for all packet pows
     pub server_pow_zbits_max:           u16, // when rps reaches max, the reqd pow zbit reaches this level
     pub server_pow_time_horiz_secs:     u64, // timestamp must be no older in seconds to now
     pub server_rps_max:                 u16, // the requests per second corresponding to maximum pow zbits
+    #[optional]
+    pub server_pow_zbits_steps:         u16, // number of increments in the stepped profile
+    #[optional]
+    pub server_pow_offences_max:        u16, // offences by an address at which its reqd pow zbits reaches max
+    #[optional]
+    pub server_pow_offence_decay_secs:  u64, // an offence is forgiven after this many seconds
     //pub packet_pow_hash_scheme:         String,
     //pub packet_signature_scheme:        String,
     pub addr_guard_map_bins:            u32, // Number of bins in shared map of incoming addresses.
@@ -75,6 +97,37 @@ pub struct ServerConfig {
     // defence is to set a relatively high difficulty for HReq1.
     pub server_accept_unknown_users:    bool,
     pub trusted_seeds:                  Vec<String>,
+    // Reliable delivery
+    #[optional]
+    pub reliable_msg_types:             Vec<String>, // Names of session messages to acknowledge and retransmit, e.g. "data".
+    #[optional]
+    pub reliable_rto_initial_ms:        u64, // Initial retransmission timeout, doubled on each retry.
+    #[optional]
+    pub reliable_rto_max_ms:            u64, // Ceiling for the retransmission timeout.
+    #[optional]
+    pub reliable_retries_max:           u8, // Retransmissions of a packet before the message is abandoned.
+    #[optional]
+    pub reliable_cwnd_initial:          u32, // Initial congestion window, in packets.
+    #[optional]
+    pub reliable_cwnd_max:              u32, // Maximum congestion window, in packets.
+    // Peer discovery
+    #[optional]
+    pub discovery_bucket_size:          u16, // Kademlia k, peers per routing table bucket and per answer.
+    #[optional]
+    pub discovery_alpha:                u16, // Peers queried in parallel during a lookup.
+    #[optional]
+    pub discovery_ping_timeout_ms:      u64, // Time allowed for a pong or peer list.
+    #[optional]
+    pub discovery_stale_secs:           u64, // Silence after which a peer is pinged.
+    #[optional]
+    pub discovery_refresh_secs:         u64, // Interval between lookups of our own identifier.
+    #[optional]
+    pub discovery_fails_max:            u8, // Missed pings before a peer is evicted.
+    // Sessions
+    #[optional]
+    pub session_rekey_secs:             u64, // Interval between session key renewals, 0 to disable.
+    #[optional]
+    pub session_idle_secs:              u64, // Silence after which a session is dropped, 0 to disable.
 }
 
 impl Config for ServerConfig {
@@ -82,6 +135,9 @@ impl Config for ServerConfig {
     fn check_and_fix(&mut self) -> Outcome<()> {
         // Checks that read only.
         res!(self.check_wire_chunk_config(&self.chunk_config()));
+        res!(self.reliable_params());
+        res!(self.discovery_params());
+        res!(self.difficulty_params());
         Ok(())
     }
 }
@@ -98,11 +154,14 @@ impl Default for ServerConfig {
             log_level:                      fmt!("debug"),
             server_address:                 fmt!("127.0.0.1"),
             server_port_udp:                60000, // numeric keypad mapping for "o3db"
-            server_rps_zbits_profile:       0, // 0 = linear, ..
+            server_rps_zbits_profile:       0, // 0 = linear, 1 = exponential, 2 = stepped
             server_pow_zbits_min:           2, // all packets must have a proof of work with at least this many zero bits
             server_pow_zbits_max:           15, // when rps reaches max, the reqd pow zbit reaches this level
             server_pow_time_horiz_secs:     600, // timestamp must be no older in seconds to now
             server_rps_max:                 30_000, // the requests per second corresponding to maximum pow zbits
+            server_pow_zbits_steps:         4,
+            server_pow_offences_max:        16,
+            server_pow_offence_decay_secs:  600, // 10 min
             //packet_pow_hash_scheme:         fmt!("Seahash"),
             //packet_signature_scheme:        fmt!("Ed25519"), // try SIKE
             addr_guard_map_bins:            128, // arbitrary
@@ -111,6 +170,23 @@ impl Default for ServerConfig {
             // Server policy.
             server_accept_unknown_users:    false,
             trusted_seeds:                  vec![],
+            // Reliable delivery.
+            reliable_msg_types:             vec![fmt!("data"), fmt!("app")],
+            reliable_rto_initial_ms:        200,
+            reliable_rto_max_ms:            5_000,
+            reliable_retries_max:           8,
+            reliable_cwnd_initial:          4,
+            reliable_cwnd_max:              256,
+            // Peer discovery.
+            discovery_bucket_size:          20,
+            discovery_alpha:                3,
+            discovery_ping_timeout_ms:      2_000,
+            discovery_stale_secs:           900, // 15 min
+            discovery_refresh_secs:         3_600, // 1 hour
+            discovery_fails_max:            2,
+            // Sessions.
+            session_rekey_secs:             3_600, // 1 hour
+            session_idle_secs:              7_200, // 2 hours, longer than the rekey interval
         }
     }
 }
@@ -265,6 +341,118 @@ impl ServerConfig {
         self.msg_assembler_map_bins
     }
 
+    /// Reliable delivery parameters, with the message types given by name.
+    pub fn reliable_params(&self) -> Outcome<ReliableParams> {
+        let mut msg_types = BTreeSet::new();
+        for name in &self.reliable_msg_types {
+            match reliable::msg_type_for_name(name) {
+                Some(typ) => { msg_types.insert(typ); },
+                None => return Err(err!(
+                    "ServerConfig: Message type '{}' in reliable_msg_types is not a session \
+                    message that can be delivered reliably.",
+                    name;
+                    Invalid, Input, Configuration)),
+            }
+        }
+        if self.reliable_cwnd_initial == 0
+            || self.reliable_cwnd_initial > self.reliable_cwnd_max
+        {
+            return Err(err!(
+                "ServerConfig: The initial congestion window of {} must be non-zero and no \
+                more than the maximum of {}.",
+                self.reliable_cwnd_initial, self.reliable_cwnd_max;
+                Invalid, Input, Configuration));
+        }
+        Ok(ReliableParams {
+            msg_types,
+            rto_init:       Duration::from_millis(self.reliable_rto_initial_ms),
+            rto_max:        Duration::from_millis(self.reliable_rto_max_ms),
+            retries_max:    self.reliable_retries_max,
+            cwnd_init:      self.reliable_cwnd_initial,
+            cwnd_max:       self.reliable_cwnd_max,
+            done_sunset:    constant::MSG_ASSEMBLY_SUNSET,
+        })
+    }
+
+    /// Peer discovery parameters.
+    pub fn discovery_params(&self) -> Outcome<DiscoveryParams> {
+        if self.discovery_bucket_size == 0 {
+            return Err(err!(
+                "ServerConfig: The discovery bucket size must be non-zero.";
+                Invalid, Input, Configuration));
+        }
+        if self.discovery_alpha == 0 || self.discovery_alpha > self.discovery_bucket_size {
+            return Err(err!(
+                "ServerConfig: The discovery alpha of {} must be non-zero and no more than \
+                the bucket size of {}.",
+                self.discovery_alpha, self.discovery_bucket_size;
+                Invalid, Input, Configuration));
+        }
+        if self.discovery_fails_max == 0 {
+            return Err(err!(
+                "ServerConfig: The number of missed discovery pings before eviction must be \
+                non-zero.";
+                Invalid, Input, Configuration));
+        }
+        Ok(DiscoveryParams {
+            k:              self.discovery_bucket_size as usize,
+            alpha:          self.discovery_alpha as usize,
+            ping_timeout:   Duration::from_millis(self.discovery_ping_timeout_ms),
+            stale_after:    Duration::from_secs(self.discovery_stale_secs),
+            refresh:        Duration::from_secs(self.discovery_refresh_secs),
+            fails_max:      self.discovery_fails_max,
+        })
+    }
+
+    /// The interval between renewals of the key of each session I initiate, if any.
+    pub fn session_rekey_interval(&self) -> Option<Duration> {
+        match self.session_rekey_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+
+    /// The time after which a session in which nothing has been heard from the peer is dropped,
+    /// if any.
+    pub fn session_idle_limit(&self) -> Option<Duration> {
+        match self.session_idle_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+
+    pub fn difficulty_params(&self) -> Outcome<DifficultyParams> {
+        let profile = res!(DifficultyProfile::try_from(self.server_rps_zbits_profile));
+        if self.server_pow_zbits_min == 0 {
+            return Err(err!(
+                "ServerConfig: The minimum proof of work zero bits must be non-zero.";
+                Invalid, Input, Configuration));
+        }
+        if self.server_pow_zbits_max < self.server_pow_zbits_min {
+            return Err(err!(
+                "ServerConfig: The maximum proof of work zero bits, {}, must be at least the \
+                minimum of {}.", self.server_pow_zbits_max, self.server_pow_zbits_min;
+                Invalid, Input, Configuration));
+        }
+        if self.server_rps_max == 0
+            || self.server_pow_zbits_steps == 0
+            || self.server_pow_offences_max == 0
+        {
+            return Err(err!(
+                "ServerConfig: The maximum requests per second, the number of proof of work \
+                steps and the maximum offences must be non-zero.";
+                Invalid, Input, Configuration));
+        }
+        Ok(DifficultyParams {
+            profile,
+            max:            self.server_pow_zbits_max,
+            min:            self.server_pow_zbits_min,
+            rps_max:        self.server_rps_max as u64,
+            steps:          self.server_pow_zbits_steps,
+            offences_max:   self.server_pow_offences_max,
+        })
+    }
+
     ///// Build the `PacketValidator`
     //pub fn packet_validator<
     //    // Proof of work validator.
@@ -321,6 +509,8 @@ impl ServerConfig {
     //    })
     //}
     
+    /// Resolve the trusted seeds.  A seed given as a bare host uses the configured
+    /// `server_port_udp`, while one given as `host:port` uses its own port.
     pub fn get_trusted_seeds(&self) -> Outcome<Vec<SocketAddr>> {
 
         if self.trusted_seeds.len() < constant::TRUSTED_SEEDS_MIN {
@@ -332,7 +522,15 @@ impl ServerConfig {
 
         let mut result = Vec::new();
         for seed in &self.trusted_seeds {
-            match fmt!("{}:{}", seed, self.server_port_udp).to_socket_addrs() {
+            let resolved = match (seed.parse::<SocketAddr>(), seed.parse::<IpAddr>()) {
+                (Ok(addr), _) => Ok(vec![addr].into_iter()),
+                (_, Ok(ip)) => Ok(vec![SocketAddr::new(ip, self.server_port_udp)].into_iter()),
+                _ => match seed.rsplit_once(':') {
+                    Some((_, port)) if port.parse::<u16>().is_ok() => seed.to_socket_addrs(),
+                    _ => fmt!("{}:{}", seed, self.server_port_udp).to_socket_addrs(),
+                },
+            };
+            match resolved {
                 Ok(mut addrs) => match addrs.next() {
                     Some(addr) => result.push(addr),
                     None => return Err(err!(
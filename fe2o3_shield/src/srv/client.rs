@@ -0,0 +1,196 @@
+//! A client for connecting to Shield servers.  Shield is peer to peer, so a client is a server
+//! that only speaks when spoken to by its owner.  The `Client` runs a `Server` as a tokio task and
+//! wraps its command channel, session map and data inbox so that a program can perform a
+//! handshake with a peer, then exchange session messages with it.  Application messages received
+//! are passed to the handler of the protocol, see `crate::srv::handler`.
+//!
+//! ```ignore
+//! let mut client = res!(Client::start(context, syntax));
+//! res!(client.connect(server_addr, Duration::from_secs(30)).await);
+//! res!(client.send(server_addr, b"hello".to_vec()));
+//! if let Some(delivery) = res!(client.recv(Duration::from_secs(5)).await) { ... }
+//! res!(client.finish().await);
+//! ```
+use crate::srv::{
+    cmd::Command,
+    context::ServerContext,
+    msg::{
+        core::IdTypes,
+        data::SessionDelivery,
+        protocol::{
+            Protocol,
+            ProtocolTypes,
+        },
+    },
+    server::Server,
+    session::SessionMap,
+};
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    channels::Simplex,
+};
+use oxedyne_fe2o3_iop_crypto::enc::Encrypter;
+use oxedyne_fe2o3_iop_db::api::Database;
+use oxedyne_fe2o3_iop_hash::api::Hasher;
+use oxedyne_fe2o3_syntax::{
+    SyntaxRef,
+    msg::Msg,
+};
+
+use std::{
+    net::SocketAddr,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use local_ip_address::local_ip;
+use tokio::task::JoinHandle;
+
+
+/// How often the client checks on the progress of a handshake or its inbox.
+pub const CLIENT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+pub struct Client<
+    const C: usize,
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    P: ProtocolTypes<ML, SL, UL>,
+> {
+    pub addr:       SocketAddr, // Where the client listens for replies.
+    pub protocol:   Protocol<C, ML, SL, UL, P>,
+    pub sessions:   SessionMap<C, ML, SL, UL, P::ID>,
+    cmd_chan:       Simplex<Command>,
+    handle:         Option<JoinHandle<Outcome<()>>>,
+}
+
+impl<
+    const C: usize,
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    P: ProtocolTypes<ML, SL, UL> + 'static,
+>
+    Client<C, ML, SL, UL, P>
+{
+    /// Start the underlying server on the port given in the context configuration.  Must be
+    /// called within a tokio runtime.
+    pub fn start<
+        ENC:    Encrypter + 'static,
+        KH:     Hasher + 'static,
+        DB:     Database<UL, <P::ID as IdTypes<ML, SL, UL>>::U, ENC, KH> + 'static,
+    >(
+        context:    ServerContext<C, ML, SL, UL, P, ENC, KH, DB>,
+        syntax:     SyntaxRef,
+    )
+        -> Outcome<Self>
+    {
+        let addr = SocketAddr::new(res!(local_ip()), context.cfg.server_port_udp);
+        let protocol = context.protocol.clone();
+        let sessions = context.sessions.clone();
+        let (mut server, cmd_chan) = Server::new(context, syntax);
+        let handle = tokio::spawn(async move { server.start().await });
+        Ok(Self {
+            addr,
+            protocol,
+            sessions,
+            cmd_chan,
+            handle: Some(handle),
+        })
+    }
+
+    /// Whether a session with the peer at the given address is ready for data.
+    pub fn is_established(&self, peer_addr: &SocketAddr) -> Outcome<bool> {
+        let unlocked_sessions = lock_read!(self.sessions);
+        Ok(match unlocked_sessions.get(peer_addr) {
+            Some(session) => session.is_established(),
+            None => false,
+        })
+    }
+
+    /// Perform a handshake with the peer at the given address, waiting up to the given duration
+    /// for the session to be established.
+    pub async fn connect(
+        &self,
+        peer_addr:  SocketAddr,
+        wait:       Duration,
+    )
+        -> Outcome<()>
+    {
+        res!(self.cmd_chan.send(Command::Connect(peer_addr)));
+        let start = Instant::now();
+        while start.elapsed() < wait {
+            if res!(self.is_established(&peer_addr)) {
+                return Ok(());
+            }
+            tokio::time::sleep(CLIENT_POLL_INTERVAL).await;
+        }
+        Err(err!(
+            "Handshake with {:?} did not complete within {:?}.", peer_addr, wait;
+            Network, Timeout))
+    }
+
+    /// Send data to a peer with whom a session has been established.
+    pub fn send(
+        &self,
+        peer_addr:  SocketAddr,
+        data:       Vec<u8>,
+    )
+        -> Outcome<()>
+    {
+        if !res!(self.is_established(&peer_addr)) {
+            return Err(err!(
+                "No session has been established with {:?}.", peer_addr;
+                Network, Missing));
+        }
+        res!(self.cmd_chan.send(Command::Send(peer_addr, data)));
+        Ok(())
+    }
+
+    /// Send an application message to a peer with whom a session has been established.  The
+    /// message must use the application syntax of the protocol.
+    pub fn send_msg(
+        &self,
+        peer_addr:  SocketAddr,
+        msg:        Msg,
+    )
+        -> Outcome<()>
+    {
+        if !res!(self.is_established(&peer_addr)) {
+            return Err(err!(
+                "No session has been established with {:?}.", peer_addr;
+                Network, Missing));
+        }
+        res!(self.cmd_chan.send(Command::SendMsg(peer_addr, msg)));
+        Ok(())
+    }
+
+    /// Wait up to the given duration for session data from any peer.
+    pub async fn recv(&self, wait: Duration) -> Outcome<Option<SessionDelivery>> {
+        let start = Instant::now();
+        loop {
+            if let Some(delivery) = res!(self.protocol.try_recv_data()) {
+                return Ok(Some(delivery));
+            }
+            if start.elapsed() >= wait {
+                return Ok(None);
+            }
+            tokio::time::sleep(CLIENT_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Stop the underlying server and wait for it to finish.
+    pub async fn finish(&mut self) -> Outcome<()> {
+        if let Some(handle) = self.handle.take() {
+            res!(self.cmd_chan.send(Command::Finish));
+            match handle.await {
+                Ok(result) => res!(result),
+                Err(e) => return Err(err!(e, "While awaiting server task completion."; Async)),
+            }
+        }
+        Ok(())
+    }
+}
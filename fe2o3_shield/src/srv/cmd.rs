@@ -1,7 +1,23 @@
-//use oxedyne_fe2o3_core::prelude::*;
+use crate::srv::guard::snapshot::GuardStatus;
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    channels::Simplex,
+};
+use oxedyne_fe2o3_syntax::msg::Msg;
+
+use std::net::SocketAddr;
 
 #[derive(Clone, Debug)]
 pub enum Command {
     DoSomething,
+    Connect(SocketAddr), // Initiate a session handshake with the given peer.
+    Send(SocketAddr, Vec<u8>), // Send data to a peer with whom a session is established.
+    SendMsg(SocketAddr, Msg), // Send an application message to a peer with whom a session is established.
+    Rekey(SocketAddr), // Renew the key of the session with the given peer.
+    RotateKey(Vec<u8>, Vec<u8>), // Adopt the given public and secret packet signing keys.
+    // Set the status of the given IP address or hexadecimal user id, if any, save the guard
+    // state, and reply with a description of it.
+    Guard(Option<(String, GuardStatus)>, Simplex<Outcome<Vec<String>>>),
     Finish,
 }
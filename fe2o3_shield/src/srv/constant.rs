@@ -44,7 +44,8 @@ pub const REQ_TIMER_LEN:                    usize = 100;
 pub const MAX_ALLOWED_AVG_REQ_PER_SEC:      u64 = 30;
 pub const POW_DIFFICULTY_PROFILE:           DifficultyProfile = DifficultyProfile::Linear;
 pub const POW_MAX_ZERO_BITS:                u16 = 30;
-pub const POW_MIN_ZERO_BITS:                u16 = 0; // TODO add bypass when zbits == 0
+pub const POW_MIN_ZERO_BITS:                u16 = 1; // ProofOfWork does not accept zero.
+pub const POW_INITIAL_ZERO_BITS:            u16 = 8; // Used until a peer states its requirement.
 pub const POW_NONCE_LEN:                    usize = 8;
 pub const POW_CODE_LEN:                     usize = 8;
 pub const POW_ADDR_LEN:                     usize = 16;
@@ -63,6 +64,7 @@ pub const ADDR_THROTTLE_SUNSET_SECS_MIN:    u64 = 1_800; // 30 min
 pub const ADDR_THROTTLE_SUNSET_SECS_MAX:    u64 = 259_200; // 3 days
 pub const THROTTLE_COUNT_BEFORE_BLACKLIST:  u16 = 10;
 pub const SESSION_REQUEST_EXPIRY:           Duration = Duration::from_secs(600); // 10 min
+pub const KEY_ROTATION_GRACE:               Duration = Duration::from_secs(120);
 pub const PARTIAL_MESSAGE_SUNSET:           Duration = Duration::from_secs(600); // 10 min
 pub const MSG_ASSEMBLY_SUNSET:              Duration = Duration::from_secs(600);
 pub const MSG_ASSEMBLY_IDLE_MAX:            Duration = Duration::from_secs(60);
@@ -86,6 +88,15 @@ pub const SERVER_BOT_ERROR_COUNT_WARNING:   usize = 10;
 
 pub const TRUSTED_SEEDS_MIN:                usize = 3;
 
+// Peer discovery.
+pub const DISCOVERY_DB_KEY:                 &'static str = "shield_routing_table";
+pub const DISCOVERY_PERSIST_INTERVAL:       Duration = Duration::from_secs(60);
+pub const GUARD_DB_KEY:                     &'static str = "shield_guard_state";
+pub const IDENTITY_DB_KEY:                  &'static str = "shield_identity";
+pub const GUARD_PERSIST_INTERVAL:           Duration = Duration::from_secs(60);
+pub const GUARD_EXPIRY_INTERVAL:            Duration = Duration::from_secs(10);
+pub const SESSION_EXPIRY_INTERVAL:          Duration = Duration::from_secs(1);
+
 // Schemes =====================================================================
 // Chunking.
 // Min chunk size rationale:
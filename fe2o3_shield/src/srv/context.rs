@@ -1,5 +1,11 @@
 use crate::srv::{
     cfg::ServerConfig,
+    constant,
+    guard::snapshot::{
+        GuardSnapshot,
+        GuardStatus,
+        GuardTarget,
+    },
     msg::{
         core::IdTypes,
         protocol::{
@@ -7,10 +13,12 @@ use crate::srv::{
             ProtocolTypes,
         },
     },
+    session::SessionMap,
 };
 
 use oxedyne_fe2o3_core::{
     prelude::*,
+    mem::Extract,
     path::NormPathBuf,
     rand::Rand,
 };
@@ -20,6 +28,11 @@ use oxedyne_fe2o3_hash::{
     hash::HashScheme,
 };
 use oxedyne_fe2o3_iop_crypto::enc::Encrypter;
+use oxedyne_fe2o3_jdat::{
+    prelude::*,
+    try_extract_tup2dat,
+    tup2dat,
+};
 use oxedyne_fe2o3_iop_db::api::Database;
 use oxedyne_fe2o3_iop_hash::api::Hasher;
 use oxedyne_fe2o3_net::id;
@@ -56,6 +69,7 @@ pub struct ServerContext<
     pub root:       NormPathBuf,
     pub db:         Option<(Arc<RwLock<DB>>, <P::ID as IdTypes<ML, SL, UL>>::U)>,
     pub protocol:   Protocol<C, ML, SL, UL, P>,
+    pub sessions:   SessionMap<C, ML, SL, UL, P::ID>,
     phantom3:       PhantomData<ENC>,
     phantom4:       PhantomData<KH>,
 }
@@ -86,11 +100,186 @@ impl<
             root,
             db: db.map(|(db, uid)| (Arc::new(RwLock::new(db)), uid)),
             protocol,
+            sessions:   Arc::new(RwLock::new(BTreeMap::new())),
             phantom3:   PhantomData,
             phantom4:   PhantomData,
         }
     }
 
+    /// Save my user id and packet signing key pair to the database, if there is one.  Returns
+    /// whether the identity was saved.
+    pub fn save_identity(&self) -> Outcome<bool> {
+        let (locked_db, uid) = match &self.db {
+            Some(db) => db,
+            None => return Ok(false),
+        };
+        let (own_uid, keys) = res!(self.protocol.identity());
+        let dat = tup2dat![
+            res!(own_uid.to_dat()),
+            Dat::Opt(Box::new(keys.map(|(sigpk, sigsk)| tup2dat![
+                Dat::BC64(sigpk),
+                Dat::BC64(sigsk),
+            ]))),
+        ];
+        let unlocked_db = lock_read!(locked_db);
+        res!(unlocked_db.insert(
+            dat!(constant::IDENTITY_DB_KEY),
+            dat,
+            uid.clone(),
+            None,
+        ));
+        Ok(true)
+    }
+
+    /// Take the user id and packet signing key pair saved in the database as my identity, so that
+    /// peers know me across restarts.  On first start, the fresh identity is saved instead.
+    /// Returns whether a saved identity was taken.
+    pub fn load_identity(&mut self) -> Outcome<bool> {
+        let locked_db = match &self.db {
+            Some((db, _)) => db,
+            None => return Ok(false),
+        };
+        let dat_opt = {
+            let unlocked_db = lock_read!(locked_db);
+            res!(unlocked_db.get(&dat!(constant::IDENTITY_DB_KEY), None))
+        };
+        match dat_opt {
+            Some((dat, _)) => {
+                let mut v = try_extract_tup2dat!(dat);
+                let uid = res!(<P::ID as IdTypes<ML, SL, UL>>::U::from_dat(v[0].extract()));
+                let keys = match *try_extract_dat!(v[1].extract(), Opt) {
+                    Some(dat) => {
+                        let mut v = try_extract_tup2dat!(dat);
+                        Some((
+                            try_extract_dat!(v[0].extract(), BC64),
+                            try_extract_dat!(v[1].extract(), BC64),
+                        ))
+                    },
+                    None => None,
+                };
+                res!(self.protocol.set_identity(
+                    uid,
+                    keys.as_ref().map(|(sigpk, sigsk)| (&sigpk[..], &sigsk[..])),
+                ));
+                Ok(true)
+            },
+            None => {
+                res!(self.save_identity());
+                Ok(false)
+            },
+        }
+    }
+
+    /// Save the peer discovery routing table to the database, if there is one.  Returns whether
+    /// the table was saved.
+    pub fn save_peers(&self) -> Outcome<bool> {
+        let (locked_db, uid) = match &self.db {
+            Some(db) => db,
+            None => return Ok(false),
+        };
+        // The flag is cleared with the snapshot, so that changes made while saving mark the
+        // table dirty again, and restored should the save fail.
+        let dat = {
+            let mut unlocked_discovery = lock_write!(self.protocol.discovery);
+            let dat = res!(unlocked_discovery.table.to_dat());
+            unlocked_discovery.dirty = false;
+            dat
+        };
+        let result = {
+            let unlocked_db = lock_read!(locked_db);
+            unlocked_db.insert(
+                dat!(constant::DISCOVERY_DB_KEY),
+                dat,
+                uid.clone(),
+                None,
+            )
+        };
+        if let Err(e) = result {
+            let mut unlocked_discovery = lock_write!(self.protocol.discovery);
+            unlocked_discovery.dirty = true;
+            return Err(e);
+        }
+        Ok(true)
+    }
+
+    /// Load the peer discovery routing table saved in the database, if any, returning the number
+    /// of peers loaded.
+    pub fn load_peers(&self) -> Outcome<usize> {
+        let locked_db = match &self.db {
+            Some((db, _)) => db,
+            None => return Ok(0),
+        };
+        let dat_opt = {
+            let unlocked_db = lock_read!(locked_db);
+            res!(unlocked_db.get(&dat!(constant::DISCOVERY_DB_KEY), None))
+        };
+        match dat_opt {
+            Some((dat, _)) => {
+                let mut unlocked_discovery = lock_write!(self.protocol.discovery);
+                unlocked_discovery.table.load(dat)
+            },
+            None => Ok(0),
+        }
+    }
+
+    /// The lasting state of the address and user guards.
+    pub fn guard_snapshot(&self) -> Outcome<GuardSnapshot> {
+        Ok(GuardSnapshot {
+            addrs: res!(self.protocol.agrd.snapshot()),
+            users: res!(self.protocol.ugrd.snapshot()),
+        })
+    }
+
+    /// Set the status of the address or user given by an operator, as either an IP address or the
+    /// hexadecimal representation of a user id.
+    pub fn set_guard_status(&self, target: &str, status: GuardStatus) -> Outcome<()> {
+        match res!(GuardTarget::parse(target)) {
+            GuardTarget::Addr(ip) => self.protocol.agrd.set_status(&ip, status),
+            GuardTarget::User(uid) => self.protocol.ugrd.set_status(&uid, status),
+        }
+    }
+
+    /// Save the lasting state of the guards to the database, if there is one.  Returns whether
+    /// the state was saved.
+    pub fn save_guards(&self) -> Outcome<bool> {
+        let (locked_db, uid) = match &self.db {
+            Some(db) => db,
+            None => return Ok(false),
+        };
+        let dat = res!(res!(self.guard_snapshot()).to_dat());
+        let unlocked_db = lock_read!(locked_db);
+        res!(unlocked_db.insert(
+            dat!(constant::GUARD_DB_KEY),
+            dat,
+            uid.clone(),
+            None,
+        ));
+        Ok(true)
+    }
+
+    /// Restore the state of the guards saved in the database, if any, returning the number of
+    /// addresses and users restored.
+    pub fn load_guards(&self) -> Outcome<(usize, usize)> {
+        let locked_db = match &self.db {
+            Some((db, _)) => db,
+            None => return Ok((0, 0)),
+        };
+        let dat_opt = {
+            let unlocked_db = lock_read!(locked_db);
+            res!(unlocked_db.get(&dat!(constant::GUARD_DB_KEY), None))
+        };
+        match dat_opt {
+            Some((dat, _)) => {
+                let snapshot = res!(GuardSnapshot::from_dat(dat));
+                Ok((
+                    res!(self.protocol.agrd.restore(snapshot.addrs)),
+                    res!(self.protocol.ugrd.restore(snapshot.users)),
+                ))
+            },
+            None => Ok((0, 0)),
+        }
+    }
+
     //pub fn clone_self(&self) -> Self {
     //    self.clone()
     //}
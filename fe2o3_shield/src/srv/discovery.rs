@@ -0,0 +1,593 @@
+//! Peer discovery using a Kademlia-style routing table.  Each peer is identified by the SHA3-256
+//! hash of its packet signing public key, and the distance between two peers is the XOR of their
+//! identifiers.  Bucket `i` of the table holds up to `k` peers whose distance from me has `i`
+//! leading zero bits, ordered from least to most recently seen.
+//!
+//! A server bootstraps by pinging its trusted seeds and asking them for the peers closest to its
+//! own identifier, then asks the closest peers it learns of in turn.  Peers only enter the table
+//! once they have been heard from directly in a signed packet, so an address learnt from a peer
+//! list must answer a ping before it is trusted.  When a bucket is full, the least recently seen
+//! peer is pinged and only replaced if it fails to answer.  Peers not heard from for a while are
+//! pinged, and evicted after too many unanswered pings.  Peer lists are signed by the responder
+//! over the nonce of the request, and only accepted in answer to a request of mine.
+//!
+//! ```ignore
+//!
+//!     PEER X                                                     SEED Y
+//!        |                                                          |
+//!        +>>>>>>>>>>>>>>>>>>>>>>>>>>> Ping >>>>>>>>>>>>>>>>>>>>>>>>>>|  Y adds X.
+//!        |<<<<<<<<<<<<<<<<<<<<<<<<<<< Pong <<<<<<<<<<<<<<<<<<<<<<<<<<+  X adds Y.
+//!        +>>>>>>>>>>>>>>>>>>>>>>> FindNode(X) >>>>>>>>>>>>>>>>>>>>>>>|
+//!        |<<<<<<<<<<<<<<<<<<<<<<<< Peers [Z] <<<<<<<<<<<<<<<<<<<<<<<<+  Signed by Y.
+//!        |                                                          |
+//!        |                                                        PEER Z
+//!        |                                                          |
+//!        +>>>>>>>>>>>>>>>>>>>>>>>>>>> Ping >>>>>>>>>>>>>>>>>>>>>>>>>>|  Z adds X.
+//!        |<<<<<<<<<<<<<<<<<<<<<<<<<<< Pong <<<<<<<<<<<<<<<<<<<<<<<<<<+  X adds Z.
+//!        |                                                          |
+//! ```
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    byte::B32,
+    mem::Extract,
+    rand::Rand,
+};
+use oxedyne_fe2o3_hash::hash::HashScheme;
+use oxedyne_fe2o3_iop_hash::api::Hasher;
+use oxedyne_fe2o3_jdat::{
+    prelude::*,
+    try_extract_tup2dat,
+    try_extract_tup3dat,
+    tup2dat,
+    tup3dat,
+};
+
+use std::{
+    collections::{
+        BTreeMap,
+        BTreeSet,
+    },
+    net::SocketAddr,
+    time::{
+        Duration,
+        Instant,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+
+pub const PEER_ID_LEN: usize = 32;
+pub const NONCE_LEN: usize = 16;
+pub const PEER_LIST_MAX: usize = 64; // Longest peer list accepted from a peer.
+
+pub type PeerId = [u8; PEER_ID_LEN];
+pub type Nonce = [u8; NONCE_LEN];
+
+/// The identifier of the peer with the given packet signing public key.
+pub fn peer_id(sigpk: &[u8]) -> Outcome<PeerId> {
+    let hash = HashScheme::new_sha3_256().hash(&[sigpk], []).as_vec();
+    Ok(res!(<PeerId>::try_from(&hash[..]), Decode, Bytes))
+}
+
+pub fn distance(a: &PeerId, b: &PeerId) -> PeerId {
+    let mut d = [0u8; PEER_ID_LEN];
+    for i in 0..PEER_ID_LEN {
+        d[i] = a[i] ^ b[i];
+    }
+    d
+}
+
+/// The bucket for the given peer, being the number of leading zero bits in its distance from me,
+/// or `None` if the identifiers are equal.
+pub fn bucket_index(own: &PeerId, id: &PeerId) -> Option<usize> {
+    let d = distance(own, id);
+    for (i, byt) in d.iter().enumerate() {
+        if *byt != 0 {
+            return Some(i * 8 + byt.leading_zeros() as usize);
+        }
+    }
+    None
+}
+
+/// Encode a list of peer identifiers and addresses for transmission.
+pub fn encode_peer_list(peers: &[(PeerId, SocketAddr)]) -> Outcome<Vec<u8>> {
+    let mut v = Vec::with_capacity(peers.len());
+    for (id, addr) in peers {
+        v.push(tup2dat![
+            Dat::B32(B32(*id)),
+            Dat::Str(addr.to_string()),
+        ]);
+    }
+    Dat::List(v).to_bytes(Vec::new())
+}
+
+pub fn decode_peer_list(byts: &[u8]) -> Outcome<Vec<(PeerId, SocketAddr)>> {
+    let (dat, _) = res!(Dat::from_bytes(byts));
+    let list = try_extract_dat!(dat, List);
+    if list.len() > PEER_LIST_MAX {
+        return Err(err!(
+            "Peer list of {} entries exceeds the limit of {}.", list.len(), PEER_LIST_MAX;
+            Input, TooBig));
+    }
+    let mut peers = Vec::with_capacity(list.len());
+    for item in list {
+        let mut v = try_extract_tup2dat!(item);
+        let id = try_extract_dat!(v[0].extract(), B32);
+        let addr_str = try_extract_dat!(v[1].extract(), Str);
+        let addr = res!(addr_str.parse::<SocketAddr>(), Decode, Input);
+        peers.push((*id, addr));
+    }
+    Ok(peers)
+}
+
+/// The bytes signed by a peer answering a `FindNode` request with the given nonce.
+pub fn peer_list_signed_bytes(nonce: &[u8], list: &[u8]) -> Vec<u8> {
+    let mut byts = Vec::with_capacity(5 + nonce.len() + list.len());
+    byts.extend_from_slice(b"peers");
+    byts.extend_from_slice(nonce);
+    byts.extend_from_slice(list);
+    byts
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PeerEntry {
+    pub id:         PeerId,
+    pub addr:       SocketAddr,
+    pub last_seen:  SystemTime,
+    pub fails:      u8, // Consecutive unanswered pings.
+}
+
+impl ToDat for PeerEntry {
+    fn to_dat(&self) -> Outcome<Dat> {
+        let secs = res!(self.last_seen.duration_since(UNIX_EPOCH)).as_secs();
+        Ok(tup3dat![
+            Dat::B32(B32(self.id)),
+            Dat::Str(self.addr.to_string()),
+            Dat::U64(secs),
+        ])
+    }
+}
+
+impl FromDat for PeerEntry {
+    fn from_dat(dat: Dat) -> Outcome<Self> {
+        let mut v = try_extract_tup3dat!(dat);
+        let id = try_extract_dat!(v[0].extract(), B32);
+        let addr_str = try_extract_dat!(v[1].extract(), Str);
+        let secs = try_extract_dat!(v[2].extract(), U64);
+        Ok(Self {
+            id:         *id,
+            addr:       res!(addr_str.parse::<SocketAddr>(), Decode, Input),
+            last_seen:  UNIX_EPOCH + Duration::from_secs(secs),
+            fails:      0,
+        })
+    }
+}
+
+/// The result of inserting a peer into the `RoutingTable`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Insertion {
+    Own, // The peer is me.
+    Added,
+    Updated,
+    Full(PeerEntry), // The bucket is full, this being its least recently seen peer.
+}
+
+#[derive(Clone, Debug)]
+pub struct RoutingTable {
+    own:        PeerId,
+    k:          usize,
+    buckets:    Vec<Vec<PeerEntry>>, // Least recently seen first.
+}
+
+impl RoutingTable {
+
+    pub fn new(own: PeerId, k: usize) -> Self {
+        Self {
+            own,
+            k,
+            buckets: vec![Vec::new(); PEER_ID_LEN * 8],
+        }
+    }
+
+    pub fn own(&self) -> &PeerId { &self.own }
+    pub fn k(&self) -> usize { self.k }
+
+    pub fn len(&self) -> usize {
+        self.buckets.iter().map(|b| b.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, id: &PeerId) -> Option<&PeerEntry> {
+        let i = bucket_index(&self.own, id)?;
+        self.buckets[i].iter().find(|e| e.id == *id)
+    }
+
+    pub fn peers(&self) -> Vec<PeerEntry> {
+        self.buckets.iter().flatten().cloned().collect()
+    }
+
+    /// Insert or refresh the given peer, moving it to the most recently seen end of its bucket.
+    pub fn insert(&mut self, entry: PeerEntry) -> Insertion {
+        let i = match bucket_index(&self.own, &entry.id) {
+            Some(i) => i,
+            None => return Insertion::Own,
+        };
+        let bucket = &mut self.buckets[i];
+        if let Some(pos) = bucket.iter().position(|e| e.id == entry.id) {
+            let mut existing = bucket.remove(pos);
+            existing.addr = entry.addr;
+            existing.last_seen = std::cmp::max(existing.last_seen, entry.last_seen);
+            existing.fails = entry.fails;
+            bucket.push(existing);
+            return Insertion::Updated;
+        }
+        if bucket.len() < self.k {
+            bucket.push(entry);
+            Insertion::Added
+        } else {
+            Insertion::Full(bucket[0].clone())
+        }
+    }
+
+    /// Record that the given peer has just been heard from at the given address.
+    pub fn seen(&mut self, id: PeerId, addr: SocketAddr) -> Insertion {
+        self.insert(PeerEntry {
+            id,
+            addr,
+            last_seen:  SystemTime::now(),
+            fails:      0,
+        })
+    }
+
+    pub fn remove(&mut self, id: &PeerId) -> Option<PeerEntry> {
+        let i = bucket_index(&self.own, id)?;
+        let pos = self.buckets[i].iter().position(|e| e.id == *id)?;
+        Some(self.buckets[i].remove(pos))
+    }
+
+    /// Record an unanswered ping, returning the number of consecutive failures.
+    pub fn missed(&mut self, id: &PeerId) -> Option<u8> {
+        let i = bucket_index(&self.own, id)?;
+        let entry = self.buckets[i].iter_mut().find(|e| e.id == *id)?;
+        entry.fails = entry.fails.saturating_add(1);
+        Some(entry.fails)
+    }
+
+    /// Up to `n` peers closest to the target, nearest first.
+    pub fn closest(&self, target: &PeerId, n: usize) -> Vec<PeerEntry> {
+        let mut peers = self.peers();
+        peers.sort_by_key(|e| distance(&e.id, target));
+        peers.truncate(n);
+        peers
+    }
+
+    /// Peers not heard from within the given age.
+    pub fn stale(&self, now: SystemTime, age: Duration) -> Vec<PeerEntry> {
+        self.buckets.iter().flatten()
+            .filter(|e| match now.duration_since(e.last_seen) {
+                Ok(d) => d >= age,
+                Err(_) => false,
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Insert peers previously saved using `to_dat`, returning the number added.  Peers that no
+    /// longer fit are skipped.
+    pub fn load(&mut self, dat: Dat) -> Outcome<usize> {
+        let list = try_extract_dat!(dat, List);
+        let mut count = 0;
+        for item in list {
+            if self.insert(res!(PeerEntry::from_dat(item))) == Insertion::Added {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+}
+
+impl ToDat for RoutingTable {
+    fn to_dat(&self) -> Outcome<Dat> {
+        let mut v = Vec::with_capacity(self.len());
+        for entry in self.buckets.iter().flatten() {
+            v.push(res!(entry.to_dat()));
+        }
+        Ok(Dat::List(v))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DiscoveryParams {
+    pub k:              usize, // Bucket capacity, and the length of peer lists.
+    pub alpha:          usize, // Peers asked at each step of a lookup.
+    pub ping_timeout:   Duration,
+    pub stale_after:    Duration, // Ping peers not heard from for this long.
+    pub refresh:        Duration, // Interval between lookups of my own identifier.
+    pub fails_max:      u8, // Evict a peer after this many consecutive unanswered pings.
+}
+
+/// A discovery message for the `Protocol` to send.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DiscoveryAction {
+    Ping {
+        addr:   SocketAddr,
+        nonce:  Nonce,
+    },
+    FindNode {
+        addr:   SocketAddr,
+        nonce:  Nonce,
+        target: PeerId,
+    },
+}
+
+#[derive(Clone, Debug)]
+struct PendingPing {
+    addr:           SocketAddr,
+    id_opt:         Option<PeerId>, // The table entry being checked, if any.
+    replacement:    Option<(PeerId, SocketAddr)>, // Awaiting a place in a full bucket.
+    sent:           Instant,
+}
+
+#[derive(Clone, Debug)]
+struct PendingFind {
+    addr:   SocketAddr,
+    target: PeerId,
+    sent:   Instant,
+}
+
+#[derive(Clone, Debug)]
+struct Lookup {
+    target:     PeerId,
+    queried:    BTreeSet<SocketAddr>,
+}
+
+/// The routing table together with the requests awaiting answers.
+#[derive(Clone, Debug)]
+pub struct PeerDiscovery {
+    pub params:     DiscoveryParams,
+    pub table:      RoutingTable,
+    pub seeds:      Vec<SocketAddr>,
+    pub dirty:      bool, // The table has changed since it was last saved.
+    pings:          BTreeMap<Nonce, PendingPing>,
+    finds:          BTreeMap<Nonce, PendingFind>,
+    lookup:         Option<Lookup>,
+    refreshed:      Option<Instant>,
+}
+
+impl PeerDiscovery {
+
+    pub fn new(
+        own:    PeerId,
+        params: DiscoveryParams,
+        seeds:  Vec<SocketAddr>,
+    )
+        -> Self
+    {
+        Self {
+            table:      RoutingTable::new(own, params.k),
+            params,
+            seeds,
+            dirty:      false,
+            pings:      BTreeMap::new(),
+            finds:      BTreeMap::new(),
+            lookup:     None,
+            refreshed:  None,
+        }
+    }
+
+    fn nonce() -> Nonce {
+        let mut nonce = [0u8; NONCE_LEN];
+        Rand::fill_u8(&mut nonce);
+        nonce
+    }
+
+    /// Adopt a new identifier, following a change of my packet signing key.  The table is rebuilt
+    /// around it, and my own identifier is looked up afresh at the next tick.
+    pub fn set_own(&mut self, own: PeerId) {
+        let peers = self.table.peers();
+        self.table = RoutingTable::new(own, self.params.k);
+        for entry in peers {
+            self.table.insert(entry);
+        }
+        self.lookup = None;
+        self.refreshed = None;
+        self.dirty = true;
+    }
+
+    /// The number of pings and peer list requests awaiting answers.
+    pub fn pending(&self) -> usize {
+        self.pings.len() + self.finds.len()
+    }
+
+    /// Ping the given address, unless a ping is already awaiting an answer from it.
+    pub fn ping(
+        &mut self,
+        addr:           SocketAddr,
+        id_opt:         Option<PeerId>,
+        replacement:    Option<(PeerId, SocketAddr)>,
+        now:            Instant,
+    )
+        -> Option<DiscoveryAction>
+    {
+        if let Some(pending) = self.pings.values_mut().find(|p| p.addr == addr) {
+            if pending.replacement.is_none() {
+                pending.replacement = replacement;
+            }
+            return None;
+        }
+        let nonce = Self::nonce();
+        self.pings.insert(nonce, PendingPing { addr, id_opt, replacement, sent: now });
+        Some(DiscoveryAction::Ping { addr, nonce })
+    }
+
+    /// Ask the peer at the given address for the peers it knows closest to the target.
+    pub fn find(&mut self, addr: SocketAddr, target: PeerId, now: Instant) -> DiscoveryAction {
+        let nonce = Self::nonce();
+        self.finds.insert(nonce, PendingFind { addr, target, sent: now });
+        DiscoveryAction::FindNode { addr, nonce, target }
+    }
+
+    /// Record a signed message from the given peer, returning a ping of the least recently seen
+    /// peer in its bucket if there is no room for it.
+    pub fn observe(&mut self, id: PeerId, addr: SocketAddr, now: Instant) -> Vec<DiscoveryAction> {
+        match self.table.seen(id, addr) {
+            Insertion::Own => Vec::new(),
+            Insertion::Added | Insertion::Updated => {
+                self.dirty = true;
+                Vec::new()
+            },
+            Insertion::Full(lru) => self.ping(lru.addr, Some(lru.id), Some((id, addr)), now)
+                .into_iter().collect(),
+        }
+    }
+
+    /// Record an answer to one of my pings.  Unsolicited answers are ignored.  If the peer was
+    /// pinged to make room for another, the other is forgotten.
+    pub fn pong(
+        &mut self,
+        nonce:  &Nonce,
+        id:     PeerId,
+        addr:   SocketAddr,
+        now:    Instant,
+    )
+        -> Vec<DiscoveryAction>
+    {
+        match self.pings.get(nonce) {
+            Some(pending) if pending.addr == addr => { self.pings.remove(nonce); },
+            _ => return Vec::new(),
+        }
+        self.observe(id, addr, now)
+    }
+
+    /// Whether the given nonce belongs to a request of mine for peers, sent to the given address.
+    pub fn awaiting_peers(&self, nonce: &Nonce, addr: &SocketAddr) -> bool {
+        match self.finds.get(nonce) {
+            Some(pending) => pending.addr == *addr,
+            None => false,
+        }
+    }
+
+    /// Accept a verified peer list answering one of my requests.  Unknown peers are pinged, and
+    /// if a lookup is under way, the peers closest to its target not yet asked are asked in turn.
+    pub fn found(
+        &mut self,
+        nonce:  &Nonce,
+        addr:   &SocketAddr,
+        peers:  Vec<(PeerId, SocketAddr)>,
+        now:    Instant,
+    )
+        -> Vec<DiscoveryAction>
+    {
+        if !self.awaiting_peers(nonce, addr) {
+            return Vec::new();
+        }
+        let target = match self.finds.remove(nonce) {
+            Some(pending) => pending.target,
+            None => return Vec::new(),
+        };
+        let own = *self.table.own();
+        let mut peers: Vec<(PeerId, SocketAddr)> = peers.into_iter()
+            .filter(|(id, _)| *id != own)
+            .collect();
+        let mut actions = Vec::new();
+        for (id, peer_addr) in &peers {
+            if self.table.get(id).is_none() {
+                if let Some(action) = self.ping(*peer_addr, None, None, now) {
+                    actions.push(action);
+                }
+            }
+        }
+        let query_max = self.params.k * self.params.alpha;
+        let mut next = Vec::new();
+        if let Some(lookup) = &mut self.lookup {
+            if lookup.target == target {
+                peers.sort_by_key(|(id, _)| distance(id, &target));
+                for (_, peer_addr) in peers {
+                    if next.len() >= self.params.alpha || lookup.queried.len() >= query_max {
+                        break;
+                    }
+                    if lookup.queried.insert(peer_addr) {
+                        next.push(peer_addr);
+                    }
+                }
+            }
+        }
+        for peer_addr in next {
+            actions.push(self.find(peer_addr, target, now));
+        }
+        actions
+    }
+
+    /// Evict peers that failed to answer pings, start a lookup of my own identifier when one is
+    /// due, and ping peers not heard from for a while.  The first lookup also asks the trusted
+    /// seeds.
+    pub fn tick(&mut self, now: Instant) -> Vec<DiscoveryAction> {
+        let mut actions = Vec::new();
+        // Unanswered pings.
+        let timeout = self.params.ping_timeout;
+        let expired: Vec<Nonce> = self.pings.iter()
+            .filter(|(_, p)| now.duration_since(p.sent) >= timeout)
+            .map(|(nonce, _)| *nonce)
+            .collect();
+        for nonce in expired {
+            let pending = match self.pings.remove(&nonce) {
+                Some(pending) => pending,
+                None => continue,
+            };
+            if let Some(id) = pending.id_opt {
+                let fails = self.table.missed(&id).unwrap_or(0);
+                let evict = pending.replacement.is_some() || fails >= self.params.fails_max;
+                if evict && self.table.remove(&id).is_some() {
+                    debug!(async_log::stream(), "Evicting peer {:02x?} at {:?} after {} \
+                        unanswered pings.", &id[..4], pending.addr, fails);
+                    self.dirty = true;
+                }
+            }
+            if let Some((id, addr)) = pending.replacement {
+                actions.append(&mut self.observe(id, addr, now));
+            }
+        }
+        self.finds.retain(|_, f| now.duration_since(f.sent) < timeout);
+        // Lookup of my own identifier, populating the buckets near me.
+        let due = match self.refreshed {
+            Some(when) => now.duration_since(when) >= self.params.refresh,
+            None => true,
+        };
+        if due {
+            let own = *self.table.own();
+            let mut targets: Vec<SocketAddr> = self.table.closest(&own, self.params.alpha)
+                .into_iter()
+                .map(|e| e.addr)
+                .collect();
+            if self.refreshed.is_none() || targets.is_empty() {
+                for seed in self.seeds.clone() {
+                    if let Some(action) = self.ping(seed, None, None, now) {
+                        actions.push(action);
+                    }
+                    if !targets.contains(&seed) {
+                        targets.push(seed);
+                    }
+                }
+            }
+            self.refreshed = Some(now);
+            self.lookup = Some(Lookup {
+                target:     own,
+                queried:    targets.iter().cloned().collect(),
+            });
+            for addr in targets {
+                actions.push(self.find(addr, own, now));
+            }
+        }
+        // Peers not heard from for a while.
+        for entry in self.table.stale(SystemTime::now(), self.params.stale_after) {
+            if let Some(action) = self.ping(entry.addr, Some(entry.id), None, now) {
+                actions.push(action);
+            }
+        }
+        actions
+    }
+}
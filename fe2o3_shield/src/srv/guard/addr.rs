@@ -1,6 +1,11 @@
 use crate::srv::{
+    guard::snapshot::{
+        AddressEntry,
+        GuardStatus,
+    },
     msg::{
         core::MsgType,
+        discovery::DiscoveryType,
         handshake::HandshakeType,
     },
 };
@@ -50,7 +55,7 @@ pub enum AddressState<
         start:      SystemTime, // Record start time of throttling.
         sunset:     Duration, // Turn off throttling after this duration.
     },
-    Blacklist, // No soup for you.
+    Blacklist(Option<SystemTime>), // No soup for you, until the given time if any.
     Whitelist, // Come on through.
 }
 
@@ -88,6 +93,24 @@ impl<
         }
     }
 
+    /// The lasting part of the state.  Throttling is transient.
+    pub fn status(&self) -> GuardStatus {
+        match self {
+            Self::Monitor(..) |
+            Self::Throttle{..} => GuardStatus::Normal,
+            Self::Blacklist(until) => GuardStatus::Blacklist(*until),
+            Self::Whitelist => GuardStatus::Whitelist,
+        }
+    }
+
+    pub fn from_status(status: GuardStatus) -> Self {
+        match status {
+            GuardStatus::Normal => Self::default(),
+            GuardStatus::Blacklist(until) => Self::Blacklist(until),
+            GuardStatus::Whitelist => Self::Whitelist,
+        }
+    }
+
     //pub fn update(&mut self) -> Option<Duration> {
     //    match self {
     //        Self::Monitor(reqs, _) |
@@ -106,8 +129,12 @@ pub struct AddressLog<
     // AddressData
     D: Clone + Debug + Default, // user supplied data container
 > {
+    pub ip:             Option<IpAddr>, // Recorded for administration, the map key being a hash.
     pub state:          AddressState<N, R>,
     pub throttle_cnt:   u16,
+    // Misbehaviour
+    pub offences:       u16,
+    pub offended:       Option<SystemTime>, // Time of the most recent offence.
     // Handshake
     pub pending:        Option<(HandshakeType, SystemTime)>,
     //pub msgs:           BTreeMap<MsgId, MsgState>,
@@ -154,6 +181,38 @@ pub struct AddressLog<
 //
 //}
 
+impl<
+    // AddressState
+    const N: usize, // length of ring buffer
+    const R: u64, // maximum rate per second
+    // AddressData
+    D: Clone + Debug + Default, // user supplied data container
+>
+    AddressLog<N, R, D>
+{
+    /// The number of offences recorded against the address, less one for each `decay` period
+    /// that has passed since the most recent.
+    pub fn offences(&self, decay: Duration) -> u16 {
+        match self.offended {
+            Some(when) => match when.elapsed() {
+                Ok(elapsed) if decay.as_millis() > 0 => {
+                    let forgiven = elapsed.as_millis() / decay.as_millis();
+                    self.offences.saturating_sub(std::cmp::min(forgiven, u16::MAX as u128) as u16)
+                },
+                _ => self.offences,
+            },
+            None => 0,
+        }
+    }
+
+    /// Record an offence, returning the resulting number of offences.
+    pub fn offend(&mut self, decay: Duration) -> u16 {
+        self.offences = self.offences(decay).saturating_add(1);
+        self.offended = Some(SystemTime::now());
+        self.offences
+    }
+}
+
 #[derive(Debug)]
 pub struct AddressGuard<
     // ShardMap
@@ -175,6 +234,8 @@ pub struct AddressGuard<
                               // less than this minimum.
     pub tsunset:    (u64, u64), // Range for randomisation of sunset durations.
     pub blist_cnt:  u16, // Blacklist after this many throttling episodes.
+    // Misbehaviour
+    pub offence_decay:  Duration, // Forgive one offence after this duration.
     // Handshake
     pub hreq_exp:   Duration, // Set expiry window for handshake messages.
 }
@@ -209,7 +270,10 @@ impl<
         -> Outcome<bool>
     {
         let htyp = HandshakeType::from(msg_typ);
-        if htyp == HandshakeType::Unknown {
+        // Discovery messages are sessionless, so they are rate limited like handshake requests
+        // but take no part in the handshake sequence.
+        let discovery = DiscoveryType::from(msg_typ) != DiscoveryType::Unknown;
+        if htyp == HandshakeType::Unknown && !discovery {
             return Ok(false);
         }
         //let ip_addr = src_addr.ip();
@@ -228,18 +292,19 @@ impl<
                                 // Downgrade treatment of address.
                                 if alog.throttle_cnt >= self.blist_cnt {
                                     // Blacklist after too many throttling episodes.
-                                    alog.state = AddressState::Blacklist;
+                                    alog.state = AddressState::Blacklist(Some(
+                                        SystemTime::now() + self.sunset()
+                                    ));
                                     alog.throttle_cnt = alog.throttle_cnt + 1;
                                     return Ok(true);
                                 } else {
                                     // Downgrade to throttled state.
                                     alog.state = AddressState::new_throttle(
                                         self.tint_min,
-                                        Duration::from_secs(rand::thread_rng().gen_range(
-                                            self.tsunset.0..self.tsunset.1
-                                        )),
+                                        self.sunset(),
                                     );
                                     alog.throttle_cnt = alog.throttle_cnt + 1;
+                                    alog.offend(self.offence_decay);
                                 }
                             }
                         },
@@ -254,9 +319,18 @@ impl<
                                 return Ok(true);
                             }
                         },
-                        AddressState::Blacklist => return Ok(true),
+                        AddressState::Blacklist(..) => {
+                            if alog.state.status().expired(SystemTime::now()) {
+                                alog.state = AddressState::default();
+                            } else {
+                                return Ok(true);
+                            }
+                        },
                         AddressState::Whitelist => (),
                     }
+                    if discovery {
+                        return Ok(false);
+                    }
                     // Impose sequence order on session requests.
                     match alog.pending {
                         Some((typ, when)) => {
@@ -266,7 +340,10 @@ impl<
                                 } else {
                                     match typ {
                                         HandshakeType::Req1 => { // Waiting for a HREQ2.
-                                            if !htyp.is_hreq2() {
+                                            if htyp == HandshakeType::Req1 {
+                                                // Remaining packets of a multi-packet HREQ1.
+                                            } else if !htyp.is_hreq2() {
+                                                alog.offend(self.offence_decay);
                                                 return Ok(true);
                                             } else {
                                                 alog.pending = Some((
@@ -276,7 +353,10 @@ impl<
                                             }
                                         },
                                         HandshakeType::Req2 => { // Waiting for a HREQ3.
-                                            if htyp != HandshakeType::Req3 {
+                                            if htyp == HandshakeType::Req2 {
+                                                // Remaining packets of a multi-packet HREQ2.
+                                            } else if htyp != HandshakeType::Req3 {
+                                                alog.offend(self.offence_decay);
                                                 return Ok(true);
                                             } else {
                                                 alog.pending = None;
@@ -295,7 +375,10 @@ impl<
                                     SystemTime::now(),
                                 )),
                                 HandshakeType::Req2 |
-                                HandshakeType::Req3 => return Ok(true), // Must be preceded by a HREQ1.
+                                HandshakeType::Req3 => { // Must be preceded by a HREQ1.
+                                    alog.offend(self.offence_decay);
+                                    return Ok(true);
+                                },
                                 _ => (),
                             }
                         },
@@ -306,12 +389,20 @@ impl<
         } // Release write lock on scr_addr shard.
 
         if new {
-            // If we have no record of the address, the only acceptable request is a
-            // HREQ1.
+            // If we have no record of the address, the only acceptable requests are a
+            // HREQ1 or a discovery message.
+            if discovery {
+                res!(self.amap.insert_using_hash(key, AddressLog {
+                    ip: Some(src_addr.ip()),
+                    ..Default::default()
+                }));
+                return Ok(false);
+            }
             if htyp != HandshakeType::Req1 {
                 return Ok(true);
             }
             let alog = AddressLog {
+                ip: Some(src_addr.ip()),
                 pending: Some((
                     HandshakeType::Req1,
                     SystemTime::now(),
@@ -324,14 +415,215 @@ impl<
         Ok(false)
     }
 
+    /// Create or update the log for an address we are about to contact, so that its responses
+    /// are not treated as coming from an unknown address.
+    pub fn register(
+        &self,
+        addr: &SocketAddr,
+        data: D,
+    )
+        -> Outcome<()>
+    {
+        let (key, locked_map) = res!(self.get_locked_map(addr));
+        {
+            let mut unlocked_map = lock_write!(locked_map);
+            if let Some(alog) = unlocked_map.get_mut(&key) {
+                alog.data = data;
+                return Ok(());
+            }
+        } // Release write lock on addr shard.
+        let alog = AddressLog {
+            ip: Some(addr.ip()),
+            data,
+            ..Default::default()
+        };
+        res!(self.amap.insert_using_hash(key, alog));
+        Ok(())
+    }
+
+    /// Create a log for an address we are about to contact, unless one already exists.  Returns
+    /// whether a log was created.
+    pub fn register_new(
+        &self,
+        addr: &SocketAddr,
+        data: D,
+    )
+        -> Outcome<bool>
+    {
+        let (key, locked_map) = res!(self.get_locked_map(addr));
+        {
+            let unlocked_map = lock_read!(locked_map);
+            if unlocked_map.get(&key).is_some() {
+                return Ok(false);
+            }
+        } // Release read lock on addr shard.
+        let alog = AddressLog {
+            ip: Some(addr.ip()),
+            data,
+            ..Default::default()
+        };
+        res!(self.amap.insert_using_hash(key, alog));
+        Ok(true)
+    }
+
+    /// Record an offence by the given address, such as a packet with a valid proof of work but
+    /// an invalid signature, returning the resulting number of offences.  Offences raise the
+    /// proof of work difficulty required of the address, see
+    /// [`crate::srv::pow::DifficultyParams::required_addr_zbits`].
+    pub fn offend(
+        &self,
+        addr: &SocketAddr,
+    )
+        -> Outcome<u16>
+    {
+        let (key, locked_map) = res!(self.get_locked_map(addr));
+        {
+            let mut unlocked_map = lock_write!(locked_map);
+            if let Some(alog) = unlocked_map.get_mut(&key) {
+                return Ok(alog.offend(self.offence_decay));
+            }
+        } // Release write lock on addr shard.
+        let mut alog = AddressLog {
+            ip: Some(addr.ip()),
+            ..Default::default()
+        };
+        let offences = alog.offend(self.offence_decay);
+        res!(self.amap.insert_using_hash(key, alog));
+        Ok(offences)
+    }
+
+    /// The number of recent offences by the given address.
+    pub fn offences(
+        &self,
+        addr: &SocketAddr,
+    )
+        -> Outcome<u16>
+    {
+        let (key, locked_map) = res!(self.get_locked_map(addr));
+        let unlocked_map = lock_read!(locked_map);
+        Ok(match unlocked_map.get(&key) {
+            Some(alog) => alog.offences(self.offence_decay),
+            None => 0,
+        })
+    }
+
+    /// A random duration within the sunset range, for throttling and blacklisting.
+    fn sunset(&self) -> Duration {
+        Duration::from_secs(rand::thread_rng().gen_range(self.tsunset.0..self.tsunset.1))
+    }
+
+    /// Return addresses whose blacklisting has expired to monitoring, returning the number of
+    /// addresses released.
+    pub fn expire(&self) -> Outcome<usize> {
+        let now = SystemTime::now();
+        let mut count = 0;
+        for locked_map in self.amap.shards.iter().flatten() {
+            let mut unlocked_map = lock_write!(locked_map);
+            for (_, alog) in unlocked_map.iter_mut() {
+                if alog.state.status().expired(now) {
+                    alog.state = AddressState::default();
+                    count += 1;
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    /// Set the lasting state of the given address, creating a log for it if necessary.
+    /// Returning an address to normal also clears its history.
+    pub fn set_status(
+        &self,
+        ip:     &IpAddr,
+        status: GuardStatus,
+    )
+        -> Outcome<()>
+    {
+        let (key, locked_map) = res!(self.get_locked_map_using_ip(ip));
+        {
+            let mut unlocked_map = lock_write!(locked_map);
+            if let Some(alog) = unlocked_map.get_mut(&key) {
+                alog.state = AddressState::from_status(status);
+                if status == GuardStatus::Normal {
+                    alog.throttle_cnt = 0;
+                    alog.offences = 0;
+                }
+                return Ok(());
+            }
+        } // Release write lock on addr shard.
+        let alog = AddressLog {
+            ip:     Some(*ip),
+            state:  AddressState::from_status(status),
+            ..Default::default()
+        };
+        res!(self.amap.insert_using_hash(key, alog));
+        Ok(())
+    }
+
+    /// The lasting state of all addresses that are not in good standing, or have a history of
+    /// misbehaviour.
+    pub fn snapshot(&self) -> Outcome<Vec<AddressEntry>> {
+        let mut result = Vec::new();
+        for locked_map in self.amap.shards.iter().flatten() {
+            let unlocked_map = lock_read!(locked_map);
+            for (_, alog) in unlocked_map.iter() {
+                let ip = match alog.ip {
+                    Some(ip) => ip,
+                    None => continue,
+                };
+                let entry = AddressEntry {
+                    ip,
+                    status:         alog.state.status(),
+                    throttle_cnt:   alog.throttle_cnt,
+                    offences:       alog.offences(self.offence_decay),
+                };
+                if entry.status != GuardStatus::Normal
+                    || entry.throttle_cnt > 0
+                    || entry.offences > 0
+                {
+                    result.push(entry);
+                }
+            }
+        }
+        result.sort_by_key(|a| a.ip);
+        Ok(result)
+    }
+
+    /// Restore the lasting state of the given addresses, returning the number restored.  Recent
+    /// offences decay from the time of restoration.
+    pub fn restore(&self, entries: Vec<AddressEntry>) -> Outcome<usize> {
+        let count = entries.len();
+        for entry in entries {
+            let (key, locked_map) = res!(self.get_locked_map_using_ip(&entry.ip));
+            let mut unlocked_map = lock_write!(locked_map);
+            let alog = AddressLog {
+                ip:             Some(entry.ip),
+                state:          AddressState::from_status(entry.status),
+                throttle_cnt:   entry.throttle_cnt,
+                offences:       entry.offences,
+                offended:       if entry.offences > 0 { Some(SystemTime::now()) } else { None },
+                ..Default::default()
+            };
+            unlocked_map.insert(key, alog);
+        }
+        Ok(count)
+    }
+
     pub fn get_locked_map(
         &self,
         addr: &SocketAddr,
     )
         -> Outcome<(HashForm, &RwLock<M>)>
     {
-        let ip_addr = addr.ip();
-        let key = self.amap.key(&Self::ip_addr_to_bytes(&ip_addr));
+        self.get_locked_map_using_ip(&addr.ip())
+    }
+
+    pub fn get_locked_map_using_ip(
+        &self,
+        ip_addr: &IpAddr,
+    )
+        -> Outcome<(HashForm, &RwLock<M>)>
+    {
+        let key = self.amap.key(&Self::ip_addr_to_bytes(ip_addr));
         let locked_map = res!(self.amap.get_shard_using_hash(&key));
         Ok((key, locked_map))
     }
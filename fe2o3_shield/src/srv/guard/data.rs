@@ -24,6 +24,7 @@ use std::{
         SocketAddr,
     },
     str::FromStr,
+    time::Instant,
 };
 
 /// Just a namespace for some functions to interchange between a string and a [`std::net::SocketAddress`].
@@ -80,6 +81,7 @@ pub struct UserData<
 > {
     pub sigtpk_opt:         Option<PublicKey>, // My record of your current public signing key.
     pub sigtpk_opt_old:     Option<PublicKey>, // My record of your old public signing key.
+    pub sigtpk_old_until:   Option<Instant>, // Until when I accept packets signed with the old key.
     pub waiting_for_sigpk:  bool,
     pub sessions:           BTreeMap<IdDat<SIDL, SID>, SecretKey>,
     pub code:               Option<[u8; C]>,
@@ -1,3 +1,4 @@
 pub mod addr;
 pub mod data;
+pub mod snapshot;
 pub mod user;
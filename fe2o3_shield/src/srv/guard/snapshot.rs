@@ -0,0 +1,287 @@
+//! The lasting part of the state of the `AddressGuard` and `UserGuard`, which is saved to the
+//! server database so that it survives a restart, and which an operator can inspect and edit.
+//! Request timing and throttling are transient and are not captured, but the number of throttling
+//! episodes and recent offences of an address are, so that a restart does not wipe the slate
+//! clean.
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    mem::Extract,
+};
+use oxedyne_fe2o3_jdat::{
+    prelude::*,
+    try_extract_tup2dat,
+    try_extract_tup3dat,
+    try_extract_tup5dat,
+    tup2dat,
+    tup3dat,
+    tup5dat,
+};
+
+use std::{
+    net::IpAddr,
+    time::{
+        Duration,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+
+/// The treatment of an address or user set by the guards or an operator.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GuardStatus {
+    Normal,
+    Blacklist(Option<SystemTime>), // Until the given time, or indefinitely.
+    Whitelist,
+}
+
+impl GuardStatus {
+    /// Whether this is a blacklisting that has expired by the given time.
+    pub fn expired(&self, now: SystemTime) -> bool {
+        match self {
+            Self::Blacklist(Some(until)) => *until <= now,
+            _ => false,
+        }
+    }
+
+    fn to_dats(self) -> Outcome<(Dat, Dat)> {
+        Ok(match self {
+            Self::Normal => (Dat::U8(0), Dat::Opt(Box::new(None))),
+            Self::Blacklist(until_opt) => (Dat::U8(1), Dat::Opt(Box::new(match until_opt {
+                Some(until) => Some(Dat::U64(res!(until.duration_since(UNIX_EPOCH)).as_secs())),
+                None => None,
+            }))),
+            Self::Whitelist => (Dat::U8(2), Dat::Opt(Box::new(None))),
+        })
+    }
+
+    fn from_dats(code: Dat, until: Dat) -> Outcome<Self> {
+        let code = try_extract_dat!(code, U8);
+        Ok(match code {
+            0 => Self::Normal,
+            1 => Self::Blacklist(match *try_extract_dat!(until, Opt) {
+                Some(dat) => Some(UNIX_EPOCH + Duration::from_secs(try_extract_dat!(dat, U64))),
+                None => None,
+            }),
+            2 => Self::Whitelist,
+            _ => return Err(err!(
+                "Guard status code {} not recognised.", code;
+                Decode, Invalid, Input)),
+        })
+    }
+}
+
+impl std::fmt::Display for GuardStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Normal => write!(f, "normal"),
+            Self::Blacklist(Some(until)) => match until.duration_since(SystemTime::now()) {
+                Ok(remaining) => write!(f, "blacklisted for {}s", remaining.as_secs()),
+                Err(_) => write!(f, "blacklisting expired"),
+            },
+            Self::Blacklist(None) => write!(f, "blacklisted"),
+            Self::Whitelist => write!(f, "whitelisted"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AddressEntry {
+    pub ip:             IpAddr,
+    pub status:         GuardStatus,
+    pub throttle_cnt:   u16,
+    pub offences:       u16,
+}
+
+impl ToDat for AddressEntry {
+    fn to_dat(&self) -> Outcome<Dat> {
+        let (code, until) = res!(self.status.to_dats());
+        Ok(tup5dat![
+            Dat::Str(self.ip.to_string()),
+            code,
+            until,
+            Dat::U16(self.throttle_cnt),
+            Dat::U16(self.offences),
+        ])
+    }
+}
+
+impl FromDat for AddressEntry {
+    fn from_dat(dat: Dat) -> Outcome<Self> {
+        let mut v = try_extract_tup5dat!(dat);
+        let ip_str = try_extract_dat!(v[0].extract(), Str);
+        Ok(Self {
+            ip:             res!(ip_str.parse::<IpAddr>(), Decode, Input),
+            status:         res!(GuardStatus::from_dats(v[1].extract(), v[2].extract())),
+            throttle_cnt:   try_extract_dat!(v[3].extract(), U16),
+            offences:       try_extract_dat!(v[4].extract(), U16),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UserEntry {
+    pub uid:    Vec<u8>, // Big endian bytes of the user id.
+    pub status: GuardStatus,
+}
+
+impl ToDat for UserEntry {
+    fn to_dat(&self) -> Outcome<Dat> {
+        let (code, until) = res!(self.status.to_dats());
+        Ok(tup3dat![
+            Dat::BU8(self.uid.clone()),
+            code,
+            until,
+        ])
+    }
+}
+
+impl FromDat for UserEntry {
+    fn from_dat(dat: Dat) -> Outcome<Self> {
+        let mut v = try_extract_tup3dat!(dat);
+        Ok(Self {
+            uid:    try_extract_dat!(v[0].extract(), BU8),
+            status: res!(GuardStatus::from_dats(v[1].extract(), v[2].extract())),
+        })
+    }
+}
+
+/// An address or user named by an operator.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum GuardTarget {
+    Addr(IpAddr),
+    User(Vec<u8>), // Big endian bytes of the user id.
+}
+
+impl GuardTarget {
+    /// Parse either an IP address or the hexadecimal representation of a user id.
+    pub fn parse(target: &str) -> Outcome<Self> {
+        match target.parse::<IpAddr>() {
+            Ok(ip) => Ok(Self::Addr(ip)),
+            Err(_) => {
+                let uid = res!(from_hex(target));
+                if uid.is_empty() {
+                    return Err(err!(
+                        "An IP address or hexadecimal user id is required.";
+                        Input, Missing));
+                }
+                Ok(Self::User(uid))
+            },
+        }
+    }
+}
+
+/// The lasting state of both guards.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct GuardSnapshot {
+    pub addrs: Vec<AddressEntry>,
+    pub users: Vec<UserEntry>,
+}
+
+impl ToDat for GuardSnapshot {
+    fn to_dat(&self) -> Outcome<Dat> {
+        let mut addrs = Vec::with_capacity(self.addrs.len());
+        for entry in &self.addrs {
+            addrs.push(res!(entry.to_dat()));
+        }
+        let mut users = Vec::with_capacity(self.users.len());
+        for entry in &self.users {
+            users.push(res!(entry.to_dat()));
+        }
+        Ok(tup2dat![
+            Dat::List(addrs),
+            Dat::List(users),
+        ])
+    }
+}
+
+impl FromDat for GuardSnapshot {
+    fn from_dat(dat: Dat) -> Outcome<Self> {
+        let mut v = try_extract_tup2dat!(dat);
+        let mut result = Self::default();
+        for item in try_extract_dat!(v[0].extract(), List) {
+            result.addrs.push(res!(AddressEntry::from_dat(item)));
+        }
+        for item in try_extract_dat!(v[1].extract(), List) {
+            result.users.push(res!(UserEntry::from_dat(item)));
+        }
+        Ok(result)
+    }
+}
+
+impl GuardSnapshot {
+
+    /// Set the status of the given address, adding it if necessary.  Returning an address to
+    /// normal also clears its history.
+    pub fn set_addr(&mut self, ip: IpAddr, status: GuardStatus) {
+        match self.addrs.iter_mut().find(|e| e.ip == ip) {
+            Some(entry) => {
+                entry.status = status;
+                if status == GuardStatus::Normal {
+                    entry.throttle_cnt = 0;
+                    entry.offences = 0;
+                }
+            },
+            None => self.addrs.push(AddressEntry {
+                ip,
+                status,
+                throttle_cnt:   0,
+                offences:       0,
+            }),
+        }
+        self.addrs.retain(|e| e.status != GuardStatus::Normal || e.throttle_cnt > 0 || e.offences > 0);
+    }
+
+    /// Set the status of the given user, adding it if necessary.
+    pub fn set_user(&mut self, uid: Vec<u8>, status: GuardStatus) {
+        match self.users.iter_mut().find(|e| e.uid == uid) {
+            Some(entry) => entry.status = status,
+            None => self.users.push(UserEntry { uid, status }),
+        }
+        self.users.retain(|e| e.status != GuardStatus::Normal);
+    }
+
+    /// Set the status of the address or user given by an operator, as either an IP address or the
+    /// hexadecimal representation of a user id.
+    pub fn set(&mut self, target: &str, status: GuardStatus) -> Outcome<()> {
+        match res!(GuardTarget::parse(target)) {
+            GuardTarget::Addr(ip) => self.set_addr(ip, status),
+            GuardTarget::User(uid) => self.set_user(uid, status),
+        }
+        Ok(())
+    }
+
+    /// Human readable lines describing the snapshot.
+    pub fn to_lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        lines.push(fmt!("{} addresses:", self.addrs.len()));
+        for entry in &self.addrs {
+            lines.push(fmt!("  {:<40} {} (throttled {} times, {} recent offences)",
+                entry.ip.to_string(), entry.status, entry.throttle_cnt, entry.offences));
+        }
+        lines.push(fmt!("{} users:", self.users.len()));
+        for entry in &self.users {
+            lines.push(fmt!("  {:<40} {}", hex(&entry.uid), entry.status));
+        }
+        lines
+    }
+}
+
+/// Lower case hexadecimal representation of user id bytes, as used by operators.
+pub fn hex(byts: &[u8]) -> String {
+    byts.iter().map(|b| fmt!("{:02x}", b)).collect()
+}
+
+/// Parse the hexadecimal representation of user id bytes.
+pub fn from_hex(s: &str) -> Outcome<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return Err(err!(
+            "'{}' is not an even length hexadecimal string.", s;
+            Decode, Invalid, Input));
+    }
+    let mut byts = Vec::with_capacity(s.len() / 2);
+    for i in (0..s.len()).step_by(2) {
+        byts.push(res!(u8::from_str_radix(&s[i..i + 2], 16), Decode, Invalid, Input));
+    }
+    Ok(byts)
+}
@@ -1,3 +1,8 @@
+use crate::srv::guard::snapshot::{
+    GuardStatus,
+    UserEntry,
+};
+
 use oxedyne_fe2o3_core::{
     prelude::*,
     map::MapMut,
@@ -13,19 +18,34 @@ use std::{
     clone::Clone,
     fmt::Debug,
     sync::RwLock,
-    //time::{
-    //    Duration,
-    //    SystemTime,
-    //},
+    time::SystemTime,
 };
 
 #[derive(Clone, Debug)]
 pub enum UserState {
     Unknown,
-    Blacklist, // No soup for you.
+    Blacklist(Option<SystemTime>), // No soup for you, until the given time if any.
     Whitelist, // Come on through.
 }
 
+impl UserState {
+    pub fn status(&self) -> GuardStatus {
+        match self {
+            Self::Unknown => GuardStatus::Normal,
+            Self::Blacklist(until) => GuardStatus::Blacklist(*until),
+            Self::Whitelist => GuardStatus::Whitelist,
+        }
+    }
+
+    pub fn from_status(status: GuardStatus) -> Self {
+        match status {
+            GuardStatus::Normal => Self::Unknown,
+            GuardStatus::Blacklist(until) => Self::Blacklist(until),
+            GuardStatus::Whitelist => Self::Whitelist,
+        }
+    }
+}
+
 impl Default for UserState {
     fn default() -> Self {
         Self::Unknown
@@ -36,6 +56,7 @@ impl Default for UserState {
 pub struct UserLog<
     D: Clone + Debug + Default, // user supplied data container
 > {
+    pub uid:    Vec<u8>, // Recorded for administration, the map key being a hash.
     pub state:  UserState,
     // Data
     pub data:   D,
@@ -79,12 +100,21 @@ impl<
         let (key, locked_map) = res!(self.get_locked_map(uid));
         let mut unlocked_map = lock_write!(locked_map);
         match unlocked_map.get_mut(&key) {
-            Some(_ulog) => {
-                // TODO examine user log
+            Some(ulog) => {
+                if let UserState::Blacklist(..) = ulog.state {
+                    if ulog.state.status().expired(SystemTime::now()) {
+                        ulog.state = UserState::Unknown;
+                    } else {
+                        return Ok(true);
+                    }
+                }
             },
             None => {
                 if accept_unknown { 
-                    let ulog = UserLog::default();
+                    let ulog = UserLog {
+                        uid: uid.to_byte_array().to_vec(),
+                        ..Default::default()
+                    };
                     unlocked_map.insert(key, ulog);
                 } else {
                     return Ok(true);
@@ -94,6 +124,75 @@ impl<
         Ok(false)
     }
 
+    /// Return users whose blacklisting has expired to normal, returning the number of users
+    /// released.
+    pub fn expire(&self) -> Outcome<usize> {
+        let now = SystemTime::now();
+        let mut count = 0;
+        for locked_map in self.umap.shards.iter().flatten() {
+            let mut unlocked_map = lock_write!(locked_map);
+            for (_, ulog) in unlocked_map.iter_mut() {
+                if ulog.state.status().expired(now) {
+                    ulog.state = UserState::Unknown;
+                    count += 1;
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    /// Set the lasting state of the user with the given id bytes, creating a log for it if
+    /// necessary.  Whitelisting a user admits it even when unknown users are not accepted.
+    pub fn set_status(
+        &self,
+        uid:    &[u8],
+        status: GuardStatus,
+    )
+        -> Outcome<()>
+    {
+        let (key, locked_map) = res!(self.get_locked_map_using_bytes(uid));
+        let mut unlocked_map = lock_write!(locked_map);
+        match unlocked_map.get_mut(&key) {
+            Some(ulog) => ulog.state = UserState::from_status(status),
+            None => {
+                unlocked_map.insert(key, UserLog {
+                    uid:    uid.to_vec(),
+                    state:  UserState::from_status(status),
+                    ..Default::default()
+                });
+            },
+        }
+        Ok(())
+    }
+
+    /// The lasting state of all users that are blacklisted or whitelisted.
+    pub fn snapshot(&self) -> Outcome<Vec<UserEntry>> {
+        let mut result = Vec::new();
+        for locked_map in self.umap.shards.iter().flatten() {
+            let unlocked_map = lock_read!(locked_map);
+            for (_, ulog) in unlocked_map.iter() {
+                let status = ulog.state.status();
+                if status != GuardStatus::Normal && !ulog.uid.is_empty() {
+                    result.push(UserEntry {
+                        uid: ulog.uid.clone(),
+                        status,
+                    });
+                }
+            }
+        }
+        result.sort_by(|a, b| a.uid.cmp(&b.uid));
+        Ok(result)
+    }
+
+    /// Restore the lasting state of the given users, returning the number restored.
+    pub fn restore(&self, entries: Vec<UserEntry>) -> Outcome<usize> {
+        let count = entries.len();
+        for entry in entries {
+            res!(self.set_status(&entry.uid, entry.status));
+        }
+        Ok(count)
+    }
+
     pub fn get_locked_map<
         const UIDL: usize,
         UID: NumIdDat<UIDL>,
@@ -103,7 +202,16 @@ impl<
     )
         -> Outcome<(HashForm, &RwLock<M>)>
     {
-        let key = self.umap.key(&uid.to_byte_array());
+        self.get_locked_map_using_bytes(&uid.to_byte_array())
+    }
+
+    pub fn get_locked_map_using_bytes(
+        &self,
+        uid: &[u8],
+    )
+        -> Outcome<(HashForm, &RwLock<M>)>
+    {
+        let key = self.umap.key(uid);
         let locked_map = res!(self.umap.get_shard_using_hash(&key));
         Ok((key, locked_map))
     }
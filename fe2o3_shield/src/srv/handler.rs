@@ -0,0 +1,84 @@
+//! Application message handling.  An application extends Shield messaging with its own commands,
+//! supplied by its `ShieldHandler` and gathered by `crate::srv::msg::syntax::app_msg` into the
+//! application syntax held by the protocol.  Application messages travel encrypted within a
+//! session as the payload of an `AppMsg`, and each command of a received message is passed to the
+//! handler, which may reply within the same session.
+use oxedyne_fe2o3_core::prelude::*;
+use oxedyne_fe2o3_syntax::{
+    SyntaxRef,
+    cmd::Cmd,
+    msg::{
+        Msg,
+        MsgCmd,
+    },
+};
+
+use std::net::SocketAddr;
+
+
+pub trait ShieldHandler:
+    Clone
+    + std::fmt::Debug
+    + Default
+    + Send
+    + Sync
+{
+    /// The commands of the application syntax.
+    fn cmds(&self) -> Outcome<Vec<Cmd>> {
+        Ok(Vec::new())
+    }
+
+    /// Handle a command of an application message received from the given peer, optionally
+    /// returning a message, using the given application syntax, to send back.
+    fn handle(
+        &mut self,
+        mcmd:   MsgCmd,
+        peer:   &SocketAddr,
+        syntax: SyntaxRef,
+    )
+        -> Outcome<Option<Msg>>;
+}
+
+/// Returns each command received to the sender, for an application syntax consisting of the
+/// given commands.
+#[derive(Clone, Debug, Default)]
+pub struct ShieldEchoHandler {
+    pub cmds: Vec<Cmd>,
+}
+
+impl ShieldHandler for ShieldEchoHandler {
+    fn cmds(&self) -> Outcome<Vec<Cmd>> {
+        Ok(self.cmds.clone())
+    }
+
+    fn handle(
+        &mut self,
+        mcmd:   MsgCmd,
+        peer:   &SocketAddr,
+        syntax: SyntaxRef,
+    )
+        -> Outcome<Option<Msg>>
+    {
+        trace!(async_log::stream(), "ShieldEchoHandler received from {:?}: {}", peer, mcmd);
+        let msg = res!(Msg::new(syntax).add_cmd(mcmd)); // Echo.
+        Ok(Some(msg))
+    }
+}
+
+/// Accepts and discards application messages.  This is the default handler.
+#[derive(Clone, Debug, Default)]
+pub struct ShieldSinkHandler;
+
+impl ShieldHandler for ShieldSinkHandler {
+    fn handle(
+        &mut self,
+        mcmd:   MsgCmd,
+        peer:   &SocketAddr,
+        _syntax: SyntaxRef,
+    )
+        -> Outcome<Option<Msg>>
+    {
+        trace!(async_log::stream(), "ShieldSinkHandler received from {:?}: {}", peer, mcmd);
+        Ok(None)
+    }
+}
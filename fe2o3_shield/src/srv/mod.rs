@@ -1,10 +1,16 @@
 pub mod cfg;
+pub mod client;
 pub mod cmd;
 pub mod constant;
 pub mod context;
+pub mod discovery;
 pub mod guard;
+pub mod handler;
 pub mod msg;
 pub mod pow;
 pub mod schemes;
 pub mod server;
+pub mod session;
+#[cfg(feature = "sim")]
+pub mod shim;
 pub mod test;
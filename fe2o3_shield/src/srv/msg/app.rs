@@ -0,0 +1,196 @@
+//! Application messages carry commands of the application syntax between peers in an
+//! established session.  The application message is encoded, then sealed
+//! with the session key exactly as for session data, so that it enjoys the same confidentiality
+//! and replay protection.  On receipt, each command of the message is passed to the
+//! `ShieldHandler` of the protocol, and any reply it returns is sent back within the session.
+use crate::{
+    srv::{
+        handler::ShieldHandler,
+        msg::{
+            core::{
+                IdentifiedMessage,
+                IdTypes,
+                MsgType,
+                MsgFmt,
+                MsgIds,
+                MsgPow,
+            },
+            data::SessionType,
+            encode::ShieldCommand,
+            handshake::{
+                bytes_arg,
+                new_msg,
+            },
+            protocol::{
+                Protocol,
+                ProtocolTypes,
+            },
+        },
+        session::SessionMap,
+    },
+};
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    byte::IntoBytes,
+};
+use oxedyne_fe2o3_jdat::prelude::*;
+use oxedyne_fe2o3_syntax::{
+    msg::{
+        Msg,
+        MsgCmd,
+    },
+};
+
+use std::{
+    net::{
+        SocketAddr,
+        UdpSocket,
+    },
+    sync::Arc,
+};
+
+
+// AppMsg ======================================================================
+/// An encrypted application message sent within an established session.
+#[derive(Clone, Debug, Default)]
+pub struct AppMsg<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+> {
+    pub fmt:    MsgFmt,
+    pub pow:    MsgPow,
+    pub mid:    MsgIds<SL, UL, ID::S, ID::U>,
+    // Command-specific
+    pub cipher: Vec<u8>, // Direction, sequence number and encoded message, encrypted.
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    IntoBytes for AppMsg<ML, SL, UL, ID>
+{
+    fn into_bytes(self, buf: Vec<u8>) -> Outcome<Vec<u8>> {
+        res!(self.construct()).into_bytes(buf)
+    }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    IdentifiedMessage for AppMsg<ML, SL, UL, ID>
+{
+    fn typ(&self) -> MsgType { SessionType::App as MsgType }
+    fn name(&self) -> &'static str { "app" }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    ShieldCommand<ML, SL, UL, ID> for AppMsg<ML, SL, UL, ID>
+{
+    fn fmt(&self) -> &MsgFmt { &self.fmt }
+    fn pow(&self) -> &MsgPow { &self.pow }
+    fn mid(&self) -> &MsgIds<SL, UL, ID::S, ID::U> { &self.mid }
+    fn inc_sigpk(&self) -> bool { false }
+
+    fn construct(self) -> Outcome<Msg> {
+        let (mut msg, mut mcmd) = res!(new_msg(&self));
+        mcmd = res!(mcmd.add_arg_val("-ct", Some(Dat::BC64(self.cipher))));
+        msg = res!(msg.add_cmd(mcmd));
+        res!(msg.validate());
+        Ok(msg)
+    }
+
+    fn deconstruct(
+        &mut self,
+        mcmd: &mut MsgCmd,
+    )
+        -> Outcome<()>
+    {
+        self.cipher = res!(bytes_arg(mcmd, "-ct", "application message ciphertext"));
+        Ok(())
+    }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    AppMsg<ML, SL, UL, ID>
+{
+    /// Decrypt and decode the application message using the session with the sender, pass each
+    /// of its commands to the handler, and send back any replies.
+    pub fn respond<
+        const C: usize,
+        P: ProtocolTypes<ML, SL, UL, ID = ID> + 'static,
+    >(
+        &mut self,
+        mcmd:       &mut MsgCmd,
+        protocol:   &Protocol<C, ML, SL, UL, P>,
+        sessions:   &SessionMap<C, ML, SL, UL, ID>,
+        src_addr:   &SocketAddr,
+        trg:        Arc<UdpSocket>,
+    )
+        -> Outcome<()>
+    {
+        res!(self.deconstruct(mcmd));
+        let byts_opt = {
+            let mut unlocked_sessions = lock_write!(sessions);
+            match unlocked_sessions.get_mut(src_addr) {
+                Some(session) if
+                    session.is_established()
+                    && session.peer_uid == Some(self.uid())
+                    && session.sid_opt.is_some()
+                    && session.sid_opt == self.sid_opt() =>
+                {
+                    res!(protocol.open_session_msg(session, &self.cipher)).map(|(byts, _)| byts)
+                },
+                _ => {
+                    debug!(async_log::stream(),
+                        "Dropping application message from {:?} outside of a session.", src_addr);
+                    return Ok(());
+                },
+            }
+        };
+        let byts = match byts_opt {
+            Some(byts) => byts,
+            None => {
+                debug!(async_log::stream(),
+                    "Dropping replayed application message from {:?}.", src_addr);
+                return Ok(());
+            },
+        };
+        let msgrx = res!(Msg::new(protocol.app_syntax.clone()).from_bytes(&byts, None));
+        debug!(async_log::stream(), "app msgrx [{}]: {}", byts.len(), msgrx);
+        // The protocol, and with it the handler, is cloned for each packet, so any state the
+        // handler keeps across messages must be shared.
+        let mut handler = protocol.handler.clone();
+        for (_, app_cmd) in msgrx.cmds {
+            let app_syntax = protocol.app_syntax.clone();
+            if let Some(reply) = res!(handler.handle(app_cmd, src_addr, app_syntax)) {
+                res!(protocol.send_app_msg(
+                    trg.clone(),
+                    src_addr,
+                    sessions,
+                    self.fmt.syntax.clone(),
+                    reply,
+                ));
+            }
+        }
+        Ok(())
+    }
+}
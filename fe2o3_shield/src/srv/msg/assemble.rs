@@ -114,6 +114,21 @@ impl<
         Ok((drop, msg_byt_opt))
     }
 
+    /// The chunk indices received so far for a partial message, if any.
+    pub fn received<
+        const MIDL: usize,
+        MID: NumIdDat<MIDL>,
+    >(
+        &self,
+        mid: &MID,
+    )
+        -> Outcome<Option<Vec<PacketCount>>>
+    {
+        let (key, locked_map) = res!(self.get_locked_map(mid));
+        let unlocked_map = lock_read!(locked_map);
+        Ok(unlocked_map.get(&key).map(|mstat| mstat.received()))
+    }
+
     pub fn remove<
         const MIDL: usize,
         MID: NumIdDat<MIDL>,
@@ -191,6 +206,11 @@ impl MsgState {
         }
     }
 
+    /// The chunk indices received so far.
+    pub fn received(&self) -> Vec<PacketCount> {
+        self.parts.keys().copied().collect()
+    }
+
     /// Inserts the packet payload into the message.  Returns whether the entire partial message
     /// should be dropped, and possibly the completed message.
     pub fn insert_part<
@@ -0,0 +1,202 @@
+//! Session data messages carry application payloads between peers once a handshake has
+//! established a session.  Each payload is encrypted with the session key via the `WireSchemes`
+//! encrypter, and prefixed before encryption with a direction byte and a sequence number so that
+//! replayed or reflected messages are dropped.  Large payloads are chunked into packets and
+//! reassembled by the `MsgAssembler` like any other message.
+use crate::{
+    srv::{
+        msg::{
+            core::{
+                IdentifiedMessage,
+                IdTypes,
+                MsgType,
+                MsgFmt,
+                MsgIds,
+                MsgPow,
+            },
+            encode::ShieldCommand,
+            handshake::{
+                bytes_arg,
+                new_msg,
+            },
+            protocol::{
+                Protocol,
+                ProtocolTypes,
+            },
+        },
+        session::SessionMap,
+    },
+};
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    byte::IntoBytes,
+};
+use oxedyne_fe2o3_jdat::prelude::*;
+use oxedyne_fe2o3_syntax::{
+    msg::{
+        Msg,
+        MsgCmd,
+    },
+};
+
+use std::{
+    net::{
+        SocketAddr,
+        UdpSocket,
+    },
+    sync::Arc,
+};
+
+
+#[repr(u16)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SessionType {
+    Unknown = 0,
+    Data    = 7, // Follows the handshake message types.
+    App     = 16, // Follows the rekey message types.
+}
+
+impl From<MsgType> for SessionType {
+    fn from(u: MsgType) -> Self {
+        match u {
+            7 =>    Self::Data,
+            16 =>   Self::App,
+            _ =>    Self::Unknown,
+        }
+    }
+}
+
+/// A decrypted session payload, delivered to the application.
+#[derive(Clone, Debug)]
+pub struct SessionDelivery {
+    pub peer:   SocketAddr,
+    pub data:   Vec<u8>,
+}
+
+// SessionData =================================================================
+/// An encrypted application payload sent within an established session.
+#[derive(Clone, Debug, Default)]
+pub struct SessionData<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+> {
+    pub fmt:    MsgFmt,
+    pub pow:    MsgPow,
+    pub mid:    MsgIds<SL, UL, ID::S, ID::U>,
+    // Command-specific
+    pub cipher: Vec<u8>, // Direction, sequence number and payload, encrypted.
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    IntoBytes for SessionData<ML, SL, UL, ID>
+{
+    fn into_bytes(self, buf: Vec<u8>) -> Outcome<Vec<u8>> {
+        res!(self.construct()).into_bytes(buf)
+    }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    IdentifiedMessage for SessionData<ML, SL, UL, ID>
+{
+    fn typ(&self) -> MsgType { SessionType::Data as MsgType }
+    fn name(&self) -> &'static str { "data" }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    ShieldCommand<ML, SL, UL, ID> for SessionData<ML, SL, UL, ID>
+{
+    fn fmt(&self) -> &MsgFmt { &self.fmt }
+    fn pow(&self) -> &MsgPow { &self.pow }
+    fn mid(&self) -> &MsgIds<SL, UL, ID::S, ID::U> { &self.mid }
+    fn inc_sigpk(&self) -> bool { false }
+
+    fn construct(self) -> Outcome<Msg> {
+        let (mut msg, mut mcmd) = res!(new_msg(&self));
+        mcmd = res!(mcmd.add_arg_val("-ct", Some(Dat::BC64(self.cipher))));
+        msg = res!(msg.add_cmd(mcmd));
+        res!(msg.validate());
+        Ok(msg)
+    }
+
+    fn deconstruct(
+        &mut self,
+        mcmd: &mut MsgCmd,
+    )
+        -> Outcome<()>
+    {
+        self.cipher = res!(bytes_arg(mcmd, "-ct", "session ciphertext"));
+        Ok(())
+    }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    SessionData<ML, SL, UL, ID>
+{
+    /// Decrypt the payload using the session with the sender, and deliver it to the application.
+    pub fn respond<
+        const C: usize,
+        P: ProtocolTypes<ML, SL, UL, ID = ID> + 'static,
+    >(
+        &mut self,
+        mcmd:       &mut MsgCmd,
+        protocol:   &Protocol<C, ML, SL, UL, P>,
+        sessions:   &SessionMap<C, ML, SL, UL, ID>,
+        src_addr:   &SocketAddr,
+        _trg:       Arc<UdpSocket>,
+    )
+        -> Outcome<()>
+    {
+        res!(self.deconstruct(mcmd));
+        let data_opt = {
+            let mut unlocked_sessions = lock_write!(sessions);
+            match unlocked_sessions.get_mut(src_addr) {
+                Some(session) if
+                    session.is_established()
+                    && session.peer_uid == Some(self.uid())
+                    && session.sid_opt.is_some()
+                    && session.sid_opt == self.sid_opt() =>
+                {
+                    res!(protocol.open_session_msg(session, &self.cipher)).map(|(data, _)| data)
+                },
+                _ => {
+                    debug!(async_log::stream(), "Dropping data from {:?} outside of a session.",
+                        src_addr);
+                    return Ok(());
+                },
+            }
+        };
+        match data_opt {
+            Some(data) => protocol.inbox.send(SessionDelivery {
+                peer: *src_addr,
+                data,
+            }),
+            None => {
+                debug!(async_log::stream(), "Dropping replayed data from {:?}.", src_addr);
+                Ok(())
+            },
+        }
+    }
+}
@@ -2,13 +2,30 @@ use crate::{
     srv::{
         constant,
         msg::{
+            app::AppMsg,
             core::{
                 IdTypes,
                 MsgFmt,
                 MsgIds,
                 MsgPow,
             },
-            handshake::HReq1,
+            data::SessionData,
+            discovery::{
+                DiscoveryType,
+                FindNode,
+                Peers,
+                Ping,
+                Pong,
+            },
+            handshake::{
+                HandshakeType,
+                HReq1,
+                HReq2,
+                HReq3,
+                HResp1,
+                HResp2,
+                HResp3,
+            },
             packet::{
                 PacketMeta,
                 PacketValidationArtefactRelativeIndices,
@@ -17,14 +34,24 @@ use crate::{
                 Protocol,
                 ProtocolTypes,
             },
+            rekey::{
+                KeyRotate,
+                RekeyReq,
+                RekeyResp,
+            },
+            reliable::Ack,
         },
         pow::PowPristine,
+        session::SessionMap,
     },
 };
 
 use oxedyne_fe2o3_core::{
     prelude::*,
-    byte::FromBytes,
+    byte::{
+        FromBytes,
+        ToByteArray,
+    },
 };
 use oxedyne_fe2o3_crypto::keys::PublicKey;
 use oxedyne_fe2o3_hash::pow::PowVars;
@@ -42,6 +69,7 @@ use std::{
         UdpSocket,
     },
     sync::Arc,
+    time::Instant,
 };
 
 
@@ -61,6 +89,7 @@ impl<
         src_addr:   SocketAddr,
         trg:        Arc<UdpSocket>,
         syntax:     SyntaxRef,
+        sessions:   SessionMap<C, ML, SL, UL, P::ID>,
     )
         -> Outcome<()>
     {
@@ -71,6 +100,7 @@ impl<
             src_addr,
             trg,
             syntax,
+            sessions,
         ) {
             Err(e) => {
                 let e2 = err!(e,
@@ -92,6 +122,7 @@ impl<
         src_addr:   SocketAddr,
         trg:        Arc<UdpSocket>,
         syntax:     SyntaxRef,
+        sessions:   SessionMap<C, ML, SL, UL, P::ID>,
     )
         -> Outcome<()>
     {
@@ -155,39 +186,46 @@ impl<
         }
         debug!(async_log::stream(), "");
         let n2 = n1 + (meta.chnk.chunk_size as usize);
+        if n2 >= n {
+            // The validation artefacts would lie beyond the end of the packet, so the proof of
+            // work cannot be checked.  Without it the source address may be spoofed, so it is
+            // not charged with an offence.
+            debug!(async_log::stream(), "Dropping packet with chunk size {} exceeding its length {}.",
+                meta.chnk.chunk_size, n);
+            return Ok(()); // Drop silently.
+        }
         let (afact_rel_ind, _) =
             res!(PacketValidationArtefactRelativeIndices::from_bytes(&buf[n2..n]));
     
-        // Get the (locked) shared address and user maps, and unlock them in tight scopes when we
-        // need to read or write.
-        let (akey, locked_amap) = res!(self.agrd.get_locked_map(&src_addr));
+        // Get the (locked) shared user map, and unlock it in tight scopes when we need to read or
+        // write.
         let (ukey, locked_umap) = res!(self.ugrd.get_locked_map(&meta.uid));
         
         debug!(async_log::stream(), "");
         // What are our proof of work requirements for the packet?
         let powvars = match self.packval.pow {
             Some(..) => {
-                let zbits = {
-                    let unlocked_amap = lock_read!(locked_amap);
-                    if let Some(alog) = unlocked_amap.get(&akey) {
-                        let unlocked_timer = lock_read!(self.timer);
-                        let zbits = res!(
-                            self.gpzparams.required_global_zbits(unlocked_timer.avg_rps() as u16),
-                            IO,
-                        );
-                        if zbits >= alog.data.my_zbits {
-                            zbits
-                        } else {
-                            alog.data.my_zbits
-                        }
-                    } else {
-                        return Err(err!(
-                            "No AddressLog entry for {:?}, which should have been created \
-                            by the AddressGuard::drop_packet call.", src_addr;
-                            Bug, Missing));
-                    }
+                // Sessionless messages that follow my statement of the difficulty I require
+                // are subject to a difficulty that rises with the offences of the address.
+                // Opening requests cannot yet know it, and session messages are not subject to
+                // it.
+                let htyp = HandshakeType::from(meta.typ);
+                let dtyp = DiscoveryType::from(meta.typ);
+                let opening = htyp == HandshakeType::Req1
+                    || dtyp == DiscoveryType::Ping
+                    || dtyp == DiscoveryType::FindNode;
+                let zbits = if !opening
+                    && (htyp != HandshakeType::Unknown || dtyp != DiscoveryType::Unknown)
+                {
+                    res!(self.request_zbits(&src_addr))
+                } else {
+                    res!(self.required_zbits(&src_addr))
                 };
-                let code = {
+                // A peer that has restarted no longer holds the code I issued it, so a HReq1
+                // carries none.  The signing key I have on record for the user still guards it.
+                let code = if htyp == HandshakeType::Req1 {
+                    [0; C]
+                } else {
                     let unlocked_umap = lock_read!(locked_umap);
                     if let Some(ulog) = unlocked_umap.get(&ukey) {
                         ulog.data.code.clone().unwrap_or([0; C])
@@ -219,7 +257,9 @@ impl<
             _  => None,
         };
         // Insert my record of your public signing key into the packet signer for the purpose of
-        // verification.
+        // verification.  If you recently rotated your key, also prepare a signer with your old
+        // key.
+        let mut grace_signer_opt = None;
         match &mut self.packval.sig {
             Some(signer) => {
                 let unlocked_umap = lock_read!(locked_umap);
@@ -241,6 +281,16 @@ impl<
                         },
                         None => (),
                     }
+                    if let (Some(sigtpk_old), Some(until)) =
+                        (&ulog.data.sigtpk_opt_old, ulog.data.sigtpk_old_until)
+                    {
+                        if Instant::now() < until {
+                            grace_signer_opt = Some(res!(signer.clone_with_keys(
+                                Some(&sigtpk_old.key[..]),
+                                None,
+                            )));
+                        }
+                    }
                 } else {
                     return Err(err!(
                         "No UserLog entry for {:02x?}, which should have been created \
@@ -267,22 +317,53 @@ impl<
         }
         ////////
         
-        let validation = res!(self.packval.validate(
+        let mut validation = res!(self.packval.clone().validate(
             &buf[..n],
             n2,
-            afact_rel_ind,
-            powvars,
+            afact_rel_ind.clone(),
+            powvars.clone(),
             meta.typ,
         ));
+        if validation.sig_invalid() && !validation.pow_invalid() {
+            if let Some(grace_signer) = grace_signer_opt {
+                // The packet may have been signed with your old key before your key rotation
+                // reached me.
+                let mut packval = self.packval.clone();
+                packval.sig = Some(grace_signer);
+                validation = res!(packval.validate(
+                    &buf[..n],
+                    n2,
+                    afact_rel_ind,
+                    powvars,
+                    meta.typ,
+                ));
+                if !validation.sig_invalid() {
+                    debug!(async_log::stream(), "Packet from {:?} verified using the old signing \
+                        key of the user during the key rotation grace period.", src_addr);
+                }
+            }
+        }
         debug!(async_log::stream(), "{:?}", validation);
         let validity = fmt!("pow {} sig {}", validation.pow_state(), validation.sig_state());
+        let pow_invalid = validation.pow_invalid();
 
+        // The public signing key included in the packet and used to verify it, which identifies
+        // the sender of a discovery message.
+        let mut verified_sigpk: Option<Vec<u8>> = None;
         match validation.is_valid() {
             // sigpk_opt = possible public signing key that may be included in the packet
             // validation artefact.
             Some((valid, sigpk_opt)) => if !valid {
-                // TODO Take action on an invalid signature provided by this address and user id.
-                trace!(async_log::stream(), "Dropping packet: {}", validity);
+                // TODO Take action on an invalid signature provided by this user id.
+                if pow_invalid {
+                    // Without a valid proof of work the source address may be spoofed, so it is
+                    // not charged with an offence.
+                    trace!(async_log::stream(), "Dropping packet: {}", validity);
+                } else {
+                    let offences = res!(self.agrd.offend(&src_addr));
+                    trace!(async_log::stream(), "Dropping packet: {}, {} offences by {:?}",
+                        validity, offences, src_addr);
+                }
                 return Ok(()); // Drop silently.
             } else {
                 // The packet signature was valid.
@@ -291,6 +372,7 @@ impl<
                     Some((nid, sigpk_given)) => {
                         // A public signing key was supplied, and was used for verification.  My
                         // existing record of your public signing key, if it exists, was not used.
+                        verified_sigpk = Some(sigpk_given.to_vec());
                         let mut unlocked_umap = lock_write!(locked_umap);
                         if let Some(ulog) = unlocked_umap.get_mut(&ukey) {
                             match &ulog.data.sigtpk_opt {
@@ -340,7 +422,19 @@ impl<
                                         }
                                     }
                                 },
-                                None => (), // TODO FINISHME I can't remember what is supposed to happen here!!!
+                                None => {
+                                    // I have no record of your public signing key, so you are
+                                    // a new user.  Whether I accept the key you supplied
+                                    // depends on our policy.
+                                    if self.accept_unknown {
+                                        ulog.data.sigtpk_opt = Some(res!(PublicKey::now(
+                                            nid,
+                                            sigpk_given.to_vec(),
+                                        )));
+                                    } else {
+                                        return Ok(());
+                                    }
+                                },
                             }
                         } else {
                             return Err(err!(
@@ -354,6 +448,31 @@ impl<
             },
             None => (),
         }
+        // Messages delivered reliably are acknowledged.  If I have already received this one in
+        // full, the sender missed my acknowledgement, so repeat it and drop the packet.
+        let reliable = {
+            let unlocked_reliable = lock_read!(self.reliable);
+            unlocked_reliable.params.is_reliable(meta.typ)
+        };
+        let mid_byts = meta.mid.to_byte_array();
+        if reliable {
+            let done_opt = {
+                let unlocked_reliable = lock_read!(self.reliable);
+                unlocked_reliable.completed(&src_addr, &mid_byts)
+            };
+            if let Some(num_chunks) = done_opt {
+                debug!(async_log::stream(), "Dropping duplicate of completed message.");
+                return self.send_ack(
+                    trg,
+                    &src_addr,
+                    &sessions,
+                    syntax,
+                    &meta.mid,
+                    num_chunks,
+                    (0..num_chunks).collect(),
+                );
+            }
+        }
         // Ok, we're almost done on a packet level.  Insert the message chunk into the AddressLog
         // partial message map, which returns the message when complete.  However, I may also have
         // to drop the packet if there is a problem.
@@ -363,7 +482,22 @@ impl<
             &buf[n1..n2], // payload + validator data
             &self.ma_params,
         )) { // Returns whether to drop the packet, and the potential syntax protocol message.
-            (false, None) => return Ok(()), // Payload remains incomplete.
+            (false, None) => { // Payload remains incomplete.
+                if reliable {
+                    if let Some(received) = res!(self.massembler.received(&meta.mid)) {
+                        res!(self.send_ack(
+                            trg,
+                            &src_addr,
+                            &sessions,
+                            syntax,
+                            &meta.mid,
+                            meta.chnk.num_chunks,
+                            received,
+                        ));
+                    }
+                }
+                return Ok(());
+            },
             (false, Some(msg_byts)) => { // We have a complete message.
                 let msgrx = Msg::new(syntax.clone());
                 let mut msgrx = res!(msgrx.from_bytes(&msg_byts, None));
@@ -389,78 +523,239 @@ impl<
         
                 // Multiple commands in a single message are permitted.
                 for (cmd_name, mut msgcmd) in msgrx.cmds {
+                    // Each command type implements its own custom respond method, which
+                    // captures only the parameters it needs.
                     match cmd_name.as_str() {
                         "hreq1" => {
                             debug!(async_log::stream(), "HREQ1");
+                            let sigpk = match &verified_sigpk {
+                                Some(sigpk) => sigpk,
+                                None => {
+                                    debug!(async_log::stream(), "Dropping hreq1 without an \
+                                        included signing key.");
+                                    return Ok(());
+                                },
+                            };
                             let mut scmd: HReq1<ML, SL, UL, P::ID> = HReq1 {
                                 fmt: msgfmt.clone(),
                                 pow: msgpow.clone(),
                                 mid: msgids.clone(),
                                 ..Default::default()
                             };
-                            // Each command type can implement its own
-                            // custom process method, which captures
-                            // only the parameters it needs.
-                            let mut unlocked_amap = lock_write!(locked_amap);
-                            if let Some(alog) = unlocked_amap.get_mut(&akey) {
-                                //if let Some(mut alog_data) = alog.data.as_mut() {
-                                    res!(scmd.respond(
-                                        &mut msgcmd,
-                                        //alog.data.as_mut(), // For pow parameters.
-                                        &mut alog.data, // For pow parameters.
-                                        //&mut self.ugrd, // For user signing pk.
-                                        // For sending HResp1.
-                                        //&self.sock_addr,
-                                        //Config::chunker(self.wschms.clone_chunk_config()),
-                                        trg.clone(),
-                                        //&self.src_addr,
-                                    ));
-                                //}
+                            res!(scmd.respond(&mut msgcmd, &self, &sessions, &src_addr, trg.clone(), sigpk));
+                        },
+                        "hresp1" => {
+                            debug!(async_log::stream(), "HRESP1");
+                            let mut scmd: HResp1<ML, SL, UL, P::ID> = HResp1 {
+                                fmt: msgfmt.clone(),
+                                pow: msgpow.clone(),
+                                mid: msgids.clone(),
+                                ..Default::default()
+                            };
+                            res!(scmd.respond(&mut msgcmd, &self, &sessions, &src_addr, trg.clone()));
+                        },
+                        "hreq2" => {
+                            debug!(async_log::stream(), "HREQ2");
+                            let mut scmd: HReq2<ML, SL, UL, P::ID> = HReq2 {
+                                fmt: msgfmt.clone(),
+                                pow: msgpow.clone(),
+                                mid: msgids.clone(),
+                                ..Default::default()
+                            };
+                            res!(scmd.respond(&mut msgcmd, &self, &sessions, &src_addr, trg.clone()));
+                        },
+                        "hresp2" => {
+                            debug!(async_log::stream(), "HRESP2");
+                            let mut scmd: HResp2<ML, SL, UL, P::ID> = HResp2 {
+                                fmt: msgfmt.clone(),
+                                pow: msgpow.clone(),
+                                mid: msgids.clone(),
+                                ..Default::default()
+                            };
+                            res!(scmd.respond(&mut msgcmd, &self, &sessions, &src_addr, trg.clone()));
+                        },
+                        "hreq3" => {
+                            debug!(async_log::stream(), "HREQ3");
+                            let mut scmd: HReq3<ML, SL, UL, P::ID> = HReq3 {
+                                fmt: msgfmt.clone(),
+                                pow: msgpow.clone(),
+                                mid: msgids.clone(),
+                                ..Default::default()
+                            };
+                            res!(scmd.respond(&mut msgcmd, &self, &sessions, &src_addr, trg.clone()));
+                        },
+                        "hresp3" => {
+                            debug!(async_log::stream(), "HRESP3");
+                            let mut scmd: HResp3<ML, SL, UL, P::ID> = HResp3 {
+                                fmt: msgfmt.clone(),
+                                pow: msgpow.clone(),
+                                mid: msgids.clone(),
+                                ..Default::default()
+                            };
+                            res!(scmd.respond(&mut msgcmd, &self, &sessions, &src_addr, trg.clone()));
+                        },
+                        "data" => {
+                            debug!(async_log::stream(), "DATA");
+                            let mut scmd: SessionData<ML, SL, UL, P::ID> = SessionData {
+                                fmt: msgfmt.clone(),
+                                pow: msgpow.clone(),
+                                mid: msgids.clone(),
+                                ..Default::default()
+                            };
+                            res!(scmd.respond(&mut msgcmd, &self, &sessions, &src_addr, trg.clone()));
+                        },
+                        "app" => {
+                            debug!(async_log::stream(), "APP");
+                            let mut scmd: AppMsg<ML, SL, UL, P::ID> = AppMsg {
+                                fmt: msgfmt.clone(),
+                                pow: msgpow.clone(),
+                                mid: msgids.clone(),
+                                ..Default::default()
+                            };
+                            res!(scmd.respond(&mut msgcmd, &self, &sessions, &src_addr, trg.clone()));
+                        },
+                        "ack" => {
+                            debug!(async_log::stream(), "ACK");
+                            let mut scmd: Ack<ML, SL, UL, P::ID> = Ack {
+                                fmt: msgfmt.clone(),
+                                pow: msgpow.clone(),
+                                mid: msgids.clone(),
+                                ..Default::default()
+                            };
+                            res!(scmd.respond(&mut msgcmd, &self, &sessions, &src_addr, trg.clone()));
+                        },
+                        "rekeyreq" => {
+                            debug!(async_log::stream(), "REKEYREQ");
+                            let mut scmd: RekeyReq<ML, SL, UL, P::ID> = RekeyReq {
+                                fmt: msgfmt.clone(),
+                                pow: msgpow.clone(),
+                                mid: msgids.clone(),
+                                ..Default::default()
+                            };
+                            res!(scmd.respond(&mut msgcmd, &self, &sessions, &src_addr, trg.clone()));
+                        },
+                        "rekeyresp" => {
+                            debug!(async_log::stream(), "REKEYRESP");
+                            let mut scmd: RekeyResp<ML, SL, UL, P::ID> = RekeyResp {
+                                fmt: msgfmt.clone(),
+                                pow: msgpow.clone(),
+                                mid: msgids.clone(),
+                                ..Default::default()
+                            };
+                            res!(scmd.respond(&mut msgcmd, &self, &sessions, &src_addr, trg.clone()));
+                        },
+                        "keyrot" => {
+                            debug!(async_log::stream(), "KEYROT");
+                            if verified_sigpk.is_some() {
+                                debug!(async_log::stream(), "Dropping key rotation verified with \
+                                    an included key rather than the key on record.");
+                                return Ok(());
                             }
+                            let mut scmd: KeyRotate<ML, SL, UL, P::ID> = KeyRotate {
+                                fmt: msgfmt.clone(),
+                                pow: msgpow.clone(),
+                                mid: msgids.clone(),
+                                ..Default::default()
+                            };
+                            res!(scmd.respond(&mut msgcmd, &self, &sessions, &src_addr, trg.clone()));
+                        },
+                        "ping" => {
+                            debug!(async_log::stream(), "PING");
+                            let sigpk = match &verified_sigpk {
+                                Some(sigpk) => sigpk,
+                                None => {
+                                    debug!(async_log::stream(), "Dropping unsigned discovery message.");
+                                    return Ok(());
+                                },
+                            };
+                            let mut scmd: Ping<ML, SL, UL, P::ID> = Ping {
+                                fmt: msgfmt.clone(),
+                                pow: msgpow.clone(),
+                                mid: msgids.clone(),
+                                ..Default::default()
+                            };
+                            res!(scmd.respond(&mut msgcmd, &self, &sessions, &src_addr, trg.clone(), sigpk));
+                        },
+                        "pong" => {
+                            debug!(async_log::stream(), "PONG");
+                            let sigpk = match &verified_sigpk {
+                                Some(sigpk) => sigpk,
+                                None => {
+                                    debug!(async_log::stream(), "Dropping unsigned discovery message.");
+                                    return Ok(());
+                                },
+                            };
+                            let mut scmd: Pong<ML, SL, UL, P::ID> = Pong {
+                                fmt: msgfmt.clone(),
+                                pow: msgpow.clone(),
+                                mid: msgids.clone(),
+                                ..Default::default()
+                            };
+                            res!(scmd.respond(&mut msgcmd, &self, &sessions, &src_addr, trg.clone(), sigpk));
+                        },
+                        "findnode" => {
+                            debug!(async_log::stream(), "FINDNODE");
+                            let sigpk = match &verified_sigpk {
+                                Some(sigpk) => sigpk,
+                                None => {
+                                    debug!(async_log::stream(), "Dropping unsigned discovery message.");
+                                    return Ok(());
+                                },
+                            };
+                            let mut scmd: FindNode<ML, SL, UL, P::ID> = FindNode {
+                                fmt: msgfmt.clone(),
+                                pow: msgpow.clone(),
+                                mid: msgids.clone(),
+                                ..Default::default()
+                            };
+                            res!(scmd.respond(&mut msgcmd, &self, &sessions, &src_addr, trg.clone(), sigpk));
+                        },
+                        "peers" => {
+                            debug!(async_log::stream(), "PEERS");
+                            let sigpk = match &verified_sigpk {
+                                Some(sigpk) => sigpk,
+                                None => {
+                                    debug!(async_log::stream(), "Dropping unsigned discovery message.");
+                                    return Ok(());
+                                },
+                            };
+                            let mut scmd: Peers<ML, SL, UL, P::ID> = Peers {
+                                fmt: msgfmt.clone(),
+                                pow: msgpow.clone(),
+                                mid: msgids.clone(),
+                                ..Default::default()
+                            };
+                            res!(scmd.respond(&mut msgcmd, &self, &sessions, &src_addr, trg.clone(), sigpk));
                         },
-                    //    "hresp1" => {
-                    //        let mut scmd = syntax::HResp1 {
-                    //            fmt: msgfmt.clone(),
-                    //            mid: msgid.clone(),
-                    //            ..Default::default()
-                    //        };
-                    //        debug!(async_log::stream(), "hresp1 recvd");
-                    //        Ok(())
-                    //        //scmd.process(
-                    //        //    &mut msgcmd,
-                    //        //    &mut self.ugrd, // For user signing pk.
-                    //        //    // For sending.
-                    //        //    self.sock_addr.clone(),
-                    //        //    self.pack_size,
-                    //        //    &self.sock,
-                    //        //    &src_addr,
-                    //        //)
-                    //    },
-                    //    //"hreq2" => {
-                    //    //    let mut scmd = syntax::HReq1 {
-                    //    //        fmt: msgfmt.clone(),
-                    //    //        mid: msgid.clone(),
-                    //    //        ..Default::default()
-                    //    //    };
-                    //    //    // Each command type can implement its own
-                    //    //    // custom process method, which captures
-                    //    //    // only the parameters it needs.
-                    //    //    scmd.server_process(
-                    //    //        &mut msgcmd,
-                    //    //        &mut alog.data, // For pow parameters.
-                    //    //        &mut self.ugrd, // For user signing pk.
-                    //    //        // For sending HResp1.
-                    //    //        self.sock_addr.clone(),
-                    //    //        self.pack_size,
-                    //    //        &self.sock,
-                    //    //        &src_addr,
-                    //    //    )
-                    //    //},
                         _ => return Err(err!(
                             "Unrecognised message command '{}'.", cmd_name;
                             Bug, Unimplemented)),
                     }
                 }
+                // Only a message that has been handled is recorded as complete and acknowledged in
+                // full, so that the sender retransmits one that failed.  Acknowledge after
+                // responding, since a handshake response may change the proof of work code the
+                // peer requires of me.
+                if reliable {
+                    {
+                        let mut unlocked_reliable = lock_write!(self.reliable);
+                        unlocked_reliable.complete(
+                            src_addr,
+                            mid_byts,
+                            meta.chnk.num_chunks,
+                            Instant::now(),
+                        );
+                    }
+                    res!(self.send_ack(
+                        trg,
+                        &src_addr,
+                        &sessions,
+                        syntax,
+                        &meta.mid,
+                        meta.chnk.num_chunks,
+                        (0..meta.chnk.num_chunks).collect(),
+                    ));
+                }
             }, // Read payload.
             (true, _) => { // Drop the message completely.
                 res!(self.massembler.remove(&meta.mid));
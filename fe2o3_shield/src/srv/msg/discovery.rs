@@ -0,0 +1,607 @@
+//! Peer discovery messages.  These are exchanged without a session, so each includes the public
+//! signing key of the sender in its packet validator, and the sender is identified by the hash of
+//! the key that verified the packet.  See `crate::srv::discovery` for the routing table they
+//! maintain.
+use crate::{
+    srv::{
+        discovery::{
+            self,
+            Nonce,
+            PeerId,
+        },
+        msg::{
+            core::{
+                IdentifiedMessage,
+                IdTypes,
+                MsgType,
+                MsgFmt,
+                MsgIds,
+                MsgPow,
+            },
+            encode::ShieldCommand,
+            handshake::{
+                bytes_arg,
+                new_msg,
+            },
+            protocol::{
+                Protocol,
+                ProtocolTypes,
+            },
+        },
+        session::SessionMap,
+    },
+};
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    byte::IntoBytes,
+};
+use oxedyne_fe2o3_iop_crypto::{
+    keys::KeyManager,
+    sign::Signer,
+};
+use oxedyne_fe2o3_jdat::prelude::*;
+use oxedyne_fe2o3_syntax::{
+    msg::{
+        Msg,
+        MsgCmd,
+    },
+};
+
+use std::{
+    net::{
+        SocketAddr,
+        UdpSocket,
+    },
+    sync::Arc,
+    time::Instant,
+};
+
+
+#[repr(u16)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DiscoveryType {
+    Unknown     = 0,
+    Ping        = 9, // Follows the reliable delivery message types.
+    Pong        = 10,
+    FindNode    = 11,
+    Peers       = 12,
+}
+
+impl From<MsgType> for DiscoveryType {
+    fn from(u: MsgType) -> Self {
+        match u {
+            9 =>    Self::Ping,
+            10 =>   Self::Pong,
+            11 =>   Self::FindNode,
+            12 =>   Self::Peers,
+            _ =>    Self::Unknown,
+        }
+    }
+}
+
+fn nonce_from(byts: &[u8], src_addr: &SocketAddr) -> Outcome<Nonce> {
+    match <Nonce>::try_from(byts) {
+        Ok(nonce) => Ok(nonce),
+        Err(_) => Err(err!(
+            "Discovery nonce from {:?} has {} bytes, expecting {}.",
+            src_addr, byts.len(), discovery::NONCE_LEN;
+            Input, Invalid)),
+    }
+}
+
+// Ping ========================================================================
+/// Asks a peer to prove it is alive and holds the signing key it claims.
+#[derive(Clone, Debug, Default)]
+pub struct Ping<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+> {
+    pub fmt:    MsgFmt,
+    pub pow:    MsgPow,
+    pub mid:    MsgIds<SL, UL, ID::S, ID::U>,
+    // Command-specific
+    pub nonce:  Vec<u8>,
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    IntoBytes for Ping<ML, SL, UL, ID>
+{
+    fn into_bytes(self, buf: Vec<u8>) -> Outcome<Vec<u8>> {
+        res!(self.construct()).into_bytes(buf)
+    }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    IdentifiedMessage for Ping<ML, SL, UL, ID>
+{
+    fn typ(&self) -> MsgType { DiscoveryType::Ping as MsgType }
+    fn name(&self) -> &'static str { "ping" }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    ShieldCommand<ML, SL, UL, ID> for Ping<ML, SL, UL, ID>
+{
+    fn fmt(&self) -> &MsgFmt { &self.fmt }
+    fn pow(&self) -> &MsgPow { &self.pow }
+    fn mid(&self) -> &MsgIds<SL, UL, ID::S, ID::U> { &self.mid }
+    fn inc_sigpk(&self) -> bool { true }
+
+    fn construct(self) -> Outcome<Msg> {
+        let (mut msg, mut mcmd) = res!(new_msg(&self));
+        mcmd = res!(mcmd.add_arg_val("-nc", Some(Dat::BC64(self.nonce))));
+        msg = res!(msg.add_cmd(mcmd));
+        res!(msg.validate());
+        Ok(msg)
+    }
+
+    fn deconstruct(
+        &mut self,
+        mcmd: &mut MsgCmd,
+    )
+        -> Outcome<()>
+    {
+        self.nonce = res!(bytes_arg(mcmd, "-nc", "discovery nonce"));
+        Ok(())
+    }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    Ping<ML, SL, UL, ID>
+{
+    /// Record the sender, and answer with a `Pong` echoing the nonce.
+    pub fn respond<
+        const C: usize,
+        P: ProtocolTypes<ML, SL, UL, ID = ID> + 'static,
+    >(
+        &mut self,
+        mcmd:       &mut MsgCmd,
+        protocol:   &Protocol<C, ML, SL, UL, P>,
+        sessions:   &SessionMap<C, ML, SL, UL, ID>,
+        src_addr:   &SocketAddr,
+        trg:        Arc<UdpSocket>,
+        sigpk:      &[u8],
+    )
+        -> Outcome<()>
+    {
+        res!(self.deconstruct(mcmd));
+        let nonce = res!(nonce_from(&self.nonce, src_addr));
+        res!(protocol.set_peer_zbits(src_addr, self.pow.zbits));
+        let actions = {
+            let mut unlocked_discovery = lock_write!(protocol.discovery);
+            unlocked_discovery.observe(res!(discovery::peer_id(sigpk)), *src_addr, Instant::now())
+        };
+        let pong = Pong::<ML, SL, UL, ID> {
+            fmt:    self.fmt.clone(),
+            pow:    MsgPow { zbits: res!(protocol.request_zbits(src_addr)) },
+            mid:    MsgIds { sid_opt: None, uid: protocol.uid.clone() },
+            nonce:  nonce.to_vec(),
+        };
+        res!(protocol.dispatch(
+            pong,
+            trg.clone(),
+            src_addr,
+            res!(protocol.session_code(sessions, src_addr)),
+            res!(protocol.peer_zbits(src_addr)),
+        ));
+        protocol.discover(trg, sessions, self.fmt.syntax.clone(), actions)
+    }
+}
+
+// Pong ========================================================================
+/// Answers a `Ping`.
+#[derive(Clone, Debug, Default)]
+pub struct Pong<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+> {
+    pub fmt:    MsgFmt,
+    pub pow:    MsgPow,
+    pub mid:    MsgIds<SL, UL, ID::S, ID::U>,
+    // Command-specific
+    pub nonce:  Vec<u8>, // Echoes the nonce of the ping.
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    IntoBytes for Pong<ML, SL, UL, ID>
+{
+    fn into_bytes(self, buf: Vec<u8>) -> Outcome<Vec<u8>> {
+        res!(self.construct()).into_bytes(buf)
+    }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    IdentifiedMessage for Pong<ML, SL, UL, ID>
+{
+    fn typ(&self) -> MsgType { DiscoveryType::Pong as MsgType }
+    fn name(&self) -> &'static str { "pong" }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    ShieldCommand<ML, SL, UL, ID> for Pong<ML, SL, UL, ID>
+{
+    fn fmt(&self) -> &MsgFmt { &self.fmt }
+    fn pow(&self) -> &MsgPow { &self.pow }
+    fn mid(&self) -> &MsgIds<SL, UL, ID::S, ID::U> { &self.mid }
+    fn inc_sigpk(&self) -> bool { true }
+
+    fn construct(self) -> Outcome<Msg> {
+        let (mut msg, mut mcmd) = res!(new_msg(&self));
+        mcmd = res!(mcmd.add_arg_val("-nc", Some(Dat::BC64(self.nonce))));
+        msg = res!(msg.add_cmd(mcmd));
+        res!(msg.validate());
+        Ok(msg)
+    }
+
+    fn deconstruct(
+        &mut self,
+        mcmd: &mut MsgCmd,
+    )
+        -> Outcome<()>
+    {
+        self.nonce = res!(bytes_arg(mcmd, "-nc", "discovery nonce"));
+        Ok(())
+    }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    Pong<ML, SL, UL, ID>
+{
+    /// Record the sender if it answers one of my pings.
+    pub fn respond<
+        const C: usize,
+        P: ProtocolTypes<ML, SL, UL, ID = ID> + 'static,
+    >(
+        &mut self,
+        mcmd:       &mut MsgCmd,
+        protocol:   &Protocol<C, ML, SL, UL, P>,
+        sessions:   &SessionMap<C, ML, SL, UL, ID>,
+        src_addr:   &SocketAddr,
+        trg:        Arc<UdpSocket>,
+        sigpk:      &[u8],
+    )
+        -> Outcome<()>
+    {
+        res!(self.deconstruct(mcmd));
+        let nonce = res!(nonce_from(&self.nonce, src_addr));
+        res!(protocol.set_peer_zbits(src_addr, self.pow.zbits));
+        let actions = {
+            let mut unlocked_discovery = lock_write!(protocol.discovery);
+            unlocked_discovery.pong(
+                &nonce,
+                res!(discovery::peer_id(sigpk)),
+                *src_addr,
+                Instant::now(),
+            )
+        };
+        protocol.discover(trg, sessions, self.fmt.syntax.clone(), actions)
+    }
+}
+
+// FindNode ====================================================================
+/// Asks a peer for the peers it knows closest to the target identifier.
+#[derive(Clone, Debug, Default)]
+pub struct FindNode<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+> {
+    pub fmt:    MsgFmt,
+    pub pow:    MsgPow,
+    pub mid:    MsgIds<SL, UL, ID::S, ID::U>,
+    // Command-specific
+    pub nonce:  Vec<u8>,
+    pub target: Vec<u8>,
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    IntoBytes for FindNode<ML, SL, UL, ID>
+{
+    fn into_bytes(self, buf: Vec<u8>) -> Outcome<Vec<u8>> {
+        res!(self.construct()).into_bytes(buf)
+    }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    IdentifiedMessage for FindNode<ML, SL, UL, ID>
+{
+    fn typ(&self) -> MsgType { DiscoveryType::FindNode as MsgType }
+    fn name(&self) -> &'static str { "findnode" }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    ShieldCommand<ML, SL, UL, ID> for FindNode<ML, SL, UL, ID>
+{
+    fn fmt(&self) -> &MsgFmt { &self.fmt }
+    fn pow(&self) -> &MsgPow { &self.pow }
+    fn mid(&self) -> &MsgIds<SL, UL, ID::S, ID::U> { &self.mid }
+    fn inc_sigpk(&self) -> bool { true }
+
+    fn construct(self) -> Outcome<Msg> {
+        let (mut msg, mut mcmd) = res!(new_msg(&self));
+        mcmd = res!(mcmd.add_arg_val("-nc", Some(Dat::BC64(self.nonce))));
+        mcmd = res!(mcmd.add_arg_val("-tg", Some(Dat::BC64(self.target))));
+        msg = res!(msg.add_cmd(mcmd));
+        res!(msg.validate());
+        Ok(msg)
+    }
+
+    fn deconstruct(
+        &mut self,
+        mcmd: &mut MsgCmd,
+    )
+        -> Outcome<()>
+    {
+        self.nonce = res!(bytes_arg(mcmd, "-nc", "discovery nonce"));
+        self.target = res!(bytes_arg(mcmd, "-tg", "target peer id"));
+        Ok(())
+    }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    FindNode<ML, SL, UL, ID>
+{
+    /// Record the sender, and answer with a signed list of the peers I know closest to the
+    /// target.
+    pub fn respond<
+        const C: usize,
+        P: ProtocolTypes<ML, SL, UL, ID = ID> + 'static,
+    >(
+        &mut self,
+        mcmd:       &mut MsgCmd,
+        protocol:   &Protocol<C, ML, SL, UL, P>,
+        sessions:   &SessionMap<C, ML, SL, UL, ID>,
+        src_addr:   &SocketAddr,
+        trg:        Arc<UdpSocket>,
+        sigpk:      &[u8],
+    )
+        -> Outcome<()>
+    {
+        res!(self.deconstruct(mcmd));
+        let nonce = res!(nonce_from(&self.nonce, src_addr));
+        let target = match <PeerId>::try_from(&self.target[..]) {
+            Ok(target) => target,
+            Err(_) => return Err(err!(
+                "Target peer id from {:?} has {} bytes, expecting {}.",
+                src_addr, self.target.len(), discovery::PEER_ID_LEN;
+                Input, Invalid)),
+        };
+        res!(protocol.set_peer_zbits(src_addr, self.pow.zbits));
+        let id = res!(discovery::peer_id(sigpk));
+        let (actions, closest) = {
+            let mut unlocked_discovery = lock_write!(protocol.discovery);
+            let actions = unlocked_discovery.observe(id, *src_addr, Instant::now());
+            let k = unlocked_discovery.table.k();
+            let closest: Vec<(PeerId, SocketAddr)> = unlocked_discovery.table
+                .closest(&target, k + 1)
+                .into_iter()
+                .filter(|e| e.id != id)
+                .take(k)
+                .map(|e| (e.id, e.addr))
+                .collect();
+            (actions, closest)
+        };
+        let peers = res!(discovery::encode_peer_list(&closest));
+        let sig = res!(protocol.schms.sign.sign(
+            &discovery::peer_list_signed_bytes(&nonce, &peers)));
+        let response = Peers::<ML, SL, UL, ID> {
+            fmt:    self.fmt.clone(),
+            pow:    MsgPow { zbits: res!(protocol.request_zbits(src_addr)) },
+            mid:    MsgIds { sid_opt: None, uid: protocol.uid.clone() },
+            nonce:  nonce.to_vec(),
+            peers,
+            sig,
+        };
+        res!(protocol.dispatch(
+            response,
+            trg.clone(),
+            src_addr,
+            res!(protocol.session_code(sessions, src_addr)),
+            res!(protocol.peer_zbits(src_addr)),
+        ));
+        protocol.discover(trg, sessions, self.fmt.syntax.clone(), actions)
+    }
+}
+
+// Peers =======================================================================
+/// Answers a `FindNode` with a list of peer identifiers and addresses, signed over the nonce of
+/// the request.
+#[derive(Clone, Debug, Default)]
+pub struct Peers<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+> {
+    pub fmt:    MsgFmt,
+    pub pow:    MsgPow,
+    pub mid:    MsgIds<SL, UL, ID::S, ID::U>,
+    // Command-specific
+    pub nonce:  Vec<u8>, // Echoes the nonce of the request.
+    pub peers:  Vec<u8>, // Encoded peer list.
+    pub sig:    Vec<u8>, // Signature of the nonce and peer list.
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    IntoBytes for Peers<ML, SL, UL, ID>
+{
+    fn into_bytes(self, buf: Vec<u8>) -> Outcome<Vec<u8>> {
+        res!(self.construct()).into_bytes(buf)
+    }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    IdentifiedMessage for Peers<ML, SL, UL, ID>
+{
+    fn typ(&self) -> MsgType { DiscoveryType::Peers as MsgType }
+    fn name(&self) -> &'static str { "peers" }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    ShieldCommand<ML, SL, UL, ID> for Peers<ML, SL, UL, ID>
+{
+    fn fmt(&self) -> &MsgFmt { &self.fmt }
+    fn pow(&self) -> &MsgPow { &self.pow }
+    fn mid(&self) -> &MsgIds<SL, UL, ID::S, ID::U> { &self.mid }
+    fn inc_sigpk(&self) -> bool { true }
+
+    fn construct(self) -> Outcome<Msg> {
+        let (mut msg, mut mcmd) = res!(new_msg(&self));
+        mcmd = res!(mcmd.add_arg_val("-nc", Some(Dat::BC64(self.nonce))));
+        mcmd = res!(mcmd.add_arg_val("-pl", Some(Dat::BC64(self.peers))));
+        mcmd = res!(mcmd.add_arg_val("-sg", Some(Dat::BC64(self.sig))));
+        msg = res!(msg.add_cmd(mcmd));
+        res!(msg.validate());
+        Ok(msg)
+    }
+
+    fn deconstruct(
+        &mut self,
+        mcmd: &mut MsgCmd,
+    )
+        -> Outcome<()>
+    {
+        self.nonce = res!(bytes_arg(mcmd, "-nc", "discovery nonce"));
+        self.peers = res!(bytes_arg(mcmd, "-pl", "peer list"));
+        self.sig = res!(bytes_arg(mcmd, "-sg", "peer list signature"));
+        Ok(())
+    }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    Peers<ML, SL, UL, ID>
+{
+    /// Verify the list signature using the key that verified the packet, and accept the list if
+    /// it answers one of my requests.
+    pub fn respond<
+        const C: usize,
+        P: ProtocolTypes<ML, SL, UL, ID = ID> + 'static,
+    >(
+        &mut self,
+        mcmd:       &mut MsgCmd,
+        protocol:   &Protocol<C, ML, SL, UL, P>,
+        sessions:   &SessionMap<C, ML, SL, UL, ID>,
+        src_addr:   &SocketAddr,
+        trg:        Arc<UdpSocket>,
+        sigpk:      &[u8],
+    )
+        -> Outcome<()>
+    {
+        res!(self.deconstruct(mcmd));
+        let nonce = res!(nonce_from(&self.nonce, src_addr));
+        let expected = {
+            let unlocked_discovery = lock_read!(protocol.discovery);
+            unlocked_discovery.awaiting_peers(&nonce, src_addr)
+        };
+        if !expected {
+            debug!(async_log::stream(), "Dropping unsolicited peer list from {:?}.", src_addr);
+            return Ok(());
+        }
+        let verifier = res!(protocol.schms.sign.clone_with_keys(Some(sigpk), None));
+        let signed = discovery::peer_list_signed_bytes(&nonce, &self.peers);
+        if !res!(verifier.verify(&signed, &self.sig)) {
+            debug!(async_log::stream(), "Dropping peer list from {:?} with an invalid \
+                signature.", src_addr);
+            return Ok(());
+        }
+        let peers = res!(discovery::decode_peer_list(&self.peers));
+        res!(protocol.set_peer_zbits(src_addr, self.pow.zbits));
+        let actions = {
+            let mut unlocked_discovery = lock_write!(protocol.discovery);
+            let now = Instant::now();
+            let mut actions = unlocked_discovery.observe(
+                res!(discovery::peer_id(sigpk)),
+                *src_addr,
+                now,
+            );
+            actions.append(&mut unlocked_discovery.found(&nonce, src_addr, peers, now));
+            actions
+        };
+        protocol.discover(trg, sessions, self.fmt.syntax.clone(), actions)
+    }
+}
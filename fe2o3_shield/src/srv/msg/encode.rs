@@ -116,9 +116,10 @@ pub trait ShieldCommand<
         src_addr:   IpAddr,
         trg_addr:   IpAddr,
         code:       [u8; C],
+        zbits:      ZeroBits, // Difficulty of the proof of work required by the target.
         schms:      WireSchemes<W>,
     )
-        -> Outcome<Vec<Vec<u8>>>
+        -> Outcome<(ID::M, Vec<Vec<u8>>)>
     {
         // Copy some self parameters before consumption by into_bytes
         let msg_name = self.name();
@@ -128,7 +129,6 @@ pub trait ShieldCommand<
 
         let uid = self.uid().clone();
 
-        let zbits = self.pow_zbits();
         let typ = self.typ();
         let msg_byts = res!(self.into_bytes(Vec::new()));
         //let tstamp = res!(SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)).as_secs();
@@ -227,7 +227,7 @@ pub trait ShieldCommand<
         if let Some(warning) = warning {
             warn!(async_log::stream(), "{}", warning);
         }
-        Ok(packets)
+        Ok((mid, packets))
     }
 
     fn send_udp(
@@ -243,19 +243,22 @@ pub trait ShieldCommand<
         Ok(())
     }
 
-    fn send<
+    /// Build the packets for the message to the given target, returning them with the message
+    /// id.
+    fn packets<
         const C: usize,
         W: WireSchemeTypes + 'static,
     >(
         self,
-        src:        Arc<UdpSocket>,
+        src:        &UdpSocket,
         trg_addr:   &SocketAddr,
         code:       [u8; C],
+        zbits:      ZeroBits,
         schms:      WireSchemes<W>,
     )
-        -> Outcome<()>
+        -> Outcome<(ID::M, Vec<Vec<u8>>)>
     {
-        let packets = res!(self.build::<
+        self.build::<
             C,
             {constant::POW_INPUT_LEN},      // N
             {constant::POW_PREFIX_LEN},     // P0
@@ -270,8 +273,25 @@ pub trait ShieldCommand<
             res!(src.local_addr()).ip(),
             trg_addr.ip(),
             code,
+            zbits,
             schms,
-        ));
+        )
+    }
+
+    fn send<
+        const C: usize,
+        W: WireSchemeTypes + 'static,
+    >(
+        self,
+        src:        Arc<UdpSocket>,
+        trg_addr:   &SocketAddr,
+        code:       [u8; C],
+        zbits:      ZeroBits,
+        schms:      WireSchemes<W>,
+    )
+        -> Outcome<()>
+    {
+        let (_, packets) = res!(self.packets::<C, W>(&src, trg_addr, code, zbits, schms));
         for packet in packets {
             res!(src.send_to(&packet, trg_addr));
         }
@@ -1,8 +1,8 @@
 //!
 //!```ignore
 //!
-//!   Handshake messages - all single-packet
-//!   --------------------------------------
+//!   Handshake messages
+//!   ------------------
 //!   HReq1 = first handshake request
 //!   HReq2 = second handshake request
 //!   HReq3 = third and final handshake request                                                                      
@@ -136,16 +136,39 @@ use crate::{
                 MsgPow,
             },
             encode::ShieldCommand,
+            protocol::{
+                Protocol,
+                ProtocolTypes,
+            },
+        },
+        session::{
+            Session,
+            SessionMap,
+            SessionState,
         },
-        guard::data::AddressData,
     },
 };
 
 use oxedyne_fe2o3_core::{
     prelude::*,
-    byte::IntoBytes,
+    byte::{
+        FromBytes,
+        IntoBytes,
+        ToByteArray,
+    },
     mem::Extract,
+    rand::RanDef,
+};
+use oxedyne_fe2o3_crypto::{
+    enc::EncryptionScheme,
+    kem::KeyExchangeScheme,
+};
+use oxedyne_fe2o3_hash::hash::HashScheme;
+use oxedyne_fe2o3_iop_crypto::{
+    enc::Encrypter,
+    kem::KeyExchanger,
 };
+use oxedyne_fe2o3_iop_hash::api::Hasher;
 use oxedyne_fe2o3_jdat::prelude::*;
 use oxedyne_fe2o3_syntax::{
     msg::{
@@ -156,8 +179,12 @@ use oxedyne_fe2o3_syntax::{
 use oxedyne_fe2o3_text::string::Stringer;
 
 use std::{
-    net::UdpSocket,
+    net::{
+        SocketAddr,
+        UdpSocket,
+    },
     sync::Arc,
+    time::Instant,
 };
 
 
@@ -204,7 +231,10 @@ impl HandshakeType {
 /// peer keys, only the current version.  If Y has no existing record of spx, X is unknown to Y
 /// and its policy regarding unknown users will determine whether the handshake continues.  If
 /// the spx included by X does not match the version kept by Y, Y responds by sending the old spx
-/// and asking for an authentic signature in HReq2.
+/// and asking for an authentic signature in HReq2.  A peer that changes its key while sessions
+/// are established announces the new key in a `KeyRotate` (see [`crate::srv::msg::rekey`]),
+/// signed with both keys, so that its peers already have the new key on record when it next
+/// sends a HReq1.
 ///
 /// Y is free to set its difficulty and code requirements for incoming proofs of work.  For
 /// example, required difficulty could increase quickly and significantly  when the incoming
@@ -315,60 +345,157 @@ impl<
 >
     HReq1<ML, SL, UL, ID>
 {
-    pub fn respond(
+    /// Y records the zero bits required by X, issues X with a fresh proof of work code to use for
+    /// all subsequent packets, opens a session and replies with a HResp1.  A HReq1 is ignored
+    /// while a session with X is established, and when X is a known user signing with a key
+    /// other than the one on record, so that neither can be used to reset the session or the
+    /// code of another user.
+    pub fn respond<
+        const C: usize,
+        P: ProtocolTypes<ML, SL, UL, ID = ID> + 'static,
+    >(
         &mut self,
         mcmd:       &mut MsgCmd,
-        adata:      &mut AddressData, // For pow parameters.
-        //mut udata:  &mut UserData<{ constant::POW_CODE_LEN }>, // For pow parameters.
-        //ugrd:       &mut UserGuard<IdDat, UserData>, // For user signing pk.
-        //// For sending HResp1.
-        //src_addr:   &SocketAddr,
-        //chunker:    Chunker,
-        ////pack_size:  usize,
-        _src:       Arc<UdpSocket>,
-        //trg_addr:   &SocketAddr,
+        protocol:   &Protocol<C, ML, SL, UL, P>,
+        sessions:   &SessionMap<C, ML, SL, UL, ID>,
+        src_addr:   &SocketAddr,
+        trg:        Arc<UdpSocket>,
+        sigpk:      &[u8],
     )
         -> Outcome<()>
     {
         res!(self.deconstruct(mcmd)); // We now have all command-specific data.
-        adata.your_zbits = self.pow.zbits;
-        debug!(async_log::stream(), "Yay it worked!");
-        //// Create a fresh pow code and assign to the source address.
-        //let mut code = [0u8; constant::POW_CODE_LEN];
-        //Rand::fill_u8(&mut code);
-        //let pow_code = code.to_vec();
-        //adata.apow_code = Some(pow_code.clone());
-        //// Request transmission of signing key?
-        //let req_send_key = match ugrd.get_user_log_mut(&self.uid()) {
-        //    Some(ulog) => {
-        //        if ulog.data.sigpk.is_none() {
-        //            ulog.data.waiting_for_sigpk = true;
-        //            true
-        //        } else {
-        //            ulog.data.waiting_for_sigpk = false;
-        //            false
-        //        }
-        //    },
-        //    None => true,
-        //};
-        //let response = HResp1 {
-        //    fmt: self.fmt().clone(),
-        //    pow: self.pow().clone(),
-        //    mid: self.mid().clone(),
-        //    req_send_key,
-        //};
-        //debug!(async_log::stream(), "Sending hresp1: {}", res!(response.clone().construct()));
-        //response.send(
-        //    src_addr,
-        //    chunker,
-        //    &src_sock,
-        //    &trg_addr,
-        //)
-        Ok(())
+        {
+            let unlocked_sessions = lock_read!(sessions);
+            if let Some(session) = unlocked_sessions.get(src_addr) {
+                if session.is_established() {
+                    debug!(async_log::stream(), "Dropping hreq1 from {:?}, with which a session \
+                        is already established.", src_addr);
+                    return Ok(());
+                }
+            }
+        }
+        {
+            let uid = self.uid();
+            let (ukey, locked_umap) = res!(protocol.ugrd.get_locked_map(&uid));
+            let unlocked_umap = lock_read!(locked_umap);
+            if let Some(ulog) = unlocked_umap.get(&ukey) {
+                if let Some(sigtpk) = &ulog.data.sigtpk_opt {
+                    if sigtpk.key != sigpk {
+                        debug!(async_log::stream(), "Dropping hreq1 from {:?} for user {:?}, \
+                            signed with a key other than the one on record.", src_addr, uid);
+                        return Ok(());
+                    }
+                }
+            }
+        }
+        res!(protocol.set_peer_zbits(src_addr, self.pow.zbits));
+        // Create a fresh pow code for the user, required in all subsequent packets.
+        let code = res!(protocol.new_user_code(&self.uid()));
+        let mut session = Session::new(SessionState::AwaitHReq2, false);
+        session.peer_uid = Some(self.uid());
+        let peer_code = session.code; // I don't yet know the code you require.
+        {
+            let mut unlocked_sessions = lock_write!(sessions);
+            unlocked_sessions.insert(*src_addr, session);
+        }
+        let response = HResp1::<ML, SL, UL, ID> {
+            fmt:        self.fmt.clone(),
+            pow:        MsgPow { zbits: res!(protocol.request_zbits(src_addr)) },
+            mid:        MsgIds { sid_opt: None, uid: protocol.uid.clone() },
+            pow_code:   code.to_vec(),
+            peer_sigpk: None,
+        };
+        protocol.dispatch(
+            response,
+            trg,
+            src_addr,
+            peer_code,
+            res!(protocol.peer_zbits(src_addr)),
+        )
+    }
+}
+
+/// Implements the traits common to all handshake messages.
+macro_rules! impl_handshake_message {
+    ($msg:ident, $typ:ident, $name:literal) => {
+        impl<
+            const ML: usize,
+            const SL: usize,
+            const UL: usize,
+            ID: IdTypes<ML, SL, UL>,
+        >
+            IntoBytes for $msg<ML, SL, UL, ID>
+        {
+            fn into_bytes(self, buf: Vec<u8>) -> Outcome<Vec<u8>> {
+                res!(self.construct()).into_bytes(buf)
+            }
+        }
+
+        impl<
+            const ML: usize,
+            const SL: usize,
+            const UL: usize,
+            ID: IdTypes<ML, SL, UL>,
+        >
+            IdentifiedMessage for $msg<ML, SL, UL, ID>
+        {
+            fn typ(&self) -> MsgType { HandshakeType::$typ as MsgType }
+            fn name(&self) -> &'static str { $name }
+        }
+    };
+}
+
+/// Start a message with the message level arguments.
+pub fn new_msg<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+    M: ShieldCommand<ML, SL, UL, ID>,
+>(
+    scmd: &M,
+)
+    -> Outcome<(Msg, MsgCmd)>
+{
+    let mut msg = Msg::new(scmd.syntax().clone()); // cloning ref
+    msg.set_encoding(*scmd.encoding());
+    if let Some(sid) = scmd.sid_opt() {
+        msg = res!(msg.add_arg_val("-s", Some(res!(sid.to_dat()))));
+    }
+    msg = res!(msg.add_arg_val("-zb", Some(dat!(scmd.pow_zbits()))));
+    let mcmd = res!(msg.new_cmd(scmd.name()));
+    Ok((msg, mcmd))
+}
+
+/// Extract the bytes of a required command argument.
+pub fn bytes_arg(
+    mcmd:   &mut MsgCmd,
+    arg:    &str,
+    desc:   &str,
+)
+    -> Outcome<Vec<u8>>
+{
+    match mcmd.get_arg_vals_mut(arg) {
+        Some(vals) => Ok(try_extract_dat!(vals[0].extract(), BC64)),
+        None => Err(err!(
+            "Expected {} ({}) in command '{}'.", desc, arg, mcmd.name;
+            Input, Missing)),
     }
 }
 
+/// The handshake is confirmed by each peer encrypting a hash of the session id, with a label
+/// distinguishing the request from the response.
+fn confirmation(label: &[u8], sid_byts: &[u8]) -> Vec<u8> {
+    HashScheme::new_sha3_256().hash(&[label, sid_byts], []).as_vec()
+}
+
+const HREQ3_LABEL: &[u8] = b"hreq3";
+const HRESP3_LABEL: &[u8] = b"hresp3";
+
 // HResp1 ======================================================================
+/// Y accepts the request, and provides X with the proof of work code it must use.  Y includes
+/// its public signature key in the packet validator, which X records.
 #[derive(Clone, Debug, Default)]
 pub struct HResp1<
     const ML: usize,
@@ -380,162 +507,590 @@ pub struct HResp1<
     pub pow:        MsgPow,
     pub mid:        MsgIds<SL, UL, ID::S, ID::U>,
     // Command-specific
-    pub send_key:   bool,
+    pub pow_code:   Vec<u8>, // Code expected from you.
+    pub peer_sigpk: Option<Vec<u8>>, // Your version of my signature public key.
+}
+
+impl_handshake_message!(HResp1, Resp1, "hresp1");
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    ShieldCommand<ML, SL, UL, ID> for HResp1<ML, SL, UL, ID>
+{
+    fn fmt(&self) -> &MsgFmt { &self.fmt }
+    fn pow(&self) -> &MsgPow { &self.pow }
+    fn mid(&self) -> &MsgIds<SL, UL, ID::S, ID::U> { &self.mid }
+    fn inc_sigpk(&self) -> bool { true }
+
+    fn construct(self) -> Outcome<Msg> {
+        let (mut msg, mut mcmd) = res!(new_msg(&self));
+        mcmd = res!(mcmd.add_arg_val("-pc", Some(Dat::BC64(self.pow_code))));
+        if let Some(sigpk) = self.peer_sigpk {
+            mcmd = res!(mcmd.add_arg_val("-yppsk", Some(Dat::BC64(sigpk))));
+        }
+        msg = res!(msg.add_cmd(mcmd));
+        res!(msg.validate());
+        Ok(msg)
+    }
+
+    fn deconstruct(
+        &mut self,
+        mcmd: &mut MsgCmd,
+    )
+        -> Outcome<()>
+    {
+        self.pow_code = res!(bytes_arg(mcmd, "-pc", "proof of work code"));
+        self.peer_sigpk = match mcmd.get_arg_vals_mut("-yppsk") {
+            Some(vals) => Some(try_extract_dat!(vals[0].extract(), BC64)),
+            None => None,
+        };
+        Ok(())
+    }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    HResp1<ML, SL, UL, ID>
+{
+    /// X adopts the code required by Y, issues Y with a code of its own, generates a key exchange
+    /// key pair for the session and sends the public key in a HReq2.
+    pub fn respond<
+        const C: usize,
+        P: ProtocolTypes<ML, SL, UL, ID = ID> + 'static,
+    >(
+        &mut self,
+        mcmd:       &mut MsgCmd,
+        protocol:   &Protocol<C, ML, SL, UL, P>,
+        sessions:   &SessionMap<C, ML, SL, UL, ID>,
+        src_addr:   &SocketAddr,
+        trg:        Arc<UdpSocket>,
+    )
+        -> Outcome<()>
+    {
+        res!(self.deconstruct(mcmd));
+        let peer_code = res!(<[u8; C]>::try_from(&self.pow_code[..]), Decode, Bytes);
+        let kem = KeyExchangeScheme::new_firesaber();
+        let kem_pk = match kem.get_public_key() {
+            Some(pk) => pk.to_vec(),
+            None => return Err(err!(
+                "New key exchange scheme is missing a public key."; Bug, Missing)),
+        };
+        {
+            let mut unlocked_sessions = lock_write!(sessions);
+            match unlocked_sessions.get_mut(src_addr) {
+                Some(session) if session.state == SessionState::AwaitHResp1 => {
+                    session.peer_uid = Some(self.uid());
+                    session.code = peer_code;
+                    session.kem_opt = Some(kem);
+                    session.state = SessionState::AwaitHResp2;
+                },
+                _ => {
+                    debug!(async_log::stream(), "Dropping unexpected hresp1 from {:?}.", src_addr);
+                    return Ok(());
+                },
+            }
+        }
+        res!(protocol.set_peer_zbits(src_addr, self.pow.zbits));
+        let code = res!(protocol.new_user_code(&self.uid()));
+        let request = HReq2::<ML, SL, UL, ID> {
+            fmt:        self.fmt.clone(),
+            pow:        MsgPow { zbits: res!(protocol.request_zbits(src_addr)) },
+            mid:        MsgIds { sid_opt: None, uid: protocol.uid.clone() },
+            pow_code:   code.to_vec(),
+            kem_pk,
+        };
+        protocol.dispatch(
+            request,
+            trg,
+            src_addr,
+            peer_code,
+            res!(protocol.peer_zbits(src_addr)),
+        )
+    }
+}
+
+// HReq2 =======================================================================
+/// X provides Y with the proof of work code it must use, and a public key for the key exchange.
+/// The packet proofs of work now use the code issued by Y, authenticating X as the user Y
+/// responded to.
+#[derive(Clone, Debug, Default)]
+pub struct HReq2<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+> {
+    pub fmt:        MsgFmt,
+    pub pow:        MsgPow,
+    pub mid:        MsgIds<SL, UL, ID::S, ID::U>,
+    // Command-specific
+    pub pow_code:   Vec<u8>, // Code expected from you.
+    pub kem_pk:     Vec<u8>, // Key exchange public key.
+}
+
+impl_handshake_message!(HReq2, Req2, "hreq2");
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    ShieldCommand<ML, SL, UL, ID> for HReq2<ML, SL, UL, ID>
+{
+    fn fmt(&self) -> &MsgFmt { &self.fmt }
+    fn pow(&self) -> &MsgPow { &self.pow }
+    fn mid(&self) -> &MsgIds<SL, UL, ID::S, ID::U> { &self.mid }
+    fn inc_sigpk(&self) -> bool { false }
+
+    fn construct(self) -> Outcome<Msg> {
+        let (mut msg, mut mcmd) = res!(new_msg(&self));
+        mcmd = res!(mcmd.add_arg_val("-pc", Some(Dat::BC64(self.pow_code))));
+        mcmd = res!(mcmd.add_arg_val("-kpk", Some(Dat::BC64(self.kem_pk))));
+        msg = res!(msg.add_cmd(mcmd));
+        res!(msg.validate());
+        Ok(msg)
+    }
+
+    fn deconstruct(
+        &mut self,
+        mcmd: &mut MsgCmd,
+    )
+        -> Outcome<()>
+    {
+        self.pow_code = res!(bytes_arg(mcmd, "-pc", "proof of work code"));
+        self.kem_pk = res!(bytes_arg(mcmd, "-kpk", "key exchange public key"));
+        Ok(())
+    }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    HReq2<ML, SL, UL, ID>
+{
+    /// Y encapsulates a fresh session key using the public key from X, creates a session id and
+    /// replies with a HResp2 carrying the encapsulated key and the encrypted session id.
+    pub fn respond<
+        const C: usize,
+        P: ProtocolTypes<ML, SL, UL, ID = ID> + 'static,
+    >(
+        &mut self,
+        mcmd:       &mut MsgCmd,
+        protocol:   &Protocol<C, ML, SL, UL, P>,
+        sessions:   &SessionMap<C, ML, SL, UL, ID>,
+        src_addr:   &SocketAddr,
+        trg:        Arc<UdpSocket>,
+    )
+        -> Outcome<()>
+    {
+        res!(self.deconstruct(mcmd));
+        let peer_code = res!(<[u8; C]>::try_from(&self.pow_code[..]), Decode, Bytes);
+        let kem_pk = res!(
+            <[u8; KeyExchangeScheme::FIRESABER_PK_LEN]>::try_from(&self.kem_pk[..]),
+            Decode, Bytes,
+        );
+        let kem = res!(KeyExchangeScheme::new_firesaber_with_keys(Some(&kem_pk), None));
+        let (sess_key, kem_ct) = res!(kem.encap::<
+            {KeyExchangeScheme::FIRESABER_PK_LEN},
+            {KeyExchangeScheme::FIRESABER_SESSION_KEY_LEN},
+            {KeyExchangeScheme::FIRESABER_CIPHERTEXT_LEN},
+        >(kem_pk));
+        let enc = res!(EncryptionScheme::new_aes_256_gcm_with_key(&sess_key));
+        let sid = ID::S::randef();
+        let sid_enc = res!(enc.encrypt(&sid.to_byte_array()));
+        {
+            let mut unlocked_sessions = lock_write!(sessions);
+            match unlocked_sessions.get_mut(src_addr) {
+                Some(session) if
+                    session.state == SessionState::AwaitHReq2
+                    && session.peer_uid == Some(self.uid()) =>
+                {
+                    session.code = peer_code;
+                    session.sid_opt = Some(sid);
+                    session.enc_opt = Some(enc);
+                    session.state = SessionState::AwaitHReq3;
+                },
+                _ => {
+                    debug!(async_log::stream(), "Dropping unexpected hreq2 from {:?}.", src_addr);
+                    return Ok(());
+                },
+            }
+        }
+        let response = HResp2::<ML, SL, UL, ID> {
+            fmt:        self.fmt.clone(),
+            pow:        MsgPow { zbits: res!(protocol.request_zbits(src_addr)) },
+            mid:        MsgIds { sid_opt: None, uid: protocol.uid.clone() },
+            kem_ct:     kem_ct.to_vec(),
+            sid_enc,
+        };
+        protocol.dispatch(
+            response,
+            trg,
+            src_addr,
+            peer_code,
+            res!(protocol.peer_zbits(src_addr)),
+        )
+    }
+}
+
+// HResp2 ======================================================================
+/// Y sends X the encapsulated session key, and the session id encrypted with it.
+#[derive(Clone, Debug, Default)]
+pub struct HResp2<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+> {
+    pub fmt:        MsgFmt,
+    pub pow:        MsgPow,
+    pub mid:        MsgIds<SL, UL, ID::S, ID::U>,
+    // Command-specific
+    pub kem_ct:     Vec<u8>, // Encapsulated session key.
+    pub sid_enc:    Vec<u8>, // Encrypted session id.
+}
+
+impl_handshake_message!(HResp2, Resp2, "hresp2");
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    ShieldCommand<ML, SL, UL, ID> for HResp2<ML, SL, UL, ID>
+{
+    fn fmt(&self) -> &MsgFmt { &self.fmt }
+    fn pow(&self) -> &MsgPow { &self.pow }
+    fn mid(&self) -> &MsgIds<SL, UL, ID::S, ID::U> { &self.mid }
+    fn inc_sigpk(&self) -> bool { false }
+
+    fn construct(self) -> Outcome<Msg> {
+        let (mut msg, mut mcmd) = res!(new_msg(&self));
+        mcmd = res!(mcmd.add_arg_val("-sk", Some(Dat::BC64(self.kem_ct))));
+        mcmd = res!(mcmd.add_arg_val("-es", Some(Dat::BC64(self.sid_enc))));
+        msg = res!(msg.add_cmd(mcmd));
+        res!(msg.validate());
+        Ok(msg)
+    }
+
+    fn deconstruct(
+        &mut self,
+        mcmd: &mut MsgCmd,
+    )
+        -> Outcome<()>
+    {
+        self.kem_ct = res!(bytes_arg(mcmd, "-sk", "encapsulated session key"));
+        self.sid_enc = res!(bytes_arg(mcmd, "-es", "encrypted session id"));
+        Ok(())
+    }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    HResp2<ML, SL, UL, ID>
+{
+    /// X decapsulates the session key, decrypts the session id and confirms both in a HReq3.
+    pub fn respond<
+        const C: usize,
+        P: ProtocolTypes<ML, SL, UL, ID = ID> + 'static,
+    >(
+        &mut self,
+        mcmd:       &mut MsgCmd,
+        protocol:   &Protocol<C, ML, SL, UL, P>,
+        sessions:   &SessionMap<C, ML, SL, UL, ID>,
+        src_addr:   &SocketAddr,
+        trg:        Arc<UdpSocket>,
+    )
+        -> Outcome<()>
+    {
+        res!(self.deconstruct(mcmd));
+        let kem_ct = res!(
+            <[u8; KeyExchangeScheme::FIRESABER_CIPHERTEXT_LEN]>::try_from(&self.kem_ct[..]),
+            Decode, Bytes,
+        );
+        let (peer_code, confirm) = {
+            let mut unlocked_sessions = lock_write!(sessions);
+            match unlocked_sessions.get_mut(src_addr) {
+                Some(session) if
+                    session.state == SessionState::AwaitHResp2
+                    && session.peer_uid == Some(self.uid()) =>
+                {
+                    let kem = match session.kem_opt.take() {
+                        Some(kem) => kem,
+                        None => return Err(err!(
+                            "Session with {:?} is missing its key exchange scheme.", src_addr;
+                            Bug, Missing)),
+                    };
+                    let sess_key = res!(kem.decap::<
+                        {KeyExchangeScheme::FIRESABER_SESSION_KEY_LEN},
+                        {KeyExchangeScheme::FIRESABER_CIPHERTEXT_LEN},
+                    >(kem_ct));
+                    let enc = res!(EncryptionScheme::new_aes_256_gcm_with_key(&sess_key));
+                    let sid_byts = res!(enc.decrypt(&self.sid_enc));
+                    let (sid, _) = res!(ID::S::from_bytes(&sid_byts));
+                    let confirm = res!(enc.encrypt(&confirmation(HREQ3_LABEL, &sid_byts)));
+                    session.sid_opt = Some(sid);
+                    session.enc_opt = Some(enc);
+                    session.state = SessionState::AwaitHResp3;
+                    (session.code, confirm)
+                },
+                _ => {
+                    debug!(async_log::stream(), "Dropping unexpected hresp2 from {:?}.", src_addr);
+                    return Ok(());
+                },
+            }
+        };
+        let request = HReq3::<ML, SL, UL, ID> {
+            fmt:        self.fmt.clone(),
+            pow:        MsgPow { zbits: res!(protocol.request_zbits(src_addr)) },
+            mid:        MsgIds { sid_opt: None, uid: protocol.uid.clone() },
+            confirm,
+        };
+        protocol.dispatch(
+            request,
+            trg,
+            src_addr,
+            peer_code,
+            res!(protocol.peer_zbits(src_addr)),
+        )
+    }
+}
+
+// HReq3 =======================================================================
+/// X confirms possession of the session key and id by sending an encrypted hash of the id.
+#[derive(Clone, Debug, Default)]
+pub struct HReq3<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+> {
+    pub fmt:        MsgFmt,
+    pub pow:        MsgPow,
+    pub mid:        MsgIds<SL, UL, ID::S, ID::U>,
+    // Command-specific
+    pub confirm:    Vec<u8>, // Encrypted session id hash.
+}
+
+impl_handshake_message!(HReq3, Req3, "hreq3");
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    ShieldCommand<ML, SL, UL, ID> for HReq3<ML, SL, UL, ID>
+{
+    fn fmt(&self) -> &MsgFmt { &self.fmt }
+    fn pow(&self) -> &MsgPow { &self.pow }
+    fn mid(&self) -> &MsgIds<SL, UL, ID::S, ID::U> { &self.mid }
+    fn inc_sigpk(&self) -> bool { false }
+
+    fn construct(self) -> Outcome<Msg> {
+        let (mut msg, mut mcmd) = res!(new_msg(&self));
+        mcmd = res!(mcmd.add_arg_val("-cf", Some(Dat::BC64(self.confirm))));
+        msg = res!(msg.add_cmd(mcmd));
+        res!(msg.validate());
+        Ok(msg)
+    }
+
+    fn deconstruct(
+        &mut self,
+        mcmd: &mut MsgCmd,
+    )
+        -> Outcome<()>
+    {
+        self.confirm = res!(bytes_arg(mcmd, "-cf", "handshake confirmation"));
+        Ok(())
+    }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    HReq3<ML, SL, UL, ID>
+{
+    /// Y checks the confirmation from X, establishes the session and sends its own confirmation
+    /// in a HResp3.
+    pub fn respond<
+        const C: usize,
+        P: ProtocolTypes<ML, SL, UL, ID = ID> + 'static,
+    >(
+        &mut self,
+        mcmd:       &mut MsgCmd,
+        protocol:   &Protocol<C, ML, SL, UL, P>,
+        sessions:   &SessionMap<C, ML, SL, UL, ID>,
+        src_addr:   &SocketAddr,
+        trg:        Arc<UdpSocket>,
+    )
+        -> Outcome<()>
+    {
+        res!(self.deconstruct(mcmd));
+        let (peer_code, confirm) = {
+            let mut unlocked_sessions = lock_write!(sessions);
+            match unlocked_sessions.get_mut(src_addr) {
+                Some(session) if
+                    session.state == SessionState::AwaitHReq3
+                    && session.peer_uid == Some(self.uid()) =>
+                {
+                    let (enc, sid_byts) = match (&session.enc_opt, &session.sid_opt) {
+                        (Some(enc), Some(sid)) => (enc, sid.to_byte_array()),
+                        _ => return Err(err!(
+                            "Session with {:?} is missing its key or id.", src_addr;
+                            Bug, Missing)),
+                    };
+                    if res!(enc.decrypt(&self.confirm)) != confirmation(HREQ3_LABEL, &sid_byts) {
+                        unlocked_sessions.remove(src_addr);
+                        return Err(err!(
+                            "Handshake confirmation from {:?} does not match.", src_addr;
+                            Invalid, Input));
+                    }
+                    let confirm = res!(enc.encrypt(&confirmation(HRESP3_LABEL, &sid_byts)));
+                    session.state = SessionState::Established;
+                    session.active = Instant::now();
+                    (session.code, confirm)
+                },
+                _ => {
+                    debug!(async_log::stream(), "Dropping unexpected hreq3 from {:?}.", src_addr);
+                    return Ok(());
+                },
+            }
+        };
+        info!(async_log::stream(), "Session established with {:?}.", src_addr);
+        let response = HResp3::<ML, SL, UL, ID> {
+            fmt:        self.fmt.clone(),
+            pow:        MsgPow { zbits: res!(protocol.request_zbits(src_addr)) },
+            mid:        MsgIds { sid_opt: None, uid: protocol.uid.clone() },
+            confirm,
+        };
+        protocol.dispatch(
+            response,
+            trg,
+            src_addr,
+            peer_code,
+            res!(protocol.peer_zbits(src_addr)),
+        )
+    }
+}
+
+// HResp3 ======================================================================
+/// Y confirms the session from its end, after which both peers can send session messages.
+#[derive(Clone, Debug, Default)]
+pub struct HResp3<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+> {
+    pub fmt:        MsgFmt,
+    pub pow:        MsgPow,
+    pub mid:        MsgIds<SL, UL, ID::S, ID::U>,
+    // Command-specific
+    pub confirm:    Vec<u8>, // Encrypted session id hash.
+}
+
+impl_handshake_message!(HResp3, Resp3, "hresp3");
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    ShieldCommand<ML, SL, UL, ID> for HResp3<ML, SL, UL, ID>
+{
+    fn fmt(&self) -> &MsgFmt { &self.fmt }
+    fn pow(&self) -> &MsgPow { &self.pow }
+    fn mid(&self) -> &MsgIds<SL, UL, ID::S, ID::U> { &self.mid }
+    fn inc_sigpk(&self) -> bool { false }
+
+    fn construct(self) -> Outcome<Msg> {
+        let (mut msg, mut mcmd) = res!(new_msg(&self));
+        mcmd = res!(mcmd.add_arg_val("-cf", Some(Dat::BC64(self.confirm))));
+        msg = res!(msg.add_cmd(mcmd));
+        res!(msg.validate());
+        Ok(msg)
+    }
+
+    fn deconstruct(
+        &mut self,
+        mcmd: &mut MsgCmd,
+    )
+        -> Outcome<()>
+    {
+        self.confirm = res!(bytes_arg(mcmd, "-cf", "handshake confirmation"));
+        Ok(())
+    }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    HResp3<ML, SL, UL, ID>
+{
+    /// X checks the confirmation from Y and establishes the session.
+    pub fn respond<
+        const C: usize,
+        P: ProtocolTypes<ML, SL, UL, ID = ID> + 'static,
+    >(
+        &mut self,
+        mcmd:       &mut MsgCmd,
+        _protocol:  &Protocol<C, ML, SL, UL, P>,
+        sessions:   &SessionMap<C, ML, SL, UL, ID>,
+        src_addr:   &SocketAddr,
+        _trg:       Arc<UdpSocket>,
+    )
+        -> Outcome<()>
+    {
+        res!(self.deconstruct(mcmd));
+        let mut unlocked_sessions = lock_write!(sessions);
+        match unlocked_sessions.get_mut(src_addr) {
+            Some(session) if
+                session.state == SessionState::AwaitHResp3
+                && session.peer_uid == Some(self.uid()) =>
+            {
+                let (enc, sid_byts) = match (&session.enc_opt, &session.sid_opt) {
+                    (Some(enc), Some(sid)) => (enc, sid.to_byte_array()),
+                    _ => return Err(err!(
+                        "Session with {:?} is missing its key or id.", src_addr;
+                        Bug, Missing)),
+                };
+                if res!(enc.decrypt(&self.confirm)) != confirmation(HRESP3_LABEL, &sid_byts) {
+                    unlocked_sessions.remove(src_addr);
+                    return Err(err!(
+                        "Handshake confirmation from {:?} does not match.", src_addr;
+                        Invalid, Input));
+                }
+                session.state = SessionState::Established;
+                session.active = Instant::now();
+                info!(async_log::stream(), "Session established with {:?}.", src_addr);
+            },
+            _ => debug!(async_log::stream(), "Dropping unexpected hresp3 from {:?}.", src_addr),
+        }
+        Ok(())
+    }
 }
-    
-//impl_into_bytes_for_server_msg!(HResp1);
-//
-//impl IdentifiedMessage for HResp1 {
-//    fn typ(&self) -> MsgType { HandshakeType::Response1 as MsgType }
-//    fn name(&self) -> &'static str { "hresp1" }
-//}
-//
-//impl ShieldCommand for HResp1 {
-//
-//    fn fmt(&self) -> &MsgFmt { &self.fmt }
-//    fn pow(&self) -> &MsgPow { &self.pow }
-//    fn mid(&self) -> &MsgIds { &self.mid }
-//
-//    fn deconstruct(
-//        &mut self,
-//        mcmd: &mut MsgCmd,
-//    )
-//        -> Outcome<()>
-//    {
-//        self.send_key = mcmd.has_arg("-sspk");
-//        self.pow_code = match mcmd.get_arg_vals_mut("-pc") {
-//            Some(vals) => try_extract_dat!(vals[0].extract(), BC64),
-//            None => return Err(err!(errmsg!("No proof of work code found."),
-//                Invalid, Input, Missing)),
-//        };
-//        self.pow_zbits = match mcmd.get_arg_vals_mut("-zb") {
-//            Some(vals) => try_extract_dat_as!(vals[0].extract(), pow::ZeroBits, U16),
-//            None => return Err(err!(errmsg!("No proof of work zero bit value found."),
-//                Invalid, Input, Missing)),
-//        };
-//        Ok(())
-//    }
-//
-//    fn construct(self) -> Outcome<Msg> {
-//        let mut msg = Msg::new(self.syntax().clone()); // TODO do we have to clone here?
-//        msg.set_encoding(*self.encoding());
-//        msg = res!(msg.add_arg_val("-u", Some(dat!(self.uid()))));
-//        let mut mcmd = res!(msg.new_cmd("hresp1"));
-//        if self.send_key {
-//            mcmd = res!(mcmd.add_arg("-sspk"));
-//        }
-//        mcmd = res!(mcmd.add_arg_val("-pc", Some(Daticle::BC64(self.pow_code))));
-//        mcmd = res!(mcmd.add_arg_val("-zb", Some(dat!(self.pow_zbits))));
-//        msg = res!(msg.add_cmd(mcmd));
-//        res!(msg.validate());
-//        Ok(msg)
-//    }
-//}
-//
-//impl HResp1 {
-//
-//    //pub fn client_process(
-//    //    &mut self,
-//    //    mcmd:     &mut MsgCmd,
-//    //    mut ugrd:   &mut UserGuard<IdDat, UserData>, // For user signing pk.
-//    //    // For sending.
-//    //    src_addr:   SocketAddr,
-//    //    pack_size:  usize,
-//    //    src_sock:   &UdpSocket,
-//    //    trg_addr:   &SocketAddr,
-//    //)
-//    //    -> Outcome<()>
-//    //{
-//    //    res!(self.deconstruct(mcmd)); // We now have all command-specific data.
-//
-//    //    Ok(())
-//    //}
-//}
-//
-////// HReq2 =======================================================================
-////#[derive(Debug, Default)]
-////pub struct HReq2 {
-////    pub fmt: MsgFmt,
-////    pub mid: MsgIds,
-////    // Command-specific
-////    pub sigpk: Option<Vec<u8>>,
-////}
-////    
-////impl_into_bytes_for_server_msg!(HReq2);
-////
-////impl IdentifiedMessage for HReq2 {
-////    fn typ(&self) -> MsgType { HandshakeType::Request2 as MsgType }
-////    fn name(&self) -> &'static str { "hreq2" }
-////}
-////
-////impl Message for HReq2 {}
-////
-////impl ShieldCommand for HReq2 {
-////
-////    fn fmt(&self) -> &MsgFmt { &self.fmt }
-////    fn mid(&self) -> &MsgIds { &self.mid }
-////
-////    fn construct(self) -> Outcome<Msg> {
-////        let mut msg = Msg::new(self.syntax().clone());
-////        msg.set_encoding(*self.encoding());
-////        let mut mcmd = res!(msg.new_cmd("hreq2"));
-////        mcmd = res!(mcmd.add_arg_val("-pc", Daticle::BC64(self.pow_code)));
-////        msg = res!(msg.add_cmd(mcmd));
-////        res!(msg.validate());
-////        Ok(msg)
-////    }
-////}
-////
-////impl HReq2 {
-////
-////    pub fn respond(
-////        &mut self,
-////        mcmd:     &mut MsgCmd,
-////        mut adata:  &mut AddressData, // For pow parameters.
-////        mut ugrd:   &mut UserGuard<IdDat, UserData>, // For user signing pk.
-////        // For sending HResp1.
-////        src_addr:   SocketAddr,
-////        pack_size:  usize,
-////        src_sock:   &UdpSocket,
-////        trg_addr:   &SocketAddr,
-////    )
-////        -> Outcome<()>
-////    {
-////        res!(self.deconstruct(mcmd)); // We now have all command-specific data.
-////        let waiting_for_sigpk = match ugrd.get_user_log_mut(&self.uid()) {
-////            Some(ulog) => {
-////                if ulog.data.sigpk.is_none() {
-////                    ulog.data.waiting_for_sigpk = true;
-////                    true
-////                } else {
-////                    ulog.data.waiting_for_sigpk = false;
-////                    false
-////                }
-////            },
-////            None => true,
-////        };
-////        let mut code = [0u8; PowPristine::CODE_LEN];
-////        Rand::fill_u8(&mut code);
-////        let pow_code = code.to_vec();
-////        adata.apow_code = Some(pow_code.clone());
-////        let send_key = match ugrd.get_user_log_mut(&self.uid()) {
-////            Some(ulog) => ulog.data.sigpk.is_none(),
-////            None => true,
-////        };
-////        let response = HResp1 {
-////            fmt:        self.fmt().clone(),
-////            mid:        self.mid().clone(),
-////            send_key,
-////            pow_code,
-////            pow_zbits:  adata.apow_zbits,
-////        };
-////        response.send(
-////            src_addr,
-////            code,
-////            adata.apow_zbits,
-////            pack_size,
-////            &src_sock,
-////            &trg_addr,
-////        )
-////    }
-////}
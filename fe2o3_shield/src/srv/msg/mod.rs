@@ -1,8 +1,13 @@
+pub mod app;
 pub mod assemble;
 pub mod core;
+pub mod data;
 pub mod decode;
+pub mod discovery;
 pub mod encode;
 pub mod handshake;
 pub mod protocol;
+pub mod rekey;
+pub mod reliable;
 pub mod packet;
 pub mod syntax;
@@ -3,6 +3,7 @@ use crate::{
         constant,
         msg::{
             core::MsgType,
+            discovery::DiscoveryType,
             handshake::HandshakeType,
         },
     },
@@ -253,7 +254,7 @@ impl TryFrom<u8> for PacketValidatorId {
 /// Contains the optional ranges for the validation artefacts in a byte slice, including:
 /// - Proof of work artefact p0..p1,
 /// - Signature artefact s0..s1 and s2..s3, s4..s5 when the public key is included.
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct PacketValidationArtefactRelativeIndices {
     pub pow: Option<Range<usize>>,
     pub sig: Option<(Range<usize>, Option<(Range<usize>, Range<usize>)>)>,
@@ -290,7 +291,7 @@ impl FromBytes for PacketValidationArtefactRelativeIndices {
     fn from_bytes(buf: &[u8]) -> Outcome<(Self, usize)> {
         let mut result = Self::default();
         let mut n: usize = 0;
-        match PacketValidatorId::try_from(buf[n] as u8) {
+        match PacketValidatorId::try_from(res!(Self::read_id(&buf, n))) {
             Ok(PacketValidatorId::Pow) => {
                 n += 1;
                 let (len, ns) = res!(Self::read_size(&buf, n));
@@ -300,7 +301,7 @@ impl FromBytes for PacketValidationArtefactRelativeIndices {
             },
             _ => result.pow = None,
         }
-        match PacketValidatorId::try_from(buf[n] as u8) {
+        match PacketValidatorId::try_from(res!(Self::read_id(&buf, n))) {
             Ok(pvid) => {
                 match pvid {
                     PacketValidatorId::BareSignature | PacketValidatorId::SignatureWithKey => {
@@ -340,6 +341,12 @@ impl FromBytes for PacketValidationArtefactRelativeIndices {
             },
             _ => result.sig = None,
         }
+        if n > buf.len() {
+            return Err(err!(
+                "Packet validation artefacts of {} bytes extend beyond the {} bytes available.",
+                n, buf.len();
+                Decode, Bytes, TooBig));
+        }
         Ok((result, n))
     }
 }
@@ -348,7 +355,23 @@ impl PacketValidationArtefactRelativeIndices {
 
     pub const BYTE_PREFIX_LEN: usize = 1 + 2;
 
+    fn read_id(buf: &[u8], n: usize) -> Outcome<u8> {
+        match buf.get(n) {
+            Some(byt) => Ok(*byt),
+            None => Err(err!(
+                "Packet validation artefact id expected at index {}, but only {} bytes \
+                are available.", n, buf.len();
+                Decode, Bytes, TooSmall)),
+        }
+    }
+
     fn read_size(buf: &[u8], n: usize) -> Outcome<(usize, usize)> {
+        if n + 2 > buf.len() {
+            return Err(err!(
+                "Packet validation artefact size expected at index {}, but only {} bytes \
+                are available.", n, buf.len();
+                Decode, Bytes, TooSmall));
+        }
         Ok((
             u16::from_be_bytes(res!(<[u8; 2]>::try_from(&buf[n..n+2]),
                 Decode, Bytes)) as usize, 
@@ -505,8 +528,12 @@ impl<
                 },
                 Some((range, Some((pk_rng, sig_rng)))) => { // range covers the public key and the signature.
                     // Provision of the public key is only valid if the message is a
-                    // HandshakeType::Req1.
-                    if HandshakeType::from(msg_typ) != HandshakeType::Req1 {
+                    // HandshakeType::Req1 or HandshakeType::Resp1, when the peers first
+                    // exchange their keys, or a sessionless discovery message.
+                    if !matches!(
+                        HandshakeType::from(msg_typ),
+                        HandshakeType::Req1 | HandshakeType::Resp1,
+                    ) && DiscoveryType::from(msg_typ) == DiscoveryType::Unknown {
                         return Ok(PacketValidationResult {
                             pow,
                             sig: None,
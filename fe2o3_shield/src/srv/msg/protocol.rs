@@ -1,6 +1,12 @@
 use crate::srv::{
     cfg::ServerConfig,
     constant,
+    discovery::{
+        self,
+        DiscoveryAction,
+        PeerDiscovery,
+        PeerId,
+    },
     guard::{
         addr::{
             AddressGuard,
@@ -15,7 +21,12 @@ use crate::srv::{
             UserLog,
         },
     },
+    handler::{
+        ShieldHandler,
+        ShieldSinkHandler,
+    },
     msg::{
+        app::AppMsg,
         assemble::{
             MsgAssembler,
             MsgAssemblyParams,
@@ -24,8 +35,36 @@ use crate::srv::{
         core::{
             DefaultIdTypes,
             IdTypes,
+            MsgFmt,
+            MsgIds,
+            MsgPow,
+        },
+        data::{
+            SessionData,
+            SessionDelivery,
+        },
+        discovery::{
+            FindNode,
+            Ping,
+        },
+        encode::ShieldCommand,
+        handshake::HReq1,
+        rekey::{
+            self as rekey_msg,
+            KeyRotate,
+            PendingRotation,
+            RekeyReq,
         },
-        packet::PacketValidator,
+        packet::{
+            PacketCount,
+            PacketValidator,
+        },
+        reliable::{
+            self,
+            Ack,
+            ReliableDelivery,
+        },
+        syntax,
     },
     pow::DifficultyParams,
     schemes::{
@@ -34,12 +73,32 @@ use crate::srv::{
         WireSchemesInput,
         WireSchemeTypes,
     },
+    session::{
+        Session,
+        SessionMap,
+        SessionState,
+    },
 };
 
 use oxedyne_fe2o3_core::{
     prelude::*,
+    byte::{
+        IntoBytes,
+        ToByteArray,
+    },
+    channels::{
+        Recv,
+        simplex,
+        Simplex,
+    },
+    rand::{
+        Rand,
+        RanDef,
+    },
 };
 use oxedyne_fe2o3_crypto::{
+    enc::EncryptionScheme,
+    kem::KeyExchangeScheme,
     sign::SignatureScheme,
 };
 use oxedyne_fe2o3_hash::{
@@ -48,19 +107,42 @@ use oxedyne_fe2o3_hash::{
         HashScheme,
     },
     map::ShardMap,
-    pow::ProofOfWork,
+    pow::{
+        ProofOfWork,
+        ZeroBits,
+    },
 };
 use oxedyne_fe2o3_data::ring::RingTimer;
-use oxedyne_fe2o3_iop_crypto::sign::SignerDefAlt;
+use oxedyne_fe2o3_iop_crypto::{
+    enc::EncrypterDefAlt,
+    keys::KeyManager,
+    sign::{
+        Signer,
+        SignerDefAlt,
+    },
+};
 use oxedyne_fe2o3_iop_hash::api::HashForm;
+use oxedyne_fe2o3_syntax::{
+    SyntaxRef,
+    msg::Msg,
+};
 
 use std::{
     collections::BTreeMap,
     fmt,
+    marker::PhantomData,
+    net::{
+        SocketAddr,
+        UdpSocket,
+    },
     sync::{
         Arc,
         RwLock,
     },
+    time::{
+        Duration,
+        Instant,
+    },
 };
 
 
@@ -81,25 +163,31 @@ pub trait ProtocolTypes<
 {
     type ID: IdTypes<ML, SL, UL>;    
     type W: WireSchemeTypes;
+    type H: ShieldHandler;
 }
 
+/// The default identifier and wire scheme types, with a choice of application message handler
+/// that by default discards application messages.
 #[derive(Clone, Debug, Default)]
 pub struct DefaultProtocolTypes<
     const ML: usize,
     const SL: usize,
     const UL: usize,
->;
+    H = ShieldSinkHandler,
+>(PhantomData<H>);
 
 impl<
     const ML: usize,
     const SL: usize,
     const UL: usize,
+    H: ShieldHandler,
 >
-    ProtocolTypes<ML, SL, UL> for DefaultProtocolTypes<ML, SL, UL>
+    ProtocolTypes<ML, SL, UL> for DefaultProtocolTypes<ML, SL, UL, H>
     where DefaultIdTypes<ML, SL, UL>: IdTypes<ML, SL, UL>,
 {
     type ID = DefaultIdTypes<ML, SL, UL>;
     type W = DefaultWireSchemes;
+    type H = H;
 }
 
 /// Capture all necessary information, and nothing more, allowing a thread to process an incoming
@@ -120,6 +208,7 @@ pub struct Protocol<
     _uid_template:      <P::ID as IdTypes<ML, SL, UL>>::U,
 
     pub mode:           ProtocolMode,
+    pub uid:            <P::ID as IdTypes<ML, SL, UL>>::U, // My user id when contacting peers.
     pub schms:          WireSchemes<P::W>,
 
     pub timer:          Arc<RwLock<RingTimer<{ constant::REQ_TIMER_LEN }>>>,
@@ -168,6 +257,22 @@ pub struct Protocol<
     // Policy configuration.
     pub pow_time_horiz: u64,
     pub accept_unknown: bool,
+    pub rekey_after:    Option<Duration>, // Renew the key of sessions I initiate this often.
+    pub idle_limit:     Option<Duration>, // Drop sessions with no sign of the peer for this long.
+    // Session data received, awaiting collection by the application.
+    pub inbox:          Simplex<SessionDelivery>,
+    // Application messages received, and their syntax.
+    pub handler:        P::H,
+    pub app_syntax:     SyntaxRef,
+    // Acknowledgement and retransmission of messages.
+    pub reliable:       Arc<RwLock<ReliableDelivery<ML>>>,
+    // Routing table of peers and discovery requests awaiting answers.
+    pub discovery:      Arc<RwLock<PeerDiscovery>>,
+    // A new packet signing key announced to peers, adopted once they have acknowledged it.
+    pub rotation_opt:   Option<PendingRotation<
+                            ML,
+                            SignerDefAlt<SignatureScheme, <P::W as WireSchemeTypes>::SGN>,
+                        >>,
 }
 
 impl<
@@ -254,6 +359,8 @@ impl<
                 constant::ADDR_THROTTLE_SUNSET_SECS_MAX,
             ),
             blist_cnt: constant::THROTTLE_COUNT_BEFORE_BLACKLIST,
+            // Misbehaviour
+            offence_decay: Duration::from_secs(cfg.server_pow_offence_decay_secs),
             // Handshake
             hreq_exp: constant::SESSION_REQUEST_EXPIRY,
         });
@@ -285,6 +392,24 @@ impl<
             pow: Some(res!(ProofOfWork::new(schms.powh.clone()))),
             sig: Some(schms.sign.clone()),
         };
+
+        // My discovery identifier is derived from my packet signing key.  Without one I cannot
+        // sign discovery messages, so other peers will not admit me to their tables.
+        let own_id: PeerId = match schms.sign.get_public_key() {
+            Ok(Some(pk)) => res!(discovery::peer_id(pk)),
+            _ => {
+                let mut id = [0u8; discovery::PEER_ID_LEN];
+                Rand::fill_u8(&mut id);
+                id
+            },
+        };
+        let handler = P::H::default();
+        let app_syntax = res!(syntax::app_msg(res!(handler.cmds())));
+        let seeds = if cfg.trusted_seeds.is_empty() {
+            Vec::new() // I am a seed, or stand alone.
+        } else {
+            res!(cfg.get_trusted_seeds())
+        };
         
         Ok(Self {
             _code_template,
@@ -292,17 +417,14 @@ impl<
             _sid_template,
             _uid_template,
             mode,
+            // Replaced by any identity saved by an earlier run when the server starts.
+            uid:            <P::ID as IdTypes<ML, SL, UL>>::U::randef(),
             schms,
             timer:          Arc::new(RwLock::new(RingTimer::<{ constant::REQ_TIMER_LEN }>::default())),
             agrd:           agrd.clone(),
             ugrd:           ugrd.clone(),
             packval,
-            gpzparams:      DifficultyParams {
-                                profile:    constant::POW_DIFFICULTY_PROFILE,
-                                max:        constant::POW_MAX_ZERO_BITS,
-                                min:        constant::POW_MIN_ZERO_BITS,
-                                rps_max:    constant::MAX_ALLOWED_AVG_REQ_PER_SEC,
-                            },
+            gpzparams:      res!(cfg.difficulty_params()),
             massembler:     Arc::new(res!(MsgAssembler::<
                                 { constant::MSG_ASSEMBLY_SHARDS },
                                 _, _,
@@ -321,6 +443,791 @@ impl<
                             },
             pow_time_horiz: constant::POW_TIME_HORIZON_SEC,
             accept_unknown: true,
+            rekey_after:    cfg.session_rekey_interval(),
+            idle_limit:     cfg.session_idle_limit(),
+            inbox:          simplex(),
+            handler,
+            app_syntax,
+            reliable:       Arc::new(RwLock::new(ReliableDelivery::new(
+                                res!(cfg.reliable_params())))),
+            discovery:      Arc::new(RwLock::new(PeerDiscovery::new(
+                                own_id,
+                                res!(cfg.discovery_params()),
+                                seeds,
+                            ))),
+            rotation_opt:   None,
+        })
+    }
+
+    /// Use the given handler for application messages, adopting the syntax of its commands.
+    pub fn with_handler(mut self, handler: P::H) -> Outcome<Self> {
+        self.app_syntax = res!(syntax::app_msg(res!(handler.cmds())));
+        self.handler = handler;
+        Ok(self)
+    }
+
+    /// The proof of work difficulty I require of packets from the given address, being the
+    /// greater of the global requirement and any requirement specific to the address.
+    pub fn required_zbits(&self, addr: &SocketAddr) -> Outcome<ZeroBits> {
+        let zbits = {
+            let unlocked_timer = lock_read!(self.timer);
+            res!(
+                self.gpzparams.required_global_zbits(unlocked_timer.avg_rps()),
+                IO,
+            )
+        };
+        let (akey, locked_amap) = res!(self.agrd.get_locked_map(addr));
+        let unlocked_amap = lock_read!(locked_amap);
+        match unlocked_amap.get(&akey) {
+            Some(alog) => Ok(std::cmp::max(zbits, alog.data.my_zbits)),
+            None => Err(err!(
+                "No AddressLog entry for {:?}, which should have been created \
+                by the AddressGuard.", addr;
+                Bug, Missing)),
+        }
+    }
+
+    /// The proof of work difficulty I require of sessionless requests, i.e. handshake and
+    /// discovery messages, from the given address.  This adds a requirement that rises with the
+    /// recent offences of the address, so that an address that misbehaves must work harder to
+    /// open a session or join my routing table.  It is communicated in the `MsgPow` of my
+    /// handshake and discovery messages, notably `HResp1`.
+    pub fn request_zbits(&self, addr: &SocketAddr) -> Outcome<ZeroBits> {
+        let zbits = res!(self.required_zbits(addr));
+        let offences = res!(self.agrd.offences(addr));
+        Ok(std::cmp::max(zbits, res!(self.gpzparams.required_addr_zbits(offences))))
+    }
+
+    /// The proof of work difficulty the peer at the given address requires of my packets.
+    pub fn peer_zbits(&self, addr: &SocketAddr) -> Outcome<ZeroBits> {
+        let (akey, locked_amap) = res!(self.agrd.get_locked_map(addr));
+        let unlocked_amap = lock_read!(locked_amap);
+        Ok(match unlocked_amap.get(&akey) {
+            Some(alog) => alog.data.your_zbits,
+            None => constant::POW_INITIAL_ZERO_BITS,
+        })
+    }
+
+    pub fn set_peer_zbits(&self, addr: &SocketAddr, zbits: ZeroBits) -> Outcome<()> {
+        let (akey, locked_amap) = res!(self.agrd.get_locked_map(addr));
+        let mut unlocked_amap = lock_write!(locked_amap);
+        match unlocked_amap.get_mut(&akey) {
+            Some(alog) => {
+                alog.data.your_zbits = zbits;
+                Ok(())
+            },
+            None => Err(err!(
+                "No AddressLog entry for {:?}, which should have been created \
+                by the AddressGuard.", addr;
+                Bug, Missing)),
+        }
+    }
+
+    /// Issue a fresh proof of work code to the given user, which I will require in all their
+    /// subsequent packets.
+    pub fn new_user_code(&self, uid: &<P::ID as IdTypes<ML, SL, UL>>::U) -> Outcome<[u8; C]> {
+        let mut code = [0u8; C];
+        Rand::fill_u8(&mut code);
+        let (ukey, locked_umap) = res!(self.ugrd.get_locked_map(uid));
+        let mut unlocked_umap = lock_write!(locked_umap);
+        match unlocked_umap.get_mut(&ukey) {
+            Some(ulog) => ulog.data.code = Some(code),
+            None => return Err(err!(
+                "No UserLog entry for {:?}, which should have been created \
+                by the UserGuard.", uid;
+                Bug, Missing)),
+        }
+        Ok(code)
+    }
+
+    /// Begin a handshake with the peer at the given address by sending a HReq1.  The session
+    /// state is tracked in the given map as the handshake proceeds.  An established session with
+    /// the peer is left intact and an error returned, since the peer would ignore the HReq1.
+    pub fn connect(
+        &self,
+        trg:        Arc<UdpSocket>,
+        peer_addr:  SocketAddr,
+        sessions:   &SessionMap<C, ML, SL, UL, P::ID>,
+        syntax:     SyntaxRef,
+    )
+        -> Outcome<()>
+    {
+        {
+            let unlocked_sessions = lock_read!(sessions);
+            if let Some(session) = unlocked_sessions.get(&peer_addr) {
+                if session.is_established() {
+                    return Err(err!(
+                        "A session with {:?} is already established.", peer_addr;
+                        Network, Exists));
+                }
+            }
+        }
+        // Responses from the peer should not be treated as coming from an unknown address.
+        res!(self.agrd.register(&peer_addr, AddressData {
+            your_zbits: constant::POW_INITIAL_ZERO_BITS,
+            ..Default::default()
+        }));
+        {
+            let mut unlocked_sessions = lock_write!(sessions);
+            unlocked_sessions.insert(peer_addr, Session::new(SessionState::AwaitHResp1, true));
+        }
+        let request = HReq1::<ML, SL, UL, P::ID> {
+            fmt:        MsgFmt {
+                            syntax,
+                            encoding: constant::DEFAULT_MSG_ENCODING,
+                        },
+            pow:        MsgPow { zbits: res!(self.request_zbits(&peer_addr)) },
+            mid:        MsgIds { sid_opt: None, uid: self.uid.clone() },
+            peer_sigpk: None,
+        };
+        self.dispatch(
+            request,
+            trg,
+            &peer_addr,
+            [0; C], // I don't yet know the code you require.
+            res!(self.peer_zbits(&peer_addr)),
+        )
+    }
+
+    /// The session encrypter is the `WireSchemes` encrypter, keyed with the session key.
+    pub fn session_encrypter(
+        &self,
+        session: &Session<C, ML, SL, UL, P::ID>,
+    )
+        -> Outcome<EncrypterDefAlt<EncryptionScheme, <P::W as WireSchemeTypes>::ENC>>
+    {
+        self.keyed_encrypter(&session.enc_opt)
+    }
+
+    /// The `WireSchemes` encrypter keyed with the session key in use before the last renewal.
+    pub fn previous_session_encrypter(
+        &self,
+        session: &Session<C, ML, SL, UL, P::ID>,
+    )
+        -> Outcome<EncrypterDefAlt<EncryptionScheme, <P::W as WireSchemeTypes>::ENC>>
+    {
+        self.keyed_encrypter(&session.enc_prev_opt)
+    }
+
+    fn keyed_encrypter(
+        &self,
+        enc_opt: &Option<EncryptionScheme>,
+    )
+        -> Outcome<EncrypterDefAlt<EncryptionScheme, <P::W as WireSchemeTypes>::ENC>>
+    {
+        if self.schms.enc.is_none() {
+            return Err(err!(
+                "No wire encryption scheme has been specified for session data.";
+                Configuration, Missing));
+        }
+        let key = match enc_opt {
+            Some(enc) => match res!(enc.get_secret_key()) {
+                Some(key) => key,
+                None => return Err(err!("The session has no key."; Bug, Missing)),
+            },
+            None => return Err(err!("The session has no key."; Bug, Missing)),
+        };
+        self.schms.enc.clone_with_keys(None, Some(key))
+    }
+
+    /// Decrypt an incoming session payload with the session key, or failing that, the previous
+    /// session key.  Returns the payload and whether the previous key was needed, or `None` when
+    /// the message was sent in my direction or has already been received.
+    pub fn open_session_msg(
+        &self,
+        session:    &mut Session<C, ML, SL, UL, P::ID>,
+        cipher:     &[u8],
+    )
+        -> Outcome<Option<(Vec<u8>, bool)>>
+    {
+        let enc = res!(self.session_encrypter(session));
+        let opened = match session.open(&enc, cipher) {
+            Ok(data_opt) => data_opt.map(|data| (data, false)),
+            Err(e) => {
+                if session.enc_prev_opt.is_none() {
+                    return Err(e);
+                }
+                let enc = res!(self.previous_session_encrypter(session));
+                res!(session.open(&enc, cipher)).map(|data| (data, true))
+            },
+        };
+        if opened.is_some() {
+            session.active = Instant::now();
+        }
+        Ok(opened)
+    }
+
+    /// Ask the peer at the given address, with whom I must already have an established session,
+    /// to renew the session key by sending a fresh key exchange public key in a `RekeyReq`.
+    pub fn rekey(
+        &self,
+        trg:        Arc<UdpSocket>,
+        peer_addr:  &SocketAddr,
+        sessions:   &SessionMap<C, ML, SL, UL, P::ID>,
+        syntax:     SyntaxRef,
+    )
+        -> Outcome<()>
+    {
+        let kem = KeyExchangeScheme::new_firesaber();
+        let kem_pk = match kem.get_public_key() {
+            Some(pk) => pk.to_vec(),
+            None => return Err(err!(
+                "New key exchange scheme is missing a public key."; Bug, Missing)),
+        };
+        let (peer_code, sid, cipher) = {
+            let mut unlocked_sessions = lock_write!(sessions);
+            match unlocked_sessions.get_mut(peer_addr) {
+                Some(session) if session.is_established() => {
+                    let enc = res!(self.session_encrypter(session));
+                    let cipher = res!(session.seal(&enc, &kem_pk));
+                    session.kem_opt = Some(kem);
+                    session.rekeyed = Instant::now();
+                    (session.code, session.sid_opt, cipher)
+                },
+                _ => return Err(err!(
+                    "No session has been established with {:?}.", peer_addr;
+                    Network, Missing)),
+            }
+        };
+        let msg = RekeyReq::<ML, SL, UL, P::ID> {
+            fmt:    MsgFmt {
+                        syntax,
+                        encoding: constant::DEFAULT_MSG_ENCODING,
+                    },
+            pow:    MsgPow { zbits: res!(self.required_zbits(peer_addr)) },
+            mid:    MsgIds { sid_opt: sid, uid: self.uid.clone() },
+            cipher,
+        };
+        self.dispatch(
+            msg,
+            trg,
+            peer_addr,
+            peer_code,
+            res!(self.peer_zbits(peer_addr)),
+        )
+    }
+
+    /// Renew the keys of the sessions I initiated that are due.  A renewal that goes unanswered
+    /// is requested again after the same interval.  Called regularly from the server loop.
+    pub fn rekey_tick(
+        &self,
+        trg:        Arc<UdpSocket>,
+        sessions:   &SessionMap<C, ML, SL, UL, P::ID>,
+        syntax:     SyntaxRef,
+    )
+        -> Outcome<()>
+    {
+        let rekey_after = match self.rekey_after {
+            Some(rekey_after) => rekey_after,
+            None => return Ok(()),
+        };
+        let due: Vec<SocketAddr> = {
+            let unlocked_sessions = lock_read!(sessions);
+            unlocked_sessions.iter()
+                .filter(|(_, session)| {
+                    session.is_established()
+                    && session.initiator
+                    && session.rekeyed.elapsed() >= rekey_after
+                })
+                .map(|(peer_addr, _)| *peer_addr)
+                .collect()
+        };
+        for peer_addr in due {
+            debug!(async_log::stream(), "Renewing the session key with {:?}.", peer_addr);
+            res!(self.rekey(trg.clone(), &peer_addr, sessions, syntax.clone()));
+        }
+        Ok(())
+    }
+
+    /// Drop the sessions, established or still in handshake, in which nothing has been heard from
+    /// the peer for longer than the idle limit.  A peer that restarts loses its sessions, and its
+    /// fresh HReq1 is ignored while mine remains established, so this allows it to reconnect.
+    /// Returns the number of sessions dropped.  Called regularly from the server loop.
+    pub fn expire_sessions(
+        &self,
+        sessions:   &SessionMap<C, ML, SL, UL, P::ID>,
+    )
+        -> Outcome<usize>
+    {
+        let idle_limit = match self.idle_limit {
+            Some(idle_limit) => idle_limit,
+            None => return Ok(0),
+        };
+        let mut unlocked_sessions = lock_write!(sessions);
+        let before = unlocked_sessions.len();
+        unlocked_sessions.retain(|peer_addr, session| {
+            let keep = session.active.elapsed() <= idle_limit;
+            if !keep {
+                debug!(async_log::stream(), "Dropping the idle session with {:?}.", peer_addr);
+            }
+            keep
+        });
+        Ok(before - unlocked_sessions.len())
+    }
+
+    /// Rotate to the given packet signing key pair, announcing the new public key in a reliably
+    /// delivered `KeyRotate` to each peer with whom I have an established session.  The
+    /// announcements are signed with my current key, which the peers have on record.  I continue
+    /// to sign with it until `rotation_tick` finds that every announcement has been acknowledged
+    /// or abandoned, and adopt the new key immediately if there is no one to notify.  Returns the
+    /// number of peers notified.
+    pub fn rotate_signing_key(
+        &mut self,
+        trg:        Arc<UdpSocket>,
+        sessions:   &SessionMap<C, ML, SL, UL, P::ID>,
+        syntax:     SyntaxRef,
+        sigpk:      &[u8],
+        sigsk:      &[u8],
+    )
+        -> Outcome<usize>
+    {
+        if self.rotation_opt.is_some() {
+            return Err(err!(
+                "A new packet signing key has already been announced, and is awaiting \
+                acknowledgement by peers."; Input, Exists));
+        }
+        let old_sigpk = match res!(self.schms.sign.get_public_key()) {
+            Some(pk) => pk.to_vec(),
+            None => return Err(err!(
+                "There is no current packet signing key to rotate."; Configuration, Missing)),
+        };
+        let signer = res!(self.schms.sign.clone_with_keys(Some(sigpk), Some(sigsk)));
+        let statement = rekey_msg::rotation_statement(
+            &self.uid.to_byte_array(),
+            &old_sigpk,
+            sigpk,
+        );
+        let old_sig = res!(self.schms.sign.sign(&statement));
+        let new_sig = res!(signer.sign(&statement));
+        if !res!(signer.verify(&statement, &new_sig)) {
+            return Err(err!(
+                "The given public and secret signing keys are not a pair."; Input, Mismatch));
+        }
+        let peers = {
+            let unlocked_sessions = lock_read!(sessions);
+            unlocked_sessions.iter()
+                .filter(|(_, session)| session.is_established())
+                .map(|(peer_addr, session)| (*peer_addr, session.code, session.sid_opt))
+                .collect::<Vec<_>>()
+        };
+        let mut announced = Vec::new();
+        for (peer_addr, peer_code, sid) in &peers {
+            let msg = KeyRotate::<ML, SL, UL, P::ID> {
+                fmt:        MsgFmt {
+                                syntax:     syntax.clone(),
+                                encoding:   constant::DEFAULT_MSG_ENCODING,
+                            },
+                pow:        MsgPow { zbits: res!(self.required_zbits(peer_addr)) },
+                mid:        MsgIds { sid_opt: *sid, uid: self.uid.clone() },
+                new_sigpk:  sigpk.to_vec(),
+                old_sig:    old_sig.clone(),
+                new_sig:    new_sig.clone(),
+            };
+            let mid = res!(self.dispatch_reliably(
+                msg,
+                trg.clone(),
+                peer_addr,
+                *peer_code,
+                res!(self.peer_zbits(peer_addr)),
+            ));
+            announced.push((*peer_addr, mid));
+        }
+        let rotation = PendingRotation {
+            signer,
+            sigpk: sigpk.to_vec(),
+            announced,
+        };
+        if rotation.announced.is_empty() {
+            res!(self.adopt_signing_key(rotation));
+        } else {
+            self.rotation_opt = Some(rotation);
+        }
+        Ok(peers.len())
+    }
+
+    /// Adopt a pending new packet signing key once none of the peers to whom it was announced
+    /// still await the announcement, each having acknowledged it or been abandoned.  Returns
+    /// whether the key was adopted.
+    pub fn rotation_tick(&mut self) -> Outcome<bool> {
+        let ready = match &self.rotation_opt {
+            Some(rotation) => {
+                let unlocked_reliable = lock_read!(self.reliable);
+                !rotation.announced.iter()
+                    .any(|(peer_addr, mid)| unlocked_reliable.is_pending(peer_addr, mid))
+            },
+            None => return Ok(false),
+        };
+        if ready {
+            if let Some(rotation) = self.rotation_opt.take() {
+                res!(self.adopt_signing_key(rotation));
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Sign my packets with the new key from now on, and take the new peer identifier it implies.
+    fn adopt_signing_key(
+        &mut self,
+        rotation: PendingRotation<
+            ML,
+            SignerDefAlt<SignatureScheme, <P::W as WireSchemeTypes>::SGN>,
+        >,
+    )
+        -> Outcome<()>
+    {
+        self.use_signer(rotation.signer, &rotation.sigpk)
+    }
+
+    /// Sign my packets with the given signer, whose public key implies my peer identifier.
+    fn use_signer(
+        &mut self,
+        signer: SignerDefAlt<SignatureScheme, <P::W as WireSchemeTypes>::SGN>,
+        sigpk:  &[u8],
+    )
+        -> Outcome<()>
+    {
+        self.schms.sign = signer.clone();
+        self.packval.sig = Some(signer);
+        let mut unlocked_discovery = lock_write!(self.discovery);
+        unlocked_discovery.set_own(res!(discovery::peer_id(sigpk)));
+        Ok(())
+    }
+
+    /// My user id and packet signing key pair, if I have one.  Together they identify me to
+    /// peers, who record the key used by each user.
+    pub fn identity(&self)
+        -> Outcome<(<P::ID as IdTypes<ML, SL, UL>>::U, Option<(Vec<u8>, Vec<u8>)>)>
+    {
+        let keys = match (
+            res!(self.schms.sign.get_public_key()),
+            res!(self.schms.sign.get_secret_key()),
+        ) {
+            (Some(sigpk), Some(sigsk)) => Some((sigpk.to_vec(), sigsk.to_vec())),
+            _ => None,
+        };
+        Ok((self.uid.clone(), keys))
+    }
+
+    /// Take the given user id and packet signing key pair, if any, as my identity, typically one
+    /// saved by an earlier run.
+    pub fn set_identity(
+        &mut self,
+        uid:    <P::ID as IdTypes<ML, SL, UL>>::U,
+        keys:   Option<(&[u8], &[u8])>,
+    )
+        -> Outcome<()>
+    {
+        if let Some((sigpk, sigsk)) = keys {
+            let signer = res!(self.schms.sign.clone_with_keys(Some(sigpk), Some(sigsk)));
+            res!(self.use_signer(signer, sigpk));
+        }
+        self.uid = uid;
+        Ok(())
+    }
+
+    /// Encrypt and send data to the peer at the given address, with whom I must already have an
+    /// established session.
+    pub fn send_data(
+        &self,
+        trg:        Arc<UdpSocket>,
+        peer_addr:  &SocketAddr,
+        sessions:   &SessionMap<C, ML, SL, UL, P::ID>,
+        syntax:     SyntaxRef,
+        data:       &[u8],
+    )
+        -> Outcome<()>
+    {
+        let (peer_code, sid, cipher) = res!(self.seal_session_msg(peer_addr, sessions, data));
+        let msg = SessionData::<ML, SL, UL, P::ID> {
+            fmt:    MsgFmt {
+                        syntax,
+                        encoding: constant::DEFAULT_MSG_ENCODING,
+                    },
+            pow:    MsgPow { zbits: res!(self.required_zbits(peer_addr)) },
+            mid:    MsgIds { sid_opt: sid, uid: self.uid.clone() },
+            cipher,
+        };
+        self.dispatch(
+            msg,
+            trg,
+            peer_addr,
+            peer_code,
+            res!(self.peer_zbits(peer_addr)),
+        )
+    }
+
+    /// Encode, encrypt and send an application message to the peer at the given address, with
+    /// whom I must already have an established session.  The application message must use the
+    /// application syntax, while the given syntax is that of the protocol.
+    pub fn send_app_msg(
+        &self,
+        trg:        Arc<UdpSocket>,
+        peer_addr:  &SocketAddr,
+        sessions:   &SessionMap<C, ML, SL, UL, P::ID>,
+        syntax:     SyntaxRef,
+        app_msg:    Msg,
+    )
+        -> Outcome<()>
+    {
+        let mut app_msg = app_msg;
+        app_msg.set_encoding(constant::DEFAULT_MSG_ENCODING);
+        res!(app_msg.validate());
+        let byts = res!(app_msg.into_bytes(Vec::new()));
+        let (peer_code, sid, cipher) = res!(self.seal_session_msg(peer_addr, sessions, &byts));
+        let msg = AppMsg::<ML, SL, UL, P::ID> {
+            fmt:    MsgFmt {
+                        syntax,
+                        encoding: constant::DEFAULT_MSG_ENCODING,
+                    },
+            pow:    MsgPow { zbits: res!(self.required_zbits(peer_addr)) },
+            mid:    MsgIds { sid_opt: sid, uid: self.uid.clone() },
+            cipher,
+        };
+        self.dispatch(
+            msg,
+            trg,
+            peer_addr,
+            peer_code,
+            res!(self.peer_zbits(peer_addr)),
+        )
+    }
+
+    /// Seal the given bytes with the key of my established session with the peer at the given
+    /// address, returning them with the proof of work code the peer requires and the session id.
+    fn seal_session_msg(
+        &self,
+        peer_addr:  &SocketAddr,
+        sessions:   &SessionMap<C, ML, SL, UL, P::ID>,
+        byts:       &[u8],
+    )
+        -> Outcome<([u8; C], Option<<P::ID as IdTypes<ML, SL, UL>>::S>, Vec<u8>)>
+    {
+        let mut unlocked_sessions = lock_write!(sessions);
+        match unlocked_sessions.get_mut(peer_addr) {
+            Some(session) if session.is_established() => {
+                let enc = res!(self.session_encrypter(session));
+                Ok((session.code, session.sid_opt, res!(session.seal(&enc, byts))))
+            },
+            _ => Err(err!(
+                "No session has been established with {:?}.", peer_addr;
+                Network, Missing)),
+        }
+    }
+
+    /// Wait up to the given duration for session data from any peer.
+    pub fn recv_data(&self, wait: Duration) -> Outcome<Option<SessionDelivery>> {
+        match self.inbox.recv_timeout(wait) {
+            Recv::Empty => Ok(None),
+            Recv::Result(result) => Ok(Some(res!(result))),
+        }
+    }
+
+    /// Collect session data from any peer, without waiting.
+    pub fn try_recv_data(&self) -> Outcome<Option<SessionDelivery>> {
+        match self.inbox.try_recv() {
+            Recv::Empty => Ok(None),
+            Recv::Result(result) => Ok(Some(res!(result))),
+        }
+    }
+
+    /// Send a message to the peer at the given address.  Messages of a type configured for
+    /// reliable delivery are held until acknowledged, and sent as the congestion window allows.
+    pub fn dispatch<M: ShieldCommand<ML, SL, UL, P::ID>>(
+        &self,
+        msg:        M,
+        trg:        Arc<UdpSocket>,
+        peer_addr:  &SocketAddr,
+        code:       [u8; C],
+        zbits:      ZeroBits,
+    )
+        -> Outcome<()>
+    {
+        let reliable = {
+            let unlocked_reliable = lock_read!(self.reliable);
+            unlocked_reliable.params.is_reliable(msg.typ())
+        };
+        if !reliable {
+            return msg.send::<C, P::W>(trg, peer_addr, code, zbits, self.schms.clone());
+        }
+        res!(self.dispatch_reliably(msg, trg, peer_addr, code, zbits));
+        Ok(())
+    }
+
+    /// Hold the packets of the message until the peer acknowledges them all, sending those the
+    /// congestion window allows.  Returns the message id, by which the delivery can be followed.
+    pub fn dispatch_reliably<M: ShieldCommand<ML, SL, UL, P::ID>>(
+        &self,
+        msg:        M,
+        trg:        Arc<UdpSocket>,
+        peer_addr:  &SocketAddr,
+        code:       [u8; C],
+        zbits:      ZeroBits,
+    )
+        -> Outcome<[u8; ML]>
+    {
+        let (mid, packets) = res!(msg.packets::<C, P::W>(
+            &trg,
+            peer_addr,
+            code,
+            zbits,
+            self.schms.clone(),
+        ));
+        let mid = mid.to_byte_array();
+        {
+            let mut unlocked_reliable = lock_write!(self.reliable);
+            unlocked_reliable.register(*peer_addr, mid, packets);
+        }
+        res!(self.reliable_tick(&trg));
+        Ok(mid)
+    }
+
+    /// Acknowledge the chunks received so far of a reliably delivered message from the peer at
+    /// the given address.
+    pub fn send_ack(
+        &self,
+        trg:        Arc<UdpSocket>,
+        peer_addr:  &SocketAddr,
+        sessions:   &SessionMap<C, ML, SL, UL, P::ID>,
+        syntax:     SyntaxRef,
+        mid:        &<P::ID as IdTypes<ML, SL, UL>>::M,
+        num_chunks: PacketCount,
+        received:   Vec<PacketCount>,
+    )
+        -> Outcome<()>
+    {
+        // Identify the session, if any, so that the peer can tell that I still hold it.
+        let (peer_code, sid_opt) = {
+            let unlocked_sessions = lock_read!(sessions);
+            match unlocked_sessions.get(peer_addr) {
+                Some(session) if session.is_established() =>
+                    (session.code, session.sid_opt.clone()),
+                Some(session) => (session.code, None),
+                None => ([0; C], None),
+            }
+        };
+        let ack = Ack::<ML, SL, UL, P::ID> {
+            fmt:        MsgFmt {
+                            syntax,
+                            encoding: constant::DEFAULT_MSG_ENCODING,
+                        },
+            pow:        MsgPow { zbits: res!(self.required_zbits(peer_addr)) },
+            mid:        MsgIds { sid_opt, uid: self.uid.clone() },
+            msg_id:     mid.to_byte_array().to_vec(),
+            received:   reliable::ack_bitmap(num_chunks, received),
+        };
+        ack.send::<C, P::W>(
+            trg,
+            peer_addr,
+            peer_code,
+            res!(self.peer_zbits(peer_addr)),
+            self.schms.clone(),
+        )
+    }
+
+    /// The proof of work code the peer at the given address requires of me, if I have a session
+    /// with it, otherwise zero.
+    pub fn session_code(
+        &self,
+        sessions:   &SessionMap<C, ML, SL, UL, P::ID>,
+        peer_addr:  &SocketAddr,
+    )
+        -> Outcome<[u8; C]>
+    {
+        let unlocked_sessions = lock_read!(sessions);
+        Ok(match unlocked_sessions.get(peer_addr) {
+            Some(session) => session.code,
+            None => [0; C],
         })
     }
+
+    /// Send the given discovery requests.
+    pub fn discover(
+        &self,
+        trg:        Arc<UdpSocket>,
+        sessions:   &SessionMap<C, ML, SL, UL, P::ID>,
+        syntax:     SyntaxRef,
+        actions:    Vec<DiscoveryAction>,
+    )
+        -> Outcome<()>
+    {
+        for action in actions {
+            let peer_addr = match &action {
+                DiscoveryAction::Ping { addr, .. } |
+                DiscoveryAction::FindNode { addr, .. } => *addr,
+            };
+            // Answers from the peer should not be treated as coming from an unknown address.
+            res!(self.agrd.register_new(&peer_addr, AddressData {
+                your_zbits: constant::POW_INITIAL_ZERO_BITS,
+                ..Default::default()
+            }));
+            let fmt = MsgFmt {
+                syntax:     syntax.clone(),
+                encoding:   constant::DEFAULT_MSG_ENCODING,
+            };
+            let pow = MsgPow { zbits: res!(self.request_zbits(&peer_addr)) };
+            let mid = MsgIds { sid_opt: None, uid: self.uid.clone() };
+            let code = res!(self.session_code(sessions, &peer_addr));
+            let zbits = res!(self.peer_zbits(&peer_addr));
+            match action {
+                DiscoveryAction::Ping { nonce, .. } => res!(self.dispatch(
+                    Ping::<ML, SL, UL, P::ID> { fmt, pow, mid, nonce: nonce.to_vec() },
+                    trg.clone(),
+                    &peer_addr,
+                    code,
+                    zbits,
+                )),
+                DiscoveryAction::FindNode { nonce, target, .. } => res!(self.dispatch(
+                    FindNode::<ML, SL, UL, P::ID> {
+                        fmt,
+                        pow,
+                        mid,
+                        nonce:  nonce.to_vec(),
+                        target: target.to_vec(),
+                    },
+                    trg.clone(),
+                    &peer_addr,
+                    code,
+                    zbits,
+                )),
+            }
+        }
+        Ok(())
+    }
+
+    /// Expire unanswered discovery requests, and send any pings and lookups that are due.
+    /// Called regularly from the server loop.
+    pub fn discovery_tick(
+        &self,
+        trg:        Arc<UdpSocket>,
+        sessions:   &SessionMap<C, ML, SL, UL, P::ID>,
+        syntax:     SyntaxRef,
+    )
+        -> Outcome<()>
+    {
+        let actions = {
+            let mut unlocked_discovery = lock_write!(self.discovery);
+            unlocked_discovery.tick(Instant::now())
+        };
+        self.discover(trg, sessions, syntax, actions)
+    }
+
+    /// Send any packets due for transmission or retransmission, and forget old completed
+    /// messages.  Called regularly from the server loop.
+    pub fn reliable_tick(&self, trg: &UdpSocket) -> Outcome<()> {
+        let now = Instant::now();
+        let poll = {
+            let mut unlocked_reliable = lock_write!(self.reliable);
+            unlocked_reliable.garbage_collection(now);
+            unlocked_reliable.poll(now)
+        };
+        for (peer_addr, mid) in poll.abandoned {
+            warn!(async_log::stream(), "Abandoning delivery of message {:02x?} to {:?} after \
+                too many retransmissions.", mid, peer_addr);
+        }
+        for (peer_addr, packet) in poll.packets {
+            res!(trg.send_to(&packet, peer_addr));
+        }
+        Ok(())
+    }
 }
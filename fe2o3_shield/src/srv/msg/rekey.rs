@@ -0,0 +1,602 @@
+//! Renewal of keys within an established session.
+//!
+//! The session key is renewed periodically by the session initiator, and on demand by either
+//! peer.  X sends a fresh key exchange public key in a `RekeyReq`, and Y answers with a new session
+//! key encapsulated using it in a `RekeyResp`.  Both messages are sealed with the session key like
+//! session data, so they are protected against replay and cannot be forged by a third party.  Each
+//! peer retains the previous session key until the next renewal, so that session messages already
+//! in flight can still be opened.
+//!
+//! ```ignore
+//!
+//!     PEER X                                                     PEER Y
+//!        |                                                          |
+//!   generate KEM key pair                                           |
+//!        +>>>>>>>>>>>>>>>>> RekeyReq [kem pk] >>>>>>>>>>>>>>>>>>>>>>|  encapsulate new key,
+//!        |                                                          |  answer, then switch.
+//!        |<<<<<<<<<<<<<<<<< RekeyResp [kem ct] <<<<<<<<<<<<<<<<<<<<<+
+//!   decapsulate new key,                                            |
+//!   switch.                                                         |
+//!        |                                                          |
+//! ```
+//!
+//! A peer also announces a change of its packet signing key to each peer with whom it has an
+//! established session, in a `KeyRotate` carrying the new public key.  The statement that the key
+//! of the user has changed from the old key to the new one is signed with both keys, proving
+//! continuity from the old key and possession of the new one, and the packet itself is signed
+//! with the old key.  The receiver checks both signatures against the old key it has on record,
+//! then records the new key as current and the old key as old.
+//!
+//! The `KeyRotate` is always delivered reliably.  The sender keeps signing its packets with the
+//! old key until every peer has acknowledged the announcement, or delivery to it has been
+//! abandoned, and only then adopts the new key.  The receiver meanwhile continues to accept
+//! packets signed with the old key for a grace period, so that packets already in flight, and
+//! repeats of the announcement itself, are not dropped.
+use crate::{
+    srv::{
+        constant,
+        msg::{
+            core::{
+                IdentifiedMessage,
+                IdTypes,
+                MsgType,
+                MsgFmt,
+                MsgIds,
+                MsgPow,
+            },
+            encode::ShieldCommand,
+            handshake::{
+                bytes_arg,
+                new_msg,
+            },
+            protocol::{
+                Protocol,
+                ProtocolTypes,
+            },
+        },
+        session::SessionMap,
+    },
+};
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    byte::{
+        IntoBytes,
+        ToByteArray,
+    },
+};
+use oxedyne_fe2o3_crypto::{
+    enc::EncryptionScheme,
+    kem::KeyExchangeScheme,
+    keys::PublicKey,
+};
+use oxedyne_fe2o3_iop_crypto::{
+    kem::KeyExchanger,
+    keys::KeyManager,
+    sign::Signer,
+};
+use oxedyne_fe2o3_jdat::prelude::*;
+use oxedyne_fe2o3_syntax::{
+    msg::{
+        Msg,
+        MsgCmd,
+    },
+};
+
+use std::{
+    net::{
+        SocketAddr,
+        UdpSocket,
+    },
+    sync::Arc,
+    time::Instant,
+};
+
+
+#[repr(u16)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RekeyType {
+    Unknown = 0,
+    Req     = 13, // Follows the discovery message types.
+    Resp    = 14,
+    Rotate  = 15,
+}
+
+impl From<MsgType> for RekeyType {
+    fn from(u: MsgType) -> Self {
+        match u {
+            13 =>   Self::Req,
+            14 =>   Self::Resp,
+            15 =>   Self::Rotate,
+            _ =>    Self::Unknown,
+        }
+    }
+}
+
+const KEY_ROTATION_LABEL: &[u8] = b"keyrot";
+
+/// The bytes signed by both the old and new signing keys of a user announcing a key rotation.
+pub fn rotation_statement(uid: &[u8], old_sigpk: &[u8], new_sigpk: &[u8]) -> Vec<u8> {
+    let mut byts = Vec::with_capacity(
+        KEY_ROTATION_LABEL.len() + 6 + uid.len() + old_sigpk.len() + new_sigpk.len());
+    byts.extend_from_slice(KEY_ROTATION_LABEL);
+    for part in [uid, old_sigpk, new_sigpk] {
+        byts.extend_from_slice(&(part.len() as u16).to_be_bytes());
+        byts.extend_from_slice(part);
+    }
+    byts
+}
+
+// RekeyReq ====================================================================
+/// X asks Y to renew the session key, sending a fresh key exchange public key sealed with the
+/// current session key.
+#[derive(Clone, Debug, Default)]
+pub struct RekeyReq<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+> {
+    pub fmt:    MsgFmt,
+    pub pow:    MsgPow,
+    pub mid:    MsgIds<SL, UL, ID::S, ID::U>,
+    // Command-specific
+    pub cipher: Vec<u8>, // Key exchange public key, sealed.
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    IntoBytes for RekeyReq<ML, SL, UL, ID>
+{
+    fn into_bytes(self, buf: Vec<u8>) -> Outcome<Vec<u8>> {
+        res!(self.construct()).into_bytes(buf)
+    }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    IdentifiedMessage for RekeyReq<ML, SL, UL, ID>
+{
+    fn typ(&self) -> MsgType { RekeyType::Req as MsgType }
+    fn name(&self) -> &'static str { "rekeyreq" }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    ShieldCommand<ML, SL, UL, ID> for RekeyReq<ML, SL, UL, ID>
+{
+    fn fmt(&self) -> &MsgFmt { &self.fmt }
+    fn pow(&self) -> &MsgPow { &self.pow }
+    fn mid(&self) -> &MsgIds<SL, UL, ID::S, ID::U> { &self.mid }
+    fn inc_sigpk(&self) -> bool { false }
+
+    fn construct(self) -> Outcome<Msg> {
+        let (mut msg, mut mcmd) = res!(new_msg(&self));
+        mcmd = res!(mcmd.add_arg_val("-ct", Some(Dat::BC64(self.cipher))));
+        msg = res!(msg.add_cmd(mcmd));
+        res!(msg.validate());
+        Ok(msg)
+    }
+
+    fn deconstruct(
+        &mut self,
+        mcmd: &mut MsgCmd,
+    )
+        -> Outcome<()>
+    {
+        self.cipher = res!(bytes_arg(mcmd, "-ct", "sealed key exchange public key"));
+        Ok(())
+    }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    RekeyReq<ML, SL, UL, ID>
+{
+    /// Y encapsulates a new session key using the public key from X, seals it with the key X
+    /// used, replies with a RekeyResp and switches to the new key.  When both peers ask at once,
+    /// the request of the session initiator prevails.
+    pub fn respond<
+        const C: usize,
+        P: ProtocolTypes<ML, SL, UL, ID = ID> + 'static,
+    >(
+        &mut self,
+        mcmd:       &mut MsgCmd,
+        protocol:   &Protocol<C, ML, SL, UL, P>,
+        sessions:   &SessionMap<C, ML, SL, UL, ID>,
+        src_addr:   &SocketAddr,
+        trg:        Arc<UdpSocket>,
+    )
+        -> Outcome<()>
+    {
+        res!(self.deconstruct(mcmd));
+        let (peer_code, sid, cipher) = {
+            let mut unlocked_sessions = lock_write!(sessions);
+            match unlocked_sessions.get_mut(src_addr) {
+                Some(session) if
+                    session.is_established()
+                    && session.peer_uid == Some(self.uid())
+                    && session.sid_opt.is_some()
+                    && session.sid_opt == self.sid_opt() =>
+                {
+                    let (kem_pk, prev) = match res!(protocol.open_session_msg(session, &self.cipher)) {
+                        Some(opened) => opened,
+                        None => {
+                            debug!(async_log::stream(), "Dropping replayed rekey request from {:?}.",
+                                src_addr);
+                            return Ok(());
+                        },
+                    };
+                    if session.kem_opt.is_some() {
+                        if session.initiator {
+                            debug!(async_log::stream(), "Ignoring rekey request from {:?} while \
+                                awaiting the response to my own.", src_addr);
+                            return Ok(());
+                        }
+                        session.kem_opt = None; // Yield to the initiator.
+                    }
+                    let kem_pk = res!(
+                        <[u8; KeyExchangeScheme::FIRESABER_PK_LEN]>::try_from(&kem_pk[..]),
+                        Decode, Bytes,
+                    );
+                    let kem = res!(KeyExchangeScheme::new_firesaber_with_keys(Some(&kem_pk), None));
+                    let (sess_key, kem_ct) = res!(kem.encap::<
+                        {KeyExchangeScheme::FIRESABER_PK_LEN},
+                        {KeyExchangeScheme::FIRESABER_SESSION_KEY_LEN},
+                        {KeyExchangeScheme::FIRESABER_CIPHERTEXT_LEN},
+                    >(kem_pk));
+                    let enc = if prev {
+                        res!(protocol.previous_session_encrypter(session))
+                    } else {
+                        res!(protocol.session_encrypter(session))
+                    };
+                    let cipher = res!(session.seal(&enc, &kem_ct));
+                    session.rekey(res!(EncryptionScheme::new_aes_256_gcm_with_key(&sess_key)), prev);
+                    (session.code, session.sid_opt, cipher)
+                },
+                _ => {
+                    debug!(async_log::stream(), "Dropping rekey request from {:?} outside of a \
+                        session.", src_addr);
+                    return Ok(());
+                },
+            }
+        };
+        let response = RekeyResp::<ML, SL, UL, ID> {
+            fmt:    self.fmt.clone(),
+            pow:    MsgPow { zbits: res!(protocol.required_zbits(src_addr)) },
+            mid:    MsgIds { sid_opt: sid, uid: protocol.uid.clone() },
+            cipher,
+        };
+        protocol.dispatch(
+            response,
+            trg,
+            src_addr,
+            peer_code,
+            res!(protocol.peer_zbits(src_addr)),
+        )
+    }
+}
+
+// RekeyResp ===================================================================
+/// Y sends X the new session key, encapsulated using the public key from X, and sealed with the
+/// current session key.
+#[derive(Clone, Debug, Default)]
+pub struct RekeyResp<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+> {
+    pub fmt:    MsgFmt,
+    pub pow:    MsgPow,
+    pub mid:    MsgIds<SL, UL, ID::S, ID::U>,
+    // Command-specific
+    pub cipher: Vec<u8>, // Encapsulated session key, sealed.
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    IntoBytes for RekeyResp<ML, SL, UL, ID>
+{
+    fn into_bytes(self, buf: Vec<u8>) -> Outcome<Vec<u8>> {
+        res!(self.construct()).into_bytes(buf)
+    }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    IdentifiedMessage for RekeyResp<ML, SL, UL, ID>
+{
+    fn typ(&self) -> MsgType { RekeyType::Resp as MsgType }
+    fn name(&self) -> &'static str { "rekeyresp" }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    ShieldCommand<ML, SL, UL, ID> for RekeyResp<ML, SL, UL, ID>
+{
+    fn fmt(&self) -> &MsgFmt { &self.fmt }
+    fn pow(&self) -> &MsgPow { &self.pow }
+    fn mid(&self) -> &MsgIds<SL, UL, ID::S, ID::U> { &self.mid }
+    fn inc_sigpk(&self) -> bool { false }
+
+    fn construct(self) -> Outcome<Msg> {
+        let (mut msg, mut mcmd) = res!(new_msg(&self));
+        mcmd = res!(mcmd.add_arg_val("-ct", Some(Dat::BC64(self.cipher))));
+        msg = res!(msg.add_cmd(mcmd));
+        res!(msg.validate());
+        Ok(msg)
+    }
+
+    fn deconstruct(
+        &mut self,
+        mcmd: &mut MsgCmd,
+    )
+        -> Outcome<()>
+    {
+        self.cipher = res!(bytes_arg(mcmd, "-ct", "sealed encapsulated session key"));
+        Ok(())
+    }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    RekeyResp<ML, SL, UL, ID>
+{
+    /// X decapsulates the new session key and switches to it.
+    pub fn respond<
+        const C: usize,
+        P: ProtocolTypes<ML, SL, UL, ID = ID> + 'static,
+    >(
+        &mut self,
+        mcmd:       &mut MsgCmd,
+        protocol:   &Protocol<C, ML, SL, UL, P>,
+        sessions:   &SessionMap<C, ML, SL, UL, ID>,
+        src_addr:   &SocketAddr,
+        _trg:       Arc<UdpSocket>,
+    )
+        -> Outcome<()>
+    {
+        res!(self.deconstruct(mcmd));
+        let mut unlocked_sessions = lock_write!(sessions);
+        match unlocked_sessions.get_mut(src_addr) {
+            Some(session) if
+                session.is_established()
+                && session.peer_uid == Some(self.uid())
+                && session.sid_opt.is_some()
+                && session.sid_opt == self.sid_opt() =>
+            {
+                let kem_ct = match res!(protocol.open_session_msg(session, &self.cipher)) {
+                    Some((kem_ct, _)) => kem_ct,
+                    None => {
+                        debug!(async_log::stream(), "Dropping replayed rekey response from {:?}.",
+                            src_addr);
+                        return Ok(());
+                    },
+                };
+                let kem = match session.kem_opt.take() {
+                    Some(kem) => kem,
+                    None => {
+                        debug!(async_log::stream(), "Dropping unexpected rekey response from {:?}.",
+                            src_addr);
+                        return Ok(());
+                    },
+                };
+                let kem_ct = res!(
+                    <[u8; KeyExchangeScheme::FIRESABER_CIPHERTEXT_LEN]>::try_from(&kem_ct[..]),
+                    Decode, Bytes,
+                );
+                let sess_key = res!(kem.decap::<
+                    {KeyExchangeScheme::FIRESABER_SESSION_KEY_LEN},
+                    {KeyExchangeScheme::FIRESABER_CIPHERTEXT_LEN},
+                >(kem_ct));
+                session.rekey(res!(EncryptionScheme::new_aes_256_gcm_with_key(&sess_key)), false);
+                debug!(async_log::stream(), "Renewed the session key with {:?}.", src_addr);
+            },
+            _ => debug!(async_log::stream(), "Dropping rekey response from {:?} outside of a \
+                session.", src_addr),
+        }
+        Ok(())
+    }
+}
+
+// KeyRotate ===================================================================
+/// A new packet signing key that has been announced to peers, but not yet adopted.
+#[derive(Clone, Debug)]
+pub struct PendingRotation<const ML: usize, S> {
+    pub signer:     S,
+    pub sigpk:      Vec<u8>,
+    pub announced:  Vec<(SocketAddr, [u8; ML])>, // The peers notified, and the message ids used.
+}
+
+/// X announces a new packet signing key to Y, signing the rotation statement with both its old
+/// and new keys.
+#[derive(Clone, Debug, Default)]
+pub struct KeyRotate<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+> {
+    pub fmt:        MsgFmt,
+    pub pow:        MsgPow,
+    pub mid:        MsgIds<SL, UL, ID::S, ID::U>,
+    // Command-specific
+    pub new_sigpk:  Vec<u8>, // My new public signing key.
+    pub old_sig:    Vec<u8>, // Rotation statement signed with my old key.
+    pub new_sig:    Vec<u8>, // Rotation statement signed with my new key.
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    IntoBytes for KeyRotate<ML, SL, UL, ID>
+{
+    fn into_bytes(self, buf: Vec<u8>) -> Outcome<Vec<u8>> {
+        res!(self.construct()).into_bytes(buf)
+    }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    IdentifiedMessage for KeyRotate<ML, SL, UL, ID>
+{
+    fn typ(&self) -> MsgType { RekeyType::Rotate as MsgType }
+    fn name(&self) -> &'static str { "keyrot" }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    ShieldCommand<ML, SL, UL, ID> for KeyRotate<ML, SL, UL, ID>
+{
+    fn fmt(&self) -> &MsgFmt { &self.fmt }
+    fn pow(&self) -> &MsgPow { &self.pow }
+    fn mid(&self) -> &MsgIds<SL, UL, ID::S, ID::U> { &self.mid }
+    fn inc_sigpk(&self) -> bool { false }
+
+    fn construct(self) -> Outcome<Msg> {
+        let (mut msg, mut mcmd) = res!(new_msg(&self));
+        mcmd = res!(mcmd.add_arg_val("-npk", Some(Dat::BC64(self.new_sigpk))));
+        mcmd = res!(mcmd.add_arg_val("-osg", Some(Dat::BC64(self.old_sig))));
+        mcmd = res!(mcmd.add_arg_val("-nsg", Some(Dat::BC64(self.new_sig))));
+        msg = res!(msg.add_cmd(mcmd));
+        res!(msg.validate());
+        Ok(msg)
+    }
+
+    fn deconstruct(
+        &mut self,
+        mcmd: &mut MsgCmd,
+    )
+        -> Outcome<()>
+    {
+        self.new_sigpk = res!(bytes_arg(mcmd, "-npk", "new public signing key"));
+        self.old_sig = res!(bytes_arg(mcmd, "-osg", "old key rotation signature"));
+        self.new_sig = res!(bytes_arg(mcmd, "-nsg", "new key rotation signature"));
+        Ok(())
+    }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    KeyRotate<ML, SL, UL, ID>
+{
+    /// Y verifies the rotation statement using both the key it has on record for X and the new
+    /// key, and if both signatures are valid, records the new key as current.  The packet has
+    /// already been verified using the key on record.
+    pub fn respond<
+        const C: usize,
+        P: ProtocolTypes<ML, SL, UL, ID = ID> + 'static,
+    >(
+        &mut self,
+        mcmd:       &mut MsgCmd,
+        protocol:   &Protocol<C, ML, SL, UL, P>,
+        sessions:   &SessionMap<C, ML, SL, UL, ID>,
+        src_addr:   &SocketAddr,
+        _trg:       Arc<UdpSocket>,
+    )
+        -> Outcome<()>
+    {
+        res!(self.deconstruct(mcmd));
+        let uid = self.uid();
+        {
+            let unlocked_sessions = lock_read!(sessions);
+            match unlocked_sessions.get(src_addr) {
+                Some(session) if
+                    session.is_established()
+                    && session.peer_uid == Some(uid)
+                    && session.sid_opt.is_some()
+                    && session.sid_opt == self.sid_opt() => (),
+                _ => {
+                    debug!(async_log::stream(), "Dropping key rotation from {:?} outside of a \
+                        session.", src_addr);
+                    return Ok(());
+                },
+            }
+        }
+        let (ukey, locked_umap) = res!(protocol.ugrd.get_locked_map(&uid));
+        let mut unlocked_umap = lock_write!(locked_umap);
+        let ulog = match unlocked_umap.get_mut(&ukey) {
+            Some(ulog) => ulog,
+            None => return Err(err!(
+                "No UserLog entry for {:?}, which should have been created \
+                by the UserGuard::drop_packet call.", uid;
+                Bug, Missing)),
+        };
+        let old = match &ulog.data.sigtpk_opt {
+            Some(old) => old.clone(),
+            None => {
+                debug!(async_log::stream(), "Dropping key rotation from {:?}, for which I have \
+                    no key on record.", src_addr);
+                return Ok(());
+            },
+        };
+        if old.key == self.new_sigpk {
+            return Ok(()); // Already recorded.
+        }
+        let statement = rotation_statement(&uid.to_byte_array(), &old.key, &self.new_sigpk);
+        let old_verifier = res!(protocol.schms.sign.clone_with_keys(Some(&old.key), None));
+        let new_verifier = res!(protocol.schms.sign.clone_with_keys(Some(&self.new_sigpk), None));
+        if !res!(old_verifier.verify(&statement, &self.old_sig))
+            || !res!(new_verifier.verify(&statement, &self.new_sig))
+        {
+            let offences = res!(protocol.agrd.offend(src_addr));
+            debug!(async_log::stream(), "Dropping key rotation from {:?} with an invalid \
+                signature, {} offences.", src_addr, offences);
+            return Ok(());
+        }
+        ulog.data.sigtpk_opt = Some(res!(PublicKey::now(old.sts.id.clone(), self.new_sigpk.clone())));
+        ulog.data.sigtpk_opt_old = Some(old);
+        ulog.data.sigtpk_old_until = Some(Instant::now() + constant::KEY_ROTATION_GRACE);
+        info!(async_log::stream(), "Recorded a new signing key for user {:?} at {:?}.",
+            uid, src_addr);
+        Ok(())
+    }
+}
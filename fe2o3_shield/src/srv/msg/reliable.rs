@@ -0,0 +1,538 @@
+//! Optional reliable delivery over UDP.  Messages of the types listed in the `ServerConfig` are
+//! held by the sender until the receiver acknowledges every chunk.  The receiver sends an `Ack`
+//! listing the chunk indices it holds each time a packet of such a message arrives, so the
+//! acknowledgement is selective.  Unacknowledged packets are retransmitted with an exponentially
+//! increasing timeout, and the number of packets in flight to each peer is limited by a simple
+//! additive increase, multiplicative decrease congestion window.  The receiver remembers the
+//! messages it has completed for a while, acknowledging and dropping any further copies.  A
+//! `KeyRotate` is always delivered reliably, regardless of the configured types.
+//!
+//! ```ignore
+//!
+//!     PEER X                                                     PEER Y
+//!        |                                                          |
+//!        +>>>>>>>>>>>>>>>>>>> packets 0, 1, 2 >>>>>>>>>>>>>>>>>>>>>>|  1 lost.
+//!        |<<<<<<<<<<<<<<<<<<<<< Ack [0] <<<<<<<<<<<<<<<<<<<<<<<<<<<<+
+//!        |<<<<<<<<<<<<<<<<<<<<< Ack [0, 2] <<<<<<<<<<<<<<<<<<<<<<<<<+
+//!        |                                                          |
+//!   timeout, halve window                                           |
+//!        |                                                          |
+//!        +>>>>>>>>>>>>>>>>>>>>>>> packet 1 >>>>>>>>>>>>>>>>>>>>>>>>>|  Message complete.
+//!        |<<<<<<<<<<<<<<<<<<<<< Ack [0, 1, 2] <<<<<<<<<<<<<<<<<<<<<<+
+//!        |                                                          |
+//! ```
+use crate::{
+    srv::{
+        msg::{
+            core::{
+                IdentifiedMessage,
+                IdTypes,
+                MsgType,
+                MsgFmt,
+                MsgIds,
+                MsgPow,
+            },
+            data::SessionType,
+            encode::ShieldCommand,
+            handshake::{
+                bytes_arg,
+                new_msg,
+            },
+            packet::PacketCount,
+            rekey::RekeyType,
+            protocol::{
+                Protocol,
+                ProtocolTypes,
+            },
+        },
+        session::SessionMap,
+    },
+};
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    byte::IntoBytes,
+};
+use oxedyne_fe2o3_jdat::prelude::*;
+use oxedyne_fe2o3_syntax::{
+    msg::{
+        Msg,
+        MsgCmd,
+    },
+};
+
+use std::{
+    collections::{
+        BTreeMap,
+        BTreeSet,
+    },
+    net::{
+        SocketAddr,
+        UdpSocket,
+    },
+    sync::Arc,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+
+#[repr(u16)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DeliveryType {
+    Unknown = 0,
+    Ack     = 8, // Follows the session message types.
+}
+
+impl From<MsgType> for DeliveryType {
+    fn from(u: MsgType) -> Self {
+        match u {
+            8 =>    Self::Ack,
+            _ =>    Self::Unknown,
+        }
+    }
+}
+
+/// The message type for the given command name, for those that may be delivered reliably.  Only
+/// session messages qualify, since an acknowledgement must carry the proof of work code and
+/// signature the sender expects, which are not settled until the handshake completes.
+/// Acknowledgements are never themselves acknowledged.
+pub fn msg_type_for_name(name: &str) -> Option<MsgType> {
+    match name {
+        "data"      => Some(SessionType::Data as MsgType),
+        "app"       => Some(SessionType::App as MsgType),
+        _ => None,
+    }
+}
+
+/// Encode the received chunk indices as a bitmap, with bit `i % 8` of byte `i / 8` set when
+/// chunk `i` has been received.
+pub fn ack_bitmap<I: IntoIterator<Item = PacketCount>>(
+    num_chunks: PacketCount,
+    received:   I,
+)
+    -> Vec<u8>
+{
+    let mut bitmap = vec![0u8; (num_chunks as usize + 7) / 8];
+    for i in received {
+        let i = i as usize;
+        if i / 8 < bitmap.len() {
+            bitmap[i / 8] |= 1 << (i % 8);
+        }
+    }
+    bitmap
+}
+
+pub fn ack_bitmap_has(bitmap: &[u8], i: usize) -> bool {
+    match bitmap.get(i / 8) {
+        Some(byt) => byt & (1 << (i % 8)) != 0,
+        None => false,
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ReliableParams {
+    pub msg_types:      BTreeSet<MsgType>,
+    pub rto_init:       Duration, // Initial retransmission timeout.
+    pub rto_max:        Duration, // Ceiling for the retransmission timeout under backoff.
+    pub retries_max:    u8, // Retransmissions of a packet before the message is abandoned.
+    pub cwnd_init:      u32, // Initial congestion window, in packets.
+    pub cwnd_max:       u32,
+    pub done_sunset:    Duration, // How long completed messages are remembered.
+}
+
+impl Default for ReliableParams {
+    fn default() -> Self {
+        Self {
+            msg_types:      BTreeSet::new(),
+            rto_init:       Duration::from_millis(200),
+            rto_max:        Duration::from_secs(5),
+            retries_max:    8,
+            cwnd_init:      4,
+            cwnd_max:       256,
+            done_sunset:    Duration::from_secs(60),
+        }
+    }
+}
+
+impl ReliableParams {
+
+    /// Whether messages of the given type are delivered reliably.  A `KeyRotate` always is,
+    /// since a peer that misses it can no longer verify my packets.
+    pub fn is_reliable(&self, typ: MsgType) -> bool {
+        self.msg_types.contains(&typ) || typ == RekeyType::Rotate as MsgType
+    }
+
+    /// The retransmission timeout after the given number of transmissions of a packet.
+    pub fn rto(&self, tries: u8) -> Duration {
+        let factor = 1u32.checked_shl(tries.saturating_sub(1) as u32).unwrap_or(u32::MAX);
+        std::cmp::min(self.rto_init.saturating_mul(factor), self.rto_max)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct OutboundPacket {
+    byts:   Vec<u8>,
+    acked:  bool,
+    tries:  u8,
+    due:    Option<Instant>, // When the packet should next be retransmitted, once sent.
+}
+
+#[derive(Clone, Debug)]
+struct OutboundMsg<const ML: usize> {
+    peer:       SocketAddr,
+    mid:        [u8; ML],
+    packets:    Vec<OutboundPacket>,
+}
+
+impl<const ML: usize> OutboundMsg<ML> {
+    fn in_flight(&self) -> u32 {
+        self.packets.iter().filter(|p| p.tries > 0 && !p.acked).count() as u32
+    }
+}
+
+/// Congestion control state for a peer.  The window grows by one packet per acknowledged packet
+/// until it reaches the slow start threshold, then by one packet per window, and is halved when a
+/// retransmission timeout expires.
+#[derive(Clone, Debug)]
+pub struct Congestion {
+    pub cwnd:       u32,
+    pub ssthresh:   u32,
+    acks:           u32,
+}
+
+impl Congestion {
+
+    fn new(params: &ReliableParams) -> Self {
+        Self {
+            cwnd:       params.cwnd_init,
+            ssthresh:   params.cwnd_max,
+            acks:       0,
+        }
+    }
+
+    fn on_ack(&mut self, n: u32, cwnd_max: u32) {
+        for _ in 0..n {
+            if self.cwnd < self.ssthresh {
+                self.cwnd += 1;
+            } else {
+                self.acks += 1;
+                if self.acks >= self.cwnd {
+                    self.cwnd += 1;
+                    self.acks = 0;
+                }
+            }
+        }
+        self.cwnd = std::cmp::min(self.cwnd, cwnd_max);
+    }
+
+    fn on_loss(&mut self) {
+        self.cwnd = std::cmp::max(self.cwnd / 2, 1);
+        self.ssthresh = self.cwnd;
+        self.acks = 0;
+    }
+}
+
+/// Packets to be sent, and messages abandoned after too many retransmissions.
+#[derive(Clone, Debug, Default)]
+pub struct ReliablePoll<const ML: usize> {
+    pub packets:    Vec<(SocketAddr, Vec<u8>)>,
+    pub abandoned:  Vec<(SocketAddr, [u8; ML])>,
+}
+
+/// The reliable delivery state of a server, shared by its packet handlers.
+#[derive(Clone, Debug)]
+pub struct ReliableDelivery<const ML: usize> {
+    pub params: ReliableParams,
+    outbox:     Vec<OutboundMsg<ML>>, // In the order sent.
+    peers:      BTreeMap<SocketAddr, Congestion>,
+    done:       BTreeMap<(SocketAddr, [u8; ML]), (PacketCount, Instant)>,
+}
+
+impl<const ML: usize> ReliableDelivery<ML> {
+
+    pub fn new(params: ReliableParams) -> Self {
+        Self {
+            params,
+            outbox: Vec::new(),
+            peers:  BTreeMap::new(),
+            done:   BTreeMap::new(),
+        }
+    }
+
+    /// The number of messages awaiting acknowledgement.
+    pub fn pending(&self) -> usize {
+        self.outbox.len()
+    }
+
+    /// Whether the given message to the peer awaits acknowledgement, having been neither
+    /// delivered nor abandoned.
+    pub fn is_pending(&self, peer: &SocketAddr, mid: &[u8; ML]) -> bool {
+        self.outbox.iter().any(|m| m.peer == *peer && m.mid == *mid)
+    }
+
+    pub fn congestion(&self, peer: &SocketAddr) -> Option<&Congestion> {
+        self.peers.get(peer)
+    }
+
+    /// Hold the packets of a message until they are all acknowledged.  They are sent by the next
+    /// call to `poll`.
+    pub fn register(
+        &mut self,
+        peer:       SocketAddr,
+        mid:        [u8; ML],
+        packets:    Vec<Vec<u8>>,
+    ) {
+        if !self.peers.contains_key(&peer) {
+            self.peers.insert(peer, Congestion::new(&self.params));
+        }
+        self.outbox.push(OutboundMsg {
+            peer,
+            mid,
+            packets: packets.into_iter().map(|byts| OutboundPacket {
+                byts,
+                acked:  false,
+                tries:  0,
+                due:    None,
+            }).collect(),
+        });
+    }
+
+    /// Collect the packets due for transmission at the given time.  Packets whose retransmission
+    /// timeout has expired are resent, and new packets are sent while the congestion window for
+    /// the peer allows.
+    pub fn poll(&mut self, now: Instant) -> ReliablePoll<ML> {
+        let mut result = ReliablePoll::default();
+        let mut lossy = BTreeSet::new();
+        let mut in_flight = BTreeMap::new();
+        for msg in &self.outbox {
+            *in_flight.entry(msg.peer).or_insert(0u32) += msg.in_flight();
+        }
+        let params = &self.params;
+        let peers = &self.peers;
+        self.outbox.retain_mut(|msg| {
+            let cwnd = peers.get(&msg.peer).map(|c| c.cwnd).unwrap_or(params.cwnd_init);
+            let flying = in_flight.entry(msg.peer).or_insert(0);
+            let mut sends = Vec::new();
+            for packet in msg.packets.iter_mut().filter(|p| !p.acked) {
+                match packet.due {
+                    Some(due) if now >= due => {
+                        if packet.tries > params.retries_max {
+                            result.abandoned.push((msg.peer, msg.mid));
+                            *flying = flying.saturating_sub(msg.in_flight());
+                            return false;
+                        }
+                        lossy.insert(msg.peer);
+                        packet.tries += 1;
+                        packet.due = Some(now + params.rto(packet.tries));
+                        sends.push(packet.byts.clone());
+                    },
+                    Some(_) => (),
+                    None => if *flying < cwnd {
+                        *flying += 1;
+                        packet.tries = 1;
+                        packet.due = Some(now + params.rto(1));
+                        sends.push(packet.byts.clone());
+                    },
+                }
+            }
+            for byts in sends {
+                result.packets.push((msg.peer, byts));
+            }
+            true
+        });
+        for peer in lossy {
+            if let Some(congestion) = self.peers.get_mut(&peer) {
+                congestion.on_loss();
+            }
+        }
+        result
+    }
+
+    /// Record the chunks of a message that the peer has acknowledged, returning whether the
+    /// message has been completely delivered.
+    pub fn acknowledge(
+        &mut self,
+        peer:   &SocketAddr,
+        mid:    &[u8; ML],
+        bitmap: &[u8],
+    )
+        -> bool
+    {
+        let pos = match self.outbox.iter().position(|m| m.peer == *peer && m.mid == *mid) {
+            Some(pos) => pos,
+            None => return false, // Already complete or abandoned.
+        };
+        let msg = &mut self.outbox[pos];
+        let mut newly = 0;
+        for (i, packet) in msg.packets.iter_mut().enumerate() {
+            if !packet.acked && ack_bitmap_has(bitmap, i) {
+                packet.acked = true;
+                newly += 1;
+            }
+        }
+        let complete = msg.packets.iter().all(|p| p.acked);
+        if let Some(congestion) = self.peers.get_mut(peer) {
+            congestion.on_ack(newly, self.params.cwnd_max);
+        }
+        if complete {
+            self.outbox.remove(pos);
+        }
+        complete
+    }
+
+    /// The number of chunks in a message from the peer that has already been received in full.
+    pub fn completed(&self, peer: &SocketAddr, mid: &[u8; ML]) -> Option<PacketCount> {
+        self.done.get(&(*peer, *mid)).map(|(n, _)| *n)
+    }
+
+    /// Remember that a message from the peer has been received in full, so that any further
+    /// copies can be dropped.
+    pub fn complete(
+        &mut self,
+        peer:       SocketAddr,
+        mid:        [u8; ML],
+        num_chunks: PacketCount,
+        now:        Instant,
+    ) {
+        self.done.insert((peer, mid), (num_chunks, now));
+    }
+
+    pub fn garbage_collection(&mut self, now: Instant) {
+        let sunset = self.params.done_sunset;
+        self.done.retain(|_, (_, when)| now.duration_since(*when) < sunset);
+    }
+}
+
+// Ack =========================================================================
+/// Acknowledges the chunks received of a reliably delivered message.
+#[derive(Clone, Debug, Default)]
+pub struct Ack<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+> {
+    pub fmt:        MsgFmt,
+    pub pow:        MsgPow,
+    pub mid:        MsgIds<SL, UL, ID::S, ID::U>,
+    // Command-specific
+    pub msg_id:     Vec<u8>, // Id of the message acknowledged.
+    pub received:   Vec<u8>, // Bitmap of the chunks received.
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    IntoBytes for Ack<ML, SL, UL, ID>
+{
+    fn into_bytes(self, buf: Vec<u8>) -> Outcome<Vec<u8>> {
+        res!(self.construct()).into_bytes(buf)
+    }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    IdentifiedMessage for Ack<ML, SL, UL, ID>
+{
+    fn typ(&self) -> MsgType { DeliveryType::Ack as MsgType }
+    fn name(&self) -> &'static str { "ack" }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    ShieldCommand<ML, SL, UL, ID> for Ack<ML, SL, UL, ID>
+{
+    fn fmt(&self) -> &MsgFmt { &self.fmt }
+    fn pow(&self) -> &MsgPow { &self.pow }
+    fn mid(&self) -> &MsgIds<SL, UL, ID::S, ID::U> { &self.mid }
+    fn inc_sigpk(&self) -> bool { false }
+
+    fn construct(self) -> Outcome<Msg> {
+        let (mut msg, mut mcmd) = res!(new_msg(&self));
+        mcmd = res!(mcmd.add_arg_val("-mi", Some(Dat::BC64(self.msg_id))));
+        mcmd = res!(mcmd.add_arg_val("-rc", Some(Dat::BC64(self.received))));
+        msg = res!(msg.add_cmd(mcmd));
+        res!(msg.validate());
+        Ok(msg)
+    }
+
+    fn deconstruct(
+        &mut self,
+        mcmd: &mut MsgCmd,
+    )
+        -> Outcome<()>
+    {
+        self.msg_id = res!(bytes_arg(mcmd, "-mi", "acknowledged message id"));
+        self.received = res!(bytes_arg(mcmd, "-rc", "received chunks"));
+        Ok(())
+    }
+}
+
+impl<
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    Ack<ML, SL, UL, ID>
+{
+    /// Record the acknowledged chunks, releasing the message once they have all been received.
+    pub fn respond<
+        const C: usize,
+        P: ProtocolTypes<ML, SL, UL, ID = ID> + 'static,
+    >(
+        &mut self,
+        mcmd:       &mut MsgCmd,
+        protocol:   &Protocol<C, ML, SL, UL, P>,
+        sessions :  &SessionMap<C, ML, SL, UL, ID>,
+        src_addr:   &SocketAddr,
+        _trg:       Arc<UdpSocket>,
+    )
+        -> Outcome<()>
+    {
+        res!(self.deconstruct(mcmd));
+        let mid = match <[u8; ML]>::try_from(&self.msg_id[..]) {
+            Ok(mid) => mid,
+            Err(_) => return Err(err!(
+                "Acknowledged message id from {:?} has {} bytes, expecting {}.",
+                src_addr, self.msg_id.len(), ML;
+                Input, Invalid)),
+        };
+        let complete = {
+            let mut unlocked_reliable = lock_write!(protocol.reliable);
+            unlocked_reliable.acknowledge(src_addr, &mid, &self.received)
+        };
+        if complete {
+            debug!(async_log::stream(), "Message {:02x?} delivered to {:?}.", mid, src_addr);
+        }
+        // An acknowledgement carrying the id of our session shows that the peer still holds it.
+        {
+            let mut unlocked_sessions = lock_write!(sessions);
+            match unlocked_sessions.get_mut(src_addr) {
+                Some(session) if
+                    session.is_established()
+                    && session.peer_uid == Some(self.uid())
+                    && session.sid_opt.is_some()
+                    && session.sid_opt == self.sid_opt() =>
+                {
+                    session.active = Instant::now();
+                },
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+}
@@ -51,14 +51,14 @@ pub fn base_msg() -> Outcome<SyntaxRef> {
         help:   Some(fmt!("Session identifier")),
         ..Default::default()
     });
-    //let arg_pow_code = Arg::from(ArgConfig {
-    //    name:   fmt!("PowCode"),
-    //    hyph1:  fmt!("pc"),
-    //    hyph2:  Some(fmt!("pow-code")),
-    //    evals:  vec![Kind::BC64],
-    //    help:   Some(fmt!("Use this proof of work code for packets")),
-    //    ..Default::default()
-    //});
+    let arg_pow_code = Arg::from(ArgConfig {
+        name:   fmt!("PowCode"),
+        hyph1:  fmt!("pc"),
+        hyph2:  Some(fmt!("pow-code")),
+        vals:   vec![(Kind::BC64, fmt!("Code"))],
+        help:   Some(fmt!("Use this proof of work code for packets")),
+        ..Default::default()
+    });
     let arg_pow_zbits = Arg::from(ArgConfig {
         name:   fmt!("PowZeroBits"),
         hyph1:  fmt!("zb"),
@@ -140,19 +140,29 @@ pub fn base_msg() -> Outcome<SyntaxRef> {
     //    help:   Some(fmt!("Send signing public key")),
     //    ..Default::default()
     //});
-    c = res!(c.add_arg(arg_pow_zbits.clone().required(true)));
+    c = res!(c.add_arg(arg_pow_code.clone().required(true)));
     c = res!(c.add_arg(arg_your_pack_sign_pk)); // My version of your signature public key.
     s = res!(s.add_cmd(c));
 
     // HReq2 ==================================================================
     //
-    let c = Cmd::from(CmdConfig {
+    let mut c = Cmd::from(CmdConfig {
         name:   fmt!("hreq2"),
         help:   Some(fmt!("Second handshake request")),
         ..Default::default()
     });
+    let arg_kem_pk = Arg::from(ArgConfig {
+        name:   fmt!("KemPublicKey"),
+        hyph1:  fmt!("kpk"),
+        hyph2:  Some(fmt!("kem-pk")),
+        vals:   vec![(Kind::BC64, fmt!("Public key"))],
+        help:   Some(fmt!("Key exchange public key for session")),
+        ..Default::default()
+    });
     //c = res!(c.add_arg(arg_sign_pk.clone().required(false)));
     //c = res!(c.add_arg(arg_sign.clone().required(true)));
+    c = res!(c.add_arg(arg_pow_code.required(true)));
+    c = res!(c.add_arg(arg_kem_pk.required(true)));
     s = res!(s.add_cmd(c));
 
     // HResp2 =================================================================
@@ -167,11 +177,240 @@ pub fn base_msg() -> Outcome<SyntaxRef> {
         hyph1:  fmt!("sk"),
         hyph2:  Some(fmt!("sym-key")),
         vals:   vec![(Kind::BC64, fmt!("Private key"))],
-        help:   Some(fmt!("Encrypted symmetric encryption key for session")),
+        help:   Some(fmt!("Encapsulated symmetric encryption key for session")),
+        ..Default::default()
+    });
+    let arg_sid_enc = Arg::from(ArgConfig {
+        name:   fmt!("EncSessionId"),
+        hyph1:  fmt!("es"),
+        hyph2:  Some(fmt!("enc-sid")),
+        vals:   vec![(Kind::BC64, fmt!("Ciphertext"))],
+        help:   Some(fmt!("Session identifier encrypted with the session key")),
         ..Default::default()
     });
     c = res!(c.add_arg(arg_skey_enc.required(true)));
+    c = res!(c.add_arg(arg_sid_enc.required(true)));
+    s = res!(s.add_cmd(c));
+
+    let arg_confirm = Arg::from(ArgConfig {
+        name:   fmt!("Confirmation"),
+        hyph1:  fmt!("cf"),
+        hyph2:  Some(fmt!("confirm")),
+        vals:   vec![(Kind::BC64, fmt!("Ciphertext"))],
+        help:   Some(fmt!("Hash of the session identifier encrypted with the session key")),
+        ..Default::default()
+    });
+
+    // HReq3 ==================================================================
+    //
+    let mut c = Cmd::from(CmdConfig {
+        name:   fmt!("hreq3"),
+        help:   Some(fmt!("Third and final handshake request")),
+        ..Default::default()
+    });
+    c = res!(c.add_arg(arg_confirm.clone().required(true)));
+    s = res!(s.add_cmd(c));
+
+    // HResp3 =================================================================
+    //
+    let mut c = Cmd::from(CmdConfig {
+        name:   fmt!("hresp3"),
+        help:   Some(fmt!("Third and final handshake response")),
+        ..Default::default()
+    });
+    c = res!(c.add_arg(arg_confirm.required(true)));
     s = res!(s.add_cmd(c));
 
+    // Data ===================================================================
+    //
+    let mut c = Cmd::from(CmdConfig {
+        name:   fmt!("data"),
+        help:   Some(fmt!("Session data")),
+        ..Default::default()
+    });
+    let arg_cipher = Arg::from(ArgConfig {
+        name:   fmt!("Ciphertext"),
+        hyph1:  fmt!("ct"),
+        hyph2:  Some(fmt!("ciphertext")),
+        vals:   vec![(Kind::BC64, fmt!("Ciphertext"))],
+        help:   Some(fmt!("Payload encrypted with the session key")),
+        ..Default::default()
+    });
+    c = res!(c.add_arg(arg_cipher.clone().required(true)));
+    s = res!(s.add_cmd(c));
+
+    let mut c = Cmd::from(CmdConfig {
+        name:   fmt!("app"),
+        help:   Some(fmt!("Session application message")),
+        ..Default::default()
+    });
+    c = res!(c.add_arg(arg_cipher.clone().required(true)));
+    s = res!(s.add_cmd(c));
+
+    // Ack ====================================================================
+    //
+    let mut c = Cmd::from(CmdConfig {
+        name:   fmt!("ack"),
+        help:   Some(fmt!("Acknowledgement of reliably delivered message chunks")),
+        ..Default::default()
+    });
+    let arg_msg_id = Arg::from(ArgConfig {
+        name:   fmt!("MsgId"),
+        hyph1:  fmt!("mi"),
+        hyph2:  Some(fmt!("msg-id")),
+        vals:   vec![(Kind::BC64, fmt!("Message id"))],
+        help:   Some(fmt!("Id of the message being acknowledged")),
+        ..Default::default()
+    });
+    let arg_received = Arg::from(ArgConfig {
+        name:   fmt!("Received"),
+        hyph1:  fmt!("rc"),
+        hyph2:  Some(fmt!("received")),
+        vals:   vec![(Kind::BC64, fmt!("Bitmap"))],
+        help:   Some(fmt!("Bitmap of the message chunk indices received")),
+        ..Default::default()
+    });
+    c = res!(c.add_arg(arg_msg_id.required(true)));
+    c = res!(c.add_arg(arg_received.required(true)));
+    s = res!(s.add_cmd(c));
+
+    // Discovery ==============================================================
+    //
+    let arg_nonce = Arg::from(ArgConfig {
+        name:   fmt!("Nonce"),
+        hyph1:  fmt!("nc"),
+        hyph2:  Some(fmt!("nonce")),
+        vals:   vec![(Kind::BC64, fmt!("Nonce"))],
+        help:   Some(fmt!("Random value echoed in the answer to a discovery request")),
+        ..Default::default()
+    });
+
+    let mut c = Cmd::from(CmdConfig {
+        name:   fmt!("ping"),
+        help:   Some(fmt!("Discovery request for proof that a peer is alive")),
+        ..Default::default()
+    });
+    c = res!(c.add_arg(arg_nonce.clone().required(true)));
+    s = res!(s.add_cmd(c));
+
+    let mut c = Cmd::from(CmdConfig {
+        name:   fmt!("pong"),
+        help:   Some(fmt!("Discovery answer to a ping")),
+        ..Default::default()
+    });
+    c = res!(c.add_arg(arg_nonce.clone().required(true)));
+    s = res!(s.add_cmd(c));
+
+    let mut c = Cmd::from(CmdConfig {
+        name:   fmt!("findnode"),
+        help:   Some(fmt!("Discovery request for the peers closest to a target")),
+        ..Default::default()
+    });
+    let arg_target = Arg::from(ArgConfig {
+        name:   fmt!("Target"),
+        hyph1:  fmt!("tg"),
+        hyph2:  Some(fmt!("target")),
+        vals:   vec![(Kind::BC64, fmt!("Peer id"))],
+        help:   Some(fmt!("Peer identifier to find the closest peers to")),
+        ..Default::default()
+    });
+    c = res!(c.add_arg(arg_nonce.clone().required(true)));
+    c = res!(c.add_arg(arg_target.required(true)));
+    s = res!(s.add_cmd(c));
+
+    let mut c = Cmd::from(CmdConfig {
+        name:   fmt!("peers"),
+        help:   Some(fmt!("Discovery answer listing the peers closest to a target")),
+        ..Default::default()
+    });
+    let arg_peer_list = Arg::from(ArgConfig {
+        name:   fmt!("PeerList"),
+        hyph1:  fmt!("pl"),
+        hyph2:  Some(fmt!("peer-list")),
+        vals:   vec![(Kind::BC64, fmt!("Encoded list"))],
+        help:   Some(fmt!("Peer identifiers and addresses")),
+        ..Default::default()
+    });
+    let arg_list_sig = Arg::from(ArgConfig {
+        name:   fmt!("PeerListSignature"),
+        hyph1:  fmt!("sg"),
+        hyph2:  Some(fmt!("signature")),
+        vals:   vec![(Kind::BC64, fmt!("Signature"))],
+        help:   Some(fmt!("Signature of the request nonce and peer list")),
+        ..Default::default()
+    });
+    c = res!(c.add_arg(arg_nonce.required(true)));
+    c = res!(c.add_arg(arg_peer_list.required(true)));
+    c = res!(c.add_arg(arg_list_sig.required(true)));
+    s = res!(s.add_cmd(c));
+
+    // Rekey ==================================================================
+    //
+    let mut c = Cmd::from(CmdConfig {
+        name:   fmt!("rekeyreq"),
+        help:   Some(fmt!("Session key renewal request")),
+        ..Default::default()
+    });
+    c = res!(c.add_arg(arg_cipher.clone().required(true)));
+    s = res!(s.add_cmd(c));
+
+    let mut c = Cmd::from(CmdConfig {
+        name:   fmt!("rekeyresp"),
+        help:   Some(fmt!("Session key renewal response")),
+        ..Default::default()
+    });
+    c = res!(c.add_arg(arg_cipher.required(true)));
+    s = res!(s.add_cmd(c));
+
+    let mut c = Cmd::from(CmdConfig {
+        name:   fmt!("keyrot"),
+        help:   Some(fmt!("Announcement of a new packet signing key")),
+        ..Default::default()
+    });
+    let arg_new_sigpk = Arg::from(ArgConfig {
+        name:   fmt!("NewPublicSigningKey"),
+        hyph1:  fmt!("npk"),
+        hyph2:  Some(fmt!("new-sign-pk")),
+        vals:   vec![(Kind::BC64, fmt!("Public key"))],
+        help:   Some(fmt!("My new packet public signing key")),
+        ..Default::default()
+    });
+    let arg_old_sig = Arg::from(ArgConfig {
+        name:   fmt!("OldKeySignature"),
+        hyph1:  fmt!("osg"),
+        hyph2:  Some(fmt!("old-signature")),
+        vals:   vec![(Kind::BC64, fmt!("Signature"))],
+        help:   Some(fmt!("Signature of the key rotation using my old key")),
+        ..Default::default()
+    });
+    let arg_new_sig = Arg::from(ArgConfig {
+        name:   fmt!("NewKeySignature"),
+        hyph1:  fmt!("nsg"),
+        hyph2:  Some(fmt!("new-signature")),
+        vals:   vec![(Kind::BC64, fmt!("Signature"))],
+        help:   Some(fmt!("Signature of the key rotation using my new key")),
+        ..Default::default()
+    });
+    c = res!(c.add_arg(arg_new_sigpk.required(true)));
+    c = res!(c.add_arg(arg_old_sig.required(true)));
+    c = res!(c.add_arg(arg_new_sig.required(true)));
+    s = res!(s.add_cmd(c));
+
+    Ok(SyntaxRef::new(s))
+}
+
+/// The syntax of application messages, consisting of the given commands, typically those of the
+/// `ShieldHandler`.  Application messages travel encrypted within the `app` command of a Shield
+/// message.
+pub fn app_msg(cmds: Vec<Cmd>) -> Outcome<SyntaxRef> {
+    let mut s = Syntax::from(SyntaxConfig {
+        name:   fmt!("Shield Application"),
+        ver:    constant::VERSION.clone(),
+        about:  Some(fmt!("Application messages within a Shield session")),
+        ..Default::default()
+    });
+    for c in cmds {
+        s = res!(s.add_cmd(c));
+    }
     Ok(SyntaxRef::new(s))
 }
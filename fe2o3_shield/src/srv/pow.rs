@@ -20,7 +20,9 @@ use std::{
 #[repr(u8)]
 #[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
 pub enum DifficultyProfile {
-    Linear  = 0,
+    Linear      = 0,
+    Exponential = 1,
+    Stepped     = 2,
 }
 
 impl TryFrom<u8> for DifficultyProfile {
@@ -29,33 +31,96 @@ impl TryFrom<u8> for DifficultyProfile {
     fn try_from(n: u8) -> std::result::Result<Self, Self::Error> {
         match n {
             0 => Ok(Self::Linear),
+            1 => Ok(Self::Exponential),
+            2 => Ok(Self::Stepped),
             _ => Err(err!(
                 "'{}' not recognised as a valid server_rps_zbits_profile configuration value, \
-                use a value in the range 0..0.", n;
+                use a value in the range 0..2.", n;
                 Invalid, Input)),
         }
     }
 }
 
+impl DifficultyProfile {
+    /// Map a load between zero and `load_max` onto the range of zero bits between `min` and
+    /// `max`.  The load is capped at `load_max`.
+    ///
+    /// - `Linear`: the zero bits rise in proportion to the load.
+    /// - `Exponential`: the zero bits rise slowly at low loads and steeply as the load approaches
+    ///   its maximum, reaching `max` at `load_max`.
+    /// - `Stepped`: the zero bits rise in `steps` equal increments, staying flat in between.
+    pub fn zbits(
+        &self,
+        min:        u16,
+        max:        u16,
+        load:       u64,
+        load_max:   u64,
+        steps:      u16,
+    )
+        -> Outcome<ZeroBits>
+    {
+        if max < min {
+            return Err(err!(
+                "The maximum zero bits, {}, is less than the minimum, {}.", max, min;
+                Invalid, Input));
+        }
+        if load_max == 0 {
+            return Err(err!("The maximum load must be greater than zero."; Invalid, Input));
+        }
+        let span = (max - min) as u64;
+        let load = std::cmp::min(load, load_max);
+        let extra = match self {
+            Self::Linear => (span * load) / load_max,
+            Self::Exponential => {
+                let x = load as f64 / load_max as f64;
+                let extra = ((span + 1) as f64).powf(x) - 1.0;
+                std::cmp::min(extra.floor() as u64, span)
+            },
+            Self::Stepped => {
+                if steps == 0 {
+                    return Err(err!(
+                        "The stepped difficulty profile requires at least one step.";
+                        Invalid, Input));
+                }
+                let steps = steps as u64;
+                let step = (load * steps) / load_max;
+                (span * step) / steps
+            },
+        };
+        Ok((min as u64 + extra) as ZeroBits)
+    }
+}
+
 /// Vary the required zero bits in proof of work hashes as a function of the requests-per-second
-/// using the given profile and min/max limits.
+/// using the given profile and min/max limits.  The same profile maps the recent offences of an
+/// address onto a difficulty specific to that address, so that a misbehaving address faces a
+/// rising requirement while well-behaved addresses pay only the global requirement.
 #[derive(Clone, Debug)]
 pub struct DifficultyParams {
-    pub profile:    DifficultyProfile,
-    pub max:        u16,
-    pub min:        u16,
-    pub rps_max:    u64,//u16
+    pub profile:        DifficultyProfile,
+    pub max:            u16,
+    pub min:            u16,
+    pub rps_max:        u64, // Requests per second at which the global requirement reaches max.
+    pub steps:          u16, // Increments used by the stepped profile.
+    pub offences_max:   u16, // Offences at which the requirement for an address reaches max.
 }
 
 impl DifficultyParams {
     #[inline(always)]
-    pub fn required_global_zbits(&self, rps: u16) -> Outcome<ZeroBits> {
-        match self.profile {
-            DifficultyProfile::Linear => Ok(
-                ((((self.max - self.min) * rps)
-                / self.max) + self.min) as ZeroBits
-            ),
-        }
+    pub fn required_global_zbits(&self, rps: u64) -> Outcome<ZeroBits> {
+        self.profile.zbits(self.min, self.max, rps, self.rps_max, self.steps)
+    }
+
+    /// The difficulty required of an address with the given number of recent offences.
+    #[inline(always)]
+    pub fn required_addr_zbits(&self, offences: u16) -> Outcome<ZeroBits> {
+        self.profile.zbits(
+            self.min,
+            self.max,
+            offences as u64,
+            self.offences_max as u64,
+            self.steps,
+        )
     }
 }
 
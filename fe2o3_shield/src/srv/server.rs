@@ -2,6 +2,7 @@ use crate::{
     srv::{
         constant,
         context::ServerContext,
+        guard::snapshot::GuardStatus,
         msg::{
             core::IdTypes,
             protocol::{
@@ -68,6 +69,10 @@ pub struct Server<
     syntax:     SyntaxRef,
     ma_gc_last: Instant,
     ma_gc_int:  Duration,
+    peers_last: Instant,
+    grd_last:   Instant, // Guard state last saved.
+    grd_exp:    Instant, // Guard blacklists last expired.
+    ses_exp:    Instant, // Idle sessions last dropped.
     cmd_chan:   Simplex<Command>,
 }
 
@@ -99,6 +104,10 @@ impl<
                 syntax,
                 ma_gc_last: Instant::now(),
                 ma_gc_int:  Duration::from_secs(300),
+                peers_last: Instant::now(),
+                grd_last:   Instant::now(),
+                grd_exp:    Instant::now(),
+                ses_exp:    Instant::now(),
                 cmd_chan,
             },
             cmd_chan_clone,
@@ -119,6 +128,34 @@ impl<
         info!(async_log::stream(), "Listening on UDP at {:?}.", trg_addr);
 
         res!(trg.set_read_timeout(Some(constant::SERVER_EXT_SOCKET_CHECK_INTERVAL)));
+
+        // Identity, without which peers would take me for a new user.
+        match res!(self.context.load_identity()) {
+            true => info!(async_log::stream(), "Loaded my identity from the database."),
+            false => (),
+        }
+
+        // Peer discovery.
+        match self.context.load_peers() {
+            Ok(0) => (),
+            Ok(count) => info!(async_log::stream(), "Loaded {} peers from the database.", count),
+            Err(e) => error!(async_log::stream(), err!(e,
+                "While loading the peer routing table."; IO, Read)),
+        }
+        {
+            let mut unlocked_discovery = lock_write!(self.context.protocol.discovery);
+            unlocked_discovery.seeds.retain(|seed| *seed != trg_addr); // Don't ask myself.
+        }
+
+        // Guard state.
+        match self.context.load_guards() {
+            Ok((0, 0)) => (),
+            Ok((addrs, users)) => info!(async_log::stream(),
+                "Restored the guard state of {} addresses and {} users from the database.",
+                addrs, users),
+            Err(e) => error!(async_log::stream(), err!(e,
+                "While loading the guard state."; IO, Read)),
+        }
     
         'main: loop {
             // Check internet port.
@@ -145,6 +182,7 @@ impl<
                         src_addr,
                         trg.clone(),
                         self.syntax.clone(),
+                        self.context.sessions.clone(),
                     ));
                     match result.await {
                         Ok(result) => match result {
@@ -158,6 +196,65 @@ impl<
                 },
             } // Receive udp packet.
 
+            // Reliable delivery retransmission.
+            if let Err(e) = self.context.protocol.reliable_tick(&trg) {
+                error!(async_log::stream(), err!(e,
+                    "While retransmitting unacknowledged packets."; IO, Network));
+            }
+
+            // Adoption of a new packet signing key, once announced.
+            match self.context.protocol.rotation_tick() {
+                Ok(true) => {
+                    info!(async_log::stream(), "Adopted the new packet signing key.");
+                    self.persist_identity();
+                },
+                Ok(false) => (),
+                Err(e) => error!(async_log::stream(), err!(e,
+                    "While adopting the new packet signing key."; IO, Network)),
+            }
+
+            // Peer discovery pings and lookups.
+            if let Err(e) = self.context.protocol.discovery_tick(
+                trg.clone(),
+                &self.context.sessions,
+                self.syntax.clone(),
+            ) {
+                error!(async_log::stream(), err!(e,
+                    "While sending peer discovery requests."; IO, Network));
+            }
+            if self.peers_last.elapsed() > constant::DISCOVERY_PERSIST_INTERVAL {
+                res!(self.persist_peers());
+            }
+
+            // Session key renewal.
+            if let Err(e) = self.context.protocol.rekey_tick(
+                trg.clone(),
+                &self.context.sessions,
+                self.syntax.clone(),
+            ) {
+                error!(async_log::stream(), err!(e,
+                    "While renewing session keys."; IO, Network));
+            }
+
+            // Idle session expiry.
+            if self.ses_exp.elapsed() > constant::SESSION_EXPIRY_INTERVAL {
+                self.ses_exp = Instant::now();
+                match self.context.protocol.expire_sessions(&self.context.sessions) {
+                    Ok(0) => (),
+                    Ok(count) => info!(async_log::stream(), "Dropped {} idle sessions.", count),
+                    Err(e) => error!(async_log::stream(), err!(e,
+                        "While dropping idle sessions."; IO, Network)),
+                }
+            }
+
+            // Guard blacklist expiry and persistence.
+            if self.grd_exp.elapsed() > constant::GUARD_EXPIRY_INTERVAL {
+                res!(self.expire_guards());
+            }
+            if self.grd_last.elapsed() > constant::GUARD_PERSIST_INTERVAL {
+                res!(self.persist_guards());
+            }
+
             // Message assembly garbage collection.
             if self.ma_gc_last.elapsed() > self.ma_gc_int {
                 let result = self.context.protocol.massembler
@@ -175,7 +272,89 @@ impl<
             'cmd: loop {
                 match self.cmd_chan.try_recv() {
                     Recv::Empty => break 'cmd,
-                    Recv::Result(Ok(Command::Finish)) => break 'main,
+                    Recv::Result(Ok(Command::Finish)) => {
+                        res!(self.persist_peers());
+                        res!(self.persist_guards());
+                        break 'main;
+                    },
+                    Recv::Result(Ok(Command::Connect(peer_addr))) => {
+                        info!(async_log::stream(), "Connecting to {:?}.", peer_addr);
+                        if let Err(e) = self.context.protocol.connect(
+                            trg.clone(),
+                            peer_addr,
+                            &self.context.sessions,
+                            self.syntax.clone(),
+                        ) {
+                            error!(async_log::stream(), err!(e,
+                                "While initiating handshake with {:?}.", peer_addr;
+                                IO, Network));
+                        }
+                    },
+                    Recv::Result(Ok(Command::Send(peer_addr, data))) => {
+                        if let Err(e) = self.context.protocol.send_data(
+                            trg.clone(),
+                            &peer_addr,
+                            &self.context.sessions,
+                            self.syntax.clone(),
+                            &data,
+                        ) {
+                            error!(async_log::stream(), err!(e,
+                                "While sending {} bytes of data to {:?}.", data.len(), peer_addr;
+                                IO, Network));
+                        }
+                    },
+                    Recv::Result(Ok(Command::SendMsg(peer_addr, msg))) => {
+                        if let Err(e) = self.context.protocol.send_app_msg(
+                            trg.clone(),
+                            &peer_addr,
+                            &self.context.sessions,
+                            self.syntax.clone(),
+                            msg,
+                        ) {
+                            error!(async_log::stream(), err!(e,
+                                "While sending an application message to {:?}.", peer_addr;
+                                IO, Network));
+                        }
+                    },
+                    Recv::Result(Ok(Command::Rekey(peer_addr))) => {
+                        if let Err(e) = self.context.protocol.rekey(
+                            trg.clone(),
+                            &peer_addr,
+                            &self.context.sessions,
+                            self.syntax.clone(),
+                        ) {
+                            error!(async_log::stream(), err!(e,
+                                "While renewing the session key with {:?}.", peer_addr;
+                                IO, Network));
+                        }
+                    },
+                    Recv::Result(Ok(Command::RotateKey(sigpk, sigsk))) => {
+                        match self.context.protocol.rotate_signing_key(
+                            trg.clone(),
+                            &self.context.sessions,
+                            self.syntax.clone(),
+                            &sigpk,
+                            &sigsk,
+                        ) {
+                            Ok(count) => {
+                                info!(async_log::stream(),
+                                    "Announcing a new packet signing key to {} peers.", count);
+                                if self.context.protocol.rotation_opt.is_none() {
+                                    // Adopted straight away, there being no one to notify.
+                                    self.persist_identity();
+                                }
+                            },
+                            Err(e) => error!(async_log::stream(), err!(e,
+                                "While rotating the packet signing key."; IO, Network)),
+                        }
+                    },
+                    Recv::Result(Ok(Command::Guard(change_opt, reply))) => {
+                        let result = self.administer_guards(change_opt);
+                        if let Err(e) = reply.send(result) {
+                            error!(async_log::stream(), err!(e,
+                                "While replying to a guard command."; Channel, Write));
+                        }
+                    },
                     Recv::Result(Ok(cmd)) => {
                         test!(async_log::stream(), "Server command received: {:?}", cmd);
                     }
@@ -187,4 +366,72 @@ impl<
 
         Ok(())
     }
+
+    /// Release addresses and users whose blacklisting has expired.
+    fn expire_guards(&mut self) -> Outcome<()> {
+        self.grd_exp = Instant::now();
+        let result = match self.context.protocol.agrd.expire() {
+            Ok(addrs) => self.context.protocol.ugrd.expire().map(|users| (addrs, users)),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok((0, 0)) => (),
+            Ok((addrs, users)) => info!(async_log::stream(),
+                "Blacklisting expired for {} addresses and {} users.", addrs, users),
+            Err(e) => error!(async_log::stream(), err!(e,
+                "While expiring guard blacklists."; Data)),
+        }
+        Ok(())
+    }
+
+    /// Apply a change of guard status from an operator, if any, saving it immediately, and
+    /// describe the resulting guard state.
+    fn administer_guards(
+        &mut self,
+        change_opt: Option<(String, GuardStatus)>,
+    )
+        -> Outcome<Vec<String>>
+    {
+        if let Some((target, status)) = change_opt {
+            res!(self.context.set_guard_status(&target, status));
+            info!(async_log::stream(), "Guard status of {} set to {}.", target, status);
+            self.grd_last = Instant::now();
+            res!(self.context.save_guards());
+        }
+        Ok(res!(self.context.guard_snapshot()).to_lines())
+    }
+
+    /// Save my identity, which has changed.
+    fn persist_identity(&self) {
+        if let Err(e) = self.context.save_identity() {
+            error!(async_log::stream(), err!(e,
+                "While saving my identity."; IO, Write));
+        }
+    }
+
+    /// Save the lasting state of the guards.
+    fn persist_guards(&mut self) -> Outcome<()> {
+        self.grd_last = Instant::now();
+        if let Err(e) = self.context.save_guards() {
+            error!(async_log::stream(), err!(e,
+                "While saving the guard state."; IO, Write));
+        }
+        Ok(())
+    }
+
+    /// Save the routing table if it has changed since it was last saved.
+    fn persist_peers(&mut self) -> Outcome<()> {
+        self.peers_last = Instant::now();
+        let dirty = {
+            let unlocked_discovery = lock_read!(self.context.protocol.discovery);
+            unlocked_discovery.dirty
+        };
+        if dirty {
+            if let Err(e) = self.context.save_peers() {
+                error!(async_log::stream(), err!(e,
+                    "While saving the peer routing table."; IO, Write));
+            }
+        }
+        Ok(())
+    }
 }
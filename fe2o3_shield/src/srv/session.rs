@@ -0,0 +1,192 @@
+use crate::srv::msg::core::IdTypes;
+
+use oxedyne_fe2o3_core::prelude::*;
+use oxedyne_fe2o3_crypto::{
+    enc::EncryptionScheme,
+    kem::KeyExchangeScheme,
+};
+use oxedyne_fe2o3_iop_crypto::enc::Encrypter;
+
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::{
+        Arc,
+        RwLock,
+    },
+    time::Instant,
+};
+
+
+/// The stage reached by a session, from the point of view of the local peer.  An initiator
+/// progresses through the `AwaitHResp*` states, a responder through the `AwaitHReq*` states.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SessionState {
+    AwaitHResp1,
+    AwaitHReq2,
+    AwaitHResp2,
+    AwaitHReq3,
+    AwaitHResp3,
+    Established,
+}
+
+/// Tracks the sequence numbers recently received from a peer, so that replayed session messages
+/// can be rejected while still tolerating some reordering by the network.
+#[derive(Clone, Debug, Default)]
+pub struct ReplayWindow {
+    top:    u64, // Highest sequence number received.
+    mask:   u64, // Bit i set when sequence number top - i has been received.
+}
+
+impl ReplayWindow {
+    pub const SIZE: u64 = 64;
+
+    /// Record the given sequence number, returning false if it has already been received or is
+    /// too old to tell.
+    pub fn accept(&mut self, seq: u64) -> bool {
+        if seq == 0 {
+            return false; // Sequence numbers start at one.
+        }
+        if seq > self.top {
+            let shift = seq - self.top;
+            self.mask = if shift >= Self::SIZE { 0 } else { self.mask << shift };
+            self.mask |= 1;
+            self.top = seq;
+            true
+        } else {
+            let offset = self.top - seq;
+            if offset >= Self::SIZE {
+                return false;
+            }
+            let bit = 1u64 << offset;
+            if self.mask & bit != 0 {
+                return false;
+            }
+            self.mask |= bit;
+            true
+        }
+    }
+}
+
+/// Per-peer session state, created when a handshake begins and kept in the
+/// [`crate::srv::context::ServerContext`] session map, keyed by the peer socket address.
+#[derive(Clone, Debug)]
+pub struct Session<
+    const C: usize,
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+> {
+    pub state:        SessionState,
+    pub initiator:    bool,
+    pub peer_uid:     Option<ID::U>,
+    pub sid_opt:      Option<ID::S>,
+    pub code:         [u8; C], // The proof of work code the peer requires of my packets.
+    pub kem_opt:      Option<KeyExchangeScheme>, // Held while awaiting a session key.
+    pub enc_opt:      Option<EncryptionScheme>, // Session encryption, once the key is shared.
+    pub enc_prev_opt: Option<EncryptionScheme>, // The previous session key, until the next renewal.
+    pub start:        Instant,
+    pub rekeyed:      Instant, // When the session key was last renewed, or renewal last requested.
+    pub active:       Instant, // When the session was established or a session message last opened.
+    pub tx_seq:       u64, // Sequence number of my next session message.
+    pub rx_win:       ReplayWindow, // Sequence numbers of your session messages.
+}
+
+impl<
+    const C: usize,
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID: IdTypes<ML, SL, UL>,
+>
+    Session<C, ML, SL, UL, ID>
+{
+    pub fn new(state: SessionState, initiator: bool) -> Self {
+        Self {
+            state,
+            initiator,
+            peer_uid:     None,
+            sid_opt:      None,
+            code:         [0; C],
+            kem_opt:      None,
+            enc_opt:      None,
+            enc_prev_opt: None,
+            start:        Instant::now(),
+            rekeyed:      Instant::now(),
+            active:       Instant::now(),
+            tx_seq:       1,
+            rx_win:       ReplayWindow::default(),
+        }
+    }
+
+    pub fn is_established(&self) -> bool {
+        self.state == SessionState::Established
+    }
+
+    /// Adopt a new session key.  The key the peer last used is retained so that their messages
+    /// already in flight can still be opened, which is normally the current key, unless they are
+    /// yet to adopt it.
+    pub fn rekey(&mut self, enc: EncryptionScheme, peer_on_prev: bool) {
+        if !peer_on_prev {
+            self.enc_prev_opt = self.enc_opt.take();
+        }
+        self.enc_opt = Some(enc);
+        self.kem_opt = None;
+        self.rekeyed = Instant::now();
+    }
+
+    /// Session message plaintext begins with a direction byte, so that a message cannot be
+    /// reflected back to its sender, followed by the sequence number.
+    const HEADER_LEN: usize = 9;
+
+    fn direction(initiator: bool) -> u8 {
+        if initiator { 0 } else { 1 }
+    }
+
+    /// Encrypt an outgoing session payload using the given encrypter, which should hold the
+    /// session key.
+    pub fn seal<E: Encrypter>(&mut self, enc: &E, data: &[u8]) -> Outcome<Vec<u8>> {
+        let seq = self.tx_seq;
+        self.tx_seq = match seq.checked_add(1) {
+            Some(n) => n,
+            None => return Err(err!(
+                "Session message sequence numbers exhausted, the session must be renewed.";
+                Overflow)),
+        };
+        let mut plain = Vec::with_capacity(Self::HEADER_LEN + data.len());
+        plain.push(Self::direction(self.initiator));
+        plain.extend_from_slice(&seq.to_be_bytes());
+        plain.extend_from_slice(data);
+        enc.encrypt(&plain)
+    }
+
+    /// Decrypt an incoming session payload using the given encrypter, which should hold the
+    /// session key.  Returns `None` when the message was sent in my direction, or has already
+    /// been received.
+    pub fn open<E: Encrypter>(&mut self, enc: &E, cipher: &[u8]) -> Outcome<Option<Vec<u8>>> {
+        let mut plain = res!(enc.decrypt(cipher));
+        if plain.len() < Self::HEADER_LEN {
+            return Err(err!(
+                "Session message plaintext of {} bytes is too short, expecting at least {}.",
+                plain.len(), Self::HEADER_LEN;
+                Input, Invalid));
+        }
+        if plain[0] != Self::direction(!self.initiator) {
+            return Ok(None);
+        }
+        let seq = u64::from_be_bytes(res!(<[u8; 8]>::try_from(&plain[1..Self::HEADER_LEN])));
+        if !self.rx_win.accept(seq) {
+            return Ok(None);
+        }
+        Ok(Some(plain.split_off(Self::HEADER_LEN)))
+    }
+}
+
+pub type SessionMap<
+    const C: usize,
+    const ML: usize,
+    const SL: usize,
+    const UL: usize,
+    ID,
+> = Arc<RwLock<BTreeMap<SocketAddr, Session<C, ML, SL, UL, ID>>>>;
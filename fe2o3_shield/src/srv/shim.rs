@@ -0,0 +1,285 @@
+//! A packet-forwarding shim for simulating network conditions between two peers on a single
+//! machine.  A `Link` binds a socket for each peer, standing in for the other.  Peer A sends to
+//! `Link::addr_a` to reach peer B, and the link forwards each packet from the socket at
+//! `Link::addr_b`, so that B sees the link as A and replies through it.  Every packet passes
+//! through a `PacketShim`, which decides how many copies to deliver, after what delay, and whether
+//! to alter them.  `LinkConditions` provides random loss, duplication, corruption, latency and
+//! reordering, and can be replaced while the link is running to script changing conditions.
+//!
+//! ```ignore
+//!
+//!     PEER A                         LINK                          PEER B
+//!        |                 addr_a           addr_b                    |
+//!        +>>>>>>>>>>>>>>>>>>>>+  PacketShim  +>>>>>>>>>>>>>>>>>>>>>>>>>>>|
+//!        |<<<<<<<<<<<<<<<<<<<<+  PacketShim  +<<<<<<<<<<<<<<<<<<<<<<<<<<<+
+//!        |                                                            |
+//! ```
+//!
+//! The proof of work pristine includes the source and target IP addresses, so the link and the
+//! peers must share the same IP address.
+//!
+//! The module is only built with the `sim` feature, which the integration tests enable.
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    rand::Rand,
+    thread::{
+        thread_channel,
+        Sentinel,
+    },
+};
+
+use std::{
+    fmt,
+    io,
+    net::{
+        IpAddr,
+        SocketAddr,
+        UdpSocket,
+    },
+    sync::{
+        Arc,
+        RwLock,
+    },
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LinkDirection {
+    AtoB,
+    BtoA,
+}
+
+/// Decides the fate of each packet crossing a `Link`.
+pub trait PacketShim: fmt::Debug + Send + Sync {
+    /// Return the copies of the packet to deliver, each with the delay before delivery.  An empty
+    /// result drops the packet.
+    fn shape(&mut self, dir: LinkDirection, packet: Vec<u8>) -> Vec<(Duration, Vec<u8>)>;
+}
+
+/// Random network conditions, applied independently to each packet.  Probabilities lie between
+/// 0.0 and 1.0.
+#[derive(Clone, Debug, Default)]
+pub struct LinkConditions {
+    pub loss:       f64,
+    pub duplicate:  f64,
+    pub corrupt:    f64, // Probability of flipping a random bit.
+    pub reorder:    f64, // Probability of holding a packet back by an extra latency period.
+    pub latency:    Duration,
+    pub jitter:     Duration, // Maximum additional random delay.
+}
+
+impl LinkConditions {
+
+    /// Perfect delivery.
+    pub fn ideal() -> Self {
+        Self::default()
+    }
+
+    fn chance(p: f64) -> bool {
+        p > 0.0 && Rand::value::<f64>() < p
+    }
+
+    fn delay(&self) -> Duration {
+        let mut delay = self.latency;
+        if !self.jitter.is_zero() {
+            delay += Duration::from_micros(Rand::in_range(0, self.jitter.as_micros() as u64));
+        }
+        if Self::chance(self.reorder) {
+            delay += std::cmp::max(self.latency, Duration::from_millis(1)) * 2;
+        }
+        delay
+    }
+}
+
+impl PacketShim for LinkConditions {
+    fn shape(&mut self, _dir: LinkDirection, mut packet: Vec<u8>) -> Vec<(Duration, Vec<u8>)> {
+        if Self::chance(self.loss) {
+            return Vec::new();
+        }
+        if Self::chance(self.corrupt) && !packet.is_empty() {
+            let i = Rand::in_range(0, packet.len() - 1);
+            packet[i] ^= 1 << Rand::in_range(0, 7);
+        }
+        let mut result = Vec::new();
+        if Self::chance(self.duplicate) {
+            result.push((self.delay(), packet.clone()));
+        }
+        result.push((self.delay(), packet));
+        result
+    }
+}
+
+/// Packet counts for a `Link`, in both directions.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct LinkStats {
+    pub received:   u64,
+    pub dropped:    u64,
+    pub duplicated: u64, // Extra copies created.
+    pub delivered:  u64,
+}
+
+pub struct Link {
+    pub addr_a: SocketAddr, // Peer A sends here to reach peer B.
+    pub addr_b: SocketAddr, // Peer B sends here to reach peer A.
+    shim:       Arc<RwLock<Box<dyn PacketShim>>>,
+    stats:      Arc<RwLock<LinkStats>>,
+    sentinel:   Sentinel,
+    handle:     Option<thread::JoinHandle<Outcome<()>>>,
+}
+
+impl Link {
+
+    /// Bind a link between the two peers on ephemeral ports at the given IP address, and start
+    /// forwarding.
+    pub fn new(
+        ip:     IpAddr,
+        peer_a: SocketAddr,
+        peer_b: SocketAddr,
+        shim:   Box<dyn PacketShim>,
+    )
+        -> Outcome<Self>
+    {
+        let sock_a = res!(UdpSocket::bind(SocketAddr::new(ip, 0)));
+        let sock_b = res!(UdpSocket::bind(SocketAddr::new(ip, 0)));
+        res!(sock_a.set_nonblocking(true));
+        res!(sock_b.set_nonblocking(true));
+        let addr_a = res!(sock_a.local_addr());
+        let addr_b = res!(sock_b.local_addr());
+        let shim = Arc::new(RwLock::new(shim));
+        let stats = Arc::new(RwLock::new(LinkStats::default()));
+        let (semaphore, sentinel) = thread_channel();
+        let forwarder = Forwarder {
+            sock_a,
+            sock_b,
+            peer_a,
+            peer_b,
+            shim:   shim.clone(),
+            stats:  stats.clone(),
+            queue:  Vec::new(),
+        };
+        let handle = thread::spawn(move || {
+            let mut forwarder = forwarder;
+            while semaphore.is_alive() {
+                if !res!(forwarder.step()) {
+                    thread::sleep(Duration::from_millis(1));
+                }
+            }
+            Ok(())
+        });
+        Ok(Self {
+            addr_a,
+            addr_b,
+            shim,
+            stats,
+            sentinel,
+            handle: Some(handle),
+        })
+    }
+
+    /// Replace the shim, affecting packets received from now on.
+    pub fn set_shim(&self, shim: Box<dyn PacketShim>) -> Outcome<()> {
+        let mut unlocked_shim = lock_write!(self.shim);
+        *unlocked_shim = shim;
+        Ok(())
+    }
+
+    pub fn stats(&self) -> Outcome<LinkStats> {
+        let unlocked_stats = lock_read!(self.stats);
+        Ok(unlocked_stats.clone())
+    }
+
+    /// Stop forwarding and wait for the forwarding thread to finish.
+    pub fn stop(&mut self) -> Outcome<()> {
+        self.sentinel.stop();
+        if let Some(handle) = self.handle.take() {
+            match handle.join() {
+                Ok(result) => res!(result),
+                Err(_) => return Err(err!(
+                    "The link forwarding thread panicked."; Thread, Unexpected)),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Link {
+    fn drop(&mut self) {
+        self.sentinel.stop();
+    }
+}
+
+struct Forwarder {
+    sock_a: UdpSocket,
+    sock_b: UdpSocket,
+    peer_a: SocketAddr,
+    peer_b: SocketAddr,
+    shim:   Arc<RwLock<Box<dyn PacketShim>>>,
+    stats:  Arc<RwLock<LinkStats>>,
+    queue:  Vec<(Instant, LinkDirection, Vec<u8>)>,
+}
+
+impl Forwarder {
+
+    /// Receive and deliver what packets are ready, returning whether there was anything to do.
+    fn step(&mut self) -> Outcome<bool> {
+        let mut busy = false;
+        let mut buf = [0u8; 65_536];
+        for dir in [LinkDirection::AtoB, LinkDirection::BtoA] {
+            let (sock, peer) = match dir {
+                LinkDirection::AtoB => (&self.sock_a, self.peer_a),
+                LinkDirection::BtoA => (&self.sock_b, self.peer_b),
+            };
+            match sock.recv_from(&mut buf) {
+                Ok((n, src)) => {
+                    busy = true;
+                    if src != peer {
+                        continue; // Not from the peer this side of the link serves.
+                    }
+                    let copies = {
+                        let mut unlocked_shim = lock_write!(self.shim);
+                        unlocked_shim.shape(dir, buf[..n].to_vec())
+                    };
+                    {
+                        let mut unlocked_stats = lock_write!(self.stats);
+                        unlocked_stats.received += 1;
+                        match copies.len() {
+                            0 => unlocked_stats.dropped += 1,
+                            n => unlocked_stats.duplicated += n as u64 - 1,
+                        }
+                    }
+                    let now = Instant::now();
+                    for (delay, packet) in copies {
+                        self.queue.push((now + delay, dir, packet));
+                    }
+                },
+                Err(e) => match e.kind() {
+                    io::ErrorKind::WouldBlock => (),
+                    _ => return Err(err!(e, "While receiving a packet on a link."; IO, Network)),
+                },
+            }
+        }
+        let now = Instant::now();
+        let mut i = 0;
+        while i < self.queue.len() {
+            if self.queue[i].0 <= now {
+                let (_, dir, packet) = self.queue.remove(i);
+                let (sock, trg) = match dir {
+                    LinkDirection::AtoB => (&self.sock_b, self.peer_b),
+                    LinkDirection::BtoA => (&self.sock_a, self.peer_a),
+                };
+                res!(sock.send_to(&packet, trg));
+                let mut unlocked_stats = lock_write!(self.stats);
+                unlocked_stats.delivered += 1;
+                busy = true;
+            } else {
+                i += 1;
+            }
+        }
+        Ok(busy)
+    }
+}
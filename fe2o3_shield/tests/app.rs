@@ -0,0 +1,212 @@
+use crate::handshake::{
+    HANDSHAKE_TIMEOUT,
+    TestPeer,
+    free_ports,
+    is_established,
+};
+
+use oxedyne_fe2o3_shield::srv::{
+    cfg::ServerConfig,
+    cmd::Command,
+    handler::{
+        ShieldEchoHandler,
+        ShieldHandler,
+    },
+    msg::syntax as srv_syntax,
+};
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    byte::{
+        Encoding,
+        ToBytes,
+    },
+    test::test_it,
+};
+use oxedyne_fe2o3_jdat::prelude::*;
+use oxedyne_fe2o3_syntax::{
+    SyntaxRef,
+    arg::{
+        Arg,
+        ArgConfig,
+    },
+    cmd::{
+        Cmd,
+        CmdConfig,
+    },
+    msg::{
+        Msg,
+        MsgCmd,
+    },
+};
+
+use std::{
+    net::SocketAddr,
+    sync::{
+        Arc,
+        RwLock,
+    },
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use local_ip_address::local_ip;
+
+
+const MSG_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Records the text of each note received.
+#[derive(Clone, Debug, Default)]
+struct NoteHandler {
+    notes: Arc<RwLock<Vec<(SocketAddr, String)>>>,
+}
+
+impl ShieldHandler for NoteHandler {
+
+    fn cmds(&self) -> Outcome<Vec<Cmd>> {
+        let mut c = Cmd::from(CmdConfig {
+            name:   fmt!("note"),
+            help:   Some(fmt!("A short note")),
+            ..Default::default()
+        });
+        c = res!(c.add_arg(Arg::from(ArgConfig {
+            name:   fmt!("text"),
+            hyph1:  fmt!("t"),
+            vals:   vec![(Kind::Str, fmt!("Text"))],
+            help:   Some(fmt!("Text of the note")),
+            ..Default::default()
+        }).required(true)));
+        Ok(vec![c])
+    }
+
+    fn handle(
+        &mut self,
+        mcmd:   MsgCmd,
+        peer:   &SocketAddr,
+        _syntax: SyntaxRef,
+    )
+        -> Outcome<Option<Msg>>
+    {
+        let text = match mcmd.get_arg_vals("-t") {
+            Some(vals) => try_extract_dat!(vals[0].clone(), Str),
+            None => return Err(err!("Note has no text."; Test, Missing)),
+        };
+        let mut unlocked_notes = lock_write!(self.notes);
+        unlocked_notes.push((*peer, text));
+        Ok(None)
+    }
+}
+
+fn note(syntax: &SyntaxRef, text: &str) -> Outcome<Msg> {
+    let msg = Msg::new(syntax.clone());
+    let mcmd = res!(res!(msg.new_cmd("note")).add_arg_val("-t", Some(Dat::Str(text.to_string()))));
+    msg.add_cmd(mcmd)
+}
+
+pub fn test_app(filter: &'static str) -> Outcome<()> {
+
+    res!(test_it(filter, &["App syntax 000", "all", "app", "syntax"], || {
+        let syntax = res!(srv_syntax::app_msg(res!(NoteHandler::default().cmds())));
+        req!(true, syntax.get_cmd("note").is_some());
+        // Protocol commands are not part of the application syntax.
+        req!(true, syntax.get_cmd("data").is_none());
+        // Application messages survive encoding.
+        let mut msg = res!(note(&syntax, "hello"));
+        msg.set_encoding(Encoding::Binary);
+        let byts = res!(msg.to_bytes(Vec::new()));
+        let msgrx = res!(Msg::new(syntax.clone()).from_bytes(&byts, None));
+        let text = match msgrx.get_cmd_arg_vals("note", "-t") {
+            Some(vals) => try_extract_dat!(vals[0].clone(), Str),
+            None => return Err(err!("Decoded note has no text."; Test, Missing)),
+        };
+        req!(fmt!("hello"), text);
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["App msg 000", "all", "app", "network"], || {
+        let rt = res!(tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(4)
+            .enable_all()
+            .build());
+        rt.block_on(run_test_app_msg())
+    }));
+
+    Ok(())
+}
+
+/// Peer A sends notes to peer B, which echoes them back to A's handler.
+pub async fn run_test_app_msg() -> Outcome<()> {
+
+    let ip_addr = res!(local_ip());
+    let [port_a, port_b] = res!(free_ports());
+    let addr_a = SocketAddr::new(ip_addr, port_a);
+    let addr_b = SocketAddr::new(ip_addr, port_b);
+
+    let handler_a = NoteHandler::default();
+    let notes = handler_a.notes.clone();
+    let cfg_a = ServerConfig {
+        server_port_udp: port_a,
+        ..Default::default()
+    };
+    let cfg_b = ServerConfig {
+        server_port_udp: port_b,
+        ..Default::default()
+    };
+    let (sessions_a, chan_a, protocol_a, handle_a) =
+        res!(TestPeer::new(cfg_a).with_handler(handler_a).start());
+    let (sessions_b, chan_b, _, handle_b) =
+        res!(TestPeer::new(cfg_b).with_handler(ShieldEchoHandler {
+            cmds: res!(NoteHandler::default().cmds()),
+        }).start());
+    let syntax = protocol_a.app_syntax.clone();
+    thread::sleep(Duration::from_millis(500)); // Allow the servers to bind.
+
+    res!(chan_a.send(Command::Connect(addr_b)));
+    let start = Instant::now();
+    while start.elapsed() < HANDSHAKE_TIMEOUT {
+        if res!(is_established(&sessions_a, &addr_b)) && res!(is_established(&sessions_b, &addr_a)) {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    // One short, and one long enough to be chunked.
+    let long = "0123456789".repeat(300);
+    res!(chan_a.send(Command::SendMsg(addr_b, res!(note(&syntax, "hello")))));
+    res!(chan_a.send(Command::SendMsg(addr_b, res!(note(&syntax, &long)))));
+
+    let start = Instant::now();
+    let mut received = Vec::new();
+    while start.elapsed() < MSG_TIMEOUT {
+        received = {
+            let unlocked_notes = lock_read!(notes);
+            unlocked_notes.clone()
+        };
+        if received.len() >= 2 {
+            test!("Echoed notes received after {:?}.", start.elapsed());
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    res!(chan_a.send(Command::Finish));
+    res!(chan_b.send(Command::Finish));
+    for handle in [handle_a, handle_b] {
+        match handle.await {
+            Ok(result) => res!(result),
+            Err(e) => return Err(err!(e, "While awaiting server task completion."; Async)),
+        }
+    }
+
+    req!(2, received.len());
+    let mut texts = received.iter().map(|(_, text)| text.clone()).collect::<Vec<_>>();
+    texts.sort();
+    req!(vec![long.clone(), fmt!("hello")], texts);
+    for (peer, _) in &received {
+        req!(addr_b, *peer);
+    }
+    Ok(())
+}
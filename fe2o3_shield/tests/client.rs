@@ -0,0 +1,154 @@
+use crate::handshake::{
+    TestDb,
+    TestPeer,
+    TestProtocol,
+    TestProtocolTypes,
+    free_ports,
+};
+
+use oxedyne_fe2o3_shield::srv::{
+    cfg::ServerConfig,
+    client::Client,
+    cmd::Command,
+    context::ServerContext,
+    msg::{
+        protocol::{
+            Protocol,
+            ProtocolMode,
+        },
+        syntax as srv_syntax,
+    },
+    schemes::WireSchemesInput,
+};
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    alt::Alt,
+    channels::Simplex,
+    path::NormalPath,
+    test::test_it,
+};
+use oxedyne_fe2o3_crypto::{
+    enc::EncryptionScheme,
+    sign::SignatureScheme,
+};
+use oxedyne_fe2o3_hash::{
+    csum::ChecksumScheme,
+    hash::HashScheme,
+};
+use oxedyne_fe2o3_net::id;
+
+use std::{
+    net::SocketAddr,
+    path::Path,
+    thread,
+    time::Duration,
+};
+
+use local_ip_address::local_ip;
+
+
+const WAIT: Duration = Duration::from_secs(30);
+
+pub type TestClient = Client<8, {id::MID_LEN}, {id::SID_LEN}, {id::UID_LEN}, TestProtocolTypes>;
+
+pub fn test_client(filter: &'static str) -> Outcome<()> {
+
+    res!(test_it(filter, &["Client 000", "all", "client", "network"], || {
+        let rt = res!(tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(4)
+            .enable_all()
+            .build());
+        rt.block_on(run_test_client())
+    }));
+
+    Ok(())
+}
+
+/// Start a client without a database, listening on the given port.
+pub fn start_client(port: u16) -> Outcome<TestClient> {
+    let cfg = ServerConfig {
+        server_port_udp: port,
+        ..Default::default()
+    };
+    let protocol = res!(Protocol::new(
+        &cfg,
+        WireSchemesInput {
+            enc:    Alt::Specific(Some(EncryptionScheme::new_aes_256_gcm())),
+            csum:   Alt::Specific(None::<ChecksumScheme>),
+            powh:   Alt::Specific(ServerConfig::default_packet_pow_hash_scheme()),
+            sign:   Alt::Specific(Some(SignatureScheme::new_ed25519())),
+            hsenc:  Alt::Specific(None::<EncryptionScheme>),
+            chnk:   Some(ServerConfig::new_chunk_cfg(1_000, 200, false, true)),
+        },
+        [0u8; 8],
+        id::Mid::default(),
+        id::Sid::default(),
+        id::Uid::default(),
+        ProtocolMode::Test,
+    ));
+    let context = ServerContext::<_, _, _, _, _, EncryptionScheme, HashScheme, TestDb>::new(
+        cfg,
+        Path::new(".").normalise().absolute(),
+        None,
+        protocol,
+    );
+    Client::start(context, res!(srv_syntax::base_msg()))
+}
+
+/// A client connects to a server, sends it a message and receives the reply.
+pub async fn run_test_client() -> Outcome<()> {
+
+    let ip_addr = res!(local_ip());
+    let [port_client, port_server] = res!(free_ports());
+    let server_addr = SocketAddr::new(ip_addr, port_server);
+
+    let (_, chan, protocol, handle) = res!(TestPeer::on_port(port_server).start());
+    let mut client = res!(start_client(port_client));
+    thread::sleep(Duration::from_millis(500)); // Allow the servers to bind.
+
+    let before = client.send(server_addr, b"too soon".to_vec()).is_err();
+    let result = exchange(&client, server_addr, &chan, &protocol).await;
+
+    res!(client.finish().await);
+    res!(chan.send(Command::Finish));
+    match handle.await {
+        Ok(result) => res!(result),
+        Err(e) => return Err(err!(e, "While awaiting server task completion."; Async)),
+    }
+
+    req!(true, before);
+    let (request, reply) = res!(result);
+    req!(b"ping".to_vec(), request);
+    req!(b"pong".to_vec(), reply);
+    Ok(())
+}
+
+async fn exchange(
+    client:         &TestClient,
+    server_addr:    SocketAddr,
+    chan:           &Simplex<Command>,
+    protocol:       &TestProtocol,
+)
+    -> Outcome<(Vec<u8>, Vec<u8>)>
+{
+    res!(client.connect(server_addr, WAIT).await);
+    test!("Client at {:?} connected to server at {:?}.", client.addr, server_addr);
+    res!(client.send(server_addr, b"ping".to_vec()));
+    let request = match res!(protocol.recv_data(WAIT)) {
+        Some(delivery) => {
+            req!(client.addr, delivery.peer);
+            delivery.data
+        },
+        None => return Err(err!("The server received nothing."; Test, Missing)),
+    };
+    res!(chan.send(Command::Send(client.addr, b"pong".to_vec())));
+    let reply = match res!(client.recv(WAIT).await) {
+        Some(delivery) => {
+            req!(server_addr, delivery.peer);
+            delivery.data
+        },
+        None => return Err(err!("The client received nothing."; Test, Missing)),
+    };
+    Ok((request, reply))
+}
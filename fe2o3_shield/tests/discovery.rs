@@ -0,0 +1,313 @@
+use crate::handshake::{
+    TestPeer,
+    cfg_without,
+    free_ports,
+};
+
+use oxedyne_fe2o3_shield::srv::{
+    cfg::ServerConfig,
+    cmd::Command,
+    constant,
+    context::new_db,
+    discovery::{
+        self,
+        DiscoveryAction,
+        DiscoveryParams,
+        Insertion,
+        PeerDiscovery,
+        PeerEntry,
+        PeerId,
+        RoutingTable,
+    },
+};
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    rand::Rand,
+    test::test_it,
+};
+use oxedyne_fe2o3_iop_db::api::Database;
+use oxedyne_fe2o3_jdat::prelude::*;
+
+use std::{
+    collections::BTreeSet,
+    fs,
+    net::SocketAddr,
+    thread,
+    time::{
+        Duration,
+        Instant,
+        SystemTime,
+    },
+};
+
+use local_ip_address::local_ip;
+
+
+const PEERS: usize = 5;
+const MESH_TIMEOUT: Duration = Duration::from_secs(30);
+const EVICT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A peer identifier with the given first byte, and the remaining bytes random.
+fn id_with(first: u8) -> PeerId {
+    let mut id = [0u8; discovery::PEER_ID_LEN];
+    Rand::fill_u8(&mut id);
+    id[0] = first;
+    id
+}
+
+fn entry(id: PeerId, port: u16) -> PeerEntry {
+    PeerEntry {
+        id,
+        addr:       SocketAddr::from(([127, 0, 0, 1], port)),
+        last_seen:  SystemTime::now(),
+        fails:      0,
+    }
+}
+
+fn test_params() -> DiscoveryParams {
+    DiscoveryParams {
+        k:              2,
+        alpha:          1,
+        ping_timeout:   Duration::from_millis(100),
+        stale_after:    Duration::from_secs(60),
+        refresh:        Duration::from_secs(60),
+        fails_max:      2,
+    }
+}
+
+pub fn test_discovery(filter: &'static str) -> Outcome<()> {
+
+    res!(test_it(filter, &["Routing table 000", "all", "table"], || {
+        let own = [0u8; discovery::PEER_ID_LEN];
+        let mut table = RoutingTable::new(own, 2);
+        req!(Insertion::Own, table.insert(entry(own, 1)));
+        // Identifiers starting 0b1... share no prefix with mine, 0b01... share one bit.
+        let (far1, far2, far3) = (id_with(0x80), id_with(0xc0), id_with(0xff));
+        let near = id_with(0x40);
+        req!(Some(0), discovery::bucket_index(&own, &far1));
+        req!(Some(1), discovery::bucket_index(&own, &near));
+        req!(Insertion::Added, table.insert(entry(far1, 1)));
+        req!(Insertion::Added, table.insert(entry(far2, 2)));
+        req!(Insertion::Added, table.insert(entry(near, 4)));
+        // The far bucket is full, offering its least recently seen peer for a ping.
+        match table.insert(entry(far3, 3)) {
+            Insertion::Full(lru) => req!(far1, lru.id),
+            other => return Err(err!("Unexpected insertion {:?}.", other; Test, Unexpected)),
+        }
+        // Seeing the first peer again moves it to the back.
+        req!(Insertion::Updated, table.seen(far1, SocketAddr::from(([127, 0, 0, 1], 5))));
+        match table.insert(entry(far3, 3)) {
+            Insertion::Full(lru) => req!(far2, lru.id),
+            other => return Err(err!("Unexpected insertion {:?}.", other; Test, Unexpected)),
+        }
+        req!(3, table.len());
+        let closest = table.closest(&own, 2);
+        req!(near, closest[0].id);
+        req!(2, closest.len());
+        // Persistence round trip.
+        let dat = res!(table.to_dat());
+        let mut table2 = RoutingTable::new(own, 2);
+        let count = res!(table2.load(dat));
+        req!(3, count);
+        req!(Some(5), table2.get(&far1).map(|e| e.addr.port()));
+        // Peer lists.
+        let peers = vec![(far1, entry(far1, 1).addr), (near, entry(near, 4).addr)];
+        let byts = res!(discovery::encode_peer_list(&peers));
+        let decoded = res!(discovery::decode_peer_list(&byts));
+        req!(peers, decoded);
+        let too_many: Vec<(PeerId, SocketAddr)> = (0..discovery::PEER_LIST_MAX + 1)
+            .map(|i| (id_with(i as u8), entry(far1, 1).addr))
+            .collect();
+        let byts = res!(discovery::encode_peer_list(&too_many));
+        req!(true, discovery::decode_peer_list(&byts).is_err());
+        // A configuration saved before peer discovery existed falls back to the defaults.
+        let cfg = res!(cfg_without(&["discovery_"]));
+        req!(ServerConfig::default(), cfg);
+        req!(true, cfg.discovery_params().is_ok());
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Peer discovery 000", "all", "table"], || {
+        let own = [0u8; discovery::PEER_ID_LEN];
+        let seed = SocketAddr::from(([127, 0, 0, 1], 1));
+        let mut disc = PeerDiscovery::new(own, test_params(), vec![seed]);
+        let now = Instant::now();
+        // The first tick pings and queries the seed.
+        let actions = disc.tick(now);
+        req!(2, actions.len());
+        let (ping_nonce, find_nonce) = match (&actions[0], &actions[1]) {
+            (
+                DiscoveryAction::Ping { addr: a1, nonce: n1 },
+                DiscoveryAction::FindNode { addr: a2, nonce: n2, target },
+            ) => {
+                req!(seed, *a1);
+                req!(seed, *a2);
+                req!(own, *target);
+                (*n1, *n2)
+            },
+            _ => return Err(err!("Unexpected actions {:?}.", actions; Test, Unexpected)),
+        };
+        // An answer from the wrong address is ignored.
+        let seed_id = id_with(0x80);
+        let other = SocketAddr::from(([127, 0, 0, 1], 2));
+        req!(true, disc.pong(&ping_nonce, seed_id, other, now).is_empty());
+        req!(0, disc.table.len());
+        req!(true, disc.pong(&ping_nonce, seed_id, seed, now).is_empty());
+        req!(1, disc.table.len());
+        req!(true, disc.dirty);
+        // A peer list is only accepted in answer to my request, and its peers are pinged.
+        let peer_id = id_with(0xc0);
+        req!(false, disc.awaiting_peers(&find_nonce, &other));
+        req!(true, disc.awaiting_peers(&find_nonce, &seed));
+        let actions = disc.found(&find_nonce, &seed, vec![(peer_id, other), (own, other)], now);
+        req!(true, matches!(actions[0], DiscoveryAction::Ping { addr, .. } if addr == other));
+        req!(false, disc.awaiting_peers(&find_nonce, &seed));
+        // The bucket is now full, so a newcomer causes its least recently seen peer to be
+        // pinged, and the newcomer replaces it if the ping goes unanswered.
+        req!(Insertion::Added, disc.table.seen(peer_id, other));
+        let newcomer = id_with(0xe0);
+        let newcomer_addr = SocketAddr::from(([127, 0, 0, 1], 3));
+        let actions = disc.observe(newcomer, newcomer_addr, now);
+        req!(1, actions.len());
+        req!(true, disc.table.get(&newcomer).is_none());
+        disc.tick(now + Duration::from_millis(200));
+        req!(true, disc.table.get(&seed_id).is_none());
+        req!(true, disc.table.get(&newcomer).is_some());
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Discovery 000", "all", "discovery", "network"], || {
+        // Each server loop occupies a worker thread.
+        let rt = res!(tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(PEERS + 2)
+            .enable_all()
+            .build());
+        rt.block_on(run_test_discovery())
+    }));
+
+    Ok(())
+}
+
+/// Wait until each of the given peers knows exactly the given others.
+fn await_tables(
+    discs:      &[(PeerId, std::sync::Arc<std::sync::RwLock<PeerDiscovery>>)],
+    expected:   &BTreeSet<PeerId>,
+    timeout:    Duration,
+)
+    -> Outcome<()>
+{
+    let start = Instant::now();
+    loop {
+        let mut done = true;
+        for (own, locked_disc) in discs {
+            let known: BTreeSet<PeerId> = {
+                let unlocked_disc = lock_read!(locked_disc);
+                unlocked_disc.table.peers().into_iter().map(|e| e.id).collect()
+            };
+            let mut others = expected.clone();
+            others.remove(own);
+            if known != others {
+                done = false;
+                break;
+            }
+        }
+        if done {
+            test!("Routing tables settled after {:?}.", start.elapsed());
+            return Ok(());
+        }
+        if start.elapsed() > timeout {
+            return Err(err!(
+                "Routing tables failed to settle within {:?}.", timeout;
+                Test, Timeout));
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Three seeds and two other peers discover each other.  When one peer leaves it is evicted from
+/// the tables of the others, and the table of a peer with a database is saved on finishing.
+pub async fn run_test_discovery() -> Outcome<()> {
+
+    let ip_addr = res!(local_ip());
+    let ports: [u16; PEERS] = res!(free_ports());
+    let mut cfg = ServerConfig::default();
+    cfg.trusted_seeds = ports[..3].iter().map(|port| fmt!("{}:{}", ip_addr, port)).collect();
+    cfg.discovery_ping_timeout_ms = 2_000;
+    cfg.discovery_stale_secs = 2;
+    cfg.discovery_refresh_secs = 3;
+    cfg.discovery_fails_max = 2;
+
+    // Peer D saves its routing table.
+    let db_root = std::env::temp_dir().join(fmt!("shield_discovery_{}", Rand::rand_u32()));
+    let mut enc_key = [0u8; 32];
+    Rand::fill_u8(&mut enc_key);
+    let mut db = res!(new_db(&db_root, &enc_key));
+    res!(db.start(fmt!("discovery")));
+    res!(ok!(db.updated_api()).activate_gc(true));
+
+    let mut peers = Vec::new();
+    for (i, port) in ports.iter().enumerate() {
+        let mut peer_cfg = cfg.clone();
+        peer_cfg.server_port_udp = *port;
+        let mut peer = TestPeer::new(peer_cfg);
+        if i == 3 {
+            peer = peer.with_db(db.clone());
+        }
+        let (_, chan, protocol, handle) = res!(peer.start());
+        let own = {
+            let unlocked_disc = lock_read!(protocol.discovery);
+            *unlocked_disc.table.own()
+        };
+        peers.push((own, protocol.discovery.clone(), chan, handle));
+    }
+
+    let discs: Vec<_> = peers.iter().map(|(own, disc, _, _)| (*own, disc.clone())).collect();
+    let all: BTreeSet<PeerId> = discs.iter().map(|(own, _)| *own).collect();
+    let mesh = await_tables(&discs, &all, MESH_TIMEOUT);
+
+    // Peer E leaves.
+    let (id_e, _, chan_e, handle_e) = res!(peers.pop().ok_or(err!("No peers."; Test, Missing)));
+    res!(chan_e.send(Command::Finish));
+    let mut remaining = all.clone();
+    remaining.remove(&id_e);
+    let evicted = match mesh {
+        Ok(()) => await_tables(&discs[..4], &remaining, EVICT_TIMEOUT),
+        Err(e) => Err(e),
+    };
+    let known_d: BTreeSet<PeerId> = {
+        let unlocked_disc = lock_read!(discs[3].1);
+        unlocked_disc.table.peers().into_iter().map(|e| e.id).collect()
+    };
+
+    // Peer D finishes first, saving its table while the seeds are alive.
+    let (_, _, chan_d, handle_d) = res!(peers.pop().ok_or(err!("No peers."; Test, Missing)));
+    res!(chan_d.send(Command::Finish));
+    let mut handles = vec![handle_e, handle_d];
+    for (_, _, chan, handle) in peers {
+        res!(chan.send(Command::Finish));
+        handles.push(handle);
+    }
+    for handle in handles {
+        match handle.await {
+            Ok(result) => res!(result),
+            Err(e) => return Err(err!(e, "While awaiting server task completion."; Async)),
+        }
+    }
+
+    let saved = res!(db.get(&dat!(constant::DISCOVERY_DB_KEY), None));
+    res!(db.shutdown());
+    let _ = fs::remove_dir_all(&db_root);
+    res!(evicted);
+
+    let mut table = RoutingTable::new(discs[3].0, cfg.discovery_bucket_size as usize);
+    match saved {
+        Some((dat, _)) => { res!(table.load(dat)); },
+        None => return Err(err!("Peer D did not save its routing table."; Test, Missing)),
+    }
+    let loaded: BTreeSet<PeerId> = table.peers().into_iter().map(|e| e.id).collect();
+    test!("Peer D saved {} peers.", loaded.len());
+    req!(known_d, loaded);
+    req!(3, loaded.len());
+    Ok(())
+}
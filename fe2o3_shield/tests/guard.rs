@@ -0,0 +1,287 @@
+use crate::handshake::{
+    HANDSHAKE_TIMEOUT,
+    TestPeer,
+    free_ports,
+    is_established,
+};
+
+use oxedyne_fe2o3_shield::srv::{
+    cfg::ServerConfig,
+    cmd::Command,
+    constant,
+    context::new_db,
+    guard::snapshot::{
+        self,
+        AddressEntry,
+        GuardSnapshot,
+        GuardStatus,
+    },
+};
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    channels::{
+        Recv,
+        simplex,
+        Simplex,
+    },
+    rand::Rand,
+    test::test_it,
+};
+use oxedyne_fe2o3_iop_db::api::Database;
+use oxedyne_fe2o3_jdat::prelude::*;
+use oxedyne_fe2o3_net::id;
+
+use std::{
+    fs,
+    net::{
+        IpAddr,
+        SocketAddr,
+    },
+    thread,
+    time::{
+        Duration,
+        Instant,
+        SystemTime,
+    },
+};
+
+use local_ip_address::local_ip;
+
+
+const BLACKLIST_DURATION: Duration = Duration::from_secs(4);
+
+pub fn test_guard(filter: &'static str) -> Outcome<()> {
+
+    res!(test_it(filter, &["Guard 000", "all", "guard"], || {
+        let ip: IpAddr = res!("203.0.113.7".parse(), Decode, Input);
+        let until = SystemTime::now() + Duration::from_secs(600);
+        let mut snap = GuardSnapshot::default();
+        res!(snap.set("203.0.113.7", GuardStatus::Blacklist(Some(until))));
+        res!(snap.set("2001:db8::1", GuardStatus::Whitelist));
+        res!(snap.set("00ff10", GuardStatus::Blacklist(None)));
+        req!(2, snap.addrs.len());
+        req!(1, snap.users.len());
+        req!(vec![0x00, 0xff, 0x10], snap.users[0].uid.clone());
+        req!("00ff10", snapshot::hex(&snap.users[0].uid).as_str());
+        req!(true, snap.set("not an address", GuardStatus::Whitelist).is_err());
+        req!(true, snap.set("abc", GuardStatus::Whitelist).is_err());
+        // Times are persisted to the second.
+        let dat = res!(snap.to_dat());
+        let snap2 = res!(GuardSnapshot::from_dat(dat));
+        req!(snap.addrs.len(), snap2.addrs.len());
+        req!(snap.users, snap2.users);
+        match snap2.addrs[0].status {
+            GuardStatus::Blacklist(Some(until2)) => {
+                let diff = match until.duration_since(until2) {
+                    Ok(d) => d,
+                    Err(e) => e.duration(),
+                };
+                req!(true, diff < Duration::from_secs(1));
+            },
+            status => return Err(err!(
+                "Expected a timed blacklisting, found {:?}.", status;
+                Test, Unexpected)),
+        }
+        // Unbanning an address with no history removes it, while one with history is kept.
+        snap.addrs.push(AddressEntry {
+            ip:             res!("198.51.100.1".parse(), Decode, Input),
+            status:         GuardStatus::Normal,
+            throttle_cnt:   3,
+            offences:       0,
+        });
+        res!(snap.set("203.0.113.7", GuardStatus::Normal));
+        req!(false, snap.addrs.iter().any(|e| e.ip == ip));
+        req!(2, snap.addrs.len());
+        res!(snap.set("00ff10", GuardStatus::Normal));
+        req!(0, snap.users.len());
+        for line in snap.to_lines() {
+            test!("{}", line);
+        }
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Guard 001", "all", "guard"], || {
+        let rt = res!(tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(4)
+            .enable_all()
+            .build());
+        rt.block_on(run_test_guard_state())
+    }));
+
+    res!(test_it(filter, &["Guard 002", "all", "guard", "network"], || {
+        let rt = res!(tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(4)
+            .enable_all()
+            .build());
+        rt.block_on(run_test_guard_persistence())
+    }));
+
+    Ok(())
+}
+
+/// Guard state set by an operator appears in the snapshot, and timed blacklistings expire.
+pub async fn run_test_guard_state() -> Outcome<()> {
+
+    let [port] = res!(free_ports());
+    let (_, chan, protocol, handle) = res!(TestPeer::on_port(port).start());
+
+    let ip_w: IpAddr = res!("198.51.100.2".parse(), Decode, Input);
+    let ip_b: IpAddr = res!("198.51.100.3".parse(), Decode, Input);
+    let uid = vec![1, 2, 3];
+    res!(protocol.agrd.set_status(&ip_w, GuardStatus::Whitelist));
+    res!(protocol.agrd.set_status(&ip_b,
+        GuardStatus::Blacklist(Some(SystemTime::now() + Duration::from_millis(500)))));
+    res!(protocol.ugrd.set_status(&uid, GuardStatus::Blacklist(None)));
+
+    let addrs = res!(protocol.agrd.snapshot());
+    let users = res!(protocol.ugrd.snapshot());
+    let expired_early = res!(protocol.agrd.expire());
+    thread::sleep(Duration::from_millis(700));
+    let expired = res!(protocol.agrd.expire());
+    let addrs_after = res!(protocol.agrd.snapshot());
+
+    // An operator command is applied to the running server, which replies with the guard state.
+    let ip_cmd: IpAddr = res!("198.51.100.4".parse(), Decode, Input);
+    let lines = res!(guard_command(
+        &chan,
+        Some((fmt!("198.51.100.4"), GuardStatus::Blacklist(None))),
+    ));
+    let addrs_cmd = res!(protocol.agrd.snapshot());
+    let invalid = guard_command(&chan, Some((fmt!("not a target"), GuardStatus::Whitelist)));
+
+    res!(chan.send(Command::Finish));
+    match handle.await {
+        Ok(result) => res!(result),
+        Err(e) => return Err(err!(e, "While awaiting server task completion."; Async)),
+    }
+
+    req!(2, addrs.len());
+    req!(1, users.len());
+    req!(uid, users[0].uid.clone());
+    req!(0, expired_early);
+    req!(1, expired);
+    req!(1, addrs_after.len());
+    req!(ip_w, addrs_after[0].ip);
+    req!(GuardStatus::Whitelist, addrs_after[0].status);
+    req!(true, lines.iter().any(|line| line.contains("198.51.100.4")));
+    req!(true, addrs_cmd.iter()
+        .any(|e| e.ip == ip_cmd && e.status == GuardStatus::Blacklist(None)));
+    req!(true, invalid.is_err());
+    Ok(())
+}
+
+/// Send a guard command to a running server and wait for its reply.
+fn guard_command(
+    chan:       &Simplex<Command>,
+    change_opt: Option<(String, GuardStatus)>,
+)
+    -> Outcome<Vec<String>>
+{
+    let reply = simplex();
+    res!(chan.send(Command::Guard(change_opt, reply.clone())));
+    match reply.recv_timeout(Duration::from_secs(5)) {
+        Recv::Result(result) => res!(result),
+        Recv::Empty => Err(err!("No reply to the guard command."; Test, Timeout)),
+    }
+}
+
+/// Peer B starts with a saved blacklisting of the address shared by the test peers, so ignores
+/// the handshake of peer A until the blacklisting expires.  The guard state is saved again when
+/// peer B finishes.
+pub async fn run_test_guard_persistence() -> Outcome<()> {
+
+    let ip_addr = res!(local_ip());
+    let [port_a, port_b] = res!(free_ports());
+    let addr_a = SocketAddr::new(ip_addr, port_a);
+    let addr_b = SocketAddr::new(ip_addr, port_b);
+
+    let db_root = std::env::temp_dir().join(fmt!("shield_guard_{}", Rand::rand_u32()));
+    let mut enc_key = [0u8; 32];
+    Rand::fill_u8(&mut enc_key);
+    let mut db = res!(new_db(&db_root, &enc_key));
+    res!(db.start(fmt!("guard")));
+    res!(ok!(db.updated_api()).activate_gc(true));
+
+    let mut snap = GuardSnapshot::default();
+    snap.set_addr(ip_addr, GuardStatus::Blacklist(Some(SystemTime::now() + BLACKLIST_DURATION)));
+    snap.set_user(vec![7; 4], GuardStatus::Blacklist(None));
+    res!(db.insert(dat!(constant::GUARD_DB_KEY), res!(snap.to_dat()), id::Uid::new(0), None));
+
+    let (sessions_a, chan_a, _, handle_a) = res!(TestPeer::on_port(port_a).start());
+    let cfg_b = ServerConfig {
+        server_port_udp: port_b,
+        ..Default::default()
+    };
+    let (sessions_b, chan_b, protocol_b, handle_b) = res!(TestPeer::new(cfg_b).with_db(db.clone()).start());
+    thread::sleep(Duration::from_millis(500)); // Allow the servers to bind and load.
+    let start = Instant::now();
+
+    let restored = res!(protocol_b.agrd.snapshot());
+    let restored_users = res!(protocol_b.ugrd.snapshot());
+
+    // An operator command is saved by the server straight away.
+    let ip_cmd: IpAddr = res!("198.51.100.9".parse(), Decode, Input);
+    res!(guard_command(&chan_b, Some((fmt!("198.51.100.9"), GuardStatus::Whitelist))));
+    let saved_cmd = match res!(db.get(&dat!(constant::GUARD_DB_KEY), None)) {
+        Some((dat, _)) => res!(GuardSnapshot::from_dat(dat)),
+        None => return Err(err!("Peer B did not save the guard command."; Test, Missing)),
+    };
+
+    // Blacklisted.
+    res!(chan_a.send(Command::Connect(addr_b)));
+    thread::sleep(Duration::from_secs(1));
+    let ignored = !res!(is_established(&sessions_b, &addr_a));
+
+    // No longer blacklisted.
+    let remaining = BLACKLIST_DURATION.saturating_sub(start.elapsed());
+    thread::sleep(remaining + Duration::from_millis(500));
+    res!(chan_a.send(Command::Connect(addr_b)));
+    let start = Instant::now();
+    let mut established = false;
+    while start.elapsed() < HANDSHAKE_TIMEOUT {
+        if res!(is_established(&sessions_a, &addr_b)) && res!(is_established(&sessions_b, &addr_a)) {
+            established = true;
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    test!("Handshake finished after {:?}.", start.elapsed());
+
+    res!(chan_a.send(Command::Finish));
+    res!(chan_b.send(Command::Finish));
+    for handle in [handle_a, handle_b] {
+        match handle.await {
+            Ok(result) => res!(result),
+            Err(e) => return Err(err!(e, "While awaiting server task completion."; Async)),
+        }
+    }
+
+    let saved = res!(db.get(&dat!(constant::GUARD_DB_KEY), None));
+    res!(db.shutdown());
+    let _ = fs::remove_dir_all(&db_root);
+
+    req!(1, restored.len());
+    req!(ip_addr, restored[0].ip);
+    req!(snap.users, restored_users);
+    req!(true, saved_cmd.addrs.iter()
+        .any(|e| e.ip == ip_cmd && e.status == GuardStatus::Whitelist));
+    req!(true, ignored);
+    if !established {
+        return Err(err!(
+            "Handshake failed to complete within {:?} of the blacklisting expiring.",
+            HANDSHAKE_TIMEOUT;
+            Test, Timeout));
+    }
+    let saved = match saved {
+        Some((dat, _)) => res!(GuardSnapshot::from_dat(dat)),
+        None => return Err(err!("Peer B did not save its guard state."; Test, Missing)),
+    };
+    test!("Peer B saved:");
+    for line in saved.to_lines() {
+        test!("{}", line);
+    }
+    req!(false, saved.addrs.iter().any(|e| matches!(e.status, GuardStatus::Blacklist(_))));
+    req!(snap.users, saved.users);
+    Ok(())
+}
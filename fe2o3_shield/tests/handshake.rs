@@ -0,0 +1,450 @@
+use oxedyne_fe2o3_shield::srv::{
+    cfg::ServerConfig,
+    cmd::Command,
+    constant,
+    context::{
+        ServerContext,
+        new_db,
+    },
+    handler::{
+        ShieldHandler,
+        ShieldSinkHandler,
+    },
+    msg::{
+        protocol::{
+            DefaultProtocolTypes,
+            Protocol,
+            ProtocolMode,
+            ProtocolTypes,
+        },
+        syntax as srv_syntax,
+    },
+    schemes::WireSchemesInput,
+    server::Server,
+    session::SessionMap,
+};
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    alt::Alt,
+    channels::Simplex,
+    path::NormalPath,
+    rand::Rand,
+    test::test_it,
+};
+use oxedyne_fe2o3_crypto::{
+    enc::EncryptionScheme,
+    sign::SignatureScheme,
+};
+use oxedyne_fe2o3_hash::{
+    csum::ChecksumScheme,
+    hash::HashScheme,
+};
+use oxedyne_fe2o3_iop_crypto::enc::Encrypter;
+use oxedyne_fe2o3_iop_db::api::Database;
+use oxedyne_fe2o3_jdat::prelude::*;
+use oxedyne_fe2o3_net::id;
+use oxedyne_fe2o3_o3db_sync::O3db;
+
+use std::{
+    fs,
+    net::SocketAddr,
+    path::Path,
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use local_ip_address::local_ip;
+
+
+pub type TestProtocolTypes<H = ShieldSinkHandler> =
+    DefaultProtocolTypes<{id::MID_LEN}, {id::SID_LEN}, {id::UID_LEN}, H>;
+pub type TestProtocol<H = ShieldSinkHandler> =
+    Protocol<8, {id::MID_LEN}, {id::SID_LEN}, {id::UID_LEN}, TestProtocolTypes<H>>;
+pub type TestSessionMap = SessionMap<
+    8,
+    {id::MID_LEN},
+    {id::SID_LEN},
+    {id::UID_LEN},
+    <TestProtocolTypes as ProtocolTypes<
+        {id::MID_LEN},
+        {id::SID_LEN},
+        {id::UID_LEN},
+    >>::ID,
+>;
+pub type TestDb = O3db<
+    { id::UID_LEN },
+    id::Uid,
+    EncryptionScheme,
+    HashScheme,
+    HashScheme,
+    ChecksumScheme,
+>;
+
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub fn test_handshake(filter: &'static str) -> Outcome<()> {
+
+    res!(test_it(filter, &["Handshake 000", "all", "handshake", "network"], || {
+        let rt = res!(tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(4)
+            .enable_all()
+            .build());
+        rt.block_on(run_test_handshake())
+    }));
+
+    res!(test_it(filter, &["Handshake 001", "all", "handshake", "restart", "network"], || {
+        let rt = res!(tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(4)
+            .enable_all()
+            .build());
+        rt.block_on(run_test_handshake_restart())
+    }));
+
+    Ok(())
+}
+
+/// Distinct free UDP ports on the local address, found by binding to ephemeral ports, so that
+/// tests running in parallel do not collide.
+pub fn free_ports<const N: usize>() -> Outcome<[u16; N]> {
+    let ip_addr = res!(local_ip());
+    // Hold every socket until all ports are known, so that none is handed out twice.
+    let mut sockets = Vec::with_capacity(N);
+    let mut ports = [0u16; N];
+    for port in ports.iter_mut() {
+        let socket = res!(std::net::UdpSocket::bind(SocketAddr::new(ip_addr, 0)));
+        *port = res!(socket.local_addr()).port();
+        sockets.push(socket);
+    }
+    Ok(ports)
+}
+
+/// Load the default server configuration after removing the fields whose names start with any of
+/// the given prefixes, as for a configuration saved before those fields existed.
+pub fn cfg_without(prefixes: &[&str]) -> Outcome<ServerConfig> {
+    let mut map = match ServerConfig::to_datmap(ServerConfig::default()) {
+        Dat::Map(map) => map,
+        dat => return Err(err!("Expected a Dat::Map, found {:?}.", dat; Test, Unexpected)),
+    };
+    map.retain(|k, _| !matches!(k, Dat::Str(s) if prefixes.iter().any(|p| s.starts_with(p))));
+    ServerConfig::from_datmap(map)
+}
+
+/// The session map, command channel, a copy of the protocol for collecting session data, and
+/// the task handle of a started test server.
+pub type StartedPeer<H = ShieldSinkHandler> = (
+    TestSessionMap,
+    Simplex<Command>,
+    TestProtocol<H>,
+    tokio::task::JoinHandle<Outcome<()>>,
+);
+
+/// Builds a test server, by default without a database and with a handler that discards
+/// application messages.
+pub struct TestPeer<H: ShieldHandler = ShieldSinkHandler> {
+    cfg:        ServerConfig,
+    db_opt:     Option<TestDb>,
+    handler:    H,
+}
+
+impl TestPeer {
+
+    pub fn new(cfg: ServerConfig) -> Self {
+        Self {
+            cfg,
+            db_opt:     None,
+            handler:    ShieldSinkHandler,
+        }
+    }
+
+    /// Use the default server configuration, listening on the given port.
+    pub fn on_port(port: u16) -> Self {
+        Self::new(ServerConfig {
+            server_port_udp: port,
+            ..Default::default()
+        })
+    }
+}
+
+impl<H: ShieldHandler + 'static> TestPeer<H> {
+
+    /// Use the given started database.
+    pub fn with_db(mut self, db: TestDb) -> Self {
+        self.db_opt = Some(db);
+        self
+    }
+
+    /// Use the given application message handler.
+    pub fn with_handler<H2: ShieldHandler>(self, handler: H2) -> TestPeer<H2> {
+        TestPeer {
+            cfg:        self.cfg,
+            db_opt:     self.db_opt,
+            handler,
+        }
+    }
+
+    pub fn start(self) -> Outcome<StartedPeer<H>> {
+        let protocol: TestProtocol<H> =
+            res!(Protocol::new(
+                &self.cfg,
+                WireSchemesInput {
+                    enc:    Alt::Specific(Some(EncryptionScheme::new_aes_256_gcm())),
+                    csum:   Alt::Specific(None::<ChecksumScheme>),
+                    powh:   Alt::Specific(ServerConfig::default_packet_pow_hash_scheme()),
+                    sign:   Alt::Specific(Some(SignatureScheme::new_ed25519())),
+                    hsenc:  Alt::Specific(None::<EncryptionScheme>),
+                    chnk:   Some(ServerConfig::new_chunk_cfg(1_000, 200, false, true)),
+                },
+                [0u8; 8],
+                id::Mid::default(),
+                id::Sid::default(),
+                id::Uid::default(),
+                ProtocolMode::Test,
+            ));
+        let protocol = res!(protocol.with_handler(self.handler));
+        let context = ServerContext::<_, _, _, _, _, EncryptionScheme, HashScheme, TestDb>::new(
+            self.cfg,
+            Path::new(".").normalise().absolute(),
+            self.db_opt.map(|db| (db, id::Uid::default())),
+            protocol,
+        );
+        let sessions = context.sessions.clone();
+        let protocol = context.protocol.clone();
+        let syntax = res!(srv_syntax::base_msg());
+        let (mut server, cmd_chan) = Server::new(context, syntax);
+        let handle = tokio::spawn(async move { server.start().await });
+        Ok((sessions, cmd_chan, protocol, handle))
+    }
+}
+
+pub fn is_established(sessions: &TestSessionMap, peer_addr: &SocketAddr) -> Outcome<bool> {
+    let unlocked_sessions = lock_read!(sessions);
+    Ok(match unlocked_sessions.get(peer_addr) {
+        Some(session) => session.is_established(),
+        None => false,
+    })
+}
+
+pub async fn run_test_handshake() -> Outcome<()> {
+
+    let ip_addr = res!(local_ip());
+    let [port_a, port_b] = res!(free_ports());
+    let addr_a = SocketAddr::new(ip_addr, port_a);
+    let addr_b = SocketAddr::new(ip_addr, port_b);
+
+    let (sessions_a, chan_a, _, handle_a) = res!(TestPeer::on_port(port_a).start());
+    let (sessions_b, chan_b, _, handle_b) = res!(TestPeer::on_port(port_b).start());
+    thread::sleep(Duration::from_millis(500)); // Allow the servers to bind.
+
+    test!("Peer A at {:?} initiating handshake with peer B at {:?}...", addr_a, addr_b);
+    res!(chan_a.send(Command::Connect(addr_b)));
+
+    let start = Instant::now();
+    let mut established = false;
+    while start.elapsed() < HANDSHAKE_TIMEOUT {
+        if res!(is_established(&sessions_a, &addr_b)) && res!(is_established(&sessions_b, &addr_a)) {
+            established = true;
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    test!("Handshake finished after {:?}.", start.elapsed());
+
+    let session_a = {
+        let unlocked_sessions = lock_read!(sessions_a);
+        unlocked_sessions.get(&addr_b).map(|session| (session.sid_opt, session.enc_opt.clone()))
+    };
+    let session_b = {
+        let unlocked_sessions = lock_read!(sessions_b);
+        unlocked_sessions.get(&addr_a).map(|session| (session.sid_opt, session.enc_opt.clone()))
+    };
+
+    // Connecting again must not reset the session established at either peer.
+    if established {
+        res!(chan_a.send(Command::Connect(addr_b)));
+        thread::sleep(Duration::from_millis(1_000));
+    }
+    let session_a_after = {
+        let unlocked_sessions = lock_read!(sessions_a);
+        unlocked_sessions.get(&addr_b).map(|session| (session.is_established(), session.sid_opt))
+    };
+    let session_b_after = {
+        let unlocked_sessions = lock_read!(sessions_b);
+        unlocked_sessions.get(&addr_a).map(|session| (session.is_established(), session.sid_opt))
+    };
+
+    res!(chan_a.send(Command::Finish));
+    res!(chan_b.send(Command::Finish));
+    for handle in [handle_a, handle_b] {
+        match handle.await {
+            Ok(result) => res!(result),
+            Err(e) => return Err(err!(e, "While awaiting server task completion."; Async)),
+        }
+    }
+
+    if !established {
+        return Err(err!(
+            "Peers failed to establish a session within {:?}.", HANDSHAKE_TIMEOUT;
+            Test, Timeout));
+    }
+
+    // Both peers should hold the same session id and key.
+    let (sid_a, enc_a) = match session_a {
+        Some(session) => session,
+        None => return Err(err!("Session missing for peer B."; Test, Missing)),
+    };
+    let (sid_b, enc_b) = match session_b {
+        Some(session) => session,
+        None => return Err(err!("Session missing for peer A."; Test, Missing)),
+    };
+    req!(true, sid_a.is_some());
+    req!(sid_a, sid_b);
+    match (enc_a, enc_b) {
+        (Some(enc_a), Some(enc_b)) => {
+            let plain = b"session message".to_vec();
+            let cipher = res!(enc_a.encrypt(&plain));
+            req!(plain, res!(enc_b.decrypt(&cipher)));
+        },
+        _ => return Err(err!("Session encryption missing."; Test, Missing)),
+    }
+    req!(Some((true, sid_a)), session_a_after,
+        "Peer A should refuse to connect while its session with B is established.");
+    req!(Some((true, sid_b)), session_b_after,
+        "Peer B should keep its session with A established.");
+
+    Ok(())
+}
+
+/// Wait until both peers hold an established session with each other, returning the session id
+/// held by each.  When `retry` is given, the connection is requested again at that interval.
+async fn await_sessions(
+    chan_a:     &Simplex<Command>,
+    sessions_a: &TestSessionMap,
+    sessions_b: &TestSessionMap,
+    addr_a:     SocketAddr,
+    addr_b:     SocketAddr,
+    retry:      Option<Duration>,
+)
+    -> Outcome<Option<(id::Sid, id::Sid)>>
+{
+    res!(chan_a.send(Command::Connect(addr_b)));
+    let start = Instant::now();
+    let mut last = Instant::now();
+    while start.elapsed() < HANDSHAKE_TIMEOUT {
+        let sid_a = {
+            let unlocked_sessions = lock_read!(sessions_a);
+            unlocked_sessions.get(&addr_b).filter(|s| s.is_established()).and_then(|s| s.sid_opt)
+        };
+        let sid_b = {
+            let unlocked_sessions = lock_read!(sessions_b);
+            unlocked_sessions.get(&addr_a).filter(|s| s.is_established()).and_then(|s| s.sid_opt)
+        };
+        if let (Some(sid_a), Some(sid_b)) = (sid_a, sid_b) {
+            return Ok(Some((sid_a, sid_b)));
+        }
+        if let Some(retry) = retry {
+            if last.elapsed() > retry && sid_a.is_none() {
+                res!(chan_a.send(Command::Connect(addr_b)));
+                last = Instant::now();
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    Ok(None)
+}
+
+/// The user id peer B has on record for the peer at the given address.
+fn peer_uid(sessions: &TestSessionMap, peer_addr: &SocketAddr) -> Outcome<Option<id::Uid>> {
+    let unlocked_sessions = lock_read!(sessions);
+    Ok(unlocked_sessions.get(peer_addr).and_then(|s| s.peer_uid))
+}
+
+/// Peer A restarts, losing its session, while peer B still holds an established one.  B ignores
+/// A's new handshake until its idle session expires, after which A is able to reconnect.  A
+/// loads its identity from its database, so B knows it as the same user.
+pub async fn run_test_handshake_restart() -> Outcome<()> {
+
+    let ip_addr = res!(local_ip());
+    let [port_a, port_b] = res!(free_ports());
+    let addr_a = SocketAddr::new(ip_addr, port_a);
+    let addr_b = SocketAddr::new(ip_addr, port_b);
+    let idle_cfg = |port| ServerConfig {
+        server_port_udp:    port,
+        session_idle_secs:  3,
+        ..Default::default()
+    };
+
+    let db_root = std::env::temp_dir().join(fmt!("shield_identity_{}", Rand::rand_u32()));
+    let mut enc_key = [0u8; 32];
+    Rand::fill_u8(&mut enc_key);
+    let mut db = res!(new_db(&db_root, &enc_key));
+    res!(db.start(fmt!("identity")));
+    res!(ok!(db.updated_api()).activate_gc(true));
+
+    let (sessions_a, chan_a, _, handle_a) =
+        res!(TestPeer::new(idle_cfg(port_a)).with_db(db.clone()).start());
+    let (sessions_b, chan_b, _, handle_b) = res!(TestPeer::new(idle_cfg(port_b)).start());
+    thread::sleep(Duration::from_millis(500)); // Allow the servers to bind.
+
+    let first = res!(await_sessions(&chan_a, &sessions_a, &sessions_b, addr_a, addr_b, None).await);
+    let uid_a1 = res!(peer_uid(&sessions_b, &addr_a));
+
+    // Restart A on the same port.
+    res!(chan_a.send(Command::Finish));
+    match handle_a.await {
+        Ok(result) => res!(result),
+        Err(e) => return Err(err!(e, "While awaiting server task completion."; Async)),
+    }
+    let (sessions_a, chan_a, _, handle_a) =
+        res!(TestPeer::new(idle_cfg(port_a)).with_db(db.clone()).start());
+    thread::sleep(Duration::from_millis(500));
+
+    test!("Peer A restarted, reconnecting to peer B...");
+    let start = Instant::now();
+    let second = res!(await_sessions(
+        &chan_a,
+        &sessions_a,
+        &sessions_b,
+        addr_a,
+        addr_b,
+        Some(Duration::from_millis(1_000)),
+    ).await);
+    test!("Reconnected after {:?}.", start.elapsed());
+    let uid_a2 = res!(peer_uid(&sessions_b, &addr_a));
+
+    res!(chan_a.send(Command::Finish));
+    res!(chan_b.send(Command::Finish));
+    for handle in [handle_a, handle_b] {
+        match handle.await {
+            Ok(result) => res!(result),
+            Err(e) => return Err(err!(e, "While awaiting server task completion."; Async)),
+        }
+    }
+    let saved = res!(db.get(&dat!(constant::IDENTITY_DB_KEY), None));
+    res!(db.shutdown());
+    let _ = fs::remove_dir_all(&db_root);
+
+    let (sid_a1, sid_b1) = match first {
+        Some(sids) => sids,
+        None => return Err(err!(
+            "Peers failed to establish a session within {:?}.", HANDSHAKE_TIMEOUT;
+            Test, Timeout)),
+    };
+    let (sid_a2, sid_b2) = match second {
+        Some(sids) => sids,
+        None => return Err(err!(
+            "Peers failed to re-establish a session within {:?} of a restart.", HANDSHAKE_TIMEOUT;
+            Test, Timeout)),
+    };
+    req!(sid_a1, sid_b1);
+    req!(sid_a2, sid_b2);
+    req!(false, sid_a1 == sid_a2, "The reconnection should establish a new session.");
+    req!(true, uid_a1.is_some());
+    req!(uid_a1, uid_a2, "Peer A should keep its user id across a restart.");
+    req!(true, saved.is_some(), "Peer A should save its identity.");
+
+    Ok(())
+}
@@ -1,5 +1,15 @@
 //mod msg;
+mod app;
+mod client;
+mod discovery;
+mod guard;
+mod handshake;
+mod pow;
+mod rekey;
+mod reliable;
+mod session;
 mod sim;
+mod simnet;
 
 use oxedyne_fe2o3_core::prelude::*;
 
@@ -28,6 +38,16 @@ fn run_tests() -> Outcome<()> {
 
     //res!(msg::test_msg("all"));
     res!(sim::test_sim("all"));
+    res!(handshake::test_handshake("all"));
+    res!(session::test_session("all"));
+    res!(reliable::test_reliable("all"));
+    res!(simnet::test_simnet("all"));
+    res!(discovery::test_discovery("all"));
+    res!(pow::test_pow("all"));
+    res!(guard::test_guard("all"));
+    res!(client::test_client("all"));
+    res!(rekey::test_rekey("all"));
+    res!(app::test_app("all"));
 
     Ok(())
 }
@@ -0,0 +1,172 @@
+use crate::handshake::{
+    HANDSHAKE_TIMEOUT,
+    TestPeer,
+    cfg_without,
+    free_ports,
+    is_established,
+};
+
+use oxedyne_fe2o3_shield::srv::{
+    cfg::ServerConfig,
+    cmd::Command,
+    guard::addr::AddressLog,
+    pow::{
+        DifficultyParams,
+        DifficultyProfile,
+    },
+};
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    test::test_it,
+};
+
+use std::{
+    net::SocketAddr,
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use local_ip_address::local_ip;
+
+
+
+fn params(profile: DifficultyProfile) -> DifficultyParams {
+    DifficultyParams {
+        profile,
+        max:            15,
+        min:            2,
+        rps_max:        100,
+        steps:          4,
+        offences_max:   16,
+    }
+}
+
+pub fn test_pow(filter: &'static str) -> Outcome<()> {
+
+    res!(test_it(filter, &["Difficulty 000", "all", "difficulty"], || {
+        let linear = params(DifficultyProfile::Linear);
+        req!(2, res!(linear.required_global_zbits(0)));
+        req!(8, res!(linear.required_global_zbits(50)));
+        req!(15, res!(linear.required_global_zbits(100)));
+        req!(15, res!(linear.required_global_zbits(1_000)));
+        // The exponential profile stays cheap for longer, but reaches the same maximum.
+        let exponential = params(DifficultyProfile::Exponential);
+        req!(2, res!(exponential.required_global_zbits(0)));
+        req!(4, res!(exponential.required_global_zbits(50)));
+        req!(15, res!(exponential.required_global_zbits(100)));
+        let mut prev = 0;
+        for rps in 0..=100 {
+            let zbits = res!(exponential.required_global_zbits(rps));
+            req!(true, zbits >= prev);
+            req!(true, zbits <= res!(linear.required_global_zbits(rps)));
+            prev = zbits;
+        }
+        // The stepped profile rises in four increments.
+        let stepped = params(DifficultyProfile::Stepped);
+        req!(2, res!(stepped.required_global_zbits(24)));
+        req!(5, res!(stepped.required_global_zbits(25)));
+        req!(5, res!(stepped.required_global_zbits(49)));
+        req!(8, res!(stepped.required_global_zbits(50)));
+        req!(15, res!(stepped.required_global_zbits(100)));
+        // The per address requirement is driven by offences.
+        req!(2, res!(linear.required_addr_zbits(0)));
+        req!(8, res!(linear.required_addr_zbits(8)));
+        req!(15, res!(linear.required_addr_zbits(16)));
+        // Configuration.
+        req!(true, DifficultyProfile::try_from(2).is_ok());
+        req!(true, DifficultyProfile::try_from(3).is_err());
+        let mut cfg = ServerConfig::default();
+        req!(true, cfg.difficulty_params().is_ok());
+        cfg.server_rps_zbits_profile = 3;
+        req!(true, cfg.difficulty_params().is_err());
+        cfg.server_rps_zbits_profile = 1;
+        cfg.server_pow_zbits_max = 1;
+        req!(true, cfg.difficulty_params().is_err());
+        // A configuration saved before the stepped profile and offences existed falls back to
+        // the defaults.
+        let cfg = res!(cfg_without(&["server_pow_zbits_steps", "server_pow_offence"]));
+        req!(ServerConfig::default(), cfg);
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Difficulty 001", "all", "difficulty"], || {
+        let decay = Duration::from_millis(200);
+        let mut alog = AddressLog::<10, 30, ()>::default();
+        req!(0, alog.offences(decay));
+        for i in 1..=3 {
+            req!(i, alog.offend(decay));
+        }
+        // Good behaviour is rewarded by forgiving one offence per decay period.
+        thread::sleep(Duration::from_millis(450));
+        req!(1, alog.offences(decay));
+        req!(2, alog.offend(decay));
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Difficulty 002", "all", "difficulty", "network"], || {
+        let rt = res!(tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(4)
+            .enable_all()
+            .build());
+        rt.block_on(run_test_difficulty())
+    }));
+
+    Ok(())
+}
+
+/// Peer B has recorded offences by the address of peer A, so requires more work of it during a
+/// handshake.  Peer A learns of the requirement from the `HResp1` and completes the handshake.
+pub async fn run_test_difficulty() -> Outcome<()> {
+
+    let ip_addr = res!(local_ip());
+    let [port_a, port_b] = res!(free_ports());
+    let addr_a = SocketAddr::new(ip_addr, port_a);
+    let addr_b = SocketAddr::new(ip_addr, port_b);
+
+    let (sessions_a, chan_a, protocol_a, handle_a) = res!(TestPeer::on_port(port_a).start());
+    let (sessions_b, chan_b, protocol_b, handle_b) = res!(TestPeer::on_port(port_b).start());
+    thread::sleep(Duration::from_millis(500)); // Allow the servers to bind.
+
+    for _ in 0..12 {
+        res!(protocol_b.agrd.offend(&addr_a));
+    }
+    let base = res!(protocol_b.required_zbits(&addr_a));
+    let raised = res!(protocol_b.request_zbits(&addr_a));
+    test!("Peer B requires {} zero bits of peer A, raised from {}.", raised, base);
+    req!(true, raised > base);
+
+    res!(chan_a.send(Command::Connect(addr_b)));
+    let start = Instant::now();
+    let mut established = false;
+    while start.elapsed() < HANDSHAKE_TIMEOUT {
+        if res!(is_established(&sessions_a, &addr_b)) && res!(is_established(&sessions_b, &addr_a)) {
+            established = true;
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    test!("Handshake finished after {:?}.", start.elapsed());
+    let learned = protocol_a.peer_zbits(&addr_b);
+
+    res!(chan_a.send(Command::Finish));
+    res!(chan_b.send(Command::Finish));
+    for handle in [handle_a, handle_b] {
+        match handle.await {
+            Ok(result) => res!(result),
+            Err(e) => return Err(err!(e, "While awaiting server task completion."; Async)),
+        }
+    }
+
+    if !established {
+        return Err(err!(
+            "Handshake failed to complete within {:?}.", HANDSHAKE_TIMEOUT;
+            Test, Timeout));
+    }
+    let learned = res!(learned);
+    req!(raised, learned);
+    Ok(())
+}
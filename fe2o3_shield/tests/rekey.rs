@@ -0,0 +1,391 @@
+use crate::handshake::{
+    HANDSHAKE_TIMEOUT,
+    TestPeer,
+    TestProtocol,
+    TestProtocolTypes,
+    TestSessionMap,
+    cfg_without,
+    free_ports,
+    is_established,
+};
+
+use oxedyne_fe2o3_shield::srv::{
+    cfg::ServerConfig,
+    cmd::Command,
+    discovery,
+    msg::{
+        protocol::ProtocolTypes,
+        rekey,
+    },
+    session::{
+        Session,
+        SessionState,
+    },
+};
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    channels::Simplex,
+    rand::Rand,
+    test::test_it,
+};
+use oxedyne_fe2o3_crypto::{
+    enc::EncryptionScheme,
+    sign::SignatureScheme,
+};
+use oxedyne_fe2o3_iop_crypto::keys::KeyManager;
+use oxedyne_fe2o3_net::id;
+
+use std::{
+    net::SocketAddr,
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use local_ip_address::local_ip;
+
+
+type TestSession = Session<
+    8,
+    {id::MID_LEN},
+    {id::SID_LEN},
+    {id::UID_LEN},
+    <TestProtocolTypes as ProtocolTypes<
+        {id::MID_LEN},
+        {id::SID_LEN},
+        {id::UID_LEN},
+    >>::ID,
+>;
+
+/// The current and previous public signing keys recorded for a user.
+type RecordedKeys = (Option<Vec<u8>>, Option<Vec<u8>>);
+
+const REKEY_TIMEOUT: Duration = Duration::from_secs(30);
+const DATA_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub fn test_rekey(filter: &'static str) -> Outcome<()> {
+
+    res!(test_it(filter, &["Rekey 000", "all", "rekey"], || {
+        let mut sess_x = TestSession::new(SessionState::Established, true);
+        let mut sess_y = TestSession::new(SessionState::Established, false);
+        let enc1 = res!(EncryptionScheme::new_aes_256_gcm_with_key(&random_key()));
+        let enc2 = res!(EncryptionScheme::new_aes_256_gcm_with_key(&random_key()));
+        sess_x.enc_opt = Some(enc1.clone());
+        sess_y.enc_opt = Some(enc1.clone());
+        let c1 = res!(sess_x.seal(&enc1, b"in flight"));
+        // Y renews the key, keeping the one X is still using.
+        sess_y.rekey(enc2.clone(), false);
+        req!(true, sess_y.enc_prev_opt.is_some());
+        let prev = match &sess_y.enc_prev_opt {
+            Some(prev) => prev.clone(),
+            None => return Err(err!("Expected a previous session key."; Test, Missing)),
+        };
+        let p1 = res!(sess_y.open(&prev, &c1));
+        req!(Some(b"in flight".to_vec()), p1);
+        // A second renewal while the peer was still using the previous key keeps that key.
+        let enc3 = res!(EncryptionScheme::new_aes_256_gcm_with_key(&random_key()));
+        sess_y.rekey(enc3, true);
+        let c2 = res!(sess_x.seal(&enc1, b"still old"));
+        let prev = match &sess_y.enc_prev_opt {
+            Some(prev) => prev.clone(),
+            None => return Err(err!("Expected a previous session key."; Test, Missing)),
+        };
+        let p2 = res!(sess_y.open(&prev, &c2));
+        req!(Some(b"still old".to_vec()), p2);
+        // The rotation statement binds the user and both keys.
+        let s1 = rekey::rotation_statement(&[1, 2], &[3; 4], &[5; 4]);
+        let s2 = rekey::rotation_statement(&[1, 2], &[5; 4], &[3; 4]);
+        let s3 = rekey::rotation_statement(&[1], &[2, 3, 3, 3], &[3, 5, 5, 5, 5]);
+        req!(false, s1 == s2);
+        req!(false, s1 == s3);
+        // A configuration saved before session rekeying existed falls back to the default.
+        let cfg = res!(cfg_without(&["session_rekey_secs"]));
+        req!(ServerConfig::default(), cfg);
+        req!(Some(Duration::from_secs(3_600)), cfg.session_rekey_interval());
+        let cfg = res!(cfg_without(&["session_idle_secs"]));
+        req!(ServerConfig::default(), cfg);
+        req!(Some(Duration::from_secs(7_200)), cfg.session_idle_limit());
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Rekey 001", "all", "rekey", "network"], || {
+        let rt = res!(tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(4)
+            .enable_all()
+            .build());
+        rt.block_on(run_test_rekey_on_demand())
+    }));
+
+    res!(test_it(filter, &["Rekey 002", "all", "rekey", "network"], || {
+        let rt = res!(tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(4)
+            .enable_all()
+            .build());
+        rt.block_on(run_test_rekey_periodic())
+    }));
+
+    res!(test_it(filter, &["Key rotation 000", "all", "rekey", "network"], || {
+        let rt = res!(tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(4)
+            .enable_all()
+            .build());
+        rt.block_on(run_test_key_rotation())
+    }));
+
+    Ok(())
+}
+
+fn random_key() -> [u8; 32] {
+    let mut key = [0u8; 32];
+    Rand::fill_u8(&mut key);
+    key
+}
+
+/// The current session key held for the given peer, if any.
+fn session_key(sessions: &TestSessionMap, peer_addr: &SocketAddr) -> Outcome<Option<Vec<u8>>> {
+    let unlocked_sessions = lock_read!(sessions);
+    Ok(match unlocked_sessions.get(peer_addr) {
+        Some(session) => match &session.enc_opt {
+            Some(enc) => res!(enc.get_secret_key()).map(|key| key.to_vec()),
+            None => None,
+        },
+        None => None,
+    })
+}
+
+fn connect(
+    chan_a:     &Simplex<Command>,
+    sessions_a: &TestSessionMap,
+    sessions_b: &TestSessionMap,
+    addr_a:     SocketAddr,
+    addr_b:     SocketAddr,
+)
+    -> Outcome<bool>
+{
+    res!(chan_a.send(Command::Connect(addr_b)));
+    let start = Instant::now();
+    while start.elapsed() < HANDSHAKE_TIMEOUT {
+        if res!(is_established(sessions_a, &addr_b)) && res!(is_established(sessions_b, &addr_a)) {
+            return Ok(true);
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    Ok(false)
+}
+
+/// Wait until both peers hold the same session key, differing from the given one, returning it.
+fn await_new_key(
+    sessions_a: &TestSessionMap,
+    sessions_b: &TestSessionMap,
+    addr_a:     &SocketAddr,
+    addr_b:     &SocketAddr,
+    old_key:    &Option<Vec<u8>>,
+)
+    -> Outcome<Option<Vec<u8>>>
+{
+    let start = Instant::now();
+    while start.elapsed() < REKEY_TIMEOUT {
+        let key_a = res!(session_key(sessions_a, addr_b));
+        let key_b = res!(session_key(sessions_b, addr_a));
+        if key_a.is_some() && key_a == key_b && key_a != *old_key {
+            test!("Session key renewed after {:?}.", start.elapsed());
+            return Ok(key_a);
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    Ok(None)
+}
+
+/// Peer A renews the session key on demand, after which data still flows both ways.
+pub async fn run_test_rekey_on_demand() -> Outcome<()> {
+
+    let ip_addr = res!(local_ip());
+    let [port_a, port_b] = res!(free_ports());
+    let addr_a = SocketAddr::new(ip_addr, port_a);
+    let addr_b = SocketAddr::new(ip_addr, port_b);
+
+    let (sessions_a, chan_a, protocol_a, handle_a) = res!(TestPeer::on_port(port_a).start());
+    let (sessions_b, chan_b, protocol_b, handle_b) = res!(TestPeer::on_port(port_b).start());
+    thread::sleep(Duration::from_millis(500)); // Allow the servers to bind.
+
+    let established = res!(connect(&chan_a, &sessions_a, &sessions_b, addr_a, addr_b));
+    let key1 = res!(session_key(&sessions_a, &addr_b));
+
+    // Responder asks.
+    res!(chan_b.send(Command::Rekey(addr_a)));
+    let key2 = res!(await_new_key(&sessions_a, &sessions_b, &addr_a, &addr_b, &key1));
+    // Initiator asks.
+    res!(chan_a.send(Command::Rekey(addr_b)));
+    let key3 = res!(await_new_key(&sessions_a, &sessions_b, &addr_a, &addr_b, &key2));
+
+    res!(chan_a.send(Command::Send(addr_b, b"Hello from A".to_vec())));
+    res!(chan_b.send(Command::Send(addr_a, b"Hello from B".to_vec())));
+    let rx_b = res!(protocol_b.recv_data(DATA_TIMEOUT));
+    let rx_a = res!(protocol_a.recv_data(DATA_TIMEOUT));
+
+    res!(chan_a.send(Command::Finish));
+    res!(chan_b.send(Command::Finish));
+    for handle in [handle_a, handle_b] {
+        match handle.await {
+            Ok(result) => res!(result),
+            Err(e) => return Err(err!(e, "While awaiting server task completion."; Async)),
+        }
+    }
+
+    req!(true, established);
+    req!(true, key1.is_some());
+    req!(true, key2.is_some());
+    req!(true, key3.is_some());
+    match (rx_a, rx_b) {
+        (Some(rx_a), Some(rx_b)) => {
+            req!(b"Hello from B".to_vec(), rx_a.data);
+            req!(b"Hello from A".to_vec(), rx_b.data);
+        },
+        _ => return Err(err!(
+            "Session data was not delivered after the session key was renewed.";
+            Test, Timeout)),
+    }
+    Ok(())
+}
+
+/// Peer C, the session initiator, renews the session key every second.
+pub async fn run_test_rekey_periodic() -> Outcome<()> {
+
+    let ip_addr = res!(local_ip());
+    let [port_c, port_d] = res!(free_ports());
+    let addr_c = SocketAddr::new(ip_addr, port_c);
+    let addr_d = SocketAddr::new(ip_addr, port_d);
+
+    let cfg_c = ServerConfig {
+        server_port_udp:    port_c,
+        session_rekey_secs: 1,
+        ..Default::default()
+    };
+    let (sessions_c, chan_c, _, handle_c) = res!(TestPeer::new(cfg_c).start());
+    let (sessions_d, chan_d, protocol_d, handle_d) = res!(TestPeer::on_port(port_d).start());
+    thread::sleep(Duration::from_millis(500)); // Allow the servers to bind.
+
+    let established = res!(connect(&chan_c, &sessions_c, &sessions_d, addr_c, addr_d));
+    let key1 = res!(session_key(&sessions_c, &addr_d));
+    let key2 = res!(await_new_key(&sessions_c, &sessions_d, &addr_c, &addr_d, &key1));
+    let key3 = res!(await_new_key(&sessions_c, &sessions_d, &addr_c, &addr_d, &key2));
+
+    res!(chan_c.send(Command::Send(addr_d, b"Hello from C".to_vec())));
+    let rx_d = res!(protocol_d.recv_data(DATA_TIMEOUT));
+
+    res!(chan_c.send(Command::Finish));
+    res!(chan_d.send(Command::Finish));
+    for handle in [handle_c, handle_d] {
+        match handle.await {
+            Ok(result) => res!(result),
+            Err(e) => return Err(err!(e, "While awaiting server task completion."; Async)),
+        }
+    }
+
+    req!(true, established);
+    req!(true, key2.is_some());
+    req!(true, key3.is_some());
+    match rx_d {
+        Some(rx_d) => req!(b"Hello from C".to_vec(), rx_d.data),
+        None => return Err(err!(
+            "Session data was not delivered after periodic session key renewal.";
+            Test, Timeout)),
+    }
+    Ok(())
+}
+
+/// Peer E rotates its packet signing key.  Peer F records the new key, and once F has
+/// acknowledged it, E adopts the key, along with the peer identifier it implies.  F verifies
+/// subsequent packets from E with the new key.
+pub async fn run_test_key_rotation() -> Outcome<()> {
+
+    let ip_addr = res!(local_ip());
+    let [port_e, port_f] = res!(free_ports());
+    let addr_e = SocketAddr::new(ip_addr, port_e);
+    let addr_f = SocketAddr::new(ip_addr, port_f);
+
+    let (sessions_e, chan_e, protocol_e, handle_e) = res!(TestPeer::on_port(port_e).start());
+    let (sessions_f, chan_f, protocol_f, handle_f) = res!(TestPeer::on_port(port_f).start());
+    thread::sleep(Duration::from_millis(500)); // Allow the servers to bind.
+
+    let established = res!(connect(&chan_e, &sessions_e, &sessions_f, addr_e, addr_f));
+
+    let old_sigpk = match res!(protocol_e.schms.sign.get_public_key()) {
+        Some(pk) => pk.to_vec(),
+        None => return Err(err!("Peer E has no public signing key."; Test, Missing)),
+    };
+    let signer = SignatureScheme::new_ed25519();
+    let (new_sigpk, new_sigsk) = match (
+        res!(signer.get_public_key()),
+        res!(signer.get_secret_key()),
+    ) {
+        (Some(pk), Some(sk)) => (pk.to_vec(), sk.to_vec()),
+        _ => return Err(err!("New signature scheme is missing keys."; Test, Missing)),
+    };
+    let recorded = |protocol_f: &TestProtocol| -> Outcome<RecordedKeys> {
+        let (ukey, locked_umap) = res!(protocol_f.ugrd.get_locked_map(&protocol_e.uid));
+        let unlocked_umap = lock_read!(locked_umap);
+        Ok(match unlocked_umap.get(&ukey) {
+            Some(ulog) => (
+                ulog.data.sigtpk_opt.as_ref().map(|pk| pk.key.clone()),
+                ulog.data.sigtpk_opt_old.as_ref().map(|pk| pk.key.clone()),
+            ),
+            None => (None, None),
+        })
+    };
+    let (before, _) = res!(recorded(&protocol_f));
+
+    res!(chan_e.send(Command::RotateKey(new_sigpk.clone(), new_sigsk)));
+    let start = Instant::now();
+    let mut after = (None, None);
+    while start.elapsed() < REKEY_TIMEOUT {
+        after = res!(recorded(&protocol_f));
+        if after.0.as_ref() == Some(&new_sigpk) {
+            test!("New signing key recorded after {:?}.", start.elapsed());
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    let own_new = res!(discovery::peer_id(&new_sigpk));
+    let mut own_after = None;
+    while start.elapsed() < REKEY_TIMEOUT {
+        let own = {
+            let unlocked_discovery = lock_read!(protocol_e.discovery);
+            *unlocked_discovery.table.own()
+        };
+        own_after = Some(own);
+        if own == own_new {
+            test!("New signing key adopted after {:?}.", start.elapsed());
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    // Signed with the new key.
+    res!(chan_e.send(Command::Send(addr_f, b"Hello from E".to_vec())));
+    let rx_f = res!(protocol_f.recv_data(DATA_TIMEOUT));
+
+    res!(chan_e.send(Command::Finish));
+    res!(chan_f.send(Command::Finish));
+    for handle in [handle_e, handle_f] {
+        match handle.await {
+            Ok(result) => res!(result),
+            Err(e) => return Err(err!(e, "While awaiting server task completion."; Async)),
+        }
+    }
+
+    req!(true, established);
+    req!(Some(old_sigpk.clone()), before);
+    req!(Some(new_sigpk.clone()), after.0);
+    req!(Some(old_sigpk.clone()), after.1);
+    req!(Some(own_new), own_after);
+    match rx_f {
+        Some(rx_f) => req!(b"Hello from E".to_vec(), rx_f.data),
+        None => return Err(err!(
+            "Session data signed with the new key was not delivered."; Test, Timeout)),
+    }
+    Ok(())
+}
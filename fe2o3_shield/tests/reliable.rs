@@ -0,0 +1,239 @@
+use crate::handshake::{
+    HANDSHAKE_TIMEOUT,
+    TestPeer,
+    cfg_without,
+    free_ports,
+    is_established,
+};
+
+use oxedyne_fe2o3_shield::srv::{
+    cfg::ServerConfig,
+    cmd::Command,
+    msg::{
+        data::SessionType,
+        reliable::{
+            self,
+            ReliableDelivery,
+            ReliableParams,
+        },
+    },
+};
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    rand::Rand,
+    test::test_it,
+};
+
+use std::{
+    collections::BTreeSet,
+    net::SocketAddr,
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use local_ip_address::local_ip;
+
+
+const DATA_TIMEOUT: Duration = Duration::from_secs(30);
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn test_params() -> ReliableParams {
+    let mut msg_types = BTreeSet::new();
+    msg_types.insert(SessionType::Data as u16);
+    ReliableParams {
+        msg_types,
+        rto_init:       Duration::from_millis(100),
+        rto_max:        Duration::from_millis(400),
+        retries_max:    3,
+        cwnd_init:      2,
+        cwnd_max:       4,
+        done_sunset:    Duration::from_secs(1),
+    }
+}
+
+fn packets(n: u8) -> Vec<Vec<u8>> {
+    (0..n).map(|i| vec![i]).collect()
+}
+
+pub fn test_reliable(filter: &'static str) -> Outcome<()> {
+
+    let peer: SocketAddr = res!("192.0.2.1:60000".parse());
+
+    res!(test_it(filter, &["Ack bitmap 000", "all", "ack"], || {
+        let bitmap = reliable::ack_bitmap(10, vec![0, 3, 9]);
+        req!(2, bitmap.len());
+        for i in 0..10 {
+            req!(i == 0 || i == 3 || i == 9, reliable::ack_bitmap_has(&bitmap, i));
+        }
+        req!(false, reliable::ack_bitmap_has(&bitmap, 16));
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Reliable params 000", "all", "params"], || {
+        let mut cfg = ServerConfig::default();
+        let params = res!(cfg.reliable_params());
+        req!(true, params.is_reliable(SessionType::Data as u16));
+        req!(false, params.is_reliable(1));
+        cfg.reliable_msg_types.push(fmt!("nonsense"));
+        req!(true, cfg.reliable_params().is_err());
+        let params = test_params();
+        req!(Duration::from_millis(100), params.rto(1));
+        req!(Duration::from_millis(200), params.rto(2));
+        req!(Duration::from_millis(400), params.rto(3));
+        req!(Duration::from_millis(400), params.rto(10));
+        // A configuration saved before reliable delivery existed falls back to the defaults.
+        let cfg = res!(cfg_without(&["reliable_"]));
+        req!(ServerConfig::default(), cfg);
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Congestion window 000", "all", "window"], move || {
+        let mut rd = ReliableDelivery::<4>::new(test_params());
+        let now = Instant::now();
+        rd.register(peer, [1; 4], packets(5));
+        // Only the initial window is sent.
+        let poll = rd.poll(now);
+        req!(2, poll.packets.len());
+        req!(vec![0u8], poll.packets[0].1.clone());
+        req!(true, rd.poll(now).packets.is_empty());
+        // Selective acknowledgement of the first packet opens the window.
+        req!(false, rd.acknowledge(&peer, &[1; 4], &reliable::ack_bitmap(5, vec![0])));
+        req!(3, rd.congestion(&peer).map(|c| c.cwnd).unwrap_or(0));
+        let poll = rd.poll(now);
+        req!(2, poll.packets.len());
+        req!(vec![2u8], poll.packets[0].1.clone());
+        // Acknowledgement of everything completes the message.
+        let all = reliable::ack_bitmap(5, 0..5);
+        req!(false, rd.acknowledge(&peer, &[1; 4], &reliable::ack_bitmap(5, 0..4)));
+        req!(4, rd.congestion(&peer).map(|c| c.cwnd).unwrap_or(0)); // Capped.
+        req!(1, rd.poll(now).packets.len());
+        req!(true, rd.acknowledge(&peer, &[1; 4], &all));
+        req!(0, rd.pending());
+        // Late acknowledgements are ignored.
+        req!(false, rd.acknowledge(&peer, &[1; 4], &all));
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Retransmission 000", "all", "retransmit"], move || {
+        let mut rd = ReliableDelivery::<4>::new(test_params());
+        let start = Instant::now();
+        rd.register(peer, [2; 4], packets(2));
+        req!(2, rd.poll(start).packets.len());
+        req!(false, rd.acknowledge(&peer, &[2; 4], &reliable::ack_bitmap(2, vec![0])));
+        // Not yet due.
+        req!(true, rd.poll(start + Duration::from_millis(50)).packets.is_empty());
+        // The unacknowledged packet is resent with a doubled timeout, and the window halves.
+        let t1 = start + Duration::from_millis(100);
+        let poll = rd.poll(t1);
+        req!(1, poll.packets.len());
+        req!(vec![1u8], poll.packets[0].1.clone());
+        req!(1, rd.congestion(&peer).map(|c| c.cwnd).unwrap_or(0));
+        req!(true, rd.poll(t1 + Duration::from_millis(150)).packets.is_empty());
+        let t2 = t1 + Duration::from_millis(200);
+        req!(1, rd.poll(t2).packets.len());
+        let t3 = t2 + Duration::from_millis(400);
+        req!(1, rd.poll(t3).packets.len());
+        // After the maximum number of retries the message is abandoned.
+        let poll = rd.poll(t3 + Duration::from_millis(400));
+        req!(true, poll.packets.is_empty());
+        req!(vec![(peer, [2u8; 4])], poll.abandoned);
+        req!(0, rd.pending());
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Duplicate suppression 000", "all", "duplicate"], move || {
+        let mut rd = ReliableDelivery::<4>::new(test_params());
+        let now = Instant::now();
+        req!(true, rd.completed(&peer, &[3; 4]).is_none());
+        rd.complete(peer, [3; 4], 7, now);
+        req!(Some(7), rd.completed(&peer, &[3; 4]));
+        req!(true, rd.completed(&peer, &[4; 4]).is_none());
+        rd.garbage_collection(now + Duration::from_millis(500));
+        req!(Some(7), rd.completed(&peer, &[3; 4]));
+        rd.garbage_collection(now + Duration::from_secs(2));
+        req!(true, rd.completed(&peer, &[3; 4]).is_none());
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Reliable data 000", "all", "data", "network"], || {
+        let rt = res!(tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(4)
+            .enable_all()
+            .build());
+        rt.block_on(run_test_reliable_data())
+    }));
+
+    Ok(())
+}
+
+pub async fn run_test_reliable_data() -> Outcome<()> {
+
+    let ip_addr = res!(local_ip());
+    let [port_a, port_b] = res!(free_ports());
+    let addr_a = SocketAddr::new(ip_addr, port_a);
+    let addr_b = SocketAddr::new(ip_addr, port_b);
+
+    let (sessions_a, chan_a, protocol_a, handle_a) = res!(TestPeer::on_port(port_a).start());
+    let (sessions_b, chan_b, protocol_b, handle_b) = res!(TestPeer::on_port(port_b).start());
+    thread::sleep(Duration::from_millis(500)); // Allow the servers to bind.
+
+    res!(chan_a.send(Command::Connect(addr_b)));
+    let start = Instant::now();
+    while start.elapsed() < HANDSHAKE_TIMEOUT {
+        if res!(is_established(&sessions_a, &addr_b)) && res!(is_established(&sessions_b, &addr_a)) {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    // Enough packets to exceed the initial congestion window.
+    let mut large = vec![0u8; 20_000];
+    Rand::fill_u8(&mut large);
+    res!(chan_a.send(Command::Send(addr_b, large.clone())));
+
+    let rx_b = res!(protocol_b.recv_data(DATA_TIMEOUT));
+
+    // The sender releases the message once every chunk has been acknowledged.
+    let start = Instant::now();
+    let mut pending = 1;
+    while start.elapsed() < DRAIN_TIMEOUT {
+        pending = {
+            let unlocked_reliable = lock_read!(protocol_a.reliable);
+            unlocked_reliable.pending()
+        };
+        if pending == 0 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    let extra = res!(protocol_b.recv_data(Duration::from_millis(500)));
+
+    res!(chan_a.send(Command::Finish));
+    res!(chan_b.send(Command::Finish));
+    for handle in [handle_a, handle_b] {
+        match handle.await {
+            Ok(result) => res!(result),
+            Err(e) => return Err(err!(e, "While awaiting server task completion."; Async)),
+        }
+    }
+
+    match rx_b {
+        Some(delivery) => {
+            test!("Peer B received {} bytes from {:?}.", delivery.data.len(), delivery.peer);
+            req!(addr_a, delivery.peer);
+            req!(large, delivery.data);
+        },
+        None => return Err(err!(
+            "Peer B received no session data within {:?}.", DATA_TIMEOUT;
+            Test, Timeout)),
+    }
+    test!("Peer A has {} messages awaiting acknowledgement.", pending);
+    req!(0, pending);
+    req!(true, extra.is_none()); // Delivered exactly once.
+
+    Ok(())
+}
@@ -0,0 +1,171 @@
+use crate::handshake::{
+    HANDSHAKE_TIMEOUT,
+    TestPeer,
+    TestProtocolTypes,
+    free_ports,
+    is_established,
+};
+
+use oxedyne_fe2o3_shield::srv::{
+    cmd::Command,
+    msg::protocol::ProtocolTypes,
+    session::{
+        ReplayWindow,
+        Session,
+        SessionState,
+    },
+};
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    rand::Rand,
+    test::test_it,
+};
+use oxedyne_fe2o3_crypto::enc::EncryptionScheme;
+use oxedyne_fe2o3_net::id;
+
+use std::{
+    net::SocketAddr,
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use local_ip_address::local_ip;
+
+
+type TestSession = Session<
+    8,
+    {id::MID_LEN},
+    {id::SID_LEN},
+    {id::UID_LEN},
+    <TestProtocolTypes as ProtocolTypes<
+        {id::MID_LEN},
+        {id::SID_LEN},
+        {id::UID_LEN},
+    >>::ID,
+>;
+
+const DATA_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub fn test_session(filter: &'static str) -> Outcome<()> {
+
+    res!(test_it(filter, &["Replay window 000", "all", "replay"], || {
+        let mut win = ReplayWindow::default();
+        req!(false, win.accept(0));
+        req!(true, win.accept(1));
+        req!(false, win.accept(1));
+        req!(true, win.accept(3));
+        req!(true, win.accept(2)); // Late, but not yet seen.
+        req!(false, win.accept(2));
+        req!(true, win.accept(3 + ReplayWindow::SIZE));
+        req!(false, win.accept(3)); // Now too old to tell.
+        req!(true, win.accept(4));
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Seal and open 000", "all", "seal"], || {
+        let mut key = [0u8; 32];
+        Rand::fill_u8(&mut key);
+        let enc = res!(EncryptionScheme::new_aes_256_gcm_with_key(&key));
+        let mut sess_x = TestSession::new(SessionState::Established, true);
+        let mut sess_y = TestSession::new(SessionState::Established, false);
+
+        let c1 = res!(sess_x.seal(&enc, b"first"));
+        let c2 = res!(sess_x.seal(&enc, b"second"));
+        // Delivered out of order.
+        let p2 = res!(sess_y.open(&enc, &c2));
+        req!(Some(b"second".to_vec()), p2);
+        let p1 = res!(sess_y.open(&enc, &c1));
+        req!(Some(b"first".to_vec()), p1);
+        // Replayed.
+        let p1 = res!(sess_y.open(&enc, &c1));
+        req!(true, p1.is_none());
+        // Reflected back to the sender.
+        let c3 = res!(sess_x.seal(&enc, b"third"));
+        let p3 = res!(sess_x.open(&enc, &c3));
+        req!(true, p3.is_none());
+        // Both directions use their own sequence.
+        let c4 = res!(sess_y.seal(&enc, b"fourth"));
+        let p4 = res!(sess_x.open(&enc, &c4));
+        req!(Some(b"fourth".to_vec()), p4);
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Session data 000", "all", "data", "network"], || {
+        let rt = res!(tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(4)
+            .enable_all()
+            .build());
+        rt.block_on(run_test_session_data())
+    }));
+
+    Ok(())
+}
+
+pub async fn run_test_session_data() -> Outcome<()> {
+
+    let ip_addr = res!(local_ip());
+    let [port_a, port_b] = res!(free_ports());
+    let addr_a = SocketAddr::new(ip_addr, port_a);
+    let addr_b = SocketAddr::new(ip_addr, port_b);
+
+    let (sessions_a, chan_a, protocol_a, handle_a) = res!(TestPeer::on_port(port_a).start());
+    let (sessions_b, chan_b, protocol_b, handle_b) = res!(TestPeer::on_port(port_b).start());
+    thread::sleep(Duration::from_millis(500)); // Allow the servers to bind.
+
+    res!(chan_a.send(Command::Connect(addr_b)));
+    let start = Instant::now();
+    while start.elapsed() < HANDSHAKE_TIMEOUT {
+        if res!(is_established(&sessions_a, &addr_b)) && res!(is_established(&sessions_b, &addr_a)) {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    // A small payload from the initiator, and one large enough to be chunked from the responder.
+    let small = b"Hello from A".to_vec();
+    let mut large = vec![0u8; 10_000];
+    Rand::fill_u8(&mut large);
+    res!(chan_a.send(Command::Send(addr_b, small.clone())));
+    res!(chan_b.send(Command::Send(addr_a, large.clone())));
+
+    let rx_b = res!(protocol_b.recv_data(DATA_TIMEOUT));
+    let rx_a = res!(protocol_a.recv_data(DATA_TIMEOUT));
+
+    res!(chan_a.send(Command::Finish));
+    res!(chan_b.send(Command::Finish));
+    for handle in [handle_a, handle_b] {
+        match handle.await {
+            Ok(result) => res!(result),
+            Err(e) => return Err(err!(e, "While awaiting server task completion."; Async)),
+        }
+    }
+
+    match rx_b {
+        Some(delivery) => {
+            test!("Peer B received {} bytes from {:?}.", delivery.data.len(), delivery.peer);
+            req!(addr_a, delivery.peer);
+            req!(small, delivery.data);
+        },
+        None => return Err(err!(
+            "Peer B received no session data within {:?}.", DATA_TIMEOUT;
+            Test, Timeout)),
+    }
+    match rx_a {
+        Some(delivery) => {
+            test!("Peer A received {} bytes from {:?}.", delivery.data.len(), delivery.peer);
+            req!(addr_b, delivery.peer);
+            req!(large, delivery.data);
+        },
+        None => return Err(err!(
+            "Peer A received no session data within {:?}.", DATA_TIMEOUT;
+            Test, Timeout)),
+    }
+    let extra = res!(protocol_a.try_recv_data());
+    req!(true, extra.is_none());
+
+    Ok(())
+}
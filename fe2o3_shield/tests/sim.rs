@@ -15,6 +15,7 @@ use oxedyne_fe2o3_shield::{
 use oxedyne_fe2o3_core::{
     prelude::*,
     channels::Recv,
+    rand::Rand,
     log::{
         console::{
             switch_to_logger_console,
@@ -53,11 +54,12 @@ pub async fn run_test_sim(_filter: &'static str) -> Outcome<()> {
     const NUM_PEERS: usize = 1;
 
     let mut server_handles = Vec::new();
+    let sim_root = std::env::temp_dir().join(fmt!("shield_sim_{}", Rand::rand_u32()));
 
     for peer in 1..=NUM_PEERS {
         let id = fmt!("{:03}", peer);
         msg!("Starting peer: {}", id);
-        let peer_dir = fmt!("sims/{}", id);
+        let peer_dir = sim_root.join(&id).to_string_lossy().into_owned();
 
         if res!(fs::exists(&peer_dir)) {
             res!(fs::remove_dir_all(&peer_dir));
@@ -0,0 +1,414 @@
+//! A local multi-peer simulation harness.  Each of N Shield servers listens on its own port, and
+//! every pair of peers is joined by a `Link` that forwards their packets through a `PacketShim`,
+//! so that loss, reordering, duplication, latency and corruption can be scripted per pair.
+//! Peers address each other only via their links.
+//!
+//! The address guard keys its logs by IP address, and all simulated peers share one, so
+//! handshakes must be performed one at a time.
+use crate::handshake::{
+    TestPeer,
+    TestProtocol,
+    TestSessionMap,
+    free_ports,
+    is_established,
+};
+
+use oxedyne_fe2o3_shield::srv::{
+    cfg::ServerConfig,
+    cmd::Command,
+    guard::addr::AddressState,
+    shim::{
+        Link,
+        LinkConditions,
+        LinkDirection,
+        LinkStats,
+        PacketShim,
+    },
+};
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    channels::Simplex,
+    rand::Rand,
+    test::test_it,
+};
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    net::SocketAddr,
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use local_ip_address::local_ip;
+
+
+pub struct SimPeer {
+    pub addr:       SocketAddr,
+    pub sessions:   TestSessionMap,
+    pub chan:       Simplex<Command>,
+    pub protocol:   TestProtocol,
+    handle:         tokio::task::JoinHandle<Outcome<()>>,
+}
+
+pub struct SimNet {
+    pub peers:  Vec<SimPeer>,
+    links:      BTreeMap<(usize, usize), Link>, // Keyed by (i, j) with i < j, i being peer A.
+}
+
+impl SimNet {
+
+    /// Start a server on each of the given ports, all using the given configuration, and link
+    /// every pair with ideal conditions.
+    pub fn start(ports: &[u16], cfg: &ServerConfig) -> Outcome<Self> {
+        let ip = res!(local_ip());
+        let mut peers = Vec::new();
+        for port in ports {
+            let mut cfg = cfg.clone();
+            cfg.server_port_udp = *port;
+            let (sessions, chan, protocol, handle) = res!(TestPeer::new(cfg).start());
+            peers.push(SimPeer {
+                addr: SocketAddr::new(ip, *port),
+                sessions,
+                chan,
+                protocol,
+                handle,
+            });
+        }
+        let mut links = BTreeMap::new();
+        for i in 0..peers.len() {
+            for j in (i + 1)..peers.len() {
+                links.insert((i, j), res!(Link::new(
+                    ip,
+                    peers[i].addr,
+                    peers[j].addr,
+                    Box::new(LinkConditions::ideal()),
+                )));
+            }
+        }
+        thread::sleep(Duration::from_millis(500)); // Allow the servers to bind.
+        Ok(Self {
+            peers,
+            links,
+        })
+    }
+
+    fn link(&self, i: usize, j: usize) -> Outcome<&Link> {
+        let key = if i < j { (i, j) } else { (j, i) };
+        match self.links.get(&key) {
+            Some(link) => Ok(link),
+            None => Err(err!("No link between peers {} and {}.", i, j; Test, Missing)),
+        }
+    }
+
+    /// The address at which peer i reaches peer j.
+    pub fn addr(&self, i: usize, j: usize) -> Outcome<SocketAddr> {
+        let link = res!(self.link(i, j));
+        Ok(if i < j { link.addr_a } else { link.addr_b })
+    }
+
+    pub fn set_shim(&self, i: usize, j: usize, shim: Box<dyn PacketShim>) -> Outcome<()> {
+        res!(self.link(i, j)).set_shim(shim)
+    }
+
+    pub fn stats(&self, i: usize, j: usize) -> Outcome<LinkStats> {
+        res!(self.link(i, j)).stats()
+    }
+
+    pub fn peer(&self, i: usize) -> Outcome<&SimPeer> {
+        match self.peers.get(i) {
+            Some(peer) => Ok(peer),
+            None => Err(err!("No peer {} in a network of {}.", i, self.peers.len(); Test, Missing)),
+        }
+    }
+
+    /// Stop all servers and links.
+    pub async fn finish(self) -> Outcome<()> {
+        for peer in &self.peers {
+            res!(peer.chan.send(Command::Finish));
+        }
+        for peer in self.peers {
+            match peer.handle.await {
+                Ok(result) => res!(result),
+                Err(e) => return Err(err!(e, "While awaiting server task completion."; Async)),
+            }
+        }
+        for (_, mut link) in self.links {
+            res!(link.stop());
+        }
+        Ok(())
+    }
+}
+
+/// Assertions on the guard state of peer i with respect to peer j.
+#[derive(Clone, Debug)]
+pub enum GuardCheck {
+    UserCode, // Peer i has issued peer j a proof of work code.
+    SigningKey, // Peer i holds the public signing key of peer j.
+    Monitored, // Peer i is neither throttling nor blacklisting peer j.
+}
+
+#[derive(Clone)]
+pub enum Step {
+    Conditions(usize, usize, LinkConditions),
+    Connect(usize, usize),
+    Established(usize, usize, Duration),
+    Unestablished(usize, usize), // Neither peer has an established session with the other.
+    Send(usize, usize, Vec<u8>),
+    Delivered(usize, usize, Vec<u8>, Duration), // Peer i receives the data from peer j.
+    Guard(usize, usize, GuardCheck),
+    Sleep(Duration),
+}
+
+impl fmt::Debug for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Conditions(i, j, cond)        => write!(f, "Conditions({}, {}, {:?})", i, j, cond),
+            Self::Connect(i, j)                 => write!(f, "Connect({}, {})", i, j),
+            Self::Established(i, j, wait)       => write!(f, "Established({}, {}, {:?})", i, j, wait),
+            Self::Unestablished(i, j)           => write!(f, "Unestablished({}, {})", i, j),
+            Self::Send(i, j, data)              => write!(f, "Send({}, {}, {} bytes)", i, j, data.len()),
+            Self::Delivered(i, j, data, wait)   => write!(f, "Delivered({}, {}, {} bytes, {:?})",
+                                                        i, j, data.len(), wait),
+            Self::Guard(i, j, check)            => write!(f, "Guard({}, {}, {:?})", i, j, check),
+            Self::Sleep(wait)                   => write!(f, "Sleep({:?})", wait),
+        }
+    }
+}
+
+pub struct Scenario {
+    pub name:   &'static str,
+    pub steps:  Vec<Step>,
+}
+
+impl Scenario {
+
+    pub fn run(&self, net: &SimNet) -> Outcome<()> {
+        for (k, step) in self.steps.iter().enumerate() {
+            test!("{} step {}: {:?}", self.name, k, step);
+            res!(Self::step(net, step), Test);
+        }
+        Ok(())
+    }
+
+    fn step(net: &SimNet, step: &Step) -> Outcome<()> {
+        match step {
+            Step::Conditions(i, j, cond) => res!(net.set_shim(*i, *j, Box::new(cond.clone()))),
+            Step::Connect(i, j) => res!(res!(net.peer(*i)).chan.send(
+                Command::Connect(res!(net.addr(*i, *j))))),
+            Step::Established(i, j, wait) => {
+                let start = Instant::now();
+                loop {
+                    if res!(is_established(&res!(net.peer(*i)).sessions, &res!(net.addr(*i, *j))))
+                        && res!(is_established(&res!(net.peer(*j)).sessions, &res!(net.addr(*j, *i))))
+                    {
+                        test!("Session between peers {} and {} established after {:?}.",
+                            i, j, start.elapsed());
+                        break;
+                    }
+                    if start.elapsed() > *wait {
+                        return Err(err!(
+                            "Peers {} and {} failed to establish a session within {:?}.",
+                            i, j, wait;
+                            Test, Timeout));
+                    }
+                    thread::sleep(Duration::from_millis(100));
+                }
+            },
+            Step::Unestablished(i, j) => {
+                if res!(is_established(&res!(net.peer(*i)).sessions, &res!(net.addr(*i, *j))))
+                    || res!(is_established(&res!(net.peer(*j)).sessions, &res!(net.addr(*j, *i))))
+                {
+                    return Err(err!(
+                        "Peers {} and {} unexpectedly have an established session.", i, j;
+                        Test, Unexpected));
+                }
+            },
+            Step::Send(i, j, data) => res!(res!(net.peer(*i)).chan.send(
+                Command::Send(res!(net.addr(*i, *j)), data.clone()))),
+            Step::Delivered(i, j, data, wait) => {
+                match res!(res!(net.peer(*i)).protocol.recv_data(*wait)) {
+                    Some(delivery) => {
+                        req!(res!(net.addr(*i, *j)), delivery.peer);
+                        req!(*data, delivery.data);
+                    },
+                    None => return Err(err!(
+                        "Peer {} received no data from peer {} within {:?}.", i, j, wait;
+                        Test, Timeout)),
+                }
+            },
+            Step::Guard(i, j, check) => {
+                let protocol = &res!(net.peer(*i)).protocol;
+                let passed = match check {
+                    GuardCheck::UserCode | GuardCheck::SigningKey => {
+                        let uid = &res!(net.peer(*j)).protocol.uid;
+                        let (ukey, locked_umap) = res!(protocol.ugrd.get_locked_map(uid));
+                        let unlocked_umap = lock_read!(locked_umap);
+                        match unlocked_umap.get(&ukey) {
+                            Some(ulog) => match check {
+                                GuardCheck::UserCode => ulog.data.code.is_some(),
+                                _ => ulog.data.sigtpk_opt.is_some(),
+                            },
+                            None => false,
+                        }
+                    },
+                    GuardCheck::Monitored => {
+                        let addr = res!(net.addr(*i, *j));
+                        let (akey, locked_amap) = res!(protocol.agrd.get_locked_map(&addr));
+                        let unlocked_amap = lock_read!(locked_amap);
+                        match unlocked_amap.get(&akey) {
+                            Some(alog) => matches!(alog.state, AddressState::Monitor(..)),
+                            None => false,
+                        }
+                    },
+                };
+                if !passed {
+                    return Err(err!(
+                        "Guard check {:?} failed for peer {} with respect to peer {}.",
+                        check, i, j;
+                        Test, Mismatch));
+                }
+            },
+            Step::Sleep(wait) => thread::sleep(*wait),
+        }
+        Ok(())
+    }
+}
+
+const HANDSHAKE_WAIT: Duration = Duration::from_secs(60);
+const DATA_WAIT: Duration = Duration::from_secs(60);
+
+fn random_data(n: usize) -> Vec<u8> {
+    let mut data = vec![0u8; n];
+    Rand::fill_u8(&mut data);
+    data
+}
+
+pub fn test_simnet(filter: &'static str) -> Outcome<()> {
+
+    res!(test_it(filter, &["Link conditions 000", "all", "link"], || {
+        let packet = vec![0u8; 100];
+        let mut cond = LinkConditions { loss: 1.0, ..Default::default() };
+        req!(true, cond.shape(LinkDirection::AtoB, packet.clone()).is_empty());
+        let mut cond = LinkConditions { duplicate: 1.0, ..Default::default() };
+        req!(2, cond.shape(LinkDirection::AtoB, packet.clone()).len());
+        let mut cond = LinkConditions { corrupt: 1.0, ..Default::default() };
+        let copies = cond.shape(LinkDirection::BtoA, packet.clone());
+        req!(1, copies.len());
+        req!(1, copies[0].1.iter().map(|b| b.count_ones()).sum::<u32>());
+        let mut cond = LinkConditions {
+            latency:    Duration::from_millis(10),
+            jitter:     Duration::from_millis(5),
+            ..Default::default()
+        };
+        let delay = cond.shape(LinkDirection::AtoB, packet).remove(0).0;
+        req!(true, delay >= Duration::from_millis(10) && delay <= Duration::from_millis(15));
+        Ok(())
+    }));
+
+    res!(test_it(filter, &["Sim clean 000", "all", "sim", "network"], || {
+        let rt = res!(tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(4)
+            .enable_all()
+            .build());
+        rt.block_on(run_sim_clean())
+    }));
+
+    res!(test_it(filter, &["Sim lossy 000", "all", "sim", "network"], || {
+        let rt = res!(tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(4)
+            .enable_all()
+            .build());
+        rt.block_on(run_sim_lossy())
+    }));
+
+    Ok(())
+}
+
+/// Three peers on ideal links establish sessions pairwise and exchange data.
+pub async fn run_sim_clean() -> Outcome<()> {
+    let net = res!(SimNet::start(&res!(free_ports::<3>()), &ServerConfig::default()));
+    let (d01, d12, d20) = (random_data(100), random_data(3_000), random_data(10));
+    let scenario = Scenario {
+        name: "Sim clean",
+        steps: vec![
+            Step::Connect(0, 1),
+            Step::Established(0, 1, HANDSHAKE_WAIT),
+            Step::Connect(1, 2),
+            Step::Established(1, 2, HANDSHAKE_WAIT),
+            Step::Connect(2, 0),
+            Step::Established(2, 0, HANDSHAKE_WAIT),
+            Step::Send(0, 1, d01.clone()),
+            Step::Delivered(1, 0, d01, DATA_WAIT),
+            Step::Send(1, 2, d12.clone()),
+            Step::Delivered(2, 1, d12, DATA_WAIT),
+            Step::Send(2, 0, d20.clone()),
+            Step::Delivered(0, 2, d20, DATA_WAIT),
+            Step::Guard(1, 0, GuardCheck::UserCode),
+            Step::Guard(0, 1, GuardCheck::UserCode),
+            Step::Guard(2, 1, GuardCheck::SigningKey),
+            Step::Guard(0, 2, GuardCheck::Monitored),
+        ],
+    };
+    let result = scenario.run(&net);
+    let stats = net.stats(0, 1);
+    res!(net.finish().await);
+    res!(result);
+    let stats = res!(stats);
+    test!("Link 0-1: {:?}", stats);
+    req!(0, stats.dropped);
+    req!(stats.received, stats.delivered);
+    Ok(())
+}
+
+/// A handshake over a dead link fails, and succeeds when retried over a slow link that reorders
+/// packets.  Data then crosses the link while it loses, duplicates, corrupts and reorders packets,
+/// relying on reliable delivery.
+pub async fn run_sim_lossy() -> Outcome<()> {
+    let net = res!(SimNet::start(&res!(free_ports::<2>()), &ServerConfig::default()));
+    let data = random_data(8_000);
+    let scenario = Scenario {
+        name: "Sim lossy",
+        steps: vec![
+            Step::Conditions(0, 1, LinkConditions { loss: 1.0, ..Default::default() }),
+            Step::Connect(0, 1),
+            Step::Sleep(Duration::from_secs(2)),
+            Step::Unestablished(0, 1),
+            Step::Conditions(0, 1, LinkConditions {
+                reorder:    0.2,
+                latency:    Duration::from_millis(10),
+                jitter:     Duration::from_millis(10),
+                ..Default::default()
+            }),
+            Step::Connect(0, 1),
+            Step::Established(0, 1, HANDSHAKE_WAIT),
+            Step::Conditions(0, 1, LinkConditions {
+                loss:       0.1,
+                duplicate:  0.1,
+                corrupt:    0.05,
+                reorder:    0.1,
+                latency:    Duration::from_millis(10),
+                jitter:     Duration::from_millis(10),
+            }),
+            Step::Send(0, 1, data.clone()),
+            Step::Delivered(1, 0, data, DATA_WAIT),
+            Step::Guard(1, 0, GuardCheck::Monitored),
+            Step::Guard(1, 0, GuardCheck::SigningKey),
+        ],
+    };
+    let result = scenario.run(&net);
+    let stats = net.stats(0, 1);
+    let extra = net.peers[1].protocol.try_recv_data();
+    res!(net.finish().await);
+    res!(result);
+    let stats = res!(stats);
+    let extra = res!(extra);
+    test!("Link 0-1: {:?}", stats);
+    req!(true, stats.dropped > 0);
+    req!(true, extra.is_none()); // Delivered exactly once.
+    Ok(())
+}
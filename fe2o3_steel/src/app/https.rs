@@ -1,5 +1,6 @@
 use crate::srv::{
     cfg::ServerConfig,
+    constant,
     dev::refresh::HtmlModifier,
 };
 
@@ -15,11 +16,16 @@ use oxedyne_fe2o3_core::{
 use oxedyne_fe2o3_iop_crypto::enc::Encrypter;
 use oxedyne_fe2o3_iop_db::api::Database;
 use oxedyne_fe2o3_iop_hash::api::Hasher;
-use oxedyne_fe2o3_jdat::id::NumIdDat;
+use oxedyne_fe2o3_jdat::{
+    prelude::*,
+    id::NumIdDat,
+};
 use oxedyne_fe2o3_net::{
+    conc::AsyncReadChunks,
     file::RequestPath,
     http::{
         fields::HeaderName,
+        form::HttpUpload,
         handler::WebHandler,
         loc::HttpLocator,
         msg::HttpMessage,
@@ -85,6 +91,24 @@ impl<
         Rand::generate_random_string(6, "abcdefghikmnpqrstuvw0123456789")
     }
 
+    /// Large files are streamed rather than read into memory, except for HTML files in dev mode,
+    /// which are modified before serving.
+    async fn stream_file(
+        file:       &tokio::fs::File,
+        abs_path:   &Path,
+        dev_mode:   bool,
+    )
+        -> bool
+    {
+        if dev_mode && RequestPath::content_type(abs_path).to_string().contains("text/html") {
+            return false;
+        }
+        match file.metadata().await {
+            Ok(meta) => meta.len() > constant::HTTP_STREAM_FILE_THRESHOLD,
+            Err(_) => false,
+        }
+    }
+
     async fn router(
         &self,
         loc:    &HttpLocator,
@@ -197,6 +221,16 @@ impl<
             let result = tokio::task::spawn_blocking(move || {
                 tokio::runtime::Handle::current().block_on(async {
                     Ok(match tokio::fs::File::open(&abs_path).await {
+                        Ok(file) if Self::stream_file(&file, &abs_path, dev_mode).await => {
+                            HttpMessage::new_response(HttpStatus::OK)
+                                .with_field(
+                                    HeaderName::ContentType,
+                                    RequestPath::content_type(abs_path.as_path()),
+                                )
+                                .with_stream(
+                                    AsyncReadChunks::<{ constant::HTTP_STREAM_CHUNK_SIZE }, _>::new(file)
+                                )
+                        }
                         Ok(mut file) => {
                             let mut contents = Vec::new();
                             match file.read_to_end(&mut contents).await {
@@ -267,17 +301,46 @@ impl<
         DB:     Database<UIDL, UID, ENC, KH>,
     >(
         &self,
-        _loc:        HttpLocator,
+        loc:        HttpLocator,
         response:   Option<HttpMessage>,
         _body:       Vec<u8>,
+        form:       DaticleMap,
         _db:         Option<(Arc<RwLock<DB>>, UID)>,
         _sid_opt:    &Option<SID>,
-        _id:         &String, 
+        id:         &String, 
     )
         -> impl std::future::Future<Output = Outcome<Option<HttpMessage>>> + Send
     {
+        let id = id.to_string();
+
         async move {
-            Ok(response)
+            if form.is_empty() {
+                return Ok(response);
+            }
+            // Acknowledge the fields received, describing any uploads.
+            let mut received = Vec::new();
+            for (k, v) in &form {
+                let k = match k {
+                    Dat::Str(s) => s.clone(),
+                    _ => fmt!("{:?}", k),
+                };
+                let vals = match v {
+                    Dat::List(list) => list.iter().collect::<Vec<_>>(),
+                    _ => vec![v],
+                };
+                for val in vals {
+                    received.push(match HttpUpload::from_dat(val) {
+                        Ok(upload) => fmt!("{} ({}, {} bytes)", k, upload.filename, upload.size),
+                        Err(_) => fmt!("{}", k),
+                    });
+                }
+            }
+            let received = received.join(", ");
+            debug!("{}: Form posted to {} with {}.", id, loc.path.as_str(), received);
+            Ok(Some(HttpMessage::respond_with_text(
+                HttpStatus::OK,
+                fmt!("Received {}.", received),
+            )))
         }
     }
 }
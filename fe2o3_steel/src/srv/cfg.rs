@@ -18,7 +18,10 @@ use oxedyne_fe2o3_jdat::{
     cfg::Config,
 };
 use oxedyne_fe2o3_net::{
-    constant::SESSION_ID_KEY_LABEL,
+    constant::{
+        self as net_constant,
+        SESSION_ID_KEY_LABEL,
+    },
     dns::Fqdn,
     http::{
         fields::{
@@ -26,6 +29,7 @@ use oxedyne_fe2o3_net::{
             SetCookieAttributes,
             SameSite,
         },
+        form::HttpFormConfig,
     },
 };
 
@@ -65,6 +69,15 @@ pub struct ServerConfig {
     pub default_index_files:            Vec<String>, // Must be filenames, not paths.
     // Server policy
     pub server_accept_unknown_users:    bool,
+    // Forms
+    #[optional]
+    pub form_memory_limit:              u64, // Uploads larger than this are written to disk.
+    #[optional]
+    pub form_max_field_size:            u64, // Limit for each text field.
+    #[optional]
+    pub form_max_upload_size:           u64, // Limit for each uploaded file.
+    #[optional]
+    pub form_max_parts:                 u64, // Limit for the number of fields and uploads.
 }
 
 impl Config for ServerConfig {}
@@ -104,6 +117,11 @@ impl Default for ServerConfig {
             ],
             // Server policy.
             server_accept_unknown_users:    false,
+            // Forms.
+            form_memory_limit:              net_constant::HTTP_FORM_MEMORY_LIMIT as u64,
+            form_max_field_size:            net_constant::HTTP_FORM_MAX_FIELD_SIZE as u64,
+            form_max_upload_size:           net_constant::HTTP_FORM_MAX_UPLOAD_SIZE as u64,
+            form_max_parts:                 net_constant::HTTP_FORM_MAX_PARTS as u64,
         }
     }
 }
@@ -180,6 +198,17 @@ impl ServerConfig {
         Duration::from_secs(self.session_expiry_default_secs as u64)
     }
 
+    /// The limits applied to forms posted to the server.
+    pub fn form_config(&self) -> HttpFormConfig {
+        HttpFormConfig {
+            memory_limit:       self.form_memory_limit as usize,
+            max_field_size:     self.form_max_field_size as usize,
+            max_upload_size:    self.form_max_upload_size as usize,
+            max_parts:          self.form_max_parts as usize,
+            ..Default::default()
+        }
+    }
+
     pub fn log_level(&self) -> Outcome<LogLevel> {
         LogLevel::from_str(&self.log_level)
     }
@@ -15,6 +15,9 @@ pub const DEFAULT_INDEX_FILE:                   &'static str = "index.html";
 
 pub const HTTP_DEFAULT_HEADER_CHUNK_SIZE:       usize = 1_500;
 pub const HTTP_DEFAULT_BODY_CHUNK_SIZE:         usize = 5_000;
+// Files larger than this are streamed to the client using chunked transfer encoding.
+pub const HTTP_STREAM_FILE_THRESHOLD:           u64 = 1_048_576;
+pub const HTTP_STREAM_CHUNK_SIZE:               usize = 65_536;
 
 pub const STACK_SIZE:                           usize = 2 * 1024 * 1024;
 
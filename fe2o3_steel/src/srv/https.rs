@@ -17,8 +17,10 @@ use oxedyne_fe2o3_jdat::id::{
 use oxedyne_fe2o3_net::{
     conc::AsyncReadIterator,
     http::{
+        form::HttpForm,
         handler::WebHandler,
         header::{
+            HttpHeader,
             HttpHeadline,
             HttpMethod,
         },
@@ -38,13 +40,19 @@ use std::{
     pin::Pin,
 };
 
-use tokio::{
-    net::TcpStream,
-    io::AsyncWriteExt,
+use tokio::io::{
+    AsyncRead,
+    AsyncWrite,
+    AsyncWriteExt,
 };
-use tokio_rustls::server::TlsStream;
 
 
+/// Multipart form uploads are read directly from the connection, rather than buffered.
+fn is_form_upload(header: &HttpHeader) -> bool {
+    matches!(header.headline, HttpHeadline::Request { method: HttpMethod::POST, .. })
+        && HttpForm::multipart_boundary(&header.fields).is_some()
+}
+
 impl<
     const UIDL: usize,
     UID:    NumIdDat<UIDL> + 'static,
@@ -58,9 +66,13 @@ impl<
     //ServerContext<UIDL, UID, ENC, KH, DB, EH, WH, WSH>
     ServerContext<UIDL, UID, ENC, KH, DB, WH, WSH>
 {
-    pub async fn handle_https(
+    /// Serve HTTP requests on the given connection, normally a TLS stream, until it closes or is
+    /// upgraded to a websocket.
+    pub async fn handle_https<
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    >(
         self,
-        mut stream: TlsStream<TcpStream>,
+        mut stream: S,
         handler:    WH,
         ws_handler: WSH,
         ws_syntax:  SyntaxRef,
@@ -77,7 +89,8 @@ impl<
             { constant::HTTP_DEFAULT_HEADER_CHUNK_SIZE },
             { constant::HTTP_DEFAULT_BODY_CHUNK_SIZE },
             _,
-        > = HttpMessageReader::new(Pin::new(&mut read_stream));
+        > = HttpMessageReader::new(Pin::new(&mut read_stream))
+            .with_streamed_body_if(is_form_upload);
 
         let log_level = res!(self.cfg.log_level());
     
@@ -126,6 +139,53 @@ impl<
                                     ).await;
                                     response = res!(result);
                                 }
+                                HttpMethod::POST => {
+                                    let form_cfg = self.cfg.form_config();
+                                    let result = match HttpForm::multipart_boundary(
+                                        &request.header.fields,
+                                    ) {
+                                        Some(boundary) => HttpForm::read_multipart::<
+                                            { constant::HTTP_DEFAULT_BODY_CHUNK_SIZE },
+                                            _,
+                                        >(
+                                            reader.body(),
+                                            boundary,
+                                            &form_cfg,
+                                        ).await,
+                                        None => HttpForm::from_body(
+                                            &request.header.fields,
+                                            &body,
+                                            &form_cfg,
+                                        ).await,
+                                    };
+                                    match result {
+                                        Ok(form) => {
+                                            let uploads = HttpForm::uploads(&form);
+                                            let result = handler.handle_post(
+                                                loc,
+                                                response,
+                                                body,
+                                                form,
+                                                self.db.clone(),
+                                                &sid_opt,
+                                                &id,
+                                            ).await;
+                                            for upload in uploads {
+                                                if let Err(e) = upload.remove().await {
+                                                    error!(e, "{}: While removing upload.", id);
+                                                }
+                                            }
+                                            response = res!(result);
+                                        }
+                                        Err(e) => {
+                                            error!(e, "{}: Invalid form from {:?}.", id, src_addr);
+                                            response = Some(HttpMessage::respond_with_text(
+                                                HttpStatus::BadRequest,
+                                                "Invalid form data.",
+                                            ));
+                                        }
+                                    }
+                                }
                                 _ => fault!("{}: Unsupported HTTP request method '{}'.", id, method),
                             }
                         },
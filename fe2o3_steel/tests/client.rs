@@ -1,18 +1,12 @@
 use oxedyne_fe2o3_steel::srv::{
     constant,
     context,
-    id,
     ws::syntax::WebSocketSyntax,
 };
 
 use oxedyne_fe2o3_core::{
     prelude::*,
 };
-use oxedyne_fe2o3_crypto::enc::EncryptionScheme;
-use oxedyne_fe2o3_hash::{
-    csum::ChecksumScheme,
-    hash::HashScheme,
-};
 use oxedyne_fe2o3_jdat::version::SemVer;
 use oxedyne_fe2o3_net::{
     conc::AsyncReadIterator,
@@ -23,23 +17,18 @@ use oxedyne_fe2o3_net::{
     },
     ws::{
         self,
-        WebSocket,
         WebSocketMessage,
         handler::WebSocketSinkHandler,
         status::WebSocketStatusCode,
     },
 };
-use oxedyne_fe2o3_o3db_sync::O3db;
 
 use std::{
     fs::File,
     io::BufReader,
     path::Path,
     pin::Pin,
-    sync::{
-        Arc,
-        RwLock,
-    },
+    sync::Arc,
     thread,
     time::Duration,
 };
@@ -56,7 +45,6 @@ use tokio_rustls::{
     client::TlsStream,
     rustls::{
         self,
-        client::danger::ServerCertVerifier,
         ClientConfig,
         RootCertStore,
     },
@@ -66,7 +54,7 @@ use tokio_rustls::{
 fn load_certs() -> Outcome<RootCertStore> {
     let mut root_store = RootCertStore::empty();
     let home = res!(std::env::var("HOME"));
-    let path = Path::new(home).join("usr/code/web/apps/test/tls/fullchain.pem");
+    let path = Path::new(&home).join("usr/code/web/apps/test/tls/fullchain.pem");
     let cert_file = res!(File::open(path));
     let mut reader = BufReader::new(cert_file);
     let certs = res!(rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>, _>>());
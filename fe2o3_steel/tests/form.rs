@@ -0,0 +1,189 @@
+use oxedyne_fe2o3_steel::{
+    app::https::AppWebHandler,
+    srv::{
+        cfg::ServerConfig,
+        context::{
+            Protocol,
+            ServerContext,
+        },
+        id,
+        ws::syntax::WebSocketSyntax,
+    },
+};
+
+use oxedyne_fe2o3_core::{
+    prelude::*,
+    file::OsPath,
+    path::NormalPath,
+};
+use oxedyne_fe2o3_crypto::enc::EncryptionScheme;
+use oxedyne_fe2o3_hash::{
+    csum::ChecksumScheme,
+    hash::HashScheme,
+};
+use oxedyne_fe2o3_jdat::{
+    prelude::*,
+    version::SemVer,
+};
+use oxedyne_fe2o3_net::{
+    constant,
+    http::{
+        header::{
+            HttpHeadline,
+            HttpMethod,
+        },
+        msg::HttpMessage,
+        status::HttpStatus,
+    },
+    ws::handler::WebSocketSinkHandler,
+};
+use oxedyne_fe2o3_o3db_sync::O3db;
+
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    path::Path,
+    pin::Pin,
+};
+
+use tokio::io::AsyncWriteExt;
+
+
+type TestDb = O3db<
+    { id::UID_LEN },
+    id::Uid,
+    EncryptionScheme,
+    HashScheme,
+    HashScheme,
+    ChecksumScheme,
+>;
+
+/// A multipart form with a text field and an upload of the given size.
+fn form_request(upload_size: usize) -> Vec<u8> {
+    let mut body = b"--AaB03x\r\n\
+        Content-Disposition: form-data; name=\"note\"\r\n\r\n\
+        hello\r\n\
+        --AaB03x\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"a.bin\"\r\n\
+        Content-Type: application/octet-stream\r\n\r\n".to_vec();
+    body.extend(std::iter::repeat(b'x').take(upload_size));
+    body.extend_from_slice(b"\r\n--AaB03x--\r\n");
+    let mut request = fmt!(
+        "POST /upload HTTP/1.1\r\n\
+        Host: localhost\r\n\
+        Content-Type: multipart/form-data; boundary=AaB03x\r\n\
+        Content-Length: {}\r\n\r\n",
+        body.len(),
+    ).into_bytes();
+    request.extend(body);
+    request
+}
+
+/// Post a form with an upload of the given size to a server using the given configuration,
+/// returning the status and text of the response.
+async fn post_form(cfg: ServerConfig, upload_size: usize) -> Outcome<(HttpStatus, String)> {
+    let root = Path::new(".").normalise().absolute();
+    let web_handler = AppWebHandler::new(
+        cfg.clone(),
+        root.clone().as_pathbuf(),
+        BTreeMap::<String, OsPath>::new(),
+        Vec::new(),
+        false,
+    );
+    let ws_syntax = res!(WebSocketSyntax::new(
+        "steel_ws",
+        &SemVer::new(0, 1, 0),
+        "Steel Websocket Form Test",
+    ));
+    let context = ServerContext::<_, _, EncryptionScheme, HashScheme, TestDb, _, _>::new(
+        cfg,
+        root,
+        None::<(TestDb, id::Uid)>,
+        Protocol::Web {
+            web_handler:    web_handler.clone(),
+            ws_handler:     WebSocketSinkHandler,
+            ws_syntax:      ws_syntax.clone(),
+            dev_mode:       false,
+        },
+    );
+    let (server_stream, mut client_stream) = tokio::io::duplex(65_536);
+    let src_addr: SocketAddr = res!("127.0.0.1:50000".parse());
+    let server = tokio::spawn(context.handle_https(
+        server_stream,
+        web_handler,
+        WebSocketSinkHandler,
+        ws_syntax,
+        src_addr,
+    ));
+
+    res!(client_stream.write_all(&form_request(upload_size)).await);
+    let result = HttpMessage::read_response::<
+        { constant::HTTP_DEFAULT_HEADER_CHUNK_SIZE },
+        { constant::HTTP_DEFAULT_BODY_CHUNK_SIZE },
+        _,
+    >(Pin::new(&mut client_stream), &Vec::new(), HttpMethod::POST, 1_024).await;
+    let response = match res!(result) {
+        (Some(response), _) => response,
+        (None, _) => return Err(err!("The server closed without responding."; Test, Missing)),
+    };
+    drop(client_stream);
+    match server.await {
+        Ok(result) => res!(result),
+        Err(e) => return Err(err!(e, "While awaiting the server task."; Async)),
+    }
+    let status = match response.header.headline {
+        HttpHeadline::Response { status } => status,
+        _ => return Err(err!("Expected a response."; Test, Unexpected)),
+    };
+    Ok((status, response.body_as_string().to_string()))
+}
+
+pub async fn test_form(filter: &'static str) -> Outcome<()> {
+
+    match filter {
+        "all" | "form" => {
+            // An upload within the configured limit is passed to the handler.
+            let cfg = ServerConfig {
+                form_max_upload_size: 1_000,
+                ..Default::default()
+            };
+            let (status, text) = res!(post_form(cfg.clone(), 1_000).await);
+            req!(HttpStatus::OK, status);
+            req!("Received file (a.bin, 1000 bytes), note.", &text);
+            // A larger upload is refused.
+            let (status, _) = res!(post_form(cfg, 1_001).await);
+            req!(HttpStatus::BadRequest, status);
+            // As are more parts than configured.
+            let cfg = ServerConfig {
+                form_max_parts: 1,
+                ..Default::default()
+            };
+            let (status, _) = res!(post_form(cfg, 10).await);
+            req!(HttpStatus::BadRequest, status);
+            // A configuration saved before the form limits existed falls back to the defaults.
+            let mut map = match ServerConfig::to_datmap(ServerConfig::default()) {
+                Dat::Map(map) => map,
+                dat => return Err(err!("Expected a Dat::Map, found {:?}.", dat; Test, Unexpected)),
+            };
+            map.retain(|k, _| !matches!(k, Dat::Str(s) if s.starts_with("form_")));
+            let cfg = res!(ServerConfig::from_datmap(map));
+            req!(ServerConfig::default(), cfg);
+        },
+        _ => (),
+    }
+
+    Ok(())
+}
+
+/// Run as
+/// ```ignore
+///     cargo test --test form -- --nocapture
+/// ```
+#[test]
+fn form() -> Outcome<()> {
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(rt) => rt,
+        Err(e) => return Err(err!(e, "Failed to create Tokio runtime."; IO, Init)),
+    };
+    runtime.block_on(test_form("all"))
+}
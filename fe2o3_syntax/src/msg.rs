@@ -84,9 +84,9 @@ impl fmt::Display for Msg {
             }
             first = false;
         }
-        for (k, cmd) in &self.cmds {
+        for cmd in self.cmds.values() {
             if !first { write!(f, " ")?; } 
-            write!(f, "{} {}", k, cmd)?;
+            write!(f, "{}", cmd)?;
             first = false;
         }
         Ok(())
@@ -309,8 +309,27 @@ impl Msg {
         }
     }
 
+    /// Resolve an argument name to the key under which it is held in the message.  Received
+    /// messages key arguments by their canonical name, while those added by name keep the name
+    /// used, so fall back to any matching syntax name.
+    fn arg_key(&self, a: String) -> String {
+        if self.args.contains_key(&a) {
+            return a;
+        }
+        if let Some(arg) = self.syntax().args.get_recursive(&Key::Str(a.clone())) {
+            for k in self.args.keys() {
+                if let Some(karg) = self.syntax().args.get_recursive(&Key::Str(k.clone())) {
+                    if karg.canonical_name() == arg.canonical_name() {
+                        return k.clone();
+                    }
+                }
+            }
+        }
+        a
+    }
+
     pub fn get_arg_vals<S: Into<String>>(&self, a: S) -> Option<&Vec<Dat>> {
-        match self.args.get(&(a.into())) {
+        match self.args.get(&self.arg_key(a.into())) {
             Some(vals) => if vals.len() == 0 {
                 None
             } else {
@@ -321,7 +340,8 @@ impl Msg {
     }
 
     pub fn get_arg_vals_mut<S: Into<String>>(&mut self, a: S) -> Option<&mut Vec<Dat>> {
-        match self.args.get_mut(&(a.into())) {
+        let key = self.arg_key(a.into());
+        match self.args.get_mut(&key) {
             Some(vals) => if vals.len() == 0 {
                 None
             } else {
@@ -349,7 +369,7 @@ impl Msg {
     }
 
     pub fn has_arg<S: Into<String>>(&self, a: S) -> bool {
-        self.args.contains_key(&(a.into()))
+        self.args.contains_key(&self.arg_key(a.into()))
     }
 
     pub fn has_only_arg<S: Into<String>>(&self, a: S) -> Outcome<bool> {
@@ -439,7 +459,12 @@ impl Msg {
             if let Some(arg) = args.get_recursive(&Key::Str(arg_name.clone())) {
                 let mut found = false;
                 for (k, _) in &self.args {
-                    if *k == arg.canonical_name() {
+                    // Arguments added by name may be keyed by any of their syntax names.
+                    let canonical = match args.get_recursive(&Key::Str(k.clone())) {
+                        Some(karg) => karg.canonical_name(),
+                        None => k.clone(),
+                    };
+                    if canonical == arg.canonical_name() {
                         found = true;
                         break;
                     }
@@ -1241,20 +1266,15 @@ pub struct MsgCmd {
 
 impl fmt::Display for MsgCmd {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{} ", self.name)?;
-        let mut first = true;
+        write!(f, "{}", self.name)?;
         for val in &self.vals {
-            if !first { write!(f, " ")?; } 
-            write!(f, "{}", val)?;
-            first = false;
+            write!(f, " {:?}", val)?;
         }
         for (k, argvals) in &self.args {
-            if !first { write!(f, " ")?; } 
-            write!(f, "{}", k)?;
+            write!(f, " {}", k)?;
             for val in argvals {
-                write!(f, " {}", val)?;
+                write!(f, " {:?}", val)?;
             }
-            first = false;
         }
         Ok(())
     }
@@ -1429,8 +1449,28 @@ impl MsgCmd {
         }
     }
 
+    /// Resolve an argument name to the key under which it is held in the command, as for
+    /// [`Msg`].
+    fn arg_key(&self, a: String) -> String {
+        if self.args.contains_key(&a) {
+            return a;
+        }
+        if let Ok(cmd) = self.get_syntax_cmd() {
+            if let Some(arg) = cmd.args.get_recursive(&Key::Str(a.clone())) {
+                for k in self.args.keys() {
+                    if let Some(karg) = cmd.args.get_recursive(&Key::Str(k.clone())) {
+                        if karg.canonical_name() == arg.canonical_name() {
+                            return k.clone();
+                        }
+                    }
+                }
+            }
+        }
+        a
+    }
+
     pub fn get_arg_vals<S: Into<String>>(&self, a: S) -> Option<&Vec<Dat>> {
-        match self.args.get(&(a.into())) {
+        match self.args.get(&self.arg_key(a.into())) {
             Some(vals) => if vals.len() == 0 {
                 None
             } else {
@@ -1441,7 +1481,8 @@ impl MsgCmd {
     }
 
     pub fn get_arg_vals_mut<S: Into<String>>(&mut self, a: S) -> Option<&mut Vec<Dat>> {
-        match self.args.get_mut(&(a.into())) {
+        let key = self.arg_key(a.into());
+        match self.args.get_mut(&key) {
             Some(vals) => {
                 if vals.len() == 0 {
                     None
@@ -1454,7 +1495,7 @@ impl MsgCmd {
     }
 
     pub fn has_arg<S: Into<String>>(&self, a: S) -> bool {
-        self.args.contains_key(&(a.into()))
+        self.args.contains_key(&self.arg_key(a.into()))
     }
 
     pub fn has_args(&self) -> bool {
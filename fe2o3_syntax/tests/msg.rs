@@ -398,7 +398,7 @@ pub fn test_msg(filter: &'static str) -> Outcome<()> {
         let msgrx = res!(msgrx.from_str("hello 42 -a goodbye 1", None));
         req!(msgrx.vals, vec![dat!("hello"), dat!(42u8)]);  
         let argrx_vals = res!(msgrx.get_arg_vals("Arg_a").ok_or(err!(
-            "Failed to detect message argument '-a'."), Invalid, Output)));
+            "Failed to detect message argument '-a'."; Invalid, Output)));
         req!(argrx_vals.len(), 2);
         req!(argrx_vals[0], dat!("goodbye"));
         req!(argrx_vals[1], dat!(1i8));  
@@ -467,12 +467,12 @@ pub fn test_msg(filter: &'static str) -> Outcome<()> {
         let msgrx = res!(msgrx.from_str("hello 42 -a goodbye 1 cmd again -3", None));
         req!(msgrx.vals, vec![dat!("hello"), dat!(42u8)]);  
         let argrx_vals = res!(msgrx.get_arg_vals("Arg_a").ok_or(err!(
-            "Failed to detect message argument '-a'."), Invalid, Output)));
+            "Failed to detect message argument '-a'."; Invalid, Output)));
         req!(argrx_vals.len(), 2);
         req!(argrx_vals[0], dat!("goodbye"));
         req!(argrx_vals[1], dat!(1i8));  
         let cmdrx = res!(msgrx.get_cmd("cmd").ok_or(err!(
-            "Failed to detect command 'cmd'."), Invalid, Output)));
+            "Failed to detect command 'cmd'."; Invalid, Output)));
         req!(cmdrx.vals.len(), 2);
         req!(cmdrx.vals[0], dat!("again"));
         req!(cmdrx.vals[1], dat!(-3i16));  
@@ -580,17 +580,17 @@ pub fn test_msg(filter: &'static str) -> Outcome<()> {
         let msgrx = res!(msgrx.from_str("hello 42 -a goodbye 1 cmd again -3 -b dejavu 42", None));
         req!(msgrx.vals, vec![dat!("hello"), dat!(42i128)]);  
         let argrx_vals = res!(msgrx.get_arg_vals("Arg_a").ok_or(err!(
-            "Failed to detect message argument '-a'."), Invalid, Output)));
+            "Failed to detect message argument '-a'."; Invalid, Output)));
         req!(argrx_vals.len(), 2);
         req!(argrx_vals[0], dat!("goodbye"));
         req!(argrx_vals[1], dat!(1i32));  
         let cmdrx = res!(msgrx.get_cmd("cmd").ok_or(err!(
-            "Failed to detect command 'cmd'."), Invalid, Output)));
+            "Failed to detect command 'cmd'."; Invalid, Output)));
         req!(cmdrx.vals.len(), 2);
         req!(cmdrx.vals[0], dat!("again"));
         req!(cmdrx.vals[1], dat!(-3i16));  
         let argrx_vals = res!(cmdrx.get_arg_vals("Arg_b").ok_or(err!(
-            "Failed to detect command 'cmd' argument '-b'."), Invalid, Output)));
+            "Failed to detect command 'cmd' argument '-b'."; Invalid, Output)));
         req!(argrx_vals.len(), 2);
         req!(argrx_vals[0], dat!("dejavu"));
         req!(argrx_vals[1], dat!(42u8));  
@@ -698,11 +698,11 @@ pub fn test_msg(filter: &'static str) -> Outcome<()> {
         let msgrx = res!(msgrx.from_str("42 cmd1 hello cmd2 goodbye -42 -a1 --arg2 done    ", None));
         req!(msgrx.vals, vec![dat!(42u8)]);  
         let cmdrx = res!(msgrx.get_cmd("cmd1").ok_or(err!(
-            "Failed to detect command 'cmd1'."), Invalid, Output)));
+            "Failed to detect command 'cmd1'."; Invalid, Output)));
         req!(cmdrx.vals.len(), 1);
         req!(cmdrx.vals[0], dat!("hello"));
         let cmdrx = res!(msgrx.get_cmd("cmd2").ok_or(err!(
-            "Failed to detect command 'cmd2'."), Invalid, Output)));
+            "Failed to detect command 'cmd2'."; Invalid, Output)));
         req!(cmdrx.vals.len(), 2);
         req!(cmdrx.vals[0], dat!("goodbye"));
         req!(cmdrx.vals[1], dat!(-42i8));
@@ -999,6 +999,7 @@ pub fn test_msg(filter: &'static str) -> Outcome<()> {
         let msgrx2 = res!(msgrx.from_str(&msgrx.to_string(), None));
         debug!("Tx: {}", msgrx);
         debug!("Rx: {}", msgrx2);
+        req!(msgrx2.to_string(), msgrx.to_string());
         //for line in msgrx2.to_lines() {
         //    debug!("{}", line);
         //}